@@ -11,6 +11,12 @@ from pathlib import Path
 from typing import Optional, Tuple
 import numpy as np
 
+# Resample filter codes, matching IMAGE_PIPELINE_RESAMPLE_* in ffi.rs
+RESAMPLE_FILTERS = {"nearest": 0, "bilinear": 1, "catmull_rom": 2, "lanczos3": 3}
+
+# Perceptual hash algorithm codes, matching IMAGE_PIPELINE_HASH_* in ffi.rs
+HASH_ALGORITHMS = {"ahash": 0, "dhash": 1, "phash": 2}
+
 
 def _find_library() -> Path:
     """Find the compiled Rust library"""
@@ -132,12 +138,28 @@ class ImageProcessor:
         ]
         self._lib.image_pipeline_resize.restype = ctypes.c_int32
         
+        self._lib.image_pipeline_resize_with_filter.argtypes = [
+            ctypes.POINTER(ImageHandle), ctypes.c_uint32, ctypes.c_uint32, ctypes.c_int32
+        ]
+        self._lib.image_pipeline_resize_with_filter.restype = ctypes.c_int32
+        
         self._lib.image_pipeline_invert.argtypes = [ctypes.POINTER(ImageHandle)]
         self._lib.image_pipeline_invert.restype = ctypes.c_int32
         
         self._lib.image_pipeline_sepia.argtypes = [ctypes.POINTER(ImageHandle)]
         self._lib.image_pipeline_sepia.restype = ctypes.c_int32
         
+        self._lib.image_pipeline_pixelate.argtypes = [
+            ctypes.POINTER(ImageHandle), ctypes.c_uint32
+        ]
+        self._lib.image_pipeline_pixelate.restype = ctypes.c_int32
+        
+        # Hashing
+        self._lib.image_pipeline_perceptual_hash.argtypes = [
+            ctypes.POINTER(ImageHandle), ctypes.c_int32, ctypes.POINTER(ctypes.c_uint64)
+        ]
+        self._lib.image_pipeline_perceptual_hash.restype = ctypes.c_int32
+        
         # Copy
         self._lib.image_pipeline_copy_to.argtypes = [
             ctypes.POINTER(ImageHandle),
@@ -146,6 +168,10 @@ class ImageProcessor:
         ]
         self._lib.image_pipeline_copy_to.restype = ctypes.c_int32
         
+        # Errors
+        self._lib.image_pipeline_last_error.argtypes = []
+        self._lib.image_pipeline_last_error.restype = ctypes.c_char_p
+        
         # Version
         self._lib.image_pipeline_version.argtypes = []
         self._lib.image_pipeline_version.restype = ctypes.c_char_p
@@ -160,6 +186,11 @@ class ImageProcessor:
             self._lib.image_pipeline_free(self._handle)
             self._handle = None
     
+    def _last_error(self) -> str:
+        """Message of the last failed library call on this thread"""
+        message = self._lib.image_pipeline_last_error()
+        return message.decode("utf-8", "replace") if message else "unknown error"
+    
     def load_from_numpy(self, image: np.ndarray) -> "ImageProcessor":
         """
         Load an image from a numpy array
@@ -201,7 +232,7 @@ class ImageProcessor:
         )
         
         if not self._handle:
-            raise RuntimeError("Failed to create image handle")
+            raise RuntimeError(f"Failed to create image handle: {self._last_error()}")
         
         return self
     
@@ -231,7 +262,9 @@ class ImageProcessor:
         )
         
         if result != 0:
-            raise RuntimeError(f"Failed to copy image data: error code {result}")
+            raise RuntimeError(
+                f"Failed to copy image data: error code {result}: {self._last_error()}"
+            )
         
         return output
     
@@ -320,20 +353,24 @@ class ImageProcessor:
             raise RuntimeError("Edge detection failed")
         return self
     
-    def resize(self, width: int, height: int) -> "ImageProcessor":
+    def resize(self, width: int, height: int, filter: str = "lanczos3") -> "ImageProcessor":
         """
         Resize image
         
         Args:
             width: new width
             height: new height
+            filter: "nearest", "bilinear", "catmull_rom" or "lanczos3"
         """
         if self._handle is None:
             raise RuntimeError("No image loaded")
-        result = self._lib.image_pipeline_resize(
+        if filter not in RESAMPLE_FILTERS:
+            raise ValueError(f"Unknown resample filter: {filter}")
+        result = self._lib.image_pipeline_resize_with_filter(
             self._handle,
             ctypes.c_uint32(width),
             ctypes.c_uint32(height),
+            ctypes.c_int32(RESAMPLE_FILTERS[filter]),
         )
         if result != 0:
             raise RuntimeError("Resize failed")
@@ -356,6 +393,46 @@ class ImageProcessor:
         if result != 0:
             raise RuntimeError("Sepia filter failed")
         return self
+    
+    def pixelate(self, block_size: int) -> "ImageProcessor":
+        """
+        Pixelate into square blocks
+        
+        Args:
+            block_size: edge length of each block in pixels (>= 1)
+        """
+        if self._handle is None:
+            raise RuntimeError("No image loaded")
+        result = self._lib.image_pipeline_pixelate(
+            self._handle, ctypes.c_uint32(block_size)
+        )
+        if result != 0:
+            raise RuntimeError("Pixelate filter failed")
+        return self
+    
+    def perceptual_hash(self, algorithm: str = "phash") -> int:
+        """
+        64-bit perceptual hash of the current image
+        
+        Args:
+            algorithm: "ahash", "dhash" or "phash"
+        """
+        if self._handle is None:
+            raise RuntimeError("No image loaded")
+        if algorithm not in HASH_ALGORITHMS:
+            raise ValueError(f"Unknown hash algorithm: {algorithm}")
+        out = ctypes.c_uint64()
+        result = self._lib.image_pipeline_perceptual_hash(
+            self._handle, ctypes.c_int32(HASH_ALGORITHMS[algorithm]), ctypes.byref(out)
+        )
+        if result != 0:
+            raise RuntimeError("Hashing failed")
+        return out.value
+
+
+def hamming_distance(a: int, b: int) -> int:
+    """Number of differing bits between two perceptual hashes"""
+    return bin(a ^ b).count("1")
 
 
 def get_library_version() -> str:
@@ -0,0 +1,17 @@
+use image_pipeline::PipelineError;
+use thiserror::Error;
+
+#[derive(Error, Debug)]
+pub enum CliError {
+    #[error("{0}")]
+    Pipeline(#[from] PipelineError),
+
+    #[error("IO error: {0}")]
+    Io(#[from] std::io::Error),
+
+    #[error("Invalid glob pattern: {0}")]
+    Pattern(#[from] glob::PatternError),
+
+    #[error("{0}")]
+    Usage(String),
+}
@@ -0,0 +1,241 @@
+mod error;
+
+use clap::Parser;
+use error::CliError;
+use image_pipeline::{FilterOperation, ImagePipeline, OutputFormat, PipelineSpec};
+use rayon::prelude::*;
+use std::fs;
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+
+/// Batch image processing with the image-pipeline filters
+#[derive(Parser, Debug)]
+#[command(name = "imgpipe", version, about)]
+struct Args {
+    /// Input files or glob patterns ("-" reads from stdin)
+    #[arg(required = true)]
+    inputs: Vec<String>,
+
+    /// Operations as a JSON/YAML spec file or an inline JSON array
+    #[arg(long)]
+    ops: String,
+
+    /// Output file for a single input ("-" writes to stdout)
+    #[arg(short, long, conflicts_with = "out_dir")]
+    output: Option<String>,
+
+    /// Output directory for batch mode
+    #[arg(long)]
+    out_dir: Option<PathBuf>,
+
+    /// Output format (png, jpeg, webp, avif with the avif feature); inferred from --output when omitted
+    #[arg(short, long, value_parser = parse_format)]
+    format: Option<OutputFormat>,
+
+    /// JPEG and AVIF quality (1-100)
+    #[arg(short, long, default_value_t = 90)]
+    quality: u8,
+
+    /// Number of worker threads (0 = all cores)
+    #[arg(short = 'j', long, default_value_t = 0)]
+    jobs: usize,
+}
+
+enum Input {
+    Stdin,
+    File(PathBuf),
+}
+
+fn main() -> ExitCode {
+    let args = Args::parse();
+    match run(args) {
+        Ok(code) => code,
+        Err(e) => {
+            eprintln!("imgpipe: {}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+fn run(args: Args) -> Result<ExitCode, CliError> {
+    if args.jobs > 0 {
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(args.jobs)
+            .build_global()
+            .map_err(|e| CliError::Usage(e.to_string()))?;
+    }
+
+    let operations = load_operations(&args.ops)?;
+    let inputs = resolve_inputs(&args.inputs)?;
+    let pipeline = ImagePipeline::with_threads(args.jobs);
+
+    match (inputs.as_slice(), &args.out_dir) {
+        ([input], None) => {
+            let output = match (&args.output, input) {
+                (Some(output), _) => output.clone(),
+                (None, Input::Stdin) => "-".to_string(),
+                (None, Input::File(_)) => {
+                    return Err(CliError::Usage(
+                        "specify -o <file> or --out-dir <dir>".to_string(),
+                    ))
+                }
+            };
+            let format = args
+                .format
+                .or_else(|| format_from_path(Path::new(&output)))
+                .unwrap_or(OutputFormat::Png);
+
+            let bytes = read_input(input)?;
+            let encoded = process_one(&pipeline, &bytes, &operations, format, args.quality)?;
+            write_output(&output, &encoded)?;
+            Ok(ExitCode::SUCCESS)
+        }
+        (_, Some(out_dir)) => {
+            let format = args.format.unwrap_or(OutputFormat::Png);
+            run_batch(
+                &pipeline,
+                &inputs,
+                &operations,
+                out_dir,
+                format,
+                args.quality,
+            )
+        }
+        (_, None) => Err(CliError::Usage(
+            "multiple inputs require --out-dir <dir>".to_string(),
+        )),
+    }
+}
+
+/// Process every input in parallel, reporting failures without stopping the batch
+fn run_batch(
+    pipeline: &ImagePipeline,
+    inputs: &[Input],
+    operations: &[FilterOperation],
+    out_dir: &Path,
+    format: OutputFormat,
+    quality: u8,
+) -> Result<ExitCode, CliError> {
+    let paths: Vec<&PathBuf> = inputs
+        .iter()
+        .map(|input| match input {
+            Input::File(path) => Ok(path),
+            Input::Stdin => Err(CliError::Usage(
+                "stdin input cannot be combined with batch mode".to_string(),
+            )),
+        })
+        .collect::<Result<_, _>>()?;
+
+    fs::create_dir_all(out_dir)?;
+
+    let failures: Vec<(PathBuf, CliError)> = paths
+        .par_iter()
+        .filter_map(|path| {
+            let stem = path.file_stem().unwrap_or_default();
+            let out_path = out_dir.join(stem).with_extension(format.extension());
+            image_pipeline::fs::process_file(pipeline, path, operations, out_path, format, quality)
+                .err()
+                .map(|e| ((*path).clone(), CliError::from(e)))
+        })
+        .collect();
+
+    for (path, err) in &failures {
+        eprintln!("imgpipe: {}: {}", path.display(), err);
+    }
+    eprintln!(
+        "imgpipe: processed {} of {} images",
+        paths.len() - failures.len(),
+        paths.len()
+    );
+
+    if failures.is_empty() {
+        Ok(ExitCode::SUCCESS)
+    } else {
+        Ok(ExitCode::FAILURE)
+    }
+}
+
+fn process_one(
+    pipeline: &ImagePipeline,
+    bytes: &[u8],
+    operations: &[FilterOperation],
+    format: OutputFormat,
+    quality: u8,
+) -> Result<Vec<u8>, CliError> {
+    let image = ImagePipeline::load_from_bytes(bytes)?;
+    let result = pipeline.process(&image, operations)?;
+    Ok(ImagePipeline::encode(&result, format, quality)?)
+}
+
+fn load_operations(ops: &str) -> Result<Vec<FilterOperation>, CliError> {
+    let spec = if ops.trim_start().starts_with(['[', '{']) {
+        PipelineSpec::from_json(ops)?
+    } else {
+        let path = Path::new(ops);
+        let contents = fs::read_to_string(path)?;
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("yaml" | "yml") => PipelineSpec::from_yaml(&contents)?,
+            _ => PipelineSpec::from_json(&contents)?,
+        }
+    };
+    Ok(spec.compile()?)
+}
+
+/// Expand glob patterns; plain paths and "-" are passed through
+fn resolve_inputs(patterns: &[String]) -> Result<Vec<Input>, CliError> {
+    let mut inputs = Vec::new();
+
+    for pattern in patterns {
+        if pattern == "-" {
+            inputs.push(Input::Stdin);
+        } else if pattern.contains(['*', '?', '[']) {
+            let before = inputs.len();
+            for entry in glob::glob(pattern)? {
+                let path = entry.map_err(|e| CliError::Io(e.into()))?;
+                if path.is_file() {
+                    inputs.push(Input::File(path));
+                }
+            }
+            if inputs.len() == before {
+                return Err(CliError::Usage(format!("no files match \"{}\"", pattern)));
+            }
+        } else {
+            inputs.push(Input::File(PathBuf::from(pattern)));
+        }
+    }
+
+    Ok(inputs)
+}
+
+fn read_input(input: &Input) -> Result<Vec<u8>, CliError> {
+    match input {
+        Input::Stdin => {
+            let mut bytes = Vec::new();
+            io::stdin().lock().read_to_end(&mut bytes)?;
+            Ok(bytes)
+        }
+        Input::File(path) => Ok(fs::read(path)?),
+    }
+}
+
+fn write_output(output: &str, bytes: &[u8]) -> Result<(), CliError> {
+    if output == "-" {
+        let mut stdout = io::stdout().lock();
+        stdout.write_all(bytes)?;
+        stdout.flush()?;
+    } else {
+        fs::write(output, bytes)?;
+    }
+    Ok(())
+}
+
+fn format_from_path(path: &Path) -> Option<OutputFormat> {
+    path.extension()
+        .and_then(|ext| ext.to_str())
+        .and_then(OutputFormat::from_extension)
+}
+
+fn parse_format(s: &str) -> Result<OutputFormat, String> {
+    OutputFormat::from_extension(s).ok_or_else(|| format!("unsupported format \"{}\"", s))
+}
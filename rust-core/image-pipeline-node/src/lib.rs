@@ -0,0 +1,303 @@
+use image_pipeline::{
+    filters, FilterOperation, FitMode, GrayscaleMode, ImagePipeline, PipelineSpec, ResampleFilter,
+    SHARPEN_AMOUNT, SHARPEN_RADIUS,
+};
+use napi::bindgen_prelude::*;
+use napi_derive::napi;
+
+/// Image processor instance for Node.js
+///
+/// Mirrors `WasmImageProcessor`, but runs natively so filters use the full
+/// rayon thread pool.
+#[napi]
+pub struct NodeImageProcessor {
+    data: Vec<u8>,
+    width: u32,
+    height: u32,
+}
+
+#[napi]
+impl NodeImageProcessor {
+    /// Create a new processor from RGBA image data
+    #[napi(constructor)]
+    pub fn new(data: Buffer, width: u32, height: u32) -> Result<Self> {
+        let expected_size = expected_size(width, height)?;
+        if data.len() != expected_size {
+            return Err(Error::new(
+                Status::InvalidArg,
+                format!(
+                    "Invalid data size: expected {}, got {}",
+                    expected_size,
+                    data.len()
+                ),
+            ));
+        }
+
+        Ok(NodeImageProcessor {
+            data: data.to_vec(),
+            width,
+            height,
+        })
+    }
+
+    /// Get image width
+    #[napi(getter)]
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    /// Get image height
+    #[napi(getter)]
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    /// Get the processed image data as a Buffer
+    #[napi]
+    pub fn get_data(&self) -> Buffer {
+        self.data.clone().into()
+    }
+
+    /// Apply grayscale filter
+    /// mode: "bt601", "bt709" (default), "average", "red", "green" or "blue"
+    #[napi]
+    pub fn grayscale(&mut self, mode: Option<String>) -> Result<()> {
+        let mode = match mode {
+            Some(name) => GrayscaleMode::from_name(&name).ok_or_else(|| {
+                Error::new(
+                    Status::InvalidArg,
+                    format!("Unknown grayscale mode: {}", name),
+                )
+            })?,
+            None => GrayscaleMode::default(),
+        };
+        self.apply_filter(|img| filters::grayscale_with(img, mode))
+    }
+
+    /// Apply brightness adjustment (-1.0 to 1.0)
+    #[napi]
+    pub fn brightness(&mut self, value: f64) -> Result<()> {
+        let value = value as f32;
+        validate(FilterOperation::Brightness(value))?;
+        self.apply_filter(|img| filters::brightness(img, value))
+    }
+
+    /// Apply contrast adjustment (0.0 to 2.0+)
+    #[napi]
+    pub fn contrast(&mut self, value: f64) -> Result<()> {
+        let value = value as f32;
+        validate(FilterOperation::Contrast(value))?;
+        self.apply_filter(|img| filters::contrast(img, value))
+    }
+
+    /// Apply Gaussian blur
+    #[napi]
+    pub fn blur(&mut self, sigma: f64) -> Result<()> {
+        let sigma = sigma as f32;
+        validate(FilterOperation::Blur(sigma))?;
+        self.apply_filter(|img| filters::blur(img, sigma))
+    }
+
+    /// Apply unsharp mask sharpening (defaults: amount 1.5, radius 1.0, threshold 0)
+    #[napi]
+    pub fn sharpen(
+        &mut self,
+        amount: Option<f64>,
+        radius: Option<f64>,
+        threshold: Option<u8>,
+    ) -> Result<()> {
+        let amount = amount.map_or(SHARPEN_AMOUNT, |v| v as f32);
+        let radius = radius.map_or(SHARPEN_RADIUS, |v| v as f32);
+        let threshold = threshold.unwrap_or(0);
+        validate(FilterOperation::Sharpen {
+            amount,
+            radius,
+            threshold,
+        })?;
+        self.apply_filter(|img| filters::sharpen(img, amount, radius, threshold))
+    }
+
+    /// Apply edge detection (Sobel)
+    #[napi]
+    pub fn edge_detect(&mut self) -> Result<()> {
+        self.apply_filter(filters::edge_detect)
+    }
+
+    /// Resize image
+    /// filter: "nearest", "bilinear", "catmull_rom" or "lanczos3" (default)
+    #[napi]
+    pub fn resize(
+        &mut self,
+        new_width: u32,
+        new_height: u32,
+        filter: Option<String>,
+    ) -> Result<()> {
+        let filter = match filter {
+            Some(name) => ResampleFilter::from_name(&name).ok_or_else(|| {
+                Error::new(
+                    Status::InvalidArg,
+                    format!("Unknown resample filter: {}", name),
+                )
+            })?,
+            None => ResampleFilter::default(),
+        };
+        let img = self.to_image()?;
+        let result = filters::resize(&img, new_width, new_height, filter);
+        self.width = new_width;
+        self.height = new_height;
+        self.data = result.into_raw();
+        Ok(())
+    }
+
+    /// Fit into maxWidth x maxHeight preserving aspect ratio
+    /// fit: "contain", "cover" or "pad"; background is 0xRRGGBBAA and only used by "pad"
+    #[napi]
+    pub fn thumbnail(
+        &mut self,
+        max_width: u32,
+        max_height: u32,
+        fit: String,
+        background: Option<u32>,
+    ) -> Result<()> {
+        if max_width == 0 || max_height == 0 {
+            return Err(Error::new(
+                Status::InvalidArg,
+                "Thumbnail size must be at least 1x1",
+            ));
+        }
+        let fit = match fit.as_str() {
+            "contain" => FitMode::Contain,
+            "cover" => FitMode::Cover,
+            "pad" => FitMode::Pad(image::Rgba(background.unwrap_or(0).to_be_bytes())),
+            other => {
+                return Err(Error::new(
+                    Status::InvalidArg,
+                    format!("Unknown fit mode: {}", other),
+                ))
+            }
+        };
+
+        let img = self.to_image()?;
+        let result = filters::thumbnail(&img, max_width, max_height, fit);
+        self.width = result.width();
+        self.height = result.height();
+        self.data = result.into_raw();
+        Ok(())
+    }
+
+    /// Invert colors
+    #[napi]
+    pub fn invert(&mut self) -> Result<()> {
+        self.apply_filter(filters::invert)
+    }
+
+    /// Apply sepia tone
+    #[napi]
+    pub fn sepia(&mut self) -> Result<()> {
+        self.apply_filter(filters::sepia)
+    }
+
+    /// Apply multiple filters in sequence from a JSON pipeline spec
+    #[napi]
+    pub fn apply_filters(&mut self, filters_json: String) -> Result<()> {
+        let operations = PipelineSpec::from_json(&filters_json)
+            .and_then(|spec| spec.compile())
+            .map_err(|e| Error::new(Status::InvalidArg, e.to_string()))?;
+
+        let img = self.to_image()?;
+        let result = ImagePipeline::new()
+            .process(&img, &operations)
+            .map_err(|e| Error::from_reason(e.to_string()))?;
+
+        self.width = result.width();
+        self.height = result.height();
+        self.data = result.into_raw();
+        Ok(())
+    }
+
+    /// Replace the image data
+    #[napi]
+    pub fn reset(&mut self, data: Buffer, width: u32, height: u32) -> Result<()> {
+        let expected_size = expected_size(width, height)?;
+        if data.len() != expected_size {
+            return Err(Error::new(
+                Status::InvalidArg,
+                format!(
+                    "Invalid data size: expected {}, got {}",
+                    expected_size,
+                    data.len()
+                ),
+            ));
+        }
+
+        self.data = data.to_vec();
+        self.width = width;
+        self.height = height;
+        Ok(())
+    }
+
+    // Helper to convert internal data to RgbaImage
+    fn to_image(&self) -> Result<image::RgbaImage> {
+        image::RgbaImage::from_raw(self.width, self.height, self.data.clone())
+            .ok_or_else(|| Error::from_reason("Failed to create image from data"))
+    }
+
+    // Helper to apply a filter function
+    fn apply_filter<F>(&mut self, f: F) -> Result<()>
+    where
+        F: FnOnce(&image::RgbaImage) -> image::RgbaImage,
+    {
+        let img = self.to_image()?;
+        let result = f(&img);
+        self.data = result.into_raw();
+        Ok(())
+    }
+}
+
+/// Get library version
+#[napi]
+pub fn get_version() -> String {
+    env!("CARGO_PKG_VERSION").to_string()
+}
+
+/// Quick grayscale conversion without creating processor object
+#[napi]
+pub fn quick_grayscale(data: Buffer, width: u32, height: u32) -> Result<Buffer> {
+    let img = to_image(data, width, height)?;
+    Ok(filters::grayscale(&img).into_raw().into())
+}
+
+/// Quick brightness adjustment
+#[napi]
+pub fn quick_brightness(data: Buffer, width: u32, height: u32, value: f64) -> Result<Buffer> {
+    let value = value as f32;
+    validate(FilterOperation::Brightness(value))?;
+    let img = to_image(data, width, height)?;
+    Ok(filters::brightness(&img, value).into_raw().into())
+}
+
+/// Quick blur
+#[napi]
+pub fn quick_blur(data: Buffer, width: u32, height: u32, sigma: f64) -> Result<Buffer> {
+    let sigma = sigma as f32;
+    validate(FilterOperation::Blur(sigma))?;
+    let img = to_image(data, width, height)?;
+    Ok(filters::blur(&img, sigma).into_raw().into())
+}
+
+// Reject parameters the pipeline would, before they reach a filter
+fn validate(op: FilterOperation) -> Result<()> {
+    op.validate()
+        .map_err(|e| Error::new(Status::InvalidArg, e.to_string()))
+}
+
+fn to_image(data: Buffer, width: u32, height: u32) -> Result<image::RgbaImage> {
+    image::RgbaImage::from_raw(width, height, data.to_vec())
+        .ok_or_else(|| Error::new(Status::InvalidArg, "Invalid image data"))
+}
+
+// Bytes of RGBA data for the dimensions, refusing sizes that overflow
+fn expected_size(width: u32, height: u32) -> Result<usize> {
+    image_pipeline::rgba_len(width, height)
+        .map_err(|e| Error::new(Status::InvalidArg, e.to_string()))
+}
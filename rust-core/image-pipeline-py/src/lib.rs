@@ -0,0 +1,438 @@
+// pyo3 0.22 macro expansion trips this lint on every #[pyfunction]
+#![allow(clippy::useless_conversion)]
+
+use image::RgbaImage;
+use image_pipeline::{
+    filters, Anchor, EdgeBorder, EdgeOptions, EdgeOutput, FilterOperation, GrayscaleMode,
+    ImagePipeline, Mark, PipelineError, Region, ResampleFilter, UpscaleMethod, SHARPEN_AMOUNT,
+    SHARPEN_RADIUS,
+};
+use numpy::ndarray::Array3;
+use numpy::{IntoPyArray, PyArray3, PyReadonlyArray3, PyUntypedArrayMethods};
+use pyo3::exceptions::{PyRuntimeError, PyValueError};
+use pyo3::prelude::*;
+use std::sync::Arc;
+
+/// Filter operation that can be passed to `ImagePipeline.process`
+#[pyclass(name = "FilterOperation", module = "image_pipeline_py")]
+#[derive(Clone)]
+pub struct PyFilterOperation {
+    inner: FilterOperation,
+}
+
+#[pymethods]
+impl PyFilterOperation {
+    /// Convert to grayscale
+    /// mode: "bt601", "bt709", "average", "red", "green" or "blue"
+    #[staticmethod]
+    #[pyo3(signature = (mode="bt709"))]
+    fn grayscale(mode: &str) -> PyResult<Self> {
+        Ok(FilterOperation::Grayscale(parse_grayscale_mode(mode)?).into())
+    }
+
+    /// Adjust brightness (-1.0 to 1.0)
+    #[staticmethod]
+    fn brightness(value: f32) -> Self {
+        FilterOperation::Brightness(value).into()
+    }
+
+    /// Adjust contrast (0.0 to 2.0+)
+    #[staticmethod]
+    fn contrast(value: f32) -> Self {
+        FilterOperation::Contrast(value).into()
+    }
+
+    /// Apply Gaussian blur with sigma
+    #[staticmethod]
+    fn blur(sigma: f32) -> Self {
+        FilterOperation::Blur(sigma).into()
+    }
+
+    /// Unsharp mask sharpening; differences below `threshold` (0-255) are kept as is
+    #[staticmethod]
+    #[pyo3(signature = (amount=SHARPEN_AMOUNT, radius=SHARPEN_RADIUS, threshold=0))]
+    fn sharpen(amount: f32, radius: f32, threshold: u8) -> Self {
+        FilterOperation::Sharpen {
+            amount,
+            radius,
+            threshold,
+        }
+        .into()
+    }
+
+    /// Detect edges using Sobel operator
+    /// border: "zero" or "replicate"; output: "magnitude" or "direction"
+    #[staticmethod]
+    #[pyo3(signature = (border="zero", output="magnitude", threshold=None))]
+    fn edge_detect(border: &str, output: &str, threshold: Option<u8>) -> PyResult<Self> {
+        let border = match border {
+            "zero" => EdgeBorder::Zero,
+            "replicate" => EdgeBorder::Replicate,
+            other => {
+                return Err(PyValueError::new_err(format!(
+                    "Unknown edge border: {}",
+                    other
+                )))
+            }
+        };
+        let output = match output {
+            "magnitude" => EdgeOutput::Magnitude,
+            "direction" => EdgeOutput::Direction,
+            other => {
+                return Err(PyValueError::new_err(format!(
+                    "Unknown edge output: {}",
+                    other
+                )))
+            }
+        };
+        Ok(FilterOperation::EdgeDetect(EdgeOptions {
+            border,
+            output,
+            threshold,
+        })
+        .into())
+    }
+
+    /// Resize to specific dimensions
+    /// filter: "nearest", "bilinear", "catmull_rom" or "lanczos3"
+    #[staticmethod]
+    #[pyo3(signature = (width, height, filter="lanczos3"))]
+    fn resize(width: u32, height: u32, filter: &str) -> PyResult<Self> {
+        Ok(FilterOperation::Resize {
+            width,
+            height,
+            filter: parse_resample_filter(filter)?,
+        }
+        .into())
+    }
+
+    /// Invert colors
+    #[staticmethod]
+    fn invert() -> Self {
+        FilterOperation::Invert.into()
+    }
+
+    /// Apply sepia tone
+    #[staticmethod]
+    fn sepia() -> Self {
+        FilterOperation::Sepia.into()
+    }
+
+    /// Mix channels with a 4x5 color matrix: four rows of R, G, B, A weights and an offset
+    #[staticmethod]
+    fn color_matrix(matrix: [[f32; 5]; 4]) -> Self {
+        FilterOperation::ColorMatrix(matrix).into()
+    }
+
+    /// Make pixels near `key_color` (r, g, b, a) transparent and suppress its spill
+    /// tolerance, softness: chroma distances from 0.0 to 1.0
+    #[staticmethod]
+    #[pyo3(signature = (tolerance, softness=0.0, key_color=[0, 255, 0, 255]))]
+    fn chroma_key(tolerance: f32, softness: f32, key_color: [u8; 4]) -> Self {
+        FilterOperation::ChromaKey {
+            key_color: image::Rgba(key_color),
+            tolerance,
+            softness,
+        }
+        .into()
+    }
+
+    /// Enlarge by `factor` (1-16); method: "detail" (sharpened without halos) or "lanczos"
+    #[staticmethod]
+    #[pyo3(signature = (factor, method="detail"))]
+    fn upscale(factor: f32, method: &str) -> PyResult<Self> {
+        let method = match method {
+            "detail" => UpscaleMethod::Detail,
+            "lanczos" => UpscaleMethod::Lanczos,
+            other => {
+                return Err(PyValueError::new_err(format!(
+                    "Unknown upscale method: {}",
+                    other
+                )))
+            }
+        };
+        Ok(FilterOperation::Upscale { factor, method }.into())
+    }
+
+    /// Blur the background, keeping the subject sharp
+    /// mask: path of a subject mask image (white = subject); None estimates the subject
+    #[staticmethod]
+    #[pyo3(signature = (sigma, feather=0.0, mask=None))]
+    fn portrait_blur(sigma: f32, feather: f32, mask: Option<&str>) -> PyResult<Self> {
+        let mask = mask.map(Region::load_mask).transpose().map_err(to_py_err)?;
+        Ok(FilterOperation::PortraitBlur {
+            mask,
+            sigma,
+            feather,
+        }
+        .into())
+    }
+
+    /// Draw the watermark image at `path` over the image
+    /// anchor: "top_left", "top", ..., "bottom_right"; margin: pixels from the edges and between tiles
+    /// scale: mark width as a fraction of the image width; None keeps its own size
+    #[staticmethod]
+    #[pyo3(signature = (path, anchor="bottom_right", margin=0, opacity=1.0, tile=false, scale=None))]
+    fn watermark(
+        path: &str,
+        anchor: &str,
+        margin: u32,
+        opacity: f32,
+        tile: bool,
+        scale: Option<f32>,
+    ) -> PyResult<Self> {
+        let anchor = Anchor::from_name(anchor)
+            .ok_or_else(|| PyValueError::new_err(format!("Unknown anchor: {}", anchor)))?;
+        let mark = Mark::load(path).map_err(to_py_err)?;
+        Ok(FilterOperation::Watermark {
+            mark: Arc::new(mark),
+            anchor,
+            margin,
+            opacity,
+            tile,
+            scale,
+        }
+        .into())
+    }
+
+    fn __repr__(&self) -> String {
+        format!("FilterOperation.{:?}", self.inner)
+    }
+}
+
+impl From<FilterOperation> for PyFilterOperation {
+    fn from(inner: FilterOperation) -> Self {
+        Self { inner }
+    }
+}
+
+/// Core image processing pipeline
+#[pyclass(name = "ImagePipeline", module = "image_pipeline_py")]
+pub struct PyImagePipeline {
+    inner: ImagePipeline,
+}
+
+#[pymethods]
+impl PyImagePipeline {
+    #[new]
+    #[pyo3(signature = (thread_count = 0))]
+    fn new(thread_count: usize) -> Self {
+        Self {
+            inner: ImagePipeline::with_threads(thread_count),
+        }
+    }
+
+    /// Number of threads to use (0 = auto)
+    #[getter]
+    fn thread_count(&self) -> usize {
+        self.inner.thread_count
+    }
+
+    /// Run `operations` over an (H, W, 4) uint8 array and return a new array
+    fn process<'py>(
+        &self,
+        py: Python<'py>,
+        image: PyReadonlyArray3<'py, u8>,
+        operations: Vec<PyFilterOperation>,
+    ) -> PyResult<Bound<'py, PyArray3<u8>>> {
+        let image = to_image(&image)?;
+        let operations: Vec<FilterOperation> = operations.into_iter().map(|op| op.inner).collect();
+
+        let result = py
+            .allow_threads(|| self.inner.process(&image, &operations))
+            .map_err(to_py_err)?;
+        Ok(to_array(py, result))
+    }
+}
+
+// Copy a numpy array into an RgbaImage (a single memcpy for C-contiguous input)
+fn to_image(array: &PyReadonlyArray3<'_, u8>) -> PyResult<RgbaImage> {
+    let shape = array.shape();
+    if shape[2] != 4 {
+        return Err(PyValueError::new_err(format!(
+            "Expected an (H, W, 4) RGBA array, got shape {:?}",
+            shape
+        )));
+    }
+
+    let (height, width) = (shape[0] as u32, shape[1] as u32);
+    let data = match array.as_slice() {
+        Ok(slice) => slice.to_vec(),
+        Err(_) => array.as_array().iter().copied().collect(),
+    };
+
+    RgbaImage::from_raw(width, height, data)
+        .ok_or_else(|| PyValueError::new_err("Invalid image data"))
+}
+
+// Hand the image buffer over to numpy without copying
+fn to_array(py: Python<'_>, image: RgbaImage) -> Bound<'_, PyArray3<u8>> {
+    let (width, height) = image.dimensions();
+    Array3::from_shape_vec((height as usize, width as usize, 4), image.into_raw())
+        .expect("RgbaImage buffer always matches its dimensions")
+        .into_pyarray_bound(py)
+}
+
+fn to_py_err(err: PipelineError) -> PyErr {
+    match err {
+        PipelineError::InvalidParameter(msg) | PipelineError::InvalidSpec(msg) => {
+            PyValueError::new_err(msg)
+        }
+        other => PyRuntimeError::new_err(other.to_string()),
+    }
+}
+
+// Run a single filter on a numpy array with the GIL released
+fn apply_filter<'py, F>(
+    py: Python<'py>,
+    image: PyReadonlyArray3<'py, u8>,
+    f: F,
+) -> PyResult<Bound<'py, PyArray3<u8>>>
+where
+    F: FnOnce(&RgbaImage) -> RgbaImage + Send,
+{
+    let image = to_image(&image)?;
+    let result = py.allow_threads(|| f(&image));
+    Ok(to_array(py, result))
+}
+
+/// Convert to grayscale
+/// mode: "bt601", "bt709", "average", "red", "green" or "blue"
+#[pyfunction]
+#[pyo3(signature = (image, mode="bt709"))]
+fn grayscale<'py>(
+    py: Python<'py>,
+    image: PyReadonlyArray3<'py, u8>,
+    mode: &str,
+) -> PyResult<Bound<'py, PyArray3<u8>>> {
+    let mode = parse_grayscale_mode(mode)?;
+    apply_filter(py, image, |img| filters::grayscale_with(img, mode))
+}
+
+/// Adjust brightness (-1.0 to 1.0)
+#[pyfunction]
+fn brightness<'py>(
+    py: Python<'py>,
+    image: PyReadonlyArray3<'py, u8>,
+    value: f32,
+) -> PyResult<Bound<'py, PyArray3<u8>>> {
+    FilterOperation::Brightness(value)
+        .validate()
+        .map_err(to_py_err)?;
+    apply_filter(py, image, |img| filters::brightness(img, value))
+}
+
+/// Adjust contrast (0.0 to 2.0+)
+#[pyfunction]
+fn contrast<'py>(
+    py: Python<'py>,
+    image: PyReadonlyArray3<'py, u8>,
+    value: f32,
+) -> PyResult<Bound<'py, PyArray3<u8>>> {
+    FilterOperation::Contrast(value)
+        .validate()
+        .map_err(to_py_err)?;
+    apply_filter(py, image, |img| filters::contrast(img, value))
+}
+
+/// Apply Gaussian blur with sigma
+#[pyfunction]
+fn blur<'py>(
+    py: Python<'py>,
+    image: PyReadonlyArray3<'py, u8>,
+    sigma: f32,
+) -> PyResult<Bound<'py, PyArray3<u8>>> {
+    FilterOperation::Blur(sigma).validate().map_err(to_py_err)?;
+    apply_filter(py, image, |img| filters::blur(img, sigma))
+}
+
+/// Apply unsharp mask sharpening
+#[pyfunction]
+#[pyo3(signature = (image, amount=SHARPEN_AMOUNT, radius=SHARPEN_RADIUS, threshold=0))]
+fn sharpen<'py>(
+    py: Python<'py>,
+    image: PyReadonlyArray3<'py, u8>,
+    amount: f32,
+    radius: f32,
+    threshold: u8,
+) -> PyResult<Bound<'py, PyArray3<u8>>> {
+    FilterOperation::Sharpen {
+        amount,
+        radius,
+        threshold,
+    }
+    .validate()
+    .map_err(to_py_err)?;
+    apply_filter(py, image, |img| {
+        filters::sharpen(img, amount, radius, threshold)
+    })
+}
+
+/// Detect edges using Sobel operator
+#[pyfunction]
+fn edge_detect<'py>(
+    py: Python<'py>,
+    image: PyReadonlyArray3<'py, u8>,
+) -> PyResult<Bound<'py, PyArray3<u8>>> {
+    apply_filter(py, image, filters::edge_detect)
+}
+
+/// Resize to specific dimensions
+/// filter: "nearest", "bilinear", "catmull_rom" or "lanczos3"
+#[pyfunction]
+#[pyo3(signature = (image, width, height, filter="lanczos3"))]
+fn resize<'py>(
+    py: Python<'py>,
+    image: PyReadonlyArray3<'py, u8>,
+    width: u32,
+    height: u32,
+    filter: &str,
+) -> PyResult<Bound<'py, PyArray3<u8>>> {
+    let filter = parse_resample_filter(filter)?;
+    apply_filter(py, image, |img| filters::resize(img, width, height, filter))
+}
+
+fn parse_grayscale_mode(name: &str) -> PyResult<GrayscaleMode> {
+    GrayscaleMode::from_name(name)
+        .ok_or_else(|| PyValueError::new_err(format!("Unknown grayscale mode: {}", name)))
+}
+
+fn parse_resample_filter(name: &str) -> PyResult<ResampleFilter> {
+    ResampleFilter::from_name(name)
+        .ok_or_else(|| PyValueError::new_err(format!("Unknown resample filter: {}", name)))
+}
+
+/// Invert colors
+#[pyfunction]
+fn invert<'py>(
+    py: Python<'py>,
+    image: PyReadonlyArray3<'py, u8>,
+) -> PyResult<Bound<'py, PyArray3<u8>>> {
+    apply_filter(py, image, filters::invert)
+}
+
+/// Apply sepia tone
+#[pyfunction]
+fn sepia<'py>(
+    py: Python<'py>,
+    image: PyReadonlyArray3<'py, u8>,
+) -> PyResult<Bound<'py, PyArray3<u8>>> {
+    apply_filter(py, image, filters::sepia)
+}
+
+#[pymodule]
+fn image_pipeline_py(m: &Bound<'_, PyModule>) -> PyResult<()> {
+    m.add("__version__", env!("CARGO_PKG_VERSION"))?;
+    m.add_class::<PyImagePipeline>()?;
+    m.add_class::<PyFilterOperation>()?;
+    m.add_function(wrap_pyfunction!(grayscale, m)?)?;
+    m.add_function(wrap_pyfunction!(brightness, m)?)?;
+    m.add_function(wrap_pyfunction!(contrast, m)?)?;
+    m.add_function(wrap_pyfunction!(blur, m)?)?;
+    m.add_function(wrap_pyfunction!(sharpen, m)?)?;
+    m.add_function(wrap_pyfunction!(edge_detect, m)?)?;
+    m.add_function(wrap_pyfunction!(resize, m)?)?;
+    m.add_function(wrap_pyfunction!(invert, m)?)?;
+    m.add_function(wrap_pyfunction!(sepia, m)?)?;
+    Ok(())
+}
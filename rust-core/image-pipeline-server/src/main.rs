@@ -0,0 +1,121 @@
+mod routes;
+mod signing;
+
+use clap::Parser;
+use image_pipeline::remote::{RemoteLimits, DEFAULT_MAX_BYTES};
+use image_pipeline::{ImagePipeline, MemoryCache};
+use routes::Config;
+use signing::Signer;
+use std::path::PathBuf;
+use std::process::ExitCode;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::net::TcpListener;
+
+/// Image transformation service for the image-pipeline filters
+#[derive(Parser, Debug)]
+#[command(name = "imgpipe-server", version, about)]
+struct Args {
+    /// Address to listen on
+    #[arg(long, default_value = "127.0.0.1:8080")]
+    addr: String,
+
+    /// Directory GET /transform reads sources from (disabled when omitted)
+    #[arg(long)]
+    root: Option<PathBuf>,
+
+    /// Transforms run at once; further ones get 503
+    #[arg(long, default_value_t = 8)]
+    max_concurrent: usize,
+
+    /// Largest accepted upload or source file in bytes
+    #[arg(long, default_value_t = DEFAULT_MAX_BYTES)]
+    max_body_bytes: u64,
+
+    /// Seconds a request may take, upload and processing included, before it gets 408
+    #[arg(long, default_value_t = 30)]
+    timeout: u64,
+
+    /// Largest source or result image in pixels (0 disables the check)
+    #[arg(long, default_value_t = 100_000_000)]
+    max_pixels: u64,
+
+    /// Megabytes of image buffers one request may hold (0 disables the check)
+    #[arg(long, default_value_t = 2048)]
+    max_memory_mb: u64,
+
+    /// Megabytes of encoded results kept for repeated requests (0 disables)
+    #[arg(long, default_value_t = 0)]
+    cache_mb: usize,
+
+    /// Hex-encoded HMAC key; when given, /transform URLs must be signed.
+    /// Repeat to rotate keys: the first signs, any verifies
+    #[arg(long = "signing-key", value_name = "HEX")]
+    signing_keys: Vec<String>,
+
+    /// Print TARGET (path and query) signed with the first key and exit
+    #[arg(long, value_name = "TARGET", requires = "signing_keys")]
+    sign: Option<String>,
+}
+
+#[tokio::main]
+async fn main() -> ExitCode {
+    let args = Args::parse();
+    let signer = if args.signing_keys.is_empty() {
+        None
+    } else {
+        match Signer::from_hex(&args.signing_keys) {
+            Ok(signer) => Some(signer),
+            Err(e) => {
+                eprintln!("imgpipe-server: {}", e);
+                return ExitCode::FAILURE;
+            }
+        }
+    };
+    if let (Some(target), Some(signer)) = (&args.sign, &signer) {
+        println!("{}", signer.sign(target));
+        return ExitCode::SUCCESS;
+    }
+
+    let listener = match TcpListener::bind(&args.addr).await {
+        Ok(listener) => listener,
+        Err(e) => {
+            eprintln!("imgpipe-server: cannot listen on {}: {}", args.addr, e);
+            return ExitCode::FAILURE;
+        }
+    };
+    eprintln!("imgpipe-server: listening on {}", args.addr);
+
+    let mut pipeline = ImagePipeline::new();
+    pipeline.max_pixels = args.max_pixels;
+    pipeline.max_memory_bytes = args.max_memory_mb << 20;
+    if args.cache_mb > 0 {
+        pipeline = pipeline.with_cache(Arc::new(MemoryCache::new(args.cache_mb << 20)));
+    }
+    let config = Config {
+        pipeline,
+        root: args.root,
+        limits: RemoteLimits {
+            max_bytes: args.max_body_bytes,
+            ..RemoteLimits::default()
+        },
+        signer,
+    };
+    let app = routes::router(
+        config,
+        args.max_concurrent,
+        Duration::from_secs(args.timeout),
+    );
+
+    let shutdown = async {
+        let _ = tokio::signal::ctrl_c().await;
+    };
+    if let Err(e) = axum::serve(listener, app)
+        .with_graceful_shutdown(shutdown)
+        .await
+    {
+        eprintln!("imgpipe-server: {}", e);
+        return ExitCode::FAILURE;
+    }
+    ExitCode::SUCCESS
+}
@@ -0,0 +1,642 @@
+//! Request handlers and the limits around them
+//!
+//! - `GET /health`
+//! - `GET /transform?src=<path>&ops=<spec>&format=<fmt>&quality=<q>` processes
+//!   a file below the configured source root
+//! - `POST /transform?ops=<spec>&format=<fmt>&quality=<q>` processes the
+//!   image in the body; the spec may also be sent in an `X-Pipeline` header
+//!
+//! `ops` is a JSON pipeline spec (a bare array of operations is fine). When
+//! signing keys are configured, `/transform` URLs must be signed (see
+//! `signing`) and the spec must be in the signed query, not the header.
+//!
+//! Decoding, processing and encoding run on tokio's blocking pool, so slow
+//! pipelines do not stall the connections being accepted meanwhile. Each
+//! `/transform` request holds one of the `max_concurrent` slots until that
+//! work has stopped, and dropping the request cancels it.
+
+use crate::signing::Signer;
+use axum::body::{Body, Bytes};
+use axum::extract::{DefaultBodyLimit, FromRef, FromRequestParts, Query, State};
+use axum::http::header::CONTENT_TYPE;
+use axum::http::request::Parts;
+use axum::http::{HeaderMap, StatusCode, Uri};
+use axum::response::{IntoResponse, Response};
+use axum::routing::get;
+use axum::Router;
+use image_pipeline::remote::RemoteLimits;
+use image_pipeline::{
+    CancellationToken, FilterOperation, ImagePipeline, OperationSpec, OutputFormat, PipelineError,
+    PipelineSpec,
+};
+use std::path::{Component, Path, PathBuf};
+use std::sync::Arc;
+use std::time::Duration;
+use thiserror::Error;
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tower::ServiceBuilder;
+use tower_http::limit::RequestBodyLimitLayer;
+use tower_http::map_request_body::MapRequestBodyLayer;
+use tower_http::timeout::TimeoutLayer;
+
+/// Everything the handlers need, shared by all requests
+pub struct Config {
+    pub pipeline: ImagePipeline,
+    /// Directory `GET /transform` may read from; `None` disables the route
+    pub root: Option<PathBuf>,
+    /// Largest accepted upload or source file
+    pub limits: RemoteLimits,
+    /// Required URL signatures; `None` serves unsigned requests
+    pub signer: Option<Signer>,
+}
+
+/// A request that cannot be served, answered with `status`
+#[derive(Error, Debug)]
+#[error("{message}")]
+pub struct HttpError {
+    pub status: StatusCode,
+    pub message: String,
+}
+
+impl HttpError {
+    pub fn new(status: StatusCode, message: impl Into<String>) -> Self {
+        Self {
+            status,
+            message: message.into(),
+        }
+    }
+}
+
+impl IntoResponse for HttpError {
+    fn into_response(self) -> Response {
+        (self.status, format!("{}\n", self.message)).into_response()
+    }
+}
+
+/// Query parameters in the order sent, decoded
+type Params = Query<Vec<(String, String)>>;
+
+#[derive(Clone)]
+struct AppState {
+    config: Arc<Config>,
+    slots: Arc<Semaphore>,
+}
+
+impl FromRef<AppState> for Arc<Config> {
+    fn from_ref(state: &AppState) -> Self {
+        state.config.clone()
+    }
+}
+
+/// One of the `max_concurrent` transforms allowed at once, taken before the
+/// body is read and released when the request's blocking work has stopped
+struct Slot {
+    _permit: OwnedSemaphorePermit,
+}
+
+impl FromRequestParts<AppState> for Slot {
+    type Rejection = HttpError;
+
+    async fn from_request_parts(_: &mut Parts, state: &AppState) -> Result<Self, HttpError> {
+        state
+            .slots
+            .clone()
+            .try_acquire_owned()
+            .map(|permit| Slot { _permit: permit })
+            .map_err(|_| {
+                HttpError::new(
+                    StatusCode::SERVICE_UNAVAILABLE,
+                    "too many requests in flight",
+                )
+            })
+    }
+}
+
+/// The service: routes behind the body size, timeout and concurrency limits
+///
+/// At most `max_concurrent` transforms run at once and further ones get 503
+/// instead of queueing. A request that takes longer than `timeout`, including
+/// reading the body and processing, gets 408 and the processing it started
+/// is cancelled; its slot is freed once that work has stopped.
+pub fn router(config: Config, max_concurrent: usize, timeout: Duration) -> Router {
+    let max_body = usize::try_from(config.limits.max_bytes).unwrap_or(usize::MAX);
+    let limits = ServiceBuilder::new()
+        .layer(RequestBodyLimitLayer::new(max_body))
+        .layer(MapRequestBodyLayer::new(Body::new))
+        .layer(TimeoutLayer::with_status_code(
+            StatusCode::REQUEST_TIMEOUT,
+            timeout,
+        ));
+
+    Router::new()
+        .route("/health", get(|| async { "ok\n" }))
+        .route("/transform", get(get_transform).post(post_transform))
+        .fallback(|| async { HttpError::new(StatusCode::NOT_FOUND, "not found") })
+        .method_not_allowed_fallback(|| async {
+            HttpError::new(StatusCode::METHOD_NOT_ALLOWED, "method not allowed")
+        })
+        // `RequestBodyLimitLayer` enforces `max_body` instead of axum's 2 MB default
+        .layer(DefaultBodyLimit::disable())
+        .layer(limits)
+        .with_state(AppState {
+            config: Arc::new(config),
+            slots: Arc::new(Semaphore::new(max_concurrent)),
+        })
+}
+
+fn check_signature(config: &Config, uri: &Uri) -> Result<(), HttpError> {
+    let target = uri
+        .path_and_query()
+        .map_or(uri.path(), |target| target.as_str());
+    match &config.signer {
+        Some(signer) if !signer.verify(target) => Err(HttpError::new(
+            StatusCode::FORBIDDEN,
+            "invalid or missing signature",
+        )),
+        _ => Ok(()),
+    }
+}
+
+/// First query parameter named `name`, decoded
+fn param<'a>(query: &'a [(String, String)], name: &str) -> Option<&'a str> {
+    query
+        .iter()
+        .find(|(key, _)| key == name)
+        .map(|(_, value)| value.as_str())
+}
+
+async fn get_transform(
+    slot: Slot,
+    State(config): State<Arc<Config>>,
+    uri: Uri,
+    Query(query): Params,
+) -> Result<Response, HttpError> {
+    check_signature(&config, &uri)?;
+    let root = config
+        .root
+        .as_ref()
+        .ok_or_else(|| HttpError::new(StatusCode::FORBIDDEN, "no source root configured"))?;
+    let src = param(&query, "src")
+        .ok_or_else(|| HttpError::new(StatusCode::BAD_REQUEST, "missing src parameter"))?;
+    let (root, src) = (root.clone(), src.to_string());
+
+    blocking(slot, move |token| {
+        let path = resolve_source(&root, &src)?;
+        let file = std::fs::File::open(&path)
+            .map_err(|_| HttpError::new(StatusCode::NOT_FOUND, "source not found"))?;
+        let length = file.metadata().map(|m| m.len()).ok();
+        let bytes = config
+            .limits
+            .read_body(length, file)
+            .map_err(|e| pipeline_error(e, StatusCode::PAYLOAD_TOO_LARGE))?;
+        let ops = param(&query, "ops");
+        transform(&config, &query, ops, &bytes, token)
+    })
+    .await
+}
+
+async fn post_transform(
+    slot: Slot,
+    State(config): State<Arc<Config>>,
+    uri: Uri,
+    Query(query): Params,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<Response, HttpError> {
+    check_signature(&config, &uri)?;
+    let header = |name| headers.get(name).and_then(|value| value.to_str().ok());
+    config
+        .limits
+        .check_content_type(header(CONTENT_TYPE.as_str()))
+        .map_err(|e| pipeline_error(e, StatusCode::UNSUPPORTED_MEDIA_TYPE))?;
+    // The header is not covered by the signature
+    let pipeline_header = header("X-Pipeline").map(str::to_string);
+    if config.signer.is_some() && pipeline_header.is_some() {
+        return Err(HttpError::new(
+            StatusCode::BAD_REQUEST,
+            "send the pipeline in the signed ops parameter",
+        ));
+    }
+
+    blocking(slot, move |token| {
+        let ops = param(&query, "ops").or(pipeline_header.as_deref());
+        transform(&config, &query, ops, &body, token)
+    })
+    .await
+}
+
+// Run CPU-bound or file work off the async workers, holding `slot` until it
+// returns. Dropping the future, as the timeout does, cancels the job's token.
+async fn blocking(
+    slot: Slot,
+    job: impl FnOnce(&CancellationToken) -> Result<Response, HttpError> + Send + 'static,
+) -> Result<Response, HttpError> {
+    let token = CancellationToken::new();
+    let _cancel = CancelOnDrop(token.clone());
+    tokio::task::spawn_blocking(move || {
+        let _slot = slot;
+        job(&token)
+    })
+    .await
+    .map_err(|e| HttpError::new(StatusCode::INTERNAL_SERVER_ERROR, e.to_string()))?
+}
+
+/// Cancels its token when dropped
+struct CancelOnDrop(CancellationToken);
+
+impl Drop for CancelOnDrop {
+    fn drop(&mut self) {
+        self.0.cancel();
+    }
+}
+
+fn transform(
+    config: &Config,
+    query: &[(String, String)],
+    ops: Option<&str>,
+    bytes: &[u8],
+    token: &CancellationToken,
+) -> Result<Response, HttpError> {
+    let operations = parse_operations(ops.unwrap_or("[]"))?;
+    let format = match param(query, "format") {
+        Some(name) => OutputFormat::from_extension(name).ok_or_else(|| {
+            HttpError::new(
+                StatusCode::BAD_REQUEST,
+                format!("unsupported format \"{}\"", name),
+            )
+        })?,
+        None => OutputFormat::Png,
+    };
+    let quality = match param(query, "quality") {
+        Some(value) => value.parse().map_err(|_| {
+            HttpError::new(
+                StatusCode::BAD_REQUEST,
+                "quality must be an integer from 1 to 100",
+            )
+        })?,
+        None => 90,
+    };
+
+    let encoded = config
+        .pipeline
+        .process_bytes_cancellable(bytes, &operations, format, quality, token)
+        .map_err(|e| pipeline_error(e, StatusCode::BAD_REQUEST))?;
+    Ok(([(CONTENT_TYPE, format.mime_type())], encoded).into_response())
+}
+
+/// Compile a spec, refusing operations that would read files on the server
+fn parse_operations(json: &str) -> Result<Vec<FilterOperation>, HttpError> {
+    let spec =
+        PipelineSpec::from_json(json).map_err(|e| pipeline_error(e, StatusCode::BAD_REQUEST))?;
+    if spec.operations.iter().any(OperationSpec::reads_files) {
+        return Err(HttpError::new(
+            StatusCode::FORBIDDEN,
+            "operations that read server files are not allowed",
+        ));
+    }
+    spec.compile()
+        .map_err(|e| pipeline_error(e, StatusCode::BAD_REQUEST))
+}
+
+/// Join `src` onto `root`, allowing only plain relative paths that are still
+/// inside `root` once symlinks are resolved
+fn resolve_source(root: &Path, src: &str) -> Result<PathBuf, HttpError> {
+    let outside = || {
+        HttpError::new(
+            StatusCode::FORBIDDEN,
+            "src must be a relative path inside the root",
+        )
+    };
+    let relative = Path::new(src);
+    let plain = relative
+        .components()
+        .all(|component| matches!(component, Component::Normal(_)));
+    if src.is_empty() || !plain {
+        return Err(outside());
+    }
+    let not_found = |_| HttpError::new(StatusCode::NOT_FOUND, "source not found");
+    let root = root.canonicalize().map_err(not_found)?;
+    let path = root.join(relative).canonicalize().map_err(not_found)?;
+    if !path.starts_with(&root) {
+        return Err(outside());
+    }
+    Ok(path)
+}
+
+/// Map a pipeline error to a response; `invalid` is the status for bad input
+fn pipeline_error(e: PipelineError, invalid: StatusCode) -> HttpError {
+    let status = match e {
+        PipelineError::ImageError(_) => StatusCode::UNPROCESSABLE_ENTITY,
+        PipelineError::ResourceLimit(_) => StatusCode::PAYLOAD_TOO_LARGE,
+        PipelineError::FormatNotAllowed(_) => StatusCode::UNSUPPORTED_MEDIA_TYPE,
+        PipelineError::InvalidParameter(_)
+        | PipelineError::InvalidSpec(_)
+        | PipelineError::InvalidLut(_)
+        | PipelineError::InvalidModel(_) => invalid,
+        PipelineError::Cancelled
+        | PipelineError::ProcessingError(_)
+        | PipelineError::IoError(_) => StatusCode::INTERNAL_SERVER_ERROR,
+    };
+    HttpError::new(status, e.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use axum::http::Request;
+    use image::{Rgba, RgbaImage};
+    use tower::ServiceExt;
+
+    fn test_config(root: Option<PathBuf>) -> Config {
+        Config {
+            pipeline: ImagePipeline::new(),
+            root,
+            limits: RemoteLimits::default(),
+            signer: None,
+        }
+    }
+
+    fn png() -> Vec<u8> {
+        let image = RgbaImage::from_pixel(4, 4, Rgba([10, 20, 30, 255]));
+        ImagePipeline::encode_to_png(&image).unwrap()
+    }
+
+    fn app(config: Config) -> Router {
+        router(config, 8, Duration::from_secs(30))
+    }
+
+    struct Reply {
+        status: u16,
+        content_type: String,
+        body: Vec<u8>,
+    }
+
+    // Send a request written as its head, e.g. "GET /health HTTP/1.1\r\nHeader: value"
+    fn send(app: &Router, head: &str, body: &[u8]) -> Reply {
+        let mut lines = head.split("\r\n");
+        let mut request_line = lines.next().unwrap().split(' ');
+        let mut request = Request::builder()
+            .method(request_line.next().unwrap())
+            .uri(request_line.next().unwrap());
+        for line in lines {
+            let (name, value) = line.split_once(':').unwrap();
+            request = request.header(name.trim(), value.trim());
+        }
+        let request = request.body(Body::from(body.to_vec())).unwrap();
+
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        runtime.block_on(async {
+            let response = app.clone().oneshot(request).await.unwrap();
+            let content_type = response
+                .headers()
+                .get(CONTENT_TYPE)
+                .map(|value| value.to_str().unwrap().to_string())
+                .unwrap_or_default();
+            let status = response.status().as_u16();
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            Reply {
+                status,
+                content_type,
+                body: body.to_vec(),
+            }
+        })
+    }
+
+    #[test]
+    fn test_post_transform() {
+        let config = app(test_config(None));
+        let response = send(
+            &config,
+            "POST /transform?format=png HTTP/1.1\r\nContent-Type: image/png\r\n\
+             X-Pipeline: [{\"type\": \"invert\"}, {\"type\": \"resize\", \"width\": 2, \"height\": 1}]",
+            &png(),
+        );
+        assert_eq!(
+            response.status,
+            200,
+            "{}",
+            String::from_utf8_lossy(&response.body)
+        );
+        assert_eq!(response.content_type, "image/png");
+        let result = ImagePipeline::load_from_bytes(&response.body).unwrap();
+        assert_eq!(result.dimensions(), (2, 1));
+        assert_eq!(*result.get_pixel(0, 0), Rgba([245, 235, 225, 255]));
+
+        let status = |head: &str, body: &[u8]| send(&config, head, body).status;
+        let post = "POST /transform HTTP/1.1\r\nContent-Type: image/png";
+        assert_eq!(
+            status(
+                "POST /transform HTTP/1.1\r\nContent-Type: text/plain",
+                &png()
+            ),
+            415
+        );
+        assert_eq!(status(post, b"not an image"), 422);
+        assert_eq!(
+            status(
+                "POST /transform?ops=%5B%7B%22type%22%3A%22blur%22%7D%5D HTTP/1.1\r\nContent-Type: image/png",
+                &png()
+            ),
+            400
+        );
+        assert_eq!(
+            status(
+                "POST /transform?quality=0&format=jpg HTTP/1.1\r\nContent-Type: image/png",
+                &png()
+            ),
+            400
+        );
+        assert_eq!(status("DELETE /transform HTTP/1.1", b""), 405);
+        assert_eq!(status("GET /elsewhere HTTP/1.1", b""), 404);
+
+        let limited = |max_pixels| {
+            let mut config = test_config(None);
+            config.pipeline.max_pixels = max_pixels;
+            app(config)
+        };
+        assert_eq!(send(&limited(15), post, &png()).status, 413);
+        assert_eq!(send(&limited(16), post, &png()).status, 200);
+        let upscale = "POST /transform HTTP/1.1\r\nContent-Type: image/png\r\n\
+             X-Pipeline: [{\"type\": \"resize\", \"width\": 5, \"height\": 4}]";
+        assert_eq!(send(&limited(16), upscale, &png()).status, 413);
+    }
+
+    #[test]
+    fn test_get_transform_stays_in_root() {
+        let root = std::env::temp_dir().join(format!("imgpipe-server-{}", std::process::id()));
+        std::fs::create_dir_all(&root).unwrap();
+        std::fs::write(root.join("in.png"), png()).unwrap();
+        let config = app(test_config(Some(root.clone())));
+
+        let response = send(
+            &config,
+            "GET /transform?src=in.png&format=webp HTTP/1.1",
+            b"",
+        );
+        assert_eq!(response.status, 200);
+        assert_eq!(response.content_type, "image/webp");
+
+        let status = |head: &str| send(&config, head, b"").status;
+        assert_eq!(status("GET /transform?src=missing.png HTTP/1.1"), 404);
+        assert_eq!(status("GET /transform?src=../in.png HTTP/1.1"), 403);
+        assert_eq!(status("GET /transform?src=%2Fetc%2Fpasswd HTTP/1.1"), 403);
+        // Symlinks may not lead out of the root
+        #[cfg(unix)]
+        {
+            let outside = root.with_extension("outside.png");
+            std::fs::write(&outside, png()).unwrap();
+            std::os::unix::fs::symlink(&outside, root.join("link.png")).unwrap();
+            std::os::unix::fs::symlink(root.join("in.png"), root.join("inner.png")).unwrap();
+            assert_eq!(status("GET /transform?src=link.png HTTP/1.1"), 403);
+            assert_eq!(status("GET /transform?src=inner.png HTTP/1.1"), 200);
+            std::fs::remove_file(outside).unwrap();
+        }
+        assert_eq!(
+            status(
+                "GET /transform?src=in.png&ops=%5B%7B%22type%22%3A%22lut3d%22%2C%22path%22%3A%22%2Fetc%2Fpasswd%22%7D%5D HTTP/1.1"
+            ),
+            403
+        );
+        assert_eq!(
+            send(
+                &app(test_config(None)),
+                "GET /transform?src=in.png HTTP/1.1",
+                b""
+            )
+            .status,
+            403
+        );
+        std::fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn test_file_reading_operations_are_rejected() {
+        let config = app(test_config(None));
+        let status = |ops: &str| {
+            let head = format!(
+                "POST /transform HTTP/1.1\r\nContent-Type: image/png\r\nX-Pipeline: {}",
+                ops
+            );
+            send(&config, &head, &png()).status
+        };
+        assert_eq!(
+            status(r#"[{"type": "portrait_blur", "sigma": 4.0, "mask": {"mask": "/etc/passwd"}}]"#),
+            403
+        );
+        assert_eq!(status(r#"[{"type": "model", "path": "/etc/passwd"}]"#), 403);
+        // A readable image anywhere on disk must not end up in the response
+        let mark = std::env::temp_dir().join(format!("imgpipe-mark-{}.png", std::process::id()));
+        std::fs::write(&mark, png()).unwrap();
+        let watermark = format!(r#"[{{"type": "watermark", "path": {:?}}}]"#, mark);
+        assert_eq!(status(&watermark), 403);
+        std::fs::remove_file(mark).unwrap();
+        assert_eq!(
+            status(r#"[{"type": "upscale", "factor": 2.0, "model": "/etc/passwd"}]"#),
+            403
+        );
+        assert_eq!(status(r#"[{"type": "upscale", "factor": 2.0}]"#), 200);
+        assert_eq!(
+            status(
+                r#"[{"type": "portrait_blur", "sigma": 4.0, "mask": {"x": 0, "y": 0, "width": 2, "height": 2}}]"#
+            ),
+            200
+        );
+    }
+
+    #[test]
+    fn test_signed_requests() {
+        let signer = Signer::from_hex(&["42".repeat(32)]).unwrap();
+        let target = signer.sign("/transform?ops=%5B%7B%22type%22%3A%22invert%22%7D%5D");
+        let config = app(Config {
+            signer: Some(signer),
+            ..test_config(None)
+        });
+        let post = |target: &str, header: &str| {
+            let head = format!(
+                "POST {} HTTP/1.1\r\nContent-Type: image/png{}",
+                target, header
+            );
+            send(&config, &head, &png()).status
+        };
+        assert_eq!(post(&target, ""), 200);
+        assert_eq!(post("/transform?ops=%5B%5D", ""), 403);
+        assert_eq!(post(&target.replace("invert", "sepia"), ""), 403);
+        assert_eq!(post(&target, "\r\nX-Pipeline: []"), 400);
+        assert_eq!(send(&config, "GET /health HTTP/1.1", b"").status, 200);
+    }
+
+    #[test]
+    fn test_service_limits() {
+        let post = "POST /transform HTTP/1.1\r\nContent-Type: image/png";
+        let small = app(Config {
+            limits: RemoteLimits {
+                max_bytes: 10,
+                ..RemoteLimits::default()
+            },
+            ..test_config(None)
+        });
+        assert_eq!(send(&small, post, &png()).status, 413);
+
+        let busy = router(test_config(None), 0, Duration::from_secs(30));
+        let reply = send(&busy, post, &png());
+        assert_eq!(reply.status, 503);
+        assert_eq!(reply.body, b"too many requests in flight\n");
+        assert_eq!(send(&busy, "GET /health HTTP/1.1", b"").status, 200);
+
+        // A client that stops sending its body is cut off with 408
+        let impatient = router(test_config(None), 8, Duration::from_millis(50));
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        let reply = runtime.block_on(async {
+            let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+            let addr = listener.local_addr().unwrap();
+            tokio::spawn(async move { axum::serve(listener, impatient).await });
+            tokio::task::spawn_blocking(move || {
+                use std::io::{Read, Write};
+                let mut stream = std::net::TcpStream::connect(addr).unwrap();
+                stream
+                    .set_read_timeout(Some(Duration::from_secs(5)))
+                    .unwrap();
+                stream
+                    .write_all(
+                        b"POST /transform HTTP/1.1\r\nHost: localhost\r\n\
+                          Content-Type: image/png\r\nContent-Length: 100\r\n\r\npartial",
+                    )
+                    .unwrap();
+                let mut reply = [0u8; 64];
+                let read = stream.read(&mut reply).unwrap();
+                String::from_utf8_lossy(&reply[..read]).into_owned()
+            })
+            .await
+            .unwrap()
+        });
+        assert!(reply.starts_with("HTTP/1.1 408"), "{}", reply);
+    }
+
+    #[test]
+    fn test_timeout_cancels_processing() {
+        let app = router(test_config(None), 1, Duration::from_millis(100));
+        let large = RgbaImage::from_pixel(2048, 2048, Rgba([10, 20, 30, 255]));
+        let large = ImagePipeline::encode_to_png(&large).unwrap();
+        let post = "POST /transform HTTP/1.1\r\nContent-Type: image/png";
+        let slow = format!(
+            "{}\r\nX-Pipeline: [{{\"type\": \"blur\", \"sigma\": 100}}]",
+            post
+        );
+
+        // `send` waits for the blocking work to stop before returning, which
+        // would take minutes if the blur kept running
+        let started = std::time::Instant::now();
+        assert_eq!(send(&app, &slow, &large).status, 408);
+        assert!(started.elapsed() < Duration::from_secs(10));
+        // The slot is free again
+        assert_eq!(send(&app, post, &png()).status, 200);
+    }
+}
@@ -1,5 +1,14 @@
+use image_pipeline::{
+    align, alpha, analysis,
+    animation::{Animation, AnimationFrame, Repeat},
+    compose, filters, generate, presets, rgba_len, tonemap, watermark, AlphaMode, Anchor,
+    CancellationToken, EdgeBorder, EdgeOptions, EdgeOutput, FilterOperation, FitMode,
+    FrameProcessor, GrayscaleMode, ImagePipeline, Interpolation, LoadOptions, Lut256, Lut3D,
+    PipelineError, PipelineSpec, Preset, Region, ResampleFilter, ToneMapOperator, UpscaleMethod,
+    SHARPEN_AMOUNT, SHARPEN_RADIUS,
+};
+use std::cell::Cell;
 use wasm_bindgen::prelude::*;
-use image_pipeline::{filters, ImagePipeline, FilterOperation};
 
 // Initialize panic hook for better error messages in browser console
 #[wasm_bindgen(start)]
@@ -7,12 +16,243 @@ pub fn init() {
     console_error_panic_hook::set_once();
 }
 
+thread_local! {
+    // (max_pixels, max_memory_bytes) for every pipeline this module runs
+    static RESOURCE_LIMITS: Cell<(u64, u64)> = const { Cell::new((100_000_000, 2 << 30)) };
+    static ALPHA_MODE: Cell<AlphaMode> = const { Cell::new(AlphaMode::Straight) };
+}
+
+// Pipeline with the limits from `set_resource_limits`
+fn new_pipeline() -> ImagePipeline {
+    let mut pipeline = ImagePipeline::new();
+    (pipeline.max_pixels, pipeline.max_memory_bytes) = RESOURCE_LIMITS.with(Cell::get);
+    pipeline.alpha_mode = ALPHA_MODE.with(Cell::get);
+    pipeline
+}
+
+// Decoding limits matching `set_resource_limits`
+fn load_options() -> LoadOptions {
+    let (max_pixels, max_memory_bytes) = RESOURCE_LIMITS.with(Cell::get);
+    LoadOptions {
+        max_pixels: (max_pixels > 0).then_some(max_pixels),
+        max_alloc: (max_memory_bytes > 0).then_some(max_memory_bytes),
+        ..LoadOptions::default()
+    }
+}
+
+// Run a filter that mixes neighboring pixels, weighting colors by alpha
+// unless `set_alpha_mode` says otherwise
+fn mixing<F>(image: &image::RgbaImage, filter: F) -> image::RgbaImage
+where
+    F: FnOnce(&image::RgbaImage) -> image::RgbaImage,
+{
+    match ALPHA_MODE.with(Cell::get) {
+        AlphaMode::Straight => alpha::premultiplied(image, filter),
+        AlphaMode::Premultiplied | AlphaMode::Ignore => filter(image),
+    }
+}
+
+/// How colors relate to alpha: "straight" (default) premultiplies around
+/// blurs and resampling to avoid dark fringes, "premultiplied" expects
+/// premultiplied data, "ignore" treats alpha as a plain channel
+#[wasm_bindgen]
+pub fn set_alpha_mode(mode: &str) -> Result<(), JsValue> {
+    let mode = AlphaMode::from_name(mode)
+        .ok_or_else(|| invalid(format!("Unknown alpha mode: {}", mode)))?;
+    ALPHA_MODE.with(|current| current.set(mode));
+    Ok(())
+}
+
+/// Multiply colors by alpha
+#[wasm_bindgen]
+pub fn premultiply(data: &[u8], width: u32, height: u32) -> Result<Vec<u8>, JsValue> {
+    let img = image::RgbaImage::from_raw(width, height, data.to_vec())
+        .ok_or_else(|| invalid("Invalid image data"))?;
+    Ok(alpha::premultiply(&img).into_raw())
+}
+
+/// Divide colors by alpha, undoing `premultiply`
+#[wasm_bindgen]
+pub fn unpremultiply(data: &[u8], width: u32, height: u32) -> Result<Vec<u8>, JsValue> {
+    let img = image::RgbaImage::from_raw(width, height, data.to_vec())
+        .ok_or_else(|| invalid("Invalid image data"))?;
+    Ok(alpha::unpremultiply(&img).into_raw())
+}
+
+fn check_dimensions(width: u32, height: u32) -> Result<(), JsValue> {
+    new_pipeline()
+        .check_dimensions(width, height)
+        .map_err(js_error)
+}
+
+/// Refuse images larger than `max_pixels` or runs holding more than
+/// `max_memory_bytes` of image buffers; 0 disables a check.
+/// Defaults to 100 megapixels and 2 GiB.
+#[wasm_bindgen]
+pub fn set_resource_limits(max_pixels: f64, max_memory_bytes: f64) {
+    RESOURCE_LIMITS.with(|limits| limits.set((max_pixels as u64, max_memory_bytes as u64)));
+}
+
+/// Error thrown by every fallible function and method
+///
+/// `code` tells bad input ("invalid_parameter", "invalid_spec", "invalid_lut",
+/// "decode_error", "format_not_allowed", "resource_limit") apart from
+/// "cancelled" and internal failures ("processing_error", "io_error").
+#[wasm_bindgen]
+#[derive(Debug, Clone)]
+pub struct WasmPipelineError {
+    code: &'static str,
+    message: String,
+    operation: Option<u32>,
+}
+
+#[wasm_bindgen]
+impl WasmPipelineError {
+    #[wasm_bindgen(getter)]
+    pub fn code(&self) -> String {
+        self.code.to_string()
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn message(&self) -> String {
+        self.message.clone()
+    }
+
+    /// Index of the offending operation in the filter list, if known
+    #[wasm_bindgen(getter)]
+    pub fn operation(&self) -> Option<u32> {
+        self.operation
+    }
+
+    /// Whether the input is at fault rather than the library
+    #[wasm_bindgen(getter)]
+    pub fn is_input_error(&self) -> bool {
+        !matches!(self.code, "cancelled" | "processing_error" | "io_error")
+    }
+
+    #[wasm_bindgen(js_name = toString)]
+    pub fn to_js_string(&self) -> String {
+        format!("{}: {}", self.code, self.message)
+    }
+}
+
+impl From<PipelineError> for WasmPipelineError {
+    fn from(e: PipelineError) -> Self {
+        let code = match &e {
+            PipelineError::ImageError(_) => "decode_error",
+            PipelineError::InvalidParameter(_) => "invalid_parameter",
+            PipelineError::InvalidSpec(_) => "invalid_spec",
+            PipelineError::InvalidLut(_) => "invalid_lut",
+            PipelineError::InvalidModel(_) => "invalid_model",
+            PipelineError::FormatNotAllowed(_) => "format_not_allowed",
+            PipelineError::ResourceLimit(_) => "resource_limit",
+            PipelineError::Cancelled => "cancelled",
+            PipelineError::ProcessingError(_) => "processing_error",
+            PipelineError::IoError(_) => "io_error",
+        };
+        WasmPipelineError {
+            code,
+            operation: e.operation_index().map(|index| index as u32),
+            message: e.to_string(),
+        }
+    }
+}
+
+fn js_error(e: PipelineError) -> JsValue {
+    WasmPipelineError::from(e).into()
+}
+
+// Error for arguments rejected before reaching the pipeline
+fn invalid(message: impl Into<String>) -> JsValue {
+    WasmPipelineError {
+        code: "invalid_parameter",
+        message: message.into(),
+        operation: None,
+    }
+    .into()
+}
+
+/// Cancellation handle for long-running processor calls
+#[wasm_bindgen]
+#[derive(Default)]
+pub struct WasmCancellationToken {
+    inner: CancellationToken,
+}
+
+#[wasm_bindgen]
+impl WasmCancellationToken {
+    #[wasm_bindgen(constructor)]
+    pub fn new() -> WasmCancellationToken {
+        WasmCancellationToken::default()
+    }
+
+    /// Request cancellation of any call using this token
+    #[wasm_bindgen]
+    pub fn cancel(&self) {
+        self.inner.cancel();
+    }
+
+    /// Whether cancellation has been requested
+    #[wasm_bindgen(getter)]
+    pub fn cancelled(&self) -> bool {
+        self.inner.is_cancelled()
+    }
+}
+
+/// Pipeline compiled once for filtering a stream of same-sized frames (e.g. a webcam)
+#[wasm_bindgen]
+pub struct WasmFrameProcessor {
+    inner: FrameProcessor,
+}
+
+#[wasm_bindgen]
+impl WasmFrameProcessor {
+    /// Compile a JSON pipeline for frames of the given size
+    /// Operations that change the frame size are rejected
+    #[wasm_bindgen(constructor)]
+    pub fn new(width: u32, height: u32, filters_json: &str) -> Result<WasmFrameProcessor, JsValue> {
+        PipelineSpec::from_json(filters_json)
+            .and_then(|spec| spec.compile())
+            .and_then(|operations| FrameProcessor::new(width, height, &operations))
+            .map(|inner| WasmFrameProcessor { inner })
+            .map_err(js_error)
+    }
+
+    /// Filter one RGBA frame in place, e.g. `new Uint8Array(imageData.data.buffer)`
+    #[wasm_bindgen]
+    pub fn process_frame_in_place(&mut self, frame: &mut [u8]) -> Result<(), JsValue> {
+        self.inner.process_frame_in_place(frame).map_err(js_error)
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn width(&self) -> u32 {
+        self.inner.dimensions().0
+    }
+
+    #[wasm_bindgen(getter)]
+    pub fn height(&self) -> u32 {
+        self.inner.dimensions().1
+    }
+}
+
 /// Image processor instance for WebAssembly
+///
+/// Filters edit a working copy. Unless `discard_original` has been called the
+/// image it was created with is kept as well, so edits can be previewed,
+/// reverted and committed without re-uploading pixels from JS.
 #[wasm_bindgen]
 pub struct WasmImageProcessor {
     data: Vec<u8>,
     width: u32,
     height: u32,
+    original: Option<Snapshot>,
+}
+
+// Pixels and size of the image non-destructive edits start from
+struct Snapshot {
+    data: Vec<u8>,
+    width: u32,
+    height: u32,
 }
 
 #[wasm_bindgen]
@@ -20,22 +260,43 @@ impl WasmImageProcessor {
     /// Create a new processor from RGBA image data
     #[wasm_bindgen(constructor)]
     pub fn new(data: &[u8], width: u32, height: u32) -> Result<WasmImageProcessor, JsValue> {
-        let expected_size = (width * height * 4) as usize;
+        check_dimensions(width, height)?;
+        let expected_size = rgba_len(width, height).map_err(js_error)?;
         if data.len() != expected_size {
-            return Err(JsValue::from_str(&format!(
+            return Err(invalid(format!(
                 "Invalid data size: expected {}, got {}",
                 expected_size,
                 data.len()
             )));
         }
-        
+
         Ok(WasmImageProcessor {
             data: data.to_vec(),
             width,
             height,
+            original: Some(Snapshot {
+                data: data.to_vec(),
+                width,
+                height,
+            }),
         })
     }
 
+    /// Decode an HDR file (Radiance .hdr, OpenEXR, ...) and tone map it to 8-bit
+    /// operator: "reinhard", "aces" or "drago"; exposure is in stops (0 = none)
+    pub fn from_hdr(
+        bytes: &[u8],
+        operator: &str,
+        exposure: f32,
+    ) -> Result<WasmImageProcessor, JsValue> {
+        let operator = ToneMapOperator::from_name(operator)
+            .ok_or_else(|| invalid(format!("Unknown tone map operator: {}", operator)))?;
+        let hdr = tonemap::load_hdr(bytes, &load_options()).map_err(js_error)?;
+        let result = tonemap::tonemap(&hdr, operator, exposure);
+        let (width, height) = result.dimensions();
+        WasmImageProcessor::new(result.as_raw(), width, height)
+    }
+
     /// Get image width
     #[wasm_bindgen(getter)]
     pub fn width(&self) -> u32 {
@@ -55,46 +316,358 @@ impl WasmImageProcessor {
     }
 
     /// Apply grayscale filter
+    /// mode: "bt601", "bt709" (default), "average", "red", "green" or "blue"
     #[wasm_bindgen]
-    pub fn grayscale(&mut self) -> Result<(), JsValue> {
-        self.apply_filter(|img| filters::grayscale(img))
+    pub fn grayscale(&mut self, mode: Option<String>) -> Result<(), JsValue> {
+        let mode = match mode {
+            Some(name) => GrayscaleMode::from_name(&name)
+                .ok_or_else(|| invalid(format!("Unknown grayscale mode: {}", name)))?,
+            None => GrayscaleMode::default(),
+        };
+        self.apply_filter(|img| filters::grayscale_with(img, mode))
     }
 
     /// Apply brightness adjustment (-1.0 to 1.0)
     #[wasm_bindgen]
     pub fn brightness(&mut self, value: f32) -> Result<(), JsValue> {
-        self.apply_filter(|img| filters::brightness(img, value))
+        self.apply_operation(FilterOperation::Brightness(value))
     }
 
     /// Apply contrast adjustment (0.0 to 2.0+)
     #[wasm_bindgen]
     pub fn contrast(&mut self, value: f32) -> Result<(), JsValue> {
-        self.apply_filter(|img| filters::contrast(img, value))
+        self.apply_operation(FilterOperation::Contrast(value))
     }
 
     /// Apply Gaussian blur
     #[wasm_bindgen]
     pub fn blur(&mut self, sigma: f32) -> Result<(), JsValue> {
-        self.apply_filter(|img| filters::blur(img, sigma))
+        self.apply_operation(FilterOperation::Blur(sigma))
     }
 
-    /// Apply sharpening filter
+    /// Apply a fast Gaussian blur approximation (three box blurs), for large sigmas
     #[wasm_bindgen]
-    pub fn sharpen(&mut self) -> Result<(), JsValue> {
-        self.apply_filter(|img| filters::sharpen(img))
+    pub fn blur_approximate(&mut self, sigma: f32) -> Result<(), JsValue> {
+        self.apply_operation(FilterOperation::ApproximateBlur(sigma))
+    }
+
+    /// Apply unsharp mask sharpening (defaults: amount 1.5, radius 1.0, threshold 0)
+    #[wasm_bindgen]
+    pub fn sharpen(
+        &mut self,
+        amount: Option<f32>,
+        radius: Option<f32>,
+        threshold: Option<u8>,
+    ) -> Result<(), JsValue> {
+        self.apply_operation(FilterOperation::Sharpen {
+            amount: amount.unwrap_or(SHARPEN_AMOUNT),
+            radius: radius.unwrap_or(SHARPEN_RADIUS),
+            threshold: threshold.unwrap_or(0),
+        })
     }
 
     /// Apply edge detection (Sobel)
     #[wasm_bindgen]
     pub fn edge_detect(&mut self) -> Result<(), JsValue> {
-        self.apply_filter(|img| filters::edge_detect(img))
+        self.apply_filter(filters::edge_detect)
+    }
+
+    /// Edge detection with options
+    /// border: "zero" or "replicate"; output: "magnitude" or "direction";
+    /// threshold keeps only gradients at least that strong
+    #[wasm_bindgen]
+    pub fn edge_detect_with(
+        &mut self,
+        border: &str,
+        output: &str,
+        threshold: Option<u8>,
+    ) -> Result<(), JsValue> {
+        let border = match border {
+            "zero" => EdgeBorder::Zero,
+            "replicate" => EdgeBorder::Replicate,
+            other => return Err(invalid(format!("Unknown edge border: {}", other))),
+        };
+        let output = match output {
+            "magnitude" => EdgeOutput::Magnitude,
+            "direction" => EdgeOutput::Direction,
+            other => return Err(invalid(format!("Unknown edge output: {}", other))),
+        };
+        let options = EdgeOptions {
+            border,
+            output,
+            threshold,
+        };
+        self.apply_filter(|img| filters::edge_detect_with(img, &options))
     }
 
     /// Resize image
+    /// filter: "nearest", "bilinear", "catmull_rom" or "lanczos3" (default)
+    #[wasm_bindgen]
+    pub fn resize(
+        &mut self,
+        new_width: u32,
+        new_height: u32,
+        filter: Option<String>,
+    ) -> Result<(), JsValue> {
+        let filter = match filter {
+            Some(name) => ResampleFilter::from_name(&name)
+                .ok_or_else(|| invalid(format!("Unknown resample filter: {}", name)))?,
+            None => ResampleFilter::default(),
+        };
+        check_dimensions(new_width, new_height)?;
+        let img = self.to_image()?;
+        let result = mixing(&img, |img| {
+            filters::resize(img, new_width, new_height, filter)
+        });
+        self.width = new_width;
+        self.height = new_height;
+        self.data = result.into_raw();
+        Ok(())
+    }
+
+    /// Enlarge by factor (1 to 16); method: "detail" (default, sharpened without halos) or "lanczos"
+    #[wasm_bindgen]
+    pub fn upscale(&mut self, factor: f32, method: Option<String>) -> Result<(), JsValue> {
+        let method = match method.as_deref() {
+            None | Some("detail") => UpscaleMethod::Detail,
+            Some("lanczos") => UpscaleMethod::Lanczos,
+            Some(other) => return Err(invalid(format!("Unknown upscale method: {}", other))),
+        };
+        let img = self.to_image()?;
+        let result = new_pipeline()
+            .process(&img, &[FilterOperation::Upscale { factor, method }])
+            .map_err(js_error)?;
+        self.width = result.width();
+        self.height = result.height();
+        self.data = result.into_raw();
+        Ok(())
+    }
+
+    /// Corner-pin the image so the `src` quad lands on the `dst` quad
+    /// Each quad is 8 numbers `[x0, y0, x1, y1, x2, y2, x3, y3]`;
+    /// interpolation: "nearest", "bilinear" (default) or "bicubic"
+    #[wasm_bindgen]
+    pub fn warp_perspective(
+        &mut self,
+        src: &[f32],
+        dst: &[f32],
+        interpolation: Option<String>,
+    ) -> Result<(), JsValue> {
+        let to_quad = |points: &[f32]| -> Result<image_pipeline::Quad, JsValue> {
+            if points.len() != 8 {
+                return Err(invalid("A quad needs exactly 8 coordinates"));
+            }
+            Ok([0, 1, 2, 3].map(|i| (points[2 * i], points[2 * i + 1])))
+        };
+        let interpolation = match interpolation {
+            Some(name) => Interpolation::from_name(&name)
+                .ok_or_else(|| invalid(format!("Unknown interpolation: {}", name)))?,
+            None => Interpolation::default(),
+        };
+        let img = self.to_image()?;
+        let result = filters::warp_perspective(&img, &to_quad(src)?, &to_quad(dst)?, interpolation)
+            .map_err(js_error)?;
+        self.data = result.into_raw();
+        Ok(())
+    }
+
+    /// Fit into max_width x max_height preserving aspect ratio
+    /// fit: "contain", "cover" or "pad"; background is 0xRRGGBBAA and only used by "pad"
+    #[wasm_bindgen]
+    pub fn thumbnail(
+        &mut self,
+        max_width: u32,
+        max_height: u32,
+        fit: &str,
+        background: u32,
+    ) -> Result<(), JsValue> {
+        if max_width == 0 || max_height == 0 {
+            return Err(invalid("Thumbnail size must be at least 1x1"));
+        }
+        let fit = match fit {
+            "contain" => FitMode::Contain,
+            "cover" => FitMode::Cover,
+            "pad" => FitMode::Pad(image::Rgba(background.to_be_bytes())),
+            other => return Err(invalid(format!("Unknown fit mode: {}", other))),
+        };
+
+        check_dimensions(max_width, max_height)?;
+        let img = self.to_image()?;
+        let result = mixing(&img, |img| {
+            filters::thumbnail(img, max_width, max_height, fit)
+        });
+        self.width = result.width();
+        self.height = result.height();
+        self.data = result.into_raw();
+        Ok(())
+    }
+
+    /// Rotate clockwise by any angle with bilinear sampling
+    /// background is 0xRRGGBBAA; expand grows the canvas to fit the rotated image
     #[wasm_bindgen]
-    pub fn resize(&mut self, new_width: u32, new_height: u32) -> Result<(), JsValue> {
+    pub fn rotate(&mut self, degrees: f32, background: u32, expand: bool) -> Result<(), JsValue> {
+        if !degrees.is_finite() {
+            return Err(invalid("degrees must be a finite number"));
+        }
         let img = self.to_image()?;
-        let result = filters::resize(&img, new_width, new_height);
+        let rotate = FilterOperation::Rotate {
+            degrees,
+            background: image::Rgba(background.to_be_bytes()),
+            expand,
+        };
+        let result = new_pipeline().process(&img, &[rotate]).map_err(js_error)?;
+        self.width = result.width();
+        self.height = result.height();
+        self.data = result.into_raw();
+        Ok(())
+    }
+
+    /// Apply a 2x3 affine matrix `[a, b, c, d, e, f]` in one resampling pass
+    /// interpolation: "nearest", "bilinear" (default) or "bicubic"; background is 0xRRGGBBAA
+    #[wasm_bindgen]
+    pub fn affine(
+        &mut self,
+        matrix: &[f32],
+        interpolation: Option<String>,
+        background: u32,
+    ) -> Result<(), JsValue> {
+        let matrix: [f32; 6] = matrix
+            .try_into()
+            .map_err(|_| invalid("An affine matrix needs exactly 6 numbers"))?;
+        let interpolation = match interpolation {
+            Some(name) => Interpolation::from_name(&name)
+                .ok_or_else(|| invalid(format!("Unknown interpolation: {}", name)))?,
+            None => Interpolation::default(),
+        };
+        let img = self.to_image()?;
+        let result = filters::affine(
+            &img,
+            matrix,
+            interpolation,
+            image::Rgba(background.to_be_bytes()),
+        )
+        .map_err(js_error)?;
+        self.data = result.into_raw();
+        Ok(())
+    }
+
+    /// Undo radial lens distortion; k1, k2, k3 are the lens coefficients (negative k1 = barrel)
+    #[wasm_bindgen]
+    pub fn lens_correct(&mut self, k1: f32, k2: f32, k3: f32) -> Result<(), JsValue> {
+        self.apply_filter(|img| filters::lens_correct(img, k1, k2, k3))
+    }
+
+    /// Add radial lens distortion, the inverse of lens_correct (negative k1 = fisheye bulge)
+    #[wasm_bindgen]
+    pub fn lens_distort(&mut self, k1: f32, k2: f32, k3: f32) -> Result<(), JsValue> {
+        self.apply_filter(|img| filters::lens_distort(img, k1, k2, k3))
+    }
+
+    /// Shift red and blue radially by the given pixels at the corners
+    /// Positive shifts simulate chromatic aberration, negative ones correct fringing
+    #[wasm_bindgen]
+    pub fn chromatic_aberration(&mut self, red_shift: f32, blue_shift: f32) -> Result<(), JsValue> {
+        self.apply_filter(|img| filters::chromatic_aberration(img, red_shift, blue_shift))
+    }
+
+    /// Make pixels near key_color (0xRRGGBBAA, alpha ignored) transparent, e.g. a webcam green screen
+    /// tolerance and softness are chroma distances from 0.0 to 1.0
+    #[wasm_bindgen]
+    pub fn chroma_key(
+        &mut self,
+        key_color: u32,
+        tolerance: f32,
+        softness: f32,
+    ) -> Result<(), JsValue> {
+        if !(0.0..=1.0).contains(&tolerance) || !(0.0..=1.0).contains(&softness) {
+            return Err(invalid(
+                "tolerance and softness must be between 0.0 and 1.0",
+            ));
+        }
+        self.apply_filter(|img| {
+            filters::chroma_key(
+                img,
+                image::Rgba(key_color.to_be_bytes()),
+                tolerance,
+                softness,
+            )
+        })
+    }
+
+    /// Blur the background, keeping the subject sharp
+    /// mask: one byte per pixel or RGBA (alpha is used, e.g. a chroma_key result); omit to estimate the subject
+    #[wasm_bindgen]
+    pub fn portrait_blur(
+        &mut self,
+        sigma: f32,
+        feather: f32,
+        mask: Option<Vec<u8>>,
+    ) -> Result<(), JsValue> {
+        let mask = mask
+            .map(|mask| Region::mask_from_raw(self.width, self.height, &mask))
+            .transpose()
+            .map_err(js_error)?;
+        let portrait = FilterOperation::PortraitBlur {
+            mask,
+            sigma,
+            feather,
+        };
+        let img = self.to_image()?;
+        let result = new_pipeline()
+            .process(&img, &[portrait])
+            .map_err(js_error)?;
+        self.data = result.into_raw();
+        Ok(())
+    }
+
+    /// Draw another processor's image over this one as a watermark
+    /// anchor: "top_left", "top", ..., "bottom_right" (default); margin: pixels from the edges and between tiles
+    /// opacity: 0.0-1.0; scale: mark width as a fraction of the image width, omit for its own size
+    #[wasm_bindgen]
+    pub fn watermark(
+        &mut self,
+        mark: &WasmImageProcessor,
+        anchor: Option<String>,
+        margin: u32,
+        opacity: f32,
+        tile: bool,
+        scale: Option<f32>,
+    ) -> Result<(), JsValue> {
+        let mark = mark.to_image()?;
+        let anchor = match anchor {
+            Some(name) => Anchor::from_name(&name)
+                .ok_or_else(|| invalid(format!("Unknown anchor: {}", name)))?,
+            None => Anchor::default(),
+        };
+        let img = self.to_image()?;
+        let result = compose::watermark(&img, &mark, anchor, margin, opacity, tile, scale)
+            .map_err(js_error)?;
+        self.data = result.into_raw();
+        Ok(())
+    }
+
+    /// Crop to the target aspect ratio around the most detailed region, then resize
+    #[wasm_bindgen]
+    pub fn smart_crop(&mut self, target_width: u32, target_height: u32) -> Result<(), JsValue> {
+        if target_width == 0 || target_height == 0 {
+            return Err(invalid("Crop size must be at least 1x1"));
+        }
+        let img = self.to_image()?;
+        let result = image_pipeline::smart_crop(&img, target_width, target_height);
+        self.width = result.width();
+        self.height = result.height();
+        self.data = result.into_raw();
+        Ok(())
+    }
+
+    /// Content-aware resize (seam carving); much slower than `resize`
+    #[wasm_bindgen]
+    pub fn seam_carve(&mut self, new_width: u32, new_height: u32) -> Result<(), JsValue> {
+        if new_width == 0 || new_height == 0 {
+            return Err(invalid("Size must be at least 1x1"));
+        }
+        let img = self.to_image()?;
+        let result = filters::seam_carve(&img, new_width, new_height);
         self.width = new_width;
         self.height = new_height;
         self.data = result.into_raw();
@@ -104,13 +677,74 @@ impl WasmImageProcessor {
     /// Invert colors
     #[wasm_bindgen]
     pub fn invert(&mut self) -> Result<(), JsValue> {
-        self.apply_filter(|img| filters::invert(img))
+        self.apply_filter(filters::invert)
     }
 
     /// Apply sepia tone
     #[wasm_bindgen]
     pub fn sepia(&mut self) -> Result<(), JsValue> {
-        self.apply_filter(|img| filters::sepia(img))
+        self.apply_filter(filters::sepia)
+    }
+
+    /// Mix channels with a 4x5 color matrix given as 20 row-major numbers, like SVG feColorMatrix
+    #[wasm_bindgen]
+    pub fn color_matrix(&mut self, values: Vec<f32>) -> Result<(), JsValue> {
+        if values.len() != 20 {
+            return Err(invalid(format!(
+                "Color matrix needs 20 values, got {}",
+                values.len()
+            )));
+        }
+        let mut matrix = [[0.0f32; 5]; 4];
+        for (row, chunk) in matrix.iter_mut().zip(values.chunks(5)) {
+            row.copy_from_slice(chunk);
+        }
+        self.apply_filter(|img| filters::color_matrix(img, matrix))
+    }
+
+    /// Apply gamma correction (> 0.0, 1.0 = no change)
+    #[wasm_bindgen]
+    pub fn gamma(&mut self, value: f32) -> Result<(), JsValue> {
+        self.apply_lut(&Lut256::gamma(value))
+    }
+
+    /// Apply brightness, contrast and gamma together as one lookup table
+    ///
+    /// Cheap enough to call on every frame of a slider drag.
+    #[wasm_bindgen]
+    pub fn adjust(&mut self, brightness: f32, contrast: f32, gamma: f32) -> Result<(), JsValue> {
+        let lut = Lut256::brightness(brightness)
+            .then(&Lut256::contrast(contrast))
+            .then(&Lut256::gamma(gamma));
+        self.apply_lut(&lut)
+    }
+
+    /// Pixelate into square blocks (block_size >= 1)
+    #[wasm_bindgen]
+    pub fn pixelate(&mut self, block_size: u32) -> Result<(), JsValue> {
+        if block_size == 0 {
+            return Err(invalid("block_size must be at least 1"));
+        }
+        self.apply_filter(|img| mixing(img, |img| filters::pixelate(img, block_size)))
+    }
+
+    /// Straighten a skewed document scan, filling corners with the paper color
+    #[wasm_bindgen]
+    pub fn deskew(&mut self) -> Result<(), JsValue> {
+        self.apply_filter(filters::deskew)
+    }
+
+    /// Add film grain; the same seed always produces the same grain
+    #[wasm_bindgen]
+    pub fn add_noise(&mut self, amount: f32, monochrome: bool, seed: u32) -> Result<(), JsValue> {
+        self.apply_filter(|img| filters::add_noise(img, amount, monochrome, seed as u64))
+    }
+
+    /// Apply a 3D color LUT given the text of a .cube file
+    #[wasm_bindgen]
+    pub fn apply_cube(&mut self, cube: &str) -> Result<(), JsValue> {
+        let lut = Lut3D::parse_cube(cube).map_err(js_error)?;
+        self.apply_filter(|img| lut.apply(img))
     }
 
     /// Apply multiple filters in sequence
@@ -118,39 +752,532 @@ impl WasmImageProcessor {
     pub fn apply_filters(&mut self, filters_json: &str) -> Result<(), JsValue> {
         // Parse JSON array of filter operations
         // Format: [{"type": "grayscale"}, {"type": "brightness", "value": 0.2}]
-        let operations = parse_filter_json(filters_json)?;
-        
+        let operations = PipelineSpec::from_json(filters_json)
+            .and_then(|spec| spec.compile())
+            .map_err(js_error)?;
+
+        let img = self.to_image()?;
+        let pipeline = new_pipeline();
+
+        let result = pipeline.process(&img, &operations).map_err(js_error)?;
+
+        self.width = result.width();
+        self.height = result.height();
+        self.data = result.into_raw();
+
+        Ok(())
+    }
+
+    /// Apply filters like `apply_filters` and return a JSON report of the run
+    ///
+    /// The report lists each step's name, backend, `elapsed_ms`, input and
+    /// output size and `buffer_bytes`, plus `total_ms`, `peak_buffer_bytes`
+    /// and the output `width` and `height`.
+    #[wasm_bindgen]
+    pub fn apply_filters_with_metrics(&mut self, filters_json: &str) -> Result<String, JsValue> {
+        let operations = PipelineSpec::from_json(filters_json)
+            .and_then(|spec| spec.compile())
+            .map_err(js_error)?;
+
+        let img = self.to_image()?;
+        let (result, report) = new_pipeline()
+            .process_with_metrics(&img, &operations)
+            .map_err(js_error)?;
+
+        self.width = result.width();
+        self.height = result.height();
+        self.data = result.into_raw();
+
+        report.to_json().map_err(js_error)
+    }
+
+    /// Apply a preset, given as preset JSON or the name of a built-in preset
+    #[wasm_bindgen]
+    pub fn apply_preset(&mut self, preset: &str) -> Result<(), JsValue> {
+        let preset = if preset.trim_start().starts_with('{') {
+            Preset::from_json(preset).map_err(js_error)?
+        } else {
+            presets::find_builtin(preset)
+                .ok_or_else(|| invalid(format!("Unknown preset: {}", preset)))?
+        };
+        let operations = preset.compile().map_err(js_error)?;
+
         let img = self.to_image()?;
-        let pipeline = ImagePipeline::new();
-        
-        let result = pipeline.process(&img, &operations)
-            .map_err(|e| JsValue::from_str(&e.to_string()))?;
-        
+        let result = new_pipeline()
+            .process(&img, &operations)
+            .map_err(js_error)?;
+
         self.width = result.width();
         self.height = result.height();
         self.data = result.into_raw();
-        
+
         Ok(())
     }
 
-    /// Reset to original data (requires keeping original)
+    /// Apply filters only where `mask` is set
+    ///
+    /// `mask` is either one byte per pixel or RGBA data (e.g. from a painted
+    /// canvas), in which case its alpha channel is used as the weight.
+    #[wasm_bindgen]
+    pub fn apply_filters_masked(&mut self, filters_json: &str, mask: &[u8]) -> Result<(), JsValue> {
+        let region = Region::mask_from_raw(self.width, self.height, mask).map_err(js_error)?;
+        self.apply_in_region(filters_json, region)
+    }
+
+    /// Apply filters only inside a rectangle
+    #[wasm_bindgen]
+    pub fn apply_filters_in_rect(
+        &mut self,
+        filters_json: &str,
+        x: u32,
+        y: u32,
+        width: u32,
+        height: u32,
+    ) -> Result<(), JsValue> {
+        self.apply_in_region(
+            filters_json,
+            Region::Rect {
+                x,
+                y,
+                width,
+                height,
+            },
+        )
+    }
+
+    /// Apply filters only over detected faces, e.g. `[{"type":"pixelate","block_size":12}]` to redact them
+    /// `padding` grows each face by that fraction of its size on every side.
+    /// Returns how many faces were found; with none the image is left unchanged
+    #[wasm_bindgen]
+    pub fn apply_filters_to_faces(
+        &mut self,
+        filters_json: &str,
+        padding: f32,
+    ) -> Result<u32, JsValue> {
+        let faces: Vec<_> = analysis::detect_faces(&self.to_image()?)
+            .iter()
+            .map(|face| face.padded(padding, self.width, self.height))
+            .collect();
+        if !faces.is_empty() {
+            self.apply_in_region(
+                filters_json,
+                analysis::faces_region(self.width, self.height, &faces),
+            )?;
+        }
+        Ok(faces.len() as u32)
+    }
+
+    /// Apply multiple filters, calling `callback(opIndex, fractionComplete)` after each one
+    /// Return `false` from the callback to cancel; the image is left unchanged
+    #[wasm_bindgen]
+    pub fn apply_filters_with_progress(
+        &mut self,
+        filters_json: &str,
+        callback: &js_sys::Function,
+    ) -> Result<(), JsValue> {
+        let operations = PipelineSpec::from_json(filters_json)
+            .and_then(|spec| spec.compile())
+            .map_err(js_error)?;
+
+        let img = self.to_image()?;
+        let pipeline = new_pipeline();
+        let mut callback_error = None;
+
+        let result = pipeline.process_with_progress(&img, &operations, |index, fraction| {
+            match callback.call2(
+                &JsValue::NULL,
+                &JsValue::from(index as u32),
+                &JsValue::from(fraction),
+            ) {
+                Ok(ret) => ret.as_bool() != Some(false),
+                Err(e) => {
+                    callback_error = Some(e);
+                    false
+                }
+            }
+        });
+
+        if let Some(e) = callback_error {
+            return Err(e);
+        }
+        let result = result.map_err(js_error)?;
+
+        self.width = result.width();
+        self.height = result.height();
+        self.data = result.into_raw();
+
+        Ok(())
+    }
+
+    /// Apply multiple filters, aborting when `token.cancel()` is called
+    /// Rejects with "Operation cancelled" and leaves the image unchanged on cancellation
+    #[wasm_bindgen]
+    pub fn apply_filters_cancellable(
+        &mut self,
+        filters_json: &str,
+        token: &WasmCancellationToken,
+    ) -> Result<(), JsValue> {
+        let operations = PipelineSpec::from_json(filters_json)
+            .and_then(|spec| spec.compile())
+            .map_err(js_error)?;
+
+        let img = self.to_image()?;
+        let pipeline = new_pipeline();
+
+        let result = pipeline
+            .process_cancellable(&img, &operations, &token.inner)
+            .map_err(js_error)?;
+
+        self.width = result.width();
+        self.height = result.height();
+        self.data = result.into_raw();
+
+        Ok(())
+    }
+
+    /// Average hash of the working copy (64 bits, as a BigInt)
+    #[wasm_bindgen]
+    pub fn ahash(&self) -> Result<u64, JsValue> {
+        Ok(analysis::ahash(&self.to_image()?))
+    }
+
+    /// Difference hash of the working copy (64 bits, as a BigInt)
+    #[wasm_bindgen]
+    pub fn dhash(&self) -> Result<u64, JsValue> {
+        Ok(analysis::dhash(&self.to_image()?))
+    }
+
+    /// DCT-based perceptual hash of the working copy (64 bits, as a BigInt)
+    #[wasm_bindgen]
+    pub fn phash(&self) -> Result<u64, JsValue> {
+        Ok(analysis::phash(&self.to_image()?))
+    }
+
+    /// Up to `count` dominant colors of the working copy, most common first
+    /// Returns an array of `{ color: "#rrggbb", fraction }` objects
+    #[wasm_bindgen]
+    pub fn dominant_colors(&self, count: usize) -> Result<js_sys::Array, JsValue> {
+        let palette = analysis::dominant_colors(&self.to_image()?, count);
+        let result = js_sys::Array::new();
+        for entry in palette {
+            let object = js_sys::Object::new();
+            js_sys::Reflect::set(&object, &"color".into(), &entry.to_hex().into())?;
+            js_sys::Reflect::set(&object, &"fraction".into(), &entry.fraction.into())?;
+            result.push(&object);
+        }
+        Ok(result)
+    }
+
+    /// BlurHash placeholder string of the working copy (components 1-9, 4x3 is typical)
+    #[wasm_bindgen]
+    pub fn blurhash(&self, x_components: u32, y_components: u32) -> Result<String, JsValue> {
+        analysis::blurhash(&self.to_image()?, x_components, y_components).map_err(js_error)
+    }
+
+    /// ThumbHash placeholder bytes of the working copy
+    #[wasm_bindgen]
+    pub fn thumbhash(&self) -> Result<Vec<u8>, JsValue> {
+        Ok(analysis::thumbhash(&self.to_image()?))
+    }
+
+    /// Per-channel statistics of the working copy
+    /// Returns `{ pixelCount, red, green, blue, alpha, luma }`, each channel being
+    /// `{ mean, stddev, min, max, median, entropy }`
+    #[wasm_bindgen]
+    pub fn stats(&self) -> Result<js_sys::Object, JsValue> {
+        let stats = analysis::stats(&self.to_image()?);
+        let result = js_sys::Object::new();
+        js_sys::Reflect::set(
+            &result,
+            &"pixelCount".into(),
+            &(stats.pixel_count as f64).into(),
+        )?;
+        for (name, channel) in [
+            ("red", &stats.red),
+            ("green", &stats.green),
+            ("blue", &stats.blue),
+            ("alpha", &stats.alpha),
+            ("luma", &stats.luma),
+        ] {
+            let object = js_sys::Object::new();
+            js_sys::Reflect::set(&object, &"mean".into(), &channel.mean.into())?;
+            js_sys::Reflect::set(&object, &"stddev".into(), &channel.stddev.into())?;
+            js_sys::Reflect::set(&object, &"min".into(), &channel.min.into())?;
+            js_sys::Reflect::set(&object, &"max".into(), &channel.max.into())?;
+            js_sys::Reflect::set(&object, &"median".into(), &channel.median.into())?;
+            js_sys::Reflect::set(&object, &"entropy".into(), &channel.entropy.into())?;
+            js_sys::Reflect::set(&result, &name.into(), &object)?;
+        }
+        Ok(result)
+    }
+
+    /// Straight lines in the working copy, strongest first
+    /// Pixels with Sobel magnitude >= `edge_threshold` count as edges; a line needs
+    /// at least `threshold` of them. Returns `{ rho, angle, votes }` objects where
+    /// `angle` is the normal's angle in degrees (90 = horizontal line)
+    #[wasm_bindgen]
+    pub fn hough_lines(
+        &self,
+        edge_threshold: u8,
+        threshold: u32,
+    ) -> Result<js_sys::Array, JsValue> {
+        let edges = analysis::edge_map(&self.to_image()?, edge_threshold);
+        let result = js_sys::Array::new();
+        for line in analysis::hough_lines(&edges, threshold) {
+            let object = js_sys::Object::new();
+            js_sys::Reflect::set(&object, &"rho".into(), &line.rho.into())?;
+            js_sys::Reflect::set(&object, &"angle".into(), &line.angle.into())?;
+            js_sys::Reflect::set(&object, &"votes".into(), &line.votes.into())?;
+            result.push(&object);
+        }
+        Ok(result)
+    }
+
+    /// Circles with radius in `min_radius..=max_radius`, strongest first
+    /// `threshold` is how many of 90 sampled circumference points must be edges.
+    /// Returns `{ x, y, radius, votes }` objects
+    #[wasm_bindgen]
+    pub fn hough_circles(
+        &self,
+        edge_threshold: u8,
+        min_radius: u32,
+        max_radius: u32,
+        threshold: u32,
+    ) -> Result<js_sys::Array, JsValue> {
+        let edges = analysis::edge_map(&self.to_image()?, edge_threshold);
+        let result = js_sys::Array::new();
+        for circle in analysis::hough_circles(&edges, min_radius..=max_radius, threshold) {
+            let object = js_sys::Object::new();
+            js_sys::Reflect::set(&object, &"x".into(), &circle.x.into())?;
+            js_sys::Reflect::set(&object, &"y".into(), &circle.y.into())?;
+            js_sys::Reflect::set(&object, &"radius".into(), &circle.radius.into())?;
+            js_sys::Reflect::set(&object, &"votes".into(), &circle.votes.into())?;
+            result.push(&object);
+        }
+        Ok(result)
+    }
+
+    /// Estimated skew of a document scan in degrees (positive = clockwise)
+    #[wasm_bindgen]
+    pub fn estimate_skew(&self) -> Result<f32, JsValue> {
+        Ok(analysis::estimate_skew(&self.to_image()?))
+    }
+
+    /// QR codes in the working copy
+    /// Returns `{ text, payload, corners, version }` objects; `text` is `undefined` when
+    /// the payload is not UTF-8, and `corners` is `[[x, y], ...]` from the code's top-left clockwise
+    #[wasm_bindgen]
+    pub fn decode_qr(&self) -> Result<js_sys::Array, JsValue> {
+        let result = js_sys::Array::new();
+        for code in analysis::decode_qr(&self.to_image()?) {
+            let object = js_sys::Object::new();
+            let corners = js_sys::Array::new();
+            for (x, y) in code.corners {
+                corners.push(&js_sys::Array::of2(&x.into(), &y.into()));
+            }
+            js_sys::Reflect::set(
+                &object,
+                &"text".into(),
+                &code.text().map_or(JsValue::UNDEFINED, JsValue::from),
+            )?;
+            js_sys::Reflect::set(
+                &object,
+                &"payload".into(),
+                &js_sys::Uint8Array::from(code.payload.as_slice()),
+            )?;
+            js_sys::Reflect::set(&object, &"corners".into(), &corners)?;
+            js_sys::Reflect::set(&object, &"version".into(), &code.version.into())?;
+            result.push(&object);
+        }
+        Ok(result)
+    }
+
+    /// Hide `payload` in the image without visible change; survives JPEG re-encoding
+    /// strength: 0.25-4.0, 1.0 is invisible in photos
+    #[wasm_bindgen]
+    pub fn embed_invisible(&mut self, payload: &[u8], strength: f32) -> Result<(), JsValue> {
+        let img = self.to_image()?;
+        let result = watermark::embed_invisible(&img, payload, strength).map_err(js_error)?;
+        self.data = result.into_raw();
+        Ok(())
+    }
+
+    /// Payload hidden by `embed_invisible`, or `undefined` if there is no intact mark
+    #[wasm_bindgen]
+    pub fn extract_invisible(&self) -> Result<Option<Vec<u8>>, JsValue> {
+        Ok(watermark::extract_invisible(&self.to_image()?))
+    }
+
+    /// Bounding boxes of the faces in the working copy, largest first
+    /// Returns `{ x, y, width, height }` objects
+    #[wasm_bindgen]
+    pub fn detect_faces(&self) -> Result<js_sys::Array, JsValue> {
+        let result = js_sys::Array::new();
+        for face in analysis::detect_faces(&self.to_image()?) {
+            let object = js_sys::Object::new();
+            js_sys::Reflect::set(&object, &"x".into(), &face.x.into())?;
+            js_sys::Reflect::set(&object, &"y".into(), &face.y.into())?;
+            js_sys::Reflect::set(&object, &"width".into(), &face.width.into())?;
+            js_sys::Reflect::set(&object, &"height".into(), &face.height.into())?;
+            result.push(&object);
+        }
+        Ok(result)
+    }
+
+    /// Shift the image so it lines up with `reference` (RGBA data of the same size)
+    /// Returns the estimated offset as `{ dx, dy, confidence }`
+    #[wasm_bindgen]
+    pub fn align_to(&mut self, reference: &[u8]) -> Result<js_sys::Object, JsValue> {
+        let reference = image::RgbaImage::from_raw(self.width, self.height, reference.to_vec())
+            .ok_or_else(|| invalid("Reference must be RGBA data of the same size"))?;
+        let (aligned, alignment) =
+            align::align(&reference, &self.to_image()?, image::Rgba([0; 4])).map_err(js_error)?;
+        self.data = aligned.into_raw();
+
+        let result = js_sys::Object::new();
+        js_sys::Reflect::set(&result, &"dx".into(), &alignment.dx.into())?;
+        js_sys::Reflect::set(&result, &"dy".into(), &alignment.dy.into())?;
+        js_sys::Reflect::set(&result, &"confidence".into(), &alignment.confidence.into())?;
+        Ok(result)
+    }
+
+    /// Replace the image with new data, which also becomes the original
     #[wasm_bindgen]
     pub fn reset(&mut self, data: &[u8], width: u32, height: u32) -> Result<(), JsValue> {
-        let expected_size = (width * height * 4) as usize;
+        let expected_size = rgba_len(width, height).map_err(js_error)?;
         if data.len() != expected_size {
-            return Err(JsValue::from_str("Invalid data size"));
+            return Err(invalid(format!(
+                "Invalid data size: expected {}, got {}",
+                expected_size,
+                data.len()
+            )));
         }
-        
+
         self.data = data.to_vec();
         self.width = width;
         self.height = height;
+        if self.original.is_some() {
+            self.original = Some(Snapshot {
+                data: data.to_vec(),
+                width,
+                height,
+            });
+        }
+        Ok(())
+    }
+
+    /// Whether the original image is still kept
+    #[wasm_bindgen(getter)]
+    pub fn has_original(&self) -> bool {
+        self.original.is_some()
+    }
+
+    /// Drop the stored original to halve memory use; the non-destructive
+    /// methods (`reset_to_original`, `commit`, `preview`) then fail
+    #[wasm_bindgen]
+    pub fn discard_original(&mut self) {
+        self.original = None;
+    }
+
+    /// Throw away all edits since creation or the last `commit`
+    #[wasm_bindgen]
+    pub fn reset_to_original(&mut self) -> Result<(), JsValue> {
+        let original = self.original()?;
+        let (data, width, height) = (original.data.clone(), original.width, original.height);
+        self.data = data;
+        self.width = width;
+        self.height = height;
+        Ok(())
+    }
+
+    /// Make the working copy the new original
+    #[wasm_bindgen]
+    pub fn commit(&mut self) -> Result<(), JsValue> {
+        self.original()?;
+        self.original = Some(Snapshot {
+            data: self.data.clone(),
+            width: self.width,
+            height: self.height,
+        });
         Ok(())
     }
 
+    /// Render `filters_json` from the original into the working copy
+    ///
+    /// Each call starts over from the original, so calling it repeatedly while a
+    /// slider moves never compounds edits. With `max_dimension` the result is a
+    /// downscaled preview (see `ImagePipeline::preview`); call again without it
+    /// before `commit` to keep full resolution.
+    #[wasm_bindgen]
+    pub fn preview(
+        &mut self,
+        filters_json: &str,
+        max_dimension: Option<u32>,
+    ) -> Result<(), JsValue> {
+        let operations = PipelineSpec::from_json(filters_json)
+            .and_then(|spec| spec.compile())
+            .map_err(js_error)?;
+
+        let original = self.original()?;
+        let img =
+            image::RgbaImage::from_raw(original.width, original.height, original.data.clone())
+                .ok_or_else(|| invalid("Failed to create image from data"))?;
+        let pipeline = new_pipeline();
+
+        let result = match max_dimension {
+            Some(max_dimension) => pipeline.preview(&img, &operations, max_dimension),
+            None => pipeline.process(&img, &operations),
+        }
+        .map_err(js_error)?;
+
+        self.width = result.width();
+        self.height = result.height();
+        self.data = result.into_raw();
+
+        Ok(())
+    }
+
+    // Helper to get the original, failing if it was discarded
+    fn original(&self) -> Result<&Snapshot, JsValue> {
+        self.original
+            .as_ref()
+            .ok_or_else(|| invalid("Original image was discarded"))
+    }
+
     // Helper to convert internal data to RgbaImage
     fn to_image(&self) -> Result<image::RgbaImage, JsValue> {
         image::RgbaImage::from_raw(self.width, self.height, self.data.clone())
-            .ok_or_else(|| JsValue::from_str("Failed to create image from data"))
+            .ok_or_else(|| invalid("Failed to create image from data"))
+    }
+
+    // Helper to run a spec with every operation restricted to `region`
+    fn apply_in_region(&mut self, filters_json: &str, region: Region) -> Result<(), JsValue> {
+        let operations: Vec<FilterOperation> = PipelineSpec::from_json(filters_json)
+            .and_then(|spec| spec.compile())
+            .map_err(js_error)?
+            .into_iter()
+            .map(|op| FilterOperation::Masked {
+                region: region.clone(),
+                op: Box::new(op),
+            })
+            .collect();
+
+        let img = self.to_image()?;
+        let result = new_pipeline()
+            .process(&img, &operations)
+            .map_err(js_error)?;
+        self.data = result.into_raw();
+        Ok(())
+    }
+
+    // Helper to apply a lookup table in place, without copying the buffer
+    fn apply_lut(&mut self, lut: &Lut256) -> Result<(), JsValue> {
+        let data = std::mem::take(&mut self.data);
+        let result = lut
+            .apply_raw(self.width, self.height, data)
+            .ok_or_else(|| invalid("Failed to create image from data"))?;
+        self.data = result.into_raw();
+        Ok(())
     }
 
     // Helper to apply a filter function
@@ -163,79 +1290,255 @@ impl WasmImageProcessor {
         self.data = result.into_raw();
         Ok(())
     }
-}
 
-/// Parse JSON filter configuration
-fn parse_filter_json(json: &str) -> Result<Vec<FilterOperation>, JsValue> {
-    // Simple JSON parsing without serde (to keep WASM size small)
-    let mut operations = Vec::new();
-    
-    // Basic parsing - in production, use serde_json with wasm feature
-    let json = json.trim();
-    if !json.starts_with('[') || !json.ends_with(']') {
-        return Err(JsValue::from_str("Invalid JSON: expected array"));
-    }
-    
-    // Extract individual filter objects
-    let inner = &json[1..json.len()-1];
-    
-    for part in inner.split("},") {
-        let part = part.trim().trim_start_matches('{').trim_end_matches('}').trim();
-        if part.is_empty() {
-            continue;
-        }
-        
-        if let Some(op) = parse_single_filter(part) {
-            operations.push(op);
-        }
+    // Run `op` through a pipeline, which rejects invalid parameters
+    fn apply_operation(&mut self, op: FilterOperation) -> Result<(), JsValue> {
+        let result = new_pipeline()
+            .process(&self.to_image()?, &[op])
+            .map_err(js_error)?;
+        self.width = result.width();
+        self.height = result.height();
+        self.data = result.into_raw();
+        Ok(())
     }
-    
-    Ok(operations)
-}
-
-fn parse_single_filter(s: &str) -> Option<FilterOperation> {
-    // Extract type field
-    if s.contains("\"grayscale\"") {
-        Some(FilterOperation::Grayscale)
-    } else if s.contains("\"invert\"") {
-        Some(FilterOperation::Invert)
-    } else if s.contains("\"sepia\"") {
-        Some(FilterOperation::Sepia)
-    } else if s.contains("\"sharpen\"") {
-        Some(FilterOperation::Sharpen)
-    } else if s.contains("\"edge_detect\"") {
-        Some(FilterOperation::EdgeDetect)
-    } else if s.contains("\"brightness\"") {
-        extract_f32_value(s, "value").map(FilterOperation::Brightness)
-    } else if s.contains("\"contrast\"") {
-        extract_f32_value(s, "value").map(FilterOperation::Contrast)
-    } else if s.contains("\"blur\"") {
-        extract_f32_value(s, "sigma").or_else(|| extract_f32_value(s, "value"))
-            .map(FilterOperation::Blur)
-    } else if s.contains("\"resize\"") {
-        let width = extract_u32_value(s, "width")?;
-        let height = extract_u32_value(s, "height")?;
-        Some(FilterOperation::Resize { width, height })
+}
+
+/// Number of differing bits between two perceptual hashes
+#[wasm_bindgen]
+pub fn hamming_distance(a: u64, b: u64) -> u32 {
+    analysis::hamming_distance(a, b)
+}
+
+/// Merge differently exposed, aligned shots into one well-exposed image (Mertens fusion)
+/// frames: array of RGBA Uint8Arrays, all width x height
+#[wasm_bindgen]
+pub fn exposure_fusion(
+    frames: &js_sys::Array,
+    width: u32,
+    height: u32,
+) -> Result<WasmImageProcessor, JsValue> {
+    let images = frames_to_images(frames, width, height)?;
+    let result = compose::exposure_fusion(&images).map_err(js_error)?;
+    WasmImageProcessor::new(result.as_raw(), width, height)
+}
+
+/// Combine frames of one scene to reduce noise
+/// method: "mean", "median" or "sigma_clip"; align: register frames onto the first one first
+#[wasm_bindgen]
+pub fn stack(
+    frames: &js_sys::Array,
+    width: u32,
+    height: u32,
+    method: &str,
+    align: Option<bool>,
+) -> Result<WasmImageProcessor, JsValue> {
+    let method = compose::StackMethod::from_name(method)
+        .ok_or_else(|| invalid(format!("Unknown stack method: {}", method)))?;
+    let images = frames_to_images(frames, width, height)?;
+    let result = if align.unwrap_or(false) {
+        compose::stack_aligned(&images, method)
     } else {
-        None
+        compose::stack(&images, method)
+    }
+    .map_err(js_error)?;
+    WasmImageProcessor::new(result.as_raw(), width, height)
+}
+
+/// Square `size` x `size` QR code encoding `text`, e.g. to composite onto a poster
+/// ec_level: "low", "medium" (default), "quartile" or "high"; dark/light: 0xRRGGBBAA
+#[wasm_bindgen]
+pub fn qr_code(
+    text: &str,
+    size: u32,
+    ec_level: Option<String>,
+    dark: Option<u32>,
+    light: Option<u32>,
+) -> Result<WasmImageProcessor, JsValue> {
+    let ec_level = match ec_level {
+        Some(name) => generate::QrErrorCorrection::from_name(&name)
+            .ok_or_else(|| invalid(format!("Unknown error correction level: {}", name)))?,
+        None => generate::QrErrorCorrection::default(),
+    };
+    let defaults = generate::QrColors::default();
+    let colors = generate::QrColors {
+        dark: dark.map_or(defaults.dark, |c| image::Rgba(c.to_be_bytes())),
+        light: light.map_or(defaults.light, |c| image::Rgba(c.to_be_bytes())),
+    };
+    let result = generate::qr_code(text, size, ec_level, colors).map_err(js_error)?;
+    WasmImageProcessor::new(result.as_raw(), size, size)
+}
+
+// Decode a JS array of RGBA Uint8Arrays of one size
+fn frames_to_images(
+    frames: &js_sys::Array,
+    width: u32,
+    height: u32,
+) -> Result<Vec<image::RgbaImage>, JsValue> {
+    frames
+        .iter()
+        .enumerate()
+        .map(|(i, frame)| {
+            let data = js_sys::Uint8Array::new(&frame).to_vec();
+            image::RgbaImage::from_raw(width, height, data).ok_or_else(|| {
+                invalid(format!("Frame {} is not {}x{} RGBA data", i, width, height))
+            })
+        })
+        .collect()
+}
+
+/// Apply a pipeline to every frame of an animated GIF, keeping timing and loop count
+/// Returns the encoded GIF
+#[wasm_bindgen]
+pub fn process_gif(bytes: &[u8], filters_json: &str) -> Result<Vec<u8>, JsValue> {
+    let operations = PipelineSpec::from_json(filters_json)
+        .and_then(|spec| spec.compile())
+        .map_err(js_error)?;
+    Animation::decode_gif(bytes)
+        .and_then(|animation| animation.process(&new_pipeline(), &operations))
+        .and_then(|animation| animation.encode_gif())
+        .map_err(js_error)
+}
+
+/// Re-encode an untrusted upload without metadata, extra chunks or trailing data
+/// Returns { bytes, mimeType, removed, trailingBytes, orientationApplied, droppedFrames }
+#[wasm_bindgen]
+pub fn sanitize(bytes: &[u8]) -> Result<js_sys::Object, JsValue> {
+    let (clean, report) = ImagePipeline::sanitize(bytes).map_err(js_error)?;
+    let removed: js_sys::Array = report
+        .removed
+        .iter()
+        .map(|name| JsValue::from(name.as_str()))
+        .collect();
+    let result = js_sys::Object::new();
+    js_sys::Reflect::set(
+        &result,
+        &"bytes".into(),
+        &js_sys::Uint8Array::from(clean.as_slice()),
+    )?;
+    js_sys::Reflect::set(
+        &result,
+        &"mimeType".into(),
+        &report.format.mime_type().into(),
+    )?;
+    js_sys::Reflect::set(&result, &"removed".into(), &removed)?;
+    js_sys::Reflect::set(
+        &result,
+        &"trailingBytes".into(),
+        &(report.trailing_bytes as f64).into(),
+    )?;
+    js_sys::Reflect::set(
+        &result,
+        &"orientationApplied".into(),
+        &report.orientation_applied.into(),
+    )?;
+    js_sys::Reflect::set(
+        &result,
+        &"droppedFrames".into(),
+        &report.dropped_frames.into(),
+    )?;
+    Ok(result)
+}
+
+/// Encode a frame sequence as an animation
+/// format: "gif", "webp" or "apng"; delays: per-frame durations in milliseconds
+/// loop_count: extra plays after the first (default: loop forever)
+#[wasm_bindgen]
+pub fn encode_animation(
+    frames: js_sys::Array,
+    width: u32,
+    height: u32,
+    delays: &[u32],
+    format: &str,
+    loop_count: Option<u16>,
+) -> Result<Vec<u8>, JsValue> {
+    let images = frames_to_images(&frames, width, height)?;
+    if delays.len() != images.len() {
+        return Err(invalid(format!(
+            "Expected {} delays, got {}",
+            images.len(),
+            delays.len()
+        )));
     }
+    let animation = Animation {
+        frames: images
+            .into_iter()
+            .zip(delays)
+            .map(|(image, &delay_ms)| AnimationFrame { image, delay_ms })
+            .collect(),
+        repeat: loop_count.map_or(Repeat::Infinite, Repeat::Finite),
+    };
+    let encoded = match format.to_ascii_lowercase().as_str() {
+        "gif" => animation.encode_gif(),
+        "webp" => animation.encode_webp(),
+        "apng" | "png" => animation.encode_apng(),
+        other => return Err(invalid(format!("Unknown animation format: {}", other))),
+    };
+    encoded.map_err(js_error)
 }
 
-fn extract_f32_value(s: &str, key: &str) -> Option<f32> {
-    let pattern = format!("\"{}\":", key);
-    let idx = s.find(&pattern)?;
-    let rest = &s[idx + pattern.len()..];
-    let rest = rest.trim();
-    
-    // Find the end of the number
-    let end = rest.find(|c: char| !c.is_numeric() && c != '.' && c != '-')
-        .unwrap_or(rest.len());
-    
-    rest[..end].trim().parse().ok()
+/// JSON description of every operation: parameter names, types, ranges and defaults
+/// Use it to build filter controls; the ranges match what the pipeline accepts
+#[wasm_bindgen]
+pub fn get_filter_registry() -> String {
+    image_pipeline::registry::registry_json()
+}
+
+/// Save a JSON pipeline as a named preset; returns the preset JSON
+#[wasm_bindgen]
+pub fn save_preset(name: &str, filters_json: &str) -> Result<String, JsValue> {
+    let operations = PipelineSpec::from_json(filters_json)
+        .and_then(|spec| spec.compile())
+        .map_err(js_error)?;
+    if name.trim().is_empty() {
+        return Err(invalid("Preset name must not be empty"));
+    }
+    Ok(Preset::new(name, &operations).to_json())
 }
 
-fn extract_u32_value(s: &str, key: &str) -> Option<u32> {
-    extract_f32_value(s, key).map(|v| v as u32)
+/// JSON array of the built-in presets
+#[wasm_bindgen]
+pub fn list_presets() -> String {
+    let items: Vec<String> = presets::builtin().iter().map(Preset::to_json).collect();
+    format!("[{}]", items.join(","))
+}
+
+// Buffers one formatted trace event and writes it to the browser console
+#[derive(Default)]
+struct ConsoleWriter(Vec<u8>);
+
+impl std::io::Write for ConsoleWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+impl Drop for ConsoleWriter {
+    fn drop(&mut self) {
+        let line = String::from_utf8_lossy(&self.0);
+        web_sys::console::log_1(&JsValue::from_str(line.trim_end()));
+    }
+}
+
+/// Forward pipeline traces to console.log
+/// level: "error", "warn", "info", "debug" (one line per operation with size, backend and elapsed_ms) or "trace"
+/// Only the first call installs the forwarder; later calls are ignored
+#[wasm_bindgen]
+pub fn enable_tracing(level: &str) -> Result<(), JsValue> {
+    let level: tracing::Level = level
+        .parse()
+        .map_err(|_| invalid(format!("unknown trace level \"{}\"", level)))?;
+    // Timestamps need the system clock, which is unavailable in the browser
+    let _ = tracing_subscriber::fmt()
+        .with_max_level(level)
+        .without_time()
+        .with_writer(ConsoleWriter::default)
+        .try_init();
+    Ok(())
 }
 
 /// Get library version
@@ -248,19 +1551,26 @@ pub fn get_version() -> String {
 #[wasm_bindgen]
 pub fn quick_grayscale(data: &[u8], width: u32, height: u32) -> Result<Vec<u8>, JsValue> {
     let img = image::RgbaImage::from_raw(width, height, data.to_vec())
-        .ok_or_else(|| JsValue::from_str("Invalid image data"))?;
-    
+        .ok_or_else(|| invalid("Invalid image data"))?;
+
     let result = filters::grayscale(&img);
     Ok(result.into_raw())
 }
 
 /// Quick brightness adjustment
 #[wasm_bindgen]
-pub fn quick_brightness(data: &[u8], width: u32, height: u32, value: f32) -> Result<Vec<u8>, JsValue> {
+pub fn quick_brightness(
+    data: &[u8],
+    width: u32,
+    height: u32,
+    value: f32,
+) -> Result<Vec<u8>, JsValue> {
     let img = image::RgbaImage::from_raw(width, height, data.to_vec())
-        .ok_or_else(|| JsValue::from_str("Invalid image data"))?;
-    
-    let result = filters::brightness(&img, value);
+        .ok_or_else(|| invalid("Invalid image data"))?;
+
+    let result = new_pipeline()
+        .process(&img, &[FilterOperation::Brightness(value)])
+        .map_err(js_error)?;
     Ok(result.into_raw())
 }
 
@@ -268,8 +1578,10 @@ pub fn quick_brightness(data: &[u8], width: u32, height: u32, value: f32) -> Res
 #[wasm_bindgen]
 pub fn quick_blur(data: &[u8], width: u32, height: u32, sigma: f32) -> Result<Vec<u8>, JsValue> {
     let img = image::RgbaImage::from_raw(width, height, data.to_vec())
-        .ok_or_else(|| JsValue::from_str("Invalid image data"))?;
-    
-    let result = filters::blur(&img, sigma);
+        .ok_or_else(|| invalid("Invalid image data"))?;
+
+    let result = new_pipeline()
+        .process(&img, &[FilterOperation::Blur(sigma)])
+        .map_err(js_error)?;
     Ok(result.into_raw())
 }
@@ -0,0 +1,16 @@
+//! Inputs shared by the benchmark targets
+
+use image::RgbaImage;
+use image_pipeline::generate;
+
+/// Resolutions every filter is measured at
+pub const RESOLUTIONS: [(&str, u32, u32); 3] = [
+    ("720p", 1280, 720),
+    ("1080p", 1920, 1080),
+    ("4k", 3840, 2160),
+];
+
+/// Deterministic input with flat areas, gradients and fine detail
+pub fn input(width: u32, height: u32) -> RgbaImage {
+    generate::test_pattern(width, height)
+}
@@ -0,0 +1,122 @@
+//! Per-filter throughput at 720p, 1080p and 4K
+//!
+//! Run with `cargo bench -p image-pipeline --bench filters`; pass a filter name
+//! (e.g. `-- blur`) to measure a single group.
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use image::{Rgba, RgbaImage};
+use image_pipeline::{
+    filters, smart_crop, FitMode, Lut3D, ResampleFilter, SHARPEN_AMOUNT, SHARPEN_RADIUS,
+};
+use std::time::Duration;
+
+mod common;
+use common::{input, RESOLUTIONS};
+
+type Filter = Box<dyn Fn(&RgbaImage) -> RgbaImage>;
+
+fn point_filters() -> Vec<(&'static str, Filter)> {
+    let lut = Lut3D::identity(33);
+    vec![
+        ("grayscale", Box::new(filters::grayscale)),
+        ("brightness", Box::new(|img| filters::brightness(img, 0.2))),
+        ("contrast", Box::new(|img| filters::contrast(img, 1.3))),
+        ("gamma", Box::new(|img| filters::gamma(img, 2.2))),
+        ("invert", Box::new(filters::invert)),
+        ("sepia", Box::new(filters::sepia)),
+        ("lut3d", Box::new(move |img| lut.apply(img))),
+        (
+            "noise",
+            Box::new(|img| filters::add_noise(img, 0.1, false, 7)),
+        ),
+        ("pixelate", Box::new(|img| filters::pixelate(img, 16))),
+    ]
+}
+
+fn neighborhood_filters() -> Vec<(&'static str, Filter)> {
+    vec![
+        ("blur", Box::new(|img| filters::blur(img, 2.0))),
+        ("blur_large", Box::new(|img| filters::blur(img, 20.0))),
+        (
+            "blur_approximate_large",
+            Box::new(|img| filters::blur_approximate(img, 20.0)),
+        ),
+        (
+            "sharpen",
+            Box::new(|img| filters::sharpen(img, SHARPEN_AMOUNT, SHARPEN_RADIUS, 0)),
+        ),
+        ("edge_detect", Box::new(filters::edge_detect)),
+    ]
+}
+
+fn resampling_filters() -> Vec<(&'static str, Filter)> {
+    let mut list: Vec<(&'static str, Filter)> = [
+        ResampleFilter::Nearest,
+        ResampleFilter::Bilinear,
+        ResampleFilter::CatmullRom,
+        ResampleFilter::Lanczos3,
+    ]
+    .into_iter()
+    .map(|filter| {
+        let resize: Filter = Box::new(move |img: &RgbaImage| {
+            filters::resize(img, img.width() / 2, img.height() / 2, filter)
+        });
+        (filter.name(), resize)
+    })
+    .collect();
+    list.push((
+        "thumbnail_pad",
+        Box::new(|img| filters::thumbnail(img, 512, 512, FitMode::Pad(Rgba([0; 4])))),
+    ));
+    list.push(("smart_crop", Box::new(|img| smart_crop(img, 512, 512))));
+    list
+}
+
+fn bench_group(c: &mut Criterion, group_name: &str, filters: Vec<(&'static str, Filter)>) {
+    let mut group = c.benchmark_group(group_name);
+    group.sample_size(10);
+    group.measurement_time(Duration::from_secs(3));
+
+    for (label, width, height) in RESOLUTIONS {
+        let image = input(width, height);
+        group.throughput(Throughput::Elements(width as u64 * height as u64));
+        for (name, filter) in &filters {
+            group.bench_with_input(BenchmarkId::new(*name, label), &image, |b, img| {
+                b.iter(|| filter(img))
+            });
+        }
+    }
+    group.finish();
+}
+
+fn bench_point(c: &mut Criterion) {
+    bench_group(c, "point", point_filters());
+}
+
+fn bench_neighborhood(c: &mut Criterion) {
+    bench_group(c, "neighborhood", neighborhood_filters());
+}
+
+fn bench_resampling(c: &mut Criterion) {
+    bench_group(c, "resampling", resampling_filters());
+}
+
+// Seam carving is quadratic in the number of seams, so it only runs at 720p
+fn bench_seam_carve(c: &mut Criterion) {
+    let mut group = c.benchmark_group("seam_carve");
+    group.sample_size(10);
+    let image = input(1280, 720);
+    group.bench_function("720p_minus_5pct", |b| {
+        b.iter(|| filters::seam_carve(&image, 1216, 720))
+    });
+    group.finish();
+}
+
+criterion_group!(
+    benches,
+    bench_point,
+    bench_neighborhood,
+    bench_resampling,
+    bench_seam_carve
+);
+criterion_main!(benches);
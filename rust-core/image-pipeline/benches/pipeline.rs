@@ -0,0 +1,75 @@
+//! Whole-pipeline scenarios, with and without point-operation fusion
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use image_pipeline::{FilterOperation, ImagePipeline};
+use std::time::Duration;
+
+mod common;
+use common::{input, RESOLUTIONS};
+
+fn scenarios() -> Vec<(&'static str, Vec<FilterOperation>)> {
+    vec![
+        (
+            "color_grade",
+            vec![
+                FilterOperation::Brightness(0.1),
+                FilterOperation::Contrast(1.2),
+                FilterOperation::Gamma(1.1),
+                FilterOperation::Invert,
+            ],
+        ),
+        (
+            "mixed",
+            vec![
+                FilterOperation::Gamma(2.2),
+                FilterOperation::Brightness(-0.05),
+                FilterOperation::Sepia,
+                FilterOperation::Blur(1.5),
+                FilterOperation::Contrast(1.1),
+            ],
+        ),
+    ]
+}
+
+fn bench_fusion(c: &mut Criterion) {
+    let mut group = c.benchmark_group("pipeline");
+    group.sample_size(10);
+    group.measurement_time(Duration::from_secs(3));
+
+    let fused = ImagePipeline::new();
+    let mut unfused = ImagePipeline::new();
+    unfused.fuse_operations = false;
+
+    for (label, width, height) in RESOLUTIONS {
+        let image = input(width, height);
+        group.throughput(Throughput::Elements(width as u64 * height as u64));
+        for (name, ops) in scenarios() {
+            for (mode, pipeline) in [("fused", &fused), ("unfused", &unfused)] {
+                let id = BenchmarkId::new(format!("{name}/{mode}"), label);
+                group.bench_with_input(id, &image, |b, img| {
+                    b.iter(|| pipeline.process(img, &ops).unwrap())
+                });
+            }
+        }
+    }
+    group.finish();
+}
+
+fn bench_preview(c: &mut Criterion) {
+    let mut group = c.benchmark_group("preview");
+    group.sample_size(10);
+    let image = input(3840, 2160);
+    let ops = [FilterOperation::Blur(8.0), FilterOperation::Contrast(1.2)];
+    let pipeline = ImagePipeline::new();
+    for max_dimension in [512, 1024] {
+        group.bench_with_input(
+            BenchmarkId::new("4k_blur", max_dimension),
+            &max_dimension,
+            |b, &max| b.iter(|| pipeline.preview(&image, &ops, max).unwrap()),
+        );
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_fusion, bench_preview);
+criterion_main!(benches);
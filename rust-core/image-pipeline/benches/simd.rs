@@ -0,0 +1,49 @@
+//! `simd` kernels against their scalar references
+//!
+//! Requires `--features bench-internals`.
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use image_pipeline::{internals, simd, GrayscaleMode};
+
+mod common;
+use common::{input, RESOLUTIONS};
+
+fn bench_kernels(c: &mut Criterion) {
+    type Kernel = fn(&mut [u8]);
+    let kernels: [(&str, Kernel, Kernel); 3] = [
+        (
+            "grayscale",
+            |p| simd::grayscale_fast(p, GrayscaleMode::Bt709),
+            |p| internals::grayscale_scalar(p, GrayscaleMode::Bt709),
+        ),
+        (
+            "brightness",
+            |p| simd::brightness_simd(p, 30),
+            |p| internals::brightness_scalar(p, 30),
+        ),
+        ("invert", simd::invert_simd, internals::invert_scalar),
+    ];
+
+    let mut group = c.benchmark_group("simd");
+    group.sample_size(20);
+    for (label, width, height) in RESOLUTIONS {
+        let pixels = input(width, height).into_raw();
+        group.throughput(Throughput::Bytes(pixels.len() as u64));
+        for (name, fast, scalar) in kernels {
+            for (mode, kernel) in [("simd", fast), ("scalar", scalar)] {
+                let id = BenchmarkId::new(format!("{name}/{mode}"), label);
+                group.bench_with_input(id, &pixels, |b, pixels| {
+                    b.iter_batched_ref(
+                        || pixels.clone(),
+                        |buf| kernel(buf),
+                        criterion::BatchSize::LargeInput,
+                    )
+                });
+            }
+        }
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_kernels);
+criterion_main!(benches);
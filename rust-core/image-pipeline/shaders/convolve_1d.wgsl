@@ -0,0 +1,43 @@
+// Separable convolution pass with clamped edges, matching
+// `apply_convolution_1d_horizontal` / `apply_convolution_1d_vertical` in
+// filters.rs. A Gaussian blur is one horizontal and one vertical dispatch.
+//
+// Pixels are RGBA8 packed into one u32 each (red in the low byte).
+
+struct Params {
+    width: u32,
+    height: u32,
+    radius: u32,
+    // 1 for a horizontal pass, 0 for a vertical one
+    horizontal: u32,
+}
+
+@group(0) @binding(0) var<uniform> params: Params;
+@group(0) @binding(1) var<storage, read> weights: array<f32>;
+@group(0) @binding(2) var<storage, read> src: array<u32>;
+@group(0) @binding(3) var<storage, read_write> dst: array<u32>;
+
+@compute @workgroup_size(16, 16)
+fn main(@builtin(global_invocation_id) id: vec3<u32>) {
+    if (id.x >= params.width || id.y >= params.height) {
+        return;
+    }
+
+    let radius = i32(params.radius);
+    var sum = vec4<f32>(0.0);
+    for (var i = -radius; i <= radius; i++) {
+        var x = i32(id.x);
+        var y = i32(id.y);
+        if (params.horizontal != 0u) {
+            x = clamp(x + i, 0, i32(params.width) - 1);
+        } else {
+            y = clamp(y + i, 0, i32(params.height) - 1);
+        }
+        let pixel = unpack4x8unorm(src[u32(y) * params.width + u32(x)]) * 255.0;
+        sum += pixel * weights[u32(i + radius)];
+    }
+
+    // Truncate like the CPU path so both backends produce the same bytes
+    let value = floor(clamp(sum, vec4<f32>(0.0), vec4<f32>(255.0)));
+    dst[id.y * params.width + id.x] = pack4x8unorm(value / 255.0);
+}
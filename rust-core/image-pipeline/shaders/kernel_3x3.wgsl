@@ -0,0 +1,42 @@
+// 3x3 convolution on RGB with clamped edges; alpha is copied unchanged.
+// Used for sharpening, embossing and other small custom kernels.
+
+struct Params {
+    width: u32,
+    height: u32,
+    // Added to every channel after convolution (e.g. 128 for emboss)
+    bias: f32,
+    _padding: u32,
+    // Rows of the kernel; the fourth component of each row is unused
+    kernel: array<vec4<f32>, 3>,
+}
+
+@group(0) @binding(0) var<uniform> params: Params;
+@group(0) @binding(1) var<storage, read> src: array<u32>;
+@group(0) @binding(2) var<storage, read_write> dst: array<u32>;
+
+fn load(x: i32, y: i32) -> vec4<f32> {
+    let cx = u32(clamp(x, 0, i32(params.width) - 1));
+    let cy = u32(clamp(y, 0, i32(params.height) - 1));
+    return unpack4x8unorm(src[cy * params.width + cx]) * 255.0;
+}
+
+@compute @workgroup_size(16, 16)
+fn main(@builtin(global_invocation_id) id: vec3<u32>) {
+    if (id.x >= params.width || id.y >= params.height) {
+        return;
+    }
+
+    let x = i32(id.x);
+    let y = i32(id.y);
+    var sum = vec3<f32>(params.bias);
+    for (var ky = 0; ky < 3; ky++) {
+        for (var kx = 0; kx < 3; kx++) {
+            sum += load(x + kx - 1, y + ky - 1).rgb * params.kernel[ky][kx];
+        }
+    }
+
+    let rgb = floor(clamp(sum, vec3<f32>(0.0), vec3<f32>(255.0)));
+    let alpha = load(x, y).a;
+    dst[id.y * params.width + id.x] = pack4x8unorm(vec4<f32>(rgb, alpha) / 255.0);
+}
@@ -0,0 +1,44 @@
+// Fused point operations: per-channel lookup tables followed by an optional
+// 3x3 color matrix, mirroring `FusedPointOps` in optimize.rs. Runs of
+// brightness, contrast, gamma and invert fold into the tables; grayscale and
+// sepia become the matrix. Longer programs (a table after a matrix) run as
+// several dispatches over the same buffer.
+
+struct Params {
+    width: u32,
+    height: u32,
+    // 1 when `matrix` should be applied after the tables
+    use_matrix: u32,
+    _padding: u32,
+    // Rows of the color matrix; the fourth component of each row is unused
+    matrix: array<vec4<f32>, 3>,
+}
+
+@group(0) @binding(0) var<uniform> params: Params;
+// 256 entries each for red, green and blue, one byte value per u32
+@group(0) @binding(1) var<storage, read> tables: array<u32, 768>;
+@group(0) @binding(2) var<storage, read_write> pixels: array<u32>;
+
+@compute @workgroup_size(256)
+fn main(@builtin(global_invocation_id) id: vec3<u32>) {
+    let index = id.x;
+    if (index >= params.width * params.height) {
+        return;
+    }
+
+    let pixel = unpack4x8unorm(pixels[index]) * 255.0;
+    var rgb = vec3<f32>(
+        f32(tables[u32(pixel.r)]),
+        f32(tables[256u + u32(pixel.g)]),
+        f32(tables[512u + u32(pixel.b)]),
+    );
+    if (params.use_matrix != 0u) {
+        let m = params.matrix;
+        rgb = floor(clamp(
+            vec3<f32>(dot(m[0].xyz, rgb), dot(m[1].xyz, rgb), dot(m[2].xyz, rgb)),
+            vec3<f32>(0.0),
+            vec3<f32>(255.0),
+        ));
+    }
+    pixels[index] = pack4x8unorm(vec4<f32>(rgb, pixel.a) / 255.0);
+}
@@ -0,0 +1,42 @@
+// Bilinear resize with pixel-center alignment and clamped edges.
+// Downscaling by more than 2x should go through repeated halving (or a
+// blur pass first) to avoid aliasing, as the CPU resampler's wider
+// filters do implicitly.
+
+struct Params {
+    src_width: u32,
+    src_height: u32,
+    dst_width: u32,
+    dst_height: u32,
+}
+
+@group(0) @binding(0) var<uniform> params: Params;
+@group(0) @binding(1) var<storage, read> src: array<u32>;
+@group(0) @binding(2) var<storage, read_write> dst: array<u32>;
+
+fn load(x: i32, y: i32) -> vec4<f32> {
+    let cx = u32(clamp(x, 0, i32(params.src_width) - 1));
+    let cy = u32(clamp(y, 0, i32(params.src_height) - 1));
+    return unpack4x8unorm(src[cy * params.src_width + cx]);
+}
+
+@compute @workgroup_size(16, 16)
+fn main(@builtin(global_invocation_id) id: vec3<u32>) {
+    if (id.x >= params.dst_width || id.y >= params.dst_height) {
+        return;
+    }
+
+    let scale = vec2<f32>(
+        f32(params.src_width) / f32(params.dst_width),
+        f32(params.src_height) / f32(params.dst_height),
+    );
+    let position = (vec2<f32>(id.xy) + 0.5) * scale - 0.5;
+    let base = floor(position);
+    let t = position - base;
+    let x = i32(base.x);
+    let y = i32(base.y);
+
+    let top = mix(load(x, y), load(x + 1, y), t.x);
+    let bottom = mix(load(x, y + 1), load(x + 1, y + 1), t.x);
+    dst[id.y * params.dst_width + id.x] = pack4x8unorm(mix(top, bottom, t.y));
+}
@@ -0,0 +1,345 @@
+//! Image registration: finding and undoing the shift between two shots
+//!
+//! Translations are estimated by phase correlation, which is robust to
+//! exposure differences and noise, making it suitable ahead of stacking,
+//! exposure fusion and before/after comparisons.
+
+use crate::geometry::{self, Interpolation, Transform};
+use crate::{PipelineError, Result};
+use image::imageops::{self, FilterType};
+use image::{Rgba, RgbaImage};
+use std::f64::consts::PI;
+
+/// Longest side of the copies the correlation runs on
+const ALIGN_SAMPLE_SIZE: u32 = 512;
+/// Frequency (cycles per pixel) where the correlation weighting falls to 1/e
+const LOW_PASS_CUTOFF: f64 = 0.15;
+
+/// Offset of one image relative to another
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Alignment {
+    /// Horizontal offset in pixels; positive when the content moved right
+    pub dx: f32,
+    /// Vertical offset in pixels; positive when the content moved down
+    pub dy: f32,
+    /// Height of the correlation peak (0 to 1); values near 0 mean no reliable match
+    pub confidence: f32,
+}
+
+impl Alignment {
+    /// Transform moving the shifted image back onto the reference
+    pub fn to_transform(&self) -> Transform {
+        Transform::translation(-self.dx as f64, -self.dy as f64)
+    }
+}
+
+/// Estimate how far the content of `moving` is shifted relative to `reference`
+///
+/// Works on luma, downscaled to at most 512 pixels per side; the result is
+/// scaled back to full resolution with sub-pixel precision. Both images must
+/// have the same size.
+pub fn estimate_translation(reference: &RgbaImage, moving: &RgbaImage) -> Result<Alignment> {
+    if reference.dimensions() != moving.dimensions() {
+        return Err(PipelineError::InvalidParameter(format!(
+            "images must have the same size, got {}x{} and {}x{}",
+            reference.width(),
+            reference.height(),
+            moving.width(),
+            moving.height()
+        )));
+    }
+    let (width, height) = reference.dimensions();
+    if width < 2 || height < 2 {
+        return Err(PipelineError::InvalidParameter(
+            "images must be at least 2x2 to align".to_string(),
+        ));
+    }
+
+    let scale = (ALIGN_SAMPLE_SIZE as f64 / width.max(height) as f64).min(1.0);
+    let sample_size = (
+        crate::scale_dimension(width, scale),
+        crate::scale_dimension(height, scale),
+    );
+    let a = spectrum(reference, sample_size);
+    let b = spectrum(moving, sample_size);
+
+    // Normalized cross-power spectrum; its inverse transform peaks at the shift
+    let (w, h) = (a.width, a.height);
+    let mut cross = Spectrum {
+        width: w,
+        height: h,
+        data: a
+            .data
+            .iter()
+            .zip(&b.data)
+            .enumerate()
+            .map(|(i, (p, q))| {
+                let product = q.mul(p.conj());
+                // Whitening amplifies noise at high frequencies, so taper them off
+                let weight = low_pass(i % w, w) * low_pass(i / w, h);
+                let magnitude = product.norm().max(1e-12) / weight;
+                Complex(product.0 / magnitude, product.1 / magnitude)
+            })
+            .collect(),
+    };
+    cross.fft2(true);
+    // A perfect match peaks at the mean weight; scale so that reads as 1
+    let mean_weight = (0..w).map(|x| low_pass(x, w)).sum::<f64>() / w as f64
+        * (0..h).map(|y| low_pass(y, h)).sum::<f64>()
+        / h as f64;
+    let surface: Vec<f64> = cross.data.iter().map(|c| c.0).collect();
+
+    let (peak, &value) = surface
+        .iter()
+        .enumerate()
+        .max_by(|a, b| a.1.total_cmp(b.1))
+        .unwrap_or((0, &0.0));
+    let (px, py) = (peak % w, peak / w);
+
+    // Parabolic sub-pixel refinement along each axis, with wrap-around
+    let at = |x: usize, y: usize| surface[(y % h) * w + x % w];
+    let refine = |before: f64, after: f64| {
+        let curvature = before - 2.0 * value + after;
+        if curvature.abs() < 1e-12 {
+            0.0
+        } else {
+            (0.5 * (before - after) / curvature).clamp(-0.5, 0.5)
+        }
+    };
+    let fx = refine(at(px + w - 1, py), at(px + 1, py));
+    let fy = refine(at(px, py + h - 1), at(px, py + 1));
+
+    // Peaks past the middle are negative shifts wrapped around
+    let unwrap = |p: usize, n: usize| {
+        if p > n / 2 {
+            p as f64 - n as f64
+        } else {
+            p as f64
+        }
+    };
+    let dx = (unwrap(px, w) + fx) * width as f64 / sample_size.0 as f64;
+    let dy = (unwrap(py, h) + fy) * height as f64 / sample_size.1 as f64;
+
+    Ok(Alignment {
+        dx: dx as f32,
+        dy: dy as f32,
+        confidence: (value / mean_weight).clamp(0.0, 1.0) as f32,
+    })
+}
+
+/// Shift `moving` so it lines up with `reference`
+///
+/// Areas the shifted image no longer covers are filled with `background`.
+/// Returns the aligned image and the estimated offset.
+pub fn align(
+    reference: &RgbaImage,
+    moving: &RgbaImage,
+    background: Rgba<u8>,
+) -> Result<(RgbaImage, Alignment)> {
+    let alignment = estimate_translation(reference, moving)?;
+    let (width, height) = moving.dimensions();
+    let aligned = geometry::warp(
+        moving,
+        &alignment.to_transform(),
+        width,
+        height,
+        Interpolation::Bilinear,
+        background,
+    )?;
+    Ok((aligned, alignment))
+}
+
+// Windowed, zero-mean luma of `image` resized to `size`, zero-padded to powers
+// of two and transformed
+fn spectrum(image: &RgbaImage, size: (u32, u32)) -> Spectrum {
+    let sample = if size == image.dimensions() {
+        image.clone()
+    } else {
+        imageops::resize(image, size.0, size.1, FilterType::Triangle)
+    };
+    let (sw, sh) = (size.0 as usize, size.1 as usize);
+    let luma: Vec<f64> = sample
+        .pixels()
+        .map(|p| 0.2126 * p[0] as f64 + 0.7152 * p[1] as f64 + 0.0722 * p[2] as f64)
+        .collect();
+    let mean = luma.iter().sum::<f64>() / luma.len() as f64;
+
+    // A Hann window suppresses the edge discontinuities the FFT sees as wrap-around
+    let hann = |i: usize, n: usize| 0.5 - 0.5 * (2.0 * PI * i as f64 / (n.max(2) - 1) as f64).cos();
+    let (w, h) = (sw.next_power_of_two(), sh.next_power_of_two());
+    let mut spectrum = Spectrum {
+        width: w,
+        height: h,
+        data: vec![Complex(0.0, 0.0); w * h],
+    };
+    for y in 0..sh {
+        for x in 0..sw {
+            let value = (luma[y * sw + x] - mean) * hann(x, sw) * hann(y, sh);
+            spectrum.data[y * w + x] = Complex(value, 0.0);
+        }
+    }
+    spectrum.fft2(false);
+    spectrum
+}
+
+// Gaussian taper over the signed frequency of FFT bin `k` of `n`
+fn low_pass(k: usize, n: usize) -> f64 {
+    let frequency = k.min(n - k) as f64 / n as f64;
+    (-(frequency / LOW_PASS_CUTOFF).powi(2)).exp()
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Complex(f64, f64);
+
+impl Complex {
+    fn mul(self, other: Complex) -> Complex {
+        Complex(
+            self.0 * other.0 - self.1 * other.1,
+            self.0 * other.1 + self.1 * other.0,
+        )
+    }
+
+    fn conj(self) -> Complex {
+        Complex(self.0, -self.1)
+    }
+
+    fn norm(self) -> f64 {
+        self.0.hypot(self.1)
+    }
+}
+
+// Row-major complex grid whose sides are powers of two
+struct Spectrum {
+    width: usize,
+    height: usize,
+    data: Vec<Complex>,
+}
+
+impl Spectrum {
+    // In-place 2D FFT; the inverse is scaled by 1 / (width * height)
+    fn fft2(&mut self, inverse: bool) {
+        let (w, h) = (self.width, self.height);
+        for row in self.data.chunks_exact_mut(w) {
+            fft(row, inverse);
+        }
+        let mut column = vec![Complex(0.0, 0.0); h];
+        for x in 0..w {
+            for (y, value) in column.iter_mut().enumerate() {
+                *value = self.data[y * w + x];
+            }
+            fft(&mut column, inverse);
+            for (y, value) in column.iter().enumerate() {
+                self.data[y * w + x] = *value;
+            }
+        }
+        if inverse {
+            let n = (w * h) as f64;
+            for value in &mut self.data {
+                *value = Complex(value.0 / n, value.1 / n);
+            }
+        }
+    }
+}
+
+// Iterative radix-2 Cooley-Tukey FFT; `data.len()` must be a power of two
+fn fft(data: &mut [Complex], inverse: bool) {
+    let n = data.len();
+    if n < 2 {
+        return;
+    }
+
+    // Bit-reversal permutation
+    let mut j = 0;
+    for i in 1..n {
+        let mut bit = n >> 1;
+        while j & bit != 0 {
+            j ^= bit;
+            bit >>= 1;
+        }
+        j |= bit;
+        if i < j {
+            data.swap(i, j);
+        }
+    }
+
+    let sign = if inverse { 1.0 } else { -1.0 };
+    let mut len = 2;
+    while len <= n {
+        let angle = sign * 2.0 * PI / len as f64;
+        let step = Complex(angle.cos(), angle.sin());
+        for chunk in data.chunks_exact_mut(len) {
+            let mut twiddle = Complex(1.0, 0.0);
+            let (low, high) = chunk.split_at_mut(len / 2);
+            for (a, b) in low.iter_mut().zip(high.iter_mut()) {
+                let t = b.mul(twiddle);
+                *b = Complex(a.0 - t.0, a.1 - t.1);
+                *a = Complex(a.0 + t.0, a.1 + t.1);
+                twiddle = twiddle.mul(step);
+            }
+        }
+        len <<= 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Blurred random texture shifted by (dx, dy), so the correlation has one clear peak
+    fn scene(dx: f32, dy: f32) -> RgbaImage {
+        let mut rng = crate::filters::SplitMix64::new(7);
+        let noise = RgbaImage::from_fn(200, 160, |_, _| {
+            let v = (rng.next_u64() >> 56) as u8;
+            Rgba([v, v / 2, 255 - v, 255])
+        });
+        let texture = crate::filters::blur(&noise, 2.0);
+        let shift = Transform::translation(dx as f64, dy as f64);
+        let shifted = geometry::warp(
+            &texture,
+            &shift,
+            200,
+            160,
+            Interpolation::Bicubic,
+            Rgba([0; 4]),
+        );
+        imageops::crop_imm(&shifted.unwrap(), 20, 20, 160, 120).to_image()
+    }
+
+    #[test]
+    fn test_fft_round_trip() {
+        let original: Vec<Complex> = (0..16).map(|i| Complex(i as f64, (i * i) as f64)).collect();
+        let mut data = original.clone();
+        fft(&mut data, false);
+        fft(&mut data, true);
+        for (a, b) in data.iter().zip(&original) {
+            assert!((a.0 / 16.0 - b.0).abs() < 1e-9 && (a.1 / 16.0 - b.1).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn test_estimate_translation() {
+        let reference = scene(0.0, 0.0);
+        for (dx, dy) in [(7.0, -4.0), (-12.5, 3.25), (0.0, 0.0)] {
+            let alignment = estimate_translation(&reference, &scene(dx, dy)).unwrap();
+            assert!(
+                (alignment.dx - dx).abs() < 0.3 && (alignment.dy - dy).abs() < 0.3,
+                "expected ({dx}, {dy}), got {alignment:?}"
+            );
+            assert!(alignment.confidence > 0.1);
+        }
+        assert!(estimate_translation(&reference, &RgbaImage::new(10, 10)).is_err());
+    }
+
+    #[test]
+    fn test_align() {
+        let reference = scene(0.0, 0.0);
+        let (aligned, _) = align(&reference, &scene(6.0, 5.0), Rgba([0; 4])).unwrap();
+        // Away from the uncovered border the aligned image matches the reference
+        let max_error = (20..100)
+            .flat_map(|y| (20..140).map(move |x| (x, y)))
+            .map(|(x, y)| aligned.get_pixel(x, y)[0].abs_diff(reference.get_pixel(x, y)[0]))
+            .max()
+            .unwrap();
+        assert!(max_error <= 6, "{max_error}");
+    }
+}
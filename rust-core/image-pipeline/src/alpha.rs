@@ -0,0 +1,348 @@
+//! Premultiplied alpha
+//!
+//! Filters that mix neighboring pixels (blurs, resampling, rotation) must
+//! weight colors by their alpha, or fully transparent pixels - usually black -
+//! bleed into the edges of visible ones as dark fringes. Colors are weighted
+//! by premultiplying them before such filters and dividing by alpha after.
+//!
+//! Blurs, sharpening and resizing premultiply in f32 inside their kernels, so
+//! pixels with little alpha keep their colors; other filters run on an 8-bit
+//! premultiplied copy, which rounds those colors coarsely.
+
+use crate::{filters, CancellationToken, FilterOperation, Result};
+use image::RgbaImage;
+use rayon::prelude::*;
+
+/// How the pipeline interprets the alpha channel
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum AlphaMode {
+    /// Colors are independent of alpha; filters that mix pixels premultiply
+    /// internally
+    #[default]
+    Straight,
+    /// Colors are already multiplied by alpha; color adjustments divide by
+    /// alpha internally
+    Premultiplied,
+    /// Treat alpha as a fourth channel, as filters do on their own
+    Ignore,
+}
+
+impl AlphaMode {
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name {
+            "straight" => Some(Self::Straight),
+            "premultiplied" => Some(Self::Premultiplied),
+            "ignore" => Some(Self::Ignore),
+            _ => None,
+        }
+    }
+
+    // Whether `op` must see colors converted in this mode
+    pub(crate) fn converts(self, op: &FilterOperation) -> bool {
+        match self {
+            AlphaMode::Straight => mixes_pixels(op),
+            AlphaMode::Premultiplied => adjusts_color(op),
+            AlphaMode::Ignore => false,
+        }
+    }
+}
+
+/// Multiply each color by its pixel's alpha
+pub fn premultiply(image: &RgbaImage) -> RgbaImage {
+    let mut result = image.clone();
+    premultiply_in_place(&mut result);
+    result
+}
+
+/// Divide each color by its pixel's alpha, undoing `premultiply`
+///
+/// Colors of fully transparent pixels are lost and become black.
+pub fn unpremultiply(image: &RgbaImage) -> RgbaImage {
+    let mut result = image.clone();
+    unpremultiply_in_place(&mut result);
+    result
+}
+
+/// Run `filter` on a premultiplied copy of `image` and return its output
+/// with straight alpha
+///
+/// Opaque images are passed through untouched.
+pub fn premultiplied<F>(image: &RgbaImage, filter: F) -> RgbaImage
+where
+    F: FnOnce(&RgbaImage) -> RgbaImage,
+{
+    convert(AlphaMode::Straight, true, image, |image| Ok(filter(image)))
+        .expect("the filter cannot fail")
+}
+
+// Run `step` on `image` converted as `mode` requires when `needed`, converting
+// its output back
+pub(crate) fn convert<F>(
+    mode: AlphaMode,
+    needed: bool,
+    image: &RgbaImage,
+    step: F,
+) -> Result<RgbaImage>
+where
+    F: FnOnce(&RgbaImage) -> Result<RgbaImage>,
+{
+    if !needed || is_opaque(image) {
+        return step(image);
+    }
+    match mode {
+        AlphaMode::Straight => {
+            let mut result = step(&premultiply(image))?;
+            unpremultiply_in_place(&mut result);
+            Ok(result)
+        }
+        AlphaMode::Premultiplied => {
+            let mut result = step(&unpremultiply(image))?;
+            premultiply_in_place(&mut result);
+            Ok(result)
+        }
+        AlphaMode::Ignore => step(image),
+    }
+}
+
+// Run `op` on straight colors with a kernel that weights them by alpha in f32,
+// or return `None` if it has no such kernel
+pub(crate) fn weighted(
+    image: &RgbaImage,
+    op: &FilterOperation,
+    token: &CancellationToken,
+) -> Option<Result<RgbaImage>> {
+    let result = match op {
+        FilterOperation::Blur(sigma) => filters::blur_straight(image, *sigma, token),
+        FilterOperation::ApproximateBlur(sigma) => {
+            filters::blur_approximate_straight(image, *sigma, token)
+        }
+        FilterOperation::Sharpen {
+            amount,
+            radius,
+            threshold,
+        } => filters::sharpen_straight(image, *amount, *radius, *threshold, token),
+        FilterOperation::Resize {
+            width,
+            height,
+            filter,
+        } => Ok(filters::resize_straight(image, *width, *height, *filter)),
+        FilterOperation::Masked { region, op } => {
+            return weighted(image, op, token).map(|filtered| region.blend(image, &filtered?));
+        }
+        _ => return None,
+    };
+    Some(result)
+}
+
+pub(crate) fn premultiply_in_place(image: &mut RgbaImage) {
+    image.par_chunks_mut(4).for_each(|pixel| {
+        let alpha = pixel[3] as u32;
+        for channel in &mut pixel[..3] {
+            // Rounded division by 255
+            let value = *channel as u32 * alpha + 128;
+            *channel = ((value + (value >> 8)) >> 8) as u8;
+        }
+    });
+}
+
+pub(crate) fn unpremultiply_in_place(image: &mut RgbaImage) {
+    image.par_chunks_mut(4).for_each(|pixel| {
+        let alpha = pixel[3] as u32;
+        if alpha == 0 {
+            pixel[..3].fill(0);
+        } else if alpha < 255 {
+            for channel in &mut pixel[..3] {
+                *channel = ((*channel as u32 * 255 + alpha / 2) / alpha).min(255) as u8;
+            }
+        }
+    });
+}
+
+pub(crate) fn is_opaque(image: &RgbaImage) -> bool {
+    image.as_raw().par_chunks(4).all(|pixel| pixel[3] == 255)
+}
+
+/// Whether `op` computes output pixels from several input pixels
+pub fn mixes_pixels(op: &FilterOperation) -> bool {
+    match op {
+        FilterOperation::Blur(_)
+        | FilterOperation::ApproximateBlur(_)
+        | FilterOperation::Sharpen { .. }
+        | FilterOperation::Resize { .. }
+        | FilterOperation::Thumbnail { .. }
+        | FilterOperation::SmartCrop { .. }
+        | FilterOperation::SeamCarve { .. }
+        | FilterOperation::Pixelate(_)
+        | FilterOperation::Deskew
+        | FilterOperation::Rotate { .. }
+        | FilterOperation::Affine { .. }
+        | FilterOperation::LensCorrect { .. }
+        | FilterOperation::ChromaticAberration { .. }
+        | FilterOperation::PortraitBlur { .. }
+        | FilterOperation::Model { .. }
+        | FilterOperation::Upscale { .. } => true,
+        FilterOperation::Masked { op, .. } => mixes_pixels(op),
+        _ => false,
+    }
+}
+
+/// Whether `op` adjusts colors one pixel at a time, so it needs straight
+/// colors to give the same result regardless of alpha
+pub fn adjusts_color(op: &FilterOperation) -> bool {
+    match op {
+        FilterOperation::Grayscale(_)
+        | FilterOperation::Brightness(_)
+        | FilterOperation::Contrast(_)
+        | FilterOperation::Invert
+        | FilterOperation::Sepia
+        | FilterOperation::ColorMatrix(_)
+        | FilterOperation::Gamma(_)
+        | FilterOperation::Lut3D(_)
+        | FilterOperation::Noise { .. }
+        | FilterOperation::ChromaKey { .. }
+        | FilterOperation::GradientMap(_)
+        | FilterOperation::SelectiveColor { .. }
+        | FilterOperation::ShadowsHighlights { .. }
+        // Compositing needs straight colors on both sides
+        | FilterOperation::Watermark { .. }
+        | FilterOperation::DropShadow { .. } => true,
+        FilterOperation::Masked { op, .. } => adjusts_color(op),
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{filters, ImagePipeline, ResampleFilter};
+    use image::Rgba;
+
+    // Opaque white on the left, transparent black on the right
+    fn edge() -> RgbaImage {
+        RgbaImage::from_fn(8, 1, |x, _| {
+            if x < 4 {
+                Rgba([255, 255, 255, 255])
+            } else {
+                Rgba([0, 0, 0, 0])
+            }
+        })
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let image = RgbaImage::from_fn(16, 16, |x, y| {
+            Rgba([
+                (x * 16) as u8,
+                (y * 16) as u8,
+                200,
+                (x * 8 + y * 8 + 15) as u8,
+            ])
+        });
+        let premultiplied = premultiply(&image);
+        assert_eq!(*premultiplied.get_pixel(15, 15), Rgba([240, 240, 200, 255]));
+        assert_eq!(
+            premultiply(&RgbaImage::from_pixel(1, 1, Rgba([255, 100, 0, 128])))[(0, 0)],
+            Rgba([128, 50, 0, 128])
+        );
+        let restored = unpremultiply(&premultiplied);
+        for (a, b) in image.pixels().zip(restored.pixels()) {
+            let tolerance = 255 / a[3] as i32 + 1;
+            for c in 0..3 {
+                assert!(
+                    (a[c] as i32 - b[c] as i32).abs() <= tolerance,
+                    "{:?} {:?}",
+                    a,
+                    b
+                );
+            }
+            assert_eq!(a[3], b[3]);
+        }
+    }
+
+    #[test]
+    fn test_blur_has_no_dark_fringe() {
+        let straight = premultiplied(&edge(), |image| filters::blur(image, 1.0));
+        // Partially transparent pixels near the edge keep the white color
+        for pixel in straight.pixels().filter(|p| p[3] > 0) {
+            assert!(pixel[0] >= 254, "{:?}", pixel);
+        }
+        let naive = filters::blur(&edge(), 1.0);
+        assert!(naive.pixels().any(|p| p[3] > 0 && p[0] < 200));
+    }
+
+    #[test]
+    fn test_low_alpha_gradient_keeps_colors() {
+        // A red-to-blue gradient that is barely visible
+        let gradient = RgbaImage::from_fn(32, 8, |x, y| {
+            Rgba([255 - x as u8 * 8, 40, x as u8 * 8, 2 + (y % 3) as u8])
+        });
+        let blur = FilterOperation::Blur(1.0);
+        let blurred = ImagePipeline::new()
+            .process(&gradient, std::slice::from_ref(&blur))
+            .unwrap();
+        // Blurring a linear gradient leaves its colors in place away from the ends
+        for x in 4..28 {
+            for y in 0..8 {
+                let (before, after) = (gradient[(x, y)], blurred[(x, y)]);
+                for c in 0..3 {
+                    assert!(
+                        (before[c] as i32 - after[c] as i32).abs() <= 2,
+                        "({}, {}): {:?} {:?}",
+                        x,
+                        y,
+                        before,
+                        after
+                    );
+                }
+            }
+        }
+        // Premultiplying in 8 bits rounds these colors to a few levels
+        let coarse = premultiplied(&gradient, |image| filters::blur(image, 1.0));
+        assert!(coarse
+            .pixels()
+            .zip(gradient.pixels())
+            .any(|(a, b)| (a[0] as i32 - b[0] as i32).abs() > 20));
+    }
+
+    #[test]
+    fn test_pipeline_modes() {
+        let resize = FilterOperation::Resize {
+            width: 4,
+            height: 1,
+            filter: ResampleFilter::Bilinear,
+        };
+        let run = |mode: AlphaMode, image: &RgbaImage, op: &FilterOperation| {
+            let mut pipeline = ImagePipeline::new();
+            pipeline.alpha_mode = mode;
+            pipeline.process(image, std::slice::from_ref(op)).unwrap()
+        };
+
+        let straight = run(AlphaMode::Straight, &edge(), &resize);
+        assert!(straight.pixels().filter(|p| p[3] > 0).all(|p| p[0] >= 254));
+        assert_eq!(
+            run(AlphaMode::Ignore, &edge(), &resize),
+            filters::resize(&edge(), 4, 1, ResampleFilter::Bilinear)
+        );
+
+        // Color adjustments see straight colors in premultiplied mode
+        let half = RgbaImage::from_pixel(2, 2, Rgba([100, 100, 100, 128]));
+        let inverted = run(AlphaMode::Premultiplied, &half, &FilterOperation::Invert);
+        assert_eq!(
+            inverted,
+            premultiply(&filters::invert(&unpremultiply(&half)))
+        );
+        assert_eq!(
+            run(AlphaMode::Ignore, &half, &FilterOperation::Invert),
+            filters::invert(&half)
+        );
+
+        // Opaque images are unaffected by the mode
+        let opaque =
+            RgbaImage::from_fn(8, 8, |x, y| Rgba([(x * 30) as u8, (y * 30) as u8, 7, 255]));
+        let blur = FilterOperation::Blur(1.5);
+        assert_eq!(
+            run(AlphaMode::Straight, &opaque, &blur),
+            run(AlphaMode::Ignore, &opaque, &blur)
+        );
+    }
+}
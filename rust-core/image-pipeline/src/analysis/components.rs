@@ -0,0 +1,220 @@
+//! Connected-component labeling of binary images
+
+use image::{GrayImage, ImageBuffer, Luma};
+
+/// Which neighbors join two foreground pixels into one component
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Connectivity {
+    /// Edge neighbors only
+    Four,
+    /// Edge and corner neighbors
+    #[default]
+    Eight,
+}
+
+/// One connected region of foreground pixels
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Component {
+    /// Value of this component's pixels in `ConnectedComponents::labels` (from 1)
+    pub label: u32,
+    /// Number of pixels
+    pub area: u64,
+    /// Bounding box
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+    /// Center of mass, in pixel coordinates
+    pub centroid: (f64, f64),
+}
+
+/// Result of `connected_components`
+#[derive(Debug, Clone)]
+pub struct ConnectedComponents {
+    /// Label of every pixel; 0 is background
+    pub labels: ImageBuffer<Luma<u32>, Vec<u32>>,
+    /// Components in raster order of their first pixel
+    pub components: Vec<Component>,
+}
+
+/// Label 8-connected regions of nonzero pixels
+///
+/// Threshold first so that foreground is nonzero and background is 0.
+pub fn connected_components(binary: &GrayImage) -> ConnectedComponents {
+    connected_components_with(binary, Connectivity::Eight)
+}
+
+/// `connected_components` with explicit connectivity
+pub fn connected_components_with(
+    binary: &GrayImage,
+    connectivity: Connectivity,
+) -> ConnectedComponents {
+    let (width, height) = binary.dimensions();
+    let (w, h) = (width as usize, height as usize);
+    let foreground = |x: usize, y: usize| binary.as_raw()[y * w + x] != 0;
+
+    // First pass: provisional labels, recording equivalences in a union-find
+    let mut labels = vec![0u32; w * h];
+    let mut parents = vec![0u32];
+    for y in 0..h {
+        for x in 0..w {
+            if !foreground(x, y) {
+                continue;
+            }
+            let mut neighbors = [0u32; 4];
+            let mut count = 0;
+            let mut visit = |nx: usize, ny: usize| {
+                let label = labels[ny * w + nx];
+                if label != 0 {
+                    neighbors[count] = label;
+                    count += 1;
+                }
+            };
+            if x > 0 {
+                visit(x - 1, y);
+            }
+            if y > 0 {
+                visit(x, y - 1);
+                if connectivity == Connectivity::Eight {
+                    if x > 0 {
+                        visit(x - 1, y - 1);
+                    }
+                    if x + 1 < w {
+                        visit(x + 1, y - 1);
+                    }
+                }
+            }
+
+            labels[y * w + x] = match neighbors[..count]
+                .iter()
+                .map(|&l| find(&mut parents, l))
+                .min()
+            {
+                Some(root) => {
+                    for &l in &neighbors[..count] {
+                        let other = find(&mut parents, l);
+                        parents[other as usize] = root;
+                    }
+                    root
+                }
+                None => {
+                    parents.push(parents.len() as u32);
+                    parents.len() as u32 - 1
+                }
+            };
+        }
+    }
+
+    // Second pass: resolve to final labels, numbered in raster order
+    let mut final_label = vec![0u32; parents.len()];
+    let mut components: Vec<Component> = Vec::new();
+    let mut sums: Vec<(f64, f64)> = Vec::new();
+    for y in 0..h {
+        for x in 0..w {
+            let provisional = labels[y * w + x];
+            if provisional == 0 {
+                continue;
+            }
+            let root = find(&mut parents, provisional) as usize;
+            if final_label[root] == 0 {
+                components.push(Component {
+                    label: components.len() as u32 + 1,
+                    area: 0,
+                    x: x as u32,
+                    y: y as u32,
+                    width: 0,
+                    height: 0,
+                    centroid: (0.0, 0.0),
+                });
+                sums.push((0.0, 0.0));
+                final_label[root] = components.len() as u32;
+            }
+            let label = final_label[root];
+            labels[y * w + x] = label;
+
+            let index = label as usize - 1;
+            let component = &mut components[index];
+            component.area += 1;
+            // Bounding box is tracked as min corner and max corner until the end
+            component.x = component.x.min(x as u32);
+            component.width = component.width.max(x as u32);
+            component.height = component.height.max(y as u32);
+            sums[index].0 += x as f64;
+            sums[index].1 += y as f64;
+        }
+    }
+
+    for (component, (sx, sy)) in components.iter_mut().zip(sums) {
+        component.width = component.width - component.x + 1;
+        component.height = component.height - component.y + 1;
+        component.centroid = (sx / component.area as f64, sy / component.area as f64);
+    }
+
+    ConnectedComponents {
+        labels: ImageBuffer::from_raw(width, height, labels).expect("one label per pixel"),
+        components,
+    }
+}
+
+// Root of `label`, halving paths along the way
+fn find(parents: &mut [u32], mut label: u32) -> u32 {
+    while parents[label as usize] != label {
+        let grandparent = parents[parents[label as usize] as usize];
+        parents[label as usize] = grandparent;
+        label = grandparent;
+    }
+    label
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn from_ascii(rows: &[&str]) -> GrayImage {
+        GrayImage::from_fn(rows[0].len() as u32, rows.len() as u32, |x, y| {
+            Luma([if rows[y as usize].as_bytes()[x as usize] == b'#' {
+                255
+            } else {
+                0
+            }])
+        })
+    }
+
+    #[test]
+    fn test_components() {
+        let image = from_ascii(&[
+            "##..#", //
+            "##..#", "...#.", "#....",
+        ]);
+
+        let eight = connected_components(&image);
+        assert_eq!(eight.components.len(), 3);
+        let square = eight.components[0];
+        assert_eq!(
+            (square.x, square.y, square.width, square.height),
+            (0, 0, 2, 2)
+        );
+        assert_eq!(square.area, 4);
+        assert_eq!(square.centroid, (0.5, 0.5));
+        // The diagonal step joins the right column and the pixel below it
+        assert_eq!(eight.components[1].area, 3);
+        assert_eq!(eight.labels.get_pixel(3, 2)[0], 2);
+        assert_eq!(eight.labels.get_pixel(2, 2)[0], 0);
+
+        let four = connected_components_with(&image, Connectivity::Four);
+        assert_eq!(four.components.len(), 4);
+    }
+
+    #[test]
+    fn test_merges_u_shape() {
+        // The two arms get different provisional labels and merge at the bottom
+        let image = from_ascii(&[
+            "#...#", //
+            "#...#", "#####",
+        ]);
+        let result = connected_components_with(&image, Connectivity::Four);
+        assert_eq!(result.components.len(), 1);
+        assert_eq!(result.components[0].area, 9);
+        assert_eq!(result.components[0].width, 5);
+    }
+}
@@ -0,0 +1,179 @@
+//! Pixel diffs with the changed regions, for visual regression tests
+
+use super::components::connected_components;
+use super::faces::Rect;
+use super::quality::check_same_size;
+use crate::Result;
+use image::{GrayImage, RgbaImage};
+use rayon::prelude::*;
+
+/// Changed regions closer than this many pixels are reported as one box, so
+/// an edited word or anti-aliased edge does not come back as dozens of boxes
+const MERGE_GAP: u32 = 8;
+
+/// Result of `diff`
+#[derive(Debug, Clone, PartialEq)]
+pub struct ImageDiff {
+    /// Changed pixels in red over a faded grayscale copy of the first image
+    pub image: RgbaImage,
+    /// Bounding boxes of the changed areas, top to bottom
+    pub regions: Vec<Rect>,
+    /// Number of pixels that differ by more than the tolerance
+    pub changed_pixels: u64,
+    /// `changed_pixels` as a percentage of all pixels (0.0-100.0)
+    pub changed_percent: f64,
+}
+
+impl ImageDiff {
+    /// Whether no pixel differs by more than the tolerance
+    pub fn is_identical(&self) -> bool {
+        self.changed_pixels == 0
+    }
+}
+
+/// Compare two images of the same size pixel by pixel
+///
+/// tolerance: largest difference in any channel (alpha included) that still
+/// counts as unchanged, 0 for an exact match; a few levels absorb encoder and
+/// rendering noise
+pub fn diff(a: &RgbaImage, b: &RgbaImage, tolerance: u8) -> Result<ImageDiff> {
+    check_same_size(a, b)?;
+    let (width, height) = a.dimensions();
+
+    let mut image = RgbaImage::new(width, height);
+    let mut changed = GrayImage::new(width, height);
+    let out: &mut [u8] = &mut image;
+    out.par_chunks_mut(4)
+        .zip(changed.par_iter_mut())
+        .zip(a.as_raw().par_chunks(4).zip(b.as_raw().par_chunks(4)))
+        .for_each(|((out, mask), (pa, pb))| {
+            if (0..4).any(|c| pa[c].abs_diff(pb[c]) > tolerance) {
+                *mask = 255;
+                out.copy_from_slice(&[255, 0, 0, 255]);
+            } else {
+                // Faded toward white so the red stands out
+                let luma = (pa[0] as u32 * 299 + pa[1] as u32 * 587 + pa[2] as u32 * 114) / 1000;
+                let faded = (255 - (255 - luma) / 4) as u8;
+                out.copy_from_slice(&[faded, faded, faded, 255]);
+            }
+        });
+
+    let changed_pixels = changed.pixels().filter(|p| p[0] != 0).count() as u64;
+    let total = width as u64 * height as u64;
+    let changed_percent = if total == 0 {
+        0.0
+    } else {
+        changed_pixels as f64 * 100.0 / total as f64
+    };
+    Ok(ImageDiff {
+        image,
+        regions: changed_regions(&changed),
+        changed_pixels,
+        changed_percent,
+    })
+}
+
+// Boxes of the connected changed areas, merged while any two are within MERGE_GAP
+fn changed_regions(mask: &GrayImage) -> Vec<Rect> {
+    let mut regions: Vec<Rect> = connected_components(mask)
+        .components
+        .iter()
+        .map(|c| Rect {
+            x: c.x,
+            y: c.y,
+            width: c.width,
+            height: c.height,
+        })
+        .collect();
+
+    let near = |a: &Rect, b: &Rect| {
+        let apart = |start_a: u32, len_a: u32, start_b: u32, len_b: u32| {
+            (start_b.saturating_sub(start_a + len_a)).max(start_a.saturating_sub(start_b + len_b))
+        };
+        apart(a.x, a.width, b.x, b.width) <= MERGE_GAP
+            && apart(a.y, a.height, b.y, b.height) <= MERGE_GAP
+    };
+    let mut merged = true;
+    while merged {
+        merged = false;
+        'outer: for i in 0..regions.len() {
+            for j in i + 1..regions.len() {
+                if near(&regions[i], &regions[j]) {
+                    let other = regions.swap_remove(j);
+                    let region = &mut regions[i];
+                    let right = (region.x + region.width).max(other.x + other.width);
+                    let bottom = (region.y + region.height).max(other.y + other.height);
+                    region.x = region.x.min(other.x);
+                    region.y = region.y.min(other.y);
+                    region.width = right - region.x;
+                    region.height = bottom - region.y;
+                    merged = true;
+                    break 'outer;
+                }
+            }
+        }
+    }
+    regions.sort_by_key(|r| (r.y, r.x));
+    regions
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgba;
+
+    #[test]
+    fn test_diff_regions() {
+        let a = RgbaImage::from_pixel(100, 60, Rgba([120, 120, 120, 255]));
+        let mut b = a.clone();
+        // Two strokes 4 pixels apart make one region; a distant block another
+        for x in 10..20 {
+            b.put_pixel(x, 10, Rgba([0, 0, 0, 255]));
+            b.put_pixel(x, 14, Rgba([0, 0, 0, 255]));
+        }
+        for y in 40..50 {
+            for x in 70..80 {
+                b.put_pixel(x, y, Rgba([200, 120, 120, 255]));
+            }
+        }
+        // Within the tolerance
+        b.put_pixel(50, 5, Rgba([122, 120, 120, 255]));
+
+        let result = diff(&a, &b, 3).unwrap();
+        assert_eq!(result.changed_pixels, 120);
+        assert!((result.changed_percent - 2.0).abs() < 1e-9);
+        assert_eq!(
+            result.regions,
+            [
+                Rect {
+                    x: 10,
+                    y: 10,
+                    width: 10,
+                    height: 5
+                },
+                Rect {
+                    x: 70,
+                    y: 40,
+                    width: 10,
+                    height: 10
+                }
+            ]
+        );
+        assert_eq!(result.image[(15, 10)], Rgba([255, 0, 0, 255]));
+        assert_eq!(result.image[(50, 5)], Rgba([222, 222, 222, 255]));
+
+        // With no tolerance the off-by-two pixel counts as well
+        assert_eq!(diff(&a, &b, 0).unwrap().regions.len(), 3);
+    }
+
+    #[test]
+    fn test_identical_and_mismatched() {
+        let a = RgbaImage::from_pixel(8, 8, Rgba([1, 2, 3, 255]));
+        let same = diff(&a, &a, 0).unwrap();
+        assert!(same.is_identical());
+        assert!(same.regions.is_empty());
+        assert_eq!(same.changed_percent, 0.0);
+
+        assert!(diff(&a, &RgbaImage::new(8, 9), 0).is_err());
+    }
+}
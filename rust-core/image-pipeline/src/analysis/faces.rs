@@ -0,0 +1,205 @@
+//! Lightweight face detection
+//!
+//! Faces are found as skin-colored blobs with a face-like shape: roughly
+//! upright ellipses, neither too thin nor too square, with at least one enclosed
+//! non-skin hole where the eyes or mouth are. This needs no model and is fast
+//! enough to run on every upload, at the cost of missing profiles, heavily
+//! shadowed faces and skin tones outside the YCbCr box.
+
+use super::components::{connected_components, connected_components_with, Connectivity};
+use crate::crop::is_skin;
+use crate::Region;
+use image::imageops::{self, FilterType};
+use image::{GrayImage, Luma, RgbaImage};
+use std::sync::Arc;
+
+/// Longest side of the downscaled copy that is searched
+const ANALYSIS_SIZE: u32 = 320;
+/// Smallest face side on the downscaled copy, in pixels
+const MIN_FACE_SIDE: u32 = 8;
+
+/// An axis-aligned rectangle in image coordinates
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Rect {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl Rect {
+    /// Grow by `fraction` of the size on every side, clamped to a `width` x `height` image
+    ///
+    /// Detections hug the skin; a margin of 0.2-0.3 takes in hair and chin.
+    pub fn padded(&self, fraction: f32, width: u32, height: u32) -> Rect {
+        let dx = (self.width as f32 * fraction.max(0.0)).round() as u32;
+        let dy = (self.height as f32 * fraction.max(0.0)).round() as u32;
+        let x = self.x.saturating_sub(dx);
+        let y = self.y.saturating_sub(dy);
+        Rect {
+            x,
+            y,
+            width: (self.x + self.width + dx).min(width).saturating_sub(x),
+            height: (self.y + self.height + dy).min(height).saturating_sub(y),
+        }
+    }
+
+    /// This rectangle as the region of a masked operation
+    pub fn to_region(&self) -> Region {
+        Region::Rect {
+            x: self.x,
+            y: self.y,
+            width: self.width,
+            height: self.height,
+        }
+    }
+}
+
+/// Bounding boxes of the faces in `image`, largest first
+pub fn detect_faces(image: &RgbaImage) -> Vec<Rect> {
+    let (width, height) = image.dimensions();
+    if width == 0 || height == 0 {
+        return Vec::new();
+    }
+    let scale = (ANALYSIS_SIZE as f64 / width.max(height) as f64).min(1.0);
+    let w = ((width as f64 * scale).round() as u32).max(1);
+    let h = ((height as f64 * scale).round() as u32).max(1);
+    let small = imageops::resize(image, w, h, FilterType::Triangle);
+    let skin = GrayImage::from_fn(w, h, |x, y| {
+        let p = small[(x, y)];
+        Luma([if is_skin(p[0], p[1], p[2]) { 255 } else { 0 }])
+    });
+
+    let labeled = connected_components(&skin);
+    let mut faces: Vec<Rect> = labeled
+        .components
+        .iter()
+        .filter(|c| {
+            let aspect = c.height as f32 / c.width as f32;
+            let fill = c.area as f32 / (c.width as f32 * c.height as f32);
+            c.width.min(c.height) >= MIN_FACE_SIDE
+                && (0.8..=2.2).contains(&aspect)
+                && (0.45..=0.95).contains(&fill)
+        })
+        .filter(|c| {
+            // Non-skin pixels inside the box that do not reach its edge are enclosed holes
+            let holes = GrayImage::from_fn(c.width, c.height, |x, y| {
+                let label = labeled.labels[(c.x + x, c.y + y)][0];
+                Luma([if label == c.label { 0 } else { 255 }])
+            });
+            connected_components_with(&holes, Connectivity::Four)
+                .components
+                .iter()
+                .any(|hole| {
+                    hole.x > 0
+                        && hole.y > 0
+                        && hole.x + hole.width < c.width
+                        && hole.y + hole.height < c.height
+                })
+        })
+        .map(|c| {
+            let x = (c.x as f64 / scale).floor() as u32;
+            let y = (c.y as f64 / scale).floor() as u32;
+            Rect {
+                x,
+                y,
+                width: (((c.x + c.width) as f64 / scale).ceil() as u32).min(width) - x,
+                height: (((c.y + c.height) as f64 / scale).ceil() as u32).min(height) - y,
+            }
+        })
+        .collect();
+    faces.sort_by_key(|f| std::cmp::Reverse(f.width as u64 * f.height as u64));
+    faces
+}
+
+/// Elliptical mask over `faces` for a `width` x `height` image, for redacting them
+/// all with one masked operation (e.g. pixelate or blur)
+pub fn faces_region(width: u32, height: u32, faces: &[Rect]) -> Region {
+    let mask = GrayImage::from_fn(width, height, |x, y| {
+        let inside = faces.iter().any(|f| {
+            let (rx, ry) = (f.width as f32 / 2.0, f.height as f32 / 2.0);
+            let dx = (x as f32 + 0.5 - f.x as f32 - rx) / rx.max(0.5);
+            let dy = (y as f32 + 0.5 - f.y as f32 - ry) / ry.max(0.5);
+            dx * dx + dy * dy <= 1.0
+        });
+        Luma([if inside { 255 } else { 0 }])
+    });
+    Region::Mask(Arc::new(mask))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgba;
+
+    const SKIN: Rgba<u8> = Rgba([224, 172, 140, 255]);
+    const BACKGROUND: Rgba<u8> = Rgba([40, 70, 120, 255]);
+
+    // Skin ellipse at (cx, cy) with two dark eyes
+    fn draw_face(image: &mut RgbaImage, cx: f32, cy: f32, rx: f32, ry: f32) {
+        for (x, y, p) in image.enumerate_pixels_mut() {
+            let (dx, dy) = ((x as f32 - cx) / rx, (y as f32 - cy) / ry);
+            if dx * dx + dy * dy <= 1.0 {
+                *p = SKIN;
+            }
+            for ex in [cx - rx * 0.4, cx + rx * 0.4] {
+                if (x as f32 - ex).abs() < rx * 0.15
+                    && (y as f32 - (cy - ry * 0.2)).abs() < ry * 0.08
+                {
+                    *p = Rgba([30, 25, 20, 255]);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_detects_faces_largest_first() {
+        let mut image = RgbaImage::from_pixel(400, 240, BACKGROUND);
+        draw_face(&mut image, 100.0, 120.0, 40.0, 55.0);
+        draw_face(&mut image, 300.0, 100.0, 25.0, 34.0);
+        // A featureless skin-colored rectangle is not a face
+        for (x, y, p) in image.enumerate_pixels_mut() {
+            if (200..240).contains(&x) && (180..230).contains(&y) {
+                *p = SKIN;
+            }
+        }
+
+        let faces = detect_faces(&image);
+        assert_eq!(faces.len(), 2);
+        let big = faces[0];
+        assert!(big.x.abs_diff(60) <= 3 && big.y.abs_diff(65) <= 3);
+        assert!(big.width.abs_diff(80) <= 4 && big.height.abs_diff(110) <= 4);
+        let small = faces[1];
+        assert!(small.x.abs_diff(275) <= 3 && small.width.abs_diff(50) <= 4);
+
+        assert!(detect_faces(&RgbaImage::from_pixel(64, 64, SKIN)).is_empty());
+        assert!(detect_faces(&RgbaImage::new(0, 0)).is_empty());
+    }
+
+    #[test]
+    fn test_padding_and_region() {
+        let face = Rect {
+            x: 10,
+            y: 10,
+            width: 20,
+            height: 20,
+        };
+        let padded = face.padded(0.5, 35, 100);
+        assert_eq!(
+            padded,
+            Rect {
+                x: 0,
+                y: 0,
+                width: 35,
+                height: 40
+            }
+        );
+
+        let Region::Mask(mask) = faces_region(40, 40, &[face]) else {
+            panic!("expected a mask");
+        };
+        assert_eq!(mask[(20, 20)][0], 255);
+        assert_eq!(mask[(11, 11)][0], 0);
+        assert_eq!(mask[(35, 20)][0], 0);
+    }
+}
@@ -0,0 +1,116 @@
+//! Perceptual hashes
+
+use super::luma;
+use image::imageops::{self, FilterType};
+use image::RgbaImage;
+
+/// Average hash: bit set where the 8x8 downscaled luma is above its mean
+///
+/// Fastest of the three, but sensitive to global brightness and contrast changes.
+pub fn ahash(image: &RgbaImage) -> u64 {
+    let pixels = downscaled_luma(image, 8, 8);
+    let mean = pixels.iter().sum::<f32>() / pixels.len() as f32;
+    bits(pixels.iter().map(|&p| p > mean))
+}
+
+/// Difference hash: bit set where a pixel is brighter than its right neighbor
+/// in a 9x8 downscaled luma image
+pub fn dhash(image: &RgbaImage) -> u64 {
+    let pixels = downscaled_luma(image, 9, 8);
+    bits(
+        pixels
+            .chunks_exact(9)
+            .flat_map(|row| row.windows(2).map(|w| w[0] > w[1])),
+    )
+}
+
+/// DCT hash: bit set where a low-frequency DCT coefficient of the 32x32
+/// downscaled luma is above the median
+///
+/// The most robust of the three against re-encoding, scaling and small edits.
+pub fn phash(image: &RgbaImage) -> u64 {
+    const SIZE: usize = 32;
+    const KEEP: usize = 8;
+    let pixels = downscaled_luma(image, SIZE as u32, SIZE as u32);
+
+    // cos((2x + 1) * u * pi / 2N) for the lowest KEEP frequencies
+    let basis: Vec<f32> = (0..KEEP * SIZE)
+        .map(|i| {
+            let (u, x) = (i / SIZE, i % SIZE);
+            ((2 * x + 1) as f32 * u as f32 * std::f32::consts::PI / (2 * SIZE) as f32).cos()
+        })
+        .collect();
+
+    // Separable 2D DCT-II, computing only the kept coefficients
+    let mut rows = [[0f32; KEEP]; SIZE];
+    for (y, row) in rows.iter_mut().enumerate() {
+        for (u, out) in row.iter_mut().enumerate() {
+            *out = (0..SIZE)
+                .map(|x| pixels[y * SIZE + x] * basis[u * SIZE + x])
+                .sum();
+        }
+    }
+    let mut coefficients = [0f32; KEEP * KEEP];
+    for v in 0..KEEP {
+        for u in 0..KEEP {
+            coefficients[v * KEEP + u] = (0..SIZE).map(|y| rows[y][u] * basis[v * SIZE + y]).sum();
+        }
+    }
+
+    // The DC term only reflects average brightness, so it is left out of the median
+    let mut sorted = coefficients[1..].to_vec();
+    sorted.sort_by(f32::total_cmp);
+    let median = sorted[sorted.len() / 2];
+    bits(coefficients.iter().map(|&c| c > median))
+}
+
+/// Number of differing bits between two hashes
+///
+/// For 64-bit hashes, distances up to about 10 usually mean the same picture.
+pub fn hamming_distance(a: u64, b: u64) -> u32 {
+    (a ^ b).count_ones()
+}
+
+// Pack up to 64 booleans, first one in the most significant bit
+fn bits<I: Iterator<Item = bool>>(values: I) -> u64 {
+    values.fold(0u64, |hash, bit| (hash << 1) | bit as u64)
+}
+
+fn downscaled_luma(image: &RgbaImage, width: u32, height: u32) -> Vec<f32> {
+    if image.width() == 0 || image.height() == 0 {
+        return vec![0.0; (width * height) as usize];
+    }
+    luma(&imageops::resize(
+        image,
+        width,
+        height,
+        FilterType::Triangle,
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{filters, generate};
+
+    #[test]
+    fn test_hashes_match_near_duplicates() {
+        // Smooth content varying in both directions, like a photo
+        let image = RgbaImage::from_fn(200, 150, |x, y| {
+            let (fx, fy) = (x as f32 / 23.0, y as f32 / 17.0);
+            let v = 128.0 + 60.0 * fx.sin() * fy.cos() + 40.0 * (fx * 0.3 + fy * 0.7).sin();
+            image::Rgba([v as u8, (v * 0.8) as u8, 255 - v as u8, 255])
+        });
+        let resized = filters::resize(&image, 120, 90, crate::ResampleFilter::Bilinear);
+        let brighter = filters::brightness(&image, 0.05);
+        let different =
+            generate::radial_gradient(200, 150, image::Rgba([255; 4]), image::Rgba([0, 0, 0, 255]));
+
+        for hash in [ahash, dhash, phash] {
+            assert!(hamming_distance(hash(&image), hash(&resized)) <= 6);
+            assert!(hamming_distance(hash(&image), hash(&brighter)) <= 6);
+            assert!(hamming_distance(hash(&image), hash(&different)) > 12);
+        }
+        assert_eq!(hamming_distance(0b1011, 0b0110), 3);
+    }
+}
@@ -0,0 +1,290 @@
+//! Hough transforms for straight lines and circles
+//!
+//! Both take a binary edge map (nonzero = edge), such as `edge_map` produces,
+//! and return the detected shapes strongest first.
+
+use crate::filters;
+use image::{GrayImage, Luma, RgbaImage};
+use rayon::prelude::*;
+use std::ops::RangeInclusive;
+
+/// Angular resolution of `hough_lines`, in bins per degree
+const LINE_BINS_PER_DEGREE: usize = 4;
+/// Half-size of the non-maximum suppression window, in angle and distance bins
+const LINE_SUPPRESS_ANGLE: usize = 2 * LINE_BINS_PER_DEGREE;
+const LINE_SUPPRESS_RHO: usize = 3;
+/// Points sampled along each candidate circle
+const CIRCLE_SAMPLES: usize = 90;
+
+/// A line in normal form: `x * cos(angle) + y * sin(angle) = rho`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct HoughLine {
+    /// Signed distance from the top-left corner, in pixels
+    pub rho: f32,
+    /// Angle of the line's normal in degrees, 0 to 180
+    ///
+    /// 0 is a vertical line and 90 a horizontal one; a horizontal text line
+    /// skewed clockwise by `d` degrees has an angle of `90 + d`.
+    pub angle: f32,
+    /// Edge pixels on the line
+    pub votes: u32,
+}
+
+/// A circle found by `hough_circles`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct HoughCircle {
+    pub x: u32,
+    pub y: u32,
+    pub radius: u32,
+    /// Sampled circumference points that landed on an edge (out of 90)
+    pub votes: u32,
+}
+
+/// Binary edge map: Sobel magnitude at or above `threshold` becomes 255
+pub fn edge_map(image: &RgbaImage, threshold: u8) -> GrayImage {
+    let (width, height) = image.dimensions();
+    if width < 3 || height < 3 {
+        return GrayImage::new(width, height);
+    }
+    let edges = filters::edge_detect(image);
+    GrayImage::from_fn(width, height, |x, y| {
+        Luma([if edges.get_pixel(x, y)[0] >= threshold.max(1) {
+            255
+        } else {
+            0
+        }])
+    })
+}
+
+/// Lines through at least `threshold` edge pixels, most votes first
+///
+/// Angles are resolved to 0.25 degrees and distances to 1 pixel. Only maxima
+/// within 2 degrees and 3 pixels are reported, so a thick edge yields one line.
+pub fn hough_lines(edges: &GrayImage, threshold: u32) -> Vec<HoughLine> {
+    let (width, height) = edges.dimensions();
+    let angles = 180 * LINE_BINS_PER_DEGREE;
+    let max_rho = ((width as f64).hypot(height as f64)).ceil() as usize;
+    let rho_bins = 2 * max_rho + 1;
+
+    let trig: Vec<(f32, f32)> = (0..angles)
+        .map(|a| {
+            (a as f32 / LINE_BINS_PER_DEGREE as f32)
+                .to_radians()
+                .sin_cos()
+        })
+        .collect();
+    let points = edge_points(edges);
+
+    // One accumulator row per angle, filled in parallel. Each vote is split
+    // between the two nearest distance bins so the exact angle peaks sharpest.
+    let accumulator: Vec<Vec<f32>> = trig
+        .par_iter()
+        .map(|&(sin, cos)| {
+            let mut row = vec![0f32; rho_bins];
+            for &(x, y) in &points {
+                let rho = x as f32 * cos + y as f32 * sin + max_rho as f32;
+                let bin = (rho.floor() as usize).min(rho_bins - 2);
+                let frac = rho - bin as f32;
+                row[bin] += 1.0 - frac;
+                row[bin + 1] += frac;
+            }
+            row
+        })
+        .collect();
+
+    let accumulator = &accumulator;
+    let votes = |a: isize, r: isize| -> f32 {
+        // Angle wraps around with rho negated
+        let (a, r) = if a < 0 {
+            (a + angles as isize, rho_bins as isize - 1 - r)
+        } else if a >= angles as isize {
+            (a - angles as isize, rho_bins as isize - 1 - r)
+        } else {
+            (a, r)
+        };
+        if r < 0 || r >= rho_bins as isize {
+            0.0
+        } else {
+            accumulator[a as usize][r as usize]
+        }
+    };
+
+    let (window_a, window_r) = (LINE_SUPPRESS_ANGLE as isize, LINE_SUPPRESS_RHO as isize);
+    let mut lines: Vec<HoughLine> = (0..angles)
+        .into_par_iter()
+        .flat_map_iter(|a| {
+            let votes = &votes;
+            (0..rho_bins).filter_map(move |r| {
+                let v = accumulator[a][r];
+                if v < threshold.max(1) as f32 - 0.5 {
+                    return None;
+                }
+                // Keep only the maximum of its window; ties go to the first bin
+                let (a, r) = (a as isize, r as isize);
+                for da in -window_a..=window_a {
+                    for dr in -window_r..=window_r {
+                        if (da, dr) == (0, 0) {
+                            continue;
+                        }
+                        let other = votes(a + da, r + dr);
+                        if other > v || (other == v && (da, dr) < (0, 0)) {
+                            return None;
+                        }
+                    }
+                }
+                Some(HoughLine {
+                    rho: r as f32 - max_rho as f32,
+                    angle: a as f32 / LINE_BINS_PER_DEGREE as f32,
+                    votes: v.round() as u32,
+                })
+            })
+        })
+        .collect();
+    lines.sort_by(|a, b| b.votes.cmp(&a.votes).then(a.angle.total_cmp(&b.angle)));
+    lines
+}
+
+/// Circles with a radius in `radii` and at least `threshold` of 90 sampled
+/// circumference points on an edge, most votes first
+///
+/// Overlapping detections (centers closer than half the smaller radius) are
+/// reduced to the strongest one.
+pub fn hough_circles(
+    edges: &GrayImage,
+    radii: RangeInclusive<u32>,
+    threshold: u32,
+) -> Vec<HoughCircle> {
+    let (width, height) = edges.dimensions();
+    let points = edge_points(edges);
+    let offsets: Vec<(f32, f32)> = (0..CIRCLE_SAMPLES)
+        .map(|i| (i as f32 * std::f32::consts::TAU / CIRCLE_SAMPLES as f32).sin_cos())
+        .collect();
+
+    let mut candidates: Vec<HoughCircle> = radii
+        .filter(|&r| r > 0)
+        .collect::<Vec<_>>()
+        .into_par_iter()
+        .flat_map_iter(|radius| {
+            // Each edge pixel votes for every center at distance `radius`,
+            // at most once per center
+            let mut accumulator = vec![0u32; width as usize * height as usize];
+            let mut last_voter = vec![u32::MAX; accumulator.len()];
+            for (index, &(x, y)) in points.iter().enumerate() {
+                for &(sin, cos) in &offsets {
+                    let cx = (x as f32 - radius as f32 * cos).round();
+                    let cy = (y as f32 - radius as f32 * sin).round();
+                    if cx < 0.0 || cy < 0.0 || cx >= width as f32 || cy >= height as f32 {
+                        continue;
+                    }
+                    let cell = cy as usize * width as usize + cx as usize;
+                    if last_voter[cell] != index as u32 {
+                        last_voter[cell] = index as u32;
+                        accumulator[cell] += 1;
+                    }
+                }
+            }
+            // Votes are counted per edge pixel; convert to sampled points on the circle
+            let circumference = std::f32::consts::TAU * radius as f32;
+            let scale = CIRCLE_SAMPLES as f32 / circumference.max(1.0);
+            accumulator
+                .into_iter()
+                .enumerate()
+                .filter_map(move |(cell, v)| {
+                    let votes = ((v as f32 * scale).round() as u32).min(CIRCLE_SAMPLES as u32);
+                    (votes >= threshold.max(1)).then_some(HoughCircle {
+                        x: (cell % width as usize) as u32,
+                        y: (cell / width as usize) as u32,
+                        radius,
+                        votes,
+                    })
+                })
+                .collect::<Vec<_>>()
+        })
+        .collect();
+
+    candidates.sort_by(|a, b| {
+        b.votes
+            .cmp(&a.votes)
+            .then(a.radius.cmp(&b.radius))
+            .then((a.y, a.x).cmp(&(b.y, b.x)))
+    });
+    let mut circles: Vec<HoughCircle> = Vec::new();
+    for candidate in candidates {
+        let overlaps = circles.iter().any(|kept| {
+            let dx = kept.x as f32 - candidate.x as f32;
+            let dy = kept.y as f32 - candidate.y as f32;
+            dx.hypot(dy) < kept.radius.min(candidate.radius) as f32 / 2.0
+        });
+        if !overlaps {
+            circles.push(candidate);
+        }
+    }
+    circles
+}
+
+fn edge_points(edges: &GrayImage) -> Vec<(u32, u32)> {
+    edges
+        .enumerate_pixels()
+        .filter(|(_, _, p)| p[0] != 0)
+        .map(|(x, y, _)| (x, y))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hough_lines() {
+        // Horizontal line at y = 20 and a vertical one at x = 50
+        let edges = GrayImage::from_fn(80, 60, |x, y| {
+            Luma([if y == 20 || x == 50 { 255 } else { 0 }])
+        });
+        let lines = hough_lines(&edges, 40);
+        assert_eq!(lines.len(), 2, "{lines:?}");
+        assert_eq!((lines[0].angle, lines[0].rho), (90.0, 20.0));
+        assert_eq!((lines[1].angle, lines[1].rho), (0.0, 50.0));
+        assert_eq!(lines[0].votes, 80);
+    }
+
+    #[test]
+    fn test_skewed_line_angle() {
+        // y = 30 + x * tan(5 deg): the normal is at 95 degrees
+        let slope = 5f32.to_radians().tan();
+        let edges = GrayImage::from_fn(200, 80, |x, y| {
+            Luma([if (30.0 + x as f32 * slope).round() as u32 == y {
+                255
+            } else {
+                0
+            }])
+        });
+        let best = hough_lines(&edges, 50)[0];
+        assert!((best.angle - 95.0).abs() <= 0.25, "{best:?}");
+    }
+
+    #[test]
+    fn test_hough_circles() {
+        let edges = GrayImage::from_fn(100, 100, |x, y| {
+            let d = (x as f32 - 40.0).hypot(y as f32 - 55.0);
+            Luma([if (d - 20.0).abs() < 0.6 { 255 } else { 0 }])
+        });
+        let circles = hough_circles(&edges, 10..=30, 60);
+        assert!(!circles.is_empty());
+        let best = circles[0];
+        assert!(
+            best.x.abs_diff(40) <= 1 && best.y.abs_diff(55) <= 1,
+            "{best:?}"
+        );
+        assert!(best.radius.abs_diff(20) <= 1);
+    }
+
+    #[test]
+    fn test_edge_map() {
+        let image = RgbaImage::from_fn(20, 20, |x, _| {
+            image::Rgba(if x < 10 { [0, 0, 0, 255] } else { [255; 4] })
+        });
+        let edges = edge_map(&image, 128);
+        assert_eq!(edges.get_pixel(10, 10)[0], 255);
+        assert_eq!(edges.get_pixel(3, 10)[0], 0);
+    }
+}
@@ -0,0 +1,55 @@
+//! Image analysis: quality metrics, hashes, palettes, placeholders, statistics
+//! connected components, Hough transforms, document skew, faces and QR codes
+//!
+//! PSNR and SSIM compare two images of the same size. PSNR measures raw pixel
+//! error; SSIM tracks perceived quality more closely because it compares local
+//! structure, so it is the better target when tuning lossy encoders.
+//!
+//! `dominant_colors` extracts a small palette for placeholders and theming;
+//! `blurhash` and `thumbhash` encode a whole blurred preview as a short string.
+//!
+//! The 64-bit hashes fingerprint an image so near-duplicates (re-encoded,
+//! resized, slightly edited) land a small Hamming distance apart.
+//!
+//! `detect_faces` boxes faces for avatar cropping (`SmartCropOptions::face_weight`)
+//! and privacy redaction (`faces_region` as the region of a masked pixelate).
+//!
+//! `diff` reports which pixels changed between two renders and where, for
+//! visual regression tests.
+
+mod components;
+mod diff;
+mod faces;
+mod hash;
+mod hough;
+mod palette;
+mod placeholder;
+mod qr;
+mod quality;
+mod skew;
+mod stats;
+
+pub use components::{
+    connected_components, connected_components_with, Component, ConnectedComponents, Connectivity,
+};
+pub use diff::{diff, ImageDiff};
+pub use faces::{detect_faces, faces_region, Rect};
+pub use hash::{ahash, dhash, hamming_distance, phash};
+pub use hough::{edge_map, hough_circles, hough_lines, HoughCircle, HoughLine};
+pub use palette::{dominant_colors, PaletteColor};
+pub use placeholder::{blurhash, thumbhash};
+pub use qr::{decode_qr, QrCode};
+pub use quality::{psnr, ssim};
+pub(crate) use skew::paper_color;
+pub use skew::{estimate_skew, MAX_SKEW_DEGREES};
+pub use stats::{stats, ChannelStats, ImageStats};
+
+use image::RgbaImage;
+
+/// Rec. 709 luma of every pixel, row-major
+fn luma(image: &RgbaImage) -> Vec<f32> {
+    image
+        .pixels()
+        .map(|p| 0.2126 * p[0] as f32 + 0.7152 * p[1] as f32 + 0.0722 * p[2] as f32)
+        .collect()
+}
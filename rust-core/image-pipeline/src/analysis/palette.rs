@@ -0,0 +1,170 @@
+//! Palette extraction
+
+use image::imageops::{self, FilterType};
+use image::RgbaImage;
+
+/// Longest side of the copy `dominant_colors` samples from
+const PALETTE_SAMPLE_SIZE: u32 = 128;
+/// k-means passes that refine the median-cut palette
+const PALETTE_REFINE_PASSES: usize = 4;
+
+/// One palette entry from `dominant_colors`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PaletteColor {
+    pub rgb: [u8; 3],
+    /// Share of the (non-transparent) image closest to this color, 0.0 to 1.0
+    pub fraction: f32,
+}
+
+impl PaletteColor {
+    /// CSS-style `#rrggbb`
+    pub fn to_hex(&self) -> String {
+        let [r, g, b] = self.rgb;
+        format!("#{:02x}{:02x}{:02x}", r, g, b)
+    }
+}
+
+/// Up to `n` representative colors, most common first
+///
+/// The palette comes from median cut on a downscaled copy, refined with a few
+/// k-means passes. Fully transparent pixels are ignored; an empty or fully
+/// transparent image yields an empty palette.
+pub fn dominant_colors(image: &RgbaImage, n: usize) -> Vec<PaletteColor> {
+    if n == 0 || image.width() == 0 || image.height() == 0 {
+        return Vec::new();
+    }
+
+    let scale = (PALETTE_SAMPLE_SIZE as f64 / image.width().max(image.height()) as f64).min(1.0);
+    let sample = imageops::resize(
+        image,
+        crate::scale_dimension(image.width(), scale),
+        crate::scale_dimension(image.height(), scale),
+        FilterType::Triangle,
+    );
+    let pixels: Vec<[u8; 3]> = sample
+        .pixels()
+        .filter(|p| p[3] > 0)
+        .map(|p| [p[0], p[1], p[2]])
+        .collect();
+    if pixels.is_empty() {
+        return Vec::new();
+    }
+
+    let mut centers = median_cut(pixels.clone(), n);
+    let mut counts = vec![0usize; centers.len()];
+    for _ in 0..PALETTE_REFINE_PASSES {
+        let mut sums = vec![[0u64; 3]; centers.len()];
+        counts.iter_mut().for_each(|c| *c = 0);
+        for pixel in &pixels {
+            let nearest = nearest_center(&centers, pixel);
+            counts[nearest] += 1;
+            for c in 0..3 {
+                sums[nearest][c] += pixel[c] as u64;
+            }
+        }
+        for ((center, sum), &count) in centers.iter_mut().zip(&sums).zip(&counts) {
+            if count > 0 {
+                *center = sum.map(|v| (v as f64 / count as f64).round() as u8);
+            }
+        }
+    }
+
+    let mut palette: Vec<PaletteColor> = centers
+        .into_iter()
+        .zip(counts)
+        .filter(|&(_, count)| count > 0)
+        .map(|(rgb, count)| PaletteColor {
+            rgb,
+            fraction: count as f32 / pixels.len() as f32,
+        })
+        .collect();
+    palette.sort_by(|a, b| b.fraction.total_cmp(&a.fraction));
+    palette
+}
+
+// Split the box with the widest channel range at its median until there are `n`
+fn median_cut(pixels: Vec<[u8; 3]>, n: usize) -> Vec<[u8; 3]> {
+    let range = |bucket: &[[u8; 3]]| {
+        (0..3)
+            .map(|c| {
+                let (lo, hi) = bucket
+                    .iter()
+                    .fold((255, 0), |(lo, hi), p| (p[c].min(lo), p[c].max(hi)));
+                (hi.saturating_sub(lo), c)
+            })
+            .max()
+            .unwrap_or((0, 0))
+    };
+
+    let mut buckets = vec![pixels];
+    while buckets.len() < n {
+        let Some((index, (_, channel))) = buckets
+            .iter()
+            .enumerate()
+            .map(|(i, b)| (i, range(b)))
+            .filter(|(_, (spread, _))| *spread > 0)
+            .max_by_key(|(i, (spread, _))| (*spread as usize * buckets[*i].len(), *i))
+        else {
+            break;
+        };
+        let mut bucket = buckets.swap_remove(index);
+        bucket.sort_unstable_by_key(|p| p[channel]);
+        let upper = bucket.split_off(bucket.len() / 2);
+        buckets.push(bucket);
+        buckets.push(upper);
+    }
+
+    buckets
+        .iter()
+        .map(|bucket| {
+            let mut sum = [0u64; 3];
+            for p in bucket {
+                for c in 0..3 {
+                    sum[c] += p[c] as u64;
+                }
+            }
+            sum.map(|v| (v / bucket.len() as u64) as u8)
+        })
+        .collect()
+}
+
+fn nearest_center(centers: &[[u8; 3]], pixel: &[u8; 3]) -> usize {
+    let distance = |c: &[u8; 3]| {
+        (0..3)
+            .map(|i| (c[i] as i32 - pixel[i] as i32).pow(2))
+            .sum::<i32>()
+    };
+    (0..centers.len())
+        .min_by_key(|&i| distance(&centers[i]))
+        .unwrap_or(0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generate;
+
+    #[test]
+    fn test_dominant_colors() {
+        // 3/4 red, 1/4 blue, with a transparent strip that must be ignored
+        let mut image = generate::solid(40, 40, image::Rgba([200, 20, 20, 255]));
+        for y in 0..40 {
+            for x in 30..40 {
+                image.put_pixel(x, y, image::Rgba([20, 20, 200, 255]));
+            }
+        }
+        for x in 0..40 {
+            image.put_pixel(x, 0, image::Rgba([0, 255, 0, 0]));
+        }
+
+        let palette = dominant_colors(&image, 4);
+        assert_eq!(palette.len(), 2, "{palette:?}");
+        assert_eq!(palette[0].rgb, [200, 20, 20]);
+        assert_eq!(palette[0].to_hex(), "#c81414");
+        assert!((palette[0].fraction - 0.75).abs() < 0.03);
+        assert_eq!(palette[1].rgb, [20, 20, 200]);
+
+        assert!(dominant_colors(&image, 0).is_empty());
+        assert_eq!(dominant_colors(&generate::test_pattern(64, 64), 8).len(), 8);
+    }
+}
@@ -0,0 +1,311 @@
+//! Compact blurred placeholders: BlurHash and ThumbHash
+//!
+//! Both encode a heavily blurred version of the image in a few dozen bytes, to
+//! be shown while the real image loads. Encoding cost grows with the pixel
+//! count, so larger inputs are downscaled first; pass a thumbnail to skip that.
+
+use crate::{PipelineError, Result};
+use image::imageops::{self, FilterType};
+use image::RgbaImage;
+use std::f32::consts::PI;
+
+/// Longest side BlurHash is computed at
+const BLURHASH_SAMPLE_SIZE: u32 = 64;
+/// Longest side ThumbHash accepts (part of the format)
+const THUMBHASH_MAX_SIZE: u32 = 100;
+
+const BASE83: &[u8; 83] =
+    b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz#$%*+,-.:;=?@[]^_{|}~";
+
+/// BlurHash string with `x_components` x `y_components` (each 1-9) cosine terms
+///
+/// More components keep more detail at the cost of a longer string
+/// (4 + 2 * x * y characters); 4x3 is the usual choice. Alpha is ignored.
+pub fn blurhash(image: &RgbaImage, x_components: u32, y_components: u32) -> Result<String> {
+    if !(1..=9).contains(&x_components) || !(1..=9).contains(&y_components) {
+        return Err(PipelineError::InvalidParameter(format!(
+            "blurhash components must be between 1 and 9, got {}x{}",
+            x_components, y_components
+        )));
+    }
+    if image.width() == 0 || image.height() == 0 {
+        return Err(PipelineError::InvalidParameter(
+            "cannot hash an empty image".to_string(),
+        ));
+    }
+
+    let sample = fit_within(image, BLURHASH_SAMPLE_SIZE);
+    let (width, height) = (sample.width() as usize, sample.height() as usize);
+    let linear: Vec<[f32; 3]> = sample
+        .pixels()
+        .map(|p| [0, 1, 2].map(|c| srgb_to_linear(p[c])))
+        .collect();
+
+    let mut factors = Vec::with_capacity((x_components * y_components) as usize);
+    for j in 0..y_components as usize {
+        for i in 0..x_components as usize {
+            let normalisation = if i == 0 && j == 0 { 1.0 } else { 2.0 };
+            let mut sum = [0f32; 3];
+            for y in 0..height {
+                let basis_y = (PI * j as f32 * y as f32 / height as f32).cos();
+                for x in 0..width {
+                    let basis = basis_y * (PI * i as f32 * x as f32 / width as f32).cos();
+                    let pixel = linear[y * width + x];
+                    for c in 0..3 {
+                        sum[c] += basis * pixel[c];
+                    }
+                }
+            }
+            let scale = normalisation / (width * height) as f32;
+            factors.push(sum.map(|v| v * scale));
+        }
+    }
+
+    let mut hash = String::with_capacity(4 + 2 * factors.len());
+    push_base83(&mut hash, (x_components - 1) + (y_components - 1) * 9, 1);
+
+    let (dc, ac) = factors.split_first().expect("at least one component");
+    let maximum = if ac.is_empty() {
+        push_base83(&mut hash, 0, 1);
+        1.0
+    } else {
+        let actual = ac
+            .iter()
+            .flat_map(|f| f.iter())
+            .fold(0f32, |max, v| max.max(v.abs()));
+        let quantised = ((actual * 166.0 - 0.5).floor()).clamp(0.0, 82.0) as u32;
+        push_base83(&mut hash, quantised, 1);
+        (quantised + 1) as f32 / 166.0
+    };
+
+    let [r, g, b] = dc.map(linear_to_srgb);
+    push_base83(
+        &mut hash,
+        ((r as u32) << 16) | ((g as u32) << 8) | b as u32,
+        4,
+    );
+    for factor in ac {
+        let [r, g, b] = factor.map(|v| {
+            let v = v / maximum;
+            (v.signum() * v.abs().sqrt() * 9.0 + 9.5)
+                .floor()
+                .clamp(0.0, 18.0) as u32
+        });
+        push_base83(&mut hash, r * 19 * 19 + g * 19 + b, 2);
+    }
+    Ok(hash)
+}
+
+/// ThumbHash bytes for the image
+///
+/// Unlike BlurHash this keeps the aspect ratio and alpha, and needs no
+/// component count. The result is usually 20-30 bytes; base64 it for transport.
+pub fn thumbhash(image: &RgbaImage) -> Vec<u8> {
+    let sample = fit_within(image, THUMBHASH_MAX_SIZE);
+    let (w, h) = (sample.width() as usize, sample.height() as usize);
+    if w == 0 || h == 0 {
+        return Vec::new();
+    }
+
+    // Average color, weighted by alpha, fills in behind transparent pixels
+    let mut avg = [0f32; 3];
+    let mut avg_a = 0f32;
+    for p in sample.pixels() {
+        let alpha = p[3] as f32 / 255.0;
+        for c in 0..3 {
+            avg[c] += alpha / 255.0 * p[c] as f32;
+        }
+        avg_a += alpha;
+    }
+    if avg_a > 0.0 {
+        avg = avg.map(|v| v / avg_a);
+    }
+
+    let has_alpha = avg_a < (w * h) as f32;
+    let l_limit = if has_alpha { 5.0 } else { 7.0 };
+    let longest = w.max(h) as f32;
+    let lx = (js_round(l_limit * w as f32 / longest) as usize).max(1);
+    let ly = (js_round(l_limit * h as f32 / longest) as usize).max(1);
+
+    // Luminance, yellow-blue and red-green opponent channels, plus alpha
+    let mut l = Vec::with_capacity(w * h);
+    let mut p = Vec::with_capacity(w * h);
+    let mut q = Vec::with_capacity(w * h);
+    let mut a = Vec::with_capacity(w * h);
+    for px in sample.pixels() {
+        let alpha = px[3] as f32 / 255.0;
+        let [r, g, b] = [0, 1, 2].map(|c| avg[c] * (1.0 - alpha) + alpha / 255.0 * px[c] as f32);
+        l.push((r + g + b) / 3.0);
+        p.push((r + g) / 2.0 - b);
+        q.push(r - g);
+        a.push(alpha);
+    }
+
+    let (l_dc, l_ac, l_scale) = encode_channel(&l, w, h, lx.max(3), ly.max(3));
+    let (p_dc, p_ac, p_scale) = encode_channel(&p, w, h, 3, 3);
+    let (q_dc, q_ac, q_scale) = encode_channel(&q, w, h, 3, 3);
+
+    let is_landscape = w > h;
+    let header24 = js_round(63.0 * l_dc) as u32
+        | (js_round(31.5 + 31.5 * p_dc) as u32) << 6
+        | (js_round(31.5 + 31.5 * q_dc) as u32) << 12
+        | (js_round(31.0 * l_scale) as u32) << 18
+        | (has_alpha as u32) << 23;
+    let header16 = (if is_landscape { ly } else { lx }) as u32
+        | (js_round(63.0 * p_scale) as u32) << 3
+        | (js_round(63.0 * q_scale) as u32) << 9
+        | (is_landscape as u32) << 15;
+
+    let mut hash = vec![
+        header24 as u8,
+        (header24 >> 8) as u8,
+        (header24 >> 16) as u8,
+        header16 as u8,
+        (header16 >> 8) as u8,
+    ];
+    let mut acs = vec![l_ac, p_ac, q_ac];
+    if has_alpha {
+        let (a_dc, a_ac, a_scale) = encode_channel(&a, w, h, 5, 5);
+        hash.push(js_round(15.0 * a_dc) as u8 | (js_round(15.0 * a_scale) as u8) << 4);
+        acs.push(a_ac);
+    }
+
+    // Two 4-bit AC coefficients per byte, low nibble first
+    for (index, value) in acs.iter().flatten().enumerate() {
+        let nibble = js_round(15.0 * value) as u8;
+        if index % 2 == 0 {
+            hash.push(nibble);
+        } else {
+            *hash.last_mut().expect("pushed above") |= nibble << 4;
+        }
+    }
+    hash
+}
+
+// DCT of one channel over a triangle of low frequencies; returns the DC term,
+// the AC terms normalized to 0..1 and their scale
+fn encode_channel(
+    channel: &[f32],
+    w: usize,
+    h: usize,
+    nx: usize,
+    ny: usize,
+) -> (f32, Vec<f32>, f32) {
+    let mut dc = 0.0;
+    let mut ac = Vec::new();
+    let mut scale = 0f32;
+    let mut fx = vec![0f32; w];
+    for cy in 0..ny {
+        let mut cx = 0;
+        while cx * ny < nx * (ny - cy) {
+            for (x, f) in fx.iter_mut().enumerate() {
+                *f = (PI / w as f32 * cx as f32 * (x as f32 + 0.5)).cos();
+            }
+            let mut f = 0.0;
+            for y in 0..h {
+                let fy = (PI / h as f32 * cy as f32 * (y as f32 + 0.5)).cos();
+                for x in 0..w {
+                    f += channel[x + y * w] * fx[x] * fy;
+                }
+            }
+            f /= (w * h) as f32;
+            if cx > 0 || cy > 0 {
+                ac.push(f);
+                scale = scale.max(f.abs());
+            } else {
+                dc = f;
+            }
+            cx += 1;
+        }
+    }
+    if scale > 0.0 {
+        for v in &mut ac {
+            *v = 0.5 + 0.5 / scale * *v;
+        }
+    }
+    (dc, ac, scale)
+}
+
+fn fit_within(image: &RgbaImage, max_side: u32) -> RgbaImage {
+    let longest = image.width().max(image.height());
+    if longest <= max_side {
+        return image.clone();
+    }
+    let scale = max_side as f64 / longest as f64;
+    imageops::resize(
+        image,
+        crate::scale_dimension(image.width(), scale),
+        crate::scale_dimension(image.height(), scale),
+        FilterType::Triangle,
+    )
+}
+
+// Math.round semantics (halves round up), which the ThumbHash reference uses
+fn js_round(v: f32) -> f32 {
+    (v + 0.5).floor()
+}
+
+fn push_base83(out: &mut String, value: u32, digits: u32) {
+    for i in (0..digits).rev() {
+        let digit = (value / 83u32.pow(i)) % 83;
+        out.push(BASE83[digit as usize] as char);
+    }
+}
+
+fn srgb_to_linear(value: u8) -> f32 {
+    let v = value as f32 / 255.0;
+    if v <= 0.04045 {
+        v / 12.92
+    } else {
+        ((v + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+fn linear_to_srgb(value: f32) -> u8 {
+    let v = value.clamp(0.0, 1.0);
+    let encoded = if v <= 0.003_130_8 {
+        v * 12.92
+    } else {
+        1.055 * v.powf(1.0 / 2.4) - 0.055
+    };
+    (encoded * 255.0 + 0.5) as u8
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generate;
+    use image::Rgba;
+
+    #[test]
+    fn test_blurhash_solid_color() {
+        let black = generate::solid(32, 24, Rgba([0, 0, 0, 255]));
+        assert_eq!(
+            blurhash(&black, 4, 3).unwrap(),
+            "L00000fQfQfQfQfQfQfQfQfQfQfQ"
+        );
+
+        let hash = blurhash(&generate::test_pattern(200, 100), 5, 4).unwrap();
+        assert_eq!(hash.len(), 4 + 2 * 5 * 4);
+        assert!(blurhash(&black, 0, 3).is_err());
+        assert!(blurhash(&black, 4, 10).is_err());
+    }
+
+    #[test]
+    fn test_thumbhash_layout() {
+        // Opaque square: 5 header bytes, then 27 + 5 + 5 AC nibbles
+        let opaque = thumbhash(&generate::test_pattern(64, 64));
+        assert_eq!(opaque.len(), 5 + 19);
+        assert_eq!(opaque[2] >> 7, 0);
+
+        // Transparency adds the alpha byte and flag
+        let mut translucent = generate::test_pattern(64, 64);
+        translucent.put_pixel(0, 0, Rgba([0, 0, 0, 0]));
+        let hash = thumbhash(&translucent);
+        assert_eq!(hash[2] >> 7, 1);
+
+        // Landscape flag
+        let wide = thumbhash(&generate::test_pattern(300, 100));
+        assert_eq!(wide[4] >> 7, 1);
+    }
+}
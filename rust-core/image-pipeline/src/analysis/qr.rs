@@ -0,0 +1,121 @@
+//! QR code decoding
+//!
+//! Codes are located by their finder patterns, so they may be rotated or seen
+//! at a slant. Transparent pixels count as white, which is what a screenshot
+//! of a code on a transparent background looks like once displayed. If nothing
+//! is found the image is searched again inverted, for light-on-dark codes.
+//! Linear (1D) barcodes are not recognized.
+
+use image::RgbaImage;
+use rqrr::PreparedImage;
+
+/// One decoded QR code
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QrCode {
+    /// Decoded payload bytes
+    pub payload: Vec<u8>,
+    /// Corners in image coordinates, in the code's own top-left, top-right,
+    /// bottom-right, bottom-left order (so a rotated code has rotated corners)
+    pub corners: [(i32, i32); 4],
+    /// Symbol version, 1 (21x21 modules) to 40
+    pub version: u32,
+}
+
+impl QrCode {
+    /// The payload as text, if it is valid UTF-8
+    pub fn text(&self) -> Option<&str> {
+        std::str::from_utf8(&self.payload).ok()
+    }
+}
+
+/// Every readable QR code in `image`
+///
+/// Codes that are found but too damaged to decode are left out.
+pub fn decode_qr(image: &RgbaImage) -> Vec<QrCode> {
+    let luma: Vec<u8> = image
+        .pixels()
+        .map(|p| {
+            let y = 0.2126 * p[0] as f32 + 0.7152 * p[1] as f32 + 0.0722 * p[2] as f32;
+            let a = p[3] as f32 / 255.0;
+            (y * a + 255.0 * (1.0 - a)).round() as u8
+        })
+        .collect();
+    let codes = scan(image, &luma, false);
+    if codes.is_empty() {
+        scan(image, &luma, true)
+    } else {
+        codes
+    }
+}
+
+fn scan(image: &RgbaImage, luma: &[u8], invert: bool) -> Vec<QrCode> {
+    let width = image.width() as usize;
+    let mut prepared =
+        PreparedImage::prepare_from_greyscale(width, image.height() as usize, |x, y| {
+            let v = luma[y * width + x];
+            if invert {
+                255 - v
+            } else {
+                v
+            }
+        });
+    prepared
+        .detect_grids()
+        .into_iter()
+        .filter_map(|grid| {
+            let mut payload = Vec::new();
+            let meta = grid.decode_to(&mut payload).ok()?;
+            Some(QrCode {
+                payload,
+                corners: grid.bounds.map(|p| (p.x, p.y)),
+                version: meta.version.0 as u32,
+            })
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{imageops, Rgba};
+
+    // `data` as a QR code with 4px modules and a 4-module quiet zone
+    fn render(data: &str, dark: Rgba<u8>, light: Rgba<u8>) -> RgbaImage {
+        let code = qrcode::QrCode::new(data).unwrap();
+        let (size, colors) = (code.width() as u32, code.to_colors());
+        RgbaImage::from_fn((size + 8) * 4, (size + 8) * 4, |x, y| {
+            let (mx, my) = ((x / 4).wrapping_sub(4), (y / 4).wrapping_sub(4));
+            match (mx < size && my < size).then(|| colors[(my * size + mx) as usize]) {
+                Some(qrcode::Color::Dark) => dark,
+                _ => light,
+            }
+        })
+    }
+
+    #[test]
+    fn test_decodes_payload_and_corners() {
+        let black = Rgba([0, 0, 0, 255]);
+        let image = render("https://example.com/q?id=42", black, Rgba([255; 4]));
+        let codes = decode_qr(&image);
+        assert_eq!(codes.len(), 1);
+        assert_eq!(codes[0].text(), Some("https://example.com/q?id=42"));
+        let (x0, y0) = codes[0].corners[0];
+        assert!(x0.abs_diff(16) <= 2 && y0.abs_diff(16) <= 2);
+
+        // Rotated a quarter turn clockwise, the code's top-left corner moves to the top right
+        let rotated = decode_qr(&imageops::rotate90(&image));
+        assert_eq!(rotated[0].payload, codes[0].payload);
+        assert!(rotated[0].corners[0].0 > image.width() as i32 / 2);
+
+        assert!(decode_qr(&RgbaImage::from_pixel(64, 64, Rgba([255; 4]))).is_empty());
+    }
+
+    #[test]
+    fn test_inverted_and_transparent_codes() {
+        let inverted = render("inverted", Rgba([255; 4]), Rgba([0, 0, 0, 255]));
+        assert_eq!(decode_qr(&inverted)[0].text(), Some("inverted"));
+
+        let transparent = render("transparent", Rgba([0, 0, 0, 255]), Rgba([0, 0, 0, 0]));
+        assert_eq!(decode_qr(&transparent)[0].text(), Some("transparent"));
+    }
+}
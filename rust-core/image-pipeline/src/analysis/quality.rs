@@ -0,0 +1,184 @@
+//! Full-reference quality metrics
+
+use super::luma;
+use crate::{PipelineError, Result};
+use image::RgbaImage;
+use rayon::prelude::*;
+
+/// Side of the Gaussian window used by `ssim`
+const SSIM_WINDOW: usize = 11;
+/// Standard deviation of the `ssim` window
+const SSIM_SIGMA: f32 = 1.5;
+// Stabilizing constants (0.01 * 255)^2 and (0.03 * 255)^2 from Wang et al.
+const SSIM_C1: f32 = 6.5025;
+const SSIM_C2: f32 = 58.5225;
+
+/// Peak signal-to-noise ratio over the RGB channels, in decibels
+///
+/// Identical images return `f64::INFINITY`. Around 30 dB differences are
+/// usually hard to spot; 40 dB and above is visually lossless.
+pub fn psnr(a: &RgbaImage, b: &RgbaImage) -> Result<f64> {
+    check_same_size(a, b)?;
+
+    let row_len = (a.width() as usize * 4).max(4);
+    let squared_error: u64 = a
+        .as_raw()
+        .par_chunks(row_len)
+        .zip(b.as_raw().par_chunks(row_len))
+        .map(|(ra, rb)| {
+            ra.chunks_exact(4)
+                .zip(rb.chunks_exact(4))
+                .map(|(pa, pb)| {
+                    (0..3)
+                        .map(|c| (pa[c].abs_diff(pb[c]) as u64).pow(2))
+                        .sum::<u64>()
+                })
+                .sum::<u64>()
+        })
+        .sum();
+
+    let samples = a.width() as u64 * a.height() as u64 * 3;
+    if squared_error == 0 || samples == 0 {
+        return Ok(f64::INFINITY);
+    }
+    let mse = squared_error as f64 / samples as f64;
+    Ok(10.0 * (255.0 * 255.0 / mse).log10())
+}
+
+/// Mean structural similarity of the luma channels, from -1 to 1 (1 = identical)
+///
+/// Uses the standard 11x11 Gaussian window with sigma 1.5; edges are clamped.
+pub fn ssim(a: &RgbaImage, b: &RgbaImage) -> Result<f64> {
+    check_same_size(a, b)?;
+    let (width, height) = (a.width() as usize, a.height() as usize);
+    if width == 0 || height == 0 {
+        return Ok(1.0);
+    }
+
+    let x = luma(a);
+    let y = luma(b);
+    let product = |f: &dyn Fn(usize) -> f32| (0..x.len()).map(f).collect::<Vec<f32>>();
+    let xx = product(&|i| x[i] * x[i]);
+    let yy = product(&|i| y[i] * y[i]);
+    let xy = product(&|i| x[i] * y[i]);
+
+    let kernel = gaussian_kernel();
+    let [mu_x, mu_y, e_xx, e_yy, e_xy] =
+        [&x, &y, &xx, &yy, &xy].map(|plane| gaussian_filter(plane, width, height, &kernel));
+
+    let total: f64 = (0..x.len())
+        .into_par_iter()
+        .map(|i| {
+            let (mx, my) = (mu_x[i], mu_y[i]);
+            let var_x = e_xx[i] - mx * mx;
+            let var_y = e_yy[i] - my * my;
+            let cov = e_xy[i] - mx * my;
+            let numerator = (2.0 * mx * my + SSIM_C1) * (2.0 * cov + SSIM_C2);
+            let denominator = (mx * mx + my * my + SSIM_C1) * (var_x + var_y + SSIM_C2);
+            (numerator / denominator) as f64
+        })
+        .sum();
+    Ok(total / x.len() as f64)
+}
+
+pub(super) fn check_same_size(a: &RgbaImage, b: &RgbaImage) -> Result<()> {
+    if a.dimensions() != b.dimensions() {
+        return Err(PipelineError::InvalidParameter(format!(
+            "images must be the same size, got {}x{} and {}x{}",
+            a.width(),
+            a.height(),
+            b.width(),
+            b.height()
+        )));
+    }
+    Ok(())
+}
+
+fn gaussian_kernel() -> [f32; SSIM_WINDOW] {
+    let center = (SSIM_WINDOW / 2) as f32;
+    let mut kernel = std::array::from_fn(|i| {
+        (-((i as f32 - center).powi(2)) / (2.0 * SSIM_SIGMA.powi(2))).exp()
+    });
+    let sum: f32 = kernel.iter().sum();
+    kernel.iter_mut().for_each(|k| *k /= sum);
+    kernel
+}
+
+// Separable Gaussian filter with clamped edges
+fn gaussian_filter(
+    plane: &[f32],
+    width: usize,
+    height: usize,
+    kernel: &[f32; SSIM_WINDOW],
+) -> Vec<f32> {
+    let radius = (SSIM_WINDOW / 2) as isize;
+    let clamp = |v: isize, len: usize| v.clamp(0, len as isize - 1) as usize;
+
+    let mut horizontal = vec![0f32; plane.len()];
+    horizontal
+        .par_chunks_mut(width)
+        .enumerate()
+        .for_each(|(y, row)| {
+            let src = &plane[y * width..(y + 1) * width];
+            for (x, out) in row.iter_mut().enumerate() {
+                *out = kernel
+                    .iter()
+                    .enumerate()
+                    .map(|(k, w)| w * src[clamp(x as isize + k as isize - radius, width)])
+                    .sum();
+            }
+        });
+
+    let mut result = vec![0f32; plane.len()];
+    result
+        .par_chunks_mut(width)
+        .enumerate()
+        .for_each(|(y, row)| {
+            for (x, out) in row.iter_mut().enumerate() {
+                *out = kernel
+                    .iter()
+                    .enumerate()
+                    .map(|(k, w)| {
+                        w * horizontal[clamp(y as isize + k as isize - radius, height) * width + x]
+                    })
+                    .sum();
+            }
+        });
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{filters, generate};
+
+    #[test]
+    fn test_identical_images() {
+        let image = generate::test_pattern(64, 48);
+        assert_eq!(psnr(&image, &image).unwrap(), f64::INFINITY);
+        assert!((ssim(&image, &image).unwrap() - 1.0).abs() < 1e-6);
+    }
+
+    #[test]
+    fn test_metrics_track_degradation() {
+        let image = generate::test_pattern(96, 64);
+        let light = filters::blur(&image, 0.8);
+        let heavy = filters::blur(&image, 4.0);
+
+        assert!(psnr(&image, &light).unwrap() > psnr(&image, &heavy).unwrap());
+        let (ssim_light, ssim_heavy) =
+            (ssim(&image, &light).unwrap(), ssim(&image, &heavy).unwrap());
+        assert!(ssim_light > ssim_heavy, "{ssim_light} vs {ssim_heavy}");
+        assert!(ssim_light < 1.0);
+    }
+
+    #[test]
+    fn test_known_psnr() {
+        let a = generate::solid(4, 4, image::Rgba([100, 100, 100, 255]));
+        let b = generate::solid(4, 4, image::Rgba([110, 100, 100, 255]));
+        // MSE = 100 / 3 over RGB
+        let expected = 10.0 * (255.0f64 * 255.0 / (100.0 / 3.0)).log10();
+        assert!((psnr(&a, &b).unwrap() - expected).abs() < 1e-9);
+        assert!(psnr(&a, &generate::solid(4, 5, image::Rgba([0; 4]))).is_err());
+    }
+}
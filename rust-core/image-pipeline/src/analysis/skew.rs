@@ -0,0 +1,190 @@
+//! Skew estimation for scanned documents
+
+use super::luma;
+use image::imageops::{self, FilterType};
+use image::{Rgba, RgbaImage};
+use rayon::prelude::*;
+
+/// Longest side of the copy the skew search runs on
+const SKEW_SAMPLE_SIZE: u32 = 800;
+/// Largest skew considered, in degrees either way
+pub const MAX_SKEW_DEGREES: f32 = 15.0;
+
+/// Estimate how far the text lines of a document are rotated, in degrees
+///
+/// Positive means clockwise, so `filters::deskew` rotates by the negated
+/// value. Dark (ink) pixels are projected onto the vertical axis at candidate
+/// angles; the angle whose profile has the sharpest steps (text lines falling
+/// into as few rows as possible) wins. The search covers +-15 degrees in two
+/// passes, 0.5 degree steps then 0.05. Returns 0 when there is no ink.
+pub fn estimate_skew(image: &RgbaImage) -> f32 {
+    let (points, height) = ink_points(image);
+    if points.len() < 2 {
+        return 0.0;
+    }
+
+    let score = |degrees: f32| profile_sharpness(&points, height, degrees);
+    let coarse = search(&score, -MAX_SKEW_DEGREES, MAX_SKEW_DEGREES, 0.5);
+    search(&score, coarse - 0.5, coarse + 0.5, 0.05)
+}
+
+// Best-scoring angle in `from..=to`; the smallest angle wins ties
+fn search<F: Fn(f32) -> f64 + Sync>(score: &F, from: f32, to: f32, step: f32) -> f32 {
+    let steps = ((to - from) / step).round() as usize;
+    (0..=steps)
+        .into_par_iter()
+        .map(|i| {
+            let degrees = from + i as f32 * step;
+            (degrees, score(degrees))
+        })
+        .reduce(
+            || (0.0, f64::MIN),
+            |a, b| {
+                if b.1 > a.1 || (b.1 == a.1 && b.0.abs() < a.0.abs()) {
+                    b
+                } else {
+                    a
+                }
+            },
+        )
+        .0
+}
+
+// Squared differences between neighbouring row counts after undoing a skew of
+// `degrees`; peaks when the edges of text lines are sharpest (Postl's method)
+fn profile_sharpness(points: &[(f32, f32)], height: u32, degrees: f32) -> f64 {
+    let (sin, cos) = degrees.to_radians().sin_cos();
+    let margin = height as f32;
+    let mut rows = vec![0u32; height as usize * 3];
+    for &(x, y) in points {
+        let projected = y * cos - x * sin + margin;
+        if let Some(count) = rows.get_mut(projected.max(0.0) as usize) {
+            *count += 1;
+        }
+    }
+    rows.windows(2)
+        .map(|pair| (pair[1] as f64 - pair[0] as f64).powi(2))
+        .sum()
+}
+
+// Coordinates (relative to the center) of pixels darker than the Otsu
+// threshold, on a downscaled copy, plus that copy's height
+fn ink_points(image: &RgbaImage) -> (Vec<(f32, f32)>, u32) {
+    if image.width() == 0 || image.height() == 0 {
+        return (Vec::new(), 0);
+    }
+    let scale = (SKEW_SAMPLE_SIZE as f64 / image.width().max(image.height()) as f64).min(1.0);
+    let sample = imageops::resize(
+        image,
+        crate::scale_dimension(image.width(), scale),
+        crate::scale_dimension(image.height(), scale),
+        FilterType::Triangle,
+    );
+
+    let values = luma(&sample);
+    let threshold = otsu_threshold(&values);
+    let (w, h) = (sample.width() as usize, sample.height());
+    let (cx, cy) = (w as f32 / 2.0, h as f32 / 2.0);
+    let points = values
+        .iter()
+        .enumerate()
+        .filter(|(_, &v)| v < threshold)
+        .map(|(i, _)| ((i % w) as f32 - cx, (i / w) as f32 - cy))
+        .collect();
+    (points, h)
+}
+
+/// Per-channel median of the pixels at or above the Otsu threshold (the paper)
+///
+/// The median ignores the anti-aliased fringe around text that would darken a mean.
+pub(crate) fn paper_color(image: &RgbaImage) -> Rgba<u8> {
+    let values = luma(image);
+    let threshold = otsu_threshold(&values);
+    let mut histograms = [[0u64; 256]; 4];
+    let mut count = 0u64;
+    for (pixel, &v) in image.pixels().zip(&values) {
+        if v >= threshold {
+            for c in 0..4 {
+                histograms[c][pixel[c] as usize] += 1;
+            }
+            count += 1;
+        }
+    }
+    if count == 0 {
+        return Rgba([255; 4]);
+    }
+    Rgba(histograms.map(|histogram| {
+        let mut seen = 0;
+        histogram
+            .iter()
+            .position(|&n| {
+                seen += n;
+                seen * 2 > count
+            })
+            .unwrap_or(255) as u8
+    }))
+}
+
+/// Threshold that best separates a bimodal luma histogram (Otsu's method)
+pub(crate) fn otsu_threshold(values: &[f32]) -> f32 {
+    let mut histogram = [0u64; 256];
+    for &v in values {
+        histogram[v.clamp(0.0, 255.0) as usize] += 1;
+    }
+
+    let total = values.len() as f64;
+    let sum_all: f64 = histogram
+        .iter()
+        .enumerate()
+        .map(|(v, &n)| v as f64 * n as f64)
+        .sum();
+    let (mut weight_below, mut sum_below) = (0.0, 0.0);
+    let (mut best, mut best_variance) = (0usize, -1.0);
+    for (v, &n) in histogram.iter().enumerate() {
+        weight_below += n as f64;
+        sum_below += v as f64 * n as f64;
+        let weight_above = total - weight_below;
+        if weight_below == 0.0 || weight_above == 0.0 {
+            continue;
+        }
+        let mean_below = sum_below / weight_below;
+        let mean_above = (sum_all - sum_below) / weight_above;
+        let variance = weight_below * weight_above * (mean_below - mean_above).powi(2);
+        if variance > best_variance {
+            best_variance = variance;
+            best = v;
+        }
+    }
+    best as f32 + 0.5
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// White page with dark horizontal "text lines", rotated clockwise by `degrees`
+    pub(crate) fn skewed_page(degrees: f32) -> RgbaImage {
+        let (sin, cos) = degrees.to_radians().sin_cos();
+        RgbaImage::from_fn(400, 300, |x, y| {
+            // Map back to the unrotated page
+            let (u, v) = (x as f32 - 200.0, y as f32 - 150.0);
+            let (px, py) = (u * cos + v * sin, -u * sin + v * cos);
+            let on_line = (py + 150.0).rem_euclid(24.0) < 6.0;
+            if px.abs() < 150.0 && py.abs() < 110.0 && on_line {
+                Rgba([20, 20, 20, 255])
+            } else {
+                Rgba([250, 248, 240, 255])
+            }
+        })
+    }
+
+    #[test]
+    fn test_estimate_skew() {
+        for degrees in [-7.0, -1.5, 0.0, 3.0, 12.0] {
+            let estimate = estimate_skew(&skewed_page(degrees));
+            assert!((estimate - degrees).abs() <= 0.15, "{degrees}: {estimate}");
+        }
+        let blank = RgbaImage::from_pixel(50, 50, Rgba([255; 4]));
+        assert_eq!(estimate_skew(&blank), 0.0);
+    }
+}
@@ -0,0 +1,155 @@
+//! Per-channel image statistics
+
+use image::RgbaImage;
+use rayon::prelude::*;
+
+/// Aggregates of one channel, all on the 0-255 scale except `entropy`
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct ChannelStats {
+    pub mean: f64,
+    pub stddev: f64,
+    pub min: u8,
+    pub max: u8,
+    pub median: u8,
+    /// Shannon entropy of the histogram in bits (0 = flat, 8 = uniform)
+    pub entropy: f64,
+}
+
+/// Statistics of every channel plus Rec. 709 luma, from `stats`
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub struct ImageStats {
+    pub pixel_count: u64,
+    pub red: ChannelStats,
+    pub green: ChannelStats,
+    pub blue: ChannelStats,
+    pub alpha: ChannelStats,
+    pub luma: ChannelStats,
+}
+
+// Red, green, blue, alpha and luma
+const CHANNELS: usize = 5;
+
+type Histograms = [[u64; 256]; CHANNELS];
+
+/// Mean, standard deviation, min/max, median and entropy of each channel
+///
+/// All values come from per-channel histograms built in a single parallel pass
+/// over the pixels. An empty image yields all-zero statistics.
+pub fn stats(image: &RgbaImage) -> ImageStats {
+    let row_len = (image.width() as usize * 4).max(4);
+    let histograms = image
+        .as_raw()
+        .par_chunks(row_len)
+        .fold(
+            || Box::new([[0u64; 256]; CHANNELS]),
+            |mut hist: Box<Histograms>, row| {
+                for p in row.chunks_exact(4) {
+                    for c in 0..4 {
+                        hist[c][p[c] as usize] += 1;
+                    }
+                    // Integer Rec. 709 weights (sum to 2^16)
+                    let luma =
+                        (13933 * p[0] as u32 + 46871 * p[1] as u32 + 4732 * p[2] as u32 + 32768)
+                            >> 16;
+                    hist[4][luma as usize] += 1;
+                }
+                hist
+            },
+        )
+        .reduce(
+            || Box::new([[0u64; 256]; CHANNELS]),
+            |mut a, b| {
+                for (ha, hb) in a.iter_mut().zip(b.iter()) {
+                    for (x, y) in ha.iter_mut().zip(hb.iter()) {
+                        *x += y;
+                    }
+                }
+                a
+            },
+        );
+
+    let [red, green, blue, alpha, luma] = histograms.map(|h| channel_stats(&h));
+    ImageStats {
+        pixel_count: image.width() as u64 * image.height() as u64,
+        red,
+        green,
+        blue,
+        alpha,
+        luma,
+    }
+}
+
+fn channel_stats(histogram: &[u64; 256]) -> ChannelStats {
+    let count: u64 = histogram.iter().sum();
+    if count == 0 {
+        return ChannelStats::default();
+    }
+
+    let occupied = || histogram.iter().enumerate().filter(|(_, &n)| n > 0);
+    let min = occupied().next().map_or(0, |(v, _)| v as u8);
+    let max = occupied().next_back().map_or(0, |(v, _)| v as u8);
+
+    let total = count as f64;
+    let mean = occupied().map(|(v, &n)| v as f64 * n as f64).sum::<f64>() / total;
+    let variance = occupied()
+        .map(|(v, &n)| (v as f64 - mean).powi(2) * n as f64)
+        .sum::<f64>()
+        / total;
+    let entropy = -occupied()
+        .map(|(_, &n)| {
+            let p = n as f64 / total;
+            p * p.log2()
+        })
+        .sum::<f64>();
+
+    let mut seen = 0;
+    let median = histogram
+        .iter()
+        .position(|&n| {
+            seen += n;
+            seen * 2 >= count
+        })
+        .unwrap_or(0) as u8;
+
+    ChannelStats {
+        mean,
+        stddev: variance.sqrt(),
+        min,
+        max,
+        median,
+        entropy,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgba;
+
+    #[test]
+    fn test_stats() {
+        // Left half black, right half white, opaque
+        let image = RgbaImage::from_fn(10, 4, |x, _| {
+            if x < 5 {
+                Rgba([0, 0, 0, 255])
+            } else {
+                Rgba([255, 255, 255, 255])
+            }
+        });
+        let stats = stats(&image);
+
+        assert_eq!(stats.pixel_count, 40);
+        assert_eq!((stats.red.min, stats.red.max), (0, 255));
+        assert!((stats.red.mean - 127.5).abs() < 1e-9);
+        assert!((stats.red.stddev - 127.5).abs() < 1e-9);
+        assert!((stats.luma.entropy - 1.0).abs() < 1e-9);
+        assert_eq!(stats.luma.median, 0);
+        assert_eq!(stats.alpha.entropy, 0.0);
+        assert_eq!(stats.alpha.mean, 255.0);
+    }
+
+    #[test]
+    fn test_empty_image() {
+        assert_eq!(stats(&RgbaImage::new(0, 0)), ImageStats::default());
+    }
+}
@@ -0,0 +1,400 @@
+//! Multi-frame images: decoding, per-frame processing and re-encoding
+//!
+//! Frames are stored fully composited (disposal and blending already applied),
+//! so every frame is a complete image that filters can work on independently.
+
+use crate::{FilterOperation, ImagePipeline, PipelineError, Result};
+use image::codecs::gif::{GifDecoder, GifEncoder};
+use image::{AnimationDecoder, Delay, Frame, RgbaImage};
+use std::io::Cursor;
+use std::time::Duration;
+
+/// How often an animation plays
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Repeat {
+    /// Loop forever
+    #[default]
+    Infinite,
+    /// Repeat this many times after the first play (0 = play once)
+    Finite(u16),
+}
+
+/// One frame of an animation
+#[derive(Debug, Clone, PartialEq)]
+pub struct AnimationFrame {
+    pub image: RgbaImage,
+    /// How long the frame is shown, in milliseconds
+    pub delay_ms: u32,
+}
+
+/// A sequence of equally sized frames with timing
+#[derive(Debug, Clone, PartialEq)]
+pub struct Animation {
+    pub frames: Vec<AnimationFrame>,
+    pub repeat: Repeat,
+}
+
+impl Animation {
+    /// Decode a GIF, keeping frame delays and the loop count
+    ///
+    /// Still GIFs decode to a single frame.
+    pub fn decode_gif(bytes: &[u8]) -> Result<Self> {
+        let decoder = GifDecoder::new(Cursor::new(bytes))?;
+        let frames = decoder
+            .into_frames()
+            .map(|frame| {
+                let frame = frame?;
+                let delay_ms = delay_to_ms(frame.delay());
+                Ok(AnimationFrame {
+                    image: frame.into_buffer(),
+                    delay_ms,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        if frames.is_empty() {
+            return Err(PipelineError::ProcessingError(
+                "GIF contains no frames".to_string(),
+            ));
+        }
+
+        Ok(Self {
+            frames,
+            repeat: gif_repeat(bytes),
+        })
+    }
+
+    /// Encode as a GIF; colors are quantized to a 256-color palette per frame
+    pub fn encode_gif(&self) -> Result<Vec<u8>> {
+        self.check()?;
+        let mut buffer = Vec::new();
+        {
+            let mut encoder = GifEncoder::new_with_speed(&mut buffer, 10);
+            encoder.set_repeat(match self.repeat {
+                Repeat::Infinite => image::codecs::gif::Repeat::Infinite,
+                Repeat::Finite(n) => image::codecs::gif::Repeat::Finite(n),
+            })?;
+            encoder.encode_frames(self.frames.iter().map(|frame| {
+                Frame::from_parts(
+                    frame.image.clone(),
+                    0,
+                    0,
+                    Delay::from_saturating_duration(Duration::from_millis(frame.delay_ms as u64)),
+                )
+            }))?;
+        }
+        Ok(buffer)
+    }
+
+    /// Encode as an animated PNG (APNG); lossless with full alpha
+    ///
+    /// Viewers without APNG support show the first frame.
+    pub fn encode_apng(&self) -> Result<Vec<u8>> {
+        self.check()?;
+        let (width, height) = self.dimensions();
+        let mut buffer = Vec::new();
+        {
+            let mut encoder = png::Encoder::new(&mut buffer, width, height);
+            encoder.set_color(png::ColorType::Rgba);
+            encoder.set_depth(png::BitDepth::Eight);
+            encoder
+                .set_animated(self.frames.len() as u32, self.repeat.plays())
+                .map_err(encoding_error)?;
+            let mut writer = encoder.write_header().map_err(encoding_error)?;
+            for frame in &self.frames {
+                writer
+                    .set_frame_delay(frame.delay_ms.min(u16::MAX as u32) as u16, 1000)
+                    .map_err(encoding_error)?;
+                writer
+                    .write_image_data(frame.image.as_raw())
+                    .map_err(encoding_error)?;
+            }
+            writer.finish().map_err(encoding_error)?;
+        }
+        Ok(buffer)
+    }
+
+    /// Encode as an animated WebP; every frame is stored losslessly
+    pub fn encode_webp(&self) -> Result<Vec<u8>> {
+        self.check()?;
+        let (width, height) = self.dimensions();
+        let has_alpha = self
+            .frames
+            .iter()
+            .any(|frame| frame.image.pixels().any(|p| p[3] < 255));
+
+        let mut chunks = Vec::new();
+        // VP8X: extended header with the animation (and alpha) flag and canvas size
+        let mut vp8x = vec![if has_alpha { 0x12 } else { 0x02 }, 0, 0, 0];
+        vp8x.extend_from_slice(&u24(width - 1));
+        vp8x.extend_from_slice(&u24(height - 1));
+        push_chunk(&mut chunks, b"VP8X", &vp8x);
+
+        // ANIM: transparent background and loop count (0 = forever)
+        let loops = self.repeat.plays().min(u16::MAX as u32) as u16;
+        let mut anim = vec![0, 0, 0, 0];
+        anim.extend_from_slice(&loops.to_le_bytes());
+        push_chunk(&mut chunks, b"ANIM", &anim);
+
+        for frame in &self.frames {
+            let mut still = Vec::new();
+            image::codecs::webp::WebPEncoder::new_lossless(&mut still).encode(
+                frame.image.as_raw(),
+                width,
+                height,
+                image::ExtendedColorType::Rgba8,
+            )?;
+
+            // ANMF: full-canvas frame at the origin that replaces the previous one
+            let mut anmf = Vec::new();
+            anmf.extend_from_slice(&u24(0));
+            anmf.extend_from_slice(&u24(0));
+            anmf.extend_from_slice(&u24(width - 1));
+            anmf.extend_from_slice(&u24(height - 1));
+            anmf.extend_from_slice(&u24(frame.delay_ms.min(0xFF_FFFF)));
+            anmf.push(0x02);
+            anmf.extend_from_slice(bitstream_chunks(&still)?);
+            push_chunk(&mut chunks, b"ANMF", &anmf);
+        }
+
+        let mut buffer = Vec::with_capacity(chunks.len() + 12);
+        buffer.extend_from_slice(b"RIFF");
+        buffer.extend_from_slice(&(chunks.len() as u32 + 4).to_le_bytes());
+        buffer.extend_from_slice(b"WEBP");
+        buffer.extend_from_slice(&chunks);
+        Ok(buffer)
+    }
+
+    /// Run `operations` on every frame, keeping timing and loop count
+    ///
+    /// Every frame goes through the same operations, so size-changing operations
+    /// keep all frames the same size.
+    pub fn process(
+        &self,
+        pipeline: &ImagePipeline,
+        operations: &[FilterOperation],
+    ) -> Result<Animation> {
+        let frames = self
+            .frames
+            .iter()
+            .map(|frame| {
+                Ok(AnimationFrame {
+                    image: pipeline.process(&frame.image, operations)?,
+                    delay_ms: frame.delay_ms,
+                })
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Animation {
+            frames,
+            repeat: self.repeat,
+        })
+    }
+
+    /// Width and height shared by all frames
+    pub fn dimensions(&self) -> (u32, u32) {
+        self.frames
+            .first()
+            .map_or((0, 0), |frame| frame.image.dimensions())
+    }
+
+    /// Total play time of one loop in milliseconds
+    pub fn duration_ms(&self) -> u64 {
+        self.frames.iter().map(|frame| frame.delay_ms as u64).sum()
+    }
+
+    // Encoders need at least one frame and a single frame size
+    fn check(&self) -> Result<()> {
+        let size = self.dimensions();
+        if self.frames.is_empty() {
+            return Err(PipelineError::InvalidParameter(
+                "animation has no frames".to_string(),
+            ));
+        }
+        if let Some(frame) = self.frames.iter().find(|f| f.image.dimensions() != size) {
+            return Err(PipelineError::InvalidParameter(format!(
+                "all frames must be {}x{}, got {}x{}",
+                size.0,
+                size.1,
+                frame.image.width(),
+                frame.image.height()
+            )));
+        }
+        Ok(())
+    }
+}
+
+impl Repeat {
+    // Total number of plays as APNG and WebP store it, with 0 meaning forever
+    fn plays(&self) -> u32 {
+        match self {
+            Repeat::Infinite => 0,
+            Repeat::Finite(n) => *n as u32 + 1,
+        }
+    }
+}
+
+fn encoding_error(error: png::EncodingError) -> PipelineError {
+    PipelineError::ProcessingError(format!("APNG encoding failed: {}", error))
+}
+
+fn u24(value: u32) -> [u8; 3] {
+    let bytes = value.to_le_bytes();
+    [bytes[0], bytes[1], bytes[2]]
+}
+
+// Append a RIFF chunk, padded to an even length
+fn push_chunk(out: &mut Vec<u8>, id: &[u8; 4], payload: &[u8]) {
+    out.extend_from_slice(id);
+    out.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+    out.extend_from_slice(payload);
+    if payload.len() % 2 == 1 {
+        out.push(0);
+    }
+}
+
+// The image data chunks (ALPH, VP8 or VP8L) of a still WebP file, which is
+// what an ANMF frame embeds
+fn bitstream_chunks(webp: &[u8]) -> Result<&[u8]> {
+    let invalid = || PipelineError::ProcessingError("unexpected WebP encoder output".to_string());
+    if webp.len() < 12 || &webp[0..4] != b"RIFF" || &webp[8..12] != b"WEBP" {
+        return Err(invalid());
+    }
+    let mut offset = 12;
+    let mut start = None;
+    while offset + 8 <= webp.len() {
+        let id = &webp[offset..offset + 4];
+        let size = u32::from_le_bytes(webp[offset + 4..offset + 8].try_into().unwrap()) as usize;
+        if matches!(id, b"ALPH" | b"VP8 " | b"VP8L") {
+            start.get_or_insert(offset);
+        }
+        offset += 8 + size + size % 2;
+    }
+    start
+        .map(|start| &webp[start..offset.min(webp.len())])
+        .ok_or_else(invalid)
+}
+
+fn delay_to_ms(delay: Delay) -> u32 {
+    let (numerator, denominator) = delay.numer_denom_ms();
+    numerator.checked_div(denominator).unwrap_or(0)
+}
+
+// The loop count lives in the NETSCAPE application extension, which the `image`
+// decoder does not expose; GIFs without one play once
+fn gif_repeat(bytes: &[u8]) -> Repeat {
+    let mut options = gif::DecodeOptions::new();
+    options.set_color_output(gif::ColorOutput::Indexed);
+    match options.read_info(Cursor::new(bytes)) {
+        Ok(decoder) => match decoder.repeat() {
+            gif::Repeat::Infinite => Repeat::Infinite,
+            gif::Repeat::Finite(n) => Repeat::Finite(n),
+        },
+        Err(_) => Repeat::Finite(0),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgba;
+
+    fn sample_animation(repeat: Repeat) -> Animation {
+        let colors = [[255, 0, 0], [0, 255, 0], [0, 0, 255]];
+        Animation {
+            frames: colors
+                .iter()
+                .enumerate()
+                .map(|(i, &[r, g, b])| AnimationFrame {
+                    image: RgbaImage::from_pixel(16, 12, Rgba([r, g, b, 255])),
+                    delay_ms: 40 * (i as u32 + 1),
+                })
+                .collect(),
+            repeat,
+        }
+    }
+
+    #[test]
+    fn test_gif_round_trip() {
+        for repeat in [Repeat::Infinite, Repeat::Finite(3)] {
+            let animation = sample_animation(repeat);
+            let decoded = Animation::decode_gif(&animation.encode_gif().unwrap()).unwrap();
+            assert_eq!(decoded.repeat, repeat);
+            assert_eq!(decoded.dimensions(), (16, 12));
+            let delays: Vec<u32> = decoded.frames.iter().map(|f| f.delay_ms).collect();
+            assert_eq!(delays, [40, 80, 120]);
+            assert_eq!(decoded.duration_ms(), 240);
+            assert_eq!(
+                *decoded.frames[1].image.get_pixel(3, 3),
+                Rgba([0, 255, 0, 255])
+            );
+        }
+    }
+
+    #[test]
+    fn test_process_every_frame() {
+        let animation = sample_animation(Repeat::Infinite);
+        let processed = animation
+            .process(
+                &ImagePipeline::new(),
+                &[
+                    FilterOperation::Invert,
+                    FilterOperation::Resize {
+                        width: 8,
+                        height: 6,
+                        filter: Default::default(),
+                    },
+                ],
+            )
+            .unwrap();
+        assert_eq!(processed.dimensions(), (8, 6));
+        assert_eq!(processed.frames.len(), 3);
+        assert_eq!(processed.frames[2].delay_ms, 120);
+        assert_eq!(
+            *processed.frames[0].image.get_pixel(1, 1),
+            Rgba([0, 255, 255, 255])
+        );
+    }
+
+    #[test]
+    fn test_encode_rejects_mixed_sizes() {
+        let mut animation = sample_animation(Repeat::Infinite);
+        animation.frames[1].image = RgbaImage::new(4, 4);
+        assert!(animation.encode_gif().is_err());
+        assert!(Animation::decode_gif(b"not a gif").is_err());
+    }
+
+    #[test]
+    fn test_apng_round_trip() {
+        let animation = sample_animation(Repeat::Finite(1));
+        let bytes = animation.encode_apng().unwrap();
+        let decoder = image::codecs::png::PngDecoder::new(Cursor::new(&bytes)).unwrap();
+        assert!(decoder.is_apng().unwrap());
+        let frames: Vec<_> = decoder
+            .apng()
+            .unwrap()
+            .into_frames()
+            .collect::<std::result::Result<_, _>>()
+            .unwrap();
+        assert_eq!(frames.len(), 3);
+        assert_eq!(delay_to_ms(frames[2].delay()), 120);
+        assert_eq!(*frames[1].buffer().get_pixel(5, 5), Rgba([0, 255, 0, 255]));
+    }
+
+    #[test]
+    fn test_webp_round_trip() {
+        let mut animation = sample_animation(Repeat::Infinite);
+        animation.frames[0]
+            .image
+            .put_pixel(0, 0, Rgba([9, 9, 9, 128]));
+        let bytes = animation.encode_webp().unwrap();
+        let decoder = image::codecs::webp::WebPDecoder::new(Cursor::new(&bytes)).unwrap();
+        assert!(decoder.has_animation());
+        let frames: Vec<_> = decoder
+            .into_frames()
+            .collect::<std::result::Result<_, _>>()
+            .unwrap();
+        assert_eq!(frames.len(), 3);
+        assert_eq!(delay_to_ms(frames[1].delay()), 80);
+        assert_eq!(*frames[0].buffer().get_pixel(0, 0), Rgba([9, 9, 9, 128]));
+        assert_eq!(*frames[2].buffer().get_pixel(7, 7), Rgba([0, 0, 255, 255]));
+    }
+}
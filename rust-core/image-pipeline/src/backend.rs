@@ -0,0 +1,189 @@
+//! Execution backends that filter operations are dispatched to
+//!
+//! `ImagePipeline` hands every operation to its backend, falling back to the
+//! reference CPU implementation for operations the backend does not support.
+//! Runs of point operations fused by the optimizer always run on the CPU, as
+//! do blurs, sharpening and resizing of translucent images with straight alpha
+//! (see `alpha`).
+//! With the `gpu` feature, `GpuBackend` is selected when a GPU is available.
+
+use crate::{simd, CancellationToken, FilterOperation, ImagePipeline, Result};
+use image::RgbaImage;
+use std::sync::Arc;
+
+/// An implementation of some or all filter operations
+pub trait Backend: Send + Sync {
+    /// Short identifier, e.g. "cpu"
+    fn name(&self) -> &str;
+
+    /// Whether `apply` handles `op`; unsupported operations run on `CpuBackend`
+    fn supports(&self, op: &FilterOperation) -> bool;
+
+    /// Run one validated operation
+    fn apply(
+        &self,
+        image: &RgbaImage,
+        op: &FilterOperation,
+        token: &CancellationToken,
+    ) -> Result<RgbaImage>;
+}
+
+/// Reference implementation of every operation, multithreaded with rayon
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CpuBackend;
+
+impl Backend for CpuBackend {
+    fn name(&self) -> &str {
+        "cpu"
+    }
+
+    fn supports(&self, _op: &FilterOperation) -> bool {
+        true
+    }
+
+    fn apply(
+        &self,
+        image: &RgbaImage,
+        op: &FilterOperation,
+        token: &CancellationToken,
+    ) -> Result<RgbaImage> {
+        ImagePipeline::apply_operation(image, op, token)
+    }
+}
+
+/// In-place kernels from `simd` for the operations they reproduce exactly
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SimdBackend;
+
+impl SimdBackend {
+    /// Whether the crate was built with the `simd` feature
+    pub fn is_available() -> bool {
+        cfg!(feature = "simd")
+    }
+}
+
+impl Backend for SimdBackend {
+    fn name(&self) -> &str {
+        "cpu-simd"
+    }
+
+    fn supports(&self, op: &FilterOperation) -> bool {
+        matches!(
+            op,
+            FilterOperation::Grayscale(_)
+                | FilterOperation::Brightness(_)
+                | FilterOperation::Invert
+        )
+    }
+
+    fn apply(
+        &self,
+        image: &RgbaImage,
+        op: &FilterOperation,
+        token: &CancellationToken,
+    ) -> Result<RgbaImage> {
+        let mut result = image.clone();
+        match *op {
+            // Same truncation as `Lut256::brightness`
+            FilterOperation::Brightness(value) => {
+                simd::brightness_simd(&mut result, (value * 255.0) as i16)
+            }
+            FilterOperation::Grayscale(mode) => simd::grayscale_fast(&mut result, mode),
+            FilterOperation::Invert => simd::invert_simd(&mut result),
+            _ => return CpuBackend.apply(image, op, token),
+        }
+        Ok(result)
+    }
+}
+
+/// The fastest backend available in this build
+pub fn detect() -> Arc<dyn Backend> {
+    #[cfg(feature = "gpu")]
+    if let Some(gpu) = crate::GpuBackend::shared() {
+        return gpu;
+    }
+    if SimdBackend::is_available() {
+        Arc::new(SimdBackend)
+    } else {
+        Arc::new(CpuBackend)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::GrayscaleMode;
+    use image::Rgba;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    fn create_test_image() -> RgbaImage {
+        RgbaImage::from_fn(40, 30, |x, y| {
+            Rgba([(x * 6) as u8, (y * 8) as u8, ((x * y) % 256) as u8, 200])
+        })
+    }
+
+    #[test]
+    fn test_simd_backend_matches_cpu() {
+        let image = create_test_image();
+        let token = CancellationToken::new();
+        for op in [
+            FilterOperation::Grayscale(GrayscaleMode::Bt709),
+            FilterOperation::Grayscale(GrayscaleMode::Average),
+            FilterOperation::Brightness(0.3),
+            FilterOperation::Brightness(-0.55),
+            FilterOperation::Invert,
+        ] {
+            assert!(SimdBackend.supports(&op));
+            assert_eq!(
+                SimdBackend.apply(&image, &op, &token).unwrap(),
+                CpuBackend.apply(&image, &op, &token).unwrap()
+            );
+        }
+        assert!(!SimdBackend.supports(&FilterOperation::Sepia));
+    }
+
+    // Handles invert only, counting how often it is asked to
+    #[derive(Default)]
+    struct CountingBackend {
+        calls: AtomicUsize,
+    }
+
+    impl Backend for CountingBackend {
+        fn name(&self) -> &str {
+            "counting"
+        }
+
+        fn supports(&self, op: &FilterOperation) -> bool {
+            matches!(op, FilterOperation::Invert)
+        }
+
+        fn apply(
+            &self,
+            image: &RgbaImage,
+            _op: &FilterOperation,
+            _token: &CancellationToken,
+        ) -> Result<RgbaImage> {
+            self.calls.fetch_add(1, Ordering::SeqCst);
+            Ok(crate::filters::invert(image))
+        }
+    }
+
+    #[test]
+    fn test_custom_backend_with_fallback() {
+        let backend = Arc::new(CountingBackend::default());
+        let pipeline = ImagePipeline::new().with_backend(backend.clone());
+        assert_eq!(pipeline.backend().name(), "counting");
+
+        let image = create_test_image();
+        let operations = [
+            FilterOperation::Invert,
+            FilterOperation::Blur(1.0),
+            FilterOperation::Invert,
+        ];
+        let result = pipeline.process(&image, &operations).unwrap();
+        assert_eq!(backend.calls.load(Ordering::SeqCst), 2);
+
+        let reference = ImagePipeline::new().with_backend(Arc::new(CpuBackend));
+        assert_eq!(result, reference.process(&image, &operations).unwrap());
+    }
+}
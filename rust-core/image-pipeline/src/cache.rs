@@ -0,0 +1,269 @@
+//! Caching encoded results of `ImagePipeline::process_bytes`
+//!
+//! Entries are keyed by a hash of the source bytes and a hash of the
+//! operations (as in `ImagePipeline::digest`), output format, quality and
+//! library version. Custom filters are identified by name and params only, so
+//! re-registering a name with different behavior needs a fresh cache.
+
+use crate::digest;
+use crate::hash::{sha256, to_hex, Digest};
+use crate::{FilterOperation, OutputFormat, Result};
+use std::collections::{BTreeMap, HashMap};
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Mutex;
+
+/// Identifies one processed result
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct CacheKey {
+    /// SHA-256 of the encoded source image
+    pub source: [u8; 32],
+    /// SHA-256 of the operations and output settings
+    pub pipeline: [u8; 32],
+}
+
+impl CacheKey {
+    pub fn new(
+        source: &[u8],
+        operations: &[FilterOperation],
+        format: OutputFormat,
+        quality: u8,
+    ) -> Self {
+        let mut hasher = digest::operations_hasher(operations);
+        hasher.update(concat!("image-pipeline ", env!("CARGO_PKG_VERSION"), "\n").as_bytes());
+        hasher.update(format.extension().as_bytes());
+        hasher.update([quality]);
+        Self {
+            source: sha256(source),
+            pipeline: hasher.finalize().into(),
+        }
+    }
+
+    /// 128 hex characters, usable as a file name
+    pub fn to_hex(&self) -> String {
+        to_hex(&self.source) + &to_hex(&self.pipeline)
+    }
+}
+
+/// Storage for encoded results
+pub trait PipelineCache: Send + Sync {
+    fn get(&self, key: &CacheKey) -> Option<Vec<u8>>;
+
+    /// Store a result; implementations may drop it, e.g. when it is too large
+    fn put(&self, key: &CacheKey, encoded: &[u8]);
+}
+
+/// In-memory cache evicting the least recently used entries beyond a byte budget
+pub struct MemoryCache {
+    max_bytes: usize,
+    state: Mutex<LruState>,
+}
+
+#[derive(Default)]
+struct LruState {
+    entries: HashMap<CacheKey, (Vec<u8>, u64)>,
+    // Last use -> key, oldest first
+    recency: BTreeMap<u64, CacheKey>,
+    bytes: usize,
+    clock: u64,
+}
+
+impl LruState {
+    fn touch(&mut self, key: &CacheKey) {
+        self.clock += 1;
+        if let Some((_, used)) = self.entries.get_mut(key) {
+            self.recency.remove(used);
+            *used = self.clock;
+            self.recency.insert(self.clock, *key);
+        }
+    }
+}
+
+impl MemoryCache {
+    /// Keep at most `max_bytes` of encoded results
+    pub fn new(max_bytes: usize) -> Self {
+        Self {
+            max_bytes,
+            state: Mutex::new(LruState::default()),
+        }
+    }
+
+    /// Bytes currently held
+    pub fn used_bytes(&self) -> usize {
+        self.state.lock().unwrap_or_else(|e| e.into_inner()).bytes
+    }
+}
+
+impl PipelineCache for MemoryCache {
+    fn get(&self, key: &CacheKey) -> Option<Vec<u8>> {
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        state.touch(key);
+        state.entries.get(key).map(|(encoded, _)| encoded.clone())
+    }
+
+    fn put(&self, key: &CacheKey, encoded: &[u8]) {
+        if encoded.len() > self.max_bytes {
+            return;
+        }
+        let mut state = self.state.lock().unwrap_or_else(|e| e.into_inner());
+        state.clock += 1;
+        let clock = state.clock;
+        if let Some((old, used)) = state.entries.insert(*key, (encoded.to_vec(), clock)) {
+            state.recency.remove(&used);
+            state.bytes -= old.len();
+        }
+        state.recency.insert(clock, *key);
+        state.bytes += encoded.len();
+
+        while state.bytes > self.max_bytes {
+            let Some((_, oldest)) = state.recency.pop_first() else {
+                break;
+            };
+            if let Some((evicted, _)) = state.entries.remove(&oldest) {
+                state.bytes -= evicted.len();
+            }
+        }
+    }
+}
+
+/// Cache of one file per entry in a directory
+///
+/// Files are written atomically, so several processes can share the
+/// directory. Nothing is evicted; prune old files externally.
+pub struct DiskCache {
+    dir: PathBuf,
+    temp_counter: AtomicU64,
+}
+
+impl DiskCache {
+    /// Use `dir`, creating it if needed
+    pub fn new(dir: impl Into<PathBuf>) -> Result<Self> {
+        let dir = dir.into();
+        std::fs::create_dir_all(&dir)?;
+        Ok(Self {
+            dir,
+            temp_counter: AtomicU64::new(0),
+        })
+    }
+}
+
+impl PipelineCache for DiskCache {
+    fn get(&self, key: &CacheKey) -> Option<Vec<u8>> {
+        std::fs::read(self.dir.join(key.to_hex())).ok()
+    }
+
+    fn put(&self, key: &CacheKey, encoded: &[u8]) {
+        let temp = self.dir.join(format!(
+            ".{}.{}.{}",
+            key.to_hex(),
+            std::process::id(),
+            self.temp_counter.fetch_add(1, Ordering::Relaxed)
+        ));
+        // A failed write only costs a future cache miss
+        if std::fs::write(&temp, encoded).is_err()
+            || std::fs::rename(&temp, self.dir.join(key.to_hex())).is_err()
+        {
+            let _ = std::fs::remove_file(&temp);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ImagePipeline;
+    use crate::Region;
+    use image::{GrayImage, Rgba, RgbaImage};
+    use std::sync::Arc;
+
+    fn key(n: u8) -> CacheKey {
+        CacheKey {
+            source: [n; 32],
+            pipeline: [0; 32],
+        }
+    }
+
+    #[test]
+    fn test_keys_cover_parameters_and_embedded_data() {
+        let source = b"image bytes";
+        let base = CacheKey::new(source, &[FilterOperation::Blur(1.0)], OutputFormat::Png, 90);
+        assert_eq!(
+            base,
+            CacheKey::new(source, &[FilterOperation::Blur(1.0)], OutputFormat::Png, 90)
+        );
+        for other in [
+            CacheKey::new(source, &[FilterOperation::Blur(1.5)], OutputFormat::Png, 90),
+            CacheKey::new(
+                source,
+                &[FilterOperation::Blur(1.0)],
+                OutputFormat::Jpeg,
+                90,
+            ),
+            CacheKey::new(source, &[FilterOperation::Blur(1.0)], OutputFormat::Png, 80),
+            CacheKey::new(
+                b"other",
+                &[FilterOperation::Blur(1.0)],
+                OutputFormat::Png,
+                90,
+            ),
+        ] {
+            assert_ne!(base, other);
+        }
+
+        let masked = |value: u8| {
+            [FilterOperation::Masked {
+                region: Region::Mask(Arc::new(GrayImage::from_pixel(2, 2, image::Luma([value])))),
+                op: Box::new(FilterOperation::Invert),
+            }]
+        };
+        assert_ne!(
+            CacheKey::new(source, &masked(0), OutputFormat::Png, 90),
+            CacheKey::new(source, &masked(255), OutputFormat::Png, 90)
+        );
+        assert_eq!(base.to_hex().len(), 128);
+    }
+
+    #[test]
+    fn test_memory_cache_evicts_least_recently_used() {
+        let cache = MemoryCache::new(10);
+        cache.put(&key(1), &[1; 4]);
+        cache.put(&key(2), &[2; 4]);
+        assert!(cache.get(&key(1)).is_some());
+        cache.put(&key(3), &[3; 4]);
+        assert_eq!(cache.get(&key(1)), Some(vec![1; 4]));
+        assert_eq!(cache.get(&key(2)), None);
+        assert_eq!(cache.used_bytes(), 8);
+
+        cache.put(&key(1), &[9; 2]);
+        assert_eq!(cache.used_bytes(), 6);
+        cache.put(&key(4), &[4; 11]);
+        assert_eq!(cache.get(&key(4)), None);
+    }
+
+    #[test]
+    fn test_process_bytes_uses_cache() {
+        let dir = std::env::temp_dir().join(format!("image-pipeline-cache-{}", std::process::id()));
+        let disk = Arc::new(DiskCache::new(&dir).unwrap());
+        let pipeline = ImagePipeline::new().with_cache(disk.clone());
+
+        let image = RgbaImage::from_pixel(4, 4, Rgba([10, 20, 30, 255]));
+        let source = ImagePipeline::encode_to_png(&image).unwrap();
+        let ops = [FilterOperation::Invert];
+        let first = pipeline
+            .process_bytes(&source, &ops, OutputFormat::Png, 90)
+            .unwrap();
+        let key = CacheKey::new(&source, &ops, OutputFormat::Png, 90);
+        assert_eq!(disk.get(&key), Some(first.clone()));
+
+        // A planted entry proves the second call is served from the cache
+        disk.put(&key, b"cached");
+        let second = pipeline
+            .process_bytes(&source, &ops, OutputFormat::Png, 90)
+            .unwrap();
+        assert_eq!(second, b"cached");
+        assert!(pipeline
+            .process_bytes(b"not an image", &ops, OutputFormat::Png, 90)
+            .is_err());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,237 @@
+//! Splitting images into channels and putting them back together
+//!
+//! Filters work on RGBA images, so `apply_to_channel` shows a single channel
+//! to them as an opaque gray image and reads the result back from red. This
+//! lets e.g. a chroma-key matte in the alpha channel be blurred on its own.
+
+use crate::{FilterOperation, ImagePipeline, PipelineError, Result};
+use image::{GrayImage, RgbaImage};
+use rayon::prelude::*;
+
+/// One channel of an RGBA image
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Channel {
+    Red,
+    Green,
+    Blue,
+    Alpha,
+}
+
+impl Channel {
+    /// Parse a channel name: "red", "green", "blue", "alpha" or their initials
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "red" | "r" => Some(Channel::Red),
+            "green" | "g" => Some(Channel::Green),
+            "blue" | "b" => Some(Channel::Blue),
+            "alpha" | "a" => Some(Channel::Alpha),
+            _ => None,
+        }
+    }
+
+    /// Position of the channel within an RGBA pixel
+    pub fn index(self) -> usize {
+        match self {
+            Channel::Red => 0,
+            Channel::Green => 1,
+            Channel::Blue => 2,
+            Channel::Alpha => 3,
+        }
+    }
+}
+
+/// Split an image into red, green, blue and alpha planes
+pub fn split(image: &RgbaImage) -> [GrayImage; 4] {
+    [
+        extract(image, Channel::Red),
+        extract(image, Channel::Green),
+        extract(image, Channel::Blue),
+        extract(image, Channel::Alpha),
+    ]
+}
+
+/// Copy one channel into its own plane
+pub fn extract(image: &RgbaImage, channel: Channel) -> GrayImage {
+    let index = channel.index();
+    let data = image
+        .as_raw()
+        .par_chunks(4)
+        .map(|pixel| pixel[index])
+        .collect();
+    GrayImage::from_raw(image.width(), image.height(), data).unwrap()
+}
+
+/// Combine four planes of the same size into an RGBA image
+pub fn merge(r: &GrayImage, g: &GrayImage, b: &GrayImage, a: &GrayImage) -> Result<RgbaImage> {
+    let (width, height) = r.dimensions();
+    for plane in [g, b, a] {
+        check_size(plane, width, height)?;
+    }
+    let mut result = RgbaImage::new(width, height);
+    result
+        .par_chunks_mut(4)
+        .zip(r.as_raw().par_iter())
+        .zip(g.as_raw().par_iter())
+        .zip(b.as_raw().par_iter().zip(a.as_raw().par_iter()))
+        .for_each(|(((pixel, &r), &g), (&b, &a))| {
+            pixel.copy_from_slice(&[r, g, b, a]);
+        });
+    Ok(result)
+}
+
+/// Copy of `image` with `channel` replaced by `plane`
+pub fn replace(image: &RgbaImage, channel: Channel, plane: &GrayImage) -> Result<RgbaImage> {
+    check_size(plane, image.width(), image.height())?;
+    let index = channel.index();
+    let mut result = image.clone();
+    result
+        .par_chunks_mut(4)
+        .zip(plane.as_raw().par_iter())
+        .for_each(|(pixel, &value)| pixel[index] = value);
+    Ok(result)
+}
+
+/// Run an RGBA `filter` on one channel only, leaving the others untouched
+///
+/// The filter sees the channel as an opaque gray image; red of its output
+/// becomes the new channel. Fails if the filter changes the size.
+pub fn apply_to_channel<F>(image: &RgbaImage, channel: Channel, filter: F) -> Result<RgbaImage>
+where
+    F: FnOnce(&RgbaImage) -> RgbaImage,
+{
+    apply_with(image, channel, |gray| Ok(filter(gray)))
+}
+
+impl ImagePipeline {
+    /// Process a single channel of `image` with `operations`
+    ///
+    /// See `channels::apply_to_channel`; operations that change the size are
+    /// refused.
+    pub fn process_channel(
+        &self,
+        image: &RgbaImage,
+        channel: Channel,
+        operations: &[FilterOperation],
+    ) -> Result<RgbaImage> {
+        if let Some(op) = operations.iter().find(|op| op.changes_size()) {
+            return Err(PipelineError::InvalidParameter(format!(
+                "{} cannot run on a single channel because it changes the image size",
+                op.name()
+            )));
+        }
+        apply_with(image, channel, |gray| self.process(gray, operations))
+    }
+}
+
+fn apply_with<F>(image: &RgbaImage, channel: Channel, filter: F) -> Result<RgbaImage>
+where
+    F: FnOnce(&RgbaImage) -> Result<RgbaImage>,
+{
+    let index = channel.index();
+    let mut gray = RgbaImage::new(image.width(), image.height());
+    gray.par_chunks_mut(4)
+        .zip(image.as_raw().par_chunks(4))
+        .for_each(|(out, pixel)| {
+            let value = pixel[index];
+            out.copy_from_slice(&[value, value, value, 255]);
+        });
+    let filtered = filter(&gray)?;
+    if filtered.dimensions() != image.dimensions() {
+        return Err(PipelineError::InvalidParameter(format!(
+            "channel filter changed the size from {}x{} to {}x{}",
+            image.width(),
+            image.height(),
+            filtered.width(),
+            filtered.height()
+        )));
+    }
+    let mut result = image.clone();
+    result
+        .par_chunks_mut(4)
+        .zip(filtered.as_raw().par_chunks(4))
+        .for_each(|(pixel, filtered)| pixel[index] = filtered[0]);
+    Ok(result)
+}
+
+fn check_size(plane: &GrayImage, width: u32, height: u32) -> Result<()> {
+    if plane.dimensions() != (width, height) {
+        return Err(PipelineError::InvalidParameter(format!(
+            "channel of {}x{} does not match {}x{}",
+            plane.width(),
+            plane.height(),
+            width,
+            height
+        )));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{filters, ResampleFilter};
+    use image::{Luma, Rgba};
+
+    fn create_test_image() -> RgbaImage {
+        RgbaImage::from_fn(16, 12, |x, y| {
+            Rgba([
+                (x * 16) as u8,
+                (y * 20) as u8,
+                (x * y) as u8,
+                if x < 8 { 255 } else { 0 },
+            ])
+        })
+    }
+
+    #[test]
+    fn test_split_merge_round_trip() {
+        let image = create_test_image();
+        let [r, g, b, a] = split(&image);
+        assert_eq!(r[(3, 5)], Luma([48]));
+        assert_eq!(g[(3, 5)], Luma([100]));
+        assert_eq!(a[(9, 0)], Luma([0]));
+        assert_eq!(merge(&r, &g, &b, &a).unwrap(), image);
+
+        // Swapping planes swaps channels
+        let swapped = merge(&b, &g, &r, &a).unwrap();
+        assert_eq!(swapped[(3, 5)], Rgba([15, 100, 48, 255]));
+
+        let small = GrayImage::new(2, 2);
+        assert!(matches!(
+            merge(&r, &g, &b, &small),
+            Err(PipelineError::InvalidParameter(_))
+        ));
+        assert!(replace(&image, Channel::Green, &small).is_err());
+        let zeroed = replace(&image, Channel::Red, &GrayImage::new(16, 12)).unwrap();
+        assert!(zeroed.pixels().all(|p| p[0] == 0));
+        assert_eq!(extract(&zeroed, Channel::Blue), b);
+    }
+
+    #[test]
+    fn test_blur_only_alpha() {
+        let image = create_test_image();
+        let result =
+            apply_to_channel(&image, Channel::Alpha, |matte| filters::blur(matte, 1.5)).unwrap();
+        // The matte edge is softened, colors are untouched
+        assert!(result[(8, 6)][3] > 0 && result[(7, 6)][3] < 255);
+        for (a, b) in image.pixels().zip(result.pixels()) {
+            assert_eq!(a.0[..3], b.0[..3]);
+        }
+
+        let pipeline = ImagePipeline::new();
+        let piped = pipeline
+            .process_channel(&image, Channel::Alpha, &[FilterOperation::Blur(1.5)])
+            .unwrap();
+        assert_eq!(piped, result);
+        let resize = FilterOperation::Resize {
+            width: 4,
+            height: 4,
+            filter: ResampleFilter::Nearest,
+        };
+        assert!(pipeline
+            .process_channel(&image, Channel::Red, &[resize])
+            .is_err());
+        assert!(apply_to_channel(&image, Channel::Red, |_| RgbaImage::new(1, 1)).is_err());
+        assert_eq!(Channel::from_name("A"), Some(Channel::Alpha));
+    }
+}
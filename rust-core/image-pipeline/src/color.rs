@@ -0,0 +1,102 @@
+//! Color space conversions shared by the color filters
+
+/// A color as hue, saturation and value
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Hsv {
+    /// Hue in degrees, 0.0 up to (not including) 360.0; 0 for grays
+    pub h: f32,
+    /// Saturation, 0.0 (gray) to 1.0
+    pub s: f32,
+    /// Value (brightest channel), 0.0 to 1.0
+    pub v: f32,
+}
+
+impl Hsv {
+    /// Convert an sRGB color, without linearizing it
+    #[inline]
+    pub fn from_rgb(r: u8, g: u8, b: u8) -> Self {
+        let (r, g, b) = (r as f32 / 255.0, g as f32 / 255.0, b as f32 / 255.0);
+        let max = r.max(g).max(b);
+        let delta = max - r.min(g).min(b);
+
+        let h = if delta == 0.0 {
+            0.0
+        } else if max == r {
+            60.0 * ((g - b) / delta).rem_euclid(6.0)
+        } else if max == g {
+            60.0 * ((b - r) / delta + 2.0)
+        } else {
+            60.0 * ((r - g) / delta + 4.0)
+        };
+        let s = if max == 0.0 { 0.0 } else { delta / max };
+        Hsv { h, s, v: max }
+    }
+
+    /// Convert back to sRGB; the hue wraps and saturation and value are clamped
+    #[inline]
+    pub fn to_rgb(self) -> [u8; 3] {
+        let h = self.h.rem_euclid(360.0) / 60.0;
+        let s = self.s.clamp(0.0, 1.0);
+        let v = self.v.clamp(0.0, 1.0);
+
+        let chroma = v * s;
+        let x = chroma * (1.0 - (h % 2.0 - 1.0).abs());
+        let (r, g, b) = match h as u32 {
+            0 => (chroma, x, 0.0),
+            1 => (x, chroma, 0.0),
+            2 => (0.0, chroma, x),
+            3 => (0.0, x, chroma),
+            4 => (x, 0.0, chroma),
+            _ => (chroma, 0.0, x),
+        };
+        let m = v - chroma;
+        [r, g, b].map(|c| ((c + m) * 255.0).round() as u8)
+    }
+}
+
+/// Shortest distance between two hues in degrees, 0.0 to 180.0
+#[inline]
+pub fn hue_distance(a: f32, b: f32) -> f32 {
+    let d = (a - b).rem_euclid(360.0);
+    d.min(360.0 - d)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hsv_round_trip() {
+        let red = Hsv::from_rgb(255, 0, 0);
+        assert_eq!(
+            red,
+            Hsv {
+                h: 0.0,
+                s: 1.0,
+                v: 1.0
+            }
+        );
+        let sky = Hsv::from_rgb(90, 150, 230);
+        assert!((sky.h - 214.3).abs() < 0.1, "{}", sky.h);
+        assert_eq!(Hsv::from_rgb(128, 128, 128).s, 0.0);
+
+        for r in (0..=255).step_by(15) {
+            for g in (0..=255).step_by(15) {
+                for b in (0..=255).step_by(15) {
+                    let (r, g, b) = (r as u8, g as u8, b as u8);
+                    assert_eq!(Hsv::from_rgb(r, g, b).to_rgb(), [r, g, b]);
+                }
+            }
+        }
+        // Hue wraps around
+        let wrapped = Hsv { h: 480.0, ..red };
+        assert_eq!(wrapped.to_rgb(), [0, 255, 0]);
+    }
+
+    #[test]
+    fn test_hue_distance() {
+        assert_eq!(hue_distance(350.0, 10.0), 20.0);
+        assert_eq!(hue_distance(10.0, 350.0), 20.0);
+        assert_eq!(hue_distance(0.0, 180.0), 180.0);
+    }
+}
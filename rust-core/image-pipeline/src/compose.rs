@@ -0,0 +1,1542 @@
+//! Combining images: several shots of the same scene into one, watermarks
+//! over a base image, drop shadows, grids of many images and sprite atlases
+
+use crate::filters::{blur, resize_straight, ResampleFilter};
+use crate::font;
+use crate::geometry::{self, Interpolation};
+use crate::hash::{Digest, Sha256};
+use crate::{rgba_len, PipelineError, Result, MAX_BLUR_SIGMA};
+use image::{Rgba, RgbaImage};
+use rayon::prelude::*;
+use serde::Serialize;
+use std::path::Path;
+
+/// Merge differently exposed shots of a scene into one well-exposed image
+///
+/// Implements Mertens exposure fusion: every pixel of every input is weighted by
+/// its local contrast, saturation and closeness to mid-gray, and the inputs are
+/// blended with those weights across a Laplacian pyramid so transitions stay seamless.
+/// No HDR radiance map or tone mapping is involved. The images must be aligned and
+/// share the same size.
+pub fn exposure_fusion(images: &[RgbaImage]) -> Result<RgbaImage> {
+    let (width, height) = check_frames(images)?;
+    if images.len() == 1 {
+        return Ok(images[0].clone());
+    }
+
+    let weights = fusion_weights(images);
+    let levels = pyramid_levels(width, height);
+
+    // Blend the Laplacian pyramids of the inputs with Gaussian pyramids of their weights
+    let mut blended: Option<Vec<Plane>> = None;
+    for (image, weight) in images.iter().zip(weights) {
+        let laplacian = laplacian_pyramid(Plane::from_rgb(image), levels);
+        let gaussian = gaussian_pyramid(weight, levels);
+        let contribution: Vec<Plane> = laplacian
+            .into_iter()
+            .zip(&gaussian)
+            .map(|(mut layer, weight)| {
+                layer.multiply(weight);
+                layer
+            })
+            .collect();
+        blended = Some(match blended {
+            None => contribution,
+            Some(mut sum) => {
+                for (total, layer) in sum.iter_mut().zip(&contribution) {
+                    total.add(layer);
+                }
+                sum
+            }
+        });
+    }
+    let color = collapse(blended.unwrap_or_default());
+
+    // Alpha is not part of the fusion; keep the most opaque input
+    let mut result = RgbaImage::new(width, height);
+    for (i, pixel) in result.pixels_mut().enumerate() {
+        for c in 0..3 {
+            pixel[c] = (color.data[i * 3 + c] * 255.0).round().clamp(0.0, 255.0) as u8;
+        }
+        pixel[3] = images
+            .iter()
+            .map(|image| image.as_raw()[i * 4 + 3])
+            .max()
+            .unwrap_or(255);
+    }
+    Ok(result)
+}
+
+/// How `stack` combines the values of one pixel across frames
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StackMethod {
+    /// Average; the strongest noise reduction, but outliers (satellites, hot pixels) leak in
+    Mean,
+    /// Median; rejects outliers at the cost of slightly more noise than the mean
+    Median,
+    /// Average after repeatedly discarding values more than `sigma` standard
+    /// deviations from the mean; combines the strengths of both
+    SigmaClip { sigma: f32, iterations: u32 },
+}
+
+impl StackMethod {
+    /// Parse a method name ("mean", "median", "sigma_clip"); sigma clipping
+    /// uses 2.5 sigma and 3 iterations
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "mean" | "average" => Some(StackMethod::Mean),
+            "median" => Some(StackMethod::Median),
+            "sigma_clip" | "sigma" => Some(StackMethod::SigmaClip {
+                sigma: 2.5,
+                iterations: 3,
+            }),
+            _ => None,
+        }
+    }
+}
+
+/// Combine aligned frames of the same scene to reduce noise
+///
+/// Every channel of every pixel (alpha included) is combined independently.
+/// The frames must share one size.
+pub fn stack(images: &[RgbaImage], method: StackMethod) -> Result<RgbaImage> {
+    let (width, height) = check_frames(images)?;
+    if let StackMethod::SigmaClip { sigma, .. } = method {
+        if !sigma.is_finite() || sigma <= 0.0 {
+            return Err(PipelineError::InvalidParameter(format!(
+                "sigma must be a positive number, got {}",
+                sigma
+            )));
+        }
+    }
+
+    let mut result = RgbaImage::new(width, height);
+    let row_len = (width as usize * 4).max(4);
+    let pixels: &mut [u8] = &mut result;
+    pixels
+        .par_chunks_mut(row_len)
+        .enumerate()
+        .for_each(|(y, row)| {
+            let mut values = Vec::with_capacity(images.len());
+            for (i, channel) in row.iter_mut().enumerate() {
+                let offset = y * row_len + i;
+                values.clear();
+                values.extend(images.iter().map(|image| image.as_raw()[offset] as f32));
+                let combined = match method {
+                    StackMethod::Mean => mean(&values),
+                    StackMethod::Median => median(&mut values),
+                    StackMethod::SigmaClip { sigma, iterations } => {
+                        sigma_clipped_mean(&mut values, sigma, iterations)
+                    }
+                };
+                *channel = combined.round().clamp(0.0, 255.0) as u8;
+            }
+        });
+
+    Ok(result)
+}
+
+/// Register every frame onto the first by translation, then `stack` them
+///
+/// For hand-held or tracking-drift sequences. Border areas a shifted frame does
+/// not cover repeat its edge pixels, so crop the result by the largest drift.
+pub fn stack_aligned(images: &[RgbaImage], method: StackMethod) -> Result<RgbaImage> {
+    let (width, height) = check_frames(images)?;
+    let reference = &images[0];
+    let mut aligned = Vec::with_capacity(images.len());
+    aligned.push(reference.clone());
+    for image in &images[1..] {
+        let alignment = crate::align::estimate_translation(reference, image)?;
+        let (dx, dy) = (alignment.dx as f64, alignment.dy as f64);
+        aligned.push(geometry::remap(
+            image,
+            width,
+            height,
+            Interpolation::Bilinear,
+            Rgba([0; 4]),
+            |x, y| {
+                Some((
+                    (x + dx).clamp(0.5, width as f64 - 0.5),
+                    (y + dy).clamp(0.5, height as f64 - 0.5),
+                ))
+            },
+        ));
+    }
+    stack(&aligned, method)
+}
+
+fn mean(values: &[f32]) -> f32 {
+    values.iter().sum::<f32>() / values.len() as f32
+}
+
+fn median(values: &mut [f32]) -> f32 {
+    values.sort_unstable_by(f32::total_cmp);
+    let mid = values.len() / 2;
+    if values.len().is_multiple_of(2) {
+        (values[mid - 1] + values[mid]) / 2.0
+    } else {
+        values[mid]
+    }
+}
+
+// Drops values outside mean +- sigma * stddev until nothing changes or the
+// iterations run out; never drops every value
+fn sigma_clipped_mean(values: &mut Vec<f32>, sigma: f32, iterations: u32) -> f32 {
+    for _ in 0..iterations {
+        let center = mean(values);
+        let deviation =
+            (values.iter().map(|v| (v - center).powi(2)).sum::<f32>() / values.len() as f32).sqrt();
+        let before = values.len();
+        let limit = sigma * deviation;
+        let kept = values
+            .iter()
+            .filter(|v| (*v - center).abs() <= limit)
+            .count();
+        if kept == 0 || kept == before {
+            break;
+        }
+        values.retain(|v| (v - center).abs() <= limit);
+    }
+    mean(values)
+}
+
+// All frames must exist and share one size, which is returned
+fn check_frames(images: &[RgbaImage]) -> Result<(u32, u32)> {
+    let first = images
+        .first()
+        .ok_or_else(|| PipelineError::InvalidParameter("at least one image is required".into()))?;
+    let size = first.dimensions();
+    if let Some(other) = images.iter().find(|image| image.dimensions() != size) {
+        return Err(PipelineError::InvalidParameter(format!(
+            "all images must be {}x{}, got {}x{}",
+            size.0,
+            size.1,
+            other.width(),
+            other.height()
+        )));
+    }
+    Ok(size)
+}
+
+// Per-pixel Mertens weights of each image, normalized to sum to 1 at every pixel
+fn fusion_weights(images: &[RgbaImage]) -> Vec<Plane> {
+    const SIGMA: f32 = 0.2;
+    let mut weights: Vec<Plane> = images
+        .par_iter()
+        .map(|image| {
+            let (width, height) = image.dimensions();
+            let gray: Vec<f32> = image
+                .pixels()
+                .map(|p| (p[0] as f32 * 0.299 + p[1] as f32 * 0.587 + p[2] as f32 * 0.114) / 255.0)
+                .collect();
+            let (w, h) = (width as usize, height as usize);
+            let at = |x: usize, y: usize| gray[y * w + x];
+
+            let data = image
+                .pixels()
+                .enumerate()
+                .map(|(i, p)| {
+                    let (x, y) = (i % w, i / w);
+                    // Absolute Laplacian with clamped borders
+                    let contrast = (at(x.saturating_sub(1), y)
+                        + at((x + 1).min(w - 1), y)
+                        + at(x, y.saturating_sub(1))
+                        + at(x, (y + 1).min(h - 1))
+                        - 4.0 * at(x, y))
+                    .abs();
+
+                    let rgb = [p[0], p[1], p[2]].map(|v| v as f32 / 255.0);
+                    let mean = (rgb[0] + rgb[1] + rgb[2]) / 3.0;
+                    let saturation =
+                        (rgb.iter().map(|v| (v - mean).powi(2)).sum::<f32>() / 3.0).sqrt();
+                    let exposedness: f32 = rgb
+                        .iter()
+                        .map(|v| (-(v - 0.5).powi(2) / (2.0 * SIGMA * SIGMA)).exp())
+                        .product();
+
+                    // The small floor keeps flat, gray areas from having zero weight everywhere
+                    contrast * saturation * exposedness + 1e-12
+                })
+                .collect();
+            Plane {
+                width: w,
+                height: h,
+                channels: 1,
+                data,
+            }
+        })
+        .collect();
+
+    let pixel_count = weights[0].data.len();
+    for i in 0..pixel_count {
+        let total: f32 = weights.iter().map(|w| w.data[i]).sum();
+        for weight in weights.iter_mut() {
+            weight.data[i] /= total;
+        }
+    }
+    weights
+}
+
+// Float image with interleaved channels, used for pyramid blending
+#[derive(Debug, Clone, Default)]
+struct Plane {
+    width: usize,
+    height: usize,
+    channels: usize,
+    data: Vec<f32>,
+}
+
+impl Plane {
+    // RGB scaled to 0..1, dropping alpha
+    fn from_rgb(image: &RgbaImage) -> Self {
+        Self {
+            width: image.width() as usize,
+            height: image.height() as usize,
+            channels: 3,
+            data: image
+                .pixels()
+                .flat_map(|p| [p[0], p[1], p[2]].map(|v| v as f32 / 255.0))
+                .collect(),
+        }
+    }
+
+    fn add(&mut self, other: &Plane) {
+        for (a, b) in self.data.iter_mut().zip(&other.data) {
+            *a += b;
+        }
+    }
+
+    fn subtract(&mut self, other: &Plane) {
+        for (a, b) in self.data.iter_mut().zip(&other.data) {
+            *a -= b;
+        }
+    }
+
+    // Multiply every channel by a single-channel plane of the same size
+    fn multiply(&mut self, weight: &Plane) {
+        let channels = self.channels;
+        for (pixel, w) in self.data.chunks_exact_mut(channels).zip(&weight.data) {
+            for value in pixel {
+                *value *= w;
+            }
+        }
+    }
+
+    // Blur with the 5-tap binomial kernel and keep every second pixel
+    fn reduce(&self) -> Plane {
+        let blurred = self.binomial_blur(1.0);
+        let (width, height) = (self.width.div_ceil(2), self.height.div_ceil(2));
+        let mut data = Vec::with_capacity(width * height * self.channels);
+        for y in 0..height {
+            for x in 0..width {
+                let i = ((2 * y) * self.width + 2 * x) * self.channels;
+                data.extend_from_slice(&blurred.data[i..i + self.channels]);
+            }
+        }
+        Plane {
+            width,
+            height,
+            channels: self.channels,
+            data,
+        }
+    }
+
+    // Upsample to `width` x `height` by zero insertion and interpolating blur
+    fn expand(&self, width: usize, height: usize) -> Plane {
+        let mut spread = Plane {
+            width,
+            height,
+            channels: self.channels,
+            data: vec![0.0; width * height * self.channels],
+        };
+        for y in 0..self.height.min(height.div_ceil(2)) {
+            for x in 0..self.width.min(width.div_ceil(2)) {
+                let src = (y * self.width + x) * self.channels;
+                let dst = ((2 * y) * width + 2 * x) * self.channels;
+                spread.data[dst..dst + self.channels]
+                    .copy_from_slice(&self.data[src..src + self.channels]);
+            }
+        }
+        // Each axis had half its samples zeroed, so each pass doubles the gain
+        spread.binomial_blur(2.0)
+    }
+
+    // Separable [1, 4, 6, 4, 1] / 16 blur with mirrored borders, scaled by `gain` per axis
+    fn binomial_blur(&self, gain: f32) -> Plane {
+        const KERNEL: [f32; 5] = [1.0 / 16.0, 4.0 / 16.0, 6.0 / 16.0, 4.0 / 16.0, 1.0 / 16.0];
+        let (w, h, ch) = (self.width, self.height, self.channels);
+        let mirror = |i: isize, n: usize| -> usize {
+            let n = n as isize;
+            let i = if i < 0 { -i } else { i };
+            let i = if i >= n { 2 * (n - 1) - i } else { i };
+            i.clamp(0, n - 1) as usize
+        };
+
+        let mut horizontal = vec![0.0; self.data.len()];
+        horizontal
+            .par_chunks_mut((w * ch).max(1))
+            .enumerate()
+            .for_each(|(y, row)| {
+                for x in 0..w {
+                    for c in 0..ch {
+                        row[x * ch + c] = KERNEL
+                            .iter()
+                            .enumerate()
+                            .map(|(k, weight)| {
+                                let sx = mirror(x as isize + k as isize - 2, w);
+                                weight * self.data[(y * w + sx) * ch + c]
+                            })
+                            .sum::<f32>()
+                            * gain;
+                    }
+                }
+            });
+
+        let mut data = vec![0.0; self.data.len()];
+        data.par_chunks_mut((w * ch).max(1))
+            .enumerate()
+            .for_each(|(y, row)| {
+                for (i, value) in row.iter_mut().enumerate() {
+                    *value = KERNEL
+                        .iter()
+                        .enumerate()
+                        .map(|(k, weight)| {
+                            let sy = mirror(y as isize + k as isize - 2, h);
+                            weight * horizontal[sy * w * ch + i]
+                        })
+                        .sum::<f32>()
+                        * gain;
+                }
+            });
+
+        Plane {
+            width: w,
+            height: h,
+            channels: ch,
+            data,
+        }
+    }
+}
+
+// Levels until the smallest side would drop below 8 pixels, at most 10
+fn pyramid_levels(width: u32, height: u32) -> usize {
+    let mut side = width.min(height);
+    let mut levels = 1;
+    while side >= 16 && levels < 10 {
+        side /= 2;
+        levels += 1;
+    }
+    levels
+}
+
+fn gaussian_pyramid(base: Plane, levels: usize) -> Vec<Plane> {
+    let mut pyramid = vec![base];
+    for _ in 1..levels {
+        let next = pyramid[pyramid.len() - 1].reduce();
+        pyramid.push(next);
+    }
+    pyramid
+}
+
+fn laplacian_pyramid(base: Plane, levels: usize) -> Vec<Plane> {
+    let mut pyramid = gaussian_pyramid(base, levels);
+    for i in 0..pyramid.len() - 1 {
+        let (width, height) = (pyramid[i].width, pyramid[i].height);
+        let expanded = pyramid[i + 1].expand(width, height);
+        pyramid[i].subtract(&expanded);
+    }
+    pyramid
+}
+
+fn collapse(mut pyramid: Vec<Plane>) -> Plane {
+    let mut result = pyramid.pop().unwrap_or_default();
+    while let Some(mut layer) = pyramid.pop() {
+        layer.add(&result.expand(layer.width, layer.height));
+        result = layer;
+    }
+    result
+}
+
+/// Where a watermark sits on the base image
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Anchor {
+    TopLeft,
+    Top,
+    TopRight,
+    Left,
+    Center,
+    Right,
+    BottomLeft,
+    Bottom,
+    #[default]
+    BottomRight,
+}
+
+impl Anchor {
+    /// Parse "top_left", "top", "top_right", "left", "center", "right",
+    /// "bottom_left", "bottom" or "bottom_right" (hyphens work too)
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().replace('-', "_").as_str() {
+            "top_left" => Some(Anchor::TopLeft),
+            "top" => Some(Anchor::Top),
+            "top_right" => Some(Anchor::TopRight),
+            "left" => Some(Anchor::Left),
+            "center" | "centre" => Some(Anchor::Center),
+            "right" => Some(Anchor::Right),
+            "bottom_left" => Some(Anchor::BottomLeft),
+            "bottom" => Some(Anchor::Bottom),
+            "bottom_right" => Some(Anchor::BottomRight),
+            _ => None,
+        }
+    }
+
+    // Top-left corner of an `inner` box placed in `outer`, `margin` from the anchored edges
+    fn position(self, outer: (u32, u32), inner: (u32, u32), margin: u32) -> (i64, i64) {
+        let (column, row) = match self {
+            Anchor::TopLeft => (0, 0),
+            Anchor::Top => (1, 0),
+            Anchor::TopRight => (2, 0),
+            Anchor::Left => (0, 1),
+            Anchor::Center => (1, 1),
+            Anchor::Right => (2, 1),
+            Anchor::BottomLeft => (0, 2),
+            Anchor::Bottom => (1, 2),
+            Anchor::BottomRight => (2, 2),
+        };
+        let place = |side: u8, outer: u32, inner: u32| {
+            let (outer, inner, margin) = (outer as i64, inner as i64, margin as i64);
+            match side {
+                0 => margin,
+                1 => (outer - inner) / 2,
+                _ => outer - inner - margin,
+            }
+        };
+        (
+            place(column, outer.0, inner.0),
+            place(row, outer.1, inner.1),
+        )
+    }
+}
+
+/// A watermark image, remembering the file it was loaded from so specs can refer to it
+#[derive(Debug, Clone, PartialEq)]
+pub struct Mark {
+    image: RgbaImage,
+    source: Option<String>,
+}
+
+impl Mark {
+    /// Load a watermark, usually a PNG with transparency
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        Ok(Mark {
+            image: image::open(path)?.to_rgba8(),
+            source: Some(path.display().to_string()),
+        })
+    }
+
+    pub fn from_image(image: RgbaImage) -> Self {
+        Mark {
+            image,
+            source: None,
+        }
+    }
+
+    pub fn image(&self) -> &RgbaImage {
+        &self.image
+    }
+
+    /// Path the mark was loaded from
+    pub fn source(&self) -> Option<&str> {
+        self.source.as_deref()
+    }
+
+    // Copy resized by `scale`, for previews of an image resized by the same factor
+    pub(crate) fn scaled(&self, scale: f64) -> Mark {
+        let (width, height) = self.image.dimensions();
+        let size = |v: u32| ((v as f64 * scale).round() as u32).max(1);
+        Mark {
+            image: resize_straight(
+                &self.image,
+                size(width),
+                size(height),
+                ResampleFilter::Lanczos3,
+            ),
+            source: self.source.clone(),
+        }
+    }
+
+    // Feed everything that affects the output into `hasher`
+    pub(crate) fn hash_into(&self, hasher: &mut Sha256) {
+        hasher.update(self.image.width().to_le_bytes());
+        hasher.update(self.image.height().to_le_bytes());
+        hasher.update(self.image.as_raw());
+    }
+}
+
+/// Draw `mark` over `base`
+///
+/// anchor: where the mark sits; with `tile`, where the grid of repeated marks is aligned
+/// margin: pixels between the mark and the anchored edges, and between tiles
+/// opacity: 0.0-1.0, multiplied with the mark's own alpha
+/// scale_relative: mark width as a fraction of the base width (0.0-1.0, aspect
+/// kept), or `None` to draw the mark at its own size
+pub fn watermark(
+    base: &RgbaImage,
+    mark: &RgbaImage,
+    anchor: Anchor,
+    margin: u32,
+    opacity: f32,
+    tile: bool,
+    scale_relative: Option<f32>,
+) -> Result<RgbaImage> {
+    if !(0.0..=1.0).contains(&opacity) {
+        return Err(PipelineError::InvalidParameter(format!(
+            "opacity must be between 0.0 and 1.0, got {}",
+            opacity
+        )));
+    }
+    if let Some(scale) = scale_relative {
+        if !(scale > 0.0 && scale <= 1.0) {
+            return Err(PipelineError::InvalidParameter(format!(
+                "scale must be in (0, 1], got {}",
+                scale
+            )));
+        }
+    }
+    let mut result = base.clone();
+    if mark.width() == 0 || mark.height() == 0 || base.width() == 0 || opacity == 0.0 {
+        return Ok(result);
+    }
+
+    let scaled;
+    let mark = match scale_relative {
+        Some(scale) => {
+            let width = ((base.width() as f32 * scale).round() as u32).max(1);
+            let height = ((mark.height() as u64 * width as u64) as f64 / mark.width() as f64)
+                .round()
+                .max(1.0) as u32;
+            scaled = resize_straight(mark, width, height, ResampleFilter::Lanczos3);
+            &scaled
+        }
+        None => mark,
+    };
+
+    let (mark_w, mark_h) = (mark.width() as i64, mark.height() as i64);
+    let (x0, y0) = anchor.position(base.dimensions(), mark.dimensions(), margin);
+    let step = (mark_w + margin as i64, mark_h + margin as i64);
+    // Coordinate inside the mark that covers `v`, if any
+    let locate = |v: u32, origin: i64, size: i64, step: i64| {
+        let offset = v as i64 - origin;
+        let offset = if tile {
+            offset.rem_euclid(step)
+        } else {
+            offset
+        };
+        (0..size).contains(&offset).then_some(offset as u32)
+    };
+
+    let row_len = base.width() as usize * 4;
+    let pixels: &mut [u8] = &mut result;
+    pixels
+        .par_chunks_mut(row_len)
+        .enumerate()
+        .for_each(|(y, row)| {
+            let Some(my) = locate(y as u32, y0, mark_h, step.1) else {
+                return;
+            };
+            for (x, pixel) in row.chunks_exact_mut(4).enumerate() {
+                if let Some(mx) = locate(x as u32, x0, mark_w, step.0) {
+                    blend_over(pixel, mark.get_pixel(mx, my), opacity);
+                }
+            }
+        });
+    Ok(result)
+}
+
+/// Render a soft shadow under the opaque parts of `image`
+///
+/// The canvas grows by the blur radius (three sigmas) on every side and by the
+/// offset in its direction, so neither the image nor the shadow is cut off;
+/// the image keeps its pixels and the shadow is drawn behind it.
+/// offset: (x, y) shift of the shadow in pixels, positive is right and down
+/// blur_sigma: softness of the shadow edge (0 = hard)
+/// opacity: 0.0-1.0, multiplied with the alpha of `color` and of the image
+pub fn drop_shadow(
+    image: &RgbaImage,
+    offset: (i32, i32),
+    blur_sigma: f32,
+    color: Rgba<u8>,
+    opacity: f32,
+) -> Result<RgbaImage> {
+    if !(0.0..=MAX_BLUR_SIGMA).contains(&blur_sigma) {
+        return Err(PipelineError::InvalidParameter(format!(
+            "shadow blur sigma must be between 0 and {}, got {}",
+            MAX_BLUR_SIGMA, blur_sigma
+        )));
+    }
+    if !(0.0..=1.0).contains(&opacity) {
+        return Err(PipelineError::InvalidParameter(format!(
+            "shadow opacity must be between 0.0 and 1.0, got {}",
+            opacity
+        )));
+    }
+
+    let (width, height) = image.dimensions();
+    let margin = (blur_sigma * 3.0).ceil() as u64;
+    let (dx, dy) = (offset.0 as i64, offset.1 as i64);
+    let side = |inner: u32, shift: i64| -> Result<u32> {
+        let total = inner as u64 + 2 * margin + shift.unsigned_abs();
+        u32::try_from(total).map_err(|_| {
+            PipelineError::ResourceLimit(format!("shadow canvas side of {} is too large", total))
+        })
+    };
+    let canvas_w = side(width, dx)?;
+    let canvas_h = side(height, dy)?;
+    rgba_len(canvas_w, canvas_h)?;
+
+    // The image sits away from the side the shadow falls to
+    let image_x = margin as i64 + (-dx).max(0);
+    let image_y = margin as i64 + (-dy).max(0);
+    let strength = color[3] as f32 / 255.0 * opacity;
+    // Every pixel has the shadow color, so blurring straight alpha is exact
+    let mut shadow =
+        RgbaImage::from_pixel(canvas_w, canvas_h, Rgba([color[0], color[1], color[2], 0]));
+    for (x, y, pixel) in image.enumerate_pixels() {
+        let alpha = (pixel[3] as f32 * strength).round() as u8;
+        let (sx, sy) = (image_x + dx + x as i64, image_y + dy + y as i64);
+        shadow.get_pixel_mut(sx as u32, sy as u32)[3] = alpha;
+    }
+    let mut result = if blur_sigma > 0.0 {
+        blur(&shadow, blur_sigma)
+    } else {
+        shadow
+    };
+    for (x, y, pixel) in image.enumerate_pixels() {
+        let target = result.get_pixel_mut((image_x + x as i64) as u32, (image_y + y as i64) as u32);
+        blend_over(&mut target.0, pixel, 1.0);
+    }
+    Ok(result)
+}
+
+/// Lay out `images` in a grid, e.g. a thumbnail sheet for a video or a preview of a dataset
+///
+/// columns: cells per row; rows are added as needed
+/// cell_size: (width, height) each image is scaled to fit inside, aspect kept and centered
+/// padding: pixels around and between cells
+/// labels: captions drawn under the cells in order, in the built-in 5x7 font; cut
+/// with ".." when too wide, and fewer labels than images leaves the rest blank
+pub fn montage(
+    images: &[RgbaImage],
+    columns: u32,
+    cell_size: (u32, u32),
+    padding: u32,
+    background: Rgba<u8>,
+    labels: &[&str],
+) -> Result<RgbaImage> {
+    let (cell_w, cell_h) = cell_size;
+    if images.is_empty() {
+        return Err(PipelineError::InvalidParameter(
+            "montage needs at least one image".to_string(),
+        ));
+    }
+    if columns == 0 || cell_w == 0 || cell_h == 0 {
+        return Err(PipelineError::InvalidParameter(format!(
+            "montage needs at least one column and a non-empty cell, got {} columns of {}x{}",
+            columns, cell_w, cell_h
+        )));
+    }
+
+    let scale = (cell_w / 120).clamp(1, 4);
+    let label_h = if labels.iter().any(|label| !label.is_empty()) {
+        (font::GLYPH_HEIGHT + 4) * scale
+    } else {
+        0
+    };
+    let rows = (images.len() as u64).div_ceil(columns as u64);
+    let side = |cells: u64, cell: u64| -> Result<u32> {
+        let total = cells * cell + (cells + 1) * padding as u64;
+        u32::try_from(total).map_err(|_| {
+            PipelineError::ResourceLimit(format!("montage side of {} pixels is too large", total))
+        })
+    };
+    let width = side(columns as u64, cell_w as u64)?;
+    let height = side(rows, cell_h as u64 + label_h as u64)?;
+    rgba_len(width, height)?;
+
+    let thumbnails: Vec<RgbaImage> = images
+        .par_iter()
+        .map(|image| {
+            let (w, h) = image.dimensions();
+            if w == 0 || h == 0 || (w, h) == cell_size {
+                return image.clone();
+            }
+            let fit = (cell_w as f64 / w as f64).min(cell_h as f64 / h as f64);
+            let size = |v: u32, max: u32| ((v as f64 * fit).round() as u32).clamp(1, max);
+            resize_straight(
+                image,
+                size(w, cell_w),
+                size(h, cell_h),
+                ResampleFilter::Lanczos3,
+            )
+        })
+        .collect();
+
+    let ink =
+        if background[0] as u32 * 299 + background[1] as u32 * 587 + background[2] as u32 * 114
+            > 127_500
+        {
+            Rgba([0, 0, 0, 255])
+        } else {
+            Rgba([255, 255, 255, 255])
+        };
+    let mut sheet = RgbaImage::from_pixel(width, height, background);
+    for (i, thumbnail) in thumbnails.iter().enumerate() {
+        let (column, row) = (i as u32 % columns, i as u32 / columns);
+        let cell_x = padding + column * (cell_w + padding);
+        let cell_y = padding + row * (cell_h + label_h + padding);
+        let x0 = cell_x + (cell_w - thumbnail.width()) / 2;
+        let y0 = cell_y + (cell_h - thumbnail.height()) / 2;
+        for (x, y, pixel) in thumbnail.enumerate_pixels() {
+            blend_over(&mut sheet.get_pixel_mut(x0 + x, y0 + y).0, pixel, 1.0);
+        }
+
+        if let Some(label) = labels.get(i).filter(|label| !label.is_empty()) {
+            let text = font::fit_text(label, cell_w, scale);
+            let text_x = cell_x + (cell_w - font::text_width(&text, scale)) / 2;
+            let text_y = cell_y + cell_h + 2 * scale;
+            font::draw_text(&mut sheet, text_x as i64, text_y as i64, &text, scale, ink);
+        }
+    }
+    Ok(sheet)
+}
+
+/// How `compare` presents an image before and after processing
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CompareMode {
+    /// Both images next to each other, original on the left
+    SideBySide,
+    /// One frame showing the original left of a vertical divider and the
+    /// processed image right of it; position: 0.0-1.0 across the width
+    Split { position: f32 },
+    /// Where and how much the images differ: unchanged pixels show the
+    /// original as dimmed grayscale, changed ones a black-red-yellow-white
+    /// ramp that saturates at a difference of 64 levels
+    Difference,
+}
+
+impl CompareMode {
+    /// Parse a mode name ("side_by_side", "split", "difference"); the split
+    /// divider sits in the middle
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().replace('-', "_").as_str() {
+            "side_by_side" | "sidebyside" => Some(CompareMode::SideBySide),
+            "split" => Some(CompareMode::Split { position: 0.5 }),
+            "difference" | "diff" | "heatmap" => Some(CompareMode::Difference),
+            _ => None,
+        }
+    }
+}
+
+/// Build a before/after image for reviewing a processing change
+///
+/// Side by side accepts images of different sizes, centering them vertically
+/// on a transparent canvas; the other modes need both to be the same size.
+pub fn compare(
+    original: &RgbaImage,
+    processed: &RgbaImage,
+    mode: CompareMode,
+) -> Result<RgbaImage> {
+    if mode == CompareMode::SideBySide {
+        let width = original.width() as u64 + processed.width() as u64;
+        let width = u32::try_from(width).map_err(|_| {
+            PipelineError::ResourceLimit(format!("comparison width of {} is too large", width))
+        })?;
+        let height = original.height().max(processed.height());
+        rgba_len(width, height)?;
+        let mut result = RgbaImage::new(width, height);
+        for (image, x0) in [(original, 0), (processed, original.width())] {
+            let y0 = (height - image.height()) / 2;
+            for (x, y, pixel) in image.enumerate_pixels() {
+                result.put_pixel(x0 + x, y0 + y, *pixel);
+            }
+        }
+        return Ok(result);
+    }
+
+    let (width, height) = original.dimensions();
+    if processed.dimensions() != (width, height) {
+        return Err(PipelineError::InvalidParameter(format!(
+            "both images must be {}x{} to compare, got {}x{}",
+            width,
+            height,
+            processed.width(),
+            processed.height()
+        )));
+    }
+    match mode {
+        CompareMode::Split { position } => {
+            if !(0.0..=1.0).contains(&position) {
+                return Err(PipelineError::InvalidParameter(format!(
+                    "split position must be between 0.0 and 1.0, got {}",
+                    position
+                )));
+            }
+            let divider = (width as f32 * position).round() as i64;
+            let mut result = original.clone();
+            for (x, y, pixel) in result.enumerate_pixels_mut() {
+                // A white line two pixels wide, outlined in black to show on any content
+                *pixel = match x as i64 - divider {
+                    -1 | 0 => Rgba([255, 255, 255, 255]),
+                    -2 | 1 => Rgba([0, 0, 0, 255]),
+                    offset if offset > 1 => *processed.get_pixel(x, y),
+                    _ => *pixel,
+                };
+            }
+            Ok(result)
+        }
+        _ => {
+            let mut result = RgbaImage::new(width, height);
+            let pixels: &mut [u8] = &mut result;
+            pixels
+                .par_chunks_mut(4)
+                .zip(original.as_raw().par_chunks(4))
+                .zip(processed.as_raw().par_chunks(4))
+                .for_each(|((out, a), b)| {
+                    let difference = (0..4).map(|c| a[c].abs_diff(b[c])).max().unwrap_or(0);
+                    out.copy_from_slice(&heat(a, difference));
+                });
+            Ok(result)
+        }
+    }
+}
+
+// Dimmed gray of `pixel` when unchanged, otherwise the difference on a
+// black-red-yellow-white ramp
+fn heat(pixel: &[u8], difference: u8) -> [u8; 4] {
+    if difference == 0 {
+        let luma = (pixel[0] as u32 * 299 + pixel[1] as u32 * 587 + pixel[2] as u32 * 114) / 1000;
+        let gray = (luma / 3) as u8;
+        return [gray, gray, gray, 255];
+    }
+    let t = (difference as f32 / 64.0).min(1.0) * 3.0;
+    let ramp = |start: f32| ((t - start).clamp(0.0, 1.0) * 255.0).round() as u8;
+    [ramp(0.0), ramp(1.0), ramp(2.0), 255]
+}
+
+/// How `sprite_sheet` arranges sprites in the atlas
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum SpritePacking {
+    /// Rows of sprites sorted by height; fast and predictable
+    Shelf,
+    /// Maximal rectangles with best-short-side fit; fills gaps left by
+    /// sprites of mixed sizes for a smaller atlas
+    #[default]
+    MaxRects,
+}
+
+impl SpritePacking {
+    /// Parse "shelf" or "max_rects" (hyphens work too)
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().replace('-', "_").as_str() {
+            "shelf" => Some(SpritePacking::Shelf),
+            "max_rects" | "maxrects" => Some(SpritePacking::MaxRects),
+            _ => None,
+        }
+    }
+}
+
+/// Settings for `sprite_sheet`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SpriteOptions {
+    pub packing: SpritePacking,
+    /// Transparent pixels between sprites, so texture filtering does not
+    /// bleed one sprite into the next
+    pub padding: u32,
+    /// Widest atlas allowed; sprites wrap onto new rows beyond it
+    pub max_width: u32,
+    /// Round the atlas size up to powers of two, for older GPUs
+    pub power_of_two: bool,
+}
+
+impl Default for SpriteOptions {
+    fn default() -> Self {
+        Self {
+            packing: SpritePacking::MaxRects,
+            padding: 2,
+            max_width: 4096,
+            power_of_two: false,
+        }
+    }
+}
+
+/// Position of one sprite in the atlas
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct SpriteFrame {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// Result of `sprite_sheet`
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SpriteSheet {
+    #[serde(skip)]
+    pub image: RgbaImage,
+    pub width: u32,
+    pub height: u32,
+    /// One frame per input image, in input order
+    pub frames: Vec<SpriteFrame>,
+}
+
+impl SpriteSheet {
+    /// Atlas size and frames as JSON:
+    /// `{"width":..,"height":..,"frames":[{"x":..,"y":..,"width":..,"height":..}]}`
+    pub fn to_json(&self) -> Result<String> {
+        serde_json::to_string(self).map_err(|e| PipelineError::ProcessingError(e.to_string()))
+    }
+}
+
+/// Pack `images` into one atlas for games and CSS sprites
+///
+/// Sprites are copied unscaled; the atlas is as narrow as the packing allows
+/// within `max_width` and transparent between sprites.
+pub fn sprite_sheet(images: &[RgbaImage], options: &SpriteOptions) -> Result<SpriteSheet> {
+    if images.is_empty() {
+        return Err(PipelineError::InvalidParameter(
+            "sprite sheet needs at least one image".to_string(),
+        ));
+    }
+    let padding = options.padding as u64;
+    let max_width = options.max_width as u64;
+    // Sprites are packed with the padding added to their right and bottom
+    // edges, in a bin that is as much wider; the atlas trims it off again
+    let mut sizes = Vec::with_capacity(images.len());
+    for (i, image) in images.iter().enumerate() {
+        let (w, h) = image.dimensions();
+        if w == 0 || h == 0 {
+            return Err(PipelineError::InvalidParameter(format!(
+                "sprite {} is empty",
+                i
+            )));
+        }
+        if w as u64 > max_width {
+            return Err(PipelineError::InvalidParameter(format!(
+                "sprite {} is {} pixels wide, more than the maximum atlas width of {}",
+                i, w, max_width
+            )));
+        }
+        sizes.push((w as u64 + padding, h as u64 + padding));
+    }
+
+    // Tallest first for shelves, largest first for max-rects; ties keep input order
+    let mut order: Vec<usize> = (0..images.len()).collect();
+    match options.packing {
+        SpritePacking::Shelf => order.sort_by_key(|&i| std::cmp::Reverse(sizes[i].1)),
+        SpritePacking::MaxRects => {
+            order.sort_by_key(|&i| std::cmp::Reverse((sizes[i].0 * sizes[i].1, sizes[i].1)))
+        }
+    }
+    let area: u64 = sizes.iter().map(|(w, h)| w * h).sum();
+    let widest = sizes.iter().map(|s| s.0).max().unwrap_or(0);
+    // Aim for a roughly square atlas
+    let bin_width = widest
+        .max((area as f64).sqrt().ceil() as u64)
+        .min(max_width + padding);
+    let positions = match options.packing {
+        SpritePacking::Shelf => pack_shelves(&sizes, &order, bin_width),
+        SpritePacking::MaxRects => pack_max_rects(&sizes, &order, bin_width),
+    };
+
+    let (mut width, mut height) = sizes
+        .iter()
+        .zip(&positions)
+        .fold((0, 0), |(width, height), (&(w, h), &(x, y))| {
+            (width.max(x + w), height.max(y + h))
+        });
+    width -= padding;
+    height -= padding;
+    if options.power_of_two {
+        width = width.next_power_of_two();
+        height = height.next_power_of_two();
+    }
+    let too_large = || {
+        PipelineError::ResourceLimit(format!("sprite atlas of {}x{} is too large", width, height))
+    };
+    let width = u32::try_from(width).map_err(|_| too_large())?;
+    let height = u32::try_from(height).map_err(|_| too_large())?;
+    rgba_len(width, height)?;
+
+    let mut image = RgbaImage::new(width, height);
+    let frames = images
+        .iter()
+        .zip(&positions)
+        .map(|(sprite, &(x, y))| {
+            let (x, y) = (x as u32, y as u32);
+            image::imageops::replace(&mut image, sprite, x as i64, y as i64);
+            SpriteFrame {
+                x,
+                y,
+                width: sprite.width(),
+                height: sprite.height(),
+            }
+        })
+        .collect();
+    Ok(SpriteSheet {
+        image,
+        width,
+        height,
+        frames,
+    })
+}
+
+// Place sprites left to right in rows as tall as their first (tallest) sprite
+fn pack_shelves(sizes: &[(u64, u64)], order: &[usize], bin_width: u64) -> Vec<(u64, u64)> {
+    let mut positions = vec![(0, 0); sizes.len()];
+    let (mut x, mut y, mut shelf_height) = (0, 0, 0);
+    for &i in order {
+        let (w, h) = sizes[i];
+        if x + w > bin_width {
+            (x, y, shelf_height) = (0, y + shelf_height, 0);
+        }
+        positions[i] = (x, y);
+        x += w;
+        shelf_height = shelf_height.max(h);
+    }
+    positions
+}
+
+// Maximal rectangles packing (Jylänki, "A Thousand Ways to Pack the Bin"):
+// keep every maximal free rectangle, put each sprite where it leaves the
+// shortest leftover side, then split the free rectangles it overlaps
+fn pack_max_rects(sizes: &[(u64, u64)], order: &[usize], bin_width: u64) -> Vec<(u64, u64)> {
+    // (x, y, width, height); the bin is tall enough for every sprite stacked
+    let bin_height = sizes.iter().map(|s| s.1).sum();
+    let mut free = vec![(0u64, 0u64, bin_width, bin_height)];
+    let mut positions = vec![(0, 0); sizes.len()];
+    for &i in order {
+        let (w, h) = sizes[i];
+        let (x, y, _, _) = *free
+            .iter()
+            .filter(|f| w <= f.2 && h <= f.3)
+            .min_by_key(|f| {
+                let (dw, dh) = (f.2 - w, f.3 - h);
+                (dw.min(dh), dw.max(dh), f.1, f.0)
+            })
+            .expect("the bin fits every sprite");
+        positions[i] = (x, y);
+
+        let mut split = Vec::with_capacity(free.len() + 4);
+        for f in free {
+            if x >= f.0 + f.2 || x + w <= f.0 || y >= f.1 + f.3 || y + h <= f.1 {
+                split.push(f);
+                continue;
+            }
+            if x > f.0 {
+                split.push((f.0, f.1, x - f.0, f.3));
+            }
+            if x + w < f.0 + f.2 {
+                split.push((x + w, f.1, f.0 + f.2 - x - w, f.3));
+            }
+            if y > f.1 {
+                split.push((f.0, f.1, f.2, y - f.1));
+            }
+            if y + h < f.1 + f.3 {
+                split.push((f.0, y + h, f.2, f.1 + f.3 - y - h));
+            }
+        }
+        let contains = |a: &(u64, u64, u64, u64), b: &(u64, u64, u64, u64)| {
+            a.0 <= b.0 && a.1 <= b.1 && a.0 + a.2 >= b.0 + b.2 && a.1 + a.3 >= b.1 + b.3
+        };
+        free = split
+            .iter()
+            .enumerate()
+            .filter(|&(j, f)| {
+                !split
+                    .iter()
+                    .enumerate()
+                    .any(|(k, g)| k != j && contains(g, f) && (g != f || k < j))
+            })
+            .map(|(_, f)| *f)
+            .collect();
+    }
+    positions
+}
+
+// Porter-Duff "over" of `source`, its alpha scaled by `opacity`, onto straight-alpha `pixel`
+fn blend_over(pixel: &mut [u8], source: &Rgba<u8>, opacity: f32) {
+    let source_alpha = source[3] as f32 / 255.0 * opacity;
+    if source_alpha <= 0.0 {
+        return;
+    }
+    let dest_alpha = pixel[3] as f32 / 255.0;
+    let dest_weight = dest_alpha * (1.0 - source_alpha);
+    let alpha = source_alpha + dest_weight;
+    for c in 0..3 {
+        let value = (source[c] as f32 * source_alpha + pixel[c] as f32 * dest_weight) / alpha;
+        pixel[c] = value.round().clamp(0.0, 255.0) as u8;
+    }
+    pixel[3] = (alpha * 255.0).round() as u8;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Transform;
+
+    // The same gradient scene exposed at `gain`
+    fn exposure(gain: f32) -> RgbaImage {
+        RgbaImage::from_fn(64, 48, |x, y| {
+            let base = [x as f32 / 64.0, y as f32 / 48.0, 0.5];
+            Rgba([
+                (base[0] * gain * 255.0).min(255.0) as u8,
+                (base[1] * gain * 255.0).min(255.0) as u8,
+                (base[2] * gain * 255.0).min(255.0) as u8,
+                255,
+            ])
+        })
+    }
+
+    #[test]
+    fn test_pyramid_round_trip() {
+        let image = exposure(1.0);
+        let restored = collapse(laplacian_pyramid(Plane::from_rgb(&image), 4));
+        let original = Plane::from_rgb(&image);
+        let max_error = restored
+            .data
+            .iter()
+            .zip(&original.data)
+            .map(|(a, b)| (a - b).abs())
+            .fold(0.0, f32::max);
+        assert!(max_error < 1e-4, "{max_error}");
+    }
+
+    #[test]
+    fn test_exposure_fusion() {
+        let dark = exposure(0.3);
+        let bright = exposure(3.0);
+        let fused = exposure_fusion(&[dark.clone(), bright.clone()]).unwrap();
+        assert_eq!(fused.dimensions(), (64, 48));
+
+        // Mid-tones come out closer to mid-gray than either input
+        let distance = |image: &RgbaImage| {
+            image
+                .pixels()
+                .map(|p| (p[1] as f32 - 128.0).abs())
+                .sum::<f32>()
+        };
+        assert!(distance(&fused) < distance(&dark));
+        assert!(distance(&fused) < distance(&bright));
+
+        // A single frame passes through
+        assert_eq!(exposure_fusion(std::slice::from_ref(&dark)).unwrap(), dark);
+    }
+
+    #[test]
+    fn test_exposure_fusion_rejects_bad_input() {
+        assert!(exposure_fusion(&[]).is_err());
+        let small = RgbaImage::new(10, 10);
+        assert!(exposure_fusion(&[exposure(1.0), small]).is_err());
+    }
+
+    #[test]
+    fn test_stack_methods() {
+        // Ten noisy frames of flat gray, one with a bright outlier streak
+        let frames: Vec<RgbaImage> = (0..10u64)
+            .map(|seed| {
+                let noisy = crate::filters::add_noise(
+                    &RgbaImage::from_pixel(32, 32, Rgba([100, 100, 100, 255])),
+                    0.02,
+                    true,
+                    seed,
+                );
+                let mut frame = noisy;
+                if seed == 3 {
+                    for x in 0..32 {
+                        frame.put_pixel(x, 16, Rgba([255, 255, 255, 255]));
+                    }
+                }
+                frame
+            })
+            .collect();
+
+        let spread = |image: &RgbaImage, y: u32| {
+            (0..32)
+                .map(|x| (image.get_pixel(x, y)[0] as i32 - 100).abs())
+                .max()
+                .unwrap()
+        };
+        let mean = stack(&frames, StackMethod::Mean).unwrap();
+        let median = stack(&frames, StackMethod::Median).unwrap();
+        let clipped = stack(&frames, StackMethod::from_name("sigma_clip").unwrap()).unwrap();
+
+        // Stacking reduces noise compared with a single frame
+        assert!(spread(&mean, 5) < spread(&frames[0], 5));
+        // The streak leaks into the mean but not into the robust methods
+        assert!(spread(&mean, 16) > 10);
+        assert!(spread(&median, 16) <= 10);
+        assert!(spread(&clipped, 16) <= 10);
+        assert!(mean.pixels().all(|p| p[3] == 255));
+
+        assert!(stack(&[], StackMethod::Mean).is_err());
+        let bad_sigma = StackMethod::SigmaClip {
+            sigma: 0.0,
+            iterations: 1,
+        };
+        assert!(stack(&frames, bad_sigma).is_err());
+    }
+
+    #[test]
+    fn test_stack_aligned() {
+        let texture = crate::filters::blur(&crate::generate::test_pattern(96, 96), 1.5);
+        let frames: Vec<RgbaImage> = [(0.0, 0.0), (3.0, -2.0), (-4.0, 1.5)]
+            .iter()
+            .map(|&(dx, dy)| {
+                let shift = Transform::translation(dx, dy);
+                geometry::warp(
+                    &texture,
+                    &shift,
+                    96,
+                    96,
+                    Interpolation::Bilinear,
+                    Rgba([0; 4]),
+                )
+                .unwrap()
+            })
+            .collect();
+
+        let aligned = stack_aligned(&frames, StackMethod::Median).unwrap();
+        let naive = stack(&frames, StackMethod::Median).unwrap();
+        let error = |image: &RgbaImage| {
+            (10..86)
+                .flat_map(|y| (10..86).map(move |x| (x, y)))
+                .map(|(x, y)| image.get_pixel(x, y)[0].abs_diff(texture.get_pixel(x, y)[0]) as u32)
+                .sum::<u32>()
+        };
+        assert!(error(&aligned) * 4 < error(&naive));
+    }
+
+    #[test]
+    fn test_watermark_anchor_and_opacity() {
+        let base = RgbaImage::from_pixel(40, 30, Rgba([0, 0, 0, 255]));
+        let mark = RgbaImage::from_pixel(10, 5, Rgba([255, 255, 255, 255]));
+        let result = watermark(&base, &mark, Anchor::BottomRight, 2, 0.5, false, None).unwrap();
+        assert_eq!(result[(28, 23)], Rgba([128, 128, 128, 255]));
+        assert_eq!(result[(37, 23)], Rgba([128, 128, 128, 255]));
+        assert_eq!(result[(38, 23)], base[(38, 23)]);
+        assert_eq!(result[(27, 23)], base[(27, 23)]);
+        assert_eq!(result[(28, 22)], base[(28, 22)]);
+
+        let centered = watermark(&base, &mark, Anchor::Center, 0, 1.0, false, None).unwrap();
+        assert_eq!(centered[(15, 12)], Rgba([255; 4]));
+        assert_eq!(centered[(14, 12)], base[(14, 12)]);
+
+        // A transparent base takes on the mark's own alpha
+        let clear = RgbaImage::new(20, 20);
+        let over = watermark(&clear, &mark, Anchor::TopLeft, 0, 0.5, false, None).unwrap();
+        assert_eq!(over[(0, 0)], Rgba([255, 255, 255, 128]));
+
+        assert!(watermark(&base, &mark, Anchor::Top, 0, 1.5, false, None).is_err());
+        assert!(watermark(&base, &mark, Anchor::Top, 0, 1.0, false, Some(0.0)).is_err());
+        assert_eq!(Anchor::from_name("Bottom-Left"), Some(Anchor::BottomLeft));
+    }
+
+    #[test]
+    fn test_watermark_tile_and_scale() {
+        let base = RgbaImage::from_pixel(40, 40, Rgba([0, 0, 0, 255]));
+        let mark = RgbaImage::from_pixel(4, 2, Rgba([255, 0, 0, 255]));
+        let tiled = watermark(&base, &mark, Anchor::TopLeft, 4, 1.0, true, None).unwrap();
+        // Tiles every 8 columns and 6 rows, including partial ones at the edges
+        for (x, y) in [(4, 4), (12, 4), (36, 34), (39, 35)] {
+            assert_eq!(tiled[(x, y)], Rgba([255, 0, 0, 255]), "({x}, {y})");
+        }
+        for (x, y) in [(0, 0), (8, 4), (4, 6), (3, 3)] {
+            assert_eq!(tiled[(x, y)], base[(x, y)], "({x}, {y})");
+        }
+
+        // A quarter of the base width, keeping the 2:1 aspect
+        let scaled = watermark(&base, &mark, Anchor::TopLeft, 0, 1.0, false, Some(0.25)).unwrap();
+        assert_eq!(scaled[(9, 4)], Rgba([255, 0, 0, 255]));
+        assert_eq!(scaled[(5, 5)], base[(5, 5)]);
+        assert_eq!(scaled[(10, 2)], base[(10, 2)]);
+    }
+
+    #[test]
+    fn test_drop_shadow() {
+        // An opaque 10x10 square with transparent surroundings
+        let image = RgbaImage::from_fn(20, 20, |x, y| {
+            if (5..15).contains(&x) && (5..15).contains(&y) {
+                Rgba([255, 255, 255, 255])
+            } else {
+                Rgba([0, 0, 0, 0])
+            }
+        });
+        let black = Rgba([0, 0, 0, 255]);
+
+        let hard = drop_shadow(&image, (4, 6), 0.0, black, 0.5).unwrap();
+        assert_eq!(hard.dimensions(), (24, 26));
+        // The image stays at the top left, the shadow shows below and to the right
+        assert_eq!(hard[(10, 10)], Rgba([255, 255, 255, 255]));
+        assert_eq!(hard[(17, 19)], Rgba([0, 0, 0, 128]));
+        assert_eq!(hard[(2, 2)], Rgba([0, 0, 0, 0]));
+
+        // Blurred shadows get room on every side; negative offsets move the image
+        let soft = drop_shadow(&image, (-3, 0), 2.0, black, 1.0).unwrap();
+        assert_eq!(soft.dimensions(), (20 + 12 + 3, 20 + 12));
+        assert_eq!(soft[(6 + 3 + 10, 6 + 10)], Rgba([255, 255, 255, 255]));
+        // The shadow spans x 11..21, the image 14..24
+        let edge = soft[(8, 16)][3];
+        let center = soft[(12, 16)][3];
+        assert!(edge > 0 && edge < center, "{edge} {center}");
+
+        assert!(drop_shadow(&image, (0, 0), -1.0, black, 0.5).is_err());
+        assert!(drop_shadow(&image, (0, 0), 1.0, black, 1.5).is_err());
+    }
+
+    #[test]
+    fn test_montage() {
+        let gray = Rgba([200, 200, 200, 255]);
+        let colors = [
+            [255, 0, 0],
+            [0, 255, 0],
+            [0, 0, 255],
+            [255, 255, 0],
+            [0, 0, 0],
+        ];
+        let images: Vec<RgbaImage> = colors
+            .iter()
+            .map(|&[r, g, b]| RgbaImage::from_pixel(80, 30, Rgba([r, g, b, 255])))
+            .collect();
+
+        let sheet = montage(&images, 3, (40, 30), 4, gray, &[]).unwrap();
+        assert_eq!(sheet.dimensions(), (3 * 40 + 4 * 4, 2 * 30 + 3 * 4));
+        // Scaled to 40x15 and centered vertically in the cell
+        assert_eq!(sheet[(24, 18)], Rgba([255, 0, 0, 255]));
+        assert_eq!(sheet[(24, 8)], gray);
+        assert_eq!(sheet[(2 * 44 + 24, 18)], Rgba([0, 0, 255, 255]));
+        assert_eq!(sheet[(44 + 24, 34 + 18)], Rgba([0, 0, 0, 255]));
+        // The sixth cell stays empty
+        assert_eq!(sheet[(2 * 44 + 24, 34 + 18)], gray);
+
+        let labeled = montage(&images, 3, (40, 30), 4, gray, &["first", "", "third"]).unwrap();
+        assert_eq!(labeled.height(), 2 * (30 + 11) + 3 * 4);
+        let inked = |x0: u32| {
+            (x0..x0 + 40)
+                .flat_map(|x| (34..45).map(move |y| (x, y)))
+                .any(|(x, y)| labeled[(x, y)] == Rgba([0, 0, 0, 255]))
+        };
+        assert!(inked(4));
+        assert!(!inked(48));
+        assert!(inked(92));
+
+        assert!(montage(&[], 3, (40, 30), 4, gray, &[]).is_err());
+        assert!(montage(&images, 0, (40, 30), 4, gray, &[]).is_err());
+        assert!(montage(&images, 3, (0, 30), 4, gray, &[]).is_err());
+    }
+
+    #[test]
+    fn test_compare() {
+        let before = RgbaImage::from_pixel(20, 10, Rgba([90, 90, 90, 255]));
+        let mut after = before.clone();
+        after.put_pixel(15, 5, Rgba([90, 90, 250, 255]));
+        after.put_pixel(16, 5, Rgba([100, 90, 90, 255]));
+
+        let side = compare(&before, &RgbaImage::new(6, 4), CompareMode::SideBySide).unwrap();
+        assert_eq!(side.dimensions(), (26, 10));
+        assert_eq!(side[(19, 0)], before[(19, 0)]);
+        assert_eq!(side[(20, 0)], Rgba([0, 0, 0, 0]));
+
+        let split = compare(&before, &after, CompareMode::from_name("split").unwrap()).unwrap();
+        assert_eq!(split[(9, 0)], Rgba([255, 255, 255, 255]));
+        assert_eq!(split[(11, 0)], Rgba([0, 0, 0, 255]));
+        assert_eq!(split[(15, 5)], after[(15, 5)]);
+        let left = CompareMode::Split { position: 0.9 };
+        assert_eq!(
+            compare(&before, &after, left).unwrap()[(15, 5)],
+            before[(15, 5)]
+        );
+
+        let heatmap = compare(&before, &after, CompareMode::Difference).unwrap();
+        assert_eq!(heatmap[(0, 0)], Rgba([30, 30, 30, 255]));
+        assert_eq!(heatmap[(15, 5)], Rgba([255, 255, 255, 255]));
+        // A difference of 10 levels is dark red
+        assert_eq!(heatmap[(16, 5)], Rgba([120, 0, 0, 255]));
+
+        assert!(compare(&before, &side, CompareMode::Difference).is_err());
+        assert!(compare(&before, &after, CompareMode::Split { position: 1.5 }).is_err());
+        assert_eq!(
+            CompareMode::from_name("Side-By-Side"),
+            Some(CompareMode::SideBySide)
+        );
+    }
+
+    #[test]
+    fn test_sprite_sheet() {
+        let sizes = [(30, 20), (10, 10), (20, 30), (10, 20), (25, 5), (8, 8)];
+        let images: Vec<RgbaImage> = sizes
+            .iter()
+            .enumerate()
+            .map(|(i, &(w, h))| RgbaImage::from_pixel(w, h, Rgba([i as u8 * 40, 0, 0, 255])))
+            .collect();
+
+        for packing in [SpritePacking::Shelf, SpritePacking::MaxRects] {
+            let options = SpriteOptions {
+                packing,
+                ..SpriteOptions::default()
+            };
+            let sheet = sprite_sheet(&images, &options).unwrap();
+            assert_eq!(sheet.image.dimensions(), (sheet.width, sheet.height));
+            for (i, (frame, image)) in sheet.frames.iter().zip(&images).enumerate() {
+                assert_eq!((frame.width, frame.height), image.dimensions());
+                assert_eq!(sheet.image[(frame.x, frame.y)], image[(0, 0)]);
+                let corner = (frame.x + frame.width - 1, frame.y + frame.height - 1);
+                assert_eq!(sheet.image[corner], image[(0, 0)]);
+                // No two frames come closer than the padding
+                for other in &sheet.frames[i + 1..] {
+                    let apart = frame.x >= other.x + other.width + 2
+                        || other.x >= frame.x + frame.width + 2
+                        || frame.y >= other.y + other.height + 2
+                        || other.y >= frame.y + frame.height + 2;
+                    assert!(apart, "{packing:?}: {frame:?} and {other:?}");
+                }
+            }
+        }
+
+        // Max-rects fills the gaps next to short sprites that shelves leave empty
+        let area = |packing| {
+            let options = SpriteOptions {
+                packing,
+                padding: 0,
+                ..SpriteOptions::default()
+            };
+            let sheet = sprite_sheet(&images, &options).unwrap();
+            sheet.width * sheet.height
+        };
+        assert!(area(SpritePacking::MaxRects) <= area(SpritePacking::Shelf));
+
+        let options = SpriteOptions {
+            power_of_two: true,
+            max_width: 40,
+            ..SpriteOptions::default()
+        };
+        let sheet = sprite_sheet(&images, &options).unwrap();
+        assert!(sheet.width.is_power_of_two() && sheet.width <= 64);
+        assert!(sheet.height.is_power_of_two());
+        let json: serde_json::Value = serde_json::from_str(&sheet.to_json().unwrap()).unwrap();
+        assert_eq!(json["frames"].as_array().unwrap().len(), 6);
+        assert_eq!(json["frames"][2]["height"], 30);
+
+        assert!(sprite_sheet(&[], &SpriteOptions::default()).is_err());
+        assert!(sprite_sheet(&[RgbaImage::new(0, 4)], &SpriteOptions::default()).is_err());
+        let narrow = SpriteOptions {
+            max_width: 20,
+            ..SpriteOptions::default()
+        };
+        assert!(sprite_sheet(&images, &narrow).is_err());
+        assert_eq!(
+            SpritePacking::from_name("Max-Rects"),
+            Some(SpritePacking::MaxRects)
+        );
+    }
+}
@@ -0,0 +1,351 @@
+//! Content-aware cropping
+//!
+//! The crop window is placed where a saliency map is strongest instead of in the
+//! center. Saliency combines Sobel edge energy, local histogram entropy and,
+//! optionally, a skin-tone prior and detected faces that keep people in frame.
+
+use crate::filters::{resize, ResampleFilter};
+use image::imageops::{self, FilterType};
+use image::RgbaImage;
+
+/// Longest side of the downscaled copy used for the saliency search
+const ANALYSIS_SIZE: u32 = 256;
+/// Side of the cells used for local entropy
+const ENTROPY_CELL: usize = 8;
+
+/// Tuning for `smart_crop_with`
+#[derive(Debug, Clone, PartialEq)]
+pub struct SmartCropOptions {
+    /// Weight of Sobel edge energy
+    pub edge_weight: f32,
+    /// Weight of local histogram entropy (texture/detail)
+    pub entropy_weight: f32,
+    /// Weight of skin-colored pixels, a cheap stand-in for face detection (0 = off)
+    pub skin_weight: f32,
+    /// Weight of pixels inside faces found by `analysis::detect_faces` (0 = off)
+    pub face_weight: f32,
+}
+
+impl Default for SmartCropOptions {
+    fn default() -> Self {
+        Self {
+            edge_weight: 1.0,
+            entropy_weight: 0.5,
+            skin_weight: 1.0,
+            face_weight: 0.0,
+        }
+    }
+}
+
+/// A crop rectangle in source image coordinates
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CropWindow {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+}
+
+/// Crop to the aspect ratio of `target_width` x `target_height` around the most
+/// salient content, then resize to exactly that size
+pub fn smart_crop(image: &RgbaImage, target_width: u32, target_height: u32) -> RgbaImage {
+    smart_crop_with(
+        image,
+        target_width,
+        target_height,
+        &SmartCropOptions::default(),
+    )
+}
+
+/// `smart_crop` with explicit saliency weights
+pub fn smart_crop_with(
+    image: &RgbaImage,
+    target_width: u32,
+    target_height: u32,
+    options: &SmartCropOptions,
+) -> RgbaImage {
+    let (target_width, target_height) = (target_width.max(1), target_height.max(1));
+    if image.width() == 0 || image.height() == 0 {
+        return RgbaImage::new(0, 0);
+    }
+
+    let window = find_crop_window(image, target_width, target_height, options);
+    let cropped =
+        imageops::crop_imm(image, window.x, window.y, window.width, window.height).to_image();
+    resize(
+        &cropped,
+        target_width,
+        target_height,
+        ResampleFilter::Lanczos3,
+    )
+}
+
+/// Largest window with the target aspect ratio that covers the most salient content
+pub fn find_crop_window(
+    image: &RgbaImage,
+    target_width: u32,
+    target_height: u32,
+    options: &SmartCropOptions,
+) -> CropWindow {
+    let (width, height) = image.dimensions();
+    let aspect = target_width.max(1) as f64 / target_height.max(1) as f64;
+    let (crop_w, crop_h) = if width as f64 / height as f64 > aspect {
+        (
+            ((height as f64 * aspect).round() as u32).clamp(1, width),
+            height,
+        )
+    } else {
+        (
+            width,
+            ((width as f64 / aspect).round() as u32).clamp(1, height),
+        )
+    };
+
+    let mut window = CropWindow {
+        x: (width - crop_w) / 2,
+        y: (height - crop_h) / 2,
+        width: crop_w,
+        height: crop_h,
+    };
+    if crop_w == width && crop_h == height {
+        return window;
+    }
+
+    // Search on a small copy; only one axis has any freedom
+    let scale = (ANALYSIS_SIZE as f64 / width.max(height) as f64).min(1.0);
+    let small_w = ((width as f64 * scale).round() as u32).max(1);
+    let small_h = ((height as f64 * scale).round() as u32).max(1);
+    let small = imageops::resize(image, small_w, small_h, FilterType::Triangle);
+    let table = SummedArea::new(&saliency_map(&small, options), small_w as usize);
+
+    let win_w = ((crop_w as f64 * scale).round() as usize).clamp(1, small_w as usize);
+    let win_h = ((crop_h as f64 * scale).round() as usize).clamp(1, small_h as usize);
+    let horizontal = crop_w < width;
+    let positions = if horizontal {
+        small_w as usize - win_w
+    } else {
+        small_h as usize - win_h
+    };
+
+    // Prefer the most central position among equally salient ones
+    let center = positions as f64 / 2.0;
+    let best = (0..=positions)
+        .map(|p| {
+            let score = if horizontal {
+                table.sum(p, 0, win_w, win_h)
+            } else {
+                table.sum(0, p, win_w, win_h)
+            };
+            (p, score)
+        })
+        .max_by(|(pa, a), (pb, b)| {
+            a.total_cmp(b).then_with(|| {
+                (*pb as f64 - center)
+                    .abs()
+                    .total_cmp(&(*pa as f64 - center).abs())
+            })
+        })
+        .map_or(0, |(p, _)| p);
+
+    let offset = (best as f64 / scale).round() as u32;
+    if horizontal {
+        window.x = offset.min(width - crop_w);
+    } else {
+        window.y = offset.min(height - crop_h);
+    }
+    window
+}
+
+/// Per-pixel saliency, row-major, for an already downscaled image
+fn saliency_map(image: &RgbaImage, options: &SmartCropOptions) -> Vec<f32> {
+    let (w, h) = (image.width() as usize, image.height() as usize);
+    let luma: Vec<f32> = image
+        .pixels()
+        .map(|p| 0.2126 * p[0] as f32 + 0.7152 * p[1] as f32 + 0.0722 * p[2] as f32)
+        .collect();
+
+    // Sobel magnitude, normalized to 0..1
+    let mut edges = vec![0f32; w * h];
+    for y in 1..h.saturating_sub(1) {
+        for x in 1..w.saturating_sub(1) {
+            let at = |dx: isize, dy: isize| {
+                luma[(y as isize + dy) as usize * w + (x as isize + dx) as usize]
+            };
+            let gx =
+                at(1, -1) + 2.0 * at(1, 0) + at(1, 1) - at(-1, -1) - 2.0 * at(-1, 0) - at(-1, 1);
+            let gy =
+                at(-1, 1) + 2.0 * at(0, 1) + at(1, 1) - at(-1, -1) - 2.0 * at(0, -1) - at(1, -1);
+            edges[y * w + x] = (gx * gx + gy * gy).sqrt();
+        }
+    }
+    let max_edge = edges.iter().copied().fold(0.0, f32::max).max(f32::EPSILON);
+
+    // Histogram entropy of each cell, normalized by the maximum for 32 bins
+    let mut entropy = vec![0f32; w * h];
+    for cy in (0..h).step_by(ENTROPY_CELL) {
+        for cx in (0..w).step_by(ENTROPY_CELL) {
+            let (y1, x1) = ((cy + ENTROPY_CELL).min(h), (cx + ENTROPY_CELL).min(w));
+            let mut bins = [0u32; 32];
+            for y in cy..y1 {
+                for x in cx..x1 {
+                    bins[(luma[y * w + x] as usize / 8).min(31)] += 1;
+                }
+            }
+            let total = ((y1 - cy) * (x1 - cx)) as f32;
+            let e = bins
+                .iter()
+                .filter(|&&n| n > 0)
+                .map(|&n| {
+                    let p = n as f32 / total;
+                    -p * p.log2()
+                })
+                .sum::<f32>()
+                / 5.0;
+            for y in cy..y1 {
+                entropy[y * w + cx..y * w + x1].fill(e);
+            }
+        }
+    }
+
+    let faces = if options.face_weight > 0.0 {
+        crate::analysis::detect_faces(image)
+    } else {
+        Vec::new()
+    };
+    let in_face = |i: usize| {
+        let (x, y) = ((i % w) as u32, (i / w) as u32);
+        faces
+            .iter()
+            .any(|f| x >= f.x && x - f.x < f.width && y >= f.y && y - f.y < f.height)
+    };
+
+    image
+        .pixels()
+        .enumerate()
+        .map(|(i, p)| {
+            let face = if in_face(i) { options.face_weight } else { 0.0 };
+            let skin = if options.skin_weight > 0.0 && is_skin(p[0], p[1], p[2]) {
+                options.skin_weight
+            } else {
+                0.0
+            };
+            options.edge_weight * edges[i] / max_edge
+                + options.entropy_weight * entropy[i]
+                + skin
+                + face
+        })
+        .collect()
+}
+
+// Classic YCbCr skin-tone box (Chai & Ngan)
+pub(crate) fn is_skin(r: u8, g: u8, b: u8) -> bool {
+    let (r, g, b) = (r as f32, g as f32, b as f32);
+    let cb = 128.0 - 0.168736 * r - 0.331264 * g + 0.5 * b;
+    let cr = 128.0 + 0.5 * r - 0.418688 * g - 0.081312 * b;
+    (77.0..=127.0).contains(&cb) && (133.0..=173.0).contains(&cr)
+}
+
+/// Summed-area table for constant-time window sums
+struct SummedArea {
+    width: usize,
+    sums: Vec<f64>,
+}
+
+impl SummedArea {
+    fn new(values: &[f32], width: usize) -> Self {
+        let height = values.len() / width;
+        let stride = width + 1;
+        let mut sums = vec![0f64; stride * (height + 1)];
+        for y in 0..height {
+            let mut row = 0f64;
+            for x in 0..width {
+                row += values[y * width + x] as f64;
+                sums[(y + 1) * stride + x + 1] = sums[y * stride + x + 1] + row;
+            }
+        }
+        Self { width, sums }
+    }
+
+    fn sum(&self, x: usize, y: usize, w: usize, h: usize) -> f64 {
+        let stride = self.width + 1;
+        let at = |x: usize, y: usize| self.sums[y * stride + x];
+        at(x + w, y + h) - at(x, y + h) - at(x + w, y) + at(x, y)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generate;
+    use image::Rgba;
+
+    #[test]
+    fn test_crop_follows_detail() {
+        // Flat gray with a detailed patch near the right edge
+        let mut image = generate::solid(300, 100, Rgba([128, 128, 128, 255]));
+        let detail = generate::checkerboard(60, 60, 4, Rgba([0, 0, 0, 255]), Rgba([255; 4]));
+        imageops::replace(&mut image, &detail, 230, 20);
+
+        let options = SmartCropOptions::default();
+        let window = find_crop_window(&image, 100, 100, &options);
+        assert_eq!((window.width, window.height), (100, 100));
+        assert!(window.x >= 190, "window at {:?}", window);
+
+        assert_eq!(smart_crop(&image, 50, 50).dimensions(), (50, 50));
+    }
+
+    #[test]
+    fn test_crop_keeps_skin_in_frame() {
+        // A skin-colored block at the top of a tall, flat image
+        let mut image = generate::solid(100, 400, Rgba([40, 90, 40, 255]));
+        let face = generate::solid(60, 60, Rgba([224, 172, 140, 255]));
+        imageops::replace(&mut image, &face, 20, 10);
+
+        let window = find_crop_window(&image, 1, 1, &SmartCropOptions::default());
+        assert!(window.y <= 10, "window at {:?}", window);
+    }
+
+    #[test]
+    fn test_face_weight_prefers_faces_over_detail() {
+        // A face at the left, busy texture at the right
+        let mut image = generate::solid(300, 100, Rgba([40, 70, 120, 255]));
+        let detail = generate::checkerboard(60, 60, 4, Rgba([0, 0, 0, 255]), Rgba([255; 4]));
+        imageops::replace(&mut image, &detail, 230, 20);
+        for (x, y, p) in image.enumerate_pixels_mut() {
+            let (dx, dy) = ((x as f32 - 50.0) / 25.0, (y as f32 - 50.0) / 32.0);
+            if dx * dx + dy * dy <= 1.0 {
+                let eye = (x as i32 - 40).abs().min((x as i32 - 60).abs()) < 4
+                    && (y as i32 - 44).abs() < 3;
+                *p = if eye {
+                    Rgba([30, 25, 20, 255])
+                } else {
+                    Rgba([224, 172, 140, 255])
+                };
+            }
+        }
+
+        let mut options = SmartCropOptions {
+            skin_weight: 0.0,
+            ..SmartCropOptions::default()
+        };
+        assert!(find_crop_window(&image, 100, 100, &options).x >= 190);
+        options.face_weight = 4.0;
+        let window = find_crop_window(&image, 100, 100, &options);
+        assert!(window.x <= 25, "window at {:?}", window);
+    }
+
+    #[test]
+    fn test_matching_aspect_is_noop_window() {
+        let image = generate::test_pattern(120, 60);
+        let window = find_crop_window(&image, 40, 20, &SmartCropOptions::default());
+        assert_eq!(
+            window,
+            CropWindow {
+                x: 0,
+                y: 0,
+                width: 120,
+                height: 60
+            }
+        );
+    }
+}
@@ -0,0 +1,555 @@
+//! Encoder settings for web delivery
+//!
+//! `ImagePipeline::encode_with_options` exposes the knobs `encode` leaves at
+//! their defaults: progressive scans and chroma subsampling for JPEG, Adam7
+//! interlacing, deflate level and row filtering for PNG, and the encoder speed
+//! for AVIF. `encode_to_target_size` searches those settings for the best
+//! result under a byte budget.
+
+use crate::filters::{self, ResampleFilter};
+use crate::png_encode;
+use crate::{ImagePipeline, OutputFormat, PipelineError, Result};
+use image::error::{EncodingError, ImageFormatHint};
+use image::{ImageError, ImageFormat, RgbaImage};
+
+/// Chroma resolution of a JPEG relative to luma
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ChromaSubsampling {
+    /// Full color resolution; largest files, sharpest colored edges
+    Yuv444,
+    /// Half horizontal color resolution
+    Yuv422,
+    /// Half horizontal and vertical color resolution
+    #[default]
+    Yuv420,
+}
+
+/// Per-row prediction filter for PNG
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PngFilter {
+    None,
+    Sub,
+    Up,
+    Average,
+    Paeth,
+    /// Pick the filter per row that minimizes the sum of absolute residuals
+    #[default]
+    Adaptive,
+}
+
+/// How hard to work on a smaller PNG; every level is lossless
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum PngEffort {
+    /// Write RGBA with the given filter and compression level
+    #[default]
+    None,
+    /// Store the image as grayscale, RGB or a 1-8 bit palette when that
+    /// represents it exactly
+    Fast,
+    /// `Fast`, then keep whichever filter strategy compresses best
+    Balanced,
+    /// `Balanced`, then recompress with zopfli when built with the `zopfli`
+    /// feature (much slower), otherwise at level 9
+    Max,
+}
+
+/// Output settings for `ImagePipeline::encode_with_options`
+///
+/// Settings for other formats are ignored, so one value can be reused across
+/// output formats. WebP output is lossless and takes no settings.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct EncodeOptions {
+    /// JPEG and AVIF quality (1-100)
+    pub quality: u8,
+    /// Emit a progressive JPEG that renders coarse-to-fine while loading
+    pub progressive: bool,
+    pub chroma_subsampling: ChromaSubsampling,
+    /// Emit an Adam7-interlaced PNG
+    pub interlaced: bool,
+    /// PNG deflate level from 0 (stored) to 9 (smallest)
+    pub png_compression: u8,
+    /// Filter strategy; searched from `PngEffort::Balanced` up
+    pub png_filter: PngFilter,
+    pub png_effort: PngEffort,
+    /// AVIF encoder speed from 1 (slowest, smallest files) to 10 (fastest)
+    pub avif_speed: u8,
+}
+
+impl Default for EncodeOptions {
+    fn default() -> Self {
+        Self {
+            quality: 90,
+            progressive: false,
+            chroma_subsampling: ChromaSubsampling::default(),
+            interlaced: false,
+            png_compression: 6,
+            png_filter: PngFilter::default(),
+            png_effort: PngEffort::default(),
+            avif_speed: 6,
+        }
+    }
+}
+
+impl EncodeOptions {
+    /// Default settings with the given JPEG quality
+    pub fn with_quality(quality: u8) -> Self {
+        Self {
+            quality,
+            ..Self::default()
+        }
+    }
+
+    /// Settings for images shown on the web: progressive JPEG and optimized,
+    /// interlaced PNG, both of which display a preview before fully loading
+    pub fn web(quality: u8) -> Self {
+        Self {
+            quality,
+            progressive: true,
+            interlaced: true,
+            png_compression: 9,
+            png_effort: PngEffort::Balanced,
+            ..Self::default()
+        }
+    }
+
+    /// Check the settings used by `format`
+    pub fn validate(&self, format: OutputFormat) -> Result<()> {
+        match format {
+            OutputFormat::Jpeg if !(1..=100).contains(&self.quality) => {
+                Err(PipelineError::InvalidParameter(format!(
+                    "JPEG quality must be between 1 and 100, got {}",
+                    self.quality
+                )))
+            }
+            #[cfg(feature = "avif")]
+            OutputFormat::Avif if !(1..=100).contains(&self.quality) => {
+                Err(PipelineError::InvalidParameter(format!(
+                    "AVIF quality must be between 1 and 100, got {}",
+                    self.quality
+                )))
+            }
+            #[cfg(feature = "avif")]
+            OutputFormat::Avif if !(1..=10).contains(&self.avif_speed) => {
+                Err(PipelineError::InvalidParameter(format!(
+                    "AVIF speed must be between 1 and 10, got {}",
+                    self.avif_speed
+                )))
+            }
+            OutputFormat::Png if self.png_compression > 9 => {
+                Err(PipelineError::InvalidParameter(format!(
+                    "PNG compression level must be between 0 and 9, got {}",
+                    self.png_compression
+                )))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Search range for `encode_to_target_size_with_options`
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TargetSizeOptions {
+    /// Settings to encode with; `quality` is the highest quality tried
+    pub encode: EncodeOptions,
+    /// Lowest JPEG/AVIF quality tried before shrinking the image
+    pub min_quality: u8,
+    /// Shrink the image when no quality fits; lossless formats can only fit
+    /// a budget this way
+    pub allow_downscale: bool,
+    /// Smallest scale factor (0.0-1.0] tried when shrinking
+    pub min_scale: f32,
+}
+
+impl Default for TargetSizeOptions {
+    fn default() -> Self {
+        Self {
+            encode: EncodeOptions::default(),
+            min_quality: 30,
+            allow_downscale: true,
+            min_scale: 0.1,
+        }
+    }
+}
+
+impl ImagePipeline {
+    /// Encode under `max_bytes`, lowering quality and then size as needed
+    ///
+    /// See `encode_to_target_size_with_options` for the search.
+    pub fn encode_to_target_size(
+        image: &RgbaImage,
+        format: OutputFormat,
+        max_bytes: usize,
+    ) -> Result<Vec<u8>> {
+        Self::encode_to_target_size_with_options(
+            image,
+            format,
+            max_bytes,
+            &TargetSizeOptions::default(),
+        )
+    }
+
+    /// Encode under `max_bytes` with the highest quality and size that fit
+    ///
+    /// Lossy formats binary-search quality between `min_quality` and
+    /// `encode.quality` first. If even the lowest quality is too large, the
+    /// image is scaled down (by the square root of the size ratio, since file
+    /// size grows with area) and searched again, down to `min_scale`. Fails
+    /// with `PipelineError::ResourceLimit` when nothing fits.
+    pub fn encode_to_target_size_with_options(
+        image: &RgbaImage,
+        format: OutputFormat,
+        max_bytes: usize,
+        options: &TargetSizeOptions,
+    ) -> Result<Vec<u8>> {
+        if options.min_quality == 0 || options.min_quality > options.encode.quality {
+            return Err(PipelineError::InvalidParameter(format!(
+                "min_quality must be between 1 and the encode quality {}, got {}",
+                options.encode.quality, options.min_quality
+            )));
+        }
+        if !(options.min_scale > 0.0 && options.min_scale <= 1.0) {
+            return Err(PipelineError::InvalidParameter(format!(
+                "min_scale must be in (0, 1], got {}",
+                options.min_scale
+            )));
+        }
+        options.encode.validate(format)?;
+
+        let lossy = is_lossy(format);
+        let mut scale = 1.0f32;
+        let mut scaled = None;
+        loop {
+            let current = scaled.as_ref().unwrap_or(image);
+            let with_quality = |quality| {
+                let encode = EncodeOptions {
+                    quality,
+                    ..options.encode
+                };
+                Self::encode_with_options(current, format, &encode)
+            };
+
+            // Smallest encoding at this size, which decides whether to shrink
+            let smallest = with_quality(if lossy {
+                options.min_quality
+            } else {
+                options.encode.quality
+            })?;
+            if smallest.len() <= max_bytes {
+                if !lossy {
+                    return Ok(smallest);
+                }
+                // Highest quality that fits; `best` always fits
+                let (mut low, mut high) = (options.min_quality, options.encode.quality);
+                let mut best = smallest;
+                while low < high {
+                    let mid = low + (high - low).div_ceil(2);
+                    let encoded = with_quality(mid)?;
+                    if encoded.len() <= max_bytes {
+                        best = encoded;
+                        low = mid;
+                    } else {
+                        high = mid - 1;
+                    }
+                }
+                return Ok(best);
+            }
+
+            let too_large = || {
+                PipelineError::ResourceLimit(format!(
+                    "cannot encode {}x{} as {} under {} bytes",
+                    image.width(),
+                    image.height(),
+                    format.extension(),
+                    max_bytes
+                ))
+            };
+            if !options.allow_downscale || scale <= options.min_scale {
+                return Err(too_large());
+            }
+            // Aim slightly under the budget, shrinking by at least 10% per step
+            let ratio = (max_bytes as f32 / smallest.len() as f32).sqrt() * 0.95;
+            scale = (scale * ratio.min(0.9)).max(options.min_scale);
+            let width = ((image.width() as f32 * scale).round() as u32).max(1);
+            let height = ((image.height() as f32 * scale).round() as u32).max(1);
+            scaled = Some(filters::resize(
+                image,
+                width,
+                height,
+                ResampleFilter::Lanczos3,
+            ));
+        }
+    }
+
+    /// Encode image to the given format with explicit encoder settings
+    pub fn encode_with_options(
+        image: &RgbaImage,
+        format: OutputFormat,
+        options: &EncodeOptions,
+    ) -> Result<Vec<u8>> {
+        options.validate(format)?;
+        match format {
+            OutputFormat::Png => png_encode::encode(image, options),
+            OutputFormat::Jpeg => encode_jpeg(image, options),
+            OutputFormat::WebP => {
+                use image::ImageEncoder;
+
+                // The bundled WebP encoder is lossless, so quality is ignored
+                let mut buffer = Vec::new();
+                let encoder = image::codecs::webp::WebPEncoder::new_lossless(&mut buffer);
+                encoder.write_image(
+                    image.as_raw(),
+                    image.width(),
+                    image.height(),
+                    image::ExtendedColorType::Rgba8,
+                )?;
+                Ok(buffer)
+            }
+            #[cfg(feature = "avif")]
+            OutputFormat::Avif => {
+                use image::ImageEncoder;
+
+                let mut buffer = Vec::new();
+                let encoder = image::codecs::avif::AvifEncoder::new_with_speed_quality(
+                    &mut buffer,
+                    options.avif_speed,
+                    options.quality,
+                );
+                encoder.write_image(
+                    image.as_raw(),
+                    image.width(),
+                    image.height(),
+                    image::ExtendedColorType::Rgba8,
+                )?;
+                Ok(buffer)
+            }
+        }
+    }
+}
+
+fn is_lossy(format: OutputFormat) -> bool {
+    match format {
+        OutputFormat::Jpeg => true,
+        #[cfg(feature = "avif")]
+        OutputFormat::Avif => true,
+        OutputFormat::Png | OutputFormat::WebP => false,
+    }
+}
+
+fn encode_jpeg(image: &RgbaImage, options: &EncodeOptions) -> Result<Vec<u8>> {
+    use jpeg_encoder::{ColorType, Encoder, SamplingFactor};
+
+    let dimension = |value: u32| {
+        u16::try_from(value).map_err(|_| {
+            PipelineError::InvalidParameter(format!(
+                "JPEG dimensions are limited to 65535, got {}x{}",
+                image.width(),
+                image.height()
+            ))
+        })
+    };
+    let (width, height) = (dimension(image.width())?, dimension(image.height())?);
+
+    let mut buffer = Vec::new();
+    let mut encoder = Encoder::new(&mut buffer, options.quality);
+    encoder.set_progressive(options.progressive);
+    encoder.set_sampling_factor(match options.chroma_subsampling {
+        ChromaSubsampling::Yuv444 => SamplingFactor::R_4_4_4,
+        ChromaSubsampling::Yuv422 => SamplingFactor::R_4_2_2,
+        ChromaSubsampling::Yuv420 => SamplingFactor::R_4_2_0,
+    });
+    // JPEG has no alpha channel; the encoder drops it
+    encoder
+        .encode(image.as_raw(), width, height, ColorType::Rgba)
+        .map_err(|e| {
+            ImageError::Encoding(EncodingError::new(
+                ImageFormatHint::Exact(ImageFormat::Jpeg),
+                e,
+            ))
+        })?;
+    Ok(buffer)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{ImageDecoder, Rgba};
+    use std::io::Cursor;
+
+    fn sample(width: u32, height: u32) -> RgbaImage {
+        RgbaImage::from_fn(width, height, |x, y| {
+            Rgba([
+                (x * 255 / width) as u8,
+                (y * 255 / height) as u8,
+                ((x ^ y) * 7) as u8,
+                255 - (x * 3) as u8,
+            ])
+        })
+    }
+
+    #[test]
+    fn test_interlaced_png_roundtrip() {
+        // Sizes smaller than the Adam7 grid leave some passes empty
+        for (width, height) in [(37, 23), (1, 1), (3, 9), (8, 8)] {
+            let image = sample(width, height);
+            for png_filter in [
+                PngFilter::None,
+                PngFilter::Sub,
+                PngFilter::Up,
+                PngFilter::Average,
+                PngFilter::Paeth,
+                PngFilter::Adaptive,
+            ] {
+                let options = EncodeOptions {
+                    interlaced: true,
+                    png_filter,
+                    ..EncodeOptions::default()
+                };
+                let bytes = ImagePipeline::encode_with_options(&image, OutputFormat::Png, &options)
+                    .unwrap();
+                let decoder = png::Decoder::new(Cursor::new(&bytes));
+                assert!(decoder.read_info().unwrap().info().interlaced);
+                let decoded = ImagePipeline::load_from_bytes(&bytes).unwrap();
+                assert_eq!(decoded, image, "{:?} at {}x{}", png_filter, width, height);
+            }
+        }
+    }
+
+    #[test]
+    fn test_png_compression_levels() {
+        let image = sample(64, 64);
+        let encode = |png_compression| {
+            let options = EncodeOptions {
+                png_compression,
+                ..EncodeOptions::default()
+            };
+            ImagePipeline::encode_with_options(&image, OutputFormat::Png, &options).unwrap()
+        };
+        let (stored, best) = (encode(0), encode(9));
+        assert!(best.len() < stored.len());
+        assert_eq!(ImagePipeline::load_from_bytes(&stored).unwrap(), image);
+        assert_eq!(ImagePipeline::load_from_bytes(&best).unwrap(), image);
+
+        let options = EncodeOptions {
+            png_compression: 10,
+            ..EncodeOptions::default()
+        };
+        assert!(ImagePipeline::encode_with_options(&image, OutputFormat::Png, &options).is_err());
+    }
+
+    #[test]
+    fn test_progressive_jpeg() {
+        let image = sample(40, 30);
+        let bytes =
+            ImagePipeline::encode_with_options(&image, OutputFormat::Jpeg, &EncodeOptions::web(85))
+                .unwrap();
+        // SOF2 marks a progressive frame
+        assert!(bytes.windows(2).any(|marker| marker == [0xFF, 0xC2]));
+        let decoded = ImagePipeline::load_from_bytes(&bytes).unwrap();
+        assert_eq!(decoded.dimensions(), image.dimensions());
+
+        let baseline = ImagePipeline::encode(&image, OutputFormat::Jpeg, 85).unwrap();
+        assert!(!baseline.windows(2).any(|marker| marker == [0xFF, 0xC2]));
+    }
+
+    #[test]
+    fn test_chroma_subsampling() {
+        // Sampling factors of the luma component in the frame header
+        let luma_sampling = |chroma_subsampling| {
+            let options = EncodeOptions {
+                chroma_subsampling,
+                ..EncodeOptions::default()
+            };
+            let bytes =
+                ImagePipeline::encode_with_options(&sample(32, 32), OutputFormat::Jpeg, &options)
+                    .unwrap();
+            let sof = bytes
+                .windows(2)
+                .position(|marker| marker == [0xFF, 0xC0])
+                .unwrap();
+            let decoder = image::codecs::jpeg::JpegDecoder::new(Cursor::new(&bytes)).unwrap();
+            assert_eq!(decoder.dimensions(), (32, 32));
+            bytes[sof + 11]
+        };
+        assert_eq!(luma_sampling(ChromaSubsampling::Yuv444), 0x11);
+        assert_eq!(luma_sampling(ChromaSubsampling::Yuv422), 0x21);
+        assert_eq!(luma_sampling(ChromaSubsampling::Yuv420), 0x22);
+    }
+
+    #[test]
+    fn test_encode_to_target_size() {
+        let image = RgbaImage::from_fn(96, 64, |x, y| {
+            Rgba([
+                (x * 37 % 256) as u8,
+                (y * 53 % 256) as u8,
+                ((x * y) % 256) as u8,
+                255,
+            ])
+        });
+        let full = ImagePipeline::encode(&image, OutputFormat::Jpeg, 90).unwrap();
+
+        // A budget between the lowest and highest quality lowers quality only
+        let budget = full.len() * 3 / 4;
+        let bytes =
+            ImagePipeline::encode_to_target_size(&image, OutputFormat::Jpeg, budget).unwrap();
+        assert!(bytes.len() <= budget);
+        assert_eq!(
+            ImagePipeline::load_from_bytes(&bytes).unwrap().dimensions(),
+            image.dimensions()
+        );
+
+        // PNG can only fit by shrinking
+        let png = ImagePipeline::encode(&image, OutputFormat::Png, 90).unwrap();
+        let bytes =
+            ImagePipeline::encode_to_target_size(&image, OutputFormat::Png, png.len() / 3).unwrap();
+        assert!(bytes.len() <= png.len() / 3);
+        let (width, height) = ImagePipeline::load_from_bytes(&bytes).unwrap().dimensions();
+        assert!(width < 96 && height < 64);
+
+        let fixed = TargetSizeOptions {
+            allow_downscale: false,
+            ..TargetSizeOptions::default()
+        };
+        let err = ImagePipeline::encode_to_target_size_with_options(
+            &image,
+            OutputFormat::Png,
+            png.len() / 3,
+            &fixed,
+        )
+        .unwrap_err();
+        assert!(matches!(err, PipelineError::ResourceLimit(_)), "{}", err);
+        assert!(ImagePipeline::encode_to_target_size(&image, OutputFormat::Jpeg, 10).is_err());
+    }
+
+    #[cfg(feature = "avif")]
+    #[test]
+    fn test_avif_encode() {
+        let image = sample(24, 16);
+        let encode = |quality, avif_speed| {
+            let options = EncodeOptions {
+                quality,
+                avif_speed,
+                ..EncodeOptions::default()
+            };
+            ImagePipeline::encode_with_options(&image, OutputFormat::Avif, &options)
+        };
+        let bytes = encode(60, 10).unwrap();
+        assert_eq!(image::guess_format(&bytes).unwrap(), ImageFormat::Avif);
+        assert!(encode(0, 10).is_err());
+        assert!(encode(60, 0).is_err());
+        assert!(encode(60, 11).is_err());
+        assert_eq!(
+            OutputFormat::from_extension("AVIF"),
+            Some(OutputFormat::Avif)
+        );
+    }
+
+    #[cfg(feature = "avif-decode")]
+    #[test]
+    fn test_avif_roundtrip() {
+        let image = sample(24, 16);
+        let bytes = ImagePipeline::encode(&image, OutputFormat::Avif, 95).unwrap();
+        let decoded = ImagePipeline::load_from_bytes(&bytes).unwrap();
+        assert_eq!(decoded.dimensions(), image.dimensions());
+        assert!(crate::analysis::ssim(&image, &decoded).unwrap() > 0.9);
+    }
+}
@@ -4,13 +4,72 @@ use thiserror::Error;
 pub enum PipelineError {
     #[error("Image loading error: {0}")]
     ImageError(#[from] image::ImageError),
-    
+
     #[error("Invalid parameter: {0}")]
     InvalidParameter(String),
-    
+
+    #[error("Invalid pipeline spec: {0}")]
+    InvalidSpec(String),
+
+    #[error("Invalid LUT: {0}")]
+    InvalidLut(String),
+
+    #[error("Invalid model: {0}")]
+    InvalidModel(String),
+
+    #[error("Image format not allowed: {0}")]
+    FormatNotAllowed(String),
+
+    #[error("Resource limit exceeded: {0}")]
+    ResourceLimit(String),
+
+    #[error("Operation cancelled")]
+    Cancelled,
+
     #[error("Processing error: {0}")]
     ProcessingError(String),
-    
+
     #[error("IO error: {0}")]
     IoError(#[from] std::io::Error),
 }
+
+impl PipelineError {
+    /// Index of the operation the error is about, if it names one
+    ///
+    /// Validation, spec compilation and resource checks during processing
+    /// prefix their messages with "operation N".
+    pub fn operation_index(&self) -> Option<usize> {
+        let message = match self {
+            PipelineError::InvalidParameter(message)
+            | PipelineError::InvalidSpec(message)
+            | PipelineError::ResourceLimit(message)
+            | PipelineError::ProcessingError(message) => message,
+            _ => return None,
+        };
+        let rest = message.strip_prefix("operation ")?;
+        let digits = rest
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(rest.len());
+        rest[..digits].parse().ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{FilterOperation, ImagePipeline, PipelineSpec};
+
+    #[test]
+    fn test_operation_index() {
+        let err = ImagePipeline::validate(&[FilterOperation::Invert, FilterOperation::Blur(-1.0)])
+            .unwrap_err();
+        assert_eq!(err.operation_index(), Some(1));
+        let err = PipelineSpec::from_json(r#"[{"type": "invert"}, {"type": "nope"}]"#).unwrap_err();
+        assert_eq!(err.operation_index(), Some(1));
+        assert_eq!(PipelineError::Cancelled.operation_index(), None);
+        assert_eq!(
+            PipelineError::InvalidParameter("operations are empty".into()).operation_index(),
+            None
+        );
+    }
+}
@@ -1,5 +1,100 @@
-use crate::filters;
+use crate::{
+    analysis, filters, rgba_len, CancellationToken, FilterOperation, ImagePipeline, OutputFormat,
+    PipelineError, PipelineSpec, ResampleFilter, SHARPEN_AMOUNT, SHARPEN_RADIUS,
+};
+use image::RgbaImage;
+use std::cell::RefCell;
+use std::ffi::{c_char, CStr, CString};
 use std::slice;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+fn set_last_error(message: impl ToString) {
+    let message = CString::new(message.to_string().replace('\0', " ")).ok();
+    LAST_ERROR.with(|last| *last.borrow_mut() = message);
+}
+
+// Record `message` for `image_pipeline_last_error` and return `code`
+fn fail(code: i32, message: impl ToString) -> i32 {
+    set_last_error(message);
+    code
+}
+
+/// Default for the largest image decoded or produced, as `LoadOptions::untrusted`
+pub const IMAGE_PIPELINE_DEFAULT_MAX_PIXELS: u64 = 100_000_000;
+
+static MAX_PIXELS: AtomicU64 = AtomicU64::new(IMAGE_PIPELINE_DEFAULT_MAX_PIXELS);
+static MAX_MEMORY_BYTES: AtomicU64 = AtomicU64::new(0);
+
+/// Set the limits applied when decoding and processing, for all threads
+///
+/// `max_pixels` caps the size of any decoded or produced image and
+/// `max_memory_bytes` the image buffers a run may hold; 0 disables a limit.
+/// Images over a limit fail before their pixels are allocated, so a small
+/// compressed file cannot claim gigabytes of memory.
+#[no_mangle]
+pub extern "C" fn image_pipeline_set_limits(max_pixels: u64, max_memory_bytes: u64) {
+    MAX_PIXELS.store(max_pixels, Ordering::Relaxed);
+    MAX_MEMORY_BYTES.store(max_memory_bytes, Ordering::Relaxed);
+}
+
+// A pipeline with the limits from `image_pipeline_set_limits`
+fn pipeline() -> ImagePipeline {
+    let mut pipeline = ImagePipeline::new();
+    pipeline.max_pixels = MAX_PIXELS.load(Ordering::Relaxed);
+    pipeline.max_memory_bytes = MAX_MEMORY_BYTES.load(Ordering::Relaxed);
+    pipeline
+}
+
+// Copy of the image behind `handle`, recording an error if there is none
+unsafe fn image_of(handle: *const ImageHandle) -> Option<RgbaImage> {
+    let Some(h) = handle.as_ref() else {
+        set_last_error("handle is null");
+        return None;
+    };
+    let image = RgbaImage::from_raw(h.width, h.height, h.data.clone());
+    if image.is_none() {
+        set_last_error("handle data does not match its dimensions");
+    }
+    image
+}
+
+// Replace the image behind `handle` with `filter` applied to it
+unsafe fn apply_filter(
+    handle: *mut ImageHandle,
+    filter: impl FnOnce(&RgbaImage) -> RgbaImage,
+) -> i32 {
+    let Some(image) = image_of(handle) else {
+        return -1;
+    };
+    let result = filter(&image);
+    let h = &mut *handle;
+    h.width = result.width();
+    h.height = result.height();
+    h.data = result.into_raw();
+    0
+}
+
+/// Output format codes for `image_pipeline_encode`
+pub const IMAGE_PIPELINE_FORMAT_PNG: i32 = 0;
+pub const IMAGE_PIPELINE_FORMAT_JPEG: i32 = 1;
+pub const IMAGE_PIPELINE_FORMAT_WEBP: i32 = 2;
+/// Only accepted when built with the `avif` feature
+pub const IMAGE_PIPELINE_FORMAT_AVIF: i32 = 3;
+
+/// Resample filter codes for `image_pipeline_resize_with_filter`
+pub const IMAGE_PIPELINE_RESAMPLE_NEAREST: i32 = 0;
+pub const IMAGE_PIPELINE_RESAMPLE_BILINEAR: i32 = 1;
+pub const IMAGE_PIPELINE_RESAMPLE_CATMULL_ROM: i32 = 2;
+pub const IMAGE_PIPELINE_RESAMPLE_LANCZOS3: i32 = 3;
+
+/// Algorithm codes for `image_pipeline_perceptual_hash`
+pub const IMAGE_PIPELINE_HASH_AVERAGE: i32 = 0;
+pub const IMAGE_PIPELINE_HASH_DIFFERENCE: i32 = 1;
+pub const IMAGE_PIPELINE_HASH_DCT: i32 = 2;
 
 /// Opaque handle for image data
 pub struct ImageHandle {
@@ -10,6 +105,9 @@ pub struct ImageHandle {
 
 /// Create a new image handle from raw RGBA data
 ///
+/// Returns null if `data` is null or the dimensions are too large (see
+/// `image_pipeline_last_error`).
+///
 /// # Safety
 /// - `data` must be a valid pointer to `width * height * 4` bytes
 /// - The data must be in RGBA format
@@ -20,10 +118,17 @@ pub unsafe extern "C" fn image_pipeline_create(
     height: u32,
 ) -> *mut ImageHandle {
     if data.is_null() {
+        set_last_error("data is null");
         return std::ptr::null_mut();
     }
 
-    let size = (width * height * 4) as usize;
+    let size = match rgba_len(width, height) {
+        Ok(size) => size,
+        Err(e) => {
+            set_last_error(e);
+            return std::ptr::null_mut();
+        }
+    };
     let slice = slice::from_raw_parts(data, size);
 
     let handle = Box::new(ImageHandle {
@@ -35,6 +140,40 @@ pub unsafe extern "C" fn image_pipeline_create(
     Box::into_raw(handle)
 }
 
+/// Create a new image handle by decoding an encoded image (PNG, JPEG, WebP, ...)
+///
+/// Returns null if the data cannot be decoded or the image is over the limits
+/// of `image_pipeline_set_limits` (see `image_pipeline_last_error`).
+///
+/// # Safety
+/// - `bytes` must be a valid pointer to `len` bytes
+#[no_mangle]
+pub unsafe extern "C" fn image_pipeline_create_from_encoded(
+    bytes: *const u8,
+    len: usize,
+) -> *mut ImageHandle {
+    if bytes.is_null() || len == 0 {
+        set_last_error("bytes is null or empty");
+        return std::ptr::null_mut();
+    }
+
+    let slice = slice::from_raw_parts(bytes, len);
+    match pipeline().decode(slice) {
+        Ok(image) => {
+            let handle = Box::new(ImageHandle {
+                width: image.width(),
+                height: image.height(),
+                data: image.into_raw(),
+            });
+            Box::into_raw(handle)
+        }
+        Err(e) => {
+            set_last_error(e);
+            std::ptr::null_mut()
+        }
+    }
+}
+
 /// Free an image handle
 ///
 /// # Safety
@@ -101,101 +240,66 @@ pub unsafe extern "C" fn image_pipeline_get_data_size(handle: *const ImageHandle
 /// - `handle` must be a valid pointer
 #[no_mangle]
 pub unsafe extern "C" fn image_pipeline_grayscale(handle: *mut ImageHandle) -> i32 {
-    if handle.is_null() {
-        return -1;
-    }
-
-    let h = &mut *handle;
-    if let Some(image) = image::RgbaImage::from_raw(h.width, h.height, h.data.clone()) {
-        let result = filters::grayscale(&image);
-        h.data = result.into_raw();
-        0
-    } else {
-        -1
-    }
+    apply_filter(handle, filters::grayscale)
 }
 
 /// Apply brightness adjustment
 ///
+/// Returns -1 for a null handle or a value out of range.
+///
 /// # Safety
 /// - `handle` must be a valid pointer
 /// - `value` should be between -1.0 and 1.0
 #[no_mangle]
 pub unsafe extern "C" fn image_pipeline_brightness(handle: *mut ImageHandle, value: f32) -> i32 {
-    if handle.is_null() {
-        return -1;
-    }
-
-    let h = &mut *handle;
-    if let Some(image) = image::RgbaImage::from_raw(h.width, h.height, h.data.clone()) {
-        let result = filters::brightness(&image, value);
-        h.data = result.into_raw();
-        0
-    } else {
-        -1
+    if let Err(e) = FilterOperation::Brightness(value).validate() {
+        return fail(-1, e);
     }
+    apply_filter(handle, |image| filters::brightness(image, value))
 }
 
 /// Apply contrast adjustment
 ///
+/// Returns -1 for a null handle or a value out of range.
+///
 /// # Safety
 /// - `handle` must be a valid pointer
 /// - `value` is the contrast factor (1.0 = no change)
 #[no_mangle]
 pub unsafe extern "C" fn image_pipeline_contrast(handle: *mut ImageHandle, value: f32) -> i32 {
-    if handle.is_null() {
-        return -1;
-    }
-
-    let h = &mut *handle;
-    if let Some(image) = image::RgbaImage::from_raw(h.width, h.height, h.data.clone()) {
-        let result = filters::contrast(&image, value);
-        h.data = result.into_raw();
-        0
-    } else {
-        -1
+    if let Err(e) = FilterOperation::Contrast(value).validate() {
+        return fail(-1, e);
     }
+    apply_filter(handle, |image| filters::contrast(image, value))
 }
 
 /// Apply Gaussian blur
 ///
+/// Returns -1 for a null handle or a sigma that is not positive and finite.
+///
 /// # Safety
 /// - `handle` must be a valid pointer
 /// - `sigma` is the blur radius
 #[no_mangle]
 pub unsafe extern "C" fn image_pipeline_blur(handle: *mut ImageHandle, sigma: f32) -> i32 {
-    if handle.is_null() {
-        return -1;
-    }
-
-    let h = &mut *handle;
-    if let Some(image) = image::RgbaImage::from_raw(h.width, h.height, h.data.clone()) {
-        let result = filters::blur(&image, sigma);
-        h.data = result.into_raw();
-        0
-    } else {
-        -1
+    if let Err(e) = FilterOperation::Blur(sigma).validate() {
+        return fail(-1, e);
     }
+    apply_filter(handle, |image| filters::blur(image, sigma))
 }
 
-/// Apply sharpening filter
+/// Apply sharpening filter with the default amount and radius
 ///
 /// # Safety
 /// - `handle` must be a valid pointer
 #[no_mangle]
 pub unsafe extern "C" fn image_pipeline_sharpen(handle: *mut ImageHandle) -> i32 {
-    if handle.is_null() {
-        return -1;
-    }
-
-    let h = &mut *handle;
-    if let Some(image) = image::RgbaImage::from_raw(h.width, h.height, h.data.clone()) {
-        let result = filters::sharpen(&image);
-        h.data = result.into_raw();
-        0
-    } else {
-        -1
+    if let Err(e) = FilterOperation::sharpen().validate() {
+        return fail(-1, e);
     }
+    apply_filter(handle, |image| {
+        filters::sharpen(image, SHARPEN_AMOUNT, SHARPEN_RADIUS, 0)
+    })
 }
 
 /// Apply edge detection (Sobel)
@@ -204,21 +308,10 @@ pub unsafe extern "C" fn image_pipeline_sharpen(handle: *mut ImageHandle) -> i32
 /// - `handle` must be a valid pointer
 #[no_mangle]
 pub unsafe extern "C" fn image_pipeline_edge_detect(handle: *mut ImageHandle) -> i32 {
-    if handle.is_null() {
-        return -1;
-    }
-
-    let h = &mut *handle;
-    if let Some(image) = image::RgbaImage::from_raw(h.width, h.height, h.data.clone()) {
-        let result = filters::edge_detect(&image);
-        h.data = result.into_raw();
-        0
-    } else {
-        -1
-    }
+    apply_filter(handle, filters::edge_detect)
 }
 
-/// Resize image
+/// Resize image with Lanczos3 interpolation
 ///
 /// # Safety
 /// - `handle` must be a valid pointer
@@ -228,20 +321,41 @@ pub unsafe extern "C" fn image_pipeline_resize(
     new_width: u32,
     new_height: u32,
 ) -> i32 {
-    if handle.is_null() {
-        return -1;
-    }
+    image_pipeline_resize_with_filter(
+        handle,
+        new_width,
+        new_height,
+        IMAGE_PIPELINE_RESAMPLE_LANCZOS3,
+    )
+}
 
-    let h = &mut *handle;
-    if let Some(image) = image::RgbaImage::from_raw(h.width, h.height, h.data.clone()) {
-        let result = filters::resize(&image, new_width, new_height);
-        h.width = new_width;
-        h.height = new_height;
-        h.data = result.into_raw();
-        0
-    } else {
-        -1
+/// Resize image with one of the `IMAGE_PIPELINE_RESAMPLE_*` filters
+///
+/// Returns -1 for a null handle, an unknown filter code or a size over the
+/// limits of `image_pipeline_set_limits`.
+///
+/// # Safety
+/// - `handle` must be a valid pointer
+#[no_mangle]
+pub unsafe extern "C" fn image_pipeline_resize_with_filter(
+    handle: *mut ImageHandle,
+    new_width: u32,
+    new_height: u32,
+    filter: i32,
+) -> i32 {
+    let filter = match filter {
+        IMAGE_PIPELINE_RESAMPLE_NEAREST => ResampleFilter::Nearest,
+        IMAGE_PIPELINE_RESAMPLE_BILINEAR => ResampleFilter::Bilinear,
+        IMAGE_PIPELINE_RESAMPLE_CATMULL_ROM => ResampleFilter::CatmullRom,
+        IMAGE_PIPELINE_RESAMPLE_LANCZOS3 => ResampleFilter::Lanczos3,
+        code => return fail(-1, format!("unknown resample filter {}", code)),
+    };
+    if let Err(e) = pipeline().check_dimensions(new_width, new_height) {
+        return fail(-1, e);
     }
+    apply_filter(handle, |image| {
+        filters::resize(image, new_width, new_height, filter)
+    })
 }
 
 /// Invert colors
@@ -250,18 +364,7 @@ pub unsafe extern "C" fn image_pipeline_resize(
 /// - `handle` must be a valid pointer
 #[no_mangle]
 pub unsafe extern "C" fn image_pipeline_invert(handle: *mut ImageHandle) -> i32 {
-    if handle.is_null() {
-        return -1;
-    }
-
-    let h = &mut *handle;
-    if let Some(image) = image::RgbaImage::from_raw(h.width, h.height, h.data.clone()) {
-        let result = filters::invert(&image);
-        h.data = result.into_raw();
-        0
-    } else {
-        -1
-    }
+    apply_filter(handle, filters::invert)
 }
 
 /// Apply sepia tone
@@ -270,17 +373,110 @@ pub unsafe extern "C" fn image_pipeline_invert(handle: *mut ImageHandle) -> i32
 /// - `handle` must be a valid pointer
 #[no_mangle]
 pub unsafe extern "C" fn image_pipeline_sepia(handle: *mut ImageHandle) -> i32 {
-    if handle.is_null() {
+    apply_filter(handle, filters::sepia)
+}
+
+/// Pixelate into square blocks
+///
+/// # Safety
+/// - `handle` must be a valid pointer
+/// - `block_size` must be at least 1
+#[no_mangle]
+pub unsafe extern "C" fn image_pipeline_pixelate(handle: *mut ImageHandle, block_size: u32) -> i32 {
+    if block_size == 0 {
+        return fail(-1, "block_size must be at least 1");
+    }
+    apply_filter(handle, |image| filters::pixelate(image, block_size))
+}
+
+/// Apply a JSON pipeline spec, e.g. `[{"type": "blur", "sigma": 2.0}]`
+///
+/// Returns -1 on invalid arguments or an invalid spec and -3 if processing
+/// fails, including images over the limits of `image_pipeline_set_limits`.
+///
+/// # Safety
+/// - `handle` must be a valid pointer
+/// - `spec_json` must be a valid NUL-terminated UTF-8 string
+#[no_mangle]
+pub unsafe extern "C" fn image_pipeline_apply_spec(
+    handle: *mut ImageHandle,
+    spec_json: *const c_char,
+) -> i32 {
+    image_pipeline_apply_spec_cancellable(handle, spec_json, std::ptr::null())
+}
+
+/// Apply a JSON pipeline spec that can be aborted from another thread
+///
+/// Returns -1 on invalid arguments or an invalid spec, -3 if processing fails
+/// and -4 if the token was cancelled. The image is unchanged unless 0 is returned.
+///
+/// # Safety
+/// - `handle` must be a valid pointer
+/// - `spec_json` must be a valid NUL-terminated UTF-8 string
+/// - `token` must be null or a pointer returned by `image_pipeline_token_create`
+#[no_mangle]
+pub unsafe extern "C" fn image_pipeline_apply_spec_cancellable(
+    handle: *mut ImageHandle,
+    spec_json: *const c_char,
+    token: *const CancellationToken,
+) -> i32 {
+    if spec_json.is_null() {
+        return fail(-1, "spec_json is null");
+    }
+    let json = match CStr::from_ptr(spec_json).to_str() {
+        Ok(json) => json,
+        Err(_) => return fail(-1, "spec_json is not valid UTF-8"),
+    };
+    let operations = match PipelineSpec::from_json(json).and_then(|spec| spec.compile()) {
+        Ok(operations) => operations,
+        Err(e) => return fail(-1, e),
+    };
+    let Some(image) = image_of(handle) else {
         return -1;
+    };
+
+    let never_cancelled = CancellationToken::new();
+    let token = token.as_ref().unwrap_or(&never_cancelled);
+
+    match pipeline().process_cancellable(&image, &operations, token) {
+        Ok(result) => {
+            let h = &mut *handle;
+            h.width = result.width();
+            h.height = result.height();
+            h.data = result.into_raw();
+            0
+        }
+        Err(e @ PipelineError::Cancelled) => fail(-4, e),
+        Err(e) => fail(-3, e),
     }
+}
 
-    let h = &mut *handle;
-    if let Some(image) = image::RgbaImage::from_raw(h.width, h.height, h.data.clone()) {
-        let result = filters::sepia(&image);
-        h.data = result.into_raw();
-        0
-    } else {
-        -1
+/// Create a cancellation token for `image_pipeline_apply_spec_cancellable`
+#[no_mangle]
+pub extern "C" fn image_pipeline_token_create() -> *mut CancellationToken {
+    Box::into_raw(Box::new(CancellationToken::new()))
+}
+
+/// Request cancellation; safe to call from any thread while processing runs
+///
+/// # Safety
+/// - `token` must be a valid pointer returned by `image_pipeline_token_create`
+#[no_mangle]
+pub unsafe extern "C" fn image_pipeline_cancel(token: *const CancellationToken) {
+    if let Some(token) = token.as_ref() {
+        token.cancel();
+    }
+}
+
+/// Free a cancellation token
+///
+/// # Safety
+/// - `token` must be a valid pointer returned by `image_pipeline_token_create`
+/// - No operation may still be using the token
+#[no_mangle]
+pub unsafe extern "C" fn image_pipeline_token_free(token: *mut CancellationToken) {
+    if !token.is_null() {
+        drop(Box::from_raw(token));
     }
 }
 
@@ -296,21 +492,264 @@ pub unsafe extern "C" fn image_pipeline_copy_to(
     output_size: usize,
 ) -> i32 {
     if handle.is_null() || output.is_null() {
-        return -1;
+        return fail(-1, "handle or output is null");
     }
 
     let h = &*handle;
+    if rgba_len(h.width, h.height).ok() != Some(h.data.len()) {
+        return fail(-1, "handle data does not match its dimensions");
+    }
     if output_size < h.data.len() {
-        return -2; // Buffer too small
+        // Buffer too small
+        return fail(
+            -2,
+            format!(
+                "output holds {} bytes but the image needs {}",
+                output_size,
+                h.data.len()
+            ),
+        );
     }
 
     std::ptr::copy_nonoverlapping(h.data.as_ptr(), output, h.data.len());
     0
 }
 
+/// Encode the image to PNG, JPEG or WebP
+///
+/// On success `*out_buf` points to a newly allocated buffer of `*out_len` bytes,
+/// which must be released with `image_pipeline_free_buffer`.
+/// Returns -1 on invalid arguments and -3 if encoding fails.
+///
+/// # Safety
+/// - `handle` must be a valid pointer
+/// - `out_buf` and `out_len` must be valid pointers to writable memory
+#[no_mangle]
+pub unsafe extern "C" fn image_pipeline_encode(
+    handle: *const ImageHandle,
+    format: i32,
+    quality: u8,
+    out_buf: *mut *mut u8,
+    out_len: *mut usize,
+) -> i32 {
+    if out_buf.is_null() || out_len.is_null() {
+        return fail(-1, "out_buf or out_len is null");
+    }
+
+    let format = match format {
+        IMAGE_PIPELINE_FORMAT_PNG => OutputFormat::Png,
+        IMAGE_PIPELINE_FORMAT_JPEG => OutputFormat::Jpeg,
+        IMAGE_PIPELINE_FORMAT_WEBP => OutputFormat::WebP,
+        #[cfg(feature = "avif")]
+        IMAGE_PIPELINE_FORMAT_AVIF => OutputFormat::Avif,
+        code => return fail(-1, format!("unknown or unsupported format {}", code)),
+    };
+    let Some(image) = image_of(handle) else {
+        return -1;
+    };
+
+    match ImagePipeline::encode(&image, format, quality) {
+        Ok(bytes) => {
+            let bytes = bytes.into_boxed_slice();
+            *out_len = bytes.len();
+            *out_buf = Box::into_raw(bytes) as *mut u8;
+            0
+        }
+        Err(e) => fail(-3, e),
+    }
+}
+
+/// Free a buffer returned by `image_pipeline_encode`
+///
+/// # Safety
+/// - `buf` and `len` must come from a single successful `image_pipeline_encode` call
+#[no_mangle]
+pub unsafe extern "C" fn image_pipeline_free_buffer(buf: *mut u8, len: usize) {
+    if !buf.is_null() {
+        drop(Box::from_raw(std::ptr::slice_from_raw_parts_mut(buf, len)));
+    }
+}
+
+/// Compute a 64-bit perceptual hash with one of the `IMAGE_PIPELINE_HASH_*` algorithms
+///
+/// Returns -1 for invalid arguments or an unknown algorithm code.
+///
+/// # Safety
+/// - `handle` must be a valid pointer
+/// - `out_hash` must be a valid pointer to writable memory
+#[no_mangle]
+pub unsafe extern "C" fn image_pipeline_perceptual_hash(
+    handle: *const ImageHandle,
+    algorithm: i32,
+    out_hash: *mut u64,
+) -> i32 {
+    if out_hash.is_null() {
+        return fail(-1, "out_hash is null");
+    }
+
+    let hash = match algorithm {
+        IMAGE_PIPELINE_HASH_AVERAGE => analysis::ahash,
+        IMAGE_PIPELINE_HASH_DIFFERENCE => analysis::dhash,
+        IMAGE_PIPELINE_HASH_DCT => analysis::phash,
+        code => return fail(-1, format!("unknown hash algorithm {}", code)),
+    };
+    let Some(image) = image_of(handle) else {
+        return -1;
+    };
+    *out_hash = hash(&image);
+    0
+}
+
+/// Number of differing bits between two perceptual hashes
+#[no_mangle]
+pub extern "C" fn image_pipeline_hamming_distance(a: u64, b: u64) -> u32 {
+    analysis::hamming_distance(a, b)
+}
+
+/// Message describing the last failure on this thread, or null if none
+///
+/// Set by every function that returns null or a negative code. The string
+/// stays valid until the next failing call on the same thread.
+#[no_mangle]
+pub extern "C" fn image_pipeline_last_error() -> *const c_char {
+    LAST_ERROR.with(|last| {
+        last.borrow()
+            .as_ref()
+            .map_or(std::ptr::null(), |message| message.as_ptr())
+    })
+}
+
 /// Get version string
 #[no_mangle]
-pub extern "C" fn image_pipeline_version() -> *const std::ffi::c_char {
+pub extern "C" fn image_pipeline_version() -> *const c_char {
     static VERSION: &[u8] = b"0.1.0\0";
-    VERSION.as_ptr() as *const std::ffi::c_char
+    VERSION.as_ptr() as *const c_char
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgba;
+
+    fn last_error() -> String {
+        let message = image_pipeline_last_error();
+        assert!(!message.is_null());
+        unsafe { CStr::from_ptr(message) }
+            .to_string_lossy()
+            .into_owned()
+    }
+
+    fn png(width: u32, height: u32) -> Vec<u8> {
+        let image = RgbaImage::from_pixel(width, height, Rgba([10, 20, 30, 255]));
+        ImagePipeline::encode_to_png(&image).unwrap()
+    }
+
+    #[test]
+    fn test_create_from_encoded() {
+        let bytes = png(6, 4);
+        unsafe {
+            let handle = image_pipeline_create_from_encoded(bytes.as_ptr(), bytes.len());
+            assert!(!handle.is_null());
+            assert_eq!(image_pipeline_get_width(handle), 6);
+            assert_eq!(image_pipeline_get_data_size(handle), 6 * 4 * 4);
+            image_pipeline_free(handle);
+
+            assert!(image_pipeline_create_from_encoded(std::ptr::null(), 0).is_null());
+            assert!(last_error().contains("null"));
+            let garbage = b"not an image";
+            assert!(image_pipeline_create_from_encoded(garbage.as_ptr(), garbage.len()).is_null());
+            assert!(!last_error().is_empty());
+        }
+    }
+
+    #[test]
+    fn test_create_from_encoded_refuses_bombs() {
+        // A tiny file claiming a 60000x60000 image, over the default pixel limit
+        let mut bomb = png(1, 1);
+        bomb[16..20].copy_from_slice(&60_000u32.to_be_bytes());
+        bomb[20..24].copy_from_slice(&60_000u32.to_be_bytes());
+        let mut crc = flate2::Crc::new();
+        crc.update(&bomb[12..29]);
+        bomb[29..33].copy_from_slice(&crc.sum().to_be_bytes());
+        unsafe {
+            assert!(image_pipeline_create_from_encoded(bomb.as_ptr(), bomb.len()).is_null());
+        }
+        assert!(
+            last_error().contains("larger than allowed"),
+            "{}",
+            last_error()
+        );
+    }
+
+    #[test]
+    fn test_encode_round_trip_and_errors() {
+        let image = RgbaImage::from_pixel(3, 2, Rgba([200, 100, 50, 255]));
+        unsafe {
+            let handle = image_pipeline_create(image.as_ptr(), 3, 2);
+            let (mut buf, mut len) = (std::ptr::null_mut(), 0);
+            assert_eq!(
+                image_pipeline_encode(handle, IMAGE_PIPELINE_FORMAT_PNG, 90, &mut buf, &mut len),
+                0
+            );
+            let decoded = ImagePipeline::load_from_bytes(slice::from_raw_parts(buf, len)).unwrap();
+            assert!(decoded == image);
+            image_pipeline_free_buffer(buf, len);
+
+            assert_eq!(
+                image_pipeline_encode(handle, 99, 90, &mut buf, &mut len),
+                -1
+            );
+            assert!(last_error().contains("format 99"));
+            assert_eq!(
+                image_pipeline_encode(
+                    std::ptr::null(),
+                    IMAGE_PIPELINE_FORMAT_PNG,
+                    90,
+                    &mut buf,
+                    &mut len
+                ),
+                -1
+            );
+            assert_eq!(last_error(), "handle is null");
+            image_pipeline_free(handle);
+        }
+    }
+
+    #[test]
+    fn test_apply_spec_reports_errors() {
+        let image = RgbaImage::from_pixel(4, 4, Rgba([10, 20, 30, 255]));
+        unsafe {
+            let handle = image_pipeline_create(image.as_ptr(), 4, 4);
+            let spec = CString::new(r#"[{"type": "resize", "width": 2, "height": 3}]"#).unwrap();
+            assert_eq!(image_pipeline_apply_spec(handle, spec.as_ptr()), 0);
+            assert_eq!(image_pipeline_get_height(handle), 3);
+
+            let invalid = CString::new(r#"[{"type": "blur"}]"#).unwrap();
+            assert_eq!(image_pipeline_apply_spec(handle, invalid.as_ptr()), -1);
+            assert!(last_error().contains("sigma"), "{}", last_error());
+            assert_eq!(image_pipeline_grayscale(std::ptr::null_mut()), -1);
+            assert_eq!(last_error(), "handle is null");
+            image_pipeline_free(handle);
+        }
+    }
+
+    #[test]
+    fn test_filters_reject_invalid_parameters() {
+        let image = RgbaImage::from_pixel(4, 4, Rgba([10, 20, 30, 255]));
+        unsafe {
+            let handle = image_pipeline_create(image.as_ptr(), 4, 4);
+            for sigma in [-3.0, 0.0, f32::NAN] {
+                assert_eq!(image_pipeline_blur(handle, sigma), -1);
+                assert!(last_error().contains("sigma"), "{}", last_error());
+            }
+            assert_eq!(image_pipeline_brightness(handle, 2.0), -1);
+            assert_eq!(image_pipeline_contrast(handle, -1.0), -1);
+            assert_eq!(image_pipeline_get_data_size(handle), 4 * 4 * 4);
+            assert_eq!((*handle).data, image.as_raw().as_slice());
+
+            assert_eq!(image_pipeline_blur(handle, 1.0), 0);
+            assert_eq!(image_pipeline_sharpen(handle), 0);
+            image_pipeline_free(handle);
+        }
+    }
 }
@@ -0,0 +1,306 @@
+//! Operations as objects: the `Filter` trait and the buffer filters work on
+//!
+//! Every `FilterOperation` is a `Filter` backed by one of the built-in filters
+//! in `ops`, and applications can add their own with
+//! `ImagePipeline::register_filter` (a fixed filter) or
+//! `ImagePipeline::register_filter_factory` (built from params), then refer to
+//! them from code as `FilterOperation::Custom` or from a spec document:
+//!
+//! ```json
+//! [{"type": "custom", "name": "vignette", "params": {"strength": 0.4}}]
+//! ```
+
+use crate::registry::OperationInfo;
+use crate::{CancellationToken, FilterOperation, PipelineError, Result};
+use image::{Rgba, RgbaImage};
+use rayon::prelude::*;
+use serde_json::Value;
+use std::sync::Arc;
+
+/// Image being processed, which filters modify in place or replace
+pub struct PixelBuffer {
+    image: RgbaImage,
+    token: CancellationToken,
+}
+
+impl PixelBuffer {
+    /// Wrap an image for processing
+    pub fn new(image: RgbaImage) -> Self {
+        Self::with_token(image, &CancellationToken::new())
+    }
+
+    /// Wrap an image, letting long filters abort once `token` is cancelled
+    pub fn with_token(image: RgbaImage, token: &CancellationToken) -> Self {
+        Self {
+            image,
+            token: token.clone(),
+        }
+    }
+
+    /// Width and height in pixels
+    pub fn dimensions(&self) -> (u32, u32) {
+        self.image.dimensions()
+    }
+
+    /// Current contents
+    pub fn image(&self) -> &RgbaImage {
+        &self.image
+    }
+
+    /// Current contents, for editing in place
+    pub fn image_mut(&mut self) -> &mut RgbaImage {
+        &mut self.image
+    }
+
+    /// Raw RGBA bytes, row by row
+    pub fn pixels_mut(&mut self) -> &mut [u8] {
+        &mut self.image
+    }
+
+    /// Swap in a new result, which may have a different size
+    pub fn replace(&mut self, image: RgbaImage) {
+        self.image = image;
+    }
+
+    /// Token that filters should check in long loops
+    pub fn token(&self) -> &CancellationToken {
+        &self.token
+    }
+
+    /// Take the processed image
+    pub fn into_image(self) -> RgbaImage {
+        self.image
+    }
+}
+
+/// A configured image operation
+pub trait Filter: Send + Sync {
+    /// Short identifier used in error messages
+    fn name(&self) -> &str;
+
+    /// Check the configuration before the pipeline starts
+    fn validate(&self) -> Result<()> {
+        Ok(())
+    }
+
+    /// Whether the output size can differ from the input
+    fn changes_size(&self) -> bool {
+        false
+    }
+
+    /// Parameters the filter takes in a spec document, for front-ends
+    ///
+    /// Built-in operations describe themselves here and `registry` lists
+    /// them; custom filters can leave it at `None`.
+    fn schema(&self) -> Option<OperationInfo> {
+        None
+    }
+
+    /// Process `buffer`
+    fn apply(&self, buffer: &mut PixelBuffer) -> Result<()>;
+
+    /// Process a copy of `image`, aborting once `token` is cancelled
+    ///
+    /// Filters that build a new image anyway override this to skip the copy.
+    fn apply_to(&self, image: &RgbaImage, token: &CancellationToken) -> Result<RgbaImage> {
+        let mut buffer = PixelBuffer::with_token(image.clone(), token);
+        self.apply(&mut buffer)?;
+        Ok(buffer.into_image())
+    }
+}
+
+/// Builds a filter from the params of a `FilterOperation::Custom`
+pub type FilterFactory = Box<dyn Fn(&Value) -> Result<Box<dyn Filter>> + Send + Sync>;
+
+// A filter registered with a pipeline
+pub(crate) enum Registered {
+    Fixed(Arc<dyn Filter>),
+    Factory(FilterFactory),
+}
+
+impl Registered {
+    // The filter to run for a custom operation with `params`
+    pub(crate) fn resolve(&self, params: &Value) -> Result<Arc<dyn Filter>> {
+        match self {
+            Registered::Fixed(filter) => {
+                let empty = params.is_null() || params.as_object().is_some_and(|p| p.is_empty());
+                if !empty {
+                    return Err(PipelineError::InvalidParameter(format!(
+                        "{} does not take params",
+                        filter.name()
+                    )));
+                }
+                Ok(Arc::clone(filter))
+            }
+            Registered::Factory(factory) => factory(params).map(Arc::from),
+        }
+    }
+}
+
+impl Filter for FilterOperation {
+    fn name(&self) -> &str {
+        FilterOperation::name(self)
+    }
+
+    fn validate(&self) -> Result<()> {
+        FilterOperation::validate(self)
+    }
+
+    fn changes_size(&self) -> bool {
+        FilterOperation::changes_size(self)
+    }
+
+    fn schema(&self) -> Option<OperationInfo> {
+        self.to_filter().schema()
+    }
+
+    fn apply(&self, buffer: &mut PixelBuffer) -> Result<()> {
+        self.to_filter().apply(buffer)
+    }
+
+    fn apply_to(&self, image: &RgbaImage, token: &CancellationToken) -> Result<RgbaImage> {
+        self.to_filter().apply_to(image, token)
+    }
+}
+
+/// Filter that maps every pixel independently, in parallel
+pub struct PixelFilter<F> {
+    name: String,
+    map: F,
+}
+
+impl<F> PixelFilter<F>
+where
+    F: Fn(&mut Rgba<u8>) + Send + Sync,
+{
+    /// Wrap a function that edits one pixel
+    pub fn new(name: impl Into<String>, map: F) -> Self {
+        Self {
+            name: name.into(),
+            map,
+        }
+    }
+}
+
+impl<F> Filter for PixelFilter<F>
+where
+    F: Fn(&mut Rgba<u8>) + Send + Sync,
+{
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn apply(&self, buffer: &mut PixelBuffer) -> Result<()> {
+        let row_len = (buffer.dimensions().0 as usize * 4).max(4);
+        buffer.pixels_mut().par_chunks_mut(row_len).for_each(|row| {
+            for chunk in row.chunks_exact_mut(4) {
+                let mut pixel = Rgba([chunk[0], chunk[1], chunk[2], chunk[3]]);
+                (self.map)(&mut pixel);
+                chunk.copy_from_slice(&pixel.0);
+            }
+        });
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ImagePipeline, PipelineSpec, Region};
+
+    // Factory for a filter setting the red channel to `params.red`
+    fn set_red(params: &Value) -> Result<Box<dyn Filter>> {
+        match params["red"].as_u64() {
+            Some(red) if red <= 255 => Ok(Box::new(PixelFilter::new(
+                "set_red",
+                move |p: &mut Rgba<u8>| p[0] = red as u8,
+            ))),
+            _ => Err(PipelineError::InvalidParameter(
+                "red must be an integer from 0 to 255".to_string(),
+            )),
+        }
+    }
+
+    #[test]
+    fn test_custom_filter_from_spec() {
+        let mut pipeline = ImagePipeline::new();
+        pipeline.register_filter_factory("set_red", Box::new(set_red));
+        pipeline.register_filter(
+            "halve_green",
+            Box::new(PixelFilter::new("halve_green", |p: &mut Rgba<u8>| {
+                p[1] /= 2
+            })),
+        );
+
+        let operations = PipelineSpec::from_json(
+            r#"[{"type": "custom", "name": "set_red", "params": {"red": 9}},
+                {"type": "custom", "name": "halve_green"}]"#,
+        )
+        .and_then(|spec| spec.compile())
+        .unwrap();
+        let image = RgbaImage::from_pixel(4, 4, Rgba([200, 100, 50, 255]));
+        let result = pipeline.process(&image, &operations).unwrap();
+        assert_eq!(*result.get_pixel(2, 2), Rgba([9, 50, 50, 255]));
+
+        let masked = FilterOperation::Masked {
+            region: Region::Rect {
+                x: 0,
+                y: 0,
+                width: 2,
+                height: 4,
+            },
+            op: Box::new(operations[0].clone()),
+        };
+        let result = pipeline.process(&image, &[masked]).unwrap();
+        assert_eq!(result.get_pixel(0, 0)[0], 9);
+        assert_eq!(result.get_pixel(3, 0)[0], 200);
+    }
+
+    #[test]
+    fn test_unknown_and_invalid_custom_filters() {
+        let mut pipeline = ImagePipeline::new();
+        pipeline.register_filter_factory("set_red", Box::new(set_red));
+        pipeline.register_filter(
+            "invert",
+            Box::new(PixelFilter::new("invert", |p: &mut Rgba<u8>| p[0] = !p[0])),
+        );
+        let image = RgbaImage::new(2, 2);
+
+        for (name, params) in [
+            ("missing", Value::Null),
+            ("set_red", serde_json::json!({"red": 300})),
+            ("invert", serde_json::json!({"unexpected": 1})),
+        ] {
+            let custom = FilterOperation::Custom {
+                name: name.to_string(),
+                params,
+            };
+            assert!(matches!(
+                pipeline.process(&image, &[FilterOperation::Invert, custom]),
+                Err(PipelineError::InvalidParameter(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn test_operations_are_filters() {
+        let image = RgbaImage::from_pixel(8, 6, Rgba([10, 20, 30, 255]));
+        let filters: Vec<Box<dyn Filter>> = vec![
+            Box::new(FilterOperation::Invert),
+            Box::new(FilterOperation::Resize {
+                width: 4,
+                height: 3,
+                filter: crate::ResampleFilter::Nearest,
+            }),
+        ];
+        assert!(filters[1].changes_size());
+        assert!(Filter::validate(&FilterOperation::Blur(-1.0)).is_err());
+
+        let mut buffer = PixelBuffer::new(image);
+        for filter in &filters {
+            filter.apply(&mut buffer).unwrap();
+        }
+        assert_eq!(buffer.dimensions(), (4, 3));
+        assert_eq!(*buffer.image().get_pixel(0, 0), Rgba([245, 235, 225, 255]));
+    }
+}
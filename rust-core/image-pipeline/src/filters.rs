@@ -1,18 +1,88 @@
-use image::{ImageBuffer, Rgba, RgbaImage};
+use crate::alpha::is_opaque;
+use crate::color::{hue_distance, Hsv};
+use crate::geometry::{self, Interpolation, Quad, Transform};
+use crate::{
+    rgba_len, CancellationToken, Lut256, Model, PipelineError, Postprocess, Preprocess, Result,
+};
+use image::{ImageBuffer, Rgba, Rgba32FImage, RgbaImage};
 use rayon::prelude::*;
+use std::f32::consts::{PI, TAU};
+use std::sync::Arc;
+
+/// Parameter schemas of every operation, see `registry`
+pub use crate::registry::registry;
+
+/// Weighting used to turn RGB into gray
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum GrayscaleMode {
+    /// ITU-R BT.601 luma, as most other tools compute it
+    Bt601,
+    /// ITU-R BT.709 luma
+    #[default]
+    Bt709,
+    /// Mean of the three channels
+    Average,
+    /// Red channel only
+    Red,
+    /// Green channel only
+    Green,
+    /// Blue channel only
+    Blue,
+}
+
+impl GrayscaleMode {
+    /// Parse a mode name, e.g. "bt601" or "average"
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "bt601" => Some(GrayscaleMode::Bt601),
+            "bt709" => Some(GrayscaleMode::Bt709),
+            "average" => Some(GrayscaleMode::Average),
+            "red" => Some(GrayscaleMode::Red),
+            "green" => Some(GrayscaleMode::Green),
+            "blue" => Some(GrayscaleMode::Blue),
+            _ => None,
+        }
+    }
+
+    /// Weights of red, green and blue
+    pub fn weights(self) -> [f32; 3] {
+        match self {
+            GrayscaleMode::Bt601 => [0.299, 0.587, 0.114],
+            GrayscaleMode::Bt709 => [0.2126, 0.7152, 0.0722],
+            GrayscaleMode::Average => [1.0 / 3.0; 3],
+            GrayscaleMode::Red => [1.0, 0.0, 0.0],
+            GrayscaleMode::Green => [0.0, 1.0, 0.0],
+            GrayscaleMode::Blue => [0.0, 0.0, 1.0],
+        }
+    }
+
+    /// The same weighting as a color matrix
+    pub fn color_matrix(self) -> ColorMatrix {
+        rgb_color_matrix([self.weights(); 3])
+    }
+
+    /// Gray value of one pixel; every grayscale path computes it this way
+    #[inline]
+    pub fn gray(self, r: u8, g: u8, b: u8) -> u8 {
+        let [wr, wg, wb] = self.weights();
+        (wr * r as f32 + wg * g as f32 + wb * b as f32).clamp(0.0, 255.0) as u8
+    }
+}
 
 /// Convert image to grayscale using luminance formula
 /// Uses ITU-R BT.709 coefficients: 0.2126*R + 0.7152*G + 0.0722*B
 pub fn grayscale(image: &RgbaImage) -> RgbaImage {
+    grayscale_with(image, GrayscaleMode::Bt709)
+}
+
+/// Convert image to grayscale with the given weighting
+pub fn grayscale_with(image: &RgbaImage, mode: GrayscaleMode) -> RgbaImage {
     let (width, height) = image.dimensions();
     let pixels: Vec<u8> = image
         .as_raw()
         .par_chunks(4)
         .flat_map(|pixel| {
-            let r = pixel[0] as f32;
-            let g = pixel[1] as f32;
-            let b = pixel[2] as f32;
-            let gray = (0.2126 * r + 0.7152 * g + 0.0722 * b) as u8;
+            let gray = mode.gray(pixel[0], pixel[1], pixel[2]);
             [gray, gray, gray, pixel[3]]
         })
         .collect();
@@ -23,70 +93,75 @@ pub fn grayscale(image: &RgbaImage) -> RgbaImage {
 /// Adjust brightness of the image
 /// value: -1.0 (dark) to 1.0 (bright)
 pub fn brightness(image: &RgbaImage, value: f32) -> RgbaImage {
-    let (width, height) = image.dimensions();
-    let adjustment = (value * 255.0) as i32;
-
-    let pixels: Vec<u8> = image
-        .as_raw()
-        .par_chunks(4)
-        .flat_map(|pixel| {
-            [
-                ((pixel[0] as i32 + adjustment).clamp(0, 255)) as u8,
-                ((pixel[1] as i32 + adjustment).clamp(0, 255)) as u8,
-                ((pixel[2] as i32 + adjustment).clamp(0, 255)) as u8,
-                pixel[3],
-            ]
-        })
-        .collect();
-
-    ImageBuffer::from_raw(width, height, pixels).unwrap()
+    Lut256::brightness(value).apply(image)
 }
 
 /// Adjust contrast of the image
 /// value: 0.0 (no contrast) to 2.0+ (high contrast)
 pub fn contrast(image: &RgbaImage, value: f32) -> RgbaImage {
-    let (width, height) = image.dimensions();
-    let factor = value;
-
-    let pixels: Vec<u8> = image
-        .as_raw()
-        .par_chunks(4)
-        .flat_map(|pixel| {
-            [
-                ((((pixel[0] as f32 - 128.0) * factor) + 128.0).clamp(0.0, 255.0)) as u8,
-                ((((pixel[1] as f32 - 128.0) * factor) + 128.0).clamp(0.0, 255.0)) as u8,
-                ((((pixel[2] as f32 - 128.0) * factor) + 128.0).clamp(0.0, 255.0)) as u8,
-                pixel[3],
-            ]
-        })
-        .collect();
+    Lut256::contrast(value).apply(image)
+}
 
-    ImageBuffer::from_raw(width, height, pixels).unwrap()
+/// Apply gamma correction
+/// gamma: > 0.0, values above 1.0 brighten midtones and values below darken them
+pub fn gamma(image: &RgbaImage, gamma: f32) -> RgbaImage {
+    Lut256::gamma(gamma).apply(image)
 }
 
 /// Apply Gaussian blur with given sigma
 pub fn blur(image: &RgbaImage, sigma: f32) -> RgbaImage {
-    let (_width, _height) = image.dimensions();
+    blur_cancellable(image, sigma, &CancellationToken::new()).expect("token is never cancelled")
+}
+
+/// Gaussian blur that checks `token` once per row and aborts with `PipelineError::Cancelled`
+pub fn blur_cancellable(
+    image: &RgbaImage,
+    sigma: f32,
+    token: &CancellationToken,
+) -> Result<RgbaImage> {
     let radius = (sigma * 3.0).ceil() as i32;
     let kernel = create_gaussian_kernel(radius, sigma);
 
     // Horizontal pass
-    let horizontal = apply_convolution_1d_horizontal(image, &kernel);
+    let horizontal = apply_convolution_1d_horizontal(image, &kernel, token)?;
     // Vertical pass
-    apply_convolution_1d_vertical(&horizontal, &kernel)
+    apply_convolution_1d_vertical(&horizontal, &kernel, token)
+}
+
+// Gaussian blur weighting colors by alpha, for images with straight alpha.
+// Colors are premultiplied in f32 and divided back before rounding, so pixels
+// with little alpha keep their colors.
+pub(crate) fn blur_straight(
+    image: &RgbaImage,
+    sigma: f32,
+    token: &CancellationToken,
+) -> Result<RgbaImage> {
+    if is_opaque(image) {
+        return blur_cancellable(image, sigma, token);
+    }
+    let (width, height) = image.dimensions();
+    let radius = (sigma * 3.0).ceil() as i32;
+    let kernel = create_gaussian_kernel(radius, sigma);
+
+    let mut buffer = premultiply_f32(image);
+    convolve_rows(&mut buffer, width, &kernel, token)?;
+    let mut transposed = transpose(&buffer, width, height);
+    convolve_rows(&mut transposed, height, &kernel, token)?;
+    let buffer = transpose(&transposed, height, width);
+    Ok(unpremultiply_f32(&buffer, width, height))
 }
 
 /// Create 1D Gaussian kernel
-fn create_gaussian_kernel(radius: i32, sigma: f32) -> Vec<f32> {
+pub(crate) fn create_gaussian_kernel(radius: i32, sigma: f32) -> Vec<f32> {
     let size = (radius * 2 + 1) as usize;
     let mut kernel = vec![0.0f32; size];
     let sigma2 = 2.0 * sigma * sigma;
     let mut sum = 0.0;
 
-    for i in 0..size {
+    for (i, k) in kernel.iter_mut().enumerate() {
         let x = (i as i32 - radius) as f32;
-        kernel[i] = (-x * x / sigma2).exp();
-        sum += kernel[i];
+        *k = (-x * x / sigma2).exp();
+        sum += *k;
     }
 
     // Normalize
@@ -98,85 +173,324 @@ fn create_gaussian_kernel(radius: i32, sigma: f32) -> Vec<f32> {
 }
 
 /// Apply 1D convolution horizontally (parallel over rows)
-fn apply_convolution_1d_horizontal(image: &RgbaImage, kernel: &[f32]) -> RgbaImage {
+fn apply_convolution_1d_horizontal(
+    image: &RgbaImage,
+    kernel: &[f32],
+    token: &CancellationToken,
+) -> Result<RgbaImage> {
     let (width, height) = image.dimensions();
     let radius = (kernel.len() / 2) as i32;
+    let stride = width as usize * 4;
+    let mut result = vec![0u8; stride * height as usize];
+    if stride == 0 {
+        return Ok(ImageBuffer::from_raw(width, height, result).unwrap());
+    }
 
-    let rows: Vec<Vec<u8>> = (0..height)
-        .into_par_iter()
-        .map(|y| {
-            let mut row = Vec::with_capacity((width * 4) as usize);
-            for x in 0..width {
-                let mut r = 0.0f32;
-                let mut g = 0.0f32;
-                let mut b = 0.0f32;
-                let mut a = 0.0f32;
-
+    result
+        .par_chunks_mut(stride)
+        .zip(image.as_raw().par_chunks(stride))
+        .try_for_each(|(row, source)| -> Result<()> {
+            token.check()?;
+            for x in 0..width as i32 {
+                let mut sum = [0.0f32; 4];
                 for (i, &weight) in kernel.iter().enumerate() {
-                    let sample_x = (x as i32 + i as i32 - radius).clamp(0, width as i32 - 1) as u32;
-                    let pixel = image.get_pixel(sample_x, y);
-                    r += pixel[0] as f32 * weight;
-                    g += pixel[1] as f32 * weight;
-                    b += pixel[2] as f32 * weight;
-                    a += pixel[3] as f32 * weight;
+                    let sample_x = (x + i as i32 - radius).clamp(0, width as i32 - 1) as usize;
+                    let pixel = &source[sample_x * 4..sample_x * 4 + 4];
+                    for c in 0..4 {
+                        sum[c] += pixel[c] as f32 * weight;
+                    }
+                }
+                let out = &mut row[x as usize * 4..x as usize * 4 + 4];
+                for c in 0..4 {
+                    out[c] = sum[c].clamp(0.0, 255.0) as u8;
                 }
-
-                row.extend_from_slice(&[
-                    r.clamp(0.0, 255.0) as u8,
-                    g.clamp(0.0, 255.0) as u8,
-                    b.clamp(0.0, 255.0) as u8,
-                    a.clamp(0.0, 255.0) as u8,
-                ]);
             }
-            row
-        })
-        .collect();
+            Ok(())
+        })?;
 
-    let pixels: Vec<u8> = rows.into_iter().flatten().collect();
-    ImageBuffer::from_raw(width, height, pixels).unwrap()
+    Ok(ImageBuffer::from_raw(width, height, result).unwrap())
 }
 
-/// Apply 1D convolution vertically (parallel over columns)
-fn apply_convolution_1d_vertical(image: &RgbaImage, kernel: &[f32]) -> RgbaImage {
+/// Apply 1D convolution vertically (parallel over rows, reading whole source rows)
+fn apply_convolution_1d_vertical(
+    image: &RgbaImage,
+    kernel: &[f32],
+    token: &CancellationToken,
+) -> Result<RgbaImage> {
     let (width, height) = image.dimensions();
     let radius = (kernel.len() / 2) as i32;
-
-    let mut result = vec![0u8; (width * height * 4) as usize];
+    let stride = width as usize * 4;
+    let source = image.as_raw();
+    let mut result = vec![0u8; stride * height as usize];
+    if stride == 0 {
+        return Ok(ImageBuffer::from_raw(width, height, result).unwrap());
+    }
 
     result
-        .par_chunks_mut((width * 4) as usize)
+        .par_chunks_mut(stride)
         .enumerate()
-        .for_each(|(y, row)| {
-            for x in 0..width {
-                let mut r = 0.0f32;
-                let mut g = 0.0f32;
-                let mut b = 0.0f32;
-                let mut a = 0.0f32;
+        .try_for_each(|(y, row)| -> Result<()> {
+            token.check()?;
+            // Each tap adds a whole source row, so every pixel still sums its
+            // taps in kernel order
+            let mut sums = vec![0.0f32; stride];
+            for (i, &weight) in kernel.iter().enumerate() {
+                let sample_y = (y as i32 + i as i32 - radius).clamp(0, height as i32 - 1) as usize;
+                let source_row = &source[sample_y * stride..(sample_y + 1) * stride];
+                for (sum, &value) in sums.iter_mut().zip(source_row) {
+                    *sum += value as f32 * weight;
+                }
+            }
+            for (out, sum) in row.iter_mut().zip(sums) {
+                *out = sum.clamp(0.0, 255.0) as u8;
+            }
+            Ok(())
+        })?;
 
+    Ok(ImageBuffer::from_raw(width, height, result).unwrap())
+}
+
+// Convolve every RGBA row of `buffer` with `kernel`, extending the edge pixels
+// outward
+fn convolve_rows(
+    buffer: &mut [f32],
+    width: u32,
+    kernel: &[f32],
+    token: &CancellationToken,
+) -> Result<()> {
+    let radius = (kernel.len() / 2) as i32;
+    buffer
+        .par_chunks_mut(width as usize * 4)
+        .try_for_each(|row| -> Result<()> {
+            token.check()?;
+            let source = row.to_vec();
+            for x in 0..width as i32 {
+                let mut sum = [0.0f32; 4];
                 for (i, &weight) in kernel.iter().enumerate() {
-                    let sample_y =
-                        (y as i32 + i as i32 - radius).clamp(0, height as i32 - 1) as u32;
-                    let pixel = image.get_pixel(x, sample_y);
-                    r += pixel[0] as f32 * weight;
-                    g += pixel[1] as f32 * weight;
-                    b += pixel[2] as f32 * weight;
-                    a += pixel[3] as f32 * weight;
+                    let sample_x = (x + i as i32 - radius).clamp(0, width as i32 - 1) as usize;
+                    let pixel = &source[sample_x * 4..sample_x * 4 + 4];
+                    for c in 0..4 {
+                        sum[c] += pixel[c] * weight;
+                    }
                 }
+                row[x as usize * 4..x as usize * 4 + 4].copy_from_slice(&sum);
+            }
+            Ok(())
+        })
+}
+
+// RGBA scaled to 0-1, with colors multiplied by alpha
+fn premultiply_f32(image: &RgbaImage) -> Vec<f32> {
+    image
+        .as_raw()
+        .par_chunks(4)
+        .flat_map(|pixel| {
+            let alpha = pixel[3] as f32 / 255.0;
+            let color = |c: usize| pixel[c] as f32 / 255.0 * alpha;
+            [color(0), color(1), color(2), alpha]
+        })
+        .collect()
+}
 
-                let idx = (x * 4) as usize;
-                row[idx] = r.clamp(0.0, 255.0) as u8;
-                row[idx + 1] = g.clamp(0.0, 255.0) as u8;
-                row[idx + 2] = b.clamp(0.0, 255.0) as u8;
-                row[idx + 3] = a.clamp(0.0, 255.0) as u8;
+// Divide the colors of a `premultiply_f32` buffer by alpha and round to 8 bits.
+// Pixels whose alpha rounds to zero become transparent black.
+fn unpremultiply_f32(buffer: &[f32], width: u32, height: u32) -> RgbaImage {
+    let round = |v: f32| v.round().clamp(0.0, 255.0) as u8;
+    let pixels = buffer
+        .par_chunks(4)
+        .flat_map(|pixel| {
+            let alpha = round(pixel[3] * 255.0);
+            if alpha == 0 {
+                return [0, 0, 0, 0];
             }
-        });
+            let color = |c: usize| round(pixel[c] / pixel[3] * 255.0);
+            [color(0), color(1), color(2), alpha]
+        })
+        .collect();
+    ImageBuffer::from_raw(width, height, pixels).unwrap()
+}
 
-    ImageBuffer::from_raw(width, height, result).unwrap()
+/// Gaussian blur approximated by three box blurs
+///
+/// Runs in constant time per pixel whatever the sigma, so it is much faster
+/// than `blur` for large sigmas, at the cost of a slightly boxier falloff.
+pub fn blur_approximate(image: &RgbaImage, sigma: f32) -> RgbaImage {
+    blur_approximate_cancellable(image, sigma, &CancellationToken::new())
+        .expect("token is never cancelled")
+}
+
+/// `blur_approximate` that checks `token` once per row
+pub fn blur_approximate_cancellable(
+    image: &RgbaImage,
+    sigma: f32,
+    token: &CancellationToken,
+) -> Result<RgbaImage> {
+    let (width, height) = image.dimensions();
+    if width == 0 || height == 0 {
+        return Ok(image.clone());
+    }
+    let buffer = image.as_raw().iter().map(|&v| v as f32).collect();
+    let buffer = box_blur(buffer, width, height, sigma, token)?;
+
+    let pixels = buffer
+        .par_iter()
+        .map(|v| v.round().clamp(0.0, 255.0) as u8)
+        .collect();
+    Ok(ImageBuffer::from_raw(width, height, pixels).unwrap())
+}
+
+// `blur_approximate` weighting colors by alpha, like `blur_straight`
+pub(crate) fn blur_approximate_straight(
+    image: &RgbaImage,
+    sigma: f32,
+    token: &CancellationToken,
+) -> Result<RgbaImage> {
+    if is_opaque(image) {
+        return blur_approximate_cancellable(image, sigma, token);
+    }
+    let (width, height) = image.dimensions();
+    let buffer = box_blur(premultiply_f32(image), width, height, sigma, token)?;
+    Ok(unpremultiply_f32(&buffer, width, height))
+}
+
+// Three box blurs of a non-empty RGBA buffer approximating a Gaussian of `sigma`
+fn box_blur(
+    mut buffer: Vec<f32>,
+    width: u32,
+    height: u32,
+    sigma: f32,
+    token: &CancellationToken,
+) -> Result<Vec<f32>> {
+    let radii = box_radii(sigma, 3);
+
+    // Box blurs are separable and commute, so all horizontal passes run
+    // first, then the vertical ones on a transposed copy to keep rows contiguous
+    box_blur_rows(&mut buffer, width, &radii, token)?;
+    let mut transposed = transpose(&buffer, width, height);
+    box_blur_rows(&mut transposed, height, &radii, token)?;
+    Ok(transpose(&transposed, height, width))
+}
+
+// Radii of `passes` box blurs whose combined variance best matches `sigma`
+fn box_radii(sigma: f32, passes: usize) -> Vec<usize> {
+    let n = passes as f32;
+    let variance = 12.0 * sigma * sigma;
+    let ideal = (variance / n + 1.0).sqrt();
+    let mut lower = ideal.floor() as i32;
+    if lower % 2 == 0 {
+        lower -= 1;
+    }
+    let lower = lower.max(1);
+    let lower_f = lower as f32;
+    // How many passes use the smaller width
+    let smaller = ((variance - n * lower_f * lower_f - 4.0 * n * lower_f - 3.0 * n)
+        / (-4.0 * lower_f - 4.0))
+        .round()
+        .clamp(0.0, n) as usize;
+    (0..passes)
+        .map(|i| {
+            let size = if i < smaller { lower } else { lower + 2 };
+            (size as usize - 1) / 2
+        })
+        .collect()
+}
+
+// Box blur every RGBA row of `buffer` once per radius
+fn box_blur_rows(
+    buffer: &mut [f32],
+    width: u32,
+    radii: &[usize],
+    token: &CancellationToken,
+) -> Result<()> {
+    let stride = width as usize * 4;
+    buffer
+        .par_chunks_mut(stride)
+        .try_for_each(|row| -> Result<()> {
+            token.check()?;
+            let mut scratch = vec![0.0f32; stride];
+            for &radius in radii {
+                box_blur_row(row, &mut scratch, radius);
+                row.copy_from_slice(&scratch);
+            }
+            Ok(())
+        })
+}
+
+// Running-sum box blur of one RGBA row, extending the edge pixels outward
+fn box_blur_row(row: &[f32], out: &mut [f32], radius: usize) {
+    let width = row.len() / 4;
+    let last = width - 1;
+    let scale = 1.0 / (2 * radius + 1) as f32;
+    for c in 0..4 {
+        let at = |x: usize| row[x.min(last) * 4 + c];
+        let mut sum = row[c] * (radius + 1) as f32;
+        for x in 1..=radius {
+            sum += at(x);
+        }
+        for x in 0..width {
+            out[x * 4 + c] = sum * scale;
+            sum += at(x + radius + 1) - row[x.saturating_sub(radius) * 4 + c];
+        }
+    }
+}
+
+// Swap rows and columns of a `width`x`height` RGBA buffer
+fn transpose(buffer: &[f32], width: u32, height: u32) -> Vec<f32> {
+    let (width, height) = (width as usize, height as usize);
+    let mut result = vec![0.0f32; buffer.len()];
+    result
+        .par_chunks_mut(height * 4)
+        .enumerate()
+        .for_each(|(x, column)| {
+            for y in 0..height {
+                let from = (y * width + x) * 4;
+                column[y * 4..y * 4 + 4].copy_from_slice(&buffer[from..from + 4]);
+            }
+        });
+    result
 }
 
+/// Default strength of `sharpen`
+pub const SHARPEN_AMOUNT: f32 = 1.5;
+/// Default blur sigma of `sharpen`, in pixels
+pub const SHARPEN_RADIUS: f32 = 1.0;
+
 /// Apply sharpening filter using unsharp masking
-pub fn sharpen(image: &RgbaImage) -> RgbaImage {
-    let blurred = blur(image, 1.0);
+///
+/// Adds `amount` times the difference from a Gaussian blur of sigma `radius`.
+/// Pixels whose luminance differs from the blur by less than `threshold`
+/// (0-255) are left alone, so flat noisy areas are not amplified.
+pub fn sharpen(image: &RgbaImage, amount: f32, radius: f32, threshold: u8) -> RgbaImage {
+    sharpen_cancellable(image, amount, radius, threshold, &CancellationToken::new())
+        .expect("token is never cancelled")
+}
+
+/// Sharpening filter that can be aborted through `token`
+pub fn sharpen_cancellable(
+    image: &RgbaImage,
+    amount: f32,
+    radius: f32,
+    threshold: u8,
+    token: &CancellationToken,
+) -> Result<RgbaImage> {
+    let blurred = blur_cancellable(image, radius, token)?;
+    Ok(unsharp(image, &blurred, amount, threshold))
+}
+
+// `sharpen` against a blur that weights colors by alpha, like `blur_straight`
+pub(crate) fn sharpen_straight(
+    image: &RgbaImage,
+    amount: f32,
+    radius: f32,
+    threshold: u8,
+    token: &CancellationToken,
+) -> Result<RgbaImage> {
+    let blurred = blur_straight(image, radius, token)?;
+    Ok(unsharp(image, &blurred, amount, threshold))
+}
+
+// Add `amount` times the difference between `image` and `blurred`
+fn unsharp(image: &RgbaImage, blurred: &RgbaImage, amount: f32, threshold: u8) -> RgbaImage {
     let (width, height) = image.dimensions();
 
     let pixels: Vec<u8> = image
@@ -184,165 +498,1653 @@ pub fn sharpen(image: &RgbaImage) -> RgbaImage {
         .par_chunks(4)
         .zip(blurred.as_raw().par_chunks(4))
         .flat_map(|(orig, blur)| {
-            let amount = 1.5f32;
-            [
-                ((orig[0] as f32 + amount * (orig[0] as f32 - blur[0] as f32)).clamp(0.0, 255.0))
-                    as u8,
-                ((orig[1] as f32 + amount * (orig[1] as f32 - blur[1] as f32)).clamp(0.0, 255.0))
-                    as u8,
-                ((orig[2] as f32 + amount * (orig[2] as f32 - blur[2] as f32)).clamp(0.0, 255.0))
-                    as u8,
-                orig[3],
-            ]
+            let difference = |c: usize| orig[c] as f32 - blur[c] as f32;
+            let luminance =
+                0.2126 * difference(0) + 0.7152 * difference(1) + 0.0722 * difference(2);
+            if luminance.abs() < threshold as f32 {
+                return [orig[0], orig[1], orig[2], orig[3]];
+            }
+            let sharpened =
+                |c: usize| (orig[c] as f32 + amount * difference(c)).clamp(0.0, 255.0) as u8;
+            [sharpened(0), sharpened(1), sharpened(2), orig[3]]
         })
         .collect();
 
     ImageBuffer::from_raw(width, height, pixels).unwrap()
 }
 
+/// Convolve RGB with a 3x3 `kernel`, adding `bias` to every channel
+///
+/// Covers small custom kernels such as emboss (`bias` 128 keeps flat areas
+/// mid-gray). Edges are clamped and alpha is unchanged.
+pub fn convolve_3x3(image: &RgbaImage, kernel: [[f32; 3]; 3], bias: f32) -> RgbaImage {
+    let (width, height) = image.dimensions();
+    let source = image.as_raw();
+    let row_len = (width as usize * 4).max(4);
+    let mut result = vec![0u8; source.len()];
+
+    result
+        .par_chunks_mut(row_len)
+        .enumerate()
+        .for_each(|(y, row)| {
+            for x in 0..width as usize {
+                let mut sum = [bias; 3];
+                for (ky, kernel_row) in kernel.iter().enumerate() {
+                    let sy = (y + ky).saturating_sub(1).min(height as usize - 1);
+                    for (kx, &weight) in kernel_row.iter().enumerate() {
+                        let sx = (x + kx).saturating_sub(1).min(width as usize - 1);
+                        let pixel = &source[(sy * width as usize + sx) * 4..];
+                        for c in 0..3 {
+                            sum[c] += pixel[c] as f32 * weight;
+                        }
+                    }
+                }
+                let out = &mut row[x * 4..x * 4 + 4];
+                for c in 0..3 {
+                    out[c] = sum[c].clamp(0.0, 255.0) as u8;
+                }
+                out[3] = source[(y * width as usize + x) * 4 + 3];
+            }
+        });
+
+    ImageBuffer::from_raw(width, height, result).unwrap()
+}
+
+/// Lift shadows and recover highlights independently
+/// shadows_amount: 0.0 (unchanged) to 1.0, how much dark areas are brightened
+/// highlights_amount: 0.0 (unchanged) to 1.0, how much bright areas are darkened
+/// radius: blur sigma in pixels of the luminance mask that decides what is a
+/// dark or bright area
+///
+/// Each pixel gets a gamma curve on its luminance, weighted by how far the
+/// blurred luminance around it is below or above mid-gray, so a dark
+/// region is lifted as a whole and keeps its local contrast. Colors are
+/// scaled with the luminance to keep their hue; pure black and clipped white
+/// stay as they are. Alpha is unchanged.
+pub fn shadows_highlights(
+    image: &RgbaImage,
+    shadows_amount: f32,
+    highlights_amount: f32,
+    radius: f32,
+) -> RgbaImage {
+    shadows_highlights_cancellable(
+        image,
+        shadows_amount,
+        highlights_amount,
+        radius,
+        &CancellationToken::new(),
+    )
+    .expect("token is never cancelled")
+}
+
+/// Shadows/highlights recovery that can be aborted through `token`
+pub fn shadows_highlights_cancellable(
+    image: &RgbaImage,
+    shadows_amount: f32,
+    highlights_amount: f32,
+    radius: f32,
+    token: &CancellationToken,
+) -> Result<RgbaImage> {
+    let mut mask = grayscale(image);
+    mask.pixels_mut().for_each(|p| p[3] = 255);
+    if radius > 0.0 {
+        mask = blur_cancellable(&mask, radius, token)?;
+    }
+
+    let mut result = image.clone();
+    result
+        .par_chunks_mut(4)
+        .zip(mask.as_raw().par_chunks(4))
+        .for_each(|(pixel, around)| {
+            let around = around[0] as f32 / 255.0;
+            let dark = ((0.5 - around) * 2.0).clamp(0.0, 1.0);
+            let bright = ((around - 0.5) * 2.0).clamp(0.0, 1.0);
+            let [wr, wg, wb] = GrayscaleMode::Bt709.weights();
+            let luma = (wr * pixel[0] as f32 + wg * pixel[1] as f32 + wb * pixel[2] as f32) / 255.0;
+            if luma <= 0.0 {
+                return;
+            }
+
+            // Gamma 1 / (1 + 2a) lifts, its mirror image around white darkens
+            let mut target = luma.powf(1.0 / (1.0 + 2.0 * shadows_amount * dark * dark));
+            let exponent = 1.0 + 2.0 * highlights_amount * bright * bright;
+            target = 1.0 - (1.0 - target).max(0.0).powf(1.0 / exponent);
+            let gain = target / luma;
+            for c in &mut pixel[..3] {
+                *c = (*c as f32 * gain).round().clamp(0.0, 255.0) as u8;
+            }
+        });
+    Ok(result)
+}
+
+/// How `edge_detect_with` treats the pixels outside the image
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum EdgeBorder {
+    /// Leave a 1px transparent border where the kernel does not fit
+    #[default]
+    Zero,
+    /// Repeat the outermost pixels, so the border is detected too
+    Replicate,
+}
+
+/// What `edge_detect_with` writes for each pixel
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum EdgeOutput {
+    /// Gradient magnitude as gray
+    #[default]
+    Magnitude,
+    /// Gradient angle as gray, 0 to 255 for -180 to 180 degrees; pixels
+    /// without a gradient are black
+    Direction,
+}
+
+/// Options of `edge_detect_with`; the default matches `edge_detect`
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct EdgeOptions {
+    pub border: EdgeBorder,
+    pub output: EdgeOutput,
+    /// Keep only gradients of at least this magnitude; magnitude output
+    /// becomes black and white
+    pub threshold: Option<u8>,
+}
+
 /// Edge detection using Sobel operator
 pub fn edge_detect(image: &RgbaImage) -> RgbaImage {
+    edge_detect_cancellable(image, &CancellationToken::new()).expect("token is never cancelled")
+}
+
+/// Sobel edge detection that checks `token` once per row
+pub fn edge_detect_cancellable(image: &RgbaImage, token: &CancellationToken) -> Result<RgbaImage> {
+    edge_detect_with_cancellable(image, &EdgeOptions::default(), token)
+}
+
+/// Sobel edge detection with border, output and threshold options
+pub fn edge_detect_with(image: &RgbaImage, options: &EdgeOptions) -> RgbaImage {
+    edge_detect_with_cancellable(image, options, &CancellationToken::new())
+        .expect("token is never cancelled")
+}
+
+/// `edge_detect_with` that checks `token` once per row
+pub fn edge_detect_with_cancellable(
+    image: &RgbaImage,
+    options: &EdgeOptions,
+    token: &CancellationToken,
+) -> Result<RgbaImage> {
     let gray = grayscale(image);
     let (width, height) = gray.dimensions();
+    let stride = width as usize * 4;
+    let mut result = vec![0u8; stride * height as usize];
+    if stride == 0 {
+        return Ok(ImageBuffer::from_raw(width, height, result).unwrap());
+    }
+    let source = gray.as_raw();
+    // Gray value at (x, y), clamping coordinates into the image
+    let at = |x: i64, y: i64| {
+        let x = x.clamp(0, width as i64 - 1) as usize;
+        let y = y.clamp(0, height as i64 - 1) as usize;
+        source[y * stride + x * 4] as i32
+    };
 
     // Sobel kernels
     let sobel_x: [[i32; 3]; 3] = [[-1, 0, 1], [-2, 0, 2], [-1, 0, 1]];
     let sobel_y: [[i32; 3]; 3] = [[-1, -2, -1], [0, 0, 0], [1, 2, 1]];
 
-    let rows: Vec<Vec<u8>> = (1..height - 1)
-        .into_par_iter()
-        .map(|y| {
-            let mut row = Vec::with_capacity(((width - 2) * 4) as usize);
-            for x in 1..width - 1 {
+    result
+        .par_chunks_mut(stride)
+        .enumerate()
+        .try_for_each(|(y, row)| -> Result<()> {
+            token.check()?;
+            let y = y as i64;
+            for x in 0..width as i64 {
+                let interior = x > 0 && y > 0 && x < width as i64 - 1 && y < height as i64 - 1;
+                if !interior && options.border == EdgeBorder::Zero {
+                    continue;
+                }
+
                 let mut gx = 0i32;
                 let mut gy = 0i32;
-
                 for ky in 0..3 {
                     for kx in 0..3 {
-                        let px = gray.get_pixel(x + kx - 1, y + ky - 1)[0] as i32;
-                        gx += px * sobel_x[ky as usize][kx as usize];
-                        gy += px * sobel_y[ky as usize][kx as usize];
+                        let px = at(x + kx as i64 - 1, y + ky as i64 - 1);
+                        gx += px * sobel_x[ky][kx];
+                        gy += px * sobel_y[ky][kx];
                     }
                 }
 
                 let magnitude = ((gx * gx + gy * gy) as f32).sqrt().clamp(0.0, 255.0) as u8;
-                row.extend_from_slice(&[magnitude, magnitude, magnitude, 255]);
+                let kept = magnitude > 0 && options.threshold.is_none_or(|t| magnitude >= t);
+                let value = match options.output {
+                    EdgeOutput::Magnitude if options.threshold.is_none() => magnitude,
+                    EdgeOutput::Magnitude => 255 * kept as u8,
+                    EdgeOutput::Direction if kept => {
+                        let angle = (gy as f32).atan2(gx as f32);
+                        ((angle + PI) / TAU * 255.0).round() as u8
+                    }
+                    EdgeOutput::Direction => 0,
+                };
+                let x = x as usize;
+                row[x * 4..x * 4 + 4].copy_from_slice(&[value, value, value, 255]);
             }
-            row
-        })
-        .collect();
+            Ok(())
+        })?;
 
-    // Create output image with border handling
-    let mut result = ImageBuffer::new(width, height);
+    Ok(ImageBuffer::from_raw(width, height, result).unwrap())
+}
 
-    // Copy edge-detected content
-    for (y, row) in rows.iter().enumerate() {
-        for (x, chunk) in row.chunks(4).enumerate() {
-            result.put_pixel(
-                (x + 1) as u32,
-                (y + 1) as u32,
-                Rgba([chunk[0], chunk[1], chunk[2], chunk[3]]),
-            );
+/// Interpolation used when resampling
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ResampleFilter {
+    /// Nearest neighbor; keeps hard pixel edges (pixel art)
+    Nearest,
+    /// Linear interpolation; fast, good for previews
+    Bilinear,
+    /// Cubic interpolation; sharper than bilinear
+    CatmullRom,
+    /// Windowed sinc; highest quality, slowest
+    #[default]
+    Lanczos3,
+}
+
+impl ResampleFilter {
+    /// Parse a filter name (case-insensitive), e.g. "nearest" or "catmull_rom"
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "nearest" => Some(ResampleFilter::Nearest),
+            "bilinear" | "linear" | "triangle" => Some(ResampleFilter::Bilinear),
+            "catmull_rom" | "catmullrom" | "cubic" => Some(ResampleFilter::CatmullRom),
+            "lanczos3" | "lanczos" => Some(ResampleFilter::Lanczos3),
+            _ => None,
         }
     }
 
-    result
+    /// Canonical snake_case name
+    pub fn name(&self) -> &'static str {
+        match self {
+            ResampleFilter::Nearest => "nearest",
+            ResampleFilter::Bilinear => "bilinear",
+            ResampleFilter::CatmullRom => "catmull_rom",
+            ResampleFilter::Lanczos3 => "lanczos3",
+        }
+    }
+
+    fn filter_type(self) -> image::imageops::FilterType {
+        use image::imageops::FilterType;
+        match self {
+            ResampleFilter::Nearest => FilterType::Nearest,
+            ResampleFilter::Bilinear => FilterType::Triangle,
+            ResampleFilter::CatmullRom => FilterType::CatmullRom,
+            ResampleFilter::Lanczos3 => FilterType::Lanczos3,
+        }
+    }
 }
 
-/// Resize image to new dimensions using Lanczos3 interpolation
-pub fn resize(image: &RgbaImage, new_width: u32, new_height: u32) -> RgbaImage {
-    let resized = image::imageops::resize(
-        image,
-        new_width,
-        new_height,
-        image::imageops::FilterType::Lanczos3,
-    );
-    resized
+/// Resize image to new dimensions with the given interpolation
+pub fn resize(
+    image: &RgbaImage,
+    new_width: u32,
+    new_height: u32,
+    filter: ResampleFilter,
+) -> RgbaImage {
+    image::imageops::resize(image, new_width, new_height, filter.filter_type())
 }
 
-/// Invert colors
-pub fn invert(image: &RgbaImage) -> RgbaImage {
+// `resize` weighting colors by alpha, like `blur_straight`
+pub(crate) fn resize_straight(
+    image: &RgbaImage,
+    new_width: u32,
+    new_height: u32,
+    filter: ResampleFilter,
+) -> RgbaImage {
+    if is_opaque(image) {
+        return resize(image, new_width, new_height, filter);
+    }
     let (width, height) = image.dimensions();
+    let premultiplied: Rgba32FImage =
+        ImageBuffer::from_raw(width, height, premultiply_f32(image)).unwrap();
+    let resized =
+        image::imageops::resize(&premultiplied, new_width, new_height, filter.filter_type());
+    unpremultiply_f32(resized.as_raw(), new_width, new_height)
+}
 
-    let pixels: Vec<u8> = image
-        .as_raw()
-        .par_chunks(4)
-        .flat_map(|pixel| [255 - pixel[0], 255 - pixel[1], 255 - pixel[2], pixel[3]])
-        .collect();
-
-    ImageBuffer::from_raw(width, height, pixels).unwrap()
+/// How `upscale` fills in the detail a larger image needs
+#[derive(Debug, Clone, Default)]
+pub enum UpscaleMethod {
+    /// Plain Lanczos3 interpolation
+    Lanczos,
+    /// Bicubic interpolation sharpened in proportion to the factor, then clamped
+    /// to the surrounding source pixels so edges get crisper without halos
+    #[default]
+    Detail,
+    /// A super-resolution network such as EDSR; its output is resized to the
+    /// exact factor. Needs the `onnx` feature.
+    Model { model: Arc<Model>, pre: Preprocess },
 }
 
-/// Apply sepia tone effect
-pub fn sepia(image: &RgbaImage) -> RgbaImage {
-    let (width, height) = image.dimensions();
+/// Largest factor accepted by `upscale`
+pub const MAX_UPSCALE_FACTOR: f32 = 16.0;
 
-    let pixels: Vec<u8> = image
-        .as_raw()
-        .par_chunks(4)
-        .flat_map(|pixel| {
-            let r = pixel[0] as f32;
-            let g = pixel[1] as f32;
-            let b = pixel[2] as f32;
+/// Enlarge by `factor` (1.0 to `MAX_UPSCALE_FACTOR`) with the given method
+pub fn upscale(image: &RgbaImage, factor: f32, method: &UpscaleMethod) -> Result<RgbaImage> {
+    upscale_cancellable(image, factor, method, &CancellationToken::new())
+}
 
-            let new_r = (0.393 * r + 0.769 * g + 0.189 * b).clamp(0.0, 255.0) as u8;
-            let new_g = (0.349 * r + 0.686 * g + 0.168 * b).clamp(0.0, 255.0) as u8;
-            let new_b = (0.272 * r + 0.534 * g + 0.131 * b).clamp(0.0, 255.0) as u8;
+/// `upscale` that can be aborted through `token`
+pub fn upscale_cancellable(
+    image: &RgbaImage,
+    factor: f32,
+    method: &UpscaleMethod,
+    token: &CancellationToken,
+) -> Result<RgbaImage> {
+    let (width, height) = upscaled_size(image.width(), image.height(), factor);
+    match method {
+        UpscaleMethod::Lanczos => Ok(resize(image, width, height, ResampleFilter::Lanczos3)),
+        UpscaleMethod::Detail => {
+            let upscaled = resize(image, width, height, ResampleFilter::CatmullRom);
+            // Interpolation blurs edges over about one source pixel
+            let sharpened = sharpen_cancellable(&upscaled, 1.0, 0.5 * factor.max(1.0), 2, token)?;
+            Ok(clamp_to_source(image, sharpened))
+        }
+        UpscaleMethod::Model { model, pre } => {
+            let result = model.apply(image, pre, Postprocess::Image, token)?;
+            Ok(if result.dimensions() == (width, height) {
+                result
+            } else {
+                resize(&result, width, height, ResampleFilter::Lanczos3)
+            })
+        }
+    }
+}
 
-            [new_r, new_g, new_b, pixel[3]]
-        })
-        .collect();
+/// Output size of `upscale`
+pub fn upscaled_size(width: u32, height: u32, factor: f32) -> (u32, u32) {
+    let scale = |v: u32| ((v as f64 * factor as f64).round() as u32).max(1);
+    (scale(width), scale(height))
+}
 
-    ImageBuffer::from_raw(width, height, pixels).unwrap()
+// Limit every color of `upscaled` to the range of the 2x2 source pixels around it
+fn clamp_to_source(source: &RgbaImage, mut upscaled: RgbaImage) -> RgbaImage {
+    let (src_w, src_h) = source.dimensions();
+    let (width, height) = upscaled.dimensions();
+    if src_w == 0 || src_h == 0 {
+        return upscaled;
+    }
+    let neighbors = |v: u32, src: u32, dst: u32| {
+        let s = ((v as f64 + 0.5) * src as f64 / dst as f64 - 0.5).max(0.0);
+        let a = (s.floor() as u32).min(src - 1);
+        (a, (a + 1).min(src - 1))
+    };
+    let row_len = (width as usize * 4).max(4);
+    let pixels: &mut [u8] = &mut upscaled;
+    pixels
+        .par_chunks_mut(row_len)
+        .enumerate()
+        .for_each(|(y, row)| {
+            let (y0, y1) = neighbors(y as u32, src_h, height);
+            for (x, pixel) in row.chunks_exact_mut(4).enumerate() {
+                let (x0, x1) = neighbors(x as u32, src_w, width);
+                let around = [(x0, y0), (x1, y0), (x0, y1), (x1, y1)].map(|(x, y)| source[(x, y)]);
+                for c in 0..3 {
+                    let low = around.iter().map(|p| p[c]).min().unwrap_or(0);
+                    let high = around.iter().map(|p| p[c]).max().unwrap_or(255);
+                    pixel[c] = pixel[c].clamp(low, high);
+                }
+            }
+        });
+    upscaled
 }
 
-#[cfg(test)]
-mod tests {
-    use super::*;
+/// How `thumbnail` fits an image into its bounding box
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FitMode {
+    /// Scale down to fit entirely inside the box; output may be smaller than the box
+    Contain,
+    /// Fill the box exactly, cropping the overflow around the center
+    Cover,
+    /// Fit like `Contain`, then center on a box-sized canvas of this color
+    Pad(Rgba<u8>),
+}
 
-    fn create_test_image() -> RgbaImage {
-        ImageBuffer::from_fn(100, 100, |x, y| {
-            Rgba([(x % 256) as u8, (y % 256) as u8, ((x + y) % 256) as u8, 255])
-        })
+/// Create a thumbnail that fits in `max_width` x `max_height`
+///
+/// `Contain` and `Pad` never enlarge the image; `Cover` does when the source is
+/// smaller than the box, since its output is always exactly box-sized.
+pub fn thumbnail(image: &RgbaImage, max_width: u32, max_height: u32, fit: FitMode) -> RgbaImage {
+    let (width, height) = image.dimensions();
+    let (max_width, max_height) = (max_width.max(1), max_height.max(1));
+    if width == 0 || height == 0 {
+        return RgbaImage::new(0, 0);
     }
 
-    #[test]
-    fn test_grayscale() {
-        let image = create_test_image();
-        let result = grayscale(&image);
-        assert_eq!(result.dimensions(), image.dimensions());
-
-        // Check that all channels are equal (grayscale)
-        for pixel in result.pixels() {
-            assert_eq!(pixel[0], pixel[1]);
-            assert_eq!(pixel[1], pixel[2]);
+    match fit {
+        FitMode::Contain => {
+            let (w, h) = contain_size(width, height, max_width, max_height);
+            if (w, h) == (width, height) {
+                image.clone()
+            } else {
+                resize(image, w, h, ResampleFilter::Lanczos3)
+            }
+        }
+        FitMode::Cover => {
+            // Crop the source to the box's aspect ratio first, so the only
+            // resampling step maps the crop onto the box exactly
+            let box_aspect = max_width as f64 / max_height as f64;
+            let (crop_w, crop_h) = if width as f64 / height as f64 > box_aspect {
+                let w = (height as f64 * box_aspect).round() as u32;
+                (w.clamp(1, width), height)
+            } else {
+                let h = (width as f64 / box_aspect).round() as u32;
+                (width, h.clamp(1, height))
+            };
+            let x = (width - crop_w) / 2;
+            let y = (height - crop_h) / 2;
+            let cropped = image::imageops::crop_imm(image, x, y, crop_w, crop_h).to_image();
+            resize(&cropped, max_width, max_height, ResampleFilter::Lanczos3)
+        }
+        FitMode::Pad(color) => {
+            let contained = thumbnail(image, max_width, max_height, FitMode::Contain);
+            let mut canvas = ImageBuffer::from_pixel(max_width, max_height, color);
+            let x = (max_width - contained.width()) / 2;
+            let y = (max_height - contained.height()) / 2;
+            image::imageops::replace(&mut canvas, &contained, x as i64, y as i64);
+            canvas
         }
     }
+}
 
-    #[test]
-    fn test_brightness() {
-        let image = create_test_image();
-        let brighter = brightness(&image, 0.5);
-        let darker = brightness(&image, -0.5);
+// Largest size with the source aspect ratio that fits the box, never upscaling
+fn contain_size(width: u32, height: u32, max_width: u32, max_height: u32) -> (u32, u32) {
+    let scale = (max_width as f64 / width as f64)
+        .min(max_height as f64 / height as f64)
+        .min(1.0);
+    let w = ((width as f64 * scale).round() as u32).clamp(1, max_width);
+    let h = ((height as f64 * scale).round() as u32).clamp(1, max_height);
+    (w, h)
+}
 
-        assert_eq!(brighter.dimensions(), image.dimensions());
-        assert_eq!(darker.dimensions(), image.dimensions());
-    }
+/// What fills the border `extend_canvas` adds
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum PadFill {
+    /// A solid color
+    Color(Rgba<u8>),
+    /// Fully transparent pixels
+    #[default]
+    Transparent,
+    /// Repeat the outermost row or column of the image
+    Replicate,
+    /// Reflect the image at its edges, including the edge pixel; borders wider
+    /// than the image keep alternating
+    Mirror,
+}
 
-    #[test]
-    fn test_contrast() {
-        let image = create_test_image();
-        let result = contrast(&image, 1.5);
-        assert_eq!(result.dimensions(), image.dimensions());
-    }
+/// Add a border of `top`, `right`, `bottom` and `left` pixels around the image
+///
+/// Letterboxes to a fixed aspect ratio, or with `Mirror` or `Replicate` gives
+/// blurs and other neighborhood filters real pixels to sample at the edges.
+pub fn extend_canvas(
+    image: &RgbaImage,
+    top: u32,
+    right: u32,
+    bottom: u32,
+    left: u32,
+    fill: PadFill,
+) -> Result<RgbaImage> {
+    let (width, height) = image.dimensions();
+    let side = |inner: u32, a: u32, b: u32| {
+        u32::try_from(inner as u64 + a as u64 + b as u64).map_err(|_| {
+            PipelineError::ResourceLimit(format!(
+                "padded side of {} pixels is too large",
+                inner as u64 + a as u64 + b as u64
+            ))
+        })
+    };
+    let new_width = side(width, left, right)?;
+    let new_height = side(height, top, bottom)?;
+    rgba_len(new_width, new_height)?;
 
-    #[test]
+    let background = match fill {
+        PadFill::Color(color) => color,
+        _ => Rgba([0, 0, 0, 0]),
+    };
+    // Nothing to replicate or mirror in an empty image
+    let fill = match fill {
+        PadFill::Replicate | PadFill::Mirror if width == 0 || height == 0 => PadFill::Transparent,
+        fill => fill,
+    };
+    // Source index of every output column and row; `None` takes the background
+    let map = |count: u32, offset: u32, len: u32| -> Vec<Option<u32>> {
+        (0..count)
+            .map(|i| {
+                let s = i as i64 - offset as i64;
+                let n = len as i64;
+                match fill {
+                    _ if (0..n).contains(&s) => Some(s as u32),
+                    PadFill::Replicate => Some(s.clamp(0, n - 1) as u32),
+                    PadFill::Mirror => {
+                        let period = s.rem_euclid(2 * n);
+                        Some(if period < n {
+                            period
+                        } else {
+                            2 * n - 1 - period
+                        } as u32)
+                    }
+                    _ => None,
+                }
+            })
+            .collect()
+    };
+    let columns = map(new_width, left, width);
+    let rows = map(new_height, top, height);
+
+    let mut result = RgbaImage::new(new_width, new_height);
+    let row_len = (new_width as usize * 4).max(4);
+    let pixels: &mut [u8] = &mut result;
+    pixels
+        .par_chunks_mut(row_len)
+        .zip(rows.par_iter())
+        .for_each(|(row, source_y)| {
+            for (pixel, source_x) in row.chunks_exact_mut(4).zip(&columns) {
+                let color = match (source_x, source_y) {
+                    (Some(x), Some(y)) => image.get_pixel(*x, *y),
+                    _ => &background,
+                };
+                pixel.copy_from_slice(&color.0);
+            }
+        });
+    Ok(result)
+}
+
+/// Widths of the fixed border of a nine-patch image, in pixels
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Insets {
+    pub top: u32,
+    pub right: u32,
+    pub bottom: u32,
+    pub left: u32,
+}
+
+impl Insets {
+    /// The same inset on all four sides
+    pub fn uniform(inset: u32) -> Self {
+        Insets {
+            top: inset,
+            right: inset,
+            bottom: inset,
+            left: inset,
+        }
+    }
+}
+
+/// Resize a UI asset such as a button or panel background as a nine-patch
+///
+/// The corners inside `insets` are copied unscaled, the top and bottom edges
+/// are stretched horizontally only, the left and right edges vertically only,
+/// and the center in both directions. The target must be at least as large as
+/// the corners together.
+pub fn nine_patch_resize(
+    image: &RgbaImage,
+    insets: Insets,
+    target_width: u32,
+    target_height: u32,
+) -> Result<RgbaImage> {
+    let (width, height) = image.dimensions();
+    let Insets {
+        top,
+        right,
+        bottom,
+        left,
+    } = insets;
+    if left as u64 + right as u64 >= width as u64 || top as u64 + bottom as u64 >= height as u64 {
+        return Err(PipelineError::InvalidParameter(format!(
+            "insets {}/{}/{}/{} leave no center in a {}x{} image",
+            top, right, bottom, left, width, height
+        )));
+    }
+    if target_width < left + right || target_height < top + bottom {
+        return Err(PipelineError::InvalidParameter(format!(
+            "nine-patch target {}x{} is smaller than its corners ({}x{})",
+            target_width,
+            target_height,
+            left + right,
+            top + bottom
+        )));
+    }
+    rgba_len(target_width, target_height)?;
+
+    // (source start, source length, target start, target length) of each band
+    let bands = |start: u32, end: u32, size: u32, target: u32| {
+        [
+            (0, start, 0, start),
+            (start, size - start - end, start, target - start - end),
+            (size - end, end, target - end, end),
+        ]
+    };
+    let columns = bands(left, right, width, target_width);
+    let rows = bands(top, bottom, height, target_height);
+    let mut result = RgbaImage::new(target_width, target_height);
+    for &(sy, sh, ty, th) in &rows {
+        for &(sx, sw, tx, tw) in &columns {
+            if sw == 0 || sh == 0 || tw == 0 || th == 0 {
+                continue;
+            }
+            let patch = image::imageops::crop_imm(image, sx, sy, sw, sh).to_image();
+            let patch = if (sw, sh) == (tw, th) {
+                patch
+            } else {
+                resize_straight(&patch, tw, th, ResampleFilter::Bilinear)
+            };
+            image::imageops::replace(&mut result, &patch, tx as i64, ty as i64);
+        }
+    }
+    Ok(result)
+}
+
+/// Content-aware resize by removing or inserting low-energy seams
+///
+/// Width is retargeted first, then height. Enlarging inserts averaged copies of
+/// the lowest-energy seams, so large enlargements are done in several rounds.
+pub fn seam_carve(image: &RgbaImage, new_width: u32, new_height: u32) -> RgbaImage {
+    seam_carve_cancellable(image, new_width, new_height, &CancellationToken::new())
+        .expect("token is never cancelled")
+}
+
+/// Seam carving that checks `token` once per seam
+pub fn seam_carve_cancellable(
+    image: &RgbaImage,
+    new_width: u32,
+    new_height: u32,
+    token: &CancellationToken,
+) -> Result<RgbaImage> {
+    let (new_width, new_height) = (new_width.max(1) as usize, new_height.max(1) as usize);
+    if image.width() == 0 || image.height() == 0 {
+        return Ok(RgbaImage::new(0, 0));
+    }
+
+    let mut grid = SeamGrid::from_image(image);
+    grid.retarget_width(new_width, token)?;
+    let mut grid = grid.transposed();
+    grid.retarget_width(new_height, token)?;
+    Ok(grid.transposed().into_image())
+}
+
+/// Row-major pixel grid that can lose or gain vertical seams
+#[derive(Clone)]
+struct SeamGrid {
+    width: usize,
+    height: usize,
+    pixels: Vec<[u8; 4]>,
+}
+
+impl SeamGrid {
+    fn from_image(image: &RgbaImage) -> Self {
+        Self {
+            width: image.width() as usize,
+            height: image.height() as usize,
+            pixels: image.pixels().map(|p| p.0).collect(),
+        }
+    }
+
+    fn into_image(self) -> RgbaImage {
+        let raw = self.pixels.into_iter().flatten().collect();
+        ImageBuffer::from_raw(self.width as u32, self.height as u32, raw).unwrap()
+    }
+
+    fn transposed(&self) -> Self {
+        let mut pixels = Vec::with_capacity(self.pixels.len());
+        for x in 0..self.width {
+            for y in 0..self.height {
+                pixels.push(self.pixels[y * self.width + x]);
+            }
+        }
+        Self {
+            width: self.height,
+            height: self.width,
+            pixels,
+        }
+    }
+
+    fn retarget_width(&mut self, target: usize, token: &CancellationToken) -> Result<()> {
+        while self.width > target {
+            token.check()?;
+            let seam = self.find_seam();
+            remove_seam(&mut self.pixels, self.width, &seam);
+            self.width -= 1;
+        }
+        while self.width < target {
+            // Inserting more than half the width per round would duplicate the same seams
+            let count = (target - self.width).min((self.width / 2).max(1));
+            self.insert_seams(count, token)?;
+        }
+        Ok(())
+    }
+
+    // Absolute luminance differences to the four neighbours; unlike central
+    // differences this also gives one-pixel lines high energy
+    fn energy(&self) -> Vec<f32> {
+        let (w, h) = (self.width, self.height);
+        let luma: Vec<f32> = self
+            .pixels
+            .iter()
+            .map(|p| 0.2126 * p[0] as f32 + 0.7152 * p[1] as f32 + 0.0722 * p[2] as f32)
+            .collect();
+
+        let mut energy = vec![0f32; w * h];
+        energy.par_chunks_mut(w).enumerate().for_each(|(y, row)| {
+            let (up, down) = (y.saturating_sub(1), (y + 1).min(h - 1));
+            for (x, e) in row.iter_mut().enumerate() {
+                let (left, right) = (x.saturating_sub(1), (x + 1).min(w - 1));
+                let here = luma[y * w + x];
+                *e = (here - luma[y * w + left]).abs()
+                    + (luma[y * w + right] - here).abs()
+                    + (here - luma[up * w + x]).abs()
+                    + (luma[down * w + x] - here).abs();
+            }
+        });
+        energy
+    }
+
+    // Column index of the minimum-energy 8-connected vertical seam in each row
+    fn find_seam(&self) -> Vec<usize> {
+        let (w, h) = (self.width, self.height);
+        let mut cost = self.energy();
+        for y in 1..h {
+            for x in 0..w {
+                let prev = &cost[(y - 1) * w..y * w];
+                let best = prev[x.saturating_sub(1)..(x + 2).min(w)]
+                    .iter()
+                    .copied()
+                    .fold(f32::INFINITY, f32::min);
+                cost[y * w + x] += best;
+            }
+        }
+
+        let last = &cost[(h - 1) * w..];
+        let mut x = (0..w)
+            .min_by(|&a, &b| last[a].total_cmp(&last[b]))
+            .unwrap_or(0);
+        let mut seam = vec![0; h];
+        seam[h - 1] = x;
+        for y in (0..h - 1).rev() {
+            let row = &cost[y * w..(y + 1) * w];
+            x = (x.saturating_sub(1)..(x + 2).min(w))
+                .min_by(|&a, &b| row[a].total_cmp(&row[b]))
+                .unwrap_or(x);
+            seam[y] = x;
+        }
+        seam
+    }
+
+    fn insert_seams(&mut self, count: usize, token: &CancellationToken) -> Result<()> {
+        let (w, h) = (self.width, self.height);
+
+        // Find `count` distinct seams on a scratch copy, tracking original columns
+        let mut scratch = self.clone();
+        let mut columns: Vec<usize> = (0..h).flat_map(|_| 0..w).collect();
+        let mut chosen: Vec<Vec<usize>> = vec![Vec::with_capacity(count); h];
+        for _ in 0..count {
+            token.check()?;
+            let seam = scratch.find_seam();
+            for (y, &x) in seam.iter().enumerate() {
+                chosen[y].push(columns[y * scratch.width + x]);
+            }
+            remove_seam(&mut scratch.pixels, scratch.width, &seam);
+            remove_seam(&mut columns, scratch.width, &seam);
+            scratch.width -= 1;
+        }
+
+        // Duplicate each chosen pixel as the average of it and its right neighbour
+        let mut pixels = Vec::with_capacity((w + count) * h);
+        for (y, row_seams) in chosen.iter_mut().enumerate() {
+            row_seams.sort_unstable();
+            let row = &self.pixels[y * w..(y + 1) * w];
+            let mut next = row_seams.iter().peekable();
+            for x in 0..w {
+                pixels.push(row[x]);
+                while next.next_if(|&&c| c == x).is_some() {
+                    let right = row[(x + 1).min(w - 1)];
+                    pixels.push(std::array::from_fn(|c| {
+                        (row[x][c] as u16 + right[c] as u16).div_ceil(2) as u8
+                    }));
+                }
+            }
+        }
+
+        self.pixels = pixels;
+        self.width = w + count;
+        Ok(())
+    }
+}
+
+// Drop one element per row of a row-major buffer
+fn remove_seam<T: Copy>(data: &mut Vec<T>, width: usize, seam: &[usize]) {
+    let mut write = 0;
+    for (y, &skip) in seam.iter().enumerate() {
+        for x in 0..width {
+            if x != skip {
+                data[write] = data[y * width + x];
+                write += 1;
+            }
+        }
+    }
+    data.truncate(write);
+}
+
+/// Invert colors
+pub fn invert(image: &RgbaImage) -> RgbaImage {
+    Lut256::invert().apply(image)
+}
+
+/// 4x5 matrix mapping RGBA to RGBA, as in SVG's `feColorMatrix`
+///
+/// Output channel `c` is `m[c][0]*R + m[c][1]*G + m[c][2]*B + m[c][3]*A + m[c][4]*255`,
+/// so the last column is an offset in the 0.0-1.0 range.
+pub type ColorMatrix = [[f32; 5]; 4];
+
+/// Color matrix that leaves every pixel unchanged
+pub const IDENTITY_COLOR_MATRIX: ColorMatrix = [
+    [1.0, 0.0, 0.0, 0.0, 0.0],
+    [0.0, 1.0, 0.0, 0.0, 0.0],
+    [0.0, 0.0, 1.0, 0.0, 0.0],
+    [0.0, 0.0, 0.0, 1.0, 0.0],
+];
+
+/// Color matrix of `sepia`
+pub const SEPIA_COLOR_MATRIX: ColorMatrix = rgb_color_matrix([
+    [0.393, 0.769, 0.189],
+    [0.349, 0.686, 0.168],
+    [0.272, 0.534, 0.131],
+]);
+
+/// Color matrix mixing RGB with `matrix` and keeping alpha
+pub const fn rgb_color_matrix(matrix: [[f32; 3]; 3]) -> ColorMatrix {
+    let [r, g, b] = matrix;
+    [
+        [r[0], r[1], r[2], 0.0, 0.0],
+        [g[0], g[1], g[2], 0.0, 0.0],
+        [b[0], b[1], b[2], 0.0, 0.0],
+        [0.0, 0.0, 0.0, 1.0, 0.0],
+    ]
+}
+
+/// Mix channels with a 4x5 affine color matrix
+///
+/// Grayscale, sepia, channel swaps and tints are all special cases, e.g. a
+/// red/blue swap is `[[0,0,1,0,0], [0,1,0,0,0], [1,0,0,0,0], [0,0,0,1,0]]`.
+pub fn color_matrix(image: &RgbaImage, matrix: ColorMatrix) -> RgbaImage {
+    let mut result = image.clone();
+    result
+        .par_chunks_mut(4)
+        .for_each(|pixel| apply_color_matrix(pixel, &matrix));
+    result
+}
+
+// Apply `matrix` to one RGBA pixel; channels with an identity row are
+// reproduced exactly
+#[inline]
+pub(crate) fn apply_color_matrix(pixel: &mut [u8], matrix: &ColorMatrix) {
+    let input = [
+        pixel[0] as f32,
+        pixel[1] as f32,
+        pixel[2] as f32,
+        pixel[3] as f32,
+    ];
+    for (out, row) in pixel.iter_mut().zip(matrix) {
+        let value = row[0] * input[0]
+            + row[1] * input[1]
+            + row[2] * input[2]
+            + row[3] * input[3]
+            + row[4] * 255.0;
+        *out = value.clamp(0.0, 255.0) as u8;
+    }
+}
+
+/// Apply sepia tone effect
+pub fn sepia(image: &RgbaImage) -> RgbaImage {
+    color_matrix(image, SEPIA_COLOR_MATRIX)
+}
+
+/// Two-color tint: shadows take `shadow_color`, highlights `highlight_color`
+/// and the tones in between a blend of the two
+pub fn duotone(image: &RgbaImage, shadow_color: Rgba<u8>, highlight_color: Rgba<u8>) -> RgbaImage {
+    gradient_map(image, &[(0.0, shadow_color), (1.0, highlight_color)])
+        .expect("two stops at 0 and 1 are always valid")
+}
+
+/// Recolor the image by mapping its luminance through a color ramp
+/// stops: (position 0.0 to 1.0, color) pairs in any order; luminance below
+/// the first or above the last stop takes that stop's color
+///
+/// Luminance uses BT.709 weights as `grayscale` does; colors between stops are
+/// interpolated linearly. The alpha of the image is kept and that of the
+/// stop colors ignored. Three stops give a tritone.
+pub fn gradient_map(image: &RgbaImage, stops: &[(f32, Rgba<u8>)]) -> Result<RgbaImage> {
+    if stops.is_empty() {
+        return Err(PipelineError::InvalidParameter(
+            "gradient map needs at least one stop".to_string(),
+        ));
+    }
+    if let Some((position, _)) = stops.iter().find(|(p, _)| !(0.0..=1.0).contains(p)) {
+        return Err(PipelineError::InvalidParameter(format!(
+            "gradient stop position must be between 0 and 1, got {position}"
+        )));
+    }
+    let mut stops = stops.to_vec();
+    stops.sort_by(|a, b| a.0.total_cmp(&b.0));
+
+    let ramp: Vec<[u8; 3]> = (0..256)
+        .map(|level| {
+            let t = level as f32 / 255.0;
+            let after = stops.partition_point(|(p, _)| *p <= t);
+            let (p0, c0) = stops[after.saturating_sub(1)];
+            let (p1, c1) = stops[after.min(stops.len() - 1)];
+            let mix = if p1 > p0 {
+                ((t - p0) / (p1 - p0)).clamp(0.0, 1.0)
+            } else {
+                0.0
+            };
+            std::array::from_fn(|c| {
+                (c0[c] as f32 + (c1[c] as f32 - c0[c] as f32) * mix).round() as u8
+            })
+        })
+        .collect();
+
+    let mut result = image.clone();
+    result.par_chunks_mut(4).for_each(|pixel| {
+        let gray = GrayscaleMode::Bt709.gray(pixel[0], pixel[1], pixel[2]);
+        pixel[..3].copy_from_slice(&ramp[gray as usize]);
+    });
+    Ok(result)
+}
+
+/// Hues this many degrees outside the target range of `selective_color` are
+/// still partly adjusted, so the edge of the range does not band
+const SELECTIVE_COLOR_FEATHER: f32 = 20.0;
+/// Saturation below which `selective_color` fades out, as the hue of
+/// near-grays is mostly noise
+const SELECTIVE_COLOR_MIN_SATURATION: f32 = 0.2;
+
+/// Adjust only the colors whose hue lies in `target_hue_range`, e.g. make a
+/// sky bluer without touching skin tones
+/// target_hue_range: (start, end) in degrees going up from start, wrapping at
+/// 360, so (330, 30) selects reds
+/// hue_shift: degrees added to the hue
+/// sat_scale: saturation multiplier (1.0 = unchanged)
+/// light_shift: -1.0 to 1.0 added to the HSV value
+///
+/// The adjustment fades out over 20 degrees outside the range and for
+/// low-saturation pixels. Alpha is unchanged.
+pub fn selective_color(
+    image: &RgbaImage,
+    target_hue_range: (f32, f32),
+    hue_shift: f32,
+    sat_scale: f32,
+    light_shift: f32,
+) -> RgbaImage {
+    let (start, end) = target_hue_range;
+    let span = end - start;
+    let weight_of = |hsv: &Hsv| {
+        let inside = span >= 360.0 || (hsv.h - start).rem_euclid(360.0) <= span.rem_euclid(360.0);
+        let hue_weight = if inside {
+            1.0
+        } else {
+            let outside = hue_distance(hsv.h, start).min(hue_distance(hsv.h, end));
+            (1.0 - outside / SELECTIVE_COLOR_FEATHER).max(0.0)
+        };
+        hue_weight * (hsv.s / SELECTIVE_COLOR_MIN_SATURATION).min(1.0)
+    };
+
+    let mut result = image.clone();
+    result.par_chunks_mut(4).for_each(|pixel| {
+        let hsv = Hsv::from_rgb(pixel[0], pixel[1], pixel[2]);
+        let weight = weight_of(&hsv);
+        if weight <= 0.0 {
+            return;
+        }
+        let adjusted = Hsv {
+            h: hsv.h + hue_shift * weight,
+            s: hsv.s * (1.0 + (sat_scale - 1.0) * weight),
+            v: hsv.v + light_shift * weight,
+        };
+        pixel[..3].copy_from_slice(&adjusted.to_rgb());
+    });
+    result
+}
+
+/// Make pixels close to `key_color` transparent (green/blue screen keying)
+/// tolerance: chroma distance (0.0 to 1.0) below which pixels become fully transparent
+/// softness: width (0.0 to 1.0) of the ramp above `tolerance` that is partly transparent
+///
+/// Pixels are compared by their Cb/Cr chroma and luma is ignored, so the key
+/// should be a saturated color. Pixels near the key also lose the key color that
+/// reflected onto them (spill): its dominant channel is limited to the larger of
+/// the other two, fading out at twice the keyed distance. The alpha of
+/// `key_color` is ignored.
+pub fn chroma_key(
+    image: &RgbaImage,
+    key_color: Rgba<u8>,
+    tolerance: f32,
+    softness: f32,
+) -> RgbaImage {
+    let key = chroma(key_color[0], key_color[1], key_color[2]);
+    let dominant = (0..3).max_by_key(|&c| key_color[c]).unwrap_or(1);
+    let keyed = tolerance + softness;
+
+    let mut result = image.clone();
+    result.par_chunks_mut(4).for_each(|pixel| {
+        let (cb, cr) = chroma(pixel[0], pixel[1], pixel[2]);
+        let distance = (cb - key.0).hypot(cr - key.1);
+
+        let opacity = if distance <= tolerance {
+            0.0
+        } else if distance >= keyed {
+            1.0
+        } else {
+            (distance - tolerance) / softness
+        };
+        pixel[3] = (pixel[3] as f32 * opacity) as u8;
+
+        let spill = if keyed > 0.0 {
+            (2.0 - distance / keyed).clamp(0.0, 1.0)
+        } else {
+            0.0
+        };
+        let limit = (0..3)
+            .filter(|&c| c != dominant)
+            .map(|c| pixel[c])
+            .max()
+            .unwrap_or(0);
+        if spill > 0.0 && pixel[dominant] > limit {
+            let excess = (pixel[dominant] - limit) as f32;
+            pixel[dominant] -= (excess * spill) as u8;
+        }
+    });
+    result
+}
+
+// Cb and Cr (BT.601) scaled to -0.5..0.5
+#[inline]
+fn chroma(r: u8, g: u8, b: u8) -> (f32, f32) {
+    let (r, g, b) = (r as f32 / 255.0, g as f32 / 255.0, b as f32 / 255.0);
+    (
+        -0.168_736 * r - 0.331_264 * g + 0.5 * b,
+        0.5 * r - 0.418_688 * g - 0.081_312 * b,
+    )
+}
+
+/// Round the corners to transparency with anti-aliased edges
+/// radius: corner radius in pixels, limited to half the shorter side
+pub fn round_corners(image: &RgbaImage, radius: f32) -> RgbaImage {
+    let (width, height) = (image.width() as f32, image.height() as f32);
+    let radius = radius.max(0.0).min(width.min(height) / 2.0);
+    mask_alpha(image, |x, y| {
+        // Offset from the nearest arc center, positive only in the corner squares
+        let dx = (radius - x).max(x - (width - radius));
+        let dy = (radius - y).max(y - (height - radius));
+        if dx > 0.0 && dy > 0.0 {
+            radius - dx.hypot(dy)
+        } else {
+            f32::INFINITY
+        }
+    })
+}
+
+/// Cut out the largest centered circle, making everything outside it
+/// transparent with an anti-aliased edge
+///
+/// The image keeps its size; crop it to a square first for round avatars.
+pub fn circle_mask(image: &RgbaImage) -> RgbaImage {
+    let (width, height) = (image.width() as f32, image.height() as f32);
+    let radius = width.min(height) / 2.0;
+    mask_alpha(image, |x, y| {
+        radius - (x - width / 2.0).hypot(y - height / 2.0)
+    })
+}
+
+// Scale alpha by the coverage of each pixel, from `inside`: the signed
+// distance of a pixel center to the shape edge, positive inside
+fn mask_alpha(image: &RgbaImage, inside: impl Fn(f32, f32) -> f32 + Sync) -> RgbaImage {
+    let mut result = image.clone();
+    let row_len = (image.width() as usize * 4).max(4);
+    let pixels: &mut [u8] = &mut result;
+    pixels
+        .par_chunks_mut(row_len)
+        .enumerate()
+        .for_each(|(y, row)| {
+            for (x, pixel) in row.chunks_exact_mut(4).enumerate() {
+                let coverage = (inside(x as f32 + 0.5, y as f32 + 0.5) + 0.5).clamp(0.0, 1.0);
+                if coverage < 1.0 {
+                    pixel[3] = (pixel[3] as f32 * coverage).round() as u8;
+                }
+            }
+        });
+    result
+}
+
+/// Pixelate into square blocks of the block's average color
+/// block_size: edge length of each block in pixels (1 = no change)
+pub fn pixelate(image: &RgbaImage, block_size: u32) -> RgbaImage {
+    let block = block_size.max(1) as usize;
+    let width = image.width() as usize;
+    let mut result = image.clone();
+    let band_len = (width * 4 * block).max(4);
+
+    // Each band of `block` rows is independent, so bands run in parallel
+    let pixels: &mut [u8] = &mut result;
+    pixels.par_chunks_mut(band_len).for_each(|band| {
+        let rows = band.len() / (width * 4).max(1);
+        for x0 in (0..width).step_by(block) {
+            let x1 = (x0 + block).min(width);
+            let mut sum = [0u64; 4];
+            for y in 0..rows {
+                for x in x0..x1 {
+                    let i = (y * width + x) * 4;
+                    for c in 0..4 {
+                        sum[c] += band[i + c] as u64;
+                    }
+                }
+            }
+
+            let count = ((x1 - x0) * rows) as u64;
+            let average = sum.map(|v| ((v + count / 2) / count) as u8);
+            for y in 0..rows {
+                for x in x0..x1 {
+                    let i = (y * width + x) * 4;
+                    band[i..i + 4].copy_from_slice(&average);
+                }
+            }
+        }
+    });
+
+    result
+}
+
+/// Add Gaussian noise (film grain)
+/// amount: standard deviation as a fraction of the full range (0.0 to 1.0)
+/// monochrome: use the same noise value for R, G and B
+/// seed: the same seed always produces the same grain
+pub fn add_noise(image: &RgbaImage, amount: f32, monochrome: bool, seed: u64) -> RgbaImage {
+    let mut result = image.clone();
+    let sigma = amount * 255.0;
+    let row_len = (image.width() as usize * 4).max(4);
+
+    // Each row gets its own generator so the output does not depend on scheduling
+    let pixels: &mut [u8] = &mut result;
+    pixels
+        .par_chunks_mut(row_len)
+        .enumerate()
+        .for_each(|(y, row)| {
+            let mut rng = SplitMix64::new(seed ^ (y as u64).wrapping_mul(0xA24B_AED4_963E_E407));
+            for pixel in row.chunks_exact_mut(4) {
+                let shared = rng.next_gaussian();
+                for channel in pixel.iter_mut().take(3) {
+                    let noise = if monochrome {
+                        shared
+                    } else {
+                        rng.next_gaussian()
+                    };
+                    *channel = (*channel as f32 + noise * sigma).round().clamp(0.0, 255.0) as u8;
+                }
+            }
+        });
+
+    result
+}
+
+/// Straighten a scanned document
+/// Estimates the skew with `analysis::estimate_skew` and rotates it back,
+/// filling the exposed corners with the average paper color
+pub fn deskew(image: &RgbaImage) -> RgbaImage {
+    let degrees = crate::analysis::estimate_skew(image);
+    if degrees == 0.0 {
+        return image.clone();
+    }
+    rotate(image, -degrees, crate::analysis::paper_color(image), false)
+}
+
+/// Corner-pin the image: move the corners of `src_quad` onto `dst_quad`
+/// The canvas keeps its size; areas not covered by the warped image are transparent.
+/// Corner order must match between the quads (e.g. clockwise from top-left).
+pub fn warp_perspective(
+    image: &RgbaImage,
+    src_quad: &Quad,
+    dst_quad: &Quad,
+    interpolation: Interpolation,
+) -> Result<RgbaImage> {
+    let transform = Transform::from_quads(src_quad, dst_quad)?;
+    let (width, height) = image.dimensions();
+    geometry::warp(
+        image,
+        &transform,
+        width,
+        height,
+        interpolation,
+        Rgba([0; 4]),
+    )
+}
+
+/// Apply a 2x3 affine matrix `[a, b, c, d, e, f]` in one resampling pass
+/// A source pixel at (x, y) lands at (a*x + b*y + c, d*x + e*y + f); the canvas keeps
+/// its size and uncovered areas are filled with `background`.
+pub fn affine(
+    image: &RgbaImage,
+    matrix: [f32; 6],
+    interpolation: Interpolation,
+    background: Rgba<u8>,
+) -> Result<RgbaImage> {
+    let (width, height) = image.dimensions();
+    geometry::warp(
+        image,
+        &Transform::affine(matrix),
+        width,
+        height,
+        interpolation,
+        background,
+    )
+}
+
+/// Correct radial lens distortion (Brown-Conrady model)
+/// k1, k2, k3: the lens's coefficients of r^2, r^4 and r^6, with r = 1 at the image
+/// corners. Barrel distortion (wide-angle, action cameras) has negative k1, pincushion
+/// positive. Areas the corrected image no longer covers become transparent.
+pub fn lens_correct(image: &RgbaImage, k1: f32, k2: f32, k3: f32) -> RgbaImage {
+    let lens = RadialLens::new(image, k1, k2, k3);
+    let (width, height) = image.dimensions();
+    geometry::remap(
+        image,
+        width,
+        height,
+        Interpolation::Bilinear,
+        Rgba([0; 4]),
+        |x, y| lens.distort(x, y),
+    )
+}
+
+/// Add radial lens distortion; the exact inverse of `lens_correct`
+/// Negative k1 bulges the center outwards for a fisheye look.
+pub fn lens_distort(image: &RgbaImage, k1: f32, k2: f32, k3: f32) -> RgbaImage {
+    let lens = RadialLens::new(image, k1, k2, k3);
+    let (width, height) = image.dimensions();
+    geometry::remap(
+        image,
+        width,
+        height,
+        Interpolation::Bilinear,
+        Rgba([0; 4]),
+        |x, y| lens.undistort(x, y),
+    )
+}
+
+/// Shift the red and blue channels radially against green
+/// red_shift, blue_shift: displacement in pixels at the image corners, shrinking
+/// linearly towards the center. Positive values push the channel outwards to simulate
+/// lateral chromatic aberration; negative values pull it in to correct colored fringes.
+pub fn chromatic_aberration(image: &RgbaImage, red_shift: f32, blue_shift: f32) -> RgbaImage {
+    let (width, height) = image.dimensions();
+    let (cx, cy) = (width as f64 / 2.0, height as f64 / 2.0);
+    let radius = cx.hypot(cy).max(f64::EPSILON);
+
+    // Sample a channel scaled about the center, clamping at the edges so no
+    // background color leaks into the border
+    let scaled_channel = |shift: f32| {
+        let scale = 1.0 + shift as f64 / radius;
+        (shift != 0.0 && scale > 0.0).then(|| {
+            geometry::remap(
+                image,
+                width,
+                height,
+                Interpolation::Bilinear,
+                Rgba([0; 4]),
+                |x, y| {
+                    let sx = cx + (x - cx) / scale;
+                    let sy = cy + (y - cy) / scale;
+                    Some((
+                        sx.clamp(0.5, width as f64 - 0.5),
+                        sy.clamp(0.5, height as f64 - 0.5),
+                    ))
+                },
+            )
+        })
+    };
+    let (red, blue) = (scaled_channel(red_shift), scaled_channel(blue_shift));
+
+    let mut result = image.clone();
+    for (channel, shifted) in [(0, red), (2, blue)] {
+        if let Some(shifted) = shifted {
+            for (pixel, source) in result.pixels_mut().zip(shifted.pixels()) {
+                pixel[channel] = source[channel];
+            }
+        }
+    }
+    result
+}
+
+struct RadialLens {
+    center: (f64, f64),
+    // Distance from the center to a corner, which normalizes r to 0..1
+    radius: f64,
+    k: [f64; 3],
+}
+
+impl RadialLens {
+    fn new(image: &RgbaImage, k1: f32, k2: f32, k3: f32) -> Self {
+        let (cx, cy) = (image.width() as f64 / 2.0, image.height() as f64 / 2.0);
+        Self {
+            center: (cx, cy),
+            radius: cx.hypot(cy).max(f64::EPSILON),
+            k: [k1 as f64, k2 as f64, k3 as f64],
+        }
+    }
+
+    // Radial scale factor at normalized squared radius r2
+    fn factor(&self, r2: f64) -> f64 {
+        1.0 + r2 * (self.k[0] + r2 * (self.k[1] + r2 * self.k[2]))
+    }
+
+    // Where an undistorted point appears in the distorted image
+    fn distort(&self, x: f64, y: f64) -> Option<(f64, f64)> {
+        let (u, v) = (
+            (x - self.center.0) / self.radius,
+            (y - self.center.1) / self.radius,
+        );
+        let f = self.factor(u * u + v * v);
+        // A non-positive factor folds the image through the center
+        (f > 0.0).then_some((
+            self.center.0 + u * f * self.radius,
+            self.center.1 + v * f * self.radius,
+        ))
+    }
+
+    // Inverse of `distort`, solving r * factor(r^2) = r_d for r with Newton's method
+    fn undistort(&self, x: f64, y: f64) -> Option<(f64, f64)> {
+        let (u, v) = (
+            (x - self.center.0) / self.radius,
+            (y - self.center.1) / self.radius,
+        );
+        let rd = u.hypot(v);
+        if rd < 1e-12 {
+            return Some((x, y));
+        }
+
+        let [k1, k2, k3] = self.k;
+        let mut r = rd;
+        for _ in 0..20 {
+            let r2 = r * r;
+            let value = r * self.factor(r2) - rd;
+            let slope = 1.0 + r2 * (3.0 * k1 + r2 * (5.0 * k2 + r2 * 7.0 * k3));
+            if slope <= 0.0 {
+                return None;
+            }
+            let step = value / slope;
+            r -= step;
+            if step.abs() < 1e-9 {
+                break;
+            }
+        }
+        if !(r.is_finite() && r > 0.0) {
+            return None;
+        }
+
+        let scale = r / rd;
+        Some((
+            self.center.0 + u * scale * self.radius,
+            self.center.1 + v * scale * self.radius,
+        ))
+    }
+}
+
+/// Rotate clockwise by an arbitrary angle with bilinear sampling
+/// background: fill for the corners the rotated image no longer covers
+/// expand: grow the canvas to hold the whole rotated image instead of keeping its size
+pub fn rotate(image: &RgbaImage, degrees: f32, background: Rgba<u8>, expand: bool) -> RgbaImage {
+    let (width, height) = image.dimensions();
+    let (new_width, new_height) = if expand {
+        let (sin, cos) = (degrees as f64).to_radians().sin_cos();
+        let bound = |a: u32, b: u32| {
+            // Tolerance keeps right angles from gaining a pixel to rounding error
+            let extent = a as f64 * cos.abs() + b as f64 * sin.abs();
+            ((extent - 1e-6).ceil() as u32).max(1)
+        };
+        (bound(width, height), bound(height, width))
+    } else {
+        (width, height)
+    };
+
+    let transform = Transform::rotation(degrees as f64, width as f64 / 2.0, height as f64 / 2.0)
+        .then(&Transform::translation(
+            (new_width as f64 - width as f64) / 2.0,
+            (new_height as f64 - height as f64) / 2.0,
+        ));
+    // Rotations are always invertible
+    geometry::warp(
+        image,
+        &transform,
+        new_width,
+        new_height,
+        Interpolation::Bilinear,
+        background,
+    )
+    .unwrap()
+}
+
+/// Small deterministic PRNG; quality is ample for visual noise
+pub(crate) struct SplitMix64 {
+    state: u64,
+}
+
+impl SplitMix64 {
+    pub(crate) fn new(seed: u64) -> Self {
+        Self { state: seed }
+    }
+
+    pub(crate) fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+
+    // Uniform in (0, 1], never zero so the logarithm below stays finite
+    pub(crate) fn next_unit(&mut self) -> f32 {
+        ((self.next_u64() >> 40) as f32 + 1.0) / (1u64 << 24) as f32
+    }
+
+    // Standard normal sample via Box-Muller
+    fn next_gaussian(&mut self) -> f32 {
+        let u1 = self.next_unit();
+        let u2 = self.next_unit();
+        (-2.0 * u1.ln()).sqrt() * (TAU * u2).cos()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn create_test_image() -> RgbaImage {
+        ImageBuffer::from_fn(100, 100, |x, y| {
+            Rgba([(x % 256) as u8, (y % 256) as u8, ((x + y) % 256) as u8, 255])
+        })
+    }
+
+    #[test]
+    fn test_grayscale() {
+        let image = create_test_image();
+        let result = grayscale(&image);
+        assert_eq!(result.dimensions(), image.dimensions());
+
+        // Check that all channels are equal (grayscale)
+        for pixel in result.pixels() {
+            assert_eq!(pixel[0], pixel[1]);
+            assert_eq!(pixel[1], pixel[2]);
+        }
+    }
+
+    #[test]
+    fn test_grayscale_modes() {
+        let pixel = |mode| {
+            grayscale_with(&RgbaImage::from_pixel(1, 1, Rgba([200, 100, 50, 9])), mode)[(0, 0)]
+        };
+        assert_eq!(pixel(GrayscaleMode::Bt601), Rgba([124, 124, 124, 9]));
+        assert_eq!(pixel(GrayscaleMode::Bt709), Rgba([117, 117, 117, 9]));
+        assert_eq!(pixel(GrayscaleMode::Average), Rgba([116, 116, 116, 9]));
+        assert_eq!(pixel(GrayscaleMode::Red), Rgba([200, 200, 200, 9]));
+        assert_eq!(pixel(GrayscaleMode::Green), Rgba([100, 100, 100, 9]));
+        assert_eq!(pixel(GrayscaleMode::Blue), Rgba([50, 50, 50, 9]));
+        // Gray stays gray in every weighting, up to truncation
+        for value in 0..=255u8 {
+            for mode in [
+                GrayscaleMode::Bt601,
+                GrayscaleMode::Bt709,
+                GrayscaleMode::Average,
+            ] {
+                let gray = mode.gray(value, value, value);
+                assert!(value - gray <= 1, "{:?} {} {}", mode, value, gray);
+            }
+        }
+        assert_eq!(
+            GrayscaleMode::from_name("BT601"),
+            Some(GrayscaleMode::Bt601)
+        );
+        assert_eq!(GrayscaleMode::from_name("luma"), None);
+    }
+
+    #[test]
+    fn test_brightness() {
+        let image = create_test_image();
+        let brighter = brightness(&image, 0.5);
+        let darker = brightness(&image, -0.5);
+
+        assert_eq!(brighter.dimensions(), image.dimensions());
+        assert_eq!(darker.dimensions(), image.dimensions());
+    }
+
+    #[test]
+    fn test_contrast() {
+        let image = create_test_image();
+        let result = contrast(&image, 1.5);
+        assert_eq!(result.dimensions(), image.dimensions());
+    }
+
+    #[test]
+    fn test_gamma() {
+        let image = create_test_image();
+        assert_eq!(gamma(&image, 1.0).as_raw(), image.as_raw());
+
+        let brighter = gamma(&image, 2.2);
+        let pixel = image.get_pixel(50, 50);
+        assert!(brighter.get_pixel(50, 50)[0] > pixel[0]);
+        assert_eq!(brighter.get_pixel(50, 50)[3], pixel[3]);
+    }
+
+    #[test]
     fn test_blur() {
         let image = create_test_image();
         let result = blur(&image, 2.0);
         assert_eq!(result.dimensions(), image.dimensions());
     }
 
+    #[test]
+    fn test_blur_cancelled() {
+        let image = create_test_image();
+        let token = CancellationToken::new();
+        token.cancel();
+
+        let result = blur_cancellable(&image, 2.0, &token);
+        assert!(matches!(result, Err(crate::PipelineError::Cancelled)));
+    }
+
+    #[test]
+    fn test_color_matrix() {
+        let image = create_test_image();
+        assert_eq!(color_matrix(&image, IDENTITY_COLOR_MATRIX), image);
+        assert_eq!(
+            color_matrix(&image, GrayscaleMode::Bt601.color_matrix()),
+            grayscale_with(&image, GrayscaleMode::Bt601)
+        );
+
+        let pixel = RgbaImage::from_pixel(1, 1, Rgba([200, 100, 50, 128]));
+        let swap = [
+            [0.0, 0.0, 1.0, 0.0, 0.0],
+            [0.0, 1.0, 0.0, 0.0, 0.0],
+            [1.0, 0.0, 0.0, 0.0, 0.0],
+            [0.0, 0.0, 0.0, 1.0, 0.0],
+        ];
+        assert_eq!(
+            color_matrix(&pixel, swap)[(0, 0)],
+            Rgba([50, 100, 200, 128])
+        );
+        // Offsets are fractions of 255, and alpha can be mixed like any channel
+        let tint = [
+            [1.0, 0.0, 0.0, 0.0, 0.2],
+            [0.0, 1.0, 0.0, 0.0, 0.0],
+            [0.0, 0.0, 1.0, 0.0, -1.0],
+            [0.0, 0.0, 0.0, 0.5, 0.5],
+        ];
+        assert_eq!(color_matrix(&pixel, tint)[(0, 0)], Rgba([251, 100, 0, 191]));
+    }
+
+    #[test]
+    fn test_sharpen() {
+        let image = create_test_image();
+        assert_eq!(sharpen(&image, 0.0, 1.0, 0), image);
+        let sharpened = sharpen(&image, SHARPEN_AMOUNT, SHARPEN_RADIUS, 0);
+        assert_ne!(sharpened, image);
+        // Only the strong edge survives a high threshold
+        let step = RgbaImage::from_fn(12, 4, |x, _| {
+            let base = if x < 6 { 40 } else { 200 };
+            let noise = (x % 2) as u8 * 3;
+            Rgba([base + noise, base + noise, base + noise, 255])
+        });
+        let result = sharpen(&step, 2.0, 1.0, 10);
+        assert_eq!(result[(1, 1)], step[(1, 1)]);
+        assert_eq!(result[(10, 1)], step[(10, 1)]);
+        assert!(result[(5, 1)][0] < step[(5, 1)][0]);
+        assert!(result[(6, 1)][0] > step[(6, 1)][0]);
+    }
+
+    #[test]
+    fn test_convolve_3x3() {
+        let image = create_test_image();
+        let identity = [[0.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 0.0]];
+        assert_eq!(convolve_3x3(&image, identity, 0.0), image);
+
+        // Emboss turns flat areas mid-gray and keeps alpha
+        let emboss = [[-1.0, -1.0, 0.0], [-1.0, 0.0, 1.0], [0.0, 1.0, 1.0]];
+        let flat = RgbaImage::from_pixel(5, 4, Rgba([90, 20, 200, 77]));
+        let embossed = convolve_3x3(&flat, emboss, 128.0);
+        assert!(embossed.pixels().all(|p| *p == Rgba([128, 128, 128, 77])));
+        assert_eq!(
+            convolve_3x3(&RgbaImage::new(0, 3), emboss, 0.0).dimensions(),
+            (0, 3)
+        );
+    }
+
+    #[test]
+    fn test_blur_approximate() {
+        let image = create_test_image();
+        let exact = blur(&image, 6.0);
+        let approximate = blur_approximate(&image, 6.0);
+        assert_eq!(approximate.dimensions(), image.dimensions());
+        let max_difference = exact
+            .as_raw()
+            .iter()
+            .zip(approximate.as_raw())
+            .map(|(&a, &b)| (a as i32 - b as i32).abs())
+            .max()
+            .unwrap();
+        assert!(max_difference <= 4, "differs by {}", max_difference);
+
+        // Flat images stay flat and edge cases do not panic
+        let flat = RgbaImage::from_pixel(7, 3, Rgba([10, 20, 30, 255]));
+        assert_eq!(blur_approximate(&flat, 50.0), flat);
+        assert_eq!(
+            blur_approximate(&RgbaImage::new(0, 5), 2.0).dimensions(),
+            (0, 5)
+        );
+        assert_eq!(blur_approximate(&flat, 0.1), flat);
+        assert_eq!(box_radii(0.1, 3), vec![0, 0, 0]);
+    }
+
     #[test]
     fn test_edge_detect() {
         let image = create_test_image();
@@ -350,13 +2152,121 @@ mod tests {
         assert_eq!(result.dimensions(), image.dimensions());
     }
 
+    #[test]
+    fn test_edge_detect_options() {
+        // Vertical edge: black on the left, white on the right
+        let image = RgbaImage::from_fn(8, 6, |x, _| {
+            if x < 4 {
+                Rgba([0, 0, 0, 255])
+            } else {
+                Rgba([255, 255, 255, 255])
+            }
+        });
+        let zero = edge_detect(&image);
+        assert_eq!(zero[(0, 0)], Rgba([0, 0, 0, 0]));
+        assert_eq!(zero[(4, 3)], Rgba([255, 255, 255, 255]));
+
+        let replicate = EdgeOptions {
+            border: EdgeBorder::Replicate,
+            ..EdgeOptions::default()
+        };
+        let result = edge_detect_with(&image, &replicate);
+        // The edge reaches the top and bottom rows, flat areas stay black
+        assert_eq!(result[(4, 0)], Rgba([255, 255, 255, 255]));
+        assert_eq!(result[(4, 5)], Rgba([255, 255, 255, 255]));
+        assert_eq!(result[(0, 0)], Rgba([0, 0, 0, 255]));
+
+        // A left-to-right gradient points at 0 degrees, the middle of the range
+        let direction = edge_detect_with(
+            &image,
+            &EdgeOptions {
+                output: EdgeOutput::Direction,
+                ..replicate
+            },
+        );
+        assert_eq!(direction[(4, 2)][0], 128);
+        assert_eq!(direction[(1, 2)][0], 0);
+
+        let thresholded = edge_detect_with(
+            &RgbaImage::from_fn(8, 6, |x, _| Rgba([x as u8 * 4, 0, 0, 255])),
+            &EdgeOptions {
+                threshold: Some(40),
+                ..replicate
+            },
+        );
+        assert!(thresholded.pixels().all(|p| p[0] == 0));
+    }
+
+    #[test]
+    fn test_edge_detect_tiny_images() {
+        for (width, height) in [(0, 0), (1, 1), (2, 5), (5, 1)] {
+            let image = RgbaImage::from_pixel(width, height, Rgba([9, 9, 9, 255]));
+            assert_eq!(edge_detect(&image).dimensions(), (width, height));
+            let replicate = EdgeOptions {
+                border: EdgeBorder::Replicate,
+                ..EdgeOptions::default()
+            };
+            assert_eq!(
+                edge_detect_with(&image, &replicate).dimensions(),
+                (width, height)
+            );
+        }
+    }
+
     #[test]
     fn test_resize() {
         let image = create_test_image();
-        let result = resize(&image, 50, 50);
+        let result = resize(&image, 50, 50, ResampleFilter::Lanczos3);
         assert_eq!(result.dimensions(), (50, 50));
     }
 
+    #[test]
+    fn test_upscale() {
+        // A hard vertical edge between two flat areas
+        let image: RgbaImage = ImageBuffer::from_fn(12, 4, |x, _| {
+            let v = if x < 6 { 20 } else { 235 };
+            Rgba([v, v, v, 255])
+        });
+        let detail = upscale(&image, 4.0, &UpscaleMethod::Detail).unwrap();
+        let lanczos = upscale(&image, 4.0, &UpscaleMethod::Lanczos).unwrap();
+        assert_eq!(detail.dimensions(), (48, 16));
+        assert_eq!(lanczos.dimensions(), (48, 16));
+
+        // The edge is steeper and, unlike Lanczos, does not ring
+        let transition = |img: &RgbaImage| {
+            (0..48)
+                .filter(|&x| (40..=215).contains(&img[(x, 8)][0]))
+                .count()
+        };
+        assert!(transition(&detail) < transition(&lanczos));
+        assert!(detail.pixels().all(|p| (20..=235).contains(&p[0])));
+        assert!(lanczos.pixels().any(|p| p[0] > 235));
+
+        assert_eq!(upscaled_size(3, 5, 1.5), (5, 8));
+    }
+
+    #[test]
+    fn test_resize_filters() {
+        // 2x2 checker scaled up: nearest keeps only the original colors
+        let image = ImageBuffer::from_fn(2, 2, |x, y| {
+            if (x + y) % 2 == 0 {
+                Rgba([0, 0, 0, 255])
+            } else {
+                Rgba([255, 255, 255, 255])
+            }
+        });
+        let nearest = resize(&image, 8, 8, ResampleFilter::Nearest);
+        assert!(nearest.pixels().all(|p| p[0] == 0 || p[0] == 255));
+
+        let bilinear = resize(&image, 8, 8, ResampleFilter::Bilinear);
+        assert!(bilinear.pixels().any(|p| p[0] != 0 && p[0] != 255));
+
+        for filter in [ResampleFilter::CatmullRom, ResampleFilter::Lanczos3] {
+            assert_eq!(resize(&image, 5, 3, filter).dimensions(), (5, 3));
+            assert_eq!(ResampleFilter::from_name(filter.name()), Some(filter));
+        }
+    }
+
     #[test]
     fn test_invert() {
         let image = create_test_image();
@@ -373,4 +2283,466 @@ mod tests {
         let result = sepia(&image);
         assert_eq!(result.dimensions(), image.dimensions());
     }
+
+    #[test]
+    fn test_duotone_and_gradient_map() {
+        let image = ImageBuffer::from_fn(3, 1, |x, _| match x {
+            0 => Rgba([0, 0, 0, 255]),
+            1 => Rgba([255, 255, 255, 128]),
+            _ => Rgba([128, 128, 128, 255]),
+        });
+        let navy = Rgba([20, 30, 90, 255]);
+        let pink = Rgba([250, 120, 180, 0]);
+        let result = duotone(&image, navy, pink);
+        assert_eq!(result[(0, 0)], navy);
+        // Stop alpha is ignored, the image's kept
+        assert_eq!(result[(1, 0)], Rgba([250, 120, 180, 128]));
+        assert_eq!(result[(2, 0)], Rgba([135, 75, 135, 255]));
+
+        // Outside the stops the ends are held; stops may come in any order
+        let white = Rgba([255, 255, 255, 255]);
+        let tritone = gradient_map(&image, &[(0.75, white), (0.25, navy), (0.5, pink)]).unwrap();
+        assert_eq!(tritone[(0, 0)], navy);
+        assert_eq!(tritone[(1, 0)], Rgba([255, 255, 255, 128]));
+        assert_eq!(tritone[(2, 0)], Rgba([250, 121, 181, 255]));
+
+        assert!(gradient_map(&image, &[]).is_err());
+        assert!(gradient_map(&image, &[(1.5, white)]).is_err());
+    }
+
+    #[test]
+    fn test_selective_color() {
+        let sky = Rgba([90, 150, 230, 255]);
+        let skin = Rgba([225, 170, 140, 200]);
+        let gray = Rgba([120, 120, 120, 255]);
+        let teal = Rgba([40, 180, 160, 255]);
+        let image = ImageBuffer::from_fn(4, 1, |x, _| [sky, skin, gray, teal][x as usize]);
+
+        let result = selective_color(&image, (190.0, 250.0), 10.0, 1.5, 0.0);
+        let before = Hsv::from_rgb(sky[0], sky[1], sky[2]);
+        let after = Hsv::from_rgb(result[(0, 0)][0], result[(0, 0)][1], result[(0, 0)][2]);
+        assert!(after.s > before.s * 1.4, "{:?}", after);
+        assert!((after.h - before.h - 10.0).abs() < 1.0, "{:?}", after);
+        assert_eq!(result[(1, 0)], skin);
+        assert_eq!(result[(2, 0)], gray);
+        // Teal (hue 173) is within the feather, so partly adjusted
+        let feathered = Hsv::from_rgb(result[(3, 0)][0], result[(3, 0)][1], result[(3, 0)][2]);
+        let original = Hsv::from_rgb(teal[0], teal[1], teal[2]);
+        assert!(feathered.s > original.s && feathered.s < original.s * 1.5);
+
+        // A range wrapping through 0 selects reds
+        let reds = selective_color(&image, (350.0, 40.0), 0.0, 1.0, -0.5);
+        assert!(reds[(1, 0)][0] < 150);
+        assert_eq!(reds[(1, 0)][3], 200);
+        assert_eq!(reds[(0, 0)], sky);
+    }
+
+    #[test]
+    fn test_shadows_highlights() {
+        // Dark left half and bright right half, each with some texture
+        let image = ImageBuffer::from_fn(40, 10, |x, y| {
+            let base = if x < 20 { 30 } else { 225 };
+            let v = base + ((x + y) % 2) as u8 * 10;
+            Rgba([v, v, v.saturating_sub(20), 180])
+        });
+        let luma = |p: Rgba<u8>| GrayscaleMode::Bt709.gray(p[0], p[1], p[2]);
+
+        let lifted = shadows_highlights(&image, 0.8, 0.0, 3.0);
+        assert!(luma(lifted[(2, 5)]) > luma(image[(2, 5)]) + 10);
+        assert_eq!(lifted[(37, 5)], image[(37, 5)]);
+        assert_eq!(lifted[(2, 5)][3], 180);
+        // Local texture survives the lift
+        assert!(luma(lifted[(2, 5)]) > luma(lifted[(3, 5)]));
+
+        let recovered = shadows_highlights(&image, 0.0, 0.8, 3.0);
+        assert!(luma(recovered[(37, 5)]) < luma(image[(37, 5)]));
+        assert_eq!(recovered[(2, 5)], image[(2, 5)]);
+
+        let unchanged = shadows_highlights(&image, 0.0, 0.0, 3.0);
+        assert!(unchanged == image);
+    }
+
+    #[test]
+    fn test_chroma_key() {
+        let green = Rgba([0, 255, 0, 255]);
+        let image = ImageBuffer::from_fn(4, 1, |x, _| match x {
+            0 => green,
+            1 => Rgba([30, 200, 40, 255]),
+            2 => Rgba([200, 180, 150, 255]),
+            _ => Rgba([120, 160, 100, 255]),
+        });
+        let result = chroma_key(&image, green, 0.2, 0.1);
+
+        // The screen and a darker shade of it are keyed out
+        assert_eq!(result[(0, 0)][3], 0);
+        assert_eq!(result[(1, 0)][3], 0);
+        // Skin tones are kept as they are
+        assert_eq!(result[(2, 0)], image[(2, 0)]);
+        // A greenish pixel near the screen keeps its alpha but loses the spill
+        assert_eq!(result[(3, 0)][3], 255);
+        assert!(result[(3, 0)][1] < 160);
+        assert_eq!(result[(3, 0)][0], 120);
+
+        let hard = chroma_key(&image, green, 0.2, 0.0);
+        assert!(hard.pixels().all(|p| p[3] == 0 || p[3] == 255));
+    }
+
+    #[test]
+    fn test_round_corners() {
+        let image = RgbaImage::from_pixel(40, 20, Rgba([10, 20, 30, 255]));
+        let result = round_corners(&image, 8.0);
+        assert_eq!(result[(0, 0)][3], 0);
+        assert_eq!(result[(39, 19)][3], 0);
+        assert_eq!(result[(20, 0)], image[(20, 0)]);
+        assert_eq!(result[(0, 10)], image[(0, 10)]);
+        assert_eq!(result[(8, 8)], image[(8, 8)]);
+        // The arc passes through (2.34, 2.34) from the corner: partial coverage
+        // there, fully opaque just inside, and colors are untouched
+        let edge = result[(2, 2)];
+        assert!(edge[3] > 0 && edge[3] < 255, "{edge:?}");
+        assert_eq!(result[(3, 3)][3], 255);
+        assert_eq!(edge.0[..3], [10, 20, 30]);
+        // Symmetric in all four corners
+        assert_eq!(result[(37, 2)], edge);
+        assert_eq!(result[(2, 17)], edge);
+
+        // Oversized radii become a pill shape; zero changes nothing
+        assert!(round_corners(&image, 100.0) == round_corners(&image, 10.0));
+        assert!(round_corners(&image, 0.0) == image);
+    }
+
+    #[test]
+    fn test_circle_mask() {
+        let image = RgbaImage::from_pixel(30, 20, Rgba([200, 100, 50, 128]));
+        let result = circle_mask(&image);
+        assert_eq!(result[(15, 10)], image[(15, 10)]);
+        assert_eq!(result[(15, 1)][3], 128);
+        for (x, y) in [(0, 0), (4, 10), (25, 10), (29, 19)] {
+            assert_eq!(result[(x, y)][3], 0, "{x},{y}");
+        }
+        // Edge pixels are partly covered, scaled from the existing alpha
+        let partial = result[(22, 3)][3];
+        assert!(partial > 0 && partial < 128, "{partial}");
+    }
+
+    #[test]
+    fn test_add_noise_is_deterministic() {
+        let image = create_test_image();
+        let a = add_noise(&image, 0.1, false, 42);
+        let b = add_noise(&image, 0.1, false, 42);
+        let c = add_noise(&image, 0.1, false, 43);
+
+        assert_eq!(a, b);
+        assert_ne!(a, c);
+        assert_ne!(a, image);
+        assert_eq!(add_noise(&image, 0.0, false, 42), image);
+    }
+
+    #[test]
+    fn test_add_noise_monochrome() {
+        let gray = ImageBuffer::from_pixel(32, 32, Rgba([128, 128, 128, 200]));
+        let result = add_noise(&gray, 0.2, true, 7);
+        for pixel in result.pixels() {
+            assert_eq!(pixel[0], pixel[1]);
+            assert_eq!(pixel[1], pixel[2]);
+            assert_eq!(pixel[3], 200);
+        }
+    }
+
+    #[test]
+    fn test_pixelate() {
+        let image = create_test_image();
+        let result = pixelate(&image, 8);
+        assert_eq!(result.dimensions(), image.dimensions());
+
+        // Every pixel in a block shares its color, including the partial edge blocks
+        assert_eq!(result.get_pixel(0, 0), result.get_pixel(7, 7));
+        assert_eq!(result.get_pixel(96, 96), result.get_pixel(99, 99));
+        assert_ne!(result.get_pixel(0, 0), result.get_pixel(8, 0));
+        assert_eq!(pixelate(&image, 1), image);
+    }
+
+    #[test]
+    fn test_thumbnail_fit_modes() {
+        let wide = ImageBuffer::from_pixel(400, 100, Rgba([10, 20, 30, 255]));
+
+        assert_eq!(
+            thumbnail(&wide, 100, 100, FitMode::Contain).dimensions(),
+            (100, 25)
+        );
+        assert_eq!(
+            thumbnail(&wide, 1000, 1000, FitMode::Contain).dimensions(),
+            (400, 100)
+        );
+        assert_eq!(
+            thumbnail(&wide, 100, 100, FitMode::Cover).dimensions(),
+            (100, 100)
+        );
+        assert_eq!(
+            thumbnail(&wide, 800, 800, FitMode::Cover).dimensions(),
+            (800, 800)
+        );
+
+        let fill = Rgba([255, 0, 0, 255]);
+        let padded = thumbnail(&wide, 100, 100, FitMode::Pad(fill));
+        assert_eq!(padded.dimensions(), (100, 100));
+        assert_eq!(*padded.get_pixel(50, 0), fill);
+        assert_eq!(padded.get_pixel(50, 50)[2], 30);
+    }
+
+    #[test]
+    fn test_thumbnail_cover_crops_center() {
+        // Left third red, middle third green, right third blue
+        let image = ImageBuffer::from_fn(300, 100, |x, _| match x / 100 {
+            0 => Rgba([255, 0, 0, 255]),
+            1 => Rgba([0, 255, 0, 255]),
+            _ => Rgba([0, 0, 255, 255]),
+        });
+        let result = thumbnail(&image, 50, 50, FitMode::Cover);
+        let center = result.get_pixel(25, 25);
+        assert!(center[1] > 200 && center[0] < 50 && center[2] < 50);
+    }
+
+    #[test]
+    fn test_extend_canvas() {
+        // One row: 10 20 30
+        let image = ImageBuffer::from_fn(3, 1, |x, _| Rgba([(x as u8 + 1) * 10, 0, 0, 255]));
+        let row = |result: &RgbaImage, y: u32| -> Vec<u8> {
+            (0..result.width()).map(|x| result[(x, y)][0]).collect()
+        };
+
+        let replicated = extend_canvas(&image, 1, 2, 0, 2, PadFill::Replicate).unwrap();
+        assert_eq!(replicated.dimensions(), (7, 2));
+        assert_eq!(row(&replicated, 0), [10, 10, 10, 20, 30, 30, 30]);
+        assert_eq!(row(&replicated, 1), row(&replicated, 0));
+
+        // Borders wider than the image keep reflecting
+        let mirrored = extend_canvas(&image, 0, 4, 0, 4, PadFill::Mirror).unwrap();
+        assert_eq!(
+            row(&mirrored, 0),
+            [30, 30, 20, 10, 10, 20, 30, 30, 20, 10, 10]
+        );
+
+        let red = Rgba([255, 0, 0, 255]);
+        let colored = extend_canvas(&image, 2, 0, 1, 1, PadFill::Color(red)).unwrap();
+        assert_eq!(colored.dimensions(), (4, 4));
+        assert_eq!(colored[(0, 2)], red);
+        assert_eq!(colored[(1, 1)], red);
+        assert_eq!(colored[(1, 2)], image[(0, 0)]);
+        assert_eq!(colored[(3, 3)], red);
+        let transparent = extend_canvas(&image, 0, 1, 0, 0, PadFill::Transparent).unwrap();
+        assert_eq!(transparent[(3, 0)], Rgba([0, 0, 0, 0]));
+
+        // Empty images have nothing to replicate and become plain borders
+        let empty = extend_canvas(&RgbaImage::new(0, 0), 1, 1, 1, 1, PadFill::Mirror).unwrap();
+        assert_eq!(empty.dimensions(), (2, 2));
+        assert!(extend_canvas(&image, 0, u32::MAX, 0, 0, PadFill::Transparent).is_err());
+    }
+
+    #[test]
+    fn test_nine_patch_resize() {
+        // A 12x12 frame: red 3-pixel corners, green edges, a blue center
+        let image = ImageBuffer::from_fn(12, 12, |x, y| {
+            let border = |v: u32| !(3..9).contains(&v);
+            match (border(x), border(y)) {
+                (true, true) => Rgba([255, 0, 0, 255]),
+                (false, false) => Rgba([0, 0, 255, 128]),
+                _ => Rgba([0, 255, 0, 255]),
+            }
+        });
+        let result = nine_patch_resize(&image, Insets::uniform(3), 40, 20).unwrap();
+        assert_eq!(result.dimensions(), (40, 20));
+        for (x, y) in [(0, 0), (2, 2), (37, 0), (39, 19), (0, 17)] {
+            assert_eq!(result[(x, y)], Rgba([255, 0, 0, 255]), "corner at {x},{y}");
+        }
+        // Edges stretch only along their length and never pick up corner color
+        for (x, y) in [(3, 0), (20, 2), (36, 19), (0, 3), (39, 10), (2, 16)] {
+            assert_eq!(result[(x, y)], Rgba([0, 255, 0, 255]), "edge at {x},{y}");
+        }
+        assert_eq!(result[(20, 10)], Rgba([0, 0, 255, 128]));
+
+        // Shrinking down to the corners alone drops the center
+        let corners = nine_patch_resize(&image, Insets::uniform(3), 6, 6).unwrap();
+        assert!(corners.pixels().all(|p| *p == Rgba([255, 0, 0, 255])));
+        assert!(nine_patch_resize(&image, Insets::uniform(3), 5, 20).is_err());
+        assert!(nine_patch_resize(&image, Insets::uniform(6), 40, 20).is_err());
+    }
+
+    #[test]
+    fn test_seam_carve_dimensions() {
+        let image = create_test_image();
+        assert_eq!(seam_carve(&image, 70, 100).dimensions(), (70, 100));
+        assert_eq!(seam_carve(&image, 100, 60).dimensions(), (100, 60));
+        assert_eq!(seam_carve(&image, 180, 130).dimensions(), (180, 130));
+    }
+
+    #[test]
+    fn test_seam_carve_preserves_detail() {
+        // Flat background with one dark vertical line; shrinking must keep the line
+        let image = ImageBuffer::from_fn(60, 20, |x, _| {
+            if x == 15 {
+                Rgba([0, 0, 0, 255])
+            } else {
+                Rgba([200, 200, 200, 255])
+            }
+        });
+        let result = seam_carve(&image, 30, 20);
+        let line_kept = (0..30).any(|x| (0..20).all(|y| result.get_pixel(x, y)[0] == 0));
+        assert!(line_kept);
+    }
+
+    #[test]
+    fn test_seam_carve_cancelled() {
+        let token = CancellationToken::new();
+        token.cancel();
+        let result = seam_carve_cancellable(&create_test_image(), 50, 50, &token);
+        assert!(matches!(result, Err(crate::PipelineError::Cancelled)));
+    }
+
+    #[test]
+    fn test_deskew() {
+        // Dark text-like lines on off-white paper, tilted 4 degrees clockwise
+        let page = ImageBuffer::from_fn(400, 300, |x, y| {
+            let inside = (50..350).contains(&x) && (40..260).contains(&y);
+            if inside && y % 24 < 6 {
+                Rgba([20, 20, 20, 255])
+            } else {
+                Rgba([250, 248, 240, 255])
+            }
+        });
+        let tilted = rotate(&page, 4.0, Rgba([250, 248, 240, 255]), false);
+        assert!((crate::analysis::estimate_skew(&tilted) - 4.0).abs() <= 0.15);
+
+        let straightened = deskew(&tilted);
+        assert_eq!(straightened.dimensions(), page.dimensions());
+        assert!(crate::analysis::estimate_skew(&straightened).abs() <= 0.15);
+        // Exposed corners are filled with the paper color, not transparency
+        assert_eq!(*straightened.get_pixel(0, 0), Rgba([250, 248, 240, 255]));
+    }
+
+    #[test]
+    fn test_warp_perspective() {
+        // Pin a skewed quad holding a white square onto the full canvas
+        let image = ImageBuffer::from_fn(100, 100, |x, y| {
+            if (20..60).contains(&x) && (30..70).contains(&y) {
+                Rgba([255, 255, 255, 255])
+            } else {
+                Rgba([0, 0, 0, 255])
+            }
+        });
+        let src = [(20.0, 30.0), (60.0, 30.0), (60.0, 70.0), (20.0, 70.0)];
+        let dst = [(0.0, 0.0), (100.0, 0.0), (100.0, 100.0), (0.0, 100.0)];
+        let result = warp_perspective(&image, &src, &dst, Interpolation::Bilinear).unwrap();
+        assert_eq!(result.dimensions(), (100, 100));
+        // The outermost row and column blend with the border at the quad's edge
+        let inside = (1..99).all(|y| (1..99).all(|x| result.get_pixel(x, y)[0] == 255));
+        assert!(inside);
+
+        let degenerate = [(0.0, 0.0); 4];
+        assert!(warp_perspective(&image, &degenerate, &dst, Interpolation::Nearest).is_err());
+    }
+
+    #[test]
+    fn test_rotate() {
+        let image = ImageBuffer::from_fn(60, 40, |x, _| {
+            if x < 30 {
+                Rgba([255, 0, 0, 255])
+            } else {
+                Rgba([0, 0, 255, 255])
+            }
+        });
+        let background = Rgba([0, 255, 0, 255]);
+
+        // A quarter turn clockwise moves the left half to the top
+        let quarter = rotate(&image, 90.0, background, true);
+        assert_eq!(quarter.dimensions(), (40, 60));
+        assert_eq!(*quarter.get_pixel(20, 5), Rgba([255, 0, 0, 255]));
+        assert_eq!(*quarter.get_pixel(20, 55), Rgba([0, 0, 255, 255]));
+
+        let same_size = rotate(&image, 30.0, background, false);
+        assert_eq!(same_size.dimensions(), (60, 40));
+        assert_eq!(*same_size.get_pixel(0, 0), background);
+
+        let expanded = rotate(&image, 30.0, background, true);
+        assert_eq!(expanded.dimensions(), (72, 65));
+        assert_eq!(rotate(&image, 0.0, background, true), image);
+    }
+
+    #[test]
+    fn test_affine() {
+        let image = create_test_image();
+        let background = Rgba([0, 0, 0, 0]);
+
+        // Pure translation by whole pixels is lossless
+        let shifted = affine(
+            &image,
+            [1.0, 0.0, 5.0, 0.0, 1.0, 3.0],
+            Interpolation::Bilinear,
+            background,
+        )
+        .unwrap();
+        assert_eq!(shifted.get_pixel(5, 3), image.get_pixel(0, 0));
+        assert_eq!(shifted.get_pixel(99, 99), image.get_pixel(94, 96));
+        assert_eq!(*shifted.get_pixel(2, 2), background);
+
+        // Doubling the size shows the top-left quarter
+        let zoomed = affine(
+            &image,
+            [2.0, 0.0, 0.0, 0.0, 2.0, 0.0],
+            Interpolation::Nearest,
+            background,
+        )
+        .unwrap();
+        assert_eq!(zoomed.get_pixel(41, 61), image.get_pixel(20, 30));
+
+        let singular = [1.0, 2.0, 0.0, 2.0, 4.0, 0.0];
+        assert!(affine(&image, singular, Interpolation::Bilinear, background).is_err());
+    }
+
+    #[test]
+    fn test_lens_correct() {
+        let image = create_test_image();
+        assert_eq!(lens_correct(&image, 0.0, 0.0, 0.0), image);
+
+        // Barrel correction pulls the edges outwards, leaving the center in place
+        let corrected = lens_correct(&image, -0.2, -0.05, 0.0);
+        assert_eq!(corrected.get_pixel(50, 50), image.get_pixel(50, 50));
+        assert_ne!(corrected.get_pixel(90, 50), image.get_pixel(90, 50));
+
+        // Distorting then correcting restores the interior
+        let round_trip = lens_correct(&lens_distort(&image, -0.2, -0.05, 0.0), -0.2, -0.05, 0.0);
+        for (x, y) in [(30, 30), (50, 70), (65, 40)] {
+            let (a, b) = (round_trip.get_pixel(x, y), image.get_pixel(x, y));
+            assert!((0..3).all(|c| a[c].abs_diff(b[c]) <= 2), "{a:?} vs {b:?}");
+        }
+    }
+
+    #[test]
+    fn test_chromatic_aberration() {
+        let image = create_test_image();
+        assert_eq!(chromatic_aberration(&image, 0.0, 0.0), image);
+
+        // White dot left of center on black: red moves outwards (left), blue inwards
+        let dot = ImageBuffer::from_fn(101, 101, |x, y| {
+            if (20..=22).contains(&x) && (49..=51).contains(&y) {
+                Rgba([255, 255, 255, 255])
+            } else {
+                Rgba([0, 0, 0, 255])
+            }
+        });
+        let result = chromatic_aberration(&dot, 4.0, -4.0);
+        let centroid = |c: usize| {
+            let weights = (0..101).map(|x| result.get_pixel(x, 50)[c] as f32);
+            let total: f32 = weights.clone().sum();
+            weights.enumerate().map(|(x, w)| x as f32 * w).sum::<f32>() / total
+        };
+        assert!(centroid(0) < 20.0);
+        assert_eq!(centroid(1), 21.0);
+        assert!(centroid(2) > 22.0);
+        // Alpha and green are untouched
+        assert!(result
+            .pixels()
+            .zip(dot.pixels())
+            .all(|(a, b)| a[1] == b[1] && a[3] == b[3]));
+    }
 }
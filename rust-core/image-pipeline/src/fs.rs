@@ -0,0 +1,231 @@
+//! Processing images on disk, for native targets (`fs` feature)
+//!
+//! Files are read, processed, encoded and written on the pipeline's rayon
+//! pool, so `ImagePipeline::thread_count` sets the number of workers.
+
+use crate::{in_pool, FilterOperation, ImagePipeline, OutputFormat, PipelineError, Result};
+use rayon::prelude::*;
+use std::path::{Path, PathBuf};
+
+/// Which files `process_dir` picks up and how results are written
+#[derive(Debug, Clone)]
+pub struct DirOptions {
+    /// Glob pattern for file names, e.g. "*.jpg" or "IMG_????.png"
+    pub pattern: String,
+    /// Also look in subdirectories; outputs mirror the input tree
+    pub recursive: bool,
+    /// Encoding of the output files, which also sets their extension
+    pub format: OutputFormat,
+    /// JPEG quality (1-100)
+    pub quality: u8,
+}
+
+impl Default for DirOptions {
+    fn default() -> Self {
+        Self {
+            pattern: "*".to_string(),
+            recursive: false,
+            format: OutputFormat::Png,
+            quality: 90,
+        }
+    }
+}
+
+/// Outcome of `process_dir`
+#[derive(Debug, Default)]
+pub struct DirReport {
+    /// Output files written, sorted by path
+    pub written: Vec<PathBuf>,
+    /// Input files that could not be processed, with the reason
+    pub failed: Vec<(PathBuf, PipelineError)>,
+}
+
+/// Read an image, run `operations` on it and write the encoded result
+///
+/// Missing parent directories of `out_path` are created.
+pub fn process_file(
+    pipeline: &ImagePipeline,
+    path: impl AsRef<Path>,
+    operations: &[FilterOperation],
+    out_path: impl AsRef<Path>,
+    format: OutputFormat,
+    quality: u8,
+) -> Result<()> {
+    let bytes = std::fs::read(path)?;
+    let image = pipeline.decode(&bytes)?;
+    let result = pipeline.process(&image, operations)?;
+    let encoded = ImagePipeline::encode(&result, format, quality)?;
+
+    let out_path = out_path.as_ref();
+    if let Some(parent) = out_path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    std::fs::write(out_path, encoded)?;
+    Ok(())
+}
+
+/// Process every file in `input_dir` matching `options.pattern` into `output_dir`
+///
+/// Outputs keep their path relative to `input_dir` with the extension of
+/// `options.format`. Invalid operations fail the whole call; a file that
+/// cannot be read, decoded or written is reported in `DirReport::failed` and
+/// the rest are still processed.
+pub fn process_dir(
+    pipeline: &ImagePipeline,
+    input_dir: impl AsRef<Path>,
+    output_dir: impl AsRef<Path>,
+    operations: &[FilterOperation],
+    options: &DirOptions,
+) -> Result<DirReport> {
+    let (input_dir, output_dir) = (input_dir.as_ref(), output_dir.as_ref());
+    ImagePipeline::validate(operations)?;
+    pipeline.check_custom_filters(operations)?;
+    if !input_dir.is_dir() {
+        return Err(PipelineError::InvalidParameter(format!(
+            "{} is not a directory",
+            input_dir.display()
+        )));
+    }
+
+    let mut pattern = glob::Pattern::escape(&input_dir.to_string_lossy());
+    if options.recursive {
+        pattern.push_str("/**");
+    }
+    pattern.push('/');
+    pattern.push_str(&options.pattern);
+
+    let mut inputs = Vec::new();
+    for entry in glob::glob(&pattern).map_err(|e| {
+        PipelineError::InvalidParameter(format!("invalid pattern \"{}\": {}", options.pattern, e))
+    })? {
+        let path = entry.map_err(|e| PipelineError::IoError(e.into()))?;
+        // Earlier outputs are not inputs when the output directory is inside the input one
+        if path.is_file() && !path.starts_with(output_dir) {
+            inputs.push(path);
+        }
+    }
+
+    let pool = pipeline.thread_pool()?;
+    let outcomes: Vec<(PathBuf, Result<PathBuf>)> = in_pool(&pool, || {
+        inputs
+            .into_par_iter()
+            .map(|path| {
+                let relative = path.strip_prefix(input_dir).unwrap_or(&path);
+                let out_path = output_dir
+                    .join(relative)
+                    .with_extension(options.format.extension());
+                let result = process_file(
+                    pipeline,
+                    &path,
+                    operations,
+                    &out_path,
+                    options.format,
+                    options.quality,
+                )
+                .map(|()| out_path);
+                (path, result)
+            })
+            .collect()
+    });
+
+    let mut report = DirReport::default();
+    for (path, result) in outcomes {
+        match result {
+            Ok(out_path) => report.written.push(out_path),
+            Err(e) => report.failed.push((path, e)),
+        }
+    }
+    report.written.sort();
+    report.failed.sort_by(|a, b| a.0.cmp(&b.0));
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::GrayscaleMode;
+    use image::{Rgba, RgbaImage};
+
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("image-pipeline-fs-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn write_png(path: &Path, value: u8) {
+        std::fs::create_dir_all(path.parent().unwrap()).unwrap();
+        let image = RgbaImage::from_pixel(6, 4, Rgba([value, value, value, 255]));
+        std::fs::write(path, ImagePipeline::encode_to_png(&image).unwrap()).unwrap();
+    }
+
+    #[test]
+    fn test_process_file() {
+        let dir = temp_dir("file");
+        write_png(&dir.join("in.png"), 10);
+        let out = dir.join("nested/out.png");
+        let pipeline = ImagePipeline::new();
+        process_file(
+            &pipeline,
+            dir.join("in.png"),
+            &[FilterOperation::Invert],
+            &out,
+            OutputFormat::Png,
+            90,
+        )
+        .unwrap();
+        let result = image::open(&out).unwrap().to_rgba8();
+        assert_eq!(result.get_pixel(0, 0)[0], 245);
+
+        let missing = process_file(
+            &pipeline,
+            dir.join("missing.png"),
+            &[],
+            &out,
+            OutputFormat::Png,
+            90,
+        );
+        assert!(matches!(missing, Err(PipelineError::IoError(_))));
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_process_dir() {
+        let dir = temp_dir("dir");
+        let input = dir.join("in");
+        write_png(&input.join("a.png"), 10);
+        write_png(&input.join("sub/b.png"), 20);
+        std::fs::write(input.join("broken.png"), b"not a png").unwrap();
+        std::fs::write(input.join("notes.txt"), b"skip me").unwrap();
+
+        let pipeline = ImagePipeline::with_threads(2);
+        let ops = [FilterOperation::Grayscale(GrayscaleMode::Bt709)];
+        let options = DirOptions {
+            pattern: "*.png".to_string(),
+            format: OutputFormat::Jpeg,
+            ..DirOptions::default()
+        };
+        let report = process_dir(&pipeline, &input, dir.join("flat"), &ops, &options).unwrap();
+        assert_eq!(report.written, vec![dir.join("flat/a.jpg")]);
+        assert_eq!(report.failed.len(), 1);
+        assert!(report.failed[0].0.ends_with("broken.png"));
+
+        // Writing into the input tree does not pick up earlier outputs
+        let recursive = DirOptions {
+            recursive: true,
+            ..options.clone()
+        };
+        let out = input.join("out");
+        for _ in 0..2 {
+            let report = process_dir(&pipeline, &input, &out, &ops, &recursive).unwrap();
+            assert_eq!(
+                report.written,
+                vec![out.join("a.jpg"), out.join("sub/b.jpg")]
+            );
+        }
+
+        assert!(process_dir(&pipeline, dir.join("nope"), &out, &ops, &options).is_err());
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
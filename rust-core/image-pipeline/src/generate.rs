@@ -0,0 +1,276 @@
+//! Procedural image generators
+//!
+//! Backgrounds for compositing, QR codes, and deterministic inputs for tests and
+//! benchmarks.
+
+use crate::{PipelineError, Result};
+use image::{ImageBuffer, Rgba, RgbaImage};
+use rayon::prelude::*;
+
+/// Image filled with a single color
+pub fn solid(width: u32, height: u32, color: Rgba<u8>) -> RgbaImage {
+    ImageBuffer::from_pixel(width, height, color)
+}
+
+/// Linear gradient from `from` to `to`
+/// angle: direction in degrees, 0 = left to right, 90 = top to bottom
+pub fn linear_gradient(
+    width: u32,
+    height: u32,
+    from: Rgba<u8>,
+    to: Rgba<u8>,
+    angle: f32,
+) -> RgbaImage {
+    let (sin, cos) = angle.to_radians().sin_cos();
+    let (cx, cy) = (width as f32 / 2.0, height as f32 / 2.0);
+    // Half the length of the image's projection onto the gradient direction
+    let half_extent = ((cx * cos).abs() + (cy * sin).abs()).max(f32::EPSILON);
+
+    from_fn_parallel(width, height, |x, y| {
+        let dx = x as f32 + 0.5 - cx;
+        let dy = y as f32 + 0.5 - cy;
+        let t = ((dx * cos + dy * sin) / half_extent + 1.0) / 2.0;
+        lerp_color(from, to, t)
+    })
+}
+
+/// Radial gradient from `inner` at the center to `outer` at the farthest corner
+pub fn radial_gradient(width: u32, height: u32, inner: Rgba<u8>, outer: Rgba<u8>) -> RgbaImage {
+    let (cx, cy) = (width as f32 / 2.0, height as f32 / 2.0);
+    let radius = (cx * cx + cy * cy).sqrt().max(f32::EPSILON);
+
+    from_fn_parallel(width, height, |x, y| {
+        let dx = x as f32 + 0.5 - cx;
+        let dy = y as f32 + 0.5 - cy;
+        lerp_color(inner, outer, (dx * dx + dy * dy).sqrt() / radius)
+    })
+}
+
+/// Checkerboard of `cell_size` squares, starting with `a` in the top-left corner
+pub fn checkerboard(
+    width: u32,
+    height: u32,
+    cell_size: u32,
+    a: Rgba<u8>,
+    b: Rgba<u8>,
+) -> RgbaImage {
+    let cell = cell_size.max(1);
+    from_fn_parallel(width, height, |x, y| {
+        if (x / cell + y / cell).is_multiple_of(2) {
+            a
+        } else {
+            b
+        }
+    })
+}
+
+/// Test pattern: color bars on top, a gray ramp below and a fine checkerboard strip
+///
+/// Covers saturated colors, every gray level and high-frequency detail, which
+/// exercises point filters, resampling and convolutions respectively.
+pub fn test_pattern(width: u32, height: u32) -> RgbaImage {
+    const BARS: [[u8; 3]; 8] = [
+        [255, 255, 255],
+        [255, 255, 0],
+        [0, 255, 255],
+        [0, 255, 0],
+        [255, 0, 255],
+        [255, 0, 0],
+        [0, 0, 255],
+        [0, 0, 0],
+    ];
+    let bars_end = height * 2 / 3;
+    let ramp_end = height * 5 / 6;
+
+    from_fn_parallel(width, height, |x, y| {
+        if y < bars_end {
+            let bar = (x as usize * BARS.len()) / width as usize;
+            let [r, g, b] = BARS[bar];
+            Rgba([r, g, b, 255])
+        } else if y < ramp_end {
+            let v = (x as u64 * 255 / (width as u64 - 1).max(1)) as u8;
+            Rgba([v, v, v, 255])
+        } else if (x + y).is_multiple_of(2) {
+            Rgba([255, 255, 255, 255])
+        } else {
+            Rgba([0, 0, 0, 255])
+        }
+    })
+}
+
+/// How much of a QR code can be damaged and still decode
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum QrErrorCorrection {
+    /// About 7% of the code
+    Low,
+    /// About 15%
+    #[default]
+    Medium,
+    /// About 25%
+    Quartile,
+    /// About 30%, enough for a small logo over the center
+    High,
+}
+
+impl QrErrorCorrection {
+    /// Parse "low", "medium", "quartile", "high" or their initials L/M/Q/H
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "low" | "l" => Some(QrErrorCorrection::Low),
+            "medium" | "m" => Some(QrErrorCorrection::Medium),
+            "quartile" | "q" => Some(QrErrorCorrection::Quartile),
+            "high" | "h" => Some(QrErrorCorrection::High),
+            _ => None,
+        }
+    }
+}
+
+/// Module colors of a generated QR code
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QrColors {
+    pub dark: Rgba<u8>,
+    /// Background, including the quiet zone; transparent works for overlays
+    pub light: Rgba<u8>,
+}
+
+impl Default for QrColors {
+    fn default() -> Self {
+        Self {
+            dark: Rgba([0, 0, 0, 255]),
+            light: Rgba([255, 255, 255, 255]),
+        }
+    }
+}
+
+/// Square `size` x `size` QR code encoding `text`
+///
+/// Modules are whole pixels so the code stays crisp; whatever does not divide
+/// evenly goes into the margin around the 4-module quiet zone. Fails if `text`
+/// does not fit in a QR code or `size` leaves less than one pixel per module.
+pub fn qr_code(
+    text: &str,
+    size: u32,
+    ec_level: QrErrorCorrection,
+    colors: QrColors,
+) -> Result<RgbaImage> {
+    let level = match ec_level {
+        QrErrorCorrection::Low => qrcode::EcLevel::L,
+        QrErrorCorrection::Medium => qrcode::EcLevel::M,
+        QrErrorCorrection::Quartile => qrcode::EcLevel::Q,
+        QrErrorCorrection::High => qrcode::EcLevel::H,
+    };
+    let code = qrcode::QrCode::with_error_correction_level(text, level)
+        .map_err(|e| PipelineError::InvalidParameter(format!("cannot encode QR code: {}", e)))?;
+    let modules = code.width() as u32;
+    let total = modules + 8;
+    let scale = size / total;
+    if scale == 0 {
+        return Err(PipelineError::InvalidParameter(format!(
+            "QR code needs {} modules with its quiet zone; size {} is too small",
+            total, size
+        )));
+    }
+    let offset = (size - modules * scale) / 2;
+    let dark = code.to_colors();
+
+    Ok(from_fn_parallel(size, size, |x, y| {
+        let (mx, my) = (
+            x.wrapping_sub(offset) / scale,
+            y.wrapping_sub(offset) / scale,
+        );
+        let inside = x >= offset && y >= offset && mx < modules && my < modules;
+        if inside && dark[(my * modules + mx) as usize] == qrcode::Color::Dark {
+            colors.dark
+        } else {
+            colors.light
+        }
+    }))
+}
+
+fn lerp_color(from: Rgba<u8>, to: Rgba<u8>, t: f32) -> Rgba<u8> {
+    let t = t.clamp(0.0, 1.0);
+    Rgba(std::array::from_fn(|c| {
+        (from[c] as f32 + (to[c] as f32 - from[c] as f32) * t).round() as u8
+    }))
+}
+
+// Like `ImageBuffer::from_fn`, but fills rows in parallel
+fn from_fn_parallel<F>(width: u32, height: u32, f: F) -> RgbaImage
+where
+    F: Fn(u32, u32) -> Rgba<u8> + Sync,
+{
+    let mut image = RgbaImage::new(width, height);
+    let row_len = (width as usize * 4).max(4);
+    let pixels: &mut [u8] = &mut image;
+    pixels
+        .par_chunks_mut(row_len)
+        .enumerate()
+        .for_each(|(y, row)| {
+            for (x, pixel) in row.chunks_exact_mut(4).enumerate() {
+                pixel.copy_from_slice(&f(x as u32, y as u32).0);
+            }
+        });
+    image
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BLACK: Rgba<u8> = Rgba([0, 0, 0, 255]);
+    const WHITE: Rgba<u8> = Rgba([255, 255, 255, 255]);
+
+    #[test]
+    fn test_linear_gradient_direction() {
+        let horizontal = linear_gradient(100, 10, BLACK, WHITE, 0.0);
+        assert!(horizontal.get_pixel(0, 5)[0] < 5);
+        assert!(horizontal.get_pixel(99, 5)[0] > 250);
+        assert_eq!(horizontal.get_pixel(50, 0), horizontal.get_pixel(50, 9));
+
+        let vertical = linear_gradient(10, 100, BLACK, WHITE, 90.0);
+        assert!(vertical.get_pixel(5, 0)[0] < vertical.get_pixel(5, 99)[0]);
+    }
+
+    #[test]
+    fn test_radial_gradient_center() {
+        let image = radial_gradient(64, 64, WHITE, BLACK);
+        assert!(image.get_pixel(32, 32)[0] > 250);
+        assert!(image.get_pixel(0, 0)[0] < 10);
+    }
+
+    #[test]
+    fn test_checkerboard_and_pattern() {
+        let board = checkerboard(8, 8, 2, BLACK, WHITE);
+        assert_eq!(*board.get_pixel(0, 0), BLACK);
+        assert_eq!(*board.get_pixel(2, 0), WHITE);
+        assert_eq!(*board.get_pixel(2, 2), BLACK);
+
+        let pattern = test_pattern(80, 60);
+        assert_eq!(pattern.dimensions(), (80, 60));
+        assert_eq!(*pattern.get_pixel(0, 0), WHITE);
+        assert_eq!(*solid(3, 3, WHITE).get_pixel(2, 2), WHITE);
+    }
+
+    #[test]
+    fn test_qr_code_round_trips() {
+        let colors = QrColors {
+            dark: Rgba([20, 40, 90, 255]),
+            light: Rgba([0, 0, 0, 0]),
+        };
+        let image = qr_code("poster #7", 200, QrErrorCorrection::High, colors).unwrap();
+        assert_eq!(image.dimensions(), (200, 200));
+        assert_eq!(*image.get_pixel(0, 0), colors.light);
+        let codes = crate::analysis::decode_qr(&image);
+        assert_eq!(codes.len(), 1);
+        assert_eq!(codes[0].text(), Some("poster #7"));
+
+        // Version 1 is 21 modules plus an 8-module quiet zone
+        assert!(qr_code("hi", 28, QrErrorCorrection::Low, QrColors::default()).is_err());
+        assert!(qr_code("hi", 29, QrErrorCorrection::Low, QrColors::default()).is_ok());
+        assert!(qr_code(&"x".repeat(5000), 4096, QrErrorCorrection::Low, colors).is_err());
+        assert_eq!(
+            QrErrorCorrection::from_name("Q"),
+            Some(QrErrorCorrection::Quartile)
+        );
+    }
+}
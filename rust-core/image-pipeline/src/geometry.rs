@@ -0,0 +1,399 @@
+//! Geometric transforms: projective matrices and an inverse-mapping warp engine
+//!
+//! Coordinates are continuous, with pixel `(x, y)` covering `x..x+1` and
+//! `y..y+1`, so the image center is `(width / 2, height / 2)`.
+
+use crate::{PipelineError, Result};
+use image::{Rgba, RgbaImage};
+use rayon::prelude::*;
+
+/// Corner points of a quadrilateral, in order around its edge
+pub type Quad = [(f32, f32); 4];
+
+/// How pixels are sampled between source pixel centers
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Interpolation {
+    /// Nearest pixel, no blending
+    Nearest,
+    /// Linear blend of the 2x2 neighbourhood
+    #[default]
+    Bilinear,
+    /// Catmull-Rom spline over the 4x4 neighbourhood
+    Bicubic,
+}
+
+impl Interpolation {
+    /// Parse an interpolation name ("nearest", "bilinear", "bicubic")
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "nearest" => Some(Interpolation::Nearest),
+            "bilinear" | "linear" => Some(Interpolation::Bilinear),
+            "bicubic" | "cubic" => Some(Interpolation::Bicubic),
+            _ => None,
+        }
+    }
+
+    /// Canonical name of the interpolation
+    pub fn name(&self) -> &'static str {
+        match self {
+            Interpolation::Nearest => "nearest",
+            Interpolation::Bilinear => "bilinear",
+            Interpolation::Bicubic => "bicubic",
+        }
+    }
+}
+
+/// A 3x3 projective transform mapping source points to destination points
+///
+/// Affine transforms are the special case whose last row is `[0, 0, 1]`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Transform {
+    pub matrix: [[f64; 3]; 3],
+}
+
+impl Default for Transform {
+    fn default() -> Self {
+        Self::identity()
+    }
+}
+
+impl Transform {
+    /// The transform that leaves every point in place
+    pub fn identity() -> Self {
+        Self {
+            matrix: [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]],
+        }
+    }
+
+    /// Move every point by `(dx, dy)`
+    pub fn translation(dx: f64, dy: f64) -> Self {
+        Self {
+            matrix: [[1.0, 0.0, dx], [0.0, 1.0, dy], [0.0, 0.0, 1.0]],
+        }
+    }
+
+    /// Affine transform from a row-major 2x3 matrix `[a, b, c, d, e, f]`,
+    /// mapping `(x, y)` to `(a*x + b*y + c, d*x + e*y + f)`
+    pub fn affine(matrix: [f32; 6]) -> Self {
+        let m = matrix.map(f64::from);
+        Self {
+            matrix: [[m[0], m[1], m[2]], [m[3], m[4], m[5]], [0.0, 0.0, 1.0]],
+        }
+    }
+
+    /// Rotate clockwise (on screen, y pointing down) by `degrees` about `(cx, cy)`
+    pub fn rotation(degrees: f64, cx: f64, cy: f64) -> Self {
+        let (sin, cos) = degrees.to_radians().sin_cos();
+        Self::translation(-cx, -cy)
+            .then(&Self {
+                matrix: [[cos, -sin, 0.0], [sin, cos, 0.0], [0.0, 0.0, 1.0]],
+            })
+            .then(&Self::translation(cx, cy))
+    }
+
+    /// The homography taking each corner of `src` to the same corner of `dst`
+    ///
+    /// Fails when either quad is degenerate (three corners on one line).
+    pub fn from_quads(src: &Quad, dst: &Quad) -> Result<Self> {
+        // Eight equations in the eight unknowns h00..h21, with h22 = 1
+        let mut system = [[0.0f64; 9]; 8];
+        for (i, (&(x, y), &(u, v))) in src.iter().zip(dst).enumerate() {
+            let (x, y, u, v) = (x as f64, y as f64, u as f64, v as f64);
+            system[2 * i] = [x, y, 1.0, 0.0, 0.0, 0.0, -u * x, -u * y, u];
+            system[2 * i + 1] = [0.0, 0.0, 0.0, x, y, 1.0, -v * x, -v * y, v];
+        }
+
+        let h = solve(system).ok_or_else(|| {
+            PipelineError::InvalidParameter(
+                "quad corners must not be collinear or coincident".to_string(),
+            )
+        })?;
+        Ok(Self {
+            matrix: [[h[0], h[1], h[2]], [h[3], h[4], h[5]], [h[6], h[7], 1.0]],
+        })
+    }
+
+    /// Apply `self`, then `next`
+    pub fn then(&self, next: &Transform) -> Transform {
+        let (a, b) = (&next.matrix, &self.matrix);
+        let mut matrix = [[0.0; 3]; 3];
+        for (r, row) in matrix.iter_mut().enumerate() {
+            for (c, value) in row.iter_mut().enumerate() {
+                *value = (0..3).map(|k| a[r][k] * b[k][c]).sum();
+            }
+        }
+        Transform { matrix }
+    }
+
+    /// The transform undoing this one, or `None` if it is singular
+    pub fn inverse(&self) -> Option<Transform> {
+        let m = &self.matrix;
+        let cofactor = |r0: usize, r1: usize, c0: usize, c1: usize| {
+            m[r0][c0] * m[r1][c1] - m[r0][c1] * m[r1][c0]
+        };
+        let adjugate = [
+            [
+                cofactor(1, 2, 1, 2),
+                -cofactor(0, 2, 1, 2),
+                cofactor(0, 1, 1, 2),
+            ],
+            [
+                -cofactor(1, 2, 0, 2),
+                cofactor(0, 2, 0, 2),
+                -cofactor(0, 1, 0, 2),
+            ],
+            [
+                cofactor(1, 2, 0, 1),
+                -cofactor(0, 2, 0, 1),
+                cofactor(0, 1, 0, 1),
+            ],
+        ];
+        let determinant: f64 = (0..3).map(|c| m[0][c] * adjugate[c][0]).sum();
+        if !determinant.is_finite() || determinant.abs() < 1e-12 {
+            return None;
+        }
+        Some(Transform {
+            matrix: adjugate.map(|row| row.map(|v| v / determinant)),
+        })
+    }
+
+    /// Map a point; `None` when it lands on the line at infinity
+    pub fn apply(&self, x: f64, y: f64) -> Option<(f64, f64)> {
+        let m = &self.matrix;
+        let w = m[2][0] * x + m[2][1] * y + m[2][2];
+        if w.abs() < 1e-12 {
+            return None;
+        }
+        Some((
+            (m[0][0] * x + m[0][1] * y + m[0][2]) / w,
+            (m[1][0] * x + m[1][1] * y + m[1][2]) / w,
+        ))
+    }
+}
+
+/// Resample `image` through `transform` onto a `width` x `height` canvas
+///
+/// Every output pixel is mapped back through the inverse transform and sampled
+/// from the source; samples outside the source blend towards `background`.
+pub fn warp(
+    image: &RgbaImage,
+    transform: &Transform,
+    width: u32,
+    height: u32,
+    interpolation: Interpolation,
+    background: Rgba<u8>,
+) -> Result<RgbaImage> {
+    let inverse = transform
+        .inverse()
+        .ok_or_else(|| PipelineError::InvalidParameter("transform is not invertible".into()))?;
+    Ok(remap(
+        image,
+        width,
+        height,
+        interpolation,
+        background,
+        |x, y| inverse.apply(x, y),
+    ))
+}
+
+/// Resample `image` onto a `width` x `height` canvas through an arbitrary mapping
+///
+/// `source` receives the continuous coordinates of each output pixel center and
+/// returns where to sample the source image, or `None` to use `background`.
+/// This is the engine behind `warp` and non-linear corrections such as lens distortion.
+pub fn remap<F>(
+    image: &RgbaImage,
+    width: u32,
+    height: u32,
+    interpolation: Interpolation,
+    background: Rgba<u8>,
+    source: F,
+) -> RgbaImage
+where
+    F: Fn(f64, f64) -> Option<(f64, f64)> + Sync,
+{
+    let sampler = Sampler { image, background };
+
+    let mut result = RgbaImage::new(width, height);
+    let row_len = (width as usize * 4).max(4);
+    let pixels: &mut [u8] = &mut result;
+    pixels
+        .par_chunks_mut(row_len)
+        .enumerate()
+        .for_each(|(y, row)| {
+            for (x, pixel) in row.chunks_exact_mut(4).enumerate() {
+                let color = match source(x as f64 + 0.5, y as f64 + 0.5) {
+                    // Shift from continuous coordinates to pixel-center coordinates
+                    Some((sx, sy)) => sampler.sample(sx - 0.5, sy - 0.5, interpolation),
+                    None => background.0.map(f32::from),
+                };
+                for (channel, value) in pixel.iter_mut().zip(color) {
+                    *channel = value.round().clamp(0.0, 255.0) as u8;
+                }
+            }
+        });
+
+    result
+}
+
+struct Sampler<'a> {
+    image: &'a RgbaImage,
+    background: Rgba<u8>,
+}
+
+impl Sampler<'_> {
+    fn texel(&self, x: i64, y: i64) -> [f32; 4] {
+        let (width, height) = self.image.dimensions();
+        if x < 0 || y < 0 || x >= width as i64 || y >= height as i64 {
+            self.background.0.map(f32::from)
+        } else {
+            self.image.get_pixel(x as u32, y as u32).0.map(f32::from)
+        }
+    }
+
+    fn sample(&self, x: f64, y: f64, interpolation: Interpolation) -> [f32; 4] {
+        // Far outside the source; also keeps the casts below in range
+        let (width, height) = self.image.dimensions();
+        if !(-2.0..width as f64 + 2.0).contains(&x) || !(-2.0..height as f64 + 2.0).contains(&y) {
+            return self.background.0.map(f32::from);
+        }
+
+        let (x0, y0) = (x.floor(), y.floor());
+        let (fx, fy) = ((x - x0) as f32, (y - y0) as f32);
+        let (x0, y0) = (x0 as i64, y0 as i64);
+        match interpolation {
+            Interpolation::Nearest => self.texel(x.round() as i64, y.round() as i64),
+            Interpolation::Bilinear => {
+                let weights_x = [1.0 - fx, fx];
+                let weights_y = [1.0 - fy, fy];
+                self.blend(x0, y0, &weights_x, &weights_y)
+            }
+            Interpolation::Bicubic => {
+                let color = self.blend(x0 - 1, y0 - 1, &catmull_rom(fx), &catmull_rom(fy));
+                color.map(|v| v.clamp(0.0, 255.0))
+            }
+        }
+    }
+
+    // Weighted sum over the grid of texels starting at (x0, y0)
+    fn blend(&self, x0: i64, y0: i64, weights_x: &[f32], weights_y: &[f32]) -> [f32; 4] {
+        let mut color = [0.0f32; 4];
+        for (j, wy) in weights_y.iter().enumerate() {
+            for (i, wx) in weights_x.iter().enumerate() {
+                let texel = self.texel(x0 + i as i64, y0 + j as i64);
+                for (sum, value) in color.iter_mut().zip(texel) {
+                    *sum += value * wx * wy;
+                }
+            }
+        }
+        color
+    }
+}
+
+// Catmull-Rom weights of the four taps around a sample at offset `t` (0..1)
+fn catmull_rom(t: f32) -> [f32; 4] {
+    let (t2, t3) = (t * t, t * t * t);
+    [
+        0.5 * (-t3 + 2.0 * t2 - t),
+        0.5 * (3.0 * t3 - 5.0 * t2 + 2.0),
+        0.5 * (-3.0 * t3 + 4.0 * t2 + t),
+        0.5 * (t3 - t2),
+    ]
+}
+
+// Gaussian elimination with partial pivoting on an augmented 8x9 system
+fn solve(mut system: [[f64; 9]; 8]) -> Option<[f64; 8]> {
+    for col in 0..8 {
+        let pivot = (col..8).max_by(|&a, &b| {
+            system[a][col]
+                .abs()
+                .partial_cmp(&system[b][col].abs())
+                .unwrap_or(std::cmp::Ordering::Equal)
+        })?;
+        if system[pivot][col].abs() < 1e-10 {
+            return None;
+        }
+        system.swap(col, pivot);
+        for row in 0..8 {
+            if row != col {
+                let factor = system[row][col] / system[col][col];
+                let pivot_row = system[col];
+                for (value, pivot_value) in system[row].iter_mut().zip(pivot_row).skip(col) {
+                    *value -= factor * pivot_value;
+                }
+            }
+        }
+    }
+
+    let mut h = [0.0; 8];
+    for (i, value) in h.iter_mut().enumerate() {
+        *value = system[i][8] / system[i][i];
+    }
+    Some(h)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn close(a: (f64, f64), b: (f64, f64)) -> bool {
+        (a.0 - b.0).abs() < 1e-6 && (a.1 - b.1).abs() < 1e-6
+    }
+
+    #[test]
+    fn test_from_quads_maps_corners() {
+        let src = [(10.0, 12.0), (90.0, 5.0), (95.0, 80.0), (3.0, 70.0)];
+        let dst = [(0.0, 0.0), (100.0, 0.0), (100.0, 100.0), (0.0, 100.0)];
+        let transform = Transform::from_quads(&src, &dst).unwrap();
+        for (s, d) in src.iter().zip(&dst) {
+            let mapped = transform.apply(s.0 as f64, s.1 as f64).unwrap();
+            assert!(close(mapped, (d.0 as f64, d.1 as f64)), "{mapped:?}");
+        }
+
+        let inverse = transform.inverse().unwrap();
+        let round_trip = transform.then(&inverse).apply(42.0, 17.0).unwrap();
+        assert!(close(round_trip, (42.0, 17.0)));
+
+        let collinear = [(0.0, 0.0), (1.0, 1.0), (2.0, 2.0), (0.0, 5.0)];
+        assert!(Transform::from_quads(&collinear, &dst).is_err());
+    }
+
+    #[test]
+    fn test_rotation() {
+        // A quarter turn clockwise about the center of a 10x10 canvas
+        let transform = Transform::rotation(90.0, 5.0, 5.0);
+        assert!(close(transform.apply(10.0, 5.0).unwrap(), (5.0, 10.0)));
+    }
+
+    #[test]
+    fn test_warp_identity_and_translation() {
+        let image = RgbaImage::from_fn(16, 12, |x, y| Rgba([x as u8 * 10, y as u8 * 10, 7, 255]));
+        for interpolation in [
+            Interpolation::Nearest,
+            Interpolation::Bilinear,
+            Interpolation::Bicubic,
+        ] {
+            let same = warp(
+                &image,
+                &Transform::identity(),
+                16,
+                12,
+                interpolation,
+                Rgba([0; 4]),
+            );
+            assert_eq!(same.unwrap(), image, "{}", interpolation.name());
+        }
+
+        let shifted = warp(
+            &image,
+            &Transform::translation(3.0, 2.0),
+            16,
+            12,
+            Interpolation::Bilinear,
+            Rgba([0; 4]),
+        )
+        .unwrap();
+        assert_eq!(shifted.get_pixel(3, 2), image.get_pixel(0, 0));
+        assert_eq!(*shifted.get_pixel(1, 1), Rgba([0; 4]));
+    }
+}
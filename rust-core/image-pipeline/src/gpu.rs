@@ -0,0 +1,488 @@
+//! GPU backend running the WGSL kernels in `shaders/` through wgpu
+//!
+//! Gaussian blur, bilinear upscaling and single point operations run on the
+//! GPU; every other operation, and images too large for one storage buffer
+//! or dispatch, run on `CpuBackend`. Images with transparency in the default
+//! straight alpha mode are blurred and resized by the CPU kernels, which
+//! weight colors by alpha. The kernels truncate like the CPU
+//! filters, but devices may fuse multiply-adds, so results can differ from
+//! the CPU by one level per channel.
+
+use crate::backend::{Backend, CpuBackend};
+use crate::filters::{self, ColorMatrix, ResampleFilter, SEPIA_COLOR_MATRIX};
+use crate::{CancellationToken, FilterOperation, Lut256, PipelineError, Result};
+use image::RgbaImage;
+use std::sync::{mpsc, Arc, OnceLock};
+use wgpu::util::DeviceExt;
+
+// Workgroup sizes declared in the shaders
+const TILE: u32 = 16;
+const POINT_GROUP: u32 = 256;
+
+/// Operations dispatched to compute shaders on the default wgpu adapter
+pub struct GpuBackend {
+    device: wgpu::Device,
+    queue: wgpu::Queue,
+    adapter_name: String,
+    convolve_1d: wgpu::ComputePipeline,
+    kernel_3x3: wgpu::ComputePipeline,
+    resize: wgpu::ComputePipeline,
+    point: wgpu::ComputePipeline,
+}
+
+impl GpuBackend {
+    /// Open the default adapter, failing with `PipelineError::ProcessingError`
+    /// when there is none or it cannot run compute shaders
+    pub fn new() -> Result<Self> {
+        pollster::block_on(Self::open())
+    }
+
+    /// Backend on the default adapter shared by the whole process, or `None`
+    /// without a usable GPU; the device is opened on first use
+    pub fn shared() -> Option<Arc<GpuBackend>> {
+        static SHARED: OnceLock<Option<Arc<GpuBackend>>> = OnceLock::new();
+        SHARED
+            .get_or_init(|| GpuBackend::new().ok().map(Arc::new))
+            .clone()
+    }
+
+    /// Name the driver reports for the adapter
+    pub fn adapter_name(&self) -> &str {
+        &self.adapter_name
+    }
+
+    async fn open() -> Result<Self> {
+        let instance = wgpu::Instance::default();
+        let adapter = instance
+            .request_adapter(&wgpu::RequestAdapterOptions {
+                power_preference: wgpu::PowerPreference::HighPerformance,
+                ..Default::default()
+            })
+            .await
+            .map_err(gpu_error)?;
+        if !adapter
+            .get_downlevel_capabilities()
+            .flags
+            .contains(wgpu::DownlevelFlags::COMPUTE_SHADERS)
+        {
+            return Err(PipelineError::ProcessingError(
+                "GPU adapter does not support compute shaders".to_string(),
+            ));
+        }
+
+        let (device, queue) = adapter
+            .request_device(&wgpu::DeviceDescriptor {
+                label: Some("image-pipeline"),
+                // The adapter's own limits allow the largest buffers it can
+                required_limits: adapter.limits(),
+                ..Default::default()
+            })
+            .await
+            .map_err(gpu_error)?;
+
+        let pipeline = |label: &str, source: &str| {
+            let module = device.create_shader_module(wgpu::ShaderModuleDescriptor {
+                label: Some(label),
+                source: wgpu::ShaderSource::Wgsl(source.into()),
+            });
+            device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+                label: Some(label),
+                layout: None,
+                module: &module,
+                entry_point: Some("main"),
+                compilation_options: Default::default(),
+                cache: None,
+            })
+        };
+        let convolve_1d = pipeline("convolve_1d", include_str!("../shaders/convolve_1d.wgsl"));
+        let kernel_3x3 = pipeline("kernel_3x3", include_str!("../shaders/kernel_3x3.wgsl"));
+        let resize = pipeline("resize", include_str!("../shaders/resize.wgsl"));
+        let point = pipeline("point", include_str!("../shaders/point.wgsl"));
+
+        Ok(Self {
+            adapter_name: adapter.get_info().name,
+            device,
+            queue,
+            convolve_1d,
+            kernel_3x3,
+            resize,
+            point,
+        })
+    }
+
+    /// `filters::convolve_3x3` on the GPU, or on the CPU if the image is too large
+    pub fn convolve_3x3(
+        &self,
+        image: &RgbaImage,
+        kernel: [[f32; 3]; 3],
+        bias: f32,
+    ) -> Result<RgbaImage> {
+        let (width, height) = image.dimensions();
+        if !self.fits(width, height, TILE) {
+            return Ok(filters::convolve_3x3(image, kernel, bias));
+        }
+
+        let mut params = vec![width, height, bias.to_bits(), 0];
+        for row in kernel {
+            params.extend(row.map(f32::to_bits));
+            params.push(0);
+        }
+        let params = self.uniform(&params);
+        let source = self.storage(image.as_raw());
+        let output = self.output(image.as_raw().len());
+        let bind_group = self.bind(&self.kernel_3x3, &[&params, &source, &output]);
+        let groups = [width.div_ceil(TILE), height.div_ceil(TILE)];
+        self.run(
+            &self.kernel_3x3,
+            &[(bind_group, groups)],
+            &output,
+            width,
+            height,
+        )
+    }
+
+    fn blur(&self, image: &RgbaImage, sigma: f32) -> Result<RgbaImage> {
+        let (width, height) = image.dimensions();
+        let radius = (sigma * 3.0).ceil() as i32;
+        let weights: Vec<u8> = filters::create_gaussian_kernel(radius, sigma)
+            .iter()
+            .flat_map(|w| w.to_le_bytes())
+            .collect();
+
+        let weights = self.storage(&weights);
+        let source = self.storage(image.as_raw());
+        let between = self.output(image.as_raw().len());
+        let output = self.output(image.as_raw().len());
+        let horizontal = self.uniform(&[width, height, radius as u32, 1]);
+        let vertical = self.uniform(&[width, height, radius as u32, 0]);
+        let groups = [width.div_ceil(TILE), height.div_ceil(TILE)];
+        let passes = [
+            (
+                self.bind(
+                    &self.convolve_1d,
+                    &[&horizontal, &weights, &source, &between],
+                ),
+                groups,
+            ),
+            (
+                self.bind(&self.convolve_1d, &[&vertical, &weights, &between, &output]),
+                groups,
+            ),
+        ];
+        self.run(&self.convolve_1d, &passes, &output, width, height)
+    }
+
+    fn upscale_bilinear(&self, image: &RgbaImage, width: u32, height: u32) -> Result<RgbaImage> {
+        let params = self.uniform(&[image.width(), image.height(), width, height]);
+        let source = self.storage(image.as_raw());
+        let output = self.output(crate::rgba_len(width, height)?);
+        let bind_group = self.bind(&self.resize, &[&params, &source, &output]);
+        let groups = [width.div_ceil(TILE), height.div_ceil(TILE)];
+        self.run(
+            &self.resize,
+            &[(bind_group, groups)],
+            &output,
+            width,
+            height,
+        )
+    }
+
+    fn point(
+        &self,
+        image: &RgbaImage,
+        lut: &Lut256,
+        matrix: Option<ColorMatrix>,
+    ) -> Result<RgbaImage> {
+        let (width, height) = image.dimensions();
+        let mut params = vec![width, height, matrix.is_some() as u32, 0];
+        for row in matrix
+            .unwrap_or(filters::IDENTITY_COLOR_MATRIX)
+            .iter()
+            .take(3)
+        {
+            params.extend([row[0], row[1], row[2], 0.0].map(f32::to_bits));
+        }
+        let tables: Vec<u8> = (0..3)
+            .flat_map(|c| lut.channel(c).iter())
+            .flat_map(|&v| (v as u32).to_le_bytes())
+            .collect();
+
+        let params = self.uniform(&params);
+        let tables = self.storage(&tables);
+        let pixels = self.storage(image.as_raw());
+        let bind_group = self.bind(&self.point, &[&params, &tables, &pixels]);
+        let groups = [(width * height).div_ceil(POINT_GROUP), 1];
+        self.run(&self.point, &[(bind_group, groups)], &pixels, width, height)
+    }
+
+    // Whether a `width`x`height` image fits in one storage buffer and a
+    // dispatch of `group`-wide workgroups
+    fn fits(&self, width: u32, height: u32, group: u32) -> bool {
+        let limits = self.device.limits();
+        let bytes = width as u64 * height as u64 * 4;
+        let groups = if group == POINT_GROUP {
+            (width as u64 * height as u64).div_ceil(group as u64)
+        } else {
+            width.max(height).div_ceil(group) as u64
+        };
+        bytes > 0
+            && bytes <= limits.max_storage_buffer_binding_size
+            && groups <= limits.max_compute_workgroups_per_dimension as u64
+    }
+
+    fn uniform(&self, words: &[u32]) -> wgpu::Buffer {
+        let contents: Vec<u8> = words.iter().flat_map(|w| w.to_le_bytes()).collect();
+        self.device
+            .create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: None,
+                contents: &contents,
+                usage: wgpu::BufferUsages::UNIFORM,
+            })
+    }
+
+    fn storage(&self, contents: &[u8]) -> wgpu::Buffer {
+        self.device
+            .create_buffer_init(&wgpu::util::BufferInitDescriptor {
+                label: None,
+                contents,
+                usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+            })
+    }
+
+    fn output(&self, len: usize) -> wgpu::Buffer {
+        self.device.create_buffer(&wgpu::BufferDescriptor {
+            label: None,
+            size: len as u64,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_SRC,
+            mapped_at_creation: false,
+        })
+    }
+
+    // Bind `buffers` to bindings 0, 1, ... of group 0
+    fn bind(&self, pipeline: &wgpu::ComputePipeline, buffers: &[&wgpu::Buffer]) -> wgpu::BindGroup {
+        let entries: Vec<_> = buffers
+            .iter()
+            .enumerate()
+            .map(|(binding, buffer)| wgpu::BindGroupEntry {
+                binding: binding as u32,
+                resource: buffer.as_entire_binding(),
+            })
+            .collect();
+        self.device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: None,
+            layout: &pipeline.get_bind_group_layout(0),
+            entries: &entries,
+        })
+    }
+
+    // Dispatch `passes` in order and read `output` back as a `width`x`height` image
+    fn run(
+        &self,
+        pipeline: &wgpu::ComputePipeline,
+        passes: &[(wgpu::BindGroup, [u32; 2])],
+        output: &wgpu::Buffer,
+        width: u32,
+        height: u32,
+    ) -> Result<RgbaImage> {
+        let size = output.size();
+        let readback = self.device.create_buffer(&wgpu::BufferDescriptor {
+            label: None,
+            size,
+            usage: wgpu::BufferUsages::MAP_READ | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let mut encoder = self
+            .device
+            .create_command_encoder(&wgpu::CommandEncoderDescriptor { label: None });
+        for (bind_group, [x, y]) in passes {
+            let mut pass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor::default());
+            pass.set_pipeline(pipeline);
+            pass.set_bind_group(0, bind_group, &[]);
+            pass.dispatch_workgroups(*x, *y, 1);
+        }
+        encoder.copy_buffer_to_buffer(output, 0, &readback, 0, size);
+        self.queue.submit([encoder.finish()]);
+
+        let (sender, receiver) = mpsc::channel();
+        readback.map_async(wgpu::MapMode::Read, .., move |result| {
+            let _ = sender.send(result);
+        });
+        self.device
+            .poll(wgpu::PollType::wait_indefinitely())
+            .map_err(gpu_error)?;
+        receiver.recv().map_err(gpu_error)?.map_err(gpu_error)?;
+
+        let pixels = readback.get_mapped_range(..).map_err(gpu_error)?.to_vec();
+        readback.unmap();
+        Ok(RgbaImage::from_raw(width, height, pixels).expect("buffer holds the whole image"))
+    }
+}
+
+impl std::fmt::Debug for GpuBackend {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("GpuBackend")
+            .field("adapter_name", &self.adapter_name)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Backend for GpuBackend {
+    fn name(&self) -> &str {
+        "gpu"
+    }
+
+    // Bilinear downscales are claimed too, but run on the CPU: the shader
+    // samples 2x2 pixels and would alias where the CPU filter widens
+    fn supports(&self, op: &FilterOperation) -> bool {
+        matches!(
+            op,
+            FilterOperation::Blur(_)
+                | FilterOperation::Resize {
+                    filter: ResampleFilter::Bilinear,
+                    ..
+                }
+        ) || point_program(op).is_some()
+    }
+
+    fn apply(
+        &self,
+        image: &RgbaImage,
+        op: &FilterOperation,
+        token: &CancellationToken,
+    ) -> Result<RgbaImage> {
+        token.check()?;
+        let (width, height) = image.dimensions();
+        let result = match *op {
+            FilterOperation::Blur(sigma) if self.fits(width, height, TILE) => {
+                self.blur(image, sigma)?
+            }
+            FilterOperation::Resize {
+                width: new_width,
+                height: new_height,
+                filter: ResampleFilter::Bilinear,
+            } if new_width >= width
+                && new_height >= height
+                && self.fits(width, height, TILE)
+                && self.fits(new_width, new_height, TILE) =>
+            {
+                self.upscale_bilinear(image, new_width, new_height)?
+            }
+            _ => match point_program(op) {
+                Some((lut, matrix)) if self.fits(width, height, POINT_GROUP) => {
+                    self.point(image, &lut, matrix)?
+                }
+                _ => return CpuBackend.apply(image, op, token),
+            },
+        };
+        token.check()?;
+        Ok(result)
+    }
+}
+
+// Tables and RGB matrix of `point.wgsl` reproducing a point operation
+fn point_program(op: &FilterOperation) -> Option<(Lut256, Option<ColorMatrix>)> {
+    match op {
+        FilterOperation::Grayscale(mode) => Some((Lut256::identity(), Some(mode.color_matrix()))),
+        FilterOperation::Sepia => Some((Lut256::identity(), Some(SEPIA_COLOR_MATRIX))),
+        _ => op.to_lut().map(|lut| (lut, None)),
+    }
+}
+
+fn gpu_error(error: impl std::fmt::Display) -> PipelineError {
+    PipelineError::ProcessingError(format!("GPU: {}", error))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::testing::{compare, Tolerance};
+    use crate::GrayscaleMode;
+    use image::Rgba;
+
+    // Within one level of the CPU, allowing for fused multiply-adds
+    const TOLERANCE: Tolerance = Tolerance {
+        per_channel: 1,
+        outlier_fraction: 0.0,
+        mean_luma: 0.5,
+    };
+
+    fn gpu() -> Option<Arc<GpuBackend>> {
+        let gpu = GpuBackend::shared();
+        if gpu.is_none() {
+            eprintln!("no GPU adapter with compute shaders, skipping");
+        }
+        gpu
+    }
+
+    fn create_test_image() -> RgbaImage {
+        RgbaImage::from_fn(45, 31, |x, y| {
+            Rgba([(x * 6) as u8, (y * 8) as u8, ((x * y) % 256) as u8, 200])
+        })
+    }
+
+    #[test]
+    fn test_gpu_matches_cpu() {
+        let Some(gpu) = gpu() else { return };
+        let image = create_test_image();
+        let token = CancellationToken::new();
+        for op in [
+            FilterOperation::Blur(0.8),
+            FilterOperation::Blur(3.0),
+            FilterOperation::Resize {
+                width: 100,
+                height: 64,
+                filter: ResampleFilter::Bilinear,
+            },
+            FilterOperation::Grayscale(GrayscaleMode::Bt709),
+            FilterOperation::Sepia,
+            FilterOperation::Brightness(-0.3),
+            FilterOperation::Contrast(1.4),
+            FilterOperation::Gamma(2.2),
+            FilterOperation::Invert,
+        ] {
+            assert!(gpu.supports(&op), "{op:?}");
+            let expected = CpuBackend.apply(&image, &op, &token).unwrap();
+            let actual = gpu.apply(&image, &op, &token).unwrap();
+            if let Err(reason) = compare(&actual, &expected, TOLERANCE) {
+                panic!("{op:?} on {}: {reason}", gpu.adapter_name());
+            }
+        }
+
+        let emboss = [[-1.0, -1.0, 0.0], [-1.0, 0.0, 1.0], [0.0, 1.0, 1.0]];
+        let expected = filters::convolve_3x3(&image, emboss, 128.0);
+        let actual = gpu.convolve_3x3(&image, emboss, 128.0).unwrap();
+        compare(&actual, &expected, TOLERANCE).unwrap();
+    }
+
+    #[test]
+    fn test_cpu_fallback() {
+        let Some(gpu) = gpu() else { return };
+        let image = create_test_image();
+        let token = CancellationToken::new();
+        // Unsupported operations, downscales and empty images run on the CPU
+        for (image, op) in [
+            (image.clone(), FilterOperation::Pixelate(4)),
+            (
+                image.clone(),
+                FilterOperation::Resize {
+                    width: 20,
+                    height: 12,
+                    filter: ResampleFilter::Bilinear,
+                },
+            ),
+            (RgbaImage::new(0, 4), FilterOperation::Blur(1.0)),
+        ] {
+            assert_eq!(
+                gpu.apply(&image, &op, &token).unwrap(),
+                CpuBackend.apply(&image, &op, &token).unwrap()
+            );
+        }
+
+        token.cancel();
+        assert!(matches!(
+            gpu.apply(&image, &FilterOperation::Invert, &token),
+            Err(PipelineError::Cancelled)
+        ));
+    }
+}
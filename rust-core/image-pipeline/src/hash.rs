@@ -0,0 +1,13 @@
+//! SHA-256 helpers for cache keys and content digests
+
+pub use sha2::{Digest, Sha256};
+
+/// SHA-256 of `data`
+pub fn sha256(data: &[u8]) -> [u8; 32] {
+    Sha256::digest(data).into()
+}
+
+/// Lowercase hex encoding
+pub fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
@@ -0,0 +1,61 @@
+//! Scalar reference kernels, built only with the `bench-internals` feature
+//!
+//! Each function computes exactly what its counterpart in `simd` does, one
+//! pixel at a time on the calling thread, so benchmarks can measure what the
+//! vectorized and parallel paths actually buy. Not part of the stable API.
+
+use crate::GrayscaleMode;
+
+/// Sequential counterpart of `simd::grayscale_fast`
+pub fn grayscale_scalar(pixels: &mut [u8], mode: GrayscaleMode) {
+    for pixel in pixels.chunks_exact_mut(4) {
+        let gray = mode.gray(pixel[0], pixel[1], pixel[2]);
+        pixel[0] = gray;
+        pixel[1] = gray;
+        pixel[2] = gray;
+    }
+}
+
+/// Sequential counterpart of `simd::brightness_simd`
+pub fn brightness_scalar(pixels: &mut [u8], adjustment: i16) {
+    for pixel in pixels.chunks_exact_mut(4) {
+        for channel in &mut pixel[..3] {
+            *channel = (*channel as i16 + adjustment).clamp(0, 255) as u8;
+        }
+    }
+}
+
+/// Sequential counterpart of `simd::invert_simd`
+pub fn invert_scalar(pixels: &mut [u8]) {
+    for pixel in pixels.chunks_exact_mut(4) {
+        for channel in &mut pixel[..3] {
+            *channel = 255 - *channel;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::simd;
+
+    #[test]
+    fn test_scalar_matches_simd() {
+        let pixels: Vec<u8> = (0..4096u32).map(|i| (i * 37 % 256) as u8).collect();
+
+        let (mut a, mut b) = (pixels.clone(), pixels.clone());
+        grayscale_scalar(&mut a, GrayscaleMode::Bt601);
+        simd::grayscale_fast(&mut b, GrayscaleMode::Bt601);
+        assert_eq!(a, b);
+
+        let (mut a, mut b) = (pixels.clone(), pixels.clone());
+        brightness_scalar(&mut a, -40);
+        simd::brightness_simd(&mut b, -40);
+        assert_eq!(a, b);
+
+        let (mut a, mut b) = (pixels.clone(), pixels);
+        invert_scalar(&mut a);
+        simd::invert_simd(&mut b);
+        assert_eq!(a, b);
+    }
+}
@@ -1,20 +1,129 @@
+pub mod align;
+pub mod alpha;
+pub mod analysis;
+pub mod animation;
+pub mod backend;
+pub mod cache;
+mod cancel;
+pub mod channels;
+mod clock;
+pub mod color;
+pub mod compose;
+pub mod crop;
+mod digest;
+pub mod encode;
 mod error;
 pub mod ffi;
+pub mod filter;
 pub mod filters;
+mod font;
+#[cfg(feature = "fs")]
+pub mod fs;
+pub mod generate;
+pub mod geometry;
+#[cfg(feature = "gpu")]
+pub mod gpu;
+mod hash;
+#[cfg(feature = "bench-internals")]
+#[doc(hidden)]
+pub mod internals;
+mod limits;
+pub mod load;
+mod lut;
+mod lut3d;
+mod mask;
+pub mod metrics;
+pub mod model;
+pub mod multipage;
+pub mod negotiate;
+pub mod ops;
+pub mod optimize;
+#[cfg(feature = "pdf")]
+pub mod pdf;
+mod png_encode;
+pub mod portrait;
+pub mod presets;
+#[cfg(feature = "raw")]
+pub mod raw;
+pub mod registry;
+#[cfg(feature = "remote")]
+pub mod remote;
+pub mod sanitize;
 pub mod simd;
+pub mod spec;
+pub mod stream;
+#[cfg(feature = "svg")]
+pub mod svg;
+#[cfg(test)]
+mod testing;
+pub mod tiles;
+pub mod tonemap;
+pub mod watermark;
 
+pub use alpha::AlphaMode;
+pub use backend::{Backend, CpuBackend, SimdBackend};
+pub use cache::{CacheKey, DiskCache, MemoryCache, PipelineCache};
+pub use cancel::CancellationToken;
+pub use channels::Channel;
+pub use color::Hsv;
+pub use compose::{Anchor, Mark};
+pub use crop::{smart_crop, SmartCropOptions};
+pub use digest::DIGEST_VERSION;
+pub use encode::{ChromaSubsampling, EncodeOptions, PngEffort, PngFilter, TargetSizeOptions};
 pub use error::PipelineError;
+pub use filter::{Filter, FilterFactory, PixelBuffer, PixelFilter};
 pub use filters::*;
+pub use geometry::{Interpolation, Quad, Transform};
+#[cfg(feature = "gpu")]
+pub use gpu::GpuBackend;
+pub use limits::{rgba_len, MAX_DIMENSION};
+pub use load::LoadOptions;
+pub use lut::Lut256;
+pub use lut3d::{Lut3D, MAX_LUT3D_SIZE};
+pub use mask::Region;
+pub use metrics::{PipelineReport, StepMetrics};
+pub use model::{Model, Postprocess, Preprocess, TensorLayout};
+pub use negotiate::EncodedImage;
+pub use portrait::portrait_blur;
+pub use presets::Preset;
+pub use sanitize::SanitizeReport;
+pub use spec::{
+    AnchorSpec, EdgeBorderSpec, EdgeOutputSpec, FitSpec, GradientStopSpec, GrayscaleSpec,
+    InterpolationSpec, ModelOutputSpec, OperationSpec, PadFillSpec, PipelineSpec, PreprocessSpec,
+    RegionSpec, ResampleSpec, TensorLayoutSpec, UpscaleSpec,
+};
+pub use stream::FrameProcessor;
+pub use tonemap::ToneMapOperator;
+
+use filter::Registered;
+use image::{Rgba, RgbaImage};
+use optimize::PlanStep;
+use rayon::prelude::*;
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::Arc;
 
-use image::RgbaImage;
+/// Images loaded at once per worker thread by the batch methods
+const BATCH_IMAGES_PER_THREAD: usize = 2;
 
 /// Result type for pipeline operations
 pub type Result<T> = std::result::Result<T, PipelineError>;
 
 /// Core image processing pipeline
 pub struct ImagePipeline {
-    /// Number of threads to use (0 = auto)
+    /// Number of threads used by the batch methods (0 = auto)
     pub thread_count: usize,
+    /// Run adjacent point operations as a single pass (default: true)
+    pub fuse_operations: bool,
+    /// How colors relate to alpha in the images processed (default: straight)
+    pub alpha_mode: AlphaMode,
+    /// Largest image, in pixels, that may be decoded or produced (0 = no limit)
+    pub max_pixels: u64,
+    /// Most bytes of image buffers a run may hold at once (0 = no limit)
+    pub max_memory_bytes: u64,
+    backend: Arc<dyn Backend>,
+    cache: Option<Arc<dyn PipelineCache>>,
+    custom_filters: HashMap<String, Registered>,
 }
 
 impl Default for ImagePipeline {
@@ -26,44 +135,521 @@ impl Default for ImagePipeline {
 impl ImagePipeline {
     /// Create a new pipeline with default settings
     pub fn new() -> Self {
-        Self { thread_count: 0 }
+        Self::with_threads(0)
     }
 
     /// Create a pipeline with specific thread count
     pub fn with_threads(thread_count: usize) -> Self {
-        Self { thread_count }
+        Self {
+            thread_count,
+            fuse_operations: true,
+            alpha_mode: AlphaMode::default(),
+            max_pixels: 0,
+            max_memory_bytes: 0,
+            backend: backend::detect(),
+            cache: None,
+            custom_filters: HashMap::new(),
+        }
+    }
+
+    /// Make `filter` available to `FilterOperation::Custom` operations named `name`
+    ///
+    /// Such operations must not have params. Registering a name again replaces
+    /// the earlier filter.
+    pub fn register_filter(&mut self, name: impl Into<String>, filter: Box<dyn Filter>) {
+        self.custom_filters
+            .insert(name.into(), Registered::Fixed(Arc::from(filter)));
+    }
+
+    /// Build filters for `FilterOperation::Custom` operations named `name` from their params
+    ///
+    /// The factory runs when the pipeline is validated and again when the
+    /// operation runs, so it should be cheap; returning an error rejects the params.
+    pub fn register_filter_factory(&mut self, name: impl Into<String>, factory: FilterFactory) {
+        self.custom_filters
+            .insert(name.into(), Registered::Factory(factory));
+    }
+
+    /// Use `backend` instead of the automatically selected one
+    pub fn with_backend(mut self, backend: Arc<dyn Backend>) -> Self {
+        self.backend = backend;
+        self
+    }
+
+    /// Serve repeated `process_bytes` calls from `cache`
+    pub fn with_cache(mut self, cache: Arc<dyn PipelineCache>) -> Self {
+        self.cache = Some(cache);
+        self
+    }
+
+    /// Backend operations are dispatched to
+    pub fn backend(&self) -> &dyn Backend {
+        self.backend.as_ref()
+    }
+
+    /// Check every operation's parameters, reporting the first invalid one
+    pub fn validate(operations: &[FilterOperation]) -> Result<()> {
+        for (index, op) in operations.iter().enumerate() {
+            if let Err(reason) = op.check_parameters() {
+                return Err(PipelineError::InvalidParameter(format!(
+                    "operation {} ({}): {}",
+                    index,
+                    op.name(),
+                    reason
+                )));
+            }
+        }
+        Ok(())
     }
 
     /// Process an image through the pipeline with given operations
+    ///
+    /// All operations are validated before any work begins.
     pub fn process(&self, image: &RgbaImage, operations: &[FilterOperation]) -> Result<RgbaImage> {
+        self.process_with_progress(image, operations, |_, _| true)
+    }
+
+    /// Process an image, reporting progress after each operation
+    ///
+    /// `progress` receives the index of the operation that just finished and the
+    /// fraction of the pipeline completed (0.0 to 1.0). Returning `false` stops
+    /// the pipeline with `PipelineError::Cancelled`.
+    pub fn process_with_progress<F>(
+        &self,
+        image: &RgbaImage,
+        operations: &[FilterOperation],
+        progress: F,
+    ) -> Result<RgbaImage>
+    where
+        F: FnMut(usize, f32) -> bool,
+    {
+        self.execute(image, operations, &CancellationToken::new(), progress, None)
+    }
+
+    /// Process an image, aborting with `PipelineError::Cancelled` once `token` is cancelled
+    ///
+    /// Convolution filters check the token per row, so cancellation takes effect
+    /// in the middle of long operations rather than only between them.
+    pub fn process_cancellable(
+        &self,
+        image: &RgbaImage,
+        operations: &[FilterOperation],
+        token: &CancellationToken,
+    ) -> Result<RgbaImage> {
+        self.execute(image, operations, token, |_, _| true, None)
+    }
+
+    /// Process many images in parallel, returning one result per image, in order
+    ///
+    /// The operations are validated once up front; after that a failure only
+    /// affects its own image. Honors `thread_count`.
+    pub fn process_batch(
+        &self,
+        images: &[RgbaImage],
+        operations: &[FilterOperation],
+    ) -> Result<Vec<Result<RgbaImage>>> {
+        Self::validate(operations)?;
+        self.check_custom_filters(operations)?;
+        let pool = self.thread_pool()?;
+        Ok(in_pool(&pool, || {
+            images
+                .par_iter()
+                .map(|image| self.process(image, operations))
+                .collect()
+        }))
+    }
+
+    /// Decode and process many encoded images in parallel
+    ///
+    /// Images that fail to decode get an error result like any other failure.
+    pub fn process_batch_bytes<B: AsRef<[u8]> + Sync>(
+        &self,
+        inputs: &[B],
+        operations: &[FilterOperation],
+    ) -> Result<Vec<Result<RgbaImage>>> {
+        let mut results = Vec::with_capacity(inputs.len());
+        self.process_batch_with(
+            inputs,
+            operations,
+            |bytes| Self::load_from_bytes(bytes.as_ref()),
+            |_, result| results.push(result),
+        )?;
+        Ok(results)
+    }
+
+    /// Load and process `inputs` in parallel, handing each result to `on_result`
+    ///
+    /// Inputs are taken in windows of a few per thread and `on_result` is called
+    /// in input order after each window, so only one window of loaded and
+    /// processed images is in memory at a time. Honors `thread_count`.
+    pub fn process_batch_with<T, L, F>(
+        &self,
+        inputs: &[T],
+        operations: &[FilterOperation],
+        load: L,
+        mut on_result: F,
+    ) -> Result<()>
+    where
+        T: Sync,
+        L: Fn(&T) -> Result<RgbaImage> + Sync,
+        F: FnMut(usize, Result<RgbaImage>),
+    {
+        Self::validate(operations)?;
+        self.check_custom_filters(operations)?;
+
+        let pool = self.thread_pool()?;
+        let threads = pool
+            .as_ref()
+            .map_or_else(rayon::current_num_threads, |pool| {
+                pool.current_num_threads()
+            });
+        let window = threads * BATCH_IMAGES_PER_THREAD;
+
+        for (start, chunk) in (0..).step_by(window).zip(inputs.chunks(window)) {
+            let results: Vec<Result<RgbaImage>> = in_pool(&pool, || {
+                chunk
+                    .par_iter()
+                    .map(|input| self.process(&load(input)?, operations))
+                    .collect()
+            });
+            for (offset, result) in results.into_iter().enumerate() {
+                on_result(start + offset, result);
+            }
+        }
+        Ok(())
+    }
+
+    // Dedicated pool when `thread_count` is set, otherwise the global one is used
+    fn thread_pool(&self) -> Result<Option<rayon::ThreadPool>> {
+        if self.thread_count == 0 {
+            return Ok(None);
+        }
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(self.thread_count)
+            .build()
+            .map(Some)
+            .map_err(|e| PipelineError::ProcessingError(format!("thread pool: {}", e)))
+    }
+
+    /// Fast, approximate run of `operations` for interactive previews
+    ///
+    /// The image is first downsampled so its longest side is at most
+    /// `max_dimension`, and resolution-dependent parameters (blur sigma, block
+    /// sizes, regions, target sizes) are scaled by the same factor so the result
+    /// looks like a downsampled copy of the full-resolution output.
+    pub fn preview(
+        &self,
+        image: &RgbaImage,
+        operations: &[FilterOperation],
+        max_dimension: u32,
+    ) -> Result<RgbaImage> {
+        if max_dimension == 0 {
+            return Err(PipelineError::InvalidParameter(
+                "preview max_dimension must be at least 1".to_string(),
+            ));
+        }
+        Self::validate(operations)?;
+
+        let longest = image.width().max(image.height());
+        if longest <= max_dimension {
+            return self.process(image, operations);
+        }
+
+        let scale = max_dimension as f64 / longest as f64;
+        let small = filters::resize(
+            image,
+            scale_dimension(image.width(), scale),
+            scale_dimension(image.height(), scale),
+            ResampleFilter::Bilinear,
+        );
+        let scaled: Vec<FilterOperation> = operations.iter().map(|op| op.scaled(scale)).collect();
+        self.process(&small, &scaled)
+    }
+
+    // Runs the plan; `metrics` collects one entry per plan step when given
+    pub(crate) fn execute<F>(
+        &self,
+        image: &RgbaImage,
+        operations: &[FilterOperation],
+        token: &CancellationToken,
+        mut progress: F,
+        mut metrics: Option<&mut Vec<StepMetrics>>,
+    ) -> Result<RgbaImage>
+    where
+        F: FnMut(usize, f32) -> bool,
+    {
+        Self::validate(operations)?;
+        self.check_custom_filters(operations)?;
+
+        let source_bytes = metrics::image_bytes(image.width(), image.height());
+        self.check_size("input", image.width(), image.height(), 2 * source_bytes)?;
         let mut result = image.clone();
+        let total = operations.len();
 
-        for op in operations {
-            result = match op {
-                FilterOperation::Grayscale => filters::grayscale(&result),
-                FilterOperation::Brightness(value) => filters::brightness(&result, *value),
-                FilterOperation::Contrast(value) => filters::contrast(&result, *value),
-                FilterOperation::Blur(sigma) => filters::blur(&result, *sigma),
-                FilterOperation::Sharpen => filters::sharpen(&result),
-                FilterOperation::EdgeDetect => filters::edge_detect(&result),
-                FilterOperation::Resize { width, height } => {
-                    filters::resize(&result, *width, *height)
+        let steps = if self.fuse_operations {
+            optimize::plan(operations)
+        } else {
+            (0..total).map(PlanStep::Single).collect()
+        };
+
+        #[cfg(feature = "tracing")]
+        let (_pipeline_span, pipeline_started) = (
+            tracing::debug_span!(
+                target: "image_pipeline",
+                "pipeline",
+                operations = total,
+                width = image.width(),
+                height = image.height()
+            )
+            .entered(),
+            clock::now_ms(),
+        );
+        for step in steps {
+            token.check()?;
+            let (input_width, input_height) = result.dimensions();
+            let input_bytes = metrics::image_bytes(input_width, input_height);
+            if let PlanStep::Single(index) = step {
+                let op = &operations[index];
+                let (width, height) = limits::max_output_size(op, input_width, input_height);
+                let held = source_bytes + input_bytes + metrics::image_bytes(width, height);
+                let what = format!("operation {} ({})", index, op.name());
+                self.check_size(&what, width, height, held)?;
+            }
+            let (name, backend) = self.step_label(&step, operations);
+            #[cfg(feature = "tracing")]
+            let _span = tracing::debug_span!(
+                target: "image_pipeline",
+                "operation",
+                name = %name,
+                backend,
+                width = input_width,
+                height = input_height
+            )
+            .entered();
+            let started = clock::now_ms();
+            let indices = match step {
+                PlanStep::Single(index) => {
+                    let op = &operations[index];
+                    let weighted = match self.alpha_mode {
+                        AlphaMode::Straight => alpha::weighted(&result, op, token),
+                        _ => None,
+                    };
+                    result = match weighted {
+                        Some(output) => output?,
+                        None => {
+                            let convert = self.alpha_mode.converts(op);
+                            alpha::convert(self.alpha_mode, convert, &result, |image| {
+                                self.dispatch(image, op, token)
+                            })?
+                        }
+                    };
+                    index..index + 1
+                }
+                PlanStep::Fused(range, fused) => {
+                    // Fused steps only hold color adjustments
+                    let convert = self.alpha_mode == AlphaMode::Premultiplied;
+                    result = alpha::convert(self.alpha_mode, convert, &result, |image| {
+                        Ok(fused.apply(image))
+                    })?;
+                    range
                 }
-                FilterOperation::Invert => filters::invert(&result),
-                FilterOperation::Sepia => filters::sepia(&result),
             };
+            let elapsed_ms = clock::now_ms() - started;
+            let output_bytes = metrics::image_bytes(result.width(), result.height());
+            // Custom filters can only be checked once they have run
+            self.check_size(
+                "output",
+                result.width(),
+                result.height(),
+                source_bytes + output_bytes,
+            )?;
+            #[cfg(feature = "tracing")]
+            tracing::debug!(
+                target: "image_pipeline",
+                out_width = result.width(),
+                out_height = result.height(),
+                elapsed_ms,
+                "operation finished"
+            );
+            if let Some(collected) = metrics.as_deref_mut() {
+                collected.push(StepMetrics {
+                    name,
+                    backend: backend.to_string(),
+                    operations: indices.clone().collect(),
+                    elapsed_ms,
+                    input_width,
+                    input_height,
+                    output_width: result.width(),
+                    output_height: result.height(),
+                    buffer_bytes: input_bytes + output_bytes,
+                });
+            }
+
+            // Fused operations still report progress once per original operation
+            for index in indices {
+                if !progress(index, (index + 1) as f32 / total as f32) {
+                    return Err(PipelineError::Cancelled);
+                }
+            }
         }
 
+        #[cfg(feature = "tracing")]
+        tracing::debug!(
+            target: "image_pipeline",
+            elapsed_ms = clock::now_ms() - pipeline_started,
+            "pipeline finished"
+        );
         Ok(result)
     }
 
+    // Operation names of a plan step ("brightness+contrast" when fused) and what runs it
+    fn step_label(&self, step: &PlanStep, operations: &[FilterOperation]) -> (String, &str) {
+        match step {
+            PlanStep::Single(index) => {
+                let op = &operations[*index];
+                (op.name().to_string(), self.backend_for(op))
+            }
+            PlanStep::Fused(range, _) => {
+                let names: Vec<&str> = operations[range.clone()]
+                    .iter()
+                    .map(|op| op.name())
+                    .collect();
+                (names.join("+"), "fused")
+            }
+        }
+    }
+
+    // Name of what `dispatch` runs `op` with
+    fn backend_for(&self, op: &FilterOperation) -> &str {
+        match op {
+            FilterOperation::Custom { .. } => "custom",
+            FilterOperation::Masked { op, .. } if op.is_custom() => "custom",
+            _ if self.backend.supports(op) => self.backend.name(),
+            _ => "cpu",
+        }
+    }
+
+    // Every custom operation, including masked ones, must name a registered
+    // filter that accepts its params
+    fn check_custom_filters(&self, operations: &[FilterOperation]) -> Result<()> {
+        for (index, mut op) in operations.iter().enumerate() {
+            while let FilterOperation::Masked { op: inner, .. } = op {
+                op = inner;
+            }
+            if let FilterOperation::Custom { name, params } = op {
+                self.custom_filter(name, params)
+                    .and_then(|filter| filter.validate())
+                    .map_err(|e| {
+                        PipelineError::InvalidParameter(format!(
+                            "operation {} (custom {}): {}",
+                            index, name, e
+                        ))
+                    })?;
+            }
+        }
+        Ok(())
+    }
+
+    fn custom_filter(&self, name: &str, params: &Value) -> Result<Arc<dyn Filter>> {
+        self.custom_filters
+            .get(name)
+            .ok_or_else(|| {
+                PipelineError::InvalidParameter(format!("no filter named '{}' is registered", name))
+            })?
+            .resolve(params)
+    }
+
+    // Run `op` on the selected backend, or on the CPU if the backend lacks it.
+    // Custom filters live on the pipeline, so masks around them are applied here.
+    fn dispatch(
+        &self,
+        image: &RgbaImage,
+        op: &FilterOperation,
+        token: &CancellationToken,
+    ) -> Result<RgbaImage> {
+        match op {
+            FilterOperation::Custom { name, params } => {
+                let mut buffer = PixelBuffer::with_token(image.clone(), token);
+                self.custom_filter(name, params)?.apply(&mut buffer)?;
+                Ok(buffer.into_image())
+            }
+            FilterOperation::Masked { region, op } if op.is_custom() => {
+                let filtered = self.dispatch(image, op, token)?;
+                region.blend(image, &filtered)
+            }
+            _ if self.backend.supports(op) => self.backend.apply(image, op, token),
+            _ => Self::apply_operation(image, op, token),
+        }
+    }
+
+    fn apply_operation(
+        image: &RgbaImage,
+        op: &FilterOperation,
+        token: &CancellationToken,
+    ) -> Result<RgbaImage> {
+        op.to_filter().apply_to(image, token)
+    }
+
     /// Load an image from bytes
+    ///
+    /// For untrusted input use `load_from_bytes_with_options`.
     pub fn load_from_bytes(bytes: &[u8]) -> Result<RgbaImage> {
         let img = image::load_from_memory(bytes)?;
         Ok(img.to_rgba8())
     }
 
+    /// Decode, process and encode an image
+    ///
+    /// With a cache set by `with_cache`, a result for the same source bytes,
+    /// operations and output settings is returned without decoding.
+    pub fn process_bytes(
+        &self,
+        bytes: &[u8],
+        operations: &[FilterOperation],
+        format: OutputFormat,
+        quality: u8,
+    ) -> Result<Vec<u8>> {
+        self.process_bytes_cancellable(
+            bytes,
+            operations,
+            format,
+            quality,
+            &CancellationToken::new(),
+        )
+    }
+
+    /// `process_bytes` that aborts with `PipelineError::Cancelled` once
+    /// `token` is cancelled, as `process_cancellable` does
+    pub fn process_bytes_cancellable(
+        &self,
+        bytes: &[u8],
+        operations: &[FilterOperation],
+        format: OutputFormat,
+        quality: u8,
+        token: &CancellationToken,
+    ) -> Result<Vec<u8>> {
+        let key = self
+            .cache
+            .as_ref()
+            .map(|_| CacheKey::new(bytes, operations, format, quality));
+        if let (Some(cache), Some(key)) = (&self.cache, &key) {
+            if let Some(encoded) = cache.get(key) {
+                return Ok(encoded);
+            }
+        }
+
+        let image = self.decode(bytes)?;
+        let result = self.process_cancellable(&image, operations, token)?;
+        let encoded = Self::encode(&result, format, quality)?;
+        if let (Some(cache), Some(key)) = (&self.cache, &key) {
+            cache.put(key, &encoded);
+        }
+        Ok(encoded)
+    }
+
     /// Encode image to PNG bytes
+    ///
+    /// Always RGBA; `encode_with_options` with a `PngEffort` writes smaller files.
     pub fn encode_to_png(image: &RgbaImage) -> Result<Vec<u8>> {
         use image::ImageEncoder;
         use std::io::Cursor;
@@ -78,29 +664,515 @@ impl ImagePipeline {
         )?;
         Ok(buffer)
     }
+
+    /// Encode image to the given format
+    /// quality: 1-100, only used by lossy formats (JPEG, AVIF)
+    ///
+    /// Uses the default `EncodeOptions` otherwise; see `encode_with_options`.
+    pub fn encode(image: &RgbaImage, format: OutputFormat, quality: u8) -> Result<Vec<u8>> {
+        Self::encode_with_options(image, format, &EncodeOptions::with_quality(quality))
+    }
+}
+
+/// Largest accepted blur sigma; the kernel grows linearly with sigma
+pub const MAX_BLUR_SIGMA: f32 = 100.0;
+
+impl FilterOperation {
+    /// Unsharp mask with the default amount and radius and no threshold
+    pub fn sharpen() -> Self {
+        FilterOperation::Sharpen {
+            amount: SHARPEN_AMOUNT,
+            radius: SHARPEN_RADIUS,
+            threshold: 0,
+        }
+    }
+
+    /// Stable snake_case name, matching the `type` tag in pipeline specs
+    pub fn name(&self) -> &'static str {
+        match self {
+            FilterOperation::Grayscale(_) => "grayscale",
+            FilterOperation::Brightness(_) => "brightness",
+            FilterOperation::Contrast(_) => "contrast",
+            FilterOperation::Blur(_) => "blur",
+            FilterOperation::ApproximateBlur(_) => "approximate_blur",
+            FilterOperation::Sharpen { .. } => "sharpen",
+            FilterOperation::EdgeDetect(_) => "edge_detect",
+            FilterOperation::Resize { .. } => "resize",
+            FilterOperation::Thumbnail { .. } => "thumbnail",
+            FilterOperation::SmartCrop { .. } => "smart_crop",
+            FilterOperation::SeamCarve { .. } => "seam_carve",
+            FilterOperation::Pad { .. } => "pad",
+            FilterOperation::Invert => "invert",
+            FilterOperation::Sepia => "sepia",
+            FilterOperation::ColorMatrix(_) => "color_matrix",
+            FilterOperation::Gamma(_) => "gamma",
+            FilterOperation::Lut3D(_) => "lut3d",
+            FilterOperation::Noise { .. } => "noise",
+            FilterOperation::Pixelate(_) => "pixelate",
+            FilterOperation::Deskew => "deskew",
+            FilterOperation::Rotate { .. } => "rotate",
+            FilterOperation::Affine { .. } => "affine",
+            FilterOperation::LensCorrect { .. } => "lens_correct",
+            FilterOperation::ChromaticAberration { .. } => "chromatic_aberration",
+            FilterOperation::ChromaKey { .. } => "chroma_key",
+            FilterOperation::GradientMap(_) => "gradient_map",
+            FilterOperation::SelectiveColor { .. } => "selective_color",
+            FilterOperation::ShadowsHighlights { .. } => "shadows_highlights",
+            FilterOperation::PortraitBlur { .. } => "portrait_blur",
+            FilterOperation::Model { .. } => "model",
+            FilterOperation::Upscale { .. } => "upscale",
+            FilterOperation::Watermark { .. } => "watermark",
+            FilterOperation::DropShadow { .. } => "drop_shadow",
+            FilterOperation::Masked { .. } => "masked",
+            FilterOperation::Custom { .. } => "custom",
+        }
+    }
+
+    /// Whether this is a custom operation, possibly inside masks
+    pub fn is_custom(&self) -> bool {
+        match self {
+            FilterOperation::Custom { .. } => true,
+            FilterOperation::Masked { op, .. } => op.is_custom(),
+            _ => false,
+        }
+    }
+
+    /// Lookup table equivalent of this operation, if it maps channels independently
+    pub fn to_lut(&self) -> Option<Lut256> {
+        match *self {
+            FilterOperation::Brightness(value) => Some(Lut256::brightness(value)),
+            FilterOperation::Contrast(value) => Some(Lut256::contrast(value)),
+            FilterOperation::Gamma(value) => Some(Lut256::gamma(value)),
+            FilterOperation::Invert => Some(Lut256::invert()),
+            _ => None,
+        }
+    }
+
+    /// Check that parameters are within their supported ranges
+    pub fn validate(&self) -> Result<()> {
+        self.check_parameters().map_err(|reason| {
+            PipelineError::InvalidParameter(format!("{}: {}", self.name(), reason))
+        })
+    }
+
+    /// Whether the output dimensions can differ from the input
+    pub fn changes_size(&self) -> bool {
+        self.to_filter().changes_size()
+    }
+
+    /// Whether the visual result depends on the image resolution
+    ///
+    /// For these operations a preview on a downsampled image only matches the
+    /// full-size output if parameters are scaled (see `scaled`). Edge detection
+    /// and noise work on fixed pixel neighborhoods and cannot be scaled, so
+    /// their previews are approximate.
+    pub fn is_resolution_dependent(&self) -> bool {
+        match self {
+            FilterOperation::Blur(_)
+            | FilterOperation::ApproximateBlur(_)
+            | FilterOperation::Sharpen { .. }
+            | FilterOperation::EdgeDetect(_)
+            | FilterOperation::Noise { .. }
+            | FilterOperation::Pixelate(_)
+            | FilterOperation::Resize { .. }
+            | FilterOperation::Thumbnail { .. }
+            | FilterOperation::SmartCrop { .. }
+            | FilterOperation::SeamCarve { .. }
+            | FilterOperation::Pad { .. }
+            | FilterOperation::Affine { .. }
+            | FilterOperation::ChromaticAberration { .. }
+            | FilterOperation::PortraitBlur { .. }
+            | FilterOperation::Watermark { .. }
+            | FilterOperation::DropShadow { .. }
+            | FilterOperation::ShadowsHighlights { .. }
+            // Models see a differently sized image and may react to it in any way
+            | FilterOperation::Model { .. }
+            | FilterOperation::Masked { .. }
+            // Unknown to the pipeline, so previews of them are treated as approximate
+            | FilterOperation::Custom { .. } => true,
+            FilterOperation::Grayscale(_)
+            | FilterOperation::Brightness(_)
+            | FilterOperation::Contrast(_)
+            | FilterOperation::Invert
+            | FilterOperation::Sepia
+            | FilterOperation::ColorMatrix(_)
+            | FilterOperation::Gamma(_)
+            | FilterOperation::Lut3D(_)
+            | FilterOperation::Deskew
+            | FilterOperation::Rotate { .. }
+            | FilterOperation::LensCorrect { .. }
+            | FilterOperation::ChromaKey { .. }
+            | FilterOperation::GradientMap(_)
+            | FilterOperation::SelectiveColor { .. } => false,
+            // Sharpening follows the factor, but a model sees absolute pixels
+            FilterOperation::Upscale { method, .. } => {
+                matches!(method, UpscaleMethod::Model { .. })
+            }
+        }
+    }
+
+    /// Copy of this operation for an image resized by `scale`
+    ///
+    /// Pixel-sized parameters are multiplied by `scale`; everything else is unchanged.
+    pub fn scaled(&self, scale: f64) -> FilterOperation {
+        let dim = |v: u32| scale_dimension(v, scale);
+        match self {
+            FilterOperation::Blur(sigma) => FilterOperation::Blur(scale_sigma(*sigma, scale)),
+            FilterOperation::ApproximateBlur(sigma) => {
+                FilterOperation::ApproximateBlur(scale_sigma(*sigma, scale))
+            }
+            FilterOperation::Sharpen {
+                amount,
+                radius,
+                threshold,
+            } => FilterOperation::Sharpen {
+                amount: *amount,
+                radius: scale_sigma(*radius, scale),
+                threshold: *threshold,
+            },
+            FilterOperation::Pixelate(block_size) => FilterOperation::Pixelate(dim(*block_size)),
+            FilterOperation::Resize {
+                width,
+                height,
+                filter,
+            } => FilterOperation::Resize {
+                width: dim(*width),
+                height: dim(*height),
+                filter: *filter,
+            },
+            FilterOperation::Thumbnail { width, height, fit } => FilterOperation::Thumbnail {
+                width: dim(*width),
+                height: dim(*height),
+                fit: *fit,
+            },
+            FilterOperation::SmartCrop { width, height } => FilterOperation::SmartCrop {
+                width: dim(*width),
+                height: dim(*height),
+            },
+            FilterOperation::SeamCarve { width, height } => FilterOperation::SeamCarve {
+                width: dim(*width),
+                height: dim(*height),
+            },
+            FilterOperation::Pad {
+                top,
+                right,
+                bottom,
+                left,
+                fill,
+            } => {
+                let border = |v: u32| (v as f64 * scale).round() as u32;
+                FilterOperation::Pad {
+                    top: border(*top),
+                    right: border(*right),
+                    bottom: border(*bottom),
+                    left: border(*left),
+                    fill: *fill,
+                }
+            }
+            // The linear part is scale-invariant; only the translation is in pixels
+            FilterOperation::Affine {
+                matrix,
+                interpolation,
+                background,
+            } => {
+                let mut matrix = *matrix;
+                matrix[2] = (matrix[2] as f64 * scale) as f32;
+                matrix[5] = (matrix[5] as f64 * scale) as f32;
+                FilterOperation::Affine {
+                    matrix,
+                    interpolation: *interpolation,
+                    background: *background,
+                }
+            }
+            FilterOperation::ChromaticAberration {
+                red_shift,
+                blue_shift,
+            } => FilterOperation::ChromaticAberration {
+                red_shift: (*red_shift as f64 * scale) as f32,
+                blue_shift: (*blue_shift as f64 * scale) as f32,
+            },
+            FilterOperation::PortraitBlur {
+                mask,
+                sigma,
+                feather,
+            } => FilterOperation::PortraitBlur {
+                mask: mask.as_ref().map(|mask| mask.scaled(scale)),
+                sigma: scale_sigma(*sigma, scale),
+                feather: (*feather as f64 * scale) as f32,
+            },
+            // A mark drawn at its own size shrinks with the image
+            FilterOperation::Watermark {
+                mark,
+                anchor,
+                margin,
+                opacity,
+                tile,
+                scale: relative,
+            } => FilterOperation::Watermark {
+                mark: match relative {
+                    Some(_) => mark.clone(),
+                    None => Arc::new(mark.scaled(scale)),
+                },
+                anchor: *anchor,
+                margin: (*margin as f64 * scale).round() as u32,
+                opacity: *opacity,
+                tile: *tile,
+                scale: *relative,
+            },
+            FilterOperation::DropShadow {
+                offset,
+                sigma,
+                color,
+                opacity,
+            } => {
+                let shift = |v: i32| (v as f64 * scale).round() as i32;
+                FilterOperation::DropShadow {
+                    offset: (shift(offset.0), shift(offset.1)),
+                    sigma: (*sigma as f64 * scale) as f32,
+                    color: *color,
+                    opacity: *opacity,
+                }
+            }
+            FilterOperation::ShadowsHighlights {
+                shadows,
+                highlights,
+                radius,
+            } => FilterOperation::ShadowsHighlights {
+                shadows: *shadows,
+                highlights: *highlights,
+                radius: (*radius as f64 * scale) as f32,
+            },
+            FilterOperation::Masked { region, op } => FilterOperation::Masked {
+                region: region.scaled(scale),
+                op: Box::new(op.scaled(scale)),
+            },
+            other => other.clone(),
+        }
+    }
+
+    fn check_parameters(&self) -> std::result::Result<(), String> {
+        self.to_filter().validate().map_err(|e| match e {
+            PipelineError::InvalidParameter(reason) => reason,
+            other => other.to_string(),
+        })
+    }
+}
+
+// Run `job` on `pool`, or on the current pool when there is none
+fn in_pool<R: Send>(pool: &Option<rayon::ThreadPool>, job: impl FnOnce() -> R + Send) -> R {
+    match pool {
+        Some(pool) => pool.install(job),
+        None => job(),
+    }
+}
+
+/// Scale a pixel dimension, rounding and keeping it at least 1
+pub(crate) fn scale_dimension(value: u32, scale: f64) -> u32 {
+    ((value as f64 * scale).round() as u32).max(1)
+}
+
+// Scale a blur sigma, keeping it positive
+fn scale_sigma(sigma: f32, scale: f64) -> f32 {
+    (sigma as f64 * scale).max(f32::EPSILON as f64) as f32
+}
+
+/// Supported output encodings
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// Lossless PNG
+    Png,
+    /// Lossy JPEG (alpha is dropped)
+    Jpeg,
+    /// Lossless WebP
+    WebP,
+    /// Lossy AVIF with alpha
+    #[cfg(feature = "avif")]
+    Avif,
+}
+
+impl OutputFormat {
+    /// Guess the format from a file extension (case-insensitive)
+    pub fn from_extension(ext: &str) -> Option<Self> {
+        match ext.to_ascii_lowercase().as_str() {
+            "png" => Some(OutputFormat::Png),
+            "jpg" | "jpeg" => Some(OutputFormat::Jpeg),
+            "webp" => Some(OutputFormat::WebP),
+            #[cfg(feature = "avif")]
+            "avif" => Some(OutputFormat::Avif),
+            _ => None,
+        }
+    }
+
+    /// Canonical file extension for this format
+    pub fn extension(&self) -> &'static str {
+        match self {
+            OutputFormat::Png => "png",
+            OutputFormat::Jpeg => "jpg",
+            OutputFormat::WebP => "webp",
+            #[cfg(feature = "avif")]
+            OutputFormat::Avif => "avif",
+        }
+    }
+
+    /// Media type for HTTP responses
+    pub fn mime_type(&self) -> &'static str {
+        match self {
+            OutputFormat::Png => "image/png",
+            OutputFormat::Jpeg => "image/jpeg",
+            OutputFormat::WebP => "image/webp",
+            #[cfg(feature = "avif")]
+            OutputFormat::Avif => "image/avif",
+        }
+    }
 }
 
 /// Available filter operations
 #[derive(Debug, Clone)]
 pub enum FilterOperation {
-    /// Convert to grayscale
-    Grayscale,
+    /// Convert to grayscale with the given weighting
+    Grayscale(GrayscaleMode),
     /// Adjust brightness (-1.0 to 1.0)
     Brightness(f32),
     /// Adjust contrast (0.0 to 2.0+)
     Contrast(f32),
     /// Apply Gaussian blur with sigma
     Blur(f32),
-    /// Apply sharpening filter
-    Sharpen,
+    /// Approximate Gaussian blur with three box blurs, much faster for large sigmas
+    ApproximateBlur(f32),
+    /// Unsharp mask: add `amount` times the detail lost by a blur of sigma
+    /// `radius`, skipping luminance differences below `threshold`
+    Sharpen {
+        amount: f32,
+        radius: f32,
+        threshold: u8,
+    },
     /// Detect edges using Sobel operator
-    EdgeDetect,
+    EdgeDetect(EdgeOptions),
     /// Resize to specific dimensions
-    Resize { width: u32, height: u32 },
+    Resize {
+        width: u32,
+        height: u32,
+        filter: ResampleFilter,
+    },
+    /// Fit into a bounding box, preserving the aspect ratio
+    Thumbnail {
+        width: u32,
+        height: u32,
+        fit: FitMode,
+    },
+    /// Crop to the target aspect ratio around salient content, then resize
+    SmartCrop { width: u32, height: u32 },
+    /// Content-aware resize by removing or inserting low-energy seams
+    SeamCarve { width: u32, height: u32 },
+    /// Add a border of the given widths, see `filters::extend_canvas`
+    Pad {
+        top: u32,
+        right: u32,
+        bottom: u32,
+        left: u32,
+        fill: PadFill,
+    },
     /// Invert colors
     Invert,
     /// Apply sepia tone
     Sepia,
+    /// Mix channels with a 4x5 affine color matrix, see `filters::ColorMatrix`
+    ColorMatrix(ColorMatrix),
+    /// Apply gamma correction (> 0.0, 1.0 = no change)
+    Gamma(f32),
+    /// Apply a 3D color LUT with trilinear interpolation
+    Lut3D(Arc<Lut3D>),
+    /// Add seeded Gaussian noise (amount 0.0 to 1.0)
+    Noise {
+        amount: f32,
+        monochrome: bool,
+        seed: u64,
+    },
+    /// Pixelate into square blocks of the given size
+    Pixelate(u32),
+    /// Straighten a skewed document scan
+    Deskew,
+    /// Rotate clockwise by any angle; `expand` grows the canvas to fit
+    Rotate {
+        degrees: f32,
+        background: Rgba<u8>,
+        expand: bool,
+    },
+    /// Map (x, y) to (a*x + b*y + c, d*x + e*y + f) for `matrix = [a, b, c, d, e, f]`
+    Affine {
+        matrix: [f32; 6],
+        interpolation: Interpolation,
+        background: Rgba<u8>,
+    },
+    /// Undo radial lens distortion (negative k1 = barrel)
+    LensCorrect { k1: f32, k2: f32, k3: f32 },
+    /// Shift red and blue radially by the given pixels at the corners (negative corrects)
+    ChromaticAberration { red_shift: f32, blue_shift: f32 },
+    /// Make pixels near `key_color` transparent and suppress its spill, see
+    /// `filters::chroma_key`
+    ChromaKey {
+        key_color: Rgba<u8>,
+        tolerance: f32,
+        softness: f32,
+    },
+    /// Recolor through a ramp of (position, color) stops by luminance, see
+    /// `filters::gradient_map`
+    GradientMap(Vec<(f32, Rgba<u8>)>),
+    /// Shift hue, scale saturation and shift value of the colors in
+    /// `hue_range` only, see `filters::selective_color`
+    SelectiveColor {
+        hue_range: (f32, f32),
+        hue_shift: f32,
+        saturation: f32,
+        lightness: f32,
+    },
+    /// Brighten dark and darken bright areas, found with a luminance mask
+    /// blurred by `radius`, see `filters::shadows_highlights`
+    ShadowsHighlights {
+        shadows: f32,
+        highlights: f32,
+        radius: f32,
+    },
+    /// Blur the background with `sigma`, keeping the subject in `mask` (255 =
+    /// subject) sharp; without a mask the subject is estimated, see `portrait`
+    PortraitBlur {
+        mask: Option<Region>,
+        sigma: f32,
+        feather: f32,
+    },
+    /// Run an ONNX model on the image, see `model`
+    Model {
+        model: Arc<Model>,
+        pre: Preprocess,
+        post: Postprocess,
+    },
+    /// Enlarge by `factor`, see `filters::upscale`
+    Upscale { factor: f32, method: UpscaleMethod },
+    /// Draw `mark` over the image, see `compose::watermark`
+    Watermark {
+        mark: Arc<Mark>,
+        anchor: Anchor,
+        margin: u32,
+        opacity: f32,
+        tile: bool,
+        /// Mark width as a fraction of the image width, `None` for its own size
+        scale: Option<f32>,
+    },
+    /// Grow the canvas and draw a blurred shadow of the image's alpha behind
+    /// it, see `compose::drop_shadow`
+    DropShadow {
+        offset: (i32, i32),
+        sigma: f32,
+        color: Rgba<u8>,
+        opacity: f32,
+    },
+    /// Apply `op` only inside `region`; `op` must not change the image size
+    Masked {
+        region: Region,
+        op: Box<FilterOperation>,
+    },
+    /// Run the filter registered under `name` with `ImagePipeline::register_filter`
+    Custom { name: String, params: Value },
 }
 
 #[cfg(test)]
@@ -118,7 +1190,7 @@ mod tests {
     fn test_pipeline_grayscale() {
         let pipeline = ImagePipeline::new();
         let image = create_test_image();
-        let result = pipeline.process(&image, &[FilterOperation::Grayscale]);
+        let result = pipeline.process(&image, &[FilterOperation::Grayscale(GrayscaleMode::Bt709)]);
         assert!(result.is_ok());
     }
 
@@ -129,9 +1201,280 @@ mod tests {
         let ops = vec![
             FilterOperation::Brightness(0.2),
             FilterOperation::Contrast(1.2),
-            FilterOperation::Grayscale,
+            FilterOperation::Grayscale(GrayscaleMode::Bt709),
         ];
         let result = pipeline.process(&image, &ops);
         assert!(result.is_ok());
     }
+
+    #[test]
+    fn test_pipeline_rejects_invalid_parameters() {
+        let pipeline = ImagePipeline::new();
+        let image = create_test_image();
+
+        let invalid = [
+            FilterOperation::Blur(-3.0),
+            FilterOperation::Contrast(-5.0),
+            FilterOperation::Brightness(f32::NAN),
+            FilterOperation::Resize {
+                width: 0,
+                height: 10,
+                filter: ResampleFilter::Lanczos3,
+            },
+        ];
+        for op in invalid {
+            let err = pipeline
+                .process(&image, &[FilterOperation::Invert, op])
+                .unwrap_err();
+            match err {
+                PipelineError::InvalidParameter(msg) => assert!(msg.starts_with("operation 1 ")),
+                other => panic!("unexpected error: {other}"),
+            }
+        }
+    }
+
+    #[test]
+    fn test_process_with_progress() {
+        let pipeline = ImagePipeline::new();
+        let image = create_test_image();
+        let ops = [
+            FilterOperation::Invert,
+            FilterOperation::Grayscale(GrayscaleMode::Bt709),
+            FilterOperation::Sepia,
+            FilterOperation::Invert,
+        ];
+
+        let mut reports = Vec::new();
+        pipeline
+            .process_with_progress(&image, &ops, |index, fraction| {
+                reports.push((index, fraction));
+                true
+            })
+            .unwrap();
+        assert_eq!(reports, vec![(0, 0.25), (1, 0.5), (2, 0.75), (3, 1.0)]);
+
+        let result = pipeline.process_with_progress(&image, &ops, |index, _| index < 1);
+        assert!(matches!(result, Err(PipelineError::Cancelled)));
+    }
+
+    #[test]
+    fn test_fused_pipeline_matches_unfused() {
+        let image = create_test_image();
+        let ops = [
+            FilterOperation::Gamma(2.2),
+            FilterOperation::Brightness(-0.1),
+            FilterOperation::Sepia,
+            FilterOperation::Blur(1.0),
+            FilterOperation::Contrast(1.5),
+            FilterOperation::Invert,
+        ];
+
+        // Fused runs always use the CPU, so compare against it alone
+        let fused = ImagePipeline::new().with_backend(Arc::new(CpuBackend));
+        let mut unfused = ImagePipeline::new().with_backend(Arc::new(CpuBackend));
+        unfused.fuse_operations = false;
+        assert_eq!(
+            fused.process(&image, &ops).unwrap(),
+            unfused.process(&image, &ops).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_masked_operation() {
+        let pipeline = ImagePipeline::new();
+        let image = create_test_image();
+        let masked = FilterOperation::Masked {
+            region: Region::Rect {
+                x: 10,
+                y: 10,
+                width: 20,
+                height: 20,
+            },
+            op: Box::new(FilterOperation::Invert),
+        };
+
+        let result = pipeline.process(&image, &[masked]).unwrap();
+        assert_eq!(result.get_pixel(0, 0), image.get_pixel(0, 0));
+        assert_eq!(
+            result.get_pixel(15, 15)[0],
+            255 - image.get_pixel(15, 15)[0]
+        );
+
+        let resize = FilterOperation::Masked {
+            region: Region::Rect {
+                x: 0,
+                y: 0,
+                width: 5,
+                height: 5,
+            },
+            op: Box::new(FilterOperation::Resize {
+                width: 5,
+                height: 5,
+                filter: ResampleFilter::Lanczos3,
+            }),
+        };
+        assert!(pipeline.process(&image, &[resize]).is_err());
+    }
+
+    #[test]
+    fn test_preview_scales_parameters() {
+        let pipeline = ImagePipeline::new();
+        let image = generate::test_pattern(400, 200);
+        let ops = [
+            FilterOperation::Blur(8.0),
+            FilterOperation::Pixelate(20),
+            FilterOperation::Resize {
+                width: 200,
+                height: 100,
+                filter: ResampleFilter::Lanczos3,
+            },
+        ];
+
+        let preview = pipeline.preview(&image, &ops, 100).unwrap();
+        assert_eq!(preview.dimensions(), (50, 25));
+        assert!(matches!(ops[0].scaled(0.25), FilterOperation::Blur(s) if s == 2.0));
+        assert!(matches!(ops[1].scaled(0.25), FilterOperation::Pixelate(5)));
+        assert!(ops[0].is_resolution_dependent());
+        assert!(!FilterOperation::Gamma(2.2).is_resolution_dependent());
+
+        // Images that already fit are processed at full size
+        let small = pipeline
+            .preview(&image, &[FilterOperation::Invert], 400)
+            .unwrap();
+        assert_eq!(small.dimensions(), (400, 200));
+        assert!(pipeline.preview(&image, &ops, 0).is_err());
+    }
+
+    #[test]
+    fn test_process_cancellable() {
+        let pipeline = ImagePipeline::new();
+        let image = create_test_image();
+        let ops = [FilterOperation::Blur(2.0), FilterOperation::sharpen()];
+
+        let token = CancellationToken::new();
+        assert!(pipeline.process_cancellable(&image, &ops, &token).is_ok());
+
+        token.clone().cancel();
+        let result = pipeline.process_cancellable(&image, &ops, &token);
+        assert!(matches!(result, Err(PipelineError::Cancelled)));
+    }
+
+    #[test]
+    fn test_process_batch() {
+        let images: Vec<RgbaImage> = (1..=9)
+            .map(|i| RgbaImage::from_pixel(i * 3, 4, Rgba([i as u8 * 10, 0, 0, 255])))
+            .collect();
+        let pipeline = ImagePipeline::with_threads(2);
+        let results = pipeline
+            .process_batch(&images, &[FilterOperation::Invert])
+            .unwrap();
+        for (i, result) in results.iter().enumerate() {
+            let result = result.as_ref().unwrap();
+            assert_eq!(result.width(), (i as u32 + 1) * 3);
+            assert_eq!(result.get_pixel(0, 0)[0], 255 - (i as u8 + 1) * 10);
+        }
+        assert!(pipeline
+            .process_batch(&images, &[FilterOperation::Blur(-1.0)])
+            .is_err());
+    }
+
+    #[test]
+    fn test_process_batch_bytes_isolates_failures() {
+        let png = ImagePipeline::encode_to_png(&create_test_image()).unwrap();
+        let inputs = vec![png.clone(), b"not an image".to_vec(), png];
+        let results = ImagePipeline::new()
+            .process_batch_bytes(&inputs, &[FilterOperation::Grayscale(GrayscaleMode::Bt709)])
+            .unwrap();
+        assert_eq!(results.len(), 3);
+        assert!(results[0].is_ok() && results[2].is_ok());
+        assert!(results[1].is_err());
+    }
+
+    #[test]
+    fn test_encode_roundtrip() {
+        let image = create_test_image();
+        for format in [OutputFormat::Png, OutputFormat::Jpeg, OutputFormat::WebP] {
+            let bytes = ImagePipeline::encode(&image, format, 90).unwrap();
+            let decoded = ImagePipeline::load_from_bytes(&bytes).unwrap();
+            assert_eq!(decoded.dimensions(), image.dimensions());
+            assert!(analysis::ssim(&image, &decoded).unwrap() > 0.95);
+        }
+    }
+
+    #[test]
+    fn test_encode_rejects_invalid_quality() {
+        let image = create_test_image();
+        assert!(ImagePipeline::encode(&image, OutputFormat::Jpeg, 0).is_err());
+    }
+
+    #[cfg(feature = "tracing")]
+    #[test]
+    fn test_traces_each_operation() {
+        use std::sync::{Arc, Mutex};
+        use tracing::field::{Field, Visit};
+        use tracing::span::{Attributes, Id, Record};
+        use tracing::{Event, Metadata};
+
+        // Records span fields as "name=value" lines and each event's elapsed_ms
+        #[derive(Default)]
+        struct Capture(Arc<Mutex<Vec<String>>>);
+        struct Fields(String);
+        impl Visit for Fields {
+            fn record_debug(&mut self, field: &Field, value: &dyn std::fmt::Debug) {
+                self.0 += &format!(" {}={:?}", field.name(), value);
+            }
+        }
+        impl tracing::Subscriber for Capture {
+            fn enabled(&self, _: &Metadata<'_>) -> bool {
+                true
+            }
+            fn new_span(&self, span: &Attributes<'_>) -> Id {
+                let mut fields = Fields(span.metadata().name().to_string());
+                span.record(&mut fields);
+                let mut lines = self.0.lock().unwrap();
+                lines.push(fields.0);
+                Id::from_u64(lines.len() as u64)
+            }
+            fn record(&self, _: &Id, _: &Record<'_>) {}
+            fn record_follows_from(&self, _: &Id, _: &Id) {}
+            fn event(&self, event: &Event<'_>) {
+                let mut fields = Fields(String::from("event"));
+                event.record(&mut fields);
+                self.0.lock().unwrap().push(fields.0);
+            }
+            fn enter(&self, _: &Id) {}
+            fn exit(&self, _: &Id) {}
+        }
+
+        let capture = Capture::default();
+        let lines = Arc::clone(&capture.0);
+        let operations = [
+            FilterOperation::Brightness(0.1),
+            FilterOperation::Contrast(1.2),
+            FilterOperation::Resize {
+                width: 17,
+                height: 13,
+                filter: ResampleFilter::Nearest,
+            },
+        ];
+        tracing::subscriber::with_default(capture, || {
+            ImagePipeline::new()
+                .process(&create_test_image(), &operations)
+                .unwrap();
+        });
+        let lines = lines.lock().unwrap();
+        assert_eq!(lines[0], "pipeline operations=3 width=100 height=100");
+        assert_eq!(
+            lines[1],
+            "operation name=brightness+contrast backend=\"fused\" width=100 height=100"
+        );
+        assert_eq!(
+            lines[3],
+            "operation name=resize backend=\"cpu\" width=100 height=100"
+        );
+        assert!(lines[4].starts_with(
+            "event message=operation finished out_width=17 out_height=13 elapsed_ms="
+        ));
+        assert!(lines[5].starts_with("event message=pipeline finished elapsed_ms="));
+    }
 }
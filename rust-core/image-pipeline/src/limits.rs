@@ -0,0 +1,222 @@
+//! Refusing images too large to hold in memory
+//!
+//! Limits are checked before pixels are allocated: when decoding, from the
+//! size in the file header, and before each operation, from the largest
+//! output it can produce. Custom filters can change size arbitrarily, so
+//! their output is only checked after they run.
+
+use crate::filters::upscaled_size;
+use crate::metrics::image_bytes;
+use crate::{FilterOperation, ImagePipeline, LoadOptions, PipelineError, Result};
+use image::RgbaImage;
+
+/// Largest width or height accepted for raw RGBA buffers from callers
+pub const MAX_DIMENSION: u32 = 65_535;
+
+/// Bytes in a `width`x`height` RGBA buffer
+///
+/// Fails with `PipelineError::ResourceLimit` if a side exceeds `MAX_DIMENSION`
+/// or the size does not fit in `usize`, as on 32-bit targets like wasm32.
+pub fn rgba_len(width: u32, height: u32) -> Result<usize> {
+    if width > MAX_DIMENSION || height > MAX_DIMENSION {
+        return Err(PipelineError::ResourceLimit(format!(
+            "{}x{} exceeds the largest side of {}",
+            width, height, MAX_DIMENSION
+        )));
+    }
+    (width as usize)
+        .checked_mul(height as usize)
+        .and_then(|pixels| pixels.checked_mul(4))
+        .ok_or_else(|| {
+            PipelineError::ResourceLimit(format!(
+                "{}x{} is too large to address on this platform",
+                width, height
+            ))
+        })
+}
+
+impl ImagePipeline {
+    /// Decode an image, refusing it if it exceeds `max_pixels` or `max_memory_bytes`
+    ///
+    /// With no limits set this is `load_from_bytes`.
+    pub fn decode(&self, bytes: &[u8]) -> Result<RgbaImage> {
+        if self.max_pixels == 0 && self.max_memory_bytes == 0 {
+            return Self::load_from_bytes(bytes);
+        }
+        let positive = |limit: u64| (limit > 0).then_some(limit);
+        let options = LoadOptions {
+            max_pixels: positive(self.max_pixels),
+            max_alloc: positive(self.max_memory_bytes),
+            ..LoadOptions::default()
+        };
+        Self::load_from_bytes_with_options(bytes, &options)
+    }
+
+    /// Fail with `PipelineError::ResourceLimit` if a `width`x`height` image
+    /// would exceed the limits on its own
+    pub fn check_dimensions(&self, width: u32, height: u32) -> Result<()> {
+        self.check_size("image", width, height, image_bytes(width, height))
+    }
+
+    // Refuse a `width`x`height` image when `held_bytes` of images would be alive
+    // alongside it
+    pub(crate) fn check_size(
+        &self,
+        what: &str,
+        width: u32,
+        height: u32,
+        held_bytes: u64,
+    ) -> Result<()> {
+        let pixels = width as u64 * height as u64;
+        if self.max_pixels > 0 && pixels > self.max_pixels {
+            return Err(PipelineError::ResourceLimit(format!(
+                "{} of {}x{} is {} pixels, over the limit of {}",
+                what, width, height, pixels, self.max_pixels
+            )));
+        }
+        if self.max_memory_bytes > 0 && held_bytes > self.max_memory_bytes {
+            return Err(PipelineError::ResourceLimit(format!(
+                "{} of {}x{} needs {} bytes of image buffers, over the limit of {}",
+                what, width, height, held_bytes, self.max_memory_bytes
+            )));
+        }
+        Ok(())
+    }
+}
+
+// Largest output `op` can produce from a `width`x`height` input
+pub(crate) fn max_output_size(op: &FilterOperation, width: u32, height: u32) -> (u32, u32) {
+    match op {
+        FilterOperation::Resize {
+            width: w,
+            height: h,
+            ..
+        } => (*w, *h),
+        FilterOperation::Thumbnail {
+            width: w,
+            height: h,
+            ..
+        } => ((*w).max(1), (*h).max(1)),
+        FilterOperation::SmartCrop {
+            width: w,
+            height: h,
+        }
+        | FilterOperation::SeamCarve {
+            width: w,
+            height: h,
+        } => (*w, *h),
+        FilterOperation::Rotate {
+            degrees,
+            expand: true,
+            ..
+        } => {
+            let (sin, cos) = (*degrees as f64).to_radians().sin_cos();
+            let (w, h) = (width as f64, height as f64);
+            (
+                (w * cos.abs() + h * sin.abs()).ceil() as u32 + 1,
+                (w * sin.abs() + h * cos.abs()).ceil() as u32 + 1,
+            )
+        }
+        FilterOperation::Pad {
+            top,
+            right,
+            bottom,
+            left,
+            ..
+        } => (
+            width.saturating_add(*left).saturating_add(*right),
+            height.saturating_add(*top).saturating_add(*bottom),
+        ),
+        FilterOperation::DropShadow { offset, sigma, .. } => {
+            let grow = |side: u32, shift: i32| {
+                let total = side as u64
+                    + 2 * (*sigma as f64 * 3.0).ceil() as u64
+                    + shift.unsigned_abs() as u64;
+                total.min(u32::MAX as u64) as u32
+            };
+            (grow(width, offset.0), grow(height, offset.1))
+        }
+        FilterOperation::Upscale { factor, .. } => upscaled_size(width, height, *factor),
+        FilterOperation::Masked { op, .. } => max_output_size(op, width, height),
+        _ => (width, height),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ResampleFilter;
+    use image::Rgba;
+
+    fn limited(max_pixels: u64, max_memory_bytes: u64) -> ImagePipeline {
+        let mut pipeline = ImagePipeline::new();
+        pipeline.max_pixels = max_pixels;
+        pipeline.max_memory_bytes = max_memory_bytes;
+        pipeline
+    }
+
+    #[test]
+    fn test_rgba_len() {
+        assert_eq!(rgba_len(3, 2).unwrap(), 24);
+        assert_eq!(rgba_len(0, 7).unwrap(), 0);
+        assert!(matches!(
+            rgba_len(MAX_DIMENSION + 1, 1),
+            Err(PipelineError::ResourceLimit(_))
+        ));
+        let largest = rgba_len(MAX_DIMENSION, MAX_DIMENSION);
+        if usize::BITS == 64 {
+            assert_eq!(largest.unwrap(), 4 * 65_535 * 65_535);
+        } else {
+            assert!(largest.is_err());
+        }
+    }
+
+    #[test]
+    fn test_decode_checks_header_size() {
+        let png = ImagePipeline::encode_to_png(&RgbaImage::new(40, 30)).unwrap();
+        assert!(limited(1200, 0).decode(&png).is_ok());
+        assert!(matches!(
+            limited(1199, 0).decode(&png),
+            Err(PipelineError::ResourceLimit(_))
+        ));
+        assert!(matches!(
+            limited(0, 4000).decode(&png),
+            Err(PipelineError::ResourceLimit(_))
+        ));
+        assert!(limited(1200, 0).decode(b"not an image").is_err());
+    }
+
+    #[test]
+    fn test_refuses_growing_past_limits() {
+        let image = RgbaImage::from_pixel(10, 10, Rgba([1, 2, 3, 255]));
+        let resize = |size: u32| FilterOperation::Resize {
+            width: size,
+            height: size,
+            filter: ResampleFilter::Nearest,
+        };
+        let pipeline = limited(400, 0);
+        assert!(pipeline.process(&image, &[resize(20)]).is_ok());
+        let err = pipeline.process(&image, &[resize(21)]).unwrap_err();
+        assert!(matches!(err, PipelineError::ResourceLimit(_)), "{}", err);
+        assert_eq!(err.operation_index(), Some(0));
+        assert!(pipeline
+            .process(&image, &[resize(20), FilterOperation::Invert, resize(5)])
+            .is_ok());
+        // Source, working copy and output all count against the memory limit
+        let pipeline = limited(0, 3 * 400);
+        assert!(pipeline.process(&image, &[FilterOperation::Invert]).is_ok());
+        assert!(pipeline.process(&image, &[resize(11)]).is_err());
+
+        let rotate = FilterOperation::Rotate {
+            degrees: 45.0,
+            background: Rgba([0, 0, 0, 0]),
+            expand: true,
+        };
+        let (w, h) = max_output_size(&rotate, 10, 10);
+        let rotated = ImagePipeline::new()
+            .process(&image, std::slice::from_ref(&rotate))
+            .unwrap();
+        assert!(rotated.width() <= w && rotated.height() <= h);
+        assert!(limited(200, 0).process(&image, &[rotate]).is_err());
+    }
+}
@@ -0,0 +1,241 @@
+//! Decoding untrusted input
+//!
+//! `ImagePipeline::load_from_bytes_with_options` reads the format and size
+//! from the file header and refuses the image before any pixels are
+//! allocated, then hands the same limits to the decoder for allocations the
+//! header does not reveal (e.g. extra frames or metadata).
+
+use crate::{ImagePipeline, PipelineError, Result};
+use image::error::{DecodingError, ImageFormatHint};
+use image::{DynamicImage, ImageError, ImageFormat, ImageReader, RgbaImage};
+use std::io::Cursor;
+use std::panic::{self, AssertUnwindSafe};
+
+/// Limits and formats accepted by `load_from_bytes_with_options`
+///
+/// `None` leaves a limit unchecked. The default accepts every format the
+/// build can decode, with the `image` crate's default 512 MiB allocation cap.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LoadOptions {
+    pub max_width: Option<u32>,
+    pub max_height: Option<u32>,
+    pub max_pixels: Option<u64>,
+    /// Largest allocation while decoding, and largest RGBA output in bytes
+    pub max_alloc: Option<u64>,
+    /// Formats to accept, detected from the content; `None` accepts all
+    pub allowed_formats: Option<Vec<ImageFormat>>,
+}
+
+impl Default for LoadOptions {
+    fn default() -> Self {
+        Self {
+            max_width: None,
+            max_height: None,
+            max_pixels: None,
+            max_alloc: image::Limits::default().max_alloc,
+            allowed_formats: None,
+        }
+    }
+}
+
+impl LoadOptions {
+    /// Conservative settings for uploads: common web formats up to
+    /// 16384 pixels a side and 100 megapixels
+    pub fn untrusted() -> Self {
+        Self {
+            max_width: Some(16384),
+            max_height: Some(16384),
+            max_pixels: Some(100_000_000),
+            max_alloc: Some(512 << 20),
+            allowed_formats: Some(vec![
+                ImageFormat::Png,
+                ImageFormat::Jpeg,
+                ImageFormat::Gif,
+                ImageFormat::WebP,
+                #[cfg(feature = "avif-decode")]
+                ImageFormat::Avif,
+            ]),
+        }
+    }
+
+    fn check_format(&self, format: Option<ImageFormat>) -> Result<()> {
+        let Some(allowed) = &self.allowed_formats else {
+            return Ok(());
+        };
+        match format {
+            Some(format) if allowed.contains(&format) => Ok(()),
+            Some(format) => Err(PipelineError::FormatNotAllowed(format!("{:?}", format))),
+            None => Err(PipelineError::FormatNotAllowed(
+                "unrecognized format".to_string(),
+            )),
+        }
+    }
+
+    fn check_dimensions(&self, width: u32, height: u32, bytes_per_pixel: u64) -> Result<()> {
+        let over = |limit: Option<u64>, value: u64| limit.is_some_and(|limit| value > limit);
+        let pixels = width as u64 * height as u64;
+        if over(self.max_width.map(u64::from), width as u64)
+            || over(self.max_height.map(u64::from), height as u64)
+            || over(self.max_pixels, pixels)
+        {
+            return Err(PipelineError::ResourceLimit(format!(
+                "image of {}x{} is larger than allowed",
+                width, height
+            )));
+        }
+        if over(self.max_alloc, pixels * bytes_per_pixel) {
+            return Err(PipelineError::ResourceLimit(format!(
+                "decoding {}x{} needs {} bytes, over the limit of {}",
+                width,
+                height,
+                pixels * bytes_per_pixel,
+                self.max_alloc.unwrap_or_default()
+            )));
+        }
+        Ok(())
+    }
+
+    fn decoder_limits(&self) -> image::Limits {
+        let mut limits = image::Limits::no_limits();
+        limits.max_image_width = self.max_width;
+        limits.max_image_height = self.max_height;
+        limits.max_alloc = self.max_alloc;
+        limits
+    }
+}
+
+impl ImagePipeline {
+    /// Load an image from untrusted bytes, checking it against `options`
+    ///
+    /// Fails with `PipelineError::FormatNotAllowed` for formats outside the
+    /// allow-list and `PipelineError::ResourceLimit` for images over a limit,
+    /// before decoding any pixels. A decoder panicking on malformed data is
+    /// reported as a decoding error.
+    pub fn load_from_bytes_with_options(bytes: &[u8], options: &LoadOptions) -> Result<RgbaImage> {
+        Ok(decode(bytes, options, 4)?.to_rgba8())
+    }
+}
+
+// Decode `bytes` as `load_from_bytes_with_options` does, for an output of
+// `bytes_per_pixel` bytes per pixel
+pub(crate) fn decode(
+    bytes: &[u8],
+    options: &LoadOptions,
+    bytes_per_pixel: u64,
+) -> Result<DynamicImage> {
+    let reader = ImageReader::new(Cursor::new(bytes)).with_guessed_format()?;
+    let format = reader.format();
+    options.check_format(format)?;
+    let (width, height) = reader.into_dimensions().map_err(limit_error)?;
+    options.check_dimensions(width, height, bytes_per_pixel)?;
+
+    let mut reader = ImageReader::new(Cursor::new(bytes));
+    if let Some(format) = format {
+        reader.set_format(format);
+    }
+    reader.limits(options.decoder_limits());
+    match panic::catch_unwind(AssertUnwindSafe(|| reader.decode())) {
+        Ok(decoded) => decoded.map_err(limit_error),
+        Err(_) => Err(ImageError::Decoding(DecodingError::new(
+            format.map_or(ImageFormatHint::Unknown, ImageFormatHint::Exact),
+            "malformed image data",
+        ))
+        .into()),
+    }
+}
+
+fn limit_error(e: ImageError) -> PipelineError {
+    match e {
+        ImageError::Limits(e) => PipelineError::ResourceLimit(e.to_string()),
+        other => other.into(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn png(width: u32, height: u32) -> Vec<u8> {
+        ImagePipeline::encode_to_png(&RgbaImage::new(width, height)).unwrap()
+    }
+
+    fn crc32(bytes: &[u8]) -> u32 {
+        !bytes.iter().fold(!0u32, |crc, &byte| {
+            (0..8).fold(crc ^ byte as u32, |crc, _| {
+                (crc >> 1) ^ (0xedb8_8320 & (crc & 1).wrapping_neg())
+            })
+        })
+    }
+
+    #[test]
+    fn test_limits_are_checked_before_decoding() {
+        let load = ImagePipeline::load_from_bytes_with_options;
+        assert_eq!(
+            load(&png(30, 20), &LoadOptions::default())
+                .unwrap()
+                .dimensions(),
+            (30, 20)
+        );
+        for options in [
+            LoadOptions {
+                max_width: Some(29),
+                ..LoadOptions::default()
+            },
+            LoadOptions {
+                max_height: Some(19),
+                ..LoadOptions::default()
+            },
+            LoadOptions {
+                max_pixels: Some(599),
+                ..LoadOptions::default()
+            },
+            LoadOptions {
+                max_alloc: Some(2399),
+                ..LoadOptions::default()
+            },
+        ] {
+            let err = load(&png(30, 20), &options).unwrap_err();
+            assert!(matches!(err, PipelineError::ResourceLimit(_)), "{}", err);
+        }
+
+        // A header claiming 50000x50000 is refused without allocating for it
+        let mut bomb = png(1, 1);
+        bomb[16..20].copy_from_slice(&50_000u32.to_be_bytes());
+        bomb[20..24].copy_from_slice(&50_000u32.to_be_bytes());
+        let crc = crc32(&bomb[12..29]);
+        bomb[29..33].copy_from_slice(&crc.to_be_bytes());
+        assert!(matches!(
+            load(&bomb, &LoadOptions::untrusted()),
+            Err(PipelineError::ResourceLimit(_))
+        ));
+    }
+
+    #[test]
+    fn test_format_allow_list() {
+        let bmp = {
+            let mut bytes = Vec::new();
+            RgbaImage::new(2, 2)
+                .write_to(&mut Cursor::new(&mut bytes), ImageFormat::Bmp)
+                .unwrap();
+            bytes
+        };
+        let options = LoadOptions::untrusted();
+        assert!(ImagePipeline::load_from_bytes_with_options(&png(2, 2), &options).is_ok());
+        assert!(matches!(
+            ImagePipeline::load_from_bytes_with_options(&bmp, &options),
+            Err(PipelineError::FormatNotAllowed(_))
+        ));
+        assert!(matches!(
+            ImagePipeline::load_from_bytes_with_options(b"garbage", &options),
+            Err(PipelineError::FormatNotAllowed(_))
+        ));
+        assert!(ImagePipeline::load_from_bytes_with_options(&bmp, &LoadOptions::default()).is_ok());
+
+        // Truncated data is an ordinary decoding error
+        let truncated = &png(8, 8)[..40];
+        assert!(matches!(
+            ImagePipeline::load_from_bytes_with_options(truncated, &options),
+            Err(PipelineError::ImageError(_))
+        ));
+    }
+}
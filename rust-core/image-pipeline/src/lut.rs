@@ -0,0 +1,150 @@
+//! 256-entry lookup tables for point filters
+//!
+//! Any filter that maps each channel value independently can be precomputed
+//! once into a table and then applied with a single lookup per channel.
+//! Tables compose, so a chain of adjustments costs the same as one.
+
+use image::{ImageBuffer, RgbaImage};
+use rayon::prelude::*;
+
+/// Per-channel lookup table for the RGB channels; alpha is left untouched
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Lut256 {
+    tables: [[u8; 256]; 3],
+}
+
+impl Default for Lut256 {
+    fn default() -> Self {
+        Self::identity()
+    }
+}
+
+impl Lut256 {
+    /// Table that maps every value to itself
+    pub fn identity() -> Self {
+        Self::from_fn(|v| v)
+    }
+
+    /// Build a table applying the same curve to R, G and B
+    pub fn from_fn<F: Fn(u8) -> u8>(curve: F) -> Self {
+        let mut table = [0u8; 256];
+        for (i, v) in table.iter_mut().enumerate() {
+            *v = curve(i as u8);
+        }
+        Self { tables: [table; 3] }
+    }
+
+    /// Build a table from separate R, G and B curves
+    pub fn from_channels(red: [u8; 256], green: [u8; 256], blue: [u8; 256]) -> Self {
+        Self {
+            tables: [red, green, blue],
+        }
+    }
+
+    /// Brightness adjustment, value: -1.0 (dark) to 1.0 (bright)
+    pub fn brightness(value: f32) -> Self {
+        let adjustment = (value * 255.0) as i32;
+        Self::from_fn(|v| (v as i32 + adjustment).clamp(0, 255) as u8)
+    }
+
+    /// Contrast adjustment around mid-gray, value: 0.0 (flat) to 2.0+ (high contrast)
+    pub fn contrast(value: f32) -> Self {
+        Self::from_fn(|v| (((v as f32 - 128.0) * value) + 128.0).clamp(0.0, 255.0) as u8)
+    }
+
+    /// Gamma correction: out = 255 * (in / 255)^(1 / gamma)
+    pub fn gamma(gamma: f32) -> Self {
+        let exponent = 1.0 / gamma;
+        Self::from_fn(|v| {
+            (255.0 * (v as f32 / 255.0).powf(exponent))
+                .round()
+                .clamp(0.0, 255.0) as u8
+        })
+    }
+
+    /// Color inversion
+    pub fn invert() -> Self {
+        Self::from_fn(|v| 255 - v)
+    }
+
+    /// Table for one channel (0 = R, 1 = G, 2 = B)
+    pub fn channel(&self, index: usize) -> &[u8; 256] {
+        &self.tables[index]
+    }
+
+    /// Compose two tables: the result applies `self` first, then `next`
+    pub fn then(&self, next: &Lut256) -> Lut256 {
+        let mut tables = self.tables;
+        for (table, next_table) in tables.iter_mut().zip(&next.tables) {
+            for v in table.iter_mut() {
+                *v = next_table[*v as usize];
+            }
+        }
+        Lut256 { tables }
+    }
+
+    /// Map a single RGBA pixel
+    #[inline]
+    pub fn map_pixel(&self, pixel: &mut [u8]) {
+        pixel[0] = self.tables[0][pixel[0] as usize];
+        pixel[1] = self.tables[1][pixel[1] as usize];
+        pixel[2] = self.tables[2][pixel[2] as usize];
+    }
+
+    /// Apply the table to every pixel in one parallel pass
+    pub fn apply(&self, image: &RgbaImage) -> RgbaImage {
+        let mut result = image.clone();
+        self.apply_in_place(&mut result);
+        result
+    }
+
+    /// Apply the table in place without allocating a new image
+    pub fn apply_in_place(&self, image: &mut RgbaImage) {
+        let row_len = (image.width() as usize * 4).max(4);
+        let pixels: &mut [u8] = image;
+        pixels.par_chunks_mut(row_len).for_each(|row| {
+            for pixel in row.chunks_exact_mut(4) {
+                self.map_pixel(pixel);
+            }
+        });
+    }
+
+    /// Apply the table to a raw RGBA buffer of the given size
+    pub fn apply_raw(&self, width: u32, height: u32, pixels: Vec<u8>) -> Option<RgbaImage> {
+        let mut image: RgbaImage = ImageBuffer::from_raw(width, height, pixels)?;
+        self.apply_in_place(&mut image);
+        Some(image)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgba;
+
+    #[test]
+    fn test_identity_is_noop() {
+        let image =
+            ImageBuffer::from_fn(8, 8, |x, y| Rgba([(x * 30) as u8, (y * 30) as u8, 7, 99]));
+        assert_eq!(Lut256::identity().apply(&image), image);
+    }
+
+    #[test]
+    fn test_then_composes_in_order() {
+        let brighten = Lut256::brightness(0.2);
+        let invert = Lut256::invert();
+        let composed = brighten.then(&invert);
+
+        for v in 0..=255u8 {
+            let expected = invert.channel(0)[brighten.channel(0)[v as usize] as usize];
+            assert_eq!(composed.channel(0)[v as usize], expected);
+        }
+    }
+
+    #[test]
+    fn test_preserves_alpha() {
+        let mut image = ImageBuffer::from_pixel(4, 4, Rgba([10, 20, 30, 40]));
+        Lut256::invert().apply_in_place(&mut image);
+        assert_eq!(image.get_pixel(0, 0), &Rgba([245, 235, 225, 40]));
+    }
+}
@@ -0,0 +1,283 @@
+//! 3D color lookup tables in the Adobe/Resolve `.cube` format
+//!
+//! A 3D LUT maps every RGB triple to a new one, so unlike `Lut256` it can
+//! express looks that mix channels. Values between grid points are
+//! interpolated trilinearly.
+
+use crate::hash::{Digest, Sha256};
+use crate::{PipelineError, Result};
+use image::RgbaImage;
+use rayon::prelude::*;
+use std::fmt;
+use std::path::Path;
+
+/// Largest grid size accepted when parsing (256^3 entries)
+pub const MAX_LUT3D_SIZE: usize = 256;
+
+/// A parsed 3D LUT with `size`^3 entries, red varying fastest
+#[derive(Clone, PartialEq)]
+pub struct Lut3D {
+    title: Option<String>,
+    source: Option<String>,
+    size: usize,
+    domain_min: [f32; 3],
+    domain_max: [f32; 3],
+    table: Vec<[f32; 3]>,
+}
+
+impl fmt::Debug for Lut3D {
+    // The table itself is far too large to be useful in debug output
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Lut3D")
+            .field("title", &self.title)
+            .field("source", &self.source)
+            .field("size", &self.size)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Lut3D {
+    /// Parse the contents of a `.cube` file
+    pub fn parse_cube(text: &str) -> Result<Self> {
+        let mut title = None;
+        let mut size = None;
+        let mut domain_min = [0.0; 3];
+        let mut domain_max = [1.0; 3];
+        let mut table = Vec::new();
+
+        for (line_no, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let err = |msg: &str| invalid(format!("line {}: {}", line_no + 1, msg));
+            let mut parts = line.split_whitespace();
+            let keyword = parts.next().unwrap_or_default();
+
+            match keyword {
+                "TITLE" => {
+                    let rest = line["TITLE".len()..].trim();
+                    title = Some(rest.trim_matches('"').to_string());
+                }
+                "LUT_3D_SIZE" => {
+                    let n: usize = parts
+                        .next()
+                        .and_then(|v| v.parse().ok())
+                        .ok_or_else(|| err("LUT_3D_SIZE needs an integer"))?;
+                    if !(2..=MAX_LUT3D_SIZE).contains(&n) {
+                        return Err(err(&format!(
+                            "LUT_3D_SIZE must be between 2 and {}, got {}",
+                            MAX_LUT3D_SIZE, n
+                        )));
+                    }
+                    size = Some(n);
+                    table.reserve(n * n * n);
+                }
+                "DOMAIN_MIN" => {
+                    domain_min = parse_triple(parts).ok_or_else(|| err("bad DOMAIN_MIN"))?
+                }
+                "DOMAIN_MAX" => {
+                    domain_max = parse_triple(parts).ok_or_else(|| err("bad DOMAIN_MAX"))?
+                }
+                "LUT_1D_SIZE" => return Err(err("1D LUTs are not supported")),
+                _ if keyword.starts_with(|c: char| c.is_ascii_digit() || c == '-' || c == '.') => {
+                    let entry = parse_triple(line.split_whitespace())
+                        .ok_or_else(|| err("expected three numbers"))?;
+                    table.push(entry);
+                }
+                // Unknown keywords are skipped, as other .cube readers do
+                _ => {}
+            }
+        }
+
+        let size = size.ok_or_else(|| invalid("missing LUT_3D_SIZE".to_string()))?;
+        if table.len() != size * size * size {
+            return Err(invalid(format!(
+                "expected {} entries for size {}, found {}",
+                size * size * size,
+                size,
+                table.len()
+            )));
+        }
+        if (0..3).any(|c| domain_max[c] <= domain_min[c]) {
+            return Err(invalid("DOMAIN_MAX must exceed DOMAIN_MIN".to_string()));
+        }
+
+        Ok(Self {
+            title,
+            source: None,
+            size,
+            domain_min,
+            domain_max,
+            table,
+        })
+    }
+
+    /// Load a `.cube` file from disk
+    pub fn load_cube<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let text = std::fs::read_to_string(path)?;
+        let mut lut = Self::parse_cube(&text)?;
+        lut.source = Some(path.display().to_string());
+        Ok(lut)
+    }
+
+    /// Identity LUT of the given grid size, mainly useful for testing
+    pub fn identity(size: usize) -> Self {
+        let step = 1.0 / (size - 1) as f32;
+        let mut table = Vec::with_capacity(size * size * size);
+        for b in 0..size {
+            for g in 0..size {
+                for r in 0..size {
+                    table.push([r as f32 * step, g as f32 * step, b as f32 * step]);
+                }
+            }
+        }
+        Self {
+            title: None,
+            source: None,
+            size,
+            domain_min: [0.0; 3],
+            domain_max: [1.0; 3],
+            table,
+        }
+    }
+
+    /// Grid size along each axis
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// `TITLE` from the file, if present
+    pub fn title(&self) -> Option<&str> {
+        self.title.as_deref()
+    }
+
+    /// Path the LUT was loaded from, if it came from disk
+    pub fn source(&self) -> Option<&str> {
+        self.source.as_deref()
+    }
+
+    // Feed everything that affects the output into `hasher`
+    pub(crate) fn hash_into(&self, hasher: &mut Sha256) {
+        hasher.update((self.size as u64).to_le_bytes());
+        let mut bytes = Vec::with_capacity(1024 * 12);
+        let domain = [self.domain_min, self.domain_max];
+        for entries in std::iter::once(&domain[..]).chain(self.table.chunks(1024)) {
+            bytes.clear();
+            bytes.extend(entries.iter().flatten().flat_map(|v| v.to_le_bytes()));
+            hasher.update(&bytes);
+        }
+    }
+
+    /// Map one RGB triple (0-255) through the LUT with trilinear interpolation
+    pub fn map_rgb(&self, rgb: [u8; 3]) -> [u8; 3] {
+        let n = self.size;
+        let max_index = (n - 1) as f32;
+
+        let mut base = [0usize; 3];
+        let mut next = [0usize; 3];
+        let mut frac = [0f32; 3];
+        for c in 0..3 {
+            let normalized = rgb[c] as f32 / 255.0;
+            let x = ((normalized - self.domain_min[c]) / (self.domain_max[c] - self.domain_min[c])
+                * max_index)
+                .clamp(0.0, max_index);
+            base[c] = x.floor() as usize;
+            next[c] = (base[c] + 1).min(n - 1);
+            frac[c] = x - base[c] as f32;
+        }
+
+        let at = |r: usize, g: usize, b: usize| self.table[r + g * n + b * n * n];
+        let lerp = |a: [f32; 3], b: [f32; 3], t: f32| {
+            [
+                a[0] + (b[0] - a[0]) * t,
+                a[1] + (b[1] - a[1]) * t,
+                a[2] + (b[2] - a[2]) * t,
+            ]
+        };
+
+        let [r0, g0, b0] = base;
+        let [r1, g1, b1] = next;
+        let [fr, fg, fb] = frac;
+
+        let c00 = lerp(at(r0, g0, b0), at(r1, g0, b0), fr);
+        let c10 = lerp(at(r0, g1, b0), at(r1, g1, b0), fr);
+        let c01 = lerp(at(r0, g0, b1), at(r1, g0, b1), fr);
+        let c11 = lerp(at(r0, g1, b1), at(r1, g1, b1), fr);
+        let c0 = lerp(c00, c10, fg);
+        let c1 = lerp(c01, c11, fg);
+        let out = lerp(c0, c1, fb);
+
+        out.map(|v| (v * 255.0).round().clamp(0.0, 255.0) as u8)
+    }
+
+    /// Apply the LUT to every pixel; alpha is preserved
+    pub fn apply(&self, image: &RgbaImage) -> RgbaImage {
+        let mut result = image.clone();
+        self.apply_in_place(&mut result);
+        result
+    }
+
+    /// Apply the LUT in place without allocating a new image
+    pub fn apply_in_place(&self, image: &mut RgbaImage) {
+        let row_len = (image.width() as usize * 4).max(4);
+        let pixels: &mut [u8] = image;
+        pixels.par_chunks_mut(row_len).for_each(|row| {
+            for pixel in row.chunks_exact_mut(4) {
+                let [r, g, b] = self.map_rgb([pixel[0], pixel[1], pixel[2]]);
+                pixel[0] = r;
+                pixel[1] = g;
+                pixel[2] = b;
+            }
+        });
+    }
+}
+
+fn parse_triple<'a, I: Iterator<Item = &'a str>>(mut parts: I) -> Option<[f32; 3]> {
+    let mut values = [0.0; 3];
+    for v in values.iter_mut() {
+        *v = parts.next()?.parse().ok().filter(|v: &f32| v.is_finite())?;
+    }
+    parts.next().is_none().then_some(values)
+}
+
+fn invalid(message: String) -> PipelineError {
+    PipelineError::InvalidLut(message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{ImageBuffer, Rgba};
+
+    const SWAP_RED_BLUE: &str = "# swap red and blue\nTITLE \"Swap\"\nLUT_3D_SIZE 2\n\
+        0 0 0\n0 0 1\n0 1 0\n0 1 1\n1 0 0\n1 0 1\n1 1 0\n1 1 1\n";
+
+    #[test]
+    fn test_parse_cube() {
+        let lut = Lut3D::parse_cube(SWAP_RED_BLUE).unwrap();
+        assert_eq!(lut.size(), 2);
+        assert_eq!(lut.title(), Some("Swap"));
+        assert_eq!(lut.map_rgb([200, 100, 10]), [10, 100, 200]);
+    }
+
+    #[test]
+    fn test_parse_errors() {
+        assert!(Lut3D::parse_cube("0 0 0\n").is_err());
+        assert!(Lut3D::parse_cube("LUT_3D_SIZE 2\n0 0 0\n").is_err());
+        assert!(Lut3D::parse_cube("LUT_1D_SIZE 16\n").is_err());
+        let err = Lut3D::parse_cube("LUT_3D_SIZE 2\n0 0 x\n").unwrap_err();
+        assert!(err.to_string().contains("line 2"));
+    }
+
+    #[test]
+    fn test_identity_interpolates_exactly() {
+        let lut = Lut3D::identity(17);
+        let image = ImageBuffer::from_fn(16, 16, |x, y| {
+            Rgba([(x * 16) as u8, (y * 16) as u8, (x * 7 + y) as u8, 128])
+        });
+        assert_eq!(lut.apply(&image), image);
+    }
+}
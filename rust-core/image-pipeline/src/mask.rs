@@ -0,0 +1,189 @@
+//! Regions for restricting an operation to part of an image
+
+use crate::{scale_dimension, PipelineError, Result};
+use image::{imageops, GrayImage, RgbaImage};
+use rayon::prelude::*;
+use std::path::Path;
+use std::sync::Arc;
+
+/// Area of an image that a masked operation may change
+#[derive(Debug, Clone)]
+pub enum Region {
+    /// Axis-aligned rectangle; parts outside the image are ignored
+    Rect {
+        x: u32,
+        y: u32,
+        width: u32,
+        height: u32,
+    },
+    /// Per-pixel weight (0 = untouched, 255 = fully filtered), same size as the image
+    Mask(Arc<GrayImage>),
+}
+
+impl Region {
+    /// Load a mask image, using its alpha channel if it has one and luminance otherwise
+    pub fn load_mask<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let image = image::open(path)?;
+        let mask = if image.color().has_alpha() {
+            let rgba = image.to_rgba8();
+            GrayImage::from_fn(rgba.width(), rgba.height(), |x, y| {
+                image::Luma([rgba.get_pixel(x, y)[3]])
+            })
+        } else {
+            image.to_luma8()
+        };
+        Ok(Region::Mask(Arc::new(mask)))
+    }
+
+    /// Mask from raw bytes: one byte per pixel, or RGBA where the alpha channel is used
+    pub fn mask_from_raw(width: u32, height: u32, data: &[u8]) -> Result<Self> {
+        let pixels = width as usize * height as usize;
+        let weights = if data.len() == pixels {
+            data.to_vec()
+        } else if data.len() == pixels * 4 {
+            data.chunks_exact(4).map(|p| p[3]).collect()
+        } else {
+            return Err(PipelineError::InvalidParameter(format!(
+                "mask must be {} (gray) or {} (RGBA) bytes, got {}",
+                pixels,
+                pixels * 4,
+                data.len()
+            )));
+        };
+        let mask = GrayImage::from_raw(width, height, weights).expect("length checked above");
+        Ok(Region::Mask(Arc::new(mask)))
+    }
+
+    pub(crate) fn check(&self) -> std::result::Result<(), String> {
+        match self {
+            Region::Rect { width, height, .. } if *width == 0 || *height == 0 => Err(format!(
+                "region must be at least 1x1, got {}x{}",
+                width, height
+            )),
+            _ => Ok(()),
+        }
+    }
+
+    /// Region for an image resized by `scale`
+    pub fn scaled(&self, scale: f64) -> Region {
+        let dim = |v: u32| scale_dimension(v, scale);
+        match self {
+            Region::Rect {
+                x,
+                y,
+                width,
+                height,
+            } => Region::Rect {
+                x: (*x as f64 * scale).round() as u32,
+                y: (*y as f64 * scale).round() as u32,
+                width: dim(*width),
+                height: dim(*height),
+            },
+            Region::Mask(mask) => Region::Mask(Arc::new(imageops::resize(
+                mask.as_ref(),
+                dim(mask.width()),
+                dim(mask.height()),
+                imageops::FilterType::Triangle,
+            ))),
+        }
+    }
+
+    /// Keep `filtered` inside the region and `original` everywhere else
+    pub fn blend(&self, original: &RgbaImage, filtered: &RgbaImage) -> Result<RgbaImage> {
+        if original.dimensions() != filtered.dimensions() {
+            return Err(PipelineError::InvalidParameter(
+                "masked operations must not change the image size".to_string(),
+            ));
+        }
+
+        let (width, height) = original.dimensions();
+        let row_len = (width as usize * 4).max(4);
+        let mut result = original.clone();
+
+        match self {
+            Region::Rect {
+                x,
+                y,
+                width: w,
+                height: h,
+            } => {
+                let x0 = (*x).min(width) as usize;
+                let x1 = x.saturating_add(*w).min(width) as usize;
+                let y0 = (*y).min(height) as usize;
+                let y1 = y.saturating_add(*h).min(height) as usize;
+
+                let pixels: &mut [u8] = &mut result;
+                pixels
+                    .par_chunks_mut(row_len)
+                    .zip(filtered.as_raw().par_chunks(row_len))
+                    .skip(y0)
+                    .take(y1.saturating_sub(y0))
+                    .for_each(|(out, src)| {
+                        out[x0 * 4..x1 * 4].copy_from_slice(&src[x0 * 4..x1 * 4]);
+                    });
+            }
+            Region::Mask(mask) => {
+                if mask.dimensions() != (width, height) {
+                    return Err(PipelineError::InvalidParameter(format!(
+                        "mask is {}x{} but the image is {}x{}",
+                        mask.width(),
+                        mask.height(),
+                        width,
+                        height
+                    )));
+                }
+
+                let pixels: &mut [u8] = &mut result;
+                pixels
+                    .par_chunks_mut(4)
+                    .zip(filtered.as_raw().par_chunks(4))
+                    .zip(mask.as_raw().par_iter())
+                    .for_each(|((out, src), &m)| {
+                        let m = m as u32;
+                        for c in 0..4 {
+                            out[c] =
+                                ((src[c] as u32 * m + out[c] as u32 * (255 - m) + 127) / 255) as u8;
+                        }
+                    });
+            }
+        }
+
+        Ok(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{ImageBuffer, Rgba};
+
+    #[test]
+    fn test_rect_blend_clips_to_image() {
+        let original = ImageBuffer::from_pixel(10, 10, Rgba([0, 0, 0, 255]));
+        let filtered = ImageBuffer::from_pixel(10, 10, Rgba([255, 255, 255, 255]));
+        let region = Region::Rect {
+            x: 8,
+            y: 5,
+            width: 100,
+            height: 2,
+        };
+
+        let result = region.blend(&original, &filtered).unwrap();
+        assert_eq!(result.get_pixel(9, 5)[0], 255);
+        assert_eq!(result.get_pixel(8, 6)[0], 255);
+        assert_eq!(result.get_pixel(7, 5)[0], 0);
+        assert_eq!(result.get_pixel(9, 7)[0], 0);
+    }
+
+    #[test]
+    fn test_mask_blend_weights() {
+        let original = ImageBuffer::from_pixel(2, 1, Rgba([0, 0, 0, 255]));
+        let filtered = ImageBuffer::from_pixel(2, 1, Rgba([200, 200, 200, 255]));
+        let region = Region::mask_from_raw(2, 1, &[0, 128]).unwrap();
+
+        let result = region.blend(&original, &filtered).unwrap();
+        assert_eq!(result.get_pixel(0, 0)[0], 0);
+        assert_eq!(result.get_pixel(1, 0)[0], 100);
+        assert!(Region::mask_from_raw(3, 3, &[0; 5]).is_err());
+    }
+}
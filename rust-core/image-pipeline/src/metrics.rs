@@ -0,0 +1,148 @@
+//! Per-operation timing and memory figures from `ImagePipeline::process_with_metrics`
+//!
+//! Buffer sizes count the RGBA images the pipeline holds, not every
+//! allocation: filters with large scratch space (blurs, convolutions) use
+//! more than reported.
+
+use crate::{clock, CancellationToken, FilterOperation, ImagePipeline, PipelineError, Result};
+use image::RgbaImage;
+use serde::Serialize;
+
+/// One step of the executed plan
+///
+/// Point operations fused into a single pass share a step.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct StepMetrics {
+    /// Operation name, or names joined with `+` when fused
+    pub name: String,
+    /// What ran the step: a backend name, "fused" or "custom"
+    pub backend: String,
+    /// Indices of the operations in this step
+    pub operations: Vec<usize>,
+    pub elapsed_ms: f64,
+    pub input_width: u32,
+    pub input_height: u32,
+    pub output_width: u32,
+    pub output_height: u32,
+    /// Input plus output image bytes, both alive while the step runs
+    pub buffer_bytes: u64,
+}
+
+/// Metrics of one pipeline run
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct PipelineReport {
+    pub steps: Vec<StepMetrics>,
+    /// Wall time of the whole run, including validation
+    pub total_ms: f64,
+    /// Most image bytes held at once, counting the caller's source image
+    pub peak_buffer_bytes: u64,
+    pub width: u32,
+    pub height: u32,
+}
+
+impl PipelineReport {
+    /// The step that took longest
+    pub fn slowest(&self) -> Option<&StepMetrics> {
+        self.steps
+            .iter()
+            .max_by(|a, b| a.elapsed_ms.total_cmp(&b.elapsed_ms))
+    }
+
+    pub fn to_json(&self) -> Result<String> {
+        serde_json::to_string(self).map_err(|e| PipelineError::ProcessingError(e.to_string()))
+    }
+}
+
+pub(crate) fn image_bytes(width: u32, height: u32) -> u64 {
+    width as u64 * height as u64 * 4
+}
+
+impl ImagePipeline {
+    /// Process an image and report time and buffer memory per operation
+    pub fn process_with_metrics(
+        &self,
+        image: &RgbaImage,
+        operations: &[FilterOperation],
+    ) -> Result<(RgbaImage, PipelineReport)> {
+        let started = clock::now_ms();
+        let mut steps = Vec::new();
+        let output = self.execute(
+            image,
+            operations,
+            &CancellationToken::new(),
+            |_, _| true,
+            Some(&mut steps),
+        )?;
+        // The working copy and the step's output live next to the source
+        let source = image_bytes(image.width(), image.height());
+        let peak_buffer_bytes = source
+            + steps
+                .iter()
+                .map(|step| step.buffer_bytes)
+                .max()
+                .unwrap_or(source);
+        let report = PipelineReport {
+            steps,
+            total_ms: clock::now_ms() - started,
+            peak_buffer_bytes,
+            width: output.width(),
+            height: output.height(),
+        };
+        Ok((output, report))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ResampleFilter;
+    use image::Rgba;
+
+    #[test]
+    fn test_reports_each_step() {
+        let image = RgbaImage::from_pixel(8, 4, Rgba([100, 150, 200, 255]));
+        let ops = vec![
+            FilterOperation::Brightness(0.1),
+            FilterOperation::Contrast(0.2),
+            FilterOperation::Resize {
+                width: 16,
+                height: 8,
+                filter: ResampleFilter::Nearest,
+            },
+            FilterOperation::Invert,
+        ];
+        let pipeline = ImagePipeline::new();
+        let (output, report) = pipeline.process_with_metrics(&image, &ops).unwrap();
+        assert_eq!(output, pipeline.process(&image, &ops).unwrap());
+        assert_eq!((report.width, report.height), (16, 8));
+
+        let names: Vec<&str> = report.steps.iter().map(|s| s.name.as_str()).collect();
+        assert_eq!(names, ["brightness+contrast", "resize", "invert"]);
+        assert_eq!(report.steps[0].backend, "fused");
+        assert_eq!(report.steps[0].operations, [0, 1]);
+        let resize = &report.steps[1];
+        assert_eq!((resize.input_width, resize.output_width), (8, 16));
+        assert_eq!(resize.buffer_bytes, 8 * 4 * 4 + 16 * 8 * 4);
+        assert_eq!(report.peak_buffer_bytes, 8 * 4 * 4 + 2 * 16 * 8 * 4);
+        assert!(report.steps.iter().all(|s| s.elapsed_ms >= 0.0));
+        assert!(report.total_ms >= report.steps.iter().map(|s| s.elapsed_ms).sum::<f64>());
+        assert!(report.slowest().is_some());
+
+        let json: serde_json::Value = serde_json::from_str(&report.to_json().unwrap()).unwrap();
+        assert_eq!(json["steps"][1]["name"], "resize");
+        assert_eq!(json["width"], 16);
+    }
+
+    #[test]
+    fn test_empty_pipeline() {
+        let image = RgbaImage::new(2, 2);
+        let (_, report) = ImagePipeline::new()
+            .process_with_metrics(&image, &[])
+            .unwrap();
+        assert!(report.steps.is_empty());
+        assert_eq!(report.peak_buffer_bytes, 32);
+        assert!(ImagePipeline::new()
+            .process_with_metrics(&image, &[FilterOperation::Blur(-1.0)])
+            .is_err());
+    }
+}
@@ -0,0 +1,510 @@
+//! Running ONNX models (segmentation, super-resolution, style transfer) as operations
+//!
+//! Inference uses `tract` and is only compiled with the `onnx` feature, so the
+//! default and WASM builds stay small; without it loading a model fails. An
+//! image is turned into a float tensor by `Preprocess` and the model's first
+//! output is turned back into pixels as described by `Postprocess`.
+
+use crate::hash::{Digest, Sha256};
+use crate::{CancellationToken, PipelineError, Result};
+use image::imageops::{self, FilterType};
+use image::{GrayImage, RgbaImage};
+use std::borrow::Cow;
+use std::fmt;
+use std::path::Path;
+
+/// Order of the dimensions of image tensors
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TensorLayout {
+    /// `[1, channels, height, width]`, used by most PyTorch exports
+    #[default]
+    Nchw,
+    /// `[1, height, width, channels]`, used by most TensorFlow exports
+    Nhwc,
+}
+
+/// How an image becomes the model's input tensor
+///
+/// RGB values are scaled to 0.0-1.0, then normalized as `(v - mean) / std`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Preprocess {
+    /// Resize to this `(width, height)` first, for models with a fixed input size
+    pub size: Option<(u32, u32)>,
+    pub layout: TensorLayout,
+    pub mean: [f32; 3],
+    pub std: [f32; 3],
+}
+
+impl Preprocess {
+    pub(crate) fn check(&self) -> std::result::Result<(), String> {
+        if let Some((width, height)) = self.size {
+            if width == 0 || height == 0 {
+                return Err(format!(
+                    "input size must be at least 1x1, got {}x{}",
+                    width, height
+                ));
+            }
+        }
+        if !self.mean.iter().all(|v| v.is_finite())
+            || !self.std.iter().all(|v| v.is_finite() && *v != 0.0)
+        {
+            return Err("mean must be finite and std finite and non-zero".to_string());
+        }
+        Ok(())
+    }
+}
+
+impl Default for Preprocess {
+    fn default() -> Self {
+        Self {
+            size: None,
+            layout: TensorLayout::Nchw,
+            mean: [0.0; 3],
+            std: [1.0; 3],
+        }
+    }
+}
+
+/// How the model's output tensor becomes the result
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Postprocess {
+    /// The output is an image with 1, 3 or 4 channels, normalized like the input
+    ///
+    /// Its size relative to the model input is kept, so a 2x super-resolution
+    /// model doubles the image size even when `Preprocess::size` is set.
+    #[default]
+    Image,
+    /// The output's first channel is a 0.0-1.0 matte that replaces the alpha
+    /// channel, e.g. from a segmentation model
+    Alpha,
+}
+
+/// A loaded ONNX model
+pub struct Model {
+    source: Option<String>,
+    digest: [u8; 32],
+    #[cfg(feature = "onnx")]
+    runtime: runtime::Runtime,
+}
+
+impl fmt::Debug for Model {
+    // The network itself is far too large to be useful in debug output
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Model")
+            .field("source", &self.source)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Model {
+    /// Load a `.onnx` file
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let bytes = std::fs::read(path.as_ref())?;
+        let mut model = Self::from_bytes(&bytes)?;
+        model.source = Some(path.as_ref().to_string_lossy().into_owned());
+        Ok(model)
+    }
+
+    /// Parse an ONNX model held in memory
+    #[cfg_attr(not(feature = "onnx"), allow(unused_variables))]
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        #[cfg(feature = "onnx")]
+        return Ok(Self {
+            source: None,
+            digest: crate::hash::sha256(bytes),
+            runtime: runtime::Runtime::parse(bytes)?,
+        });
+        #[cfg(not(feature = "onnx"))]
+        Err(not_enabled())
+    }
+
+    /// Path the model was loaded from, if it came from disk
+    pub fn source(&self) -> Option<&str> {
+        self.source.as_deref()
+    }
+
+    // Feed everything that affects the output into `hasher`
+    pub(crate) fn hash_into(&self, hasher: &mut Sha256) {
+        hasher.update(self.digest);
+    }
+
+    /// Run the model on `image`
+    pub fn apply(
+        &self,
+        image: &RgbaImage,
+        pre: &Preprocess,
+        post: Postprocess,
+        token: &CancellationToken,
+    ) -> Result<RgbaImage> {
+        let (width, height) = image.dimensions();
+        let (in_width, in_height) = pre.size.unwrap_or((width, height));
+        let input = if (in_width, in_height) == (width, height) {
+            Cow::Borrowed(image)
+        } else {
+            Cow::Owned(imageops::resize(
+                image,
+                in_width,
+                in_height,
+                FilterType::Triangle,
+            ))
+        };
+
+        token.check()?;
+        let (shape, data) = self.run(&tensor_shape(&input, pre), &tensor_data(&input, pre))?;
+        token.check()?;
+        let output = Planes::new(&shape, data, pre.layout)?;
+
+        match post {
+            Postprocess::Image => {
+                let out_width = scale_size(width, output.width, in_width);
+                let out_height = scale_size(height, output.height, in_height);
+                let mut result = output.to_image(pre);
+                if result.dimensions() != (out_width, out_height) {
+                    result = imageops::resize(&result, out_width, out_height, FilterType::Triangle);
+                }
+                if output.channels < 4 {
+                    copy_alpha(image, &mut result);
+                }
+                Ok(result)
+            }
+            Postprocess::Alpha => {
+                let mut matte = output.plane(0);
+                if matte.dimensions() != (width, height) {
+                    matte = imageops::resize(&matte, width, height, FilterType::Triangle);
+                }
+                let mut result = image.clone();
+                for (pixel, alpha) in result.pixels_mut().zip(matte.pixels()) {
+                    pixel[3] = alpha[0];
+                }
+                Ok(result)
+            }
+        }
+    }
+
+    #[cfg(feature = "onnx")]
+    fn run(&self, shape: &[usize], data: &[f32]) -> Result<(Vec<usize>, Vec<f32>)> {
+        self.runtime.run(shape, data)
+    }
+
+    #[cfg(not(feature = "onnx"))]
+    fn run(&self, _shape: &[usize], _data: &[f32]) -> Result<(Vec<usize>, Vec<f32>)> {
+        Err(not_enabled())
+    }
+}
+
+#[cfg(not(feature = "onnx"))]
+fn not_enabled() -> PipelineError {
+    PipelineError::InvalidModel("built without the `onnx` feature".to_string())
+}
+
+#[cfg(feature = "onnx")]
+mod runtime {
+    use crate::{PipelineError, Result};
+    use std::sync::{Arc, Mutex, PoisonError};
+    use tract_onnx::prelude::*;
+
+    type Plan = TypedRunnableModel<TypedModel>;
+
+    /// A parsed model and the plan optimized for the last input shape
+    pub(super) struct Runtime {
+        model: InferenceModel,
+        plan: Mutex<Option<(Vec<usize>, Arc<Plan>)>>,
+    }
+
+    impl Runtime {
+        pub(super) fn parse(mut bytes: &[u8]) -> Result<Self> {
+            let model = onnx().model_for_read(&mut bytes).map_err(invalid)?;
+            Ok(Self {
+                model,
+                plan: Mutex::new(None),
+            })
+        }
+
+        pub(super) fn run(&self, shape: &[usize], data: &[f32]) -> Result<(Vec<usize>, Vec<f32>)> {
+            let input = Tensor::from_shape(shape, data).map_err(failed)?;
+            let outputs = self.plan(shape)?.run(tvec!(input.into())).map_err(failed)?;
+            let output = outputs
+                .first()
+                .ok_or_else(|| PipelineError::InvalidModel("model has no outputs".to_string()))?
+                .cast_to::<f32>()
+                .map_err(failed)?;
+            let view = output.to_array_view::<f32>().map_err(failed)?;
+            Ok((view.shape().to_vec(), view.iter().copied().collect()))
+        }
+
+        // Optimizing is expensive, so the plan is kept while the shape stays the same
+        fn plan(&self, shape: &[usize]) -> Result<Arc<Plan>> {
+            let mut cached = self.plan.lock().unwrap_or_else(PoisonError::into_inner);
+            if let Some((cached_shape, plan)) = cached.as_ref() {
+                if cached_shape == shape {
+                    return Ok(Arc::clone(plan));
+                }
+            }
+            let plan = self
+                .model
+                .clone()
+                .with_input_fact(0, f32::fact(shape).into())
+                .and_then(|model| model.into_optimized())
+                .and_then(|model| model.into_runnable())
+                .map(Arc::new)
+                .map_err(invalid)?;
+            *cached = Some((shape.to_vec(), Arc::clone(&plan)));
+            Ok(plan)
+        }
+    }
+
+    fn invalid(err: TractError) -> PipelineError {
+        PipelineError::InvalidModel(format!("{:#}", err))
+    }
+
+    fn failed(err: TractError) -> PipelineError {
+        PipelineError::ProcessingError(format!("model inference failed: {:#}", err))
+    }
+}
+
+// Shape of the input tensor for `image`
+fn tensor_shape(image: &RgbaImage, pre: &Preprocess) -> Vec<usize> {
+    let (width, height) = (image.width() as usize, image.height() as usize);
+    match pre.layout {
+        TensorLayout::Nchw => vec![1, 3, height, width],
+        TensorLayout::Nhwc => vec![1, height, width, 3],
+    }
+}
+
+// Normalized RGB values in `pre.layout` order
+fn tensor_data(image: &RgbaImage, pre: &Preprocess) -> Vec<f32> {
+    let normalize = |c: usize, value: u8| (value as f32 / 255.0 - pre.mean[c]) / pre.std[c];
+    match pre.layout {
+        TensorLayout::Nchw => (0..3)
+            .flat_map(|c| image.pixels().map(move |p| normalize(c, p[c])))
+            .collect(),
+        TensorLayout::Nhwc => image
+            .pixels()
+            .flat_map(|p| (0..3).map(move |c| normalize(c, p[c])))
+            .collect(),
+    }
+}
+
+/// Output tensor of a single image, planar
+struct Planes {
+    channels: usize,
+    width: u32,
+    height: u32,
+    data: Vec<f32>,
+}
+
+impl Planes {
+    // Accepts `[1, c, h, w]`, `[c, h, w]` and `[h, w]`, or the NHWC equivalents
+    fn new(shape: &[usize], data: Vec<f32>, layout: TensorLayout) -> Result<Self> {
+        let dims = match shape {
+            [1, a, b, c] | [a, b, c] => [*a, *b, *c],
+            [h, w] => match layout {
+                TensorLayout::Nchw => [1, *h, *w],
+                TensorLayout::Nhwc => [*h, *w, 1],
+            },
+            _ => return Err(unsupported(shape)),
+        };
+        let (channels, height, width) = match layout {
+            TensorLayout::Nchw => (dims[0], dims[1], dims[2]),
+            TensorLayout::Nhwc => (dims[2], dims[0], dims[1]),
+        };
+        if !matches!(channels, 1 | 3 | 4) || width == 0 || height == 0 {
+            return Err(unsupported(shape));
+        }
+        let data = match layout {
+            TensorLayout::Nchw => data,
+            TensorLayout::Nhwc => (0..channels)
+                .flat_map(|c| data.iter().skip(c).step_by(channels).copied())
+                .collect::<Vec<_>>(),
+        };
+        Ok(Self {
+            channels,
+            width: width as u32,
+            height: height as u32,
+            data,
+        })
+    }
+
+    // Channel `c` with values 0.0-1.0 mapped to 0-255
+    fn plane(&self, c: usize) -> GrayImage {
+        let len = self.width as usize * self.height as usize;
+        let values = self.data[c * len..(c + 1) * len]
+            .iter()
+            .map(|v| (v * 255.0).clamp(0.0, 255.0) as u8)
+            .collect();
+        GrayImage::from_raw(self.width, self.height, values)
+            .expect("plane has width * height values")
+    }
+
+    // Undo the normalization of `pre`; gray outputs fill all three channels
+    fn to_image(&self, pre: &Preprocess) -> RgbaImage {
+        let len = self.width as usize * self.height as usize;
+        let mut result = RgbaImage::new(self.width, self.height);
+        for (i, pixel) in result.pixels_mut().enumerate() {
+            for c in 0..3 {
+                let source = if self.channels == 1 { 0 } else { c };
+                let value = (self.data[source * len + i] * pre.std[c] + pre.mean[c]) * 255.0;
+                pixel[c] = value.clamp(0.0, 255.0) as u8;
+            }
+            pixel[3] = if self.channels == 4 {
+                (self.data[3 * len + i] * 255.0).clamp(0.0, 255.0) as u8
+            } else {
+                255
+            };
+        }
+        result
+    }
+}
+
+fn unsupported(shape: &[usize]) -> PipelineError {
+    PipelineError::InvalidModel(format!(
+        "output of shape {:?} is not an image with 1, 3 or 4 channels",
+        shape
+    ))
+}
+
+// `size` scaled by the ratio the model applied to its input
+fn scale_size(size: u32, output: u32, input: u32) -> u32 {
+    ((size as u64 * output as u64 + input as u64 / 2) / input.max(1) as u64).max(1) as u32
+}
+
+// Give `result` the alpha of `image`, resized when the model changed the size
+fn copy_alpha(image: &RgbaImage, result: &mut RgbaImage) {
+    let alpha = if image.dimensions() == result.dimensions() {
+        Cow::Borrowed(image)
+    } else {
+        Cow::Owned(imageops::resize(
+            image,
+            result.width(),
+            result.height(),
+            FilterType::Triangle,
+        ))
+    };
+    for (pixel, source) in result.pixels_mut().zip(alpha.pixels()) {
+        pixel[3] = source[3];
+    }
+}
+
+#[cfg(all(test, feature = "onnx"))]
+mod tests {
+    use super::*;
+    use image::Rgba;
+    use prost::Message;
+    use tract_onnx::pb;
+
+    // A single-node graph computing `op(x)` or `op(x, constant)`
+    fn onnx_model(op_type: &str, constant: Option<f32>) -> Vec<u8> {
+        let tensor_type = pb::TypeProto {
+            value: Some(pb::type_proto::Value::TensorType(pb::type_proto::Tensor {
+                elem_type: 1,
+                shape: None,
+            })),
+            ..Default::default()
+        };
+        let mut inputs = vec!["x".to_string()];
+        let mut initializer = Vec::new();
+        if let Some(value) = constant {
+            inputs.push("c".to_string());
+            initializer.push(pb::TensorProto {
+                name: "c".to_string(),
+                data_type: 1,
+                float_data: vec![value],
+                ..Default::default()
+            });
+        }
+        pb::ModelProto {
+            ir_version: 7,
+            opset_import: vec![pb::OperatorSetIdProto {
+                domain: String::new(),
+                version: 13,
+            }],
+            graph: Some(pb::GraphProto {
+                node: vec![pb::NodeProto {
+                    input: inputs,
+                    output: vec!["y".to_string()],
+                    op_type: op_type.to_string(),
+                    ..Default::default()
+                }],
+                initializer,
+                input: vec![pb::ValueInfoProto {
+                    name: "x".to_string(),
+                    r#type: Some(tensor_type),
+                    ..Default::default()
+                }],
+                output: vec![pb::ValueInfoProto {
+                    name: "y".to_string(),
+                    ..Default::default()
+                }],
+                ..Default::default()
+            }),
+            ..Default::default()
+        }
+        .encode_to_vec()
+    }
+
+    fn create_test_image() -> RgbaImage {
+        RgbaImage::from_fn(8, 6, |x, y| {
+            Rgba([(x * 30) as u8, (y * 40) as u8, 200, 128])
+        })
+    }
+
+    #[test]
+    fn test_image_output_round_trips() {
+        let image = create_test_image();
+        let token = CancellationToken::new();
+        // `x * 1` must reproduce the image in both layouts and with normalization
+        let identity = Model::from_bytes(&onnx_model("Mul", Some(1.0))).unwrap();
+        for layout in [TensorLayout::Nchw, TensorLayout::Nhwc] {
+            let pre = Preprocess {
+                layout,
+                mean: [0.485, 0.456, 0.406],
+                std: [0.229, 0.224, 0.225],
+                ..Preprocess::default()
+            };
+            let result = identity
+                .apply(&image, &pre, Postprocess::Image, &token)
+                .unwrap();
+            for (a, b) in image.pixels().zip(result.pixels()) {
+                assert!(
+                    a.0.iter().zip(b.0).all(|(a, b)| a.abs_diff(b) <= 1),
+                    "{:?} {:?}",
+                    a,
+                    b
+                );
+            }
+        }
+
+        // A fixed input size is undone afterwards
+        let pre = Preprocess {
+            size: Some((4, 4)),
+            ..Preprocess::default()
+        };
+        let result = identity
+            .apply(&image, &pre, Postprocess::Image, &token)
+            .unwrap();
+        assert_eq!(result.dimensions(), (8, 6));
+    }
+
+    #[test]
+    fn test_alpha_output_and_errors() {
+        let image = create_test_image();
+        let token = CancellationToken::new();
+        // Negative values clamp to a fully transparent matte
+        let invert = Model::from_bytes(&onnx_model("Neg", None)).unwrap();
+        let result = invert
+            .apply(&image, &Preprocess::default(), Postprocess::Alpha, &token)
+            .unwrap();
+        assert!(result.pixels().all(|p| p[3] == 0));
+        assert_eq!(result[(3, 2)].0[..3], image[(3, 2)].0[..3]);
+
+        assert!(matches!(
+            Model::from_bytes(b"not a model"),
+            Err(PipelineError::InvalidModel(_))
+        ));
+        token.cancel();
+        assert!(matches!(
+            invert.apply(&image, &Preprocess::default(), Postprocess::Image, &token),
+            Err(PipelineError::Cancelled)
+        ));
+    }
+}
@@ -0,0 +1,291 @@
+//! Multi-page documents: decoding, per-page processing and re-encoding
+//!
+//! Scanners and fax software write every page of a document as its own image
+//! directory (IFD) in one TIFF file. `MultiPage` decodes each of them to an
+//! `RgbaImage`, so pages go through the same operations as single images and
+//! are written back as one file. Unlike animation frames, pages may differ
+//! in size.
+
+use crate::{FilterOperation, ImagePipeline, LoadOptions, PipelineError, Result};
+use image::error::{DecodingError, EncodingError, ImageFormatHint};
+use image::{ImageError, ImageFormat, RgbaImage};
+use std::collections::HashSet;
+use std::io::Cursor;
+use tiff::encoder::{colortype, Compression, Predictor, TiffEncoder};
+
+/// The pages of a multi-page document, in order
+#[derive(Debug, Clone, PartialEq)]
+pub struct MultiPage {
+    pub pages: Vec<RgbaImage>,
+}
+
+impl MultiPage {
+    /// Decode every page of a TIFF
+    ///
+    /// Pages are converted to 8 bits per channel, so 16-bit scans lose their
+    /// extra precision. Single-page TIFFs decode to one page.
+    pub fn decode_tiff(bytes: &[u8]) -> Result<Self> {
+        Self::decode_tiff_with_options(bytes, &LoadOptions::default())
+    }
+
+    /// Decode every page of a TIFF, checking each page against `options`
+    ///
+    /// The limits apply to every page on its own, as in
+    /// `ImagePipeline::load_from_bytes_with_options`.
+    pub fn decode_tiff_with_options(bytes: &[u8], options: &LoadOptions) -> Result<Self> {
+        let header = TiffHeader::parse(bytes)?;
+        let offsets = header.page_offsets(bytes)?;
+
+        // Each page is decoded by pointing the header's first-IFD offset at it
+        let mut patched = bytes.to_vec();
+        let pages = offsets
+            .into_iter()
+            .map(|offset| {
+                header.set_first_ifd(&mut patched, offset);
+                ImagePipeline::load_from_bytes_with_options(&patched, options)
+            })
+            .collect::<Result<Vec<_>>>()?;
+        Ok(Self { pages })
+    }
+
+    /// Encode all pages into one LZW-compressed TIFF
+    ///
+    /// Each page is stored as grayscale, RGB or RGBA, whichever keeps all of
+    /// its pixels, so scanned text pages stay small.
+    pub fn encode_tiff(&self) -> Result<Vec<u8>> {
+        if self.pages.is_empty() {
+            return Err(PipelineError::InvalidParameter(
+                "document has no pages".to_string(),
+            ));
+        }
+
+        let mut buffer = Cursor::new(Vec::new());
+        let mut encoder = TiffEncoder::new(&mut buffer)
+            .map_err(encoding_error)?
+            .with_compression(Compression::Lzw)
+            .with_predictor(Predictor::Horizontal);
+        for page in &self.pages {
+            let (width, height) = page.dimensions();
+            let opaque = page.pixels().all(|p| p[3] == 255);
+            let written = if opaque && page.pixels().all(|p| p[0] == p[1] && p[1] == p[2]) {
+                let gray: Vec<u8> = page.pixels().map(|p| p[0]).collect();
+                encoder.write_image::<colortype::Gray8>(width, height, &gray)
+            } else if opaque {
+                let rgb: Vec<u8> = page.pixels().flat_map(|p| [p[0], p[1], p[2]]).collect();
+                encoder.write_image::<colortype::RGB8>(width, height, &rgb)
+            } else {
+                encoder.write_image::<colortype::RGBA8>(width, height, page.as_raw())
+            };
+            written.map_err(encoding_error)?;
+        }
+        Ok(buffer.into_inner())
+    }
+
+    /// Run `operations` on every page
+    pub fn process(
+        &self,
+        pipeline: &ImagePipeline,
+        operations: &[FilterOperation],
+    ) -> Result<MultiPage> {
+        let pages = self
+            .pages
+            .iter()
+            .map(|page| pipeline.process(page, operations))
+            .collect::<Result<Vec<_>>>()?;
+        Ok(MultiPage { pages })
+    }
+}
+
+/// Byte order and offset size from the first bytes of a TIFF or BigTIFF
+#[derive(Debug, Clone, Copy)]
+struct TiffHeader {
+    little_endian: bool,
+    big: bool,
+}
+
+impl TiffHeader {
+    fn parse(bytes: &[u8]) -> Result<Self> {
+        let little_endian = match bytes.get(0..2) {
+            Some(b"II") => true,
+            Some(b"MM") => false,
+            _ => return Err(decoding_error("not a TIFF file")),
+        };
+        let header = Self {
+            little_endian,
+            big: false,
+        };
+        match header.read(bytes, 2, 2) {
+            Some(42) => Ok(header),
+            // BigTIFF also fixes the offset size at 8
+            Some(43) if header.read(bytes, 4, 2) == Some(8) => Ok(Self {
+                big: true,
+                ..header
+            }),
+            _ => Err(decoding_error("not a TIFF file")),
+        }
+    }
+
+    // Unsigned integer of `len` bytes at `pos`, or `None` past the end
+    fn read(&self, bytes: &[u8], pos: u64, len: usize) -> Option<u64> {
+        let start = usize::try_from(pos).ok()?;
+        let field = bytes.get(start..start.checked_add(len)?)?;
+        let fold = |value: u64, &byte: &u8| value << 8 | byte as u64;
+        Some(if self.little_endian {
+            field.iter().rev().fold(0, fold)
+        } else {
+            field.iter().fold(0, fold)
+        })
+    }
+
+    fn offset_len(&self) -> usize {
+        if self.big {
+            8
+        } else {
+            4
+        }
+    }
+
+    fn first_ifd_pos(&self) -> u64 {
+        if self.big {
+            8
+        } else {
+            4
+        }
+    }
+
+    // Offsets of the image directories, following the chain from the header
+    fn page_offsets(&self, bytes: &[u8]) -> Result<Vec<u64>> {
+        let truncated = || decoding_error("truncated image directory");
+        let (count_len, entry_len) = if self.big { (8, 20) } else { (2, 12) };
+        let mut offsets = Vec::new();
+        let mut seen = HashSet::new();
+        let mut offset = self
+            .read(bytes, self.first_ifd_pos(), self.offset_len())
+            .ok_or_else(truncated)?;
+        while offset != 0 {
+            if !seen.insert(offset) {
+                return Err(decoding_error("image directories form a loop"));
+            }
+            offsets.push(offset);
+            let count = self.read(bytes, offset, count_len).ok_or_else(truncated)?;
+            let next_pos = count
+                .checked_mul(entry_len)
+                .and_then(|len| len.checked_add(offset + count_len as u64))
+                .ok_or_else(truncated)?;
+            offset = self
+                .read(bytes, next_pos, self.offset_len())
+                .ok_or_else(truncated)?;
+        }
+        if offsets.is_empty() {
+            return Err(decoding_error("TIFF contains no pages"));
+        }
+        Ok(offsets)
+    }
+
+    fn set_first_ifd(&self, bytes: &mut [u8], offset: u64) {
+        let pos = self.first_ifd_pos() as usize;
+        let len = self.offset_len();
+        let field = &mut bytes[pos..pos + len];
+        let value = offset.to_le_bytes();
+        for (i, byte) in field.iter_mut().enumerate() {
+            *byte = if self.little_endian {
+                value[i]
+            } else {
+                value[len - 1 - i]
+            };
+        }
+    }
+}
+
+fn decoding_error(message: &'static str) -> PipelineError {
+    ImageError::Decoding(DecodingError::new(
+        ImageFormatHint::Exact(ImageFormat::Tiff),
+        message,
+    ))
+    .into()
+}
+
+fn encoding_error(error: tiff::TiffError) -> PipelineError {
+    ImageError::Encoding(EncodingError::new(
+        ImageFormatHint::Exact(ImageFormat::Tiff),
+        error,
+    ))
+    .into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgba;
+
+    fn sample() -> MultiPage {
+        MultiPage {
+            pages: vec![
+                RgbaImage::from_fn(20, 10, |x, _| {
+                    Rgba([x as u8 * 12, x as u8 * 12, x as u8 * 12, 255])
+                }),
+                RgbaImage::from_pixel(8, 12, Rgba([200, 40, 10, 255])),
+                RgbaImage::from_pixel(5, 5, Rgba([0, 0, 255, 100])),
+            ],
+        }
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let document = sample();
+        let encoded = document.encode_tiff().unwrap();
+        assert_eq!(MultiPage::decode_tiff(&encoded).unwrap(), document);
+        // Readers without multi-page support see the first page
+        assert_eq!(
+            ImagePipeline::load_from_bytes(&encoded).unwrap(),
+            document.pages[0]
+        );
+    }
+
+    #[test]
+    fn test_single_page_and_16_bit() {
+        let page =
+            image::ImageBuffer::<Rgba<u16>, _>::from_pixel(4, 4, Rgba([0xFFFF, 0x8080, 0, 0xFFFF]));
+        let mut bytes = Vec::new();
+        image::DynamicImage::ImageRgba16(page)
+            .write_to(&mut Cursor::new(&mut bytes), ImageFormat::Tiff)
+            .unwrap();
+        let decoded = MultiPage::decode_tiff(&bytes).unwrap();
+        assert_eq!(decoded.pages.len(), 1);
+        assert_eq!(*decoded.pages[0].get_pixel(1, 1), Rgba([255, 128, 0, 255]));
+    }
+
+    #[test]
+    fn test_process_and_limits() {
+        let pipeline = ImagePipeline::new();
+        let processed = sample()
+            .process(&pipeline, &[FilterOperation::Invert])
+            .unwrap();
+        assert_eq!(
+            *processed.pages[1].get_pixel(0, 0),
+            Rgba([55, 215, 245, 255])
+        );
+
+        let encoded = sample().encode_tiff().unwrap();
+        let options = LoadOptions {
+            max_width: Some(10),
+            ..LoadOptions::default()
+        };
+        assert!(matches!(
+            MultiPage::decode_tiff_with_options(&encoded, &options),
+            Err(PipelineError::ResourceLimit(_))
+        ));
+    }
+
+    #[test]
+    fn test_invalid_input() {
+        assert!(MultiPage::decode_tiff(b"GIF89a").is_err());
+        // Header pointing at a directory past the end of the file
+        assert!(MultiPage::decode_tiff(b"II*\0\xff\0\0\0").is_err());
+        // A directory whose next offset points back at itself
+        let mut looped = b"II*\0\x08\0\0\0\0\0".to_vec();
+        looped.extend_from_slice(&8u32.to_le_bytes());
+        assert!(MultiPage::decode_tiff(&looped).is_err());
+        assert!(MultiPage { pages: vec![] }.encode_tiff().is_err());
+    }
+}
@@ -0,0 +1,286 @@
+//! Output format negotiation
+//!
+//! `ImagePipeline::encode_auto` and `Animation::encode_auto` pick the output
+//! format from what the client accepts, either an HTTP `Accept` header or a
+//! preference list of extensions, and from what the image needs: JPEG is never
+//! chosen for transparent images, and animations only get animated formats.
+
+use crate::animation::Animation;
+use crate::{EncodeOptions, ImagePipeline, OutputFormat, Result};
+use image::RgbaImage;
+
+/// Encoded bytes with the format that was chosen for them
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EncodedImage {
+    pub bytes: Vec<u8>,
+    /// Media type for the `Content-Type` header
+    pub mime_type: &'static str,
+    /// Canonical file extension
+    pub extension: &'static str,
+}
+
+/// What the client accepts, in the order to try
+#[derive(Debug)]
+struct Accepted {
+    /// Lowercase media types with a non-zero q-value
+    types: Vec<String>,
+    /// A preference list gives its own order; a header leaves it to the server
+    ordered: bool,
+    /// `*/*` or `image/*`, or no header at all
+    wildcard: bool,
+}
+
+impl Accepted {
+    fn parse(accept: &str) -> Self {
+        let ordered = !accept.contains('/');
+        let mut types = Vec::new();
+        let mut wildcard = accept.trim().is_empty();
+
+        for item in accept.split(',') {
+            let mut parts = item.split(';').map(str::trim);
+            let name = parts.next().unwrap_or_default().to_ascii_lowercase();
+            let q = parts
+                .filter_map(|param| param.strip_prefix("q="))
+                .find_map(|q| q.trim().parse::<f32>().ok())
+                .unwrap_or(1.0);
+            if name.is_empty() || q <= 0.0 {
+                continue;
+            }
+
+            let mime = if ordered {
+                match mime_for_extension(&name) {
+                    Some(mime) => mime.to_string(),
+                    None => continue,
+                }
+            } else {
+                name
+            };
+            if mime == "*/*" || mime == "image/*" {
+                wildcard = true;
+            } else {
+                types.push(mime);
+            }
+        }
+
+        Self {
+            types,
+            ordered,
+            wildcard,
+        }
+    }
+
+    // Explicitly listed; formats every client decodes also match a wildcard
+    fn accepts(&self, mime: &str) -> bool {
+        self.types.iter().any(|t| t == mime)
+            || (self.wildcard && matches!(mime, "image/jpeg" | "image/png" | "image/gif"))
+    }
+
+    // Candidates in the client's order for a preference list, otherwise in
+    // the server's order
+    fn order<'a>(&self, server: &[&'a str]) -> Vec<&'a str> {
+        let mut candidates: Vec<&str> = server
+            .iter()
+            .copied()
+            .filter(|mime| self.accepts(mime))
+            .collect();
+        if self.ordered {
+            candidates.sort_by_key(|mime| {
+                self.types
+                    .iter()
+                    .position(|t| t == mime)
+                    .unwrap_or(usize::MAX)
+            });
+        }
+        candidates
+    }
+}
+
+fn mime_for_extension(ext: &str) -> Option<&'static str> {
+    match ext {
+        "avif" => Some("image/avif"),
+        "webp" => Some("image/webp"),
+        "jpg" | "jpeg" => Some("image/jpeg"),
+        "png" => Some("image/png"),
+        "gif" => Some("image/gif"),
+        "apng" => Some("image/apng"),
+        "*" => Some("*/*"),
+        _ => None,
+    }
+}
+
+fn still_format(mime: &str) -> Option<OutputFormat> {
+    match mime {
+        #[cfg(feature = "avif")]
+        "image/avif" => Some(OutputFormat::Avif),
+        "image/webp" => Some(OutputFormat::WebP),
+        "image/jpeg" => Some(OutputFormat::Jpeg),
+        "image/png" => Some(OutputFormat::Png),
+        _ => None,
+    }
+}
+
+impl ImagePipeline {
+    /// Encode in the best format the client accepts
+    ///
+    /// `accept` is an HTTP `Accept` header (`image/avif,image/webp,*/*;q=0.8`)
+    /// or a preference list of extensions (`avif,webp,jpeg`). For a header the
+    /// server order is AVIF (with the `avif` feature), then JPEG for opaque
+    /// images or lossless WebP and PNG for transparent ones; WebP is lossless
+    /// here, so it does not replace JPEG for photos. A preference list is
+    /// tried in its own order. JPEG is skipped for transparent images, and
+    /// when nothing listed fits the result is PNG or JPEG.
+    pub fn encode_auto(
+        image: &RgbaImage,
+        accept: &str,
+        options: &EncodeOptions,
+    ) -> Result<EncodedImage> {
+        let accepted = Accepted::parse(accept);
+        let has_alpha = image.pixels().any(|p| p[3] < 255);
+
+        let server: &[&str] = if has_alpha {
+            &["image/avif", "image/webp", "image/png"]
+        } else {
+            &["image/avif", "image/jpeg", "image/webp", "image/png"]
+        };
+        let format = accepted
+            .order(server)
+            .into_iter()
+            .find_map(still_format)
+            .unwrap_or(if has_alpha {
+                OutputFormat::Png
+            } else {
+                OutputFormat::Jpeg
+            });
+
+        Ok(EncodedImage {
+            bytes: Self::encode_with_options(image, format, options)?,
+            mime_type: format.mime_type(),
+            extension: format.extension(),
+        })
+    }
+}
+
+impl Animation {
+    /// Encode in the best animated format the client accepts
+    ///
+    /// Tries animated WebP, then APNG when frames are partly transparent
+    /// (GIF only has on/off transparency), then GIF, which every client
+    /// decodes. `accept` is parsed as in `ImagePipeline::encode_auto`. A
+    /// single frame is encoded as a still image.
+    pub fn encode_auto(&self, accept: &str, options: &EncodeOptions) -> Result<EncodedImage> {
+        if let [frame] = self.frames.as_slice() {
+            return ImagePipeline::encode_auto(&frame.image, accept, options);
+        }
+
+        let accepted = Accepted::parse(accept);
+        let partial_alpha = self
+            .frames
+            .iter()
+            .any(|frame| frame.image.pixels().any(|p| p[3] > 0 && p[3] < 255));
+        let server: &[&str] = if partial_alpha {
+            &["image/webp", "image/apng", "image/gif"]
+        } else {
+            &["image/webp", "image/gif"]
+        };
+
+        let (bytes, mime_type, extension) = match accepted.order(server).first() {
+            Some(&"image/webp") => (self.encode_webp()?, "image/webp", "webp"),
+            Some(&"image/apng") => (self.encode_apng()?, "image/apng", "png"),
+            _ => (self.encode_gif()?, "image/gif", "gif"),
+        };
+        Ok(EncodedImage {
+            bytes,
+            mime_type,
+            extension,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::animation::{AnimationFrame, Repeat};
+    use image::Rgba;
+
+    const CHROME: &str = "image/avif,image/webp,image/apng,image/svg+xml,image/*,*/*;q=0.8";
+
+    fn opaque() -> RgbaImage {
+        RgbaImage::from_pixel(8, 8, Rgba([200, 100, 50, 255]))
+    }
+
+    fn transparent() -> RgbaImage {
+        RgbaImage::from_fn(8, 8, |x, _| Rgba([200, 100, 50, (x * 30) as u8]))
+    }
+
+    fn mime(image: &RgbaImage, accept: &str) -> &'static str {
+        ImagePipeline::encode_auto(image, accept, &EncodeOptions::default())
+            .unwrap()
+            .mime_type
+    }
+
+    #[test]
+    fn test_accept_header() {
+        let best = if cfg!(feature = "avif") {
+            "image/avif"
+        } else {
+            "image/jpeg"
+        };
+        assert_eq!(mime(&opaque(), CHROME), best);
+        assert_eq!(mime(&opaque(), "*/*"), "image/jpeg");
+        assert_eq!(mime(&opaque(), ""), "image/jpeg");
+
+        // Lossless WebP beats PNG but is not used for opaque images
+        assert_eq!(mime(&opaque(), "image/webp,image/png"), "image/webp");
+        assert_eq!(mime(&transparent(), "image/webp,*/*"), "image/webp");
+        assert_eq!(mime(&transparent(), "*/*"), "image/png");
+
+        // q=0 refuses a type
+        assert_eq!(mime(&transparent(), "image/webp;q=0,*/*"), "image/png");
+    }
+
+    #[test]
+    fn test_preference_list() {
+        assert_eq!(mime(&opaque(), "png, webp"), "image/png");
+        assert_eq!(mime(&opaque(), "WEBP,jpeg"), "image/webp");
+        // JPEG cannot keep transparency
+        assert_eq!(mime(&transparent(), "jpg,webp"), "image/webp");
+        assert_eq!(mime(&transparent(), "jpg"), "image/png");
+        // Unknown entries are ignored
+        assert_eq!(mime(&opaque(), "heic,bmp"), "image/jpeg");
+
+        let encoded =
+            ImagePipeline::encode_auto(&transparent(), "webp", &EncodeOptions::default()).unwrap();
+        assert_eq!(encoded.extension, "webp");
+        let decoded = ImagePipeline::load_from_bytes(&encoded.bytes).unwrap();
+        assert_eq!(decoded, transparent());
+    }
+
+    #[test]
+    fn test_animation() {
+        let animation = |image: RgbaImage, count: usize| Animation {
+            frames: vec![
+                AnimationFrame {
+                    image,
+                    delay_ms: 100,
+                };
+                count
+            ],
+            repeat: Repeat::Infinite,
+        };
+        let mime = |animation: &Animation, accept: &str| {
+            animation
+                .encode_auto(accept, &EncodeOptions::default())
+                .unwrap()
+                .mime_type
+        };
+
+        assert_eq!(mime(&animation(opaque(), 2), CHROME), "image/webp");
+        assert_eq!(mime(&animation(opaque(), 2), "image/apng,*/*"), "image/gif");
+        assert_eq!(
+            mime(&animation(transparent(), 2), "image/apng,*/*"),
+            "image/apng"
+        );
+        assert_eq!(mime(&animation(transparent(), 2), "*/*"), "image/gif");
+        assert_eq!(mime(&animation(opaque(), 1), "*/*"), "image/jpeg");
+    }
+}
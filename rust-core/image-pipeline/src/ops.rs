@@ -0,0 +1,2141 @@
+//! Built-in operations as `Filter`s
+//!
+//! Every `FilterOperation` variant has a filter here with the same
+//! parameters, which owns the operation's spec schema, validation and
+//! execution. `FilterOperation::to_filter` builds it; the pipeline runs
+//! built-in operations through these filters and `registry` lists their
+//! schemas, so adding an operation means adding its filter here plus the
+//! variant and spec form that describe it as data.
+
+use crate::registry::{number, op, resizing, size_params, OperationInfo, ParamInfo, ParamType};
+use crate::{
+    compose, filters, portrait, smart_crop, Anchor, CancellationToken, ColorMatrix, EdgeOptions,
+    Filter, FilterOperation, FitMode, GrayscaleMode, Interpolation, Lut3D, Mark, Model, PadFill,
+    PipelineError, PixelBuffer, Postprocess, Preprocess, Region, ResampleFilter, Result,
+    UpscaleMethod, MAX_BLUR_SIGMA, MAX_UPSCALE_FACTOR, SHARPEN_AMOUNT, SHARPEN_RADIUS,
+};
+use image::{Rgba, RgbaImage};
+use serde_json::{json, Value};
+use std::sync::Arc;
+
+fn invalid(reason: impl Into<String>) -> PipelineError {
+    PipelineError::InvalidParameter(reason.into())
+}
+
+// `apply` for filters that build a new image in `apply_to`
+fn replace(filter: &dyn Filter, buffer: &mut PixelBuffer) -> Result<()> {
+    let result = filter.apply_to(buffer.image(), buffer.token())?;
+    buffer.replace(result);
+    Ok(())
+}
+
+fn check_sigma(name: &str, sigma: f32) -> Result<()> {
+    if !sigma.is_finite() || sigma <= 0.0 || sigma > MAX_BLUR_SIGMA {
+        return Err(invalid(format!(
+            "{} must be in (0, {}], got {}",
+            name, MAX_BLUR_SIGMA, sigma
+        )));
+    }
+    Ok(())
+}
+
+fn check_unit(name: &str, value: f32) -> Result<()> {
+    if !(0.0..=1.0).contains(&value) {
+        return Err(invalid(format!(
+            "{} must be between 0.0 and 1.0, got {}",
+            name, value
+        )));
+    }
+    Ok(())
+}
+
+fn check_size(width: u32, height: u32) -> Result<()> {
+    if width == 0 || height == 0 {
+        return Err(invalid(format!(
+            "width and height must be at least 1, got {}x{}",
+            width, height
+        )));
+    }
+    Ok(())
+}
+
+impl FilterOperation {
+    /// The built-in filter that runs this operation
+    pub fn to_filter(&self) -> Box<dyn Filter> {
+        match self.clone() {
+            FilterOperation::Grayscale(mode) => Box::new(GrayscaleFilter { mode }),
+            FilterOperation::Brightness(value) => Box::new(BrightnessFilter { value }),
+            FilterOperation::Contrast(value) => Box::new(ContrastFilter { value }),
+            FilterOperation::Blur(sigma) => Box::new(BlurFilter { sigma }),
+            FilterOperation::ApproximateBlur(sigma) => Box::new(ApproximateBlurFilter { sigma }),
+            FilterOperation::Sharpen {
+                amount,
+                radius,
+                threshold,
+            } => Box::new(SharpenFilter {
+                amount,
+                radius,
+                threshold,
+            }),
+            FilterOperation::EdgeDetect(options) => Box::new(EdgeDetectFilter { options }),
+            FilterOperation::Resize {
+                width,
+                height,
+                filter,
+            } => Box::new(ResizeFilter {
+                width,
+                height,
+                filter,
+            }),
+            FilterOperation::Thumbnail { width, height, fit } => {
+                Box::new(ThumbnailFilter { width, height, fit })
+            }
+            FilterOperation::SmartCrop { width, height } => {
+                Box::new(SmartCropFilter { width, height })
+            }
+            FilterOperation::SeamCarve { width, height } => {
+                Box::new(SeamCarveFilter { width, height })
+            }
+            FilterOperation::Pad {
+                top,
+                right,
+                bottom,
+                left,
+                fill,
+            } => Box::new(PadFilter {
+                top,
+                right,
+                bottom,
+                left,
+                fill,
+            }),
+            FilterOperation::Invert => Box::new(InvertFilter),
+            FilterOperation::Sepia => Box::new(SepiaFilter),
+            FilterOperation::ColorMatrix(matrix) => Box::new(ColorMatrixFilter { matrix }),
+            FilterOperation::Gamma(value) => Box::new(GammaFilter { value }),
+            FilterOperation::Lut3D(lut) => Box::new(Lut3DFilter { lut }),
+            FilterOperation::Noise {
+                amount,
+                monochrome,
+                seed,
+            } => Box::new(NoiseFilter {
+                amount,
+                monochrome,
+                seed,
+            }),
+            FilterOperation::Pixelate(block_size) => Box::new(PixelateFilter { block_size }),
+            FilterOperation::Deskew => Box::new(DeskewFilter),
+            FilterOperation::Rotate {
+                degrees,
+                background,
+                expand,
+            } => Box::new(RotateFilter {
+                degrees,
+                background,
+                expand,
+            }),
+            FilterOperation::Affine {
+                matrix,
+                interpolation,
+                background,
+            } => Box::new(AffineFilter {
+                matrix,
+                interpolation,
+                background,
+            }),
+            FilterOperation::LensCorrect { k1, k2, k3 } => {
+                Box::new(LensCorrectFilter { k1, k2, k3 })
+            }
+            FilterOperation::ChromaticAberration {
+                red_shift,
+                blue_shift,
+            } => Box::new(ChromaticAberrationFilter {
+                red_shift,
+                blue_shift,
+            }),
+            FilterOperation::ChromaKey {
+                key_color,
+                tolerance,
+                softness,
+            } => Box::new(ChromaKeyFilter {
+                key_color,
+                tolerance,
+                softness,
+            }),
+            FilterOperation::GradientMap(stops) => Box::new(GradientMapFilter { stops }),
+            FilterOperation::SelectiveColor {
+                hue_range,
+                hue_shift,
+                saturation,
+                lightness,
+            } => Box::new(SelectiveColorFilter {
+                hue_range,
+                hue_shift,
+                saturation,
+                lightness,
+            }),
+            FilterOperation::ShadowsHighlights {
+                shadows,
+                highlights,
+                radius,
+            } => Box::new(ShadowsHighlightsFilter {
+                shadows,
+                highlights,
+                radius,
+            }),
+            FilterOperation::PortraitBlur {
+                mask,
+                sigma,
+                feather,
+            } => Box::new(PortraitBlurFilter {
+                mask,
+                sigma,
+                feather,
+            }),
+            FilterOperation::Model { model, pre, post } => {
+                Box::new(ModelFilter { model, pre, post })
+            }
+            FilterOperation::Upscale { factor, method } => {
+                Box::new(UpscaleFilter { factor, method })
+            }
+            FilterOperation::Watermark {
+                mark,
+                anchor,
+                margin,
+                opacity,
+                tile,
+                scale,
+            } => Box::new(WatermarkFilter {
+                mark,
+                anchor,
+                margin,
+                opacity,
+                tile,
+                scale,
+            }),
+            FilterOperation::DropShadow {
+                offset,
+                sigma,
+                color,
+                opacity,
+            } => Box::new(DropShadowFilter {
+                offset,
+                sigma,
+                color,
+                opacity,
+            }),
+            FilterOperation::Masked { region, op } => Box::new(MaskedFilter { region, op }),
+            FilterOperation::Custom { name, params } => Box::new(CustomFilter { name, params }),
+        }
+    }
+}
+
+/// Convert to grayscale with the given weighting
+#[derive(Debug, Clone)]
+pub struct GrayscaleFilter {
+    pub mode: GrayscaleMode,
+}
+
+impl GrayscaleFilter {
+    pub fn info() -> OperationInfo {
+        op(
+            "grayscale",
+            "Convert to grayscale",
+            vec![ParamInfo::new("mode", ParamType::Enum, "Channel weighting")
+                .options(&["bt601", "bt709", "average", "red", "green", "blue"])
+                .default(json!("bt709"))],
+        )
+    }
+}
+
+impl Filter for GrayscaleFilter {
+    fn name(&self) -> &str {
+        "grayscale"
+    }
+
+    fn schema(&self) -> Option<OperationInfo> {
+        Some(Self::info())
+    }
+
+    fn apply(&self, buffer: &mut PixelBuffer) -> Result<()> {
+        replace(self, buffer)
+    }
+
+    fn apply_to(&self, image: &RgbaImage, _token: &CancellationToken) -> Result<RgbaImage> {
+        Ok(filters::grayscale_with(image, self.mode))
+    }
+}
+
+/// Shift brightness by `value` (-1.0 to 1.0)
+#[derive(Debug, Clone)]
+pub struct BrightnessFilter {
+    pub value: f32,
+}
+
+impl BrightnessFilter {
+    pub fn info() -> OperationInfo {
+        op(
+            "brightness",
+            "Shift brightness",
+            vec![number("value", "Amount, -1.0 (black) to 1.0 (white)").range(-1.0, 1.0)],
+        )
+    }
+}
+
+impl Filter for BrightnessFilter {
+    fn name(&self) -> &str {
+        "brightness"
+    }
+
+    fn schema(&self) -> Option<OperationInfo> {
+        Some(Self::info())
+    }
+
+    fn validate(&self) -> Result<()> {
+        if !(-1.0..=1.0).contains(&self.value) {
+            return Err(invalid(format!(
+                "value must be between -1.0 and 1.0, got {}",
+                self.value
+            )));
+        }
+        Ok(())
+    }
+
+    fn apply(&self, buffer: &mut PixelBuffer) -> Result<()> {
+        replace(self, buffer)
+    }
+
+    fn apply_to(&self, image: &RgbaImage, _token: &CancellationToken) -> Result<RgbaImage> {
+        Ok(filters::brightness(image, self.value))
+    }
+}
+
+/// Scale contrast around mid-gray by `value` (0.0 to 2.0+)
+#[derive(Debug, Clone)]
+pub struct ContrastFilter {
+    pub value: f32,
+}
+
+impl ContrastFilter {
+    pub fn info() -> OperationInfo {
+        op(
+            "contrast",
+            "Scale contrast around mid-gray",
+            vec![number("value", "Factor; 1.0 leaves the image unchanged").min(0.0)],
+        )
+    }
+}
+
+impl Filter for ContrastFilter {
+    fn name(&self) -> &str {
+        "contrast"
+    }
+
+    fn schema(&self) -> Option<OperationInfo> {
+        Some(Self::info())
+    }
+
+    fn validate(&self) -> Result<()> {
+        if !self.value.is_finite() || self.value < 0.0 {
+            return Err(invalid(format!(
+                "value must be a non-negative number, got {}",
+                self.value
+            )));
+        }
+        Ok(())
+    }
+
+    fn apply(&self, buffer: &mut PixelBuffer) -> Result<()> {
+        replace(self, buffer)
+    }
+
+    fn apply_to(&self, image: &RgbaImage, _token: &CancellationToken) -> Result<RgbaImage> {
+        Ok(filters::contrast(image, self.value))
+    }
+}
+
+/// Gaussian blur with standard deviation `sigma`
+#[derive(Debug, Clone)]
+pub struct BlurFilter {
+    pub sigma: f32,
+}
+
+impl BlurFilter {
+    pub fn info() -> OperationInfo {
+        op(
+            "blur",
+            "Gaussian blur",
+            vec![
+                number("sigma", "Standard deviation in pixels")
+                    .above(0.0)
+                    .max(MAX_BLUR_SIGMA as f64),
+                ParamInfo::new(
+                    "approximate",
+                    ParamType::Boolean,
+                    "Use three box blurs, faster for large sigmas",
+                )
+                .default(json!(false)),
+            ],
+        )
+    }
+}
+
+impl Filter for BlurFilter {
+    fn name(&self) -> &str {
+        "blur"
+    }
+
+    fn schema(&self) -> Option<OperationInfo> {
+        Some(Self::info())
+    }
+
+    fn validate(&self) -> Result<()> {
+        check_sigma("sigma", self.sigma)
+    }
+
+    fn apply(&self, buffer: &mut PixelBuffer) -> Result<()> {
+        replace(self, buffer)
+    }
+
+    fn apply_to(&self, image: &RgbaImage, token: &CancellationToken) -> Result<RgbaImage> {
+        filters::blur_cancellable(image, self.sigma, token)
+    }
+}
+
+/// Gaussian blur approximated by three box blurs
+///
+/// Shares the `blur` spec operation, selected by its `approximate` flag.
+#[derive(Debug, Clone)]
+pub struct ApproximateBlurFilter {
+    pub sigma: f32,
+}
+
+impl Filter for ApproximateBlurFilter {
+    fn name(&self) -> &str {
+        "approximate_blur"
+    }
+
+    fn schema(&self) -> Option<OperationInfo> {
+        Some(BlurFilter::info())
+    }
+
+    fn validate(&self) -> Result<()> {
+        check_sigma("sigma", self.sigma)
+    }
+
+    fn apply(&self, buffer: &mut PixelBuffer) -> Result<()> {
+        replace(self, buffer)
+    }
+
+    fn apply_to(&self, image: &RgbaImage, token: &CancellationToken) -> Result<RgbaImage> {
+        filters::blur_approximate_cancellable(image, self.sigma, token)
+    }
+}
+
+/// Unsharp mask, see `filters::sharpen`
+#[derive(Debug, Clone)]
+pub struct SharpenFilter {
+    pub amount: f32,
+    pub radius: f32,
+    pub threshold: u8,
+}
+
+impl SharpenFilter {
+    pub fn info() -> OperationInfo {
+        op(
+            "sharpen",
+            "Unsharp mask sharpening",
+            vec![
+                number("amount", "Strength of the added detail")
+                    .min(0.0)
+                    .default(json!(SHARPEN_AMOUNT)),
+                number("radius", "Sigma of the blur in pixels")
+                    .above(0.0)
+                    .max(MAX_BLUR_SIGMA as f64)
+                    .default(json!(SHARPEN_RADIUS)),
+                ParamInfo::new(
+                    "threshold",
+                    ParamType::Integer,
+                    "Skip luminance differences below this, 0-255",
+                )
+                .range(0.0, 255.0)
+                .default(json!(0)),
+            ],
+        )
+    }
+}
+
+impl Filter for SharpenFilter {
+    fn name(&self) -> &str {
+        "sharpen"
+    }
+
+    fn schema(&self) -> Option<OperationInfo> {
+        Some(Self::info())
+    }
+
+    fn validate(&self) -> Result<()> {
+        if !self.amount.is_finite() || self.amount < 0.0 {
+            return Err(invalid(format!(
+                "amount must be a non-negative number, got {}",
+                self.amount
+            )));
+        }
+        check_sigma("radius", self.radius)
+    }
+
+    fn apply(&self, buffer: &mut PixelBuffer) -> Result<()> {
+        replace(self, buffer)
+    }
+
+    fn apply_to(&self, image: &RgbaImage, token: &CancellationToken) -> Result<RgbaImage> {
+        filters::sharpen_cancellable(image, self.amount, self.radius, self.threshold, token)
+    }
+}
+
+/// Sobel edge detection
+#[derive(Debug, Clone)]
+pub struct EdgeDetectFilter {
+    pub options: EdgeOptions,
+}
+
+impl EdgeDetectFilter {
+    pub fn info() -> OperationInfo {
+        op(
+            "edge_detect",
+            "Sobel edge detection",
+            vec![
+                ParamInfo::new("border", ParamType::Enum, "Pixels outside the image")
+                    .options(&["zero", "replicate"])
+                    .default(json!("zero")),
+                ParamInfo::new("output", ParamType::Enum, "Gradient magnitude or direction")
+                    .options(&["magnitude", "direction"])
+                    .default(json!("magnitude")),
+                ParamInfo::new(
+                    "threshold",
+                    ParamType::Integer,
+                    "Keep only gradients at least this strong; null keeps all",
+                )
+                .range(0.0, 255.0)
+                .default(Value::Null),
+            ],
+        )
+    }
+}
+
+impl Filter for EdgeDetectFilter {
+    fn name(&self) -> &str {
+        "edge_detect"
+    }
+
+    fn schema(&self) -> Option<OperationInfo> {
+        Some(Self::info())
+    }
+
+    fn apply(&self, buffer: &mut PixelBuffer) -> Result<()> {
+        replace(self, buffer)
+    }
+
+    fn apply_to(&self, image: &RgbaImage, token: &CancellationToken) -> Result<RgbaImage> {
+        filters::edge_detect_with_cancellable(image, &self.options, token)
+    }
+}
+
+/// Resize to exactly `width`x`height`
+#[derive(Debug, Clone)]
+pub struct ResizeFilter {
+    pub width: u32,
+    pub height: u32,
+    pub filter: ResampleFilter,
+}
+
+impl ResizeFilter {
+    pub fn info() -> OperationInfo {
+        resizing(op("resize", "Resize to an exact size", {
+            let mut params = size_params();
+            params.push(
+                ParamInfo::new("filter", ParamType::Enum, "Resampling filter")
+                    .options(&["nearest", "bilinear", "catmull_rom", "lanczos3"])
+                    .default(json!("lanczos3")),
+            );
+            params
+        }))
+    }
+}
+
+impl Filter for ResizeFilter {
+    fn name(&self) -> &str {
+        "resize"
+    }
+
+    fn schema(&self) -> Option<OperationInfo> {
+        Some(Self::info())
+    }
+
+    fn validate(&self) -> Result<()> {
+        check_size(self.width, self.height)
+    }
+
+    fn changes_size(&self) -> bool {
+        true
+    }
+
+    fn apply(&self, buffer: &mut PixelBuffer) -> Result<()> {
+        replace(self, buffer)
+    }
+
+    fn apply_to(&self, image: &RgbaImage, _token: &CancellationToken) -> Result<RgbaImage> {
+        Ok(filters::resize(image, self.width, self.height, self.filter))
+    }
+}
+
+/// Fit into a `width`x`height` box, preserving the aspect ratio
+#[derive(Debug, Clone)]
+pub struct ThumbnailFilter {
+    pub width: u32,
+    pub height: u32,
+    pub fit: FitMode,
+}
+
+impl ThumbnailFilter {
+    pub fn info() -> OperationInfo {
+        resizing(op("thumbnail", "Fit within a box", {
+            let mut params = size_params();
+            params.push(
+                ParamInfo::new("fit", ParamType::Enum, "How the image fits the box")
+                    .options(&["contain", "cover", "pad"])
+                    .default(json!("contain")),
+            );
+            params.push(
+                ParamInfo::new(
+                    "background",
+                    ParamType::Color,
+                    "Fill for the padding of `pad`",
+                )
+                .default(json!([0, 0, 0, 0])),
+            );
+            params
+        }))
+    }
+}
+
+impl Filter for ThumbnailFilter {
+    fn name(&self) -> &str {
+        "thumbnail"
+    }
+
+    fn schema(&self) -> Option<OperationInfo> {
+        Some(Self::info())
+    }
+
+    fn validate(&self) -> Result<()> {
+        check_size(self.width, self.height)
+    }
+
+    fn changes_size(&self) -> bool {
+        true
+    }
+
+    fn apply(&self, buffer: &mut PixelBuffer) -> Result<()> {
+        replace(self, buffer)
+    }
+
+    fn apply_to(&self, image: &RgbaImage, _token: &CancellationToken) -> Result<RgbaImage> {
+        Ok(filters::thumbnail(image, self.width, self.height, self.fit))
+    }
+}
+
+/// Crop to the target aspect ratio around salient content, then resize
+#[derive(Debug, Clone)]
+pub struct SmartCropFilter {
+    pub width: u32,
+    pub height: u32,
+}
+
+impl SmartCropFilter {
+    pub fn info() -> OperationInfo {
+        resizing(op(
+            "smart_crop",
+            "Crop around the most detailed region",
+            size_params(),
+        ))
+    }
+}
+
+impl Filter for SmartCropFilter {
+    fn name(&self) -> &str {
+        "smart_crop"
+    }
+
+    fn schema(&self) -> Option<OperationInfo> {
+        Some(Self::info())
+    }
+
+    fn validate(&self) -> Result<()> {
+        check_size(self.width, self.height)
+    }
+
+    fn changes_size(&self) -> bool {
+        true
+    }
+
+    fn apply(&self, buffer: &mut PixelBuffer) -> Result<()> {
+        replace(self, buffer)
+    }
+
+    fn apply_to(&self, image: &RgbaImage, _token: &CancellationToken) -> Result<RgbaImage> {
+        Ok(smart_crop(image, self.width, self.height))
+    }
+}
+
+/// Content-aware resize by removing or inserting low-energy seams
+#[derive(Debug, Clone)]
+pub struct SeamCarveFilter {
+    pub width: u32,
+    pub height: u32,
+}
+
+impl SeamCarveFilter {
+    pub fn info() -> OperationInfo {
+        resizing(op("seam_carve", "Content-aware resize", size_params()))
+    }
+}
+
+impl Filter for SeamCarveFilter {
+    fn name(&self) -> &str {
+        "seam_carve"
+    }
+
+    fn schema(&self) -> Option<OperationInfo> {
+        Some(Self::info())
+    }
+
+    fn validate(&self) -> Result<()> {
+        check_size(self.width, self.height)
+    }
+
+    fn changes_size(&self) -> bool {
+        true
+    }
+
+    fn apply(&self, buffer: &mut PixelBuffer) -> Result<()> {
+        replace(self, buffer)
+    }
+
+    fn apply_to(&self, image: &RgbaImage, token: &CancellationToken) -> Result<RgbaImage> {
+        filters::seam_carve_cancellable(image, self.width, self.height, token)
+    }
+}
+
+/// Add a border of the given widths, see `filters::extend_canvas`
+#[derive(Debug, Clone)]
+pub struct PadFilter {
+    pub top: u32,
+    pub right: u32,
+    pub bottom: u32,
+    pub left: u32,
+    pub fill: PadFill,
+}
+
+impl PadFilter {
+    pub fn info() -> OperationInfo {
+        resizing(op("pad", "Add a border around the image", {
+            let border = |name, description| {
+                ParamInfo::new(name, ParamType::Integer, description)
+                    .min(0.0)
+                    .default(json!(0))
+            };
+            vec![
+                border("top", "Pixels added above"),
+                border("right", "Pixels added on the right"),
+                border("bottom", "Pixels added below"),
+                border("left", "Pixels added on the left"),
+                ParamInfo::new("fill", ParamType::Enum, "What fills the border")
+                    .options(&["color", "transparent", "replicate", "mirror"])
+                    .default(json!("transparent")),
+                ParamInfo::new("color", ParamType::Color, "Fill color for `color`")
+                    .default(json!([0, 0, 0, 0])),
+            ]
+        }))
+    }
+}
+
+impl Filter for PadFilter {
+    fn name(&self) -> &str {
+        "pad"
+    }
+
+    fn schema(&self) -> Option<OperationInfo> {
+        Some(Self::info())
+    }
+
+    fn changes_size(&self) -> bool {
+        true
+    }
+
+    fn apply(&self, buffer: &mut PixelBuffer) -> Result<()> {
+        replace(self, buffer)
+    }
+
+    fn apply_to(&self, image: &RgbaImage, _token: &CancellationToken) -> Result<RgbaImage> {
+        filters::extend_canvas(
+            image,
+            self.top,
+            self.right,
+            self.bottom,
+            self.left,
+            self.fill,
+        )
+    }
+}
+
+/// Invert colors
+#[derive(Debug, Clone)]
+pub struct InvertFilter;
+
+impl InvertFilter {
+    pub fn info() -> OperationInfo {
+        op("invert", "Invert colors", vec![])
+    }
+}
+
+impl Filter for InvertFilter {
+    fn name(&self) -> &str {
+        "invert"
+    }
+
+    fn schema(&self) -> Option<OperationInfo> {
+        Some(Self::info())
+    }
+
+    fn apply(&self, buffer: &mut PixelBuffer) -> Result<()> {
+        replace(self, buffer)
+    }
+
+    fn apply_to(&self, image: &RgbaImage, _token: &CancellationToken) -> Result<RgbaImage> {
+        Ok(filters::invert(image))
+    }
+}
+
+/// Sepia tone
+#[derive(Debug, Clone)]
+pub struct SepiaFilter;
+
+impl SepiaFilter {
+    pub fn info() -> OperationInfo {
+        op("sepia", "Sepia tone", vec![])
+    }
+}
+
+impl Filter for SepiaFilter {
+    fn name(&self) -> &str {
+        "sepia"
+    }
+
+    fn schema(&self) -> Option<OperationInfo> {
+        Some(Self::info())
+    }
+
+    fn apply(&self, buffer: &mut PixelBuffer) -> Result<()> {
+        replace(self, buffer)
+    }
+
+    fn apply_to(&self, image: &RgbaImage, _token: &CancellationToken) -> Result<RgbaImage> {
+        Ok(filters::sepia(image))
+    }
+}
+
+/// Mix channels with a 4x5 affine color matrix, see `filters::color_matrix`
+#[derive(Debug, Clone)]
+pub struct ColorMatrixFilter {
+    pub matrix: ColorMatrix,
+}
+
+impl ColorMatrixFilter {
+    pub fn info() -> OperationInfo {
+        op(
+            "color_matrix",
+            "Mix channels with a 4x5 RGBA matrix like SVG feColorMatrix",
+            vec![ParamInfo::new(
+                "matrix",
+                ParamType::Matrix,
+                "Four rows of five numbers: R, G, B, A weights and an offset",
+            )],
+        )
+    }
+}
+
+impl Filter for ColorMatrixFilter {
+    fn name(&self) -> &str {
+        "color_matrix"
+    }
+
+    fn schema(&self) -> Option<OperationInfo> {
+        Some(Self::info())
+    }
+
+    fn validate(&self) -> Result<()> {
+        if !self.matrix.iter().flatten().all(|v| v.is_finite()) {
+            return Err(invalid("matrix entries must be finite numbers"));
+        }
+        Ok(())
+    }
+
+    fn apply(&self, buffer: &mut PixelBuffer) -> Result<()> {
+        replace(self, buffer)
+    }
+
+    fn apply_to(&self, image: &RgbaImage, _token: &CancellationToken) -> Result<RgbaImage> {
+        Ok(filters::color_matrix(image, self.matrix))
+    }
+}
+
+/// Gamma correction (> 0.0, 1.0 = no change)
+#[derive(Debug, Clone)]
+pub struct GammaFilter {
+    pub value: f32,
+}
+
+impl GammaFilter {
+    pub fn info() -> OperationInfo {
+        op(
+            "gamma",
+            "Gamma correction",
+            vec![number("value", "Gamma; above 1.0 brightens midtones").above(0.0)],
+        )
+    }
+}
+
+impl Filter for GammaFilter {
+    fn name(&self) -> &str {
+        "gamma"
+    }
+
+    fn schema(&self) -> Option<OperationInfo> {
+        Some(Self::info())
+    }
+
+    fn validate(&self) -> Result<()> {
+        if !self.value.is_finite() || self.value <= 0.0 {
+            return Err(invalid(format!(
+                "value must be a positive number, got {}",
+                self.value
+            )));
+        }
+        Ok(())
+    }
+
+    fn apply(&self, buffer: &mut PixelBuffer) -> Result<()> {
+        replace(self, buffer)
+    }
+
+    fn apply_to(&self, image: &RgbaImage, _token: &CancellationToken) -> Result<RgbaImage> {
+        Ok(filters::gamma(image, self.value))
+    }
+}
+
+/// 3D color LUT with trilinear interpolation
+#[derive(Debug, Clone)]
+pub struct Lut3DFilter {
+    pub lut: Arc<Lut3D>,
+}
+
+impl Lut3DFilter {
+    pub fn info() -> OperationInfo {
+        op(
+            "lut3d",
+            "Apply a .cube color lookup table",
+            vec![ParamInfo::new(
+                "path",
+                ParamType::String,
+                "Path to the .cube file",
+            )],
+        )
+    }
+}
+
+// The table is validated when the .cube file is parsed
+impl Filter for Lut3DFilter {
+    fn name(&self) -> &str {
+        "lut3d"
+    }
+
+    fn schema(&self) -> Option<OperationInfo> {
+        Some(Self::info())
+    }
+
+    fn apply(&self, buffer: &mut PixelBuffer) -> Result<()> {
+        replace(self, buffer)
+    }
+
+    fn apply_to(&self, image: &RgbaImage, _token: &CancellationToken) -> Result<RgbaImage> {
+        Ok(self.lut.apply(image))
+    }
+}
+
+/// Seeded Gaussian noise (amount 0.0 to 1.0)
+#[derive(Debug, Clone)]
+pub struct NoiseFilter {
+    pub amount: f32,
+    pub monochrome: bool,
+    pub seed: u64,
+}
+
+impl NoiseFilter {
+    pub fn info() -> OperationInfo {
+        op(
+            "noise",
+            "Add film grain",
+            vec![
+                number("amount", "Strength, 0.0 to 1.0").range(0.0, 1.0),
+                ParamInfo::new(
+                    "monochrome",
+                    ParamType::Boolean,
+                    "Same noise on every channel",
+                )
+                .default(json!(false)),
+                ParamInfo::new("seed", ParamType::Integer, "Random seed")
+                    .min(0.0)
+                    .default(json!(0)),
+            ],
+        )
+    }
+}
+
+impl Filter for NoiseFilter {
+    fn name(&self) -> &str {
+        "noise"
+    }
+
+    fn schema(&self) -> Option<OperationInfo> {
+        Some(Self::info())
+    }
+
+    fn validate(&self) -> Result<()> {
+        check_unit("amount", self.amount)
+    }
+
+    fn apply(&self, buffer: &mut PixelBuffer) -> Result<()> {
+        replace(self, buffer)
+    }
+
+    fn apply_to(&self, image: &RgbaImage, _token: &CancellationToken) -> Result<RgbaImage> {
+        Ok(filters::add_noise(
+            image,
+            self.amount,
+            self.monochrome,
+            self.seed,
+        ))
+    }
+}
+
+/// Mosaic of `block_size` square blocks
+#[derive(Debug, Clone)]
+pub struct PixelateFilter {
+    pub block_size: u32,
+}
+
+impl PixelateFilter {
+    pub fn info() -> OperationInfo {
+        op(
+            "pixelate",
+            "Mosaic of square blocks",
+            vec![ParamInfo::new("block_size", ParamType::Integer, "Block size in pixels").min(1.0)],
+        )
+    }
+}
+
+impl Filter for PixelateFilter {
+    fn name(&self) -> &str {
+        "pixelate"
+    }
+
+    fn schema(&self) -> Option<OperationInfo> {
+        Some(Self::info())
+    }
+
+    fn validate(&self) -> Result<()> {
+        if self.block_size == 0 {
+            return Err(invalid("block_size must be at least 1"));
+        }
+        Ok(())
+    }
+
+    fn apply(&self, buffer: &mut PixelBuffer) -> Result<()> {
+        replace(self, buffer)
+    }
+
+    fn apply_to(&self, image: &RgbaImage, _token: &CancellationToken) -> Result<RgbaImage> {
+        Ok(filters::pixelate(image, self.block_size))
+    }
+}
+
+/// Straighten a skewed document scan
+#[derive(Debug, Clone)]
+pub struct DeskewFilter;
+
+impl DeskewFilter {
+    pub fn info() -> OperationInfo {
+        op("deskew", "Straighten a skewed document scan", vec![])
+    }
+}
+
+impl Filter for DeskewFilter {
+    fn name(&self) -> &str {
+        "deskew"
+    }
+
+    fn schema(&self) -> Option<OperationInfo> {
+        Some(Self::info())
+    }
+
+    fn apply(&self, buffer: &mut PixelBuffer) -> Result<()> {
+        replace(self, buffer)
+    }
+
+    fn apply_to(&self, image: &RgbaImage, _token: &CancellationToken) -> Result<RgbaImage> {
+        Ok(filters::deskew(image))
+    }
+}
+
+/// Rotate clockwise by any angle; `expand` grows the canvas to fit
+#[derive(Debug, Clone)]
+pub struct RotateFilter {
+    pub degrees: f32,
+    pub background: Rgba<u8>,
+    pub expand: bool,
+}
+
+impl RotateFilter {
+    pub fn info() -> OperationInfo {
+        resizing(op(
+            "rotate",
+            "Rotate clockwise by any angle",
+            vec![
+                number("degrees", "Clockwise angle in degrees"),
+                ParamInfo::new("background", ParamType::Color, "Fill for uncovered corners")
+                    .default(json!([0, 0, 0, 0])),
+                ParamInfo::new(
+                    "expand",
+                    ParamType::Boolean,
+                    "Grow the canvas to fit the rotated image",
+                )
+                .default(json!(false)),
+            ],
+        ))
+    }
+}
+
+impl Filter for RotateFilter {
+    fn name(&self) -> &str {
+        "rotate"
+    }
+
+    fn schema(&self) -> Option<OperationInfo> {
+        Some(Self::info())
+    }
+
+    fn validate(&self) -> Result<()> {
+        if !self.degrees.is_finite() {
+            return Err(invalid(format!(
+                "degrees must be a finite number, got {}",
+                self.degrees
+            )));
+        }
+        Ok(())
+    }
+
+    fn changes_size(&self) -> bool {
+        self.expand
+    }
+
+    fn apply(&self, buffer: &mut PixelBuffer) -> Result<()> {
+        replace(self, buffer)
+    }
+
+    fn apply_to(&self, image: &RgbaImage, _token: &CancellationToken) -> Result<RgbaImage> {
+        Ok(filters::rotate(
+            image,
+            self.degrees,
+            self.background,
+            self.expand,
+        ))
+    }
+}
+
+/// Map (x, y) to (a*x + b*y + c, d*x + e*y + f) for `matrix = [a, b, c, d, e, f]`
+#[derive(Debug, Clone)]
+pub struct AffineFilter {
+    pub matrix: [f32; 6],
+    pub interpolation: Interpolation,
+    pub background: Rgba<u8>,
+}
+
+impl AffineFilter {
+    pub fn info() -> OperationInfo {
+        op(
+            "affine",
+            "Affine transform",
+            vec![
+                ParamInfo::new(
+                    "matrix",
+                    ParamType::Matrix,
+                    "Invertible row-major 2x3 matrix [a, b, c, d, e, f]",
+                ),
+                ParamInfo::new("interpolation", ParamType::Enum, "Sampling method")
+                    .options(&["nearest", "bilinear", "bicubic"])
+                    .default(json!("bilinear")),
+                ParamInfo::new("background", ParamType::Color, "Fill for uncovered areas")
+                    .default(json!([0, 0, 0, 0])),
+            ],
+        )
+    }
+}
+
+impl Filter for AffineFilter {
+    fn name(&self) -> &str {
+        "affine"
+    }
+
+    fn schema(&self) -> Option<OperationInfo> {
+        Some(Self::info())
+    }
+
+    fn validate(&self) -> Result<()> {
+        let matrix = &self.matrix;
+        if matrix.iter().any(|v| !v.is_finite()) {
+            return Err(invalid("matrix entries must be finite numbers"));
+        }
+        if (matrix[0] * matrix[4] - matrix[1] * matrix[3]).abs() < 1e-6 {
+            return Err(invalid("matrix must be invertible"));
+        }
+        Ok(())
+    }
+
+    fn apply(&self, buffer: &mut PixelBuffer) -> Result<()> {
+        replace(self, buffer)
+    }
+
+    fn apply_to(&self, image: &RgbaImage, _token: &CancellationToken) -> Result<RgbaImage> {
+        filters::affine(image, self.matrix, self.interpolation, self.background)
+    }
+}
+
+/// Undo radial lens distortion (negative `k1` = barrel)
+#[derive(Debug, Clone)]
+pub struct LensCorrectFilter {
+    pub k1: f32,
+    pub k2: f32,
+    pub k3: f32,
+}
+
+impl LensCorrectFilter {
+    pub fn info() -> OperationInfo {
+        op(
+            "lens_correct",
+            "Undo radial lens distortion",
+            vec![
+                number(
+                    "k1",
+                    "Quadratic coefficient; negative for barrel distortion",
+                ),
+                number("k2", "Quartic coefficient").default(json!(0.0)),
+                number("k3", "Sixth-order coefficient").default(json!(0.0)),
+            ],
+        )
+    }
+}
+
+impl Filter for LensCorrectFilter {
+    fn name(&self) -> &str {
+        "lens_correct"
+    }
+
+    fn schema(&self) -> Option<OperationInfo> {
+        Some(Self::info())
+    }
+
+    fn validate(&self) -> Result<()> {
+        if ![self.k1, self.k2, self.k3].iter().all(|k| k.is_finite()) {
+            return Err(invalid("k1, k2 and k3 must be finite numbers"));
+        }
+        Ok(())
+    }
+
+    fn apply(&self, buffer: &mut PixelBuffer) -> Result<()> {
+        replace(self, buffer)
+    }
+
+    fn apply_to(&self, image: &RgbaImage, _token: &CancellationToken) -> Result<RgbaImage> {
+        Ok(filters::lens_correct(image, self.k1, self.k2, self.k3))
+    }
+}
+
+/// Shift red and blue radially by the given pixels at the corners (negative corrects)
+#[derive(Debug, Clone)]
+pub struct ChromaticAberrationFilter {
+    pub red_shift: f32,
+    pub blue_shift: f32,
+}
+
+impl ChromaticAberrationFilter {
+    pub fn info() -> OperationInfo {
+        op(
+            "chromatic_aberration",
+            "Shift red and blue radially",
+            vec![
+                number(
+                    "red_shift",
+                    "Red shift at the corners in pixels; negative corrects",
+                )
+                .default(json!(0.0)),
+                number(
+                    "blue_shift",
+                    "Blue shift at the corners in pixels; negative corrects",
+                )
+                .default(json!(0.0)),
+            ],
+        )
+    }
+}
+
+impl Filter for ChromaticAberrationFilter {
+    fn name(&self) -> &str {
+        "chromatic_aberration"
+    }
+
+    fn schema(&self) -> Option<OperationInfo> {
+        Some(Self::info())
+    }
+
+    fn validate(&self) -> Result<()> {
+        if !self.red_shift.is_finite() || !self.blue_shift.is_finite() {
+            return Err(invalid("red_shift and blue_shift must be finite numbers"));
+        }
+        Ok(())
+    }
+
+    fn apply(&self, buffer: &mut PixelBuffer) -> Result<()> {
+        replace(self, buffer)
+    }
+
+    fn apply_to(&self, image: &RgbaImage, _token: &CancellationToken) -> Result<RgbaImage> {
+        Ok(filters::chromatic_aberration(
+            image,
+            self.red_shift,
+            self.blue_shift,
+        ))
+    }
+}
+
+/// Make pixels near `key_color` transparent and suppress its spill, see
+/// `filters::chroma_key`
+#[derive(Debug, Clone)]
+pub struct ChromaKeyFilter {
+    pub key_color: Rgba<u8>,
+    pub tolerance: f32,
+    pub softness: f32,
+}
+
+impl ChromaKeyFilter {
+    pub fn info() -> OperationInfo {
+        op(
+            "chroma_key",
+            "Make a green or blue screen transparent",
+            vec![
+                ParamInfo::new("key_color", ParamType::Color, "Screen color to remove")
+                    .default(json!([0, 255, 0, 255])),
+                number("tolerance", "Chroma distance that is fully transparent").range(0.0, 1.0),
+                number("softness", "Width of the partly transparent edge")
+                    .range(0.0, 1.0)
+                    .default(json!(0.0)),
+            ],
+        )
+    }
+}
+
+impl Filter for ChromaKeyFilter {
+    fn name(&self) -> &str {
+        "chroma_key"
+    }
+
+    fn schema(&self) -> Option<OperationInfo> {
+        Some(Self::info())
+    }
+
+    fn validate(&self) -> Result<()> {
+        check_unit("tolerance", self.tolerance)?;
+        check_unit("softness", self.softness)
+    }
+
+    fn apply(&self, buffer: &mut PixelBuffer) -> Result<()> {
+        replace(self, buffer)
+    }
+
+    fn apply_to(&self, image: &RgbaImage, _token: &CancellationToken) -> Result<RgbaImage> {
+        Ok(filters::chroma_key(
+            image,
+            self.key_color,
+            self.tolerance,
+            self.softness,
+        ))
+    }
+}
+
+/// Recolor through a ramp of (position, color) stops by luminance, see
+/// `filters::gradient_map`
+#[derive(Debug, Clone)]
+pub struct GradientMapFilter {
+    pub stops: Vec<(f32, Rgba<u8>)>,
+}
+
+impl GradientMapFilter {
+    pub fn info() -> OperationInfo {
+        op(
+            "gradient_map",
+            "Recolor by luminance through a color ramp, e.g. a duotone",
+            vec![ParamInfo::new(
+                "stops",
+                ParamType::Object,
+                "`[{position, color}, ...]` with positions from 0.0 to 1.0",
+            )],
+        )
+    }
+}
+
+impl Filter for GradientMapFilter {
+    fn name(&self) -> &str {
+        "gradient_map"
+    }
+
+    fn schema(&self) -> Option<OperationInfo> {
+        Some(Self::info())
+    }
+
+    fn validate(&self) -> Result<()> {
+        if self.stops.is_empty() {
+            return Err(invalid("stops must not be empty"));
+        }
+        if let Some((position, _)) = self.stops.iter().find(|(p, _)| !(0.0..=1.0).contains(p)) {
+            return Err(invalid(format!(
+                "stop positions must be between 0.0 and 1.0, got {}",
+                position
+            )));
+        }
+        Ok(())
+    }
+
+    fn apply(&self, buffer: &mut PixelBuffer) -> Result<()> {
+        replace(self, buffer)
+    }
+
+    fn apply_to(&self, image: &RgbaImage, _token: &CancellationToken) -> Result<RgbaImage> {
+        filters::gradient_map(image, &self.stops)
+    }
+}
+
+/// Shift hue, scale saturation and shift value of the colors in `hue_range`
+/// only, see `filters::selective_color`
+#[derive(Debug, Clone)]
+pub struct SelectiveColorFilter {
+    pub hue_range: (f32, f32),
+    pub hue_shift: f32,
+    pub saturation: f32,
+    pub lightness: f32,
+}
+
+impl SelectiveColorFilter {
+    pub fn info() -> OperationInfo {
+        op(
+            "selective_color",
+            "Adjust only the colors in a hue range",
+            vec![
+                number("hue_min", "Start of the hue range in degrees"),
+                number(
+                    "hue_max",
+                    "End of the hue range in degrees, wrapping at 360",
+                ),
+                number("hue_shift", "Degrees added to the hue").default(json!(0.0)),
+                number("saturation", "Saturation multiplier")
+                    .min(0.0)
+                    .default(json!(1.0)),
+                number("lightness", "Added to the value")
+                    .range(-1.0, 1.0)
+                    .default(json!(0.0)),
+            ],
+        )
+    }
+}
+
+impl Filter for SelectiveColorFilter {
+    fn name(&self) -> &str {
+        "selective_color"
+    }
+
+    fn schema(&self) -> Option<OperationInfo> {
+        Some(Self::info())
+    }
+
+    fn validate(&self) -> Result<()> {
+        let (start, end) = self.hue_range;
+        if !(start.is_finite() && end.is_finite() && self.hue_shift.is_finite()) {
+            return Err(invalid("hue_range and hue_shift must be finite"));
+        }
+        if !(self.saturation >= 0.0 && self.saturation.is_finite()) {
+            return Err(invalid(format!(
+                "saturation must be at least 0.0, got {}",
+                self.saturation
+            )));
+        }
+        if !(-1.0..=1.0).contains(&self.lightness) {
+            return Err(invalid(format!(
+                "lightness must be between -1.0 and 1.0, got {}",
+                self.lightness
+            )));
+        }
+        Ok(())
+    }
+
+    fn apply(&self, buffer: &mut PixelBuffer) -> Result<()> {
+        replace(self, buffer)
+    }
+
+    fn apply_to(&self, image: &RgbaImage, _token: &CancellationToken) -> Result<RgbaImage> {
+        Ok(filters::selective_color(
+            image,
+            self.hue_range,
+            self.hue_shift,
+            self.saturation,
+            self.lightness,
+        ))
+    }
+}
+
+/// Brighten dark and darken bright areas, see `filters::shadows_highlights`
+#[derive(Debug, Clone)]
+pub struct ShadowsHighlightsFilter {
+    pub shadows: f32,
+    pub highlights: f32,
+    pub radius: f32,
+}
+
+impl ShadowsHighlightsFilter {
+    pub fn info() -> OperationInfo {
+        op(
+            "shadows_highlights",
+            "Lift shadows and recover highlights",
+            vec![
+                number("shadows", "How much dark areas are brightened")
+                    .range(0.0, 1.0)
+                    .default(json!(0.0)),
+                number("highlights", "How much bright areas are darkened")
+                    .range(0.0, 1.0)
+                    .default(json!(0.0)),
+                number("radius", "Blur sigma of the luminance mask in pixels")
+                    .range(0.0, MAX_BLUR_SIGMA as f64),
+            ],
+        )
+    }
+}
+
+impl Filter for ShadowsHighlightsFilter {
+    fn name(&self) -> &str {
+        "shadows_highlights"
+    }
+
+    fn schema(&self) -> Option<OperationInfo> {
+        Some(Self::info())
+    }
+
+    fn validate(&self) -> Result<()> {
+        if !(0.0..=1.0).contains(&self.shadows) || !(0.0..=1.0).contains(&self.highlights) {
+            return Err(invalid(format!(
+                "shadows and highlights must be between 0.0 and 1.0, got {} and {}",
+                self.shadows, self.highlights
+            )));
+        }
+        if !(0.0..=MAX_BLUR_SIGMA).contains(&self.radius) {
+            return Err(invalid(format!(
+                "radius must be in [0, {}], got {}",
+                MAX_BLUR_SIGMA, self.radius
+            )));
+        }
+        Ok(())
+    }
+
+    fn apply(&self, buffer: &mut PixelBuffer) -> Result<()> {
+        replace(self, buffer)
+    }
+
+    fn apply_to(&self, image: &RgbaImage, token: &CancellationToken) -> Result<RgbaImage> {
+        filters::shadows_highlights_cancellable(
+            image,
+            self.shadows,
+            self.highlights,
+            self.radius,
+            token,
+        )
+    }
+}
+
+/// Blur the background with `sigma`, keeping the subject in `mask` sharp; see
+/// `portrait`
+#[derive(Debug, Clone)]
+pub struct PortraitBlurFilter {
+    pub mask: Option<Region>,
+    pub sigma: f32,
+    pub feather: f32,
+}
+
+impl PortraitBlurFilter {
+    pub fn info() -> OperationInfo {
+        op(
+            "portrait_blur",
+            "Blur the background, keeping the subject sharp",
+            vec![
+                ParamInfo::new(
+                    "mask",
+                    ParamType::Region,
+                    "Subject rectangle or mask image path; null estimates it",
+                )
+                .default(Value::Null),
+                number("sigma", "Background blur strength")
+                    .above(0.0)
+                    .max(MAX_BLUR_SIGMA as f64),
+                number("feather", "Softening of the subject edge in pixels")
+                    .range(0.0, MAX_BLUR_SIGMA as f64)
+                    .default(json!(0.0)),
+            ],
+        )
+    }
+}
+
+impl Filter for PortraitBlurFilter {
+    fn name(&self) -> &str {
+        "portrait_blur"
+    }
+
+    fn schema(&self) -> Option<OperationInfo> {
+        Some(Self::info())
+    }
+
+    fn validate(&self) -> Result<()> {
+        if let Some(mask) = &self.mask {
+            mask.check().map_err(invalid)?;
+        }
+        check_sigma("sigma", self.sigma)?;
+        if !self.feather.is_finite() || !(0.0..=MAX_BLUR_SIGMA).contains(&self.feather) {
+            return Err(invalid(format!(
+                "feather must be in [0, {}], got {}",
+                MAX_BLUR_SIGMA, self.feather
+            )));
+        }
+        Ok(())
+    }
+
+    fn apply(&self, buffer: &mut PixelBuffer) -> Result<()> {
+        replace(self, buffer)
+    }
+
+    fn apply_to(&self, image: &RgbaImage, token: &CancellationToken) -> Result<RgbaImage> {
+        portrait::portrait_blur_cancellable(
+            image,
+            self.mask.as_ref(),
+            self.sigma,
+            self.feather,
+            token,
+        )
+    }
+}
+
+/// Run an ONNX model on the image, see `model`
+#[derive(Debug, Clone)]
+pub struct ModelFilter {
+    pub model: Arc<Model>,
+    pub pre: Preprocess,
+    pub post: Postprocess,
+}
+
+impl ModelFilter {
+    pub fn info() -> OperationInfo {
+        op(
+            "model",
+            "Run an ONNX model (needs the onnx feature)",
+            vec![
+                ParamInfo::new("path", ParamType::String, "Path of the .onnx file"),
+                ParamInfo::new(
+                    "pre",
+                    ParamType::Object,
+                    "Input preparation: size, layout (nchw/nhwc), mean, std",
+                )
+                .default(json!({})),
+                ParamInfo::new("post", ParamType::Enum, "What the output becomes")
+                    .options(&["image", "alpha"])
+                    .default(json!("image")),
+            ],
+        )
+    }
+}
+
+impl Filter for ModelFilter {
+    fn name(&self) -> &str {
+        "model"
+    }
+
+    fn schema(&self) -> Option<OperationInfo> {
+        Some(Self::info())
+    }
+
+    fn validate(&self) -> Result<()> {
+        self.pre.check().map_err(invalid)
+    }
+
+    fn changes_size(&self) -> bool {
+        self.post == Postprocess::Image
+    }
+
+    fn apply(&self, buffer: &mut PixelBuffer) -> Result<()> {
+        replace(self, buffer)
+    }
+
+    fn apply_to(&self, image: &RgbaImage, token: &CancellationToken) -> Result<RgbaImage> {
+        self.model.apply(image, &self.pre, self.post, token)
+    }
+}
+
+/// Enlarge by `factor`, see `filters::upscale`
+#[derive(Debug, Clone)]
+pub struct UpscaleFilter {
+    pub factor: f32,
+    pub method: UpscaleMethod,
+}
+
+impl UpscaleFilter {
+    pub fn info() -> OperationInfo {
+        resizing(op(
+            "upscale",
+            "Enlarge with detail-preserving sharpening or a super-resolution model",
+            vec![
+                number("factor", "Scale factor").range(1.0, MAX_UPSCALE_FACTOR as f64),
+                ParamInfo::new(
+                    "method",
+                    ParamType::Enum,
+                    "Classical method without a model",
+                )
+                .options(&["lanczos", "detail"])
+                .default(json!("detail")),
+                ParamInfo::new(
+                    "model",
+                    ParamType::String,
+                    "Path of a super-resolution .onnx file (needs the onnx feature)",
+                )
+                .default(Value::Null),
+                ParamInfo::new(
+                    "pre",
+                    ParamType::Object,
+                    "Model input preparation, see model",
+                )
+                .default(json!({})),
+            ],
+        ))
+    }
+}
+
+impl Filter for UpscaleFilter {
+    fn name(&self) -> &str {
+        "upscale"
+    }
+
+    fn schema(&self) -> Option<OperationInfo> {
+        Some(Self::info())
+    }
+
+    fn validate(&self) -> Result<()> {
+        if !(1.0..=MAX_UPSCALE_FACTOR).contains(&self.factor) {
+            return Err(invalid(format!(
+                "factor must be between 1.0 and {}, got {}",
+                MAX_UPSCALE_FACTOR, self.factor
+            )));
+        }
+        if let UpscaleMethod::Model { pre, .. } = &self.method {
+            pre.check().map_err(invalid)?;
+        }
+        Ok(())
+    }
+
+    fn changes_size(&self) -> bool {
+        true
+    }
+
+    fn apply(&self, buffer: &mut PixelBuffer) -> Result<()> {
+        replace(self, buffer)
+    }
+
+    fn apply_to(&self, image: &RgbaImage, token: &CancellationToken) -> Result<RgbaImage> {
+        filters::upscale_cancellable(image, self.factor, &self.method, token)
+    }
+}
+
+/// Draw `mark` over the image, see `compose::watermark`
+#[derive(Debug, Clone)]
+pub struct WatermarkFilter {
+    pub mark: Arc<Mark>,
+    pub anchor: Anchor,
+    pub margin: u32,
+    pub opacity: f32,
+    pub tile: bool,
+    /// Mark width as a fraction of the image width, `None` for its own size
+    pub scale: Option<f32>,
+}
+
+impl WatermarkFilter {
+    pub fn info() -> OperationInfo {
+        op(
+            "watermark",
+            "Draw a watermark image, once at an anchor or tiled",
+            vec![
+                ParamInfo::new("path", ParamType::String, "Watermark image file"),
+                ParamInfo::new("anchor", ParamType::Enum, "Where the mark sits")
+                    .options(&[
+                        "top_left",
+                        "top",
+                        "top_right",
+                        "left",
+                        "center",
+                        "right",
+                        "bottom_left",
+                        "bottom",
+                        "bottom_right",
+                    ])
+                    .default(json!("bottom_right")),
+                ParamInfo::new(
+                    "margin",
+                    ParamType::Integer,
+                    "Pixels from the anchored edges and between tiles",
+                )
+                .min(0.0)
+                .default(json!(0)),
+                number("opacity", "Multiplied with the mark's alpha")
+                    .range(0.0, 1.0)
+                    .default(json!(1.0)),
+                ParamInfo::new("tile", ParamType::Boolean, "Repeat across the whole image")
+                    .default(json!(false)),
+                ParamInfo::new(
+                    "scale",
+                    ParamType::Number,
+                    "Mark width as a fraction of the image width (own size when null)",
+                )
+                .range(0.0, 1.0)
+                .default(Value::Null),
+            ],
+        )
+    }
+}
+
+impl Filter for WatermarkFilter {
+    fn name(&self) -> &str {
+        "watermark"
+    }
+
+    fn schema(&self) -> Option<OperationInfo> {
+        Some(Self::info())
+    }
+
+    fn validate(&self) -> Result<()> {
+        check_unit("opacity", self.opacity)?;
+        if let Some(scale) = self.scale {
+            if !(scale > 0.0 && scale <= 1.0) {
+                return Err(invalid(format!("scale must be in (0, 1], got {}", scale)));
+            }
+        }
+        Ok(())
+    }
+
+    fn apply(&self, buffer: &mut PixelBuffer) -> Result<()> {
+        replace(self, buffer)
+    }
+
+    fn apply_to(&self, image: &RgbaImage, _token: &CancellationToken) -> Result<RgbaImage> {
+        compose::watermark(
+            image,
+            self.mark.image(),
+            self.anchor,
+            self.margin,
+            self.opacity,
+            self.tile,
+            self.scale,
+        )
+    }
+}
+
+/// Grow the canvas and draw a blurred shadow of the image's alpha behind it,
+/// see `compose::drop_shadow`
+#[derive(Debug, Clone)]
+pub struct DropShadowFilter {
+    pub offset: (i32, i32),
+    pub sigma: f32,
+    pub color: Rgba<u8>,
+    pub opacity: f32,
+}
+
+impl DropShadowFilter {
+    pub fn info() -> OperationInfo {
+        resizing(op(
+            "drop_shadow",
+            "Draw a soft shadow behind the image, growing the canvas",
+            vec![
+                ParamInfo::new("offset_x", ParamType::Integer, "Shadow shift to the right")
+                    .default(json!(0)),
+                ParamInfo::new("offset_y", ParamType::Integer, "Shadow shift downwards")
+                    .default(json!(0)),
+                number("sigma", "Softness of the shadow edge; 0 is hard")
+                    .range(0.0, MAX_BLUR_SIGMA as f64),
+                ParamInfo::new("color", ParamType::Color, "Shadow color")
+                    .default(json!([0, 0, 0, 255])),
+                number("opacity", "Multiplied with the color and image alpha")
+                    .range(0.0, 1.0)
+                    .default(json!(1.0)),
+            ],
+        ))
+    }
+}
+
+impl Filter for DropShadowFilter {
+    fn name(&self) -> &str {
+        "drop_shadow"
+    }
+
+    fn schema(&self) -> Option<OperationInfo> {
+        Some(Self::info())
+    }
+
+    fn validate(&self) -> Result<()> {
+        if !(0.0..=MAX_BLUR_SIGMA).contains(&self.sigma) {
+            return Err(invalid(format!(
+                "sigma must be in [0, {}], got {}",
+                MAX_BLUR_SIGMA, self.sigma
+            )));
+        }
+        check_unit("opacity", self.opacity)
+    }
+
+    fn changes_size(&self) -> bool {
+        true
+    }
+
+    fn apply(&self, buffer: &mut PixelBuffer) -> Result<()> {
+        replace(self, buffer)
+    }
+
+    fn apply_to(&self, image: &RgbaImage, _token: &CancellationToken) -> Result<RgbaImage> {
+        compose::drop_shadow(image, self.offset, self.sigma, self.color, self.opacity)
+    }
+}
+
+/// Apply `op` only inside `region`; `op` must not change the image size
+#[derive(Debug, Clone)]
+pub struct MaskedFilter {
+    pub region: Region,
+    pub op: Box<FilterOperation>,
+}
+
+impl MaskedFilter {
+    pub fn info() -> OperationInfo {
+        op(
+            "masked",
+            "Apply an operation inside a region only",
+            vec![
+                ParamInfo::new("region", ParamType::Region, "Rectangle or mask image path"),
+                ParamInfo::new(
+                    "op",
+                    ParamType::Operation,
+                    "Operation that keeps the image size",
+                ),
+            ],
+        )
+    }
+}
+
+impl Filter for MaskedFilter {
+    fn name(&self) -> &str {
+        "masked"
+    }
+
+    fn schema(&self) -> Option<OperationInfo> {
+        Some(Self::info())
+    }
+
+    fn validate(&self) -> Result<()> {
+        self.region.check().map_err(invalid)?;
+        if self.op.changes_size() {
+            return Err(invalid(format!(
+                "{} cannot be masked because it changes the image size",
+                self.op.name()
+            )));
+        }
+        self.op
+            .check_parameters()
+            .map_err(|reason| invalid(format!("{}: {}", self.op.name(), reason)))
+    }
+
+    fn changes_size(&self) -> bool {
+        self.op.changes_size()
+    }
+
+    fn apply(&self, buffer: &mut PixelBuffer) -> Result<()> {
+        replace(self, buffer)
+    }
+
+    fn apply_to(&self, image: &RgbaImage, token: &CancellationToken) -> Result<RgbaImage> {
+        let filtered = self.op.to_filter().apply_to(image, token)?;
+        self.region.blend(image, &filtered)
+    }
+}
+
+/// Stand-in for an operation naming a filter registered with a pipeline
+///
+/// Only that pipeline can resolve `name`, so applying this on its own fails.
+#[derive(Debug, Clone)]
+pub struct CustomFilter {
+    pub name: String,
+    pub params: Value,
+}
+
+impl CustomFilter {
+    pub fn info() -> OperationInfo {
+        op(
+            "custom",
+            "Filter registered by the application",
+            vec![
+                ParamInfo::new("name", ParamType::String, "Registered filter name"),
+                ParamInfo::new("params", ParamType::Object, "Passed to the filter")
+                    .default(Value::Null),
+            ],
+        )
+    }
+}
+
+impl Filter for CustomFilter {
+    fn name(&self) -> &str {
+        "custom"
+    }
+
+    fn schema(&self) -> Option<OperationInfo> {
+        Some(Self::info())
+    }
+
+    fn validate(&self) -> Result<()> {
+        if self.name.is_empty() {
+            return Err(invalid("name must not be empty"));
+        }
+        Ok(())
+    }
+
+    fn apply(&self, buffer: &mut PixelBuffer) -> Result<()> {
+        replace(self, buffer)
+    }
+
+    fn apply_to(&self, _image: &RgbaImage, _token: &CancellationToken) -> Result<RgbaImage> {
+        Err(invalid(format!(
+            "custom filter '{}' can only run on the pipeline it is registered with",
+            self.name
+        )))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::registry::registry;
+    use crate::{filters::blur_cancellable, IDENTITY_COLOR_MATRIX};
+
+    fn operations() -> Vec<FilterOperation> {
+        vec![
+            FilterOperation::Grayscale(GrayscaleMode::Bt709),
+            FilterOperation::Brightness(0.2),
+            FilterOperation::Blur(1.5),
+            FilterOperation::ApproximateBlur(1.5),
+            FilterOperation::Resize {
+                width: 4,
+                height: 3,
+                filter: ResampleFilter::Lanczos3,
+            },
+            FilterOperation::Invert,
+            FilterOperation::ColorMatrix(IDENTITY_COLOR_MATRIX),
+            FilterOperation::Rotate {
+                degrees: 30.0,
+                background: Rgba([0, 0, 0, 0]),
+                expand: true,
+            },
+            FilterOperation::Masked {
+                region: Region::Rect {
+                    x: 0,
+                    y: 0,
+                    width: 2,
+                    height: 2,
+                },
+                op: Box::new(FilterOperation::Sepia),
+            },
+            FilterOperation::Custom {
+                name: "vignette".to_string(),
+                params: Value::Null,
+            },
+        ]
+    }
+
+    #[test]
+    fn test_filters_match_operations() {
+        let schemas = registry();
+        for op in operations() {
+            let filter = op.to_filter();
+            assert_eq!(filter.name(), op.name());
+            assert_eq!(filter.changes_size(), op.changes_size(), "{}", op.name());
+            let schema = filter.schema().unwrap();
+            assert!(schemas.contains(&schema), "{} is not registered", op.name());
+        }
+    }
+
+    #[test]
+    fn test_filter_runs_like_pipeline() {
+        let image = RgbaImage::from_fn(8, 6, |x, y| Rgba([x as u8 * 30, y as u8 * 40, 90, 255]));
+        let token = CancellationToken::new();
+        let op = FilterOperation::Blur(1.0);
+        let mut buffer = PixelBuffer::new(image.clone());
+        op.to_filter().apply(&mut buffer).unwrap();
+        assert_eq!(
+            buffer.into_image(),
+            blur_cancellable(&image, 1.0, &token).unwrap()
+        );
+
+        let masked = MaskedFilter {
+            region: Region::Rect {
+                x: 0,
+                y: 0,
+                width: 4,
+                height: 6,
+            },
+            op: Box::new(FilterOperation::Invert),
+        };
+        let result = masked.apply_to(&image, &token).unwrap();
+        assert_eq!(result.get_pixel(6, 2), image.get_pixel(6, 2));
+        assert_ne!(result.get_pixel(1, 2), image.get_pixel(1, 2));
+    }
+
+    #[test]
+    fn test_validation_errors_name_the_parameter() {
+        let err = BlurFilter { sigma: -1.0 }.validate().unwrap_err();
+        assert!(matches!(err, PipelineError::InvalidParameter(ref m) if m.starts_with("sigma ")));
+
+        let masked = FilterOperation::Masked {
+            region: Region::Rect {
+                x: 0,
+                y: 0,
+                width: 1,
+                height: 1,
+            },
+            op: Box::new(FilterOperation::Gamma(0.0)),
+        };
+        let err = masked.validate().unwrap_err().to_string();
+        assert!(err.contains("masked: gamma: value must be"), "{}", err);
+    }
+}
@@ -0,0 +1,246 @@
+//! Pipeline optimization: fusing adjacent point operations
+//!
+//! Point operations (brightness, contrast, gamma, invert, grayscale, sepia,
+//! color matrices) only look at one pixel at a time, so a run of them can be
+//! executed in a single pass instead of materializing an intermediate image
+//! after every step. Per-channel curves are folded into one lookup table; color
+//! mixing steps are kept as 4x5 color matrices so the fused result is
+//! bit-identical to running the operations one by one.
+
+use crate::filters::{self, ColorMatrix, SEPIA_COLOR_MATRIX};
+use crate::{FilterOperation, Lut256};
+use image::RgbaImage;
+use rayon::prelude::*;
+use std::ops::Range;
+
+/// One step of a fused point program
+#[derive(Debug, Clone)]
+enum PointStage {
+    /// Independent curve per RGB channel
+    Lut(Box<Lut256>),
+    /// Channel mixing, clamped to 0-255 after each application
+    Matrix(ColorMatrix),
+}
+
+/// A run of point operations compiled into a single per-pixel program
+#[derive(Debug, Clone)]
+pub struct FusedPointOps {
+    stages: Vec<PointStage>,
+}
+
+/// A unit of work produced by `plan`
+#[derive(Debug, Clone)]
+pub enum PlanStep {
+    /// Run the operation at this index on its own
+    Single(usize),
+    /// Run the operations in this index range as one fused pass
+    Fused(Range<usize>, FusedPointOps),
+}
+
+/// Whether an operation only depends on the pixel it writes
+pub fn is_point_operation(op: &FilterOperation) -> bool {
+    matches!(
+        op,
+        FilterOperation::Grayscale(_)
+            | FilterOperation::Brightness(_)
+            | FilterOperation::Contrast(_)
+            | FilterOperation::Invert
+            | FilterOperation::Sepia
+            | FilterOperation::Gamma(_)
+            | FilterOperation::ColorMatrix(_)
+    )
+}
+
+/// Group operations into steps, fusing every run of two or more point operations
+pub fn plan(operations: &[FilterOperation]) -> Vec<PlanStep> {
+    let mut steps = Vec::new();
+    let mut index = 0;
+
+    while index < operations.len() {
+        let run_end = operations[index..]
+            .iter()
+            .position(|op| !is_point_operation(op))
+            .map_or(operations.len(), |offset| index + offset);
+
+        if run_end - index >= 2 {
+            let fused = FusedPointOps::from_operations(&operations[index..run_end])
+                .expect("run contains only point operations");
+            steps.push(PlanStep::Fused(index..run_end, fused));
+            index = run_end;
+        } else {
+            steps.push(PlanStep::Single(index));
+            index += 1;
+        }
+    }
+
+    steps
+}
+
+impl FusedPointOps {
+    /// Compile point operations, or `None` if any operation is not a point operation
+    pub fn from_operations(operations: &[FilterOperation]) -> Option<Self> {
+        let mut fused = FusedPointOps { stages: Vec::new() };
+
+        for op in operations {
+            match op {
+                // Same weights, so the same result as `filters::grayscale_with`
+                FilterOperation::Grayscale(mode) => fused.push_matrix(mode.color_matrix()),
+                FilterOperation::Sepia => fused.push_matrix(SEPIA_COLOR_MATRIX),
+                FilterOperation::ColorMatrix(matrix) => fused.push_matrix(*matrix),
+                _ => fused.push_lut(op.to_lut()?),
+            }
+        }
+
+        Some(fused)
+    }
+
+    /// Number of passes over each pixel's channels (not over memory)
+    pub fn stage_count(&self) -> usize {
+        self.stages.len()
+    }
+
+    /// Apply the program to every pixel in one parallel pass
+    pub fn apply(&self, image: &RgbaImage) -> RgbaImage {
+        let mut result = image.clone();
+        self.apply_in_place(&mut result);
+        result
+    }
+
+    /// Apply the program in place without allocating a new image
+    pub fn apply_in_place(&self, image: &mut RgbaImage) {
+        let row_len = (image.width() as usize * 4).max(4);
+        let pixels: &mut [u8] = image;
+        pixels.par_chunks_mut(row_len).for_each(|row| {
+            for pixel in row.chunks_exact_mut(4) {
+                self.apply_pixel(pixel);
+            }
+        });
+    }
+
+    #[inline]
+    fn apply_pixel(&self, pixel: &mut [u8]) {
+        for stage in &self.stages {
+            match stage {
+                PointStage::Lut(lut) => lut.map_pixel(pixel),
+                PointStage::Matrix(matrix) => filters::apply_color_matrix(pixel, matrix),
+            }
+        }
+    }
+
+    fn push_matrix(&mut self, matrix: ColorMatrix) {
+        self.stages.push(PointStage::Matrix(matrix));
+    }
+
+    // Adjacent tables are composed so any number of them costs a single lookup
+    fn push_lut(&mut self, lut: Lut256) {
+        if let Some(PointStage::Lut(last)) = self.stages.last_mut() {
+            **last = last.then(&lut);
+        } else {
+            self.stages.push(PointStage::Lut(Box::new(lut)));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{filters, GrayscaleMode};
+    use image::{ImageBuffer, Rgba};
+
+    fn create_test_image() -> RgbaImage {
+        ImageBuffer::from_fn(64, 48, |x, y| {
+            Rgba([
+                (x * 4 % 256) as u8,
+                (y * 5 % 256) as u8,
+                ((x + y) * 3 % 256) as u8,
+                (x * y % 256) as u8,
+            ])
+        })
+    }
+
+    fn run_sequentially(image: &RgbaImage, ops: &[FilterOperation]) -> RgbaImage {
+        ops.iter().fold(image.clone(), |img, op| match *op {
+            FilterOperation::Grayscale(mode) => filters::grayscale_with(&img, mode),
+            FilterOperation::Brightness(v) => filters::brightness(&img, v),
+            FilterOperation::Contrast(v) => filters::contrast(&img, v),
+            FilterOperation::Invert => filters::invert(&img),
+            FilterOperation::Sepia => filters::sepia(&img),
+            FilterOperation::Gamma(v) => filters::gamma(&img, v),
+            FilterOperation::ColorMatrix(m) => filters::color_matrix(&img, m),
+            _ => unreachable!(),
+        })
+    }
+
+    #[test]
+    fn test_fused_matches_sequential() {
+        let image = create_test_image();
+        let ops = [
+            FilterOperation::Brightness(0.1),
+            FilterOperation::Contrast(1.3),
+            FilterOperation::Sepia,
+            FilterOperation::Gamma(1.8),
+            FilterOperation::Invert,
+            FilterOperation::Grayscale(GrayscaleMode::Bt709),
+        ];
+
+        let fused = FusedPointOps::from_operations(&ops).unwrap();
+        assert_eq!(fused.apply(&image), run_sequentially(&image, &ops));
+
+        for mode in [
+            GrayscaleMode::Bt601,
+            GrayscaleMode::Average,
+            GrayscaleMode::Green,
+        ] {
+            let ops = [
+                FilterOperation::Gamma(1.2),
+                FilterOperation::Grayscale(mode),
+            ];
+            let fused = FusedPointOps::from_operations(&ops).unwrap();
+            assert_eq!(fused.apply(&image), run_sequentially(&image, &ops));
+        }
+
+        // Matrices that also read and write alpha fuse too
+        let ops = [
+            FilterOperation::ColorMatrix([
+                [0.5, 0.2, 0.0, 0.1, 0.05],
+                [0.0, 1.1, 0.0, 0.0, -0.1],
+                [0.3, 0.0, 0.9, 0.0, 0.0],
+                [0.0, 0.0, 0.0, 0.8, 0.1],
+            ]),
+            FilterOperation::Contrast(1.2),
+            FilterOperation::Sepia,
+        ];
+        let fused = FusedPointOps::from_operations(&ops).unwrap();
+        assert_eq!(fused.apply(&image), run_sequentially(&image, &ops));
+    }
+
+    #[test]
+    fn test_adjacent_curves_compose() {
+        let ops = [
+            FilterOperation::Brightness(0.2),
+            FilterOperation::Contrast(0.8),
+            FilterOperation::Invert,
+            FilterOperation::Gamma(0.5),
+        ];
+        let fused = FusedPointOps::from_operations(&ops).unwrap();
+        assert_eq!(fused.stage_count(), 1);
+    }
+
+    #[test]
+    fn test_plan_groups_point_runs() {
+        let ops = [
+            FilterOperation::Invert,
+            FilterOperation::Blur(1.0),
+            FilterOperation::Brightness(0.1),
+            FilterOperation::Contrast(1.1),
+            FilterOperation::sharpen(),
+        ];
+        let steps = plan(&ops);
+
+        assert_eq!(steps.len(), 4);
+        assert!(matches!(steps[0], PlanStep::Single(0)));
+        assert!(matches!(steps[1], PlanStep::Single(1)));
+        assert!(matches!(&steps[2], PlanStep::Fused(range, _) if *range == (2..4)));
+        assert!(matches!(steps[3], PlanStep::Single(4)));
+    }
+}
@@ -0,0 +1,200 @@
+//! Rasterizing PDF pages
+//!
+//! Pages are rendered with [PDFium](https://pdfium.googlesource.com/pdfium/)
+//! through `pdfium-render`, which loads the PDFium shared library at runtime:
+//! from `PdfOptions::library_path`, or else from the system library path.
+//! Prebuilt libraries are published at
+//! <https://github.com/bblanchon/pdfium-binaries>.
+
+use crate::{rgba_len, ImagePipeline, PipelineError, Result};
+use image::error::{DecodingError, ImageFormatHint};
+use image::{ImageError, Rgba, RgbaImage};
+use pdfium_render::prelude::*;
+use std::path::PathBuf;
+
+/// PDF points per inch
+const POINTS_PER_INCH: f32 = 72.0;
+
+/// How `ImagePipeline::load_pdf_page` renders a page
+#[derive(Debug, Clone, PartialEq)]
+pub struct PdfOptions {
+    /// Output resolution in pixels per inch
+    pub dpi: f32,
+    /// Color behind the page content; PDF pages are usually drawn on white
+    pub background: Rgba<u8>,
+    /// Password for encrypted documents
+    pub password: Option<String>,
+    /// Directory containing the PDFium library; `None` uses the system path
+    pub library_path: Option<PathBuf>,
+}
+
+impl Default for PdfOptions {
+    fn default() -> Self {
+        Self {
+            dpi: 150.0,
+            background: Rgba([255, 255, 255, 255]),
+            password: None,
+            library_path: None,
+        }
+    }
+}
+
+impl PdfOptions {
+    fn pdfium(&self) -> Result<Pdfium> {
+        let bindings = match &self.library_path {
+            Some(dir) => Pdfium::bind_to_library(Pdfium::pdfium_platform_library_name_at_path(dir)),
+            None => Pdfium::bind_to_system_library(),
+        }
+        .map_err(|e| {
+            PipelineError::ProcessingError(format!("PDFium library not available: {}", e))
+        })?;
+        Ok(Pdfium::new(bindings))
+    }
+}
+
+impl ImagePipeline {
+    /// Number of pages in a PDF document
+    pub fn pdf_page_count(bytes: &[u8], options: &PdfOptions) -> Result<usize> {
+        let pdfium = options.pdfium()?;
+        let document = pdfium
+            .load_pdf_from_byte_slice(bytes, options.password.as_deref())
+            .map_err(pdf_error)?;
+        Ok(document.pages().len() as usize)
+    }
+
+    /// Render page `index` (0-based) of a PDF document at `options.dpi`
+    ///
+    /// Fails with `PipelineError::ResourceLimit` before rendering when the page
+    /// would exceed `MAX_DIMENSION` at that resolution.
+    pub fn load_pdf_page(bytes: &[u8], index: usize, options: &PdfOptions) -> Result<RgbaImage> {
+        if !(options.dpi.is_finite() && options.dpi > 0.0) {
+            return Err(PipelineError::InvalidParameter(format!(
+                "PDF dpi must be positive, got {}",
+                options.dpi
+            )));
+        }
+
+        let pdfium = options.pdfium()?;
+        let document = pdfium
+            .load_pdf_from_byte_slice(bytes, options.password.as_deref())
+            .map_err(pdf_error)?;
+        let pages = document.pages();
+        let count = pages.len() as usize;
+        if index >= count {
+            return Err(PipelineError::InvalidParameter(format!(
+                "page {} out of range for a document with {} pages",
+                index, count
+            )));
+        }
+        let page = pages.get(index as PdfPageIndex).map_err(pdf_error)?;
+
+        let scale = options.dpi / POINTS_PER_INCH;
+        let width = (page.width().value * scale).round().max(1.0) as u32;
+        let height = (page.height().value * scale).round().max(1.0) as u32;
+        rgba_len(width, height)?;
+
+        let [r, g, b, a] = options.background.0;
+        let config = PdfRenderConfig::new()
+            .scale_page_by_factor(scale)
+            .set_clear_color(PdfColor::new(r, g, b, a));
+        let bitmap = page.render_with_config(&config).map_err(pdf_error)?;
+        RgbaImage::from_raw(
+            bitmap.width() as u32,
+            bitmap.height() as u32,
+            bitmap.as_rgba_bytes(),
+        )
+        .ok_or_else(|| PipelineError::ProcessingError("unexpected PDFium bitmap size".into()))
+    }
+}
+
+fn pdf_error(e: PdfiumError) -> PipelineError {
+    PipelineError::ImageError(ImageError::Decoding(DecodingError::new(
+        ImageFormatHint::Name("PDF".into()),
+        e,
+    )))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // One 72x36 pt page with a red square in the left half
+    fn sample_pdf() -> Vec<u8> {
+        let content = "1 0 0 rg 0 0 36 36 re f";
+        let objects = [
+            "<< /Type /Catalog /Pages 2 0 R >>".to_string(),
+            "<< /Type /Pages /Kids [3 0 R] /Count 1 >>".to_string(),
+            "<< /Type /Page /Parent 2 0 R /MediaBox [0 0 72 36] /Contents 4 0 R >>".to_string(),
+            format!(
+                "<< /Length {} >>\nstream\n{}\nendstream",
+                content.len(),
+                content
+            ),
+        ];
+        let mut pdf = b"%PDF-1.4\n".to_vec();
+        let mut offsets = Vec::new();
+        for (i, object) in objects.iter().enumerate() {
+            offsets.push(pdf.len());
+            pdf.extend_from_slice(format!("{} 0 obj\n{}\nendobj\n", i + 1, object).as_bytes());
+        }
+        let xref = pdf.len();
+        pdf.extend_from_slice(
+            format!("xref\n0 {}\n0000000000 65535 f \n", objects.len() + 1).as_bytes(),
+        );
+        for offset in offsets {
+            pdf.extend_from_slice(format!("{:010} 00000 n \n", offset).as_bytes());
+        }
+        pdf.extend_from_slice(
+            format!(
+                "trailer\n<< /Size {} /Root 1 0 R >>\nstartxref\n{}\n%%EOF\n",
+                objects.len() + 1,
+                xref
+            )
+            .as_bytes(),
+        );
+        pdf
+    }
+
+    #[test]
+    fn test_missing_library() {
+        let options = PdfOptions {
+            library_path: Some(PathBuf::from("/nonexistent/pdfium")),
+            ..PdfOptions::default()
+        };
+        let err = ImagePipeline::load_pdf_page(&sample_pdf(), 0, &options).unwrap_err();
+        assert!(matches!(err, PipelineError::ProcessingError(_)), "{}", err);
+
+        let options = PdfOptions {
+            dpi: 0.0,
+            ..PdfOptions::default()
+        };
+        assert!(matches!(
+            ImagePipeline::load_pdf_page(&sample_pdf(), 0, &options),
+            Err(PipelineError::InvalidParameter(_))
+        ));
+    }
+
+    #[test]
+    fn test_render_page() {
+        // Only runs where PDFium is installed
+        if Pdfium::bind_to_system_library().is_err() {
+            return;
+        }
+        let pdf = sample_pdf();
+        let options = PdfOptions {
+            dpi: 144.0,
+            ..PdfOptions::default()
+        };
+        assert_eq!(ImagePipeline::pdf_page_count(&pdf, &options).unwrap(), 1);
+
+        let page = ImagePipeline::load_pdf_page(&pdf, 0, &options).unwrap();
+        assert_eq!(page.dimensions(), (144, 72));
+        assert_eq!(*page.get_pixel(20, 36), Rgba([255, 0, 0, 255]));
+        assert_eq!(*page.get_pixel(120, 36), Rgba([255, 255, 255, 255]));
+        assert!(matches!(
+            ImagePipeline::load_pdf_page(&pdf, 1, &options),
+            Err(PipelineError::InvalidParameter(_))
+        ));
+        assert!(ImagePipeline::load_pdf_page(b"%PDF-garbage", 0, &options).is_err());
+    }
+}
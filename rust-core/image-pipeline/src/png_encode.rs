@@ -0,0 +1,486 @@
+//! PNG writing: Adam7 interlacing and lossless size optimization
+//!
+//! The png crate encodes the common case (RGBA, not interlaced) quickly.
+//! Everything else goes through `Scanlines`: the image is first reduced to the
+//! smallest color type that represents it exactly (grayscale, RGB, or a
+//! palette of 1-8 bits when it has at most 256 colors), then filtered and
+//! compressed here, trying several filter strategies at higher efforts.
+
+use crate::encode::{EncodeOptions, PngEffort, PngFilter};
+use crate::{PipelineError, Result};
+use image::error::{EncodingError, ImageFormatHint};
+use image::{ImageError, ImageFormat, RgbaImage};
+use std::collections::HashMap;
+use std::io::Write;
+
+/// Adam7 passes as (x offset, y offset, x step, y step)
+const ADAM7_PASSES: [(u32, u32, u32, u32); 7] = [
+    (0, 0, 8, 8),
+    (4, 0, 8, 8),
+    (0, 4, 4, 8),
+    (2, 0, 4, 4),
+    (0, 2, 2, 4),
+    (1, 0, 2, 2),
+    (0, 1, 1, 2),
+];
+
+/// Every filter strategy, tried in turn from `PngEffort::Balanced` up
+const ALL_FILTERS: [PngFilter; 6] = [
+    PngFilter::None,
+    PngFilter::Sub,
+    PngFilter::Up,
+    PngFilter::Average,
+    PngFilter::Paeth,
+    PngFilter::Adaptive,
+];
+
+/// Pixel data in the PNG color type it will be written as
+struct Scanlines {
+    width: u32,
+    height: u32,
+    color: png::ColorType,
+    /// Bits per sample: 8, or 1/2/4 for small palettes
+    depth: u8,
+    channels: usize,
+    /// One byte per sample, unpacked
+    samples: Vec<u8>,
+    palette: Vec<u8>,
+    trns: Vec<u8>,
+}
+
+pub(crate) fn encode(image: &RgbaImage, options: &EncodeOptions) -> Result<Vec<u8>> {
+    if options.png_effort == PngEffort::None && !options.interlaced {
+        return encode_direct(image, options);
+    }
+
+    let scanlines = if options.png_effort == PngEffort::None {
+        Scanlines::rgba(image)
+    } else {
+        Scanlines::reduce(image)
+    };
+    let level = options.png_compression as u32;
+    let filtered = match options.png_effort {
+        // Stored output gains nothing from filtering
+        _ if level == 0 => scanlines.filter(options.interlaced, PngFilter::None),
+        PngEffort::None | PngEffort::Fast => {
+            scanlines.filter(options.interlaced, options.png_filter)
+        }
+        PngEffort::Balanced | PngEffort::Max => {
+            // Keep the filter strategy that compresses best at this level
+            let mut best: Option<(usize, Vec<u8>)> = None;
+            for filter in ALL_FILTERS {
+                let filtered = scanlines.filter(options.interlaced, filter);
+                let size = deflate(&filtered, level)?.len();
+                if best.as_ref().is_none_or(|(best_size, _)| size < *best_size) {
+                    best = Some((size, filtered));
+                }
+            }
+            best.map(|(_, filtered)| filtered).unwrap_or_default()
+        }
+    };
+    let compressed = match options.png_effort {
+        PngEffort::Max if level > 0 => deflate_max(&filtered)?,
+        _ => deflate(&filtered, level)?,
+    };
+    scanlines.write(options.interlaced, &compressed)
+}
+
+// Non-interlaced RGBA through the png crate's own filtering and compression
+fn encode_direct(image: &RgbaImage, options: &EncodeOptions) -> Result<Vec<u8>> {
+    let mut buffer = Vec::new();
+    let mut encoder = png::Encoder::new(&mut buffer, image.width(), image.height());
+    encoder.set_color(png::ColorType::Rgba);
+    encoder.set_depth(png::BitDepth::Eight);
+    encoder.set_deflate_compression(match options.png_compression {
+        0 => png::DeflateCompression::NoCompression,
+        level => png::DeflateCompression::Level(level),
+    });
+    encoder.set_filter(match options.png_filter {
+        PngFilter::None => png::Filter::NoFilter,
+        PngFilter::Sub => png::Filter::Sub,
+        PngFilter::Up => png::Filter::Up,
+        PngFilter::Average => png::Filter::Avg,
+        PngFilter::Paeth => png::Filter::Paeth,
+        PngFilter::Adaptive => png::Filter::Adaptive,
+    });
+    let mut writer = encoder.write_header().map_err(png_error)?;
+    writer.write_image_data(image.as_raw()).map_err(png_error)?;
+    writer.finish().map_err(png_error)?;
+    Ok(buffer)
+}
+
+fn deflate(data: &[u8], level: u32) -> Result<Vec<u8>> {
+    let mut encoder = flate2::write::ZlibEncoder::new(Vec::new(), flate2::Compression::new(level));
+    encoder.write_all(data)?;
+    Ok(encoder.finish()?)
+}
+
+#[cfg(feature = "zopfli")]
+fn deflate_max(data: &[u8]) -> Result<Vec<u8>> {
+    let mut compressed = Vec::new();
+    zopfli::compress(
+        zopfli::Options::default(),
+        zopfli::Format::Zlib,
+        data,
+        &mut compressed,
+    )?;
+    Ok(compressed)
+}
+
+#[cfg(not(feature = "zopfli"))]
+fn deflate_max(data: &[u8]) -> Result<Vec<u8>> {
+    deflate(data, 9)
+}
+
+impl Scanlines {
+    fn rgba(image: &RgbaImage) -> Self {
+        Self {
+            width: image.width(),
+            height: image.height(),
+            color: png::ColorType::Rgba,
+            depth: 8,
+            channels: 4,
+            samples: image.as_raw().clone(),
+            palette: Vec::new(),
+            trns: Vec::new(),
+        }
+    }
+
+    // Smallest exact representation of `image`
+    fn reduce(image: &RgbaImage) -> Self {
+        let opaque = image.pixels().all(|p| p[3] == 255);
+        let gray = image.pixels().all(|p| p[0] == p[1] && p[1] == p[2]);
+
+        let mut colors: Vec<[u8; 4]> = Vec::new();
+        let mut seen = HashMap::new();
+        for p in image.pixels() {
+            if seen.len() > 256 {
+                break;
+            }
+            seen.entry(p.0).or_insert_with(|| colors.push(p.0));
+        }
+
+        // 8-bit grayscale needs no PLTE chunk, so a palette only pays off
+        // when it also lowers the bit depth
+        let use_palette = colors.len() <= 256 && !(opaque && gray && colors.len() > 16);
+        if use_palette {
+            return Self::indexed(image, colors);
+        }
+
+        let (color, channels): (_, &[usize]) = match (opaque, gray) {
+            (true, true) => (png::ColorType::Grayscale, &[0]),
+            (true, false) => (png::ColorType::Rgb, &[0, 1, 2]),
+            (false, true) => (png::ColorType::GrayscaleAlpha, &[0, 3]),
+            (false, false) => return Self::rgba(image),
+        };
+        let samples = image
+            .pixels()
+            .flat_map(|p| channels.iter().map(move |&c| p[c]))
+            .collect();
+        Self {
+            width: image.width(),
+            height: image.height(),
+            color,
+            depth: 8,
+            channels: channels.len(),
+            samples,
+            palette: Vec::new(),
+            trns: Vec::new(),
+        }
+    }
+
+    fn indexed(image: &RgbaImage, mut colors: Vec<[u8; 4]>) -> Self {
+        // Translucent entries first keep the tRNS chunk short
+        colors.sort_by_key(|c| (c[3] == 255, *c));
+        let index: HashMap<[u8; 4], u8> = colors
+            .iter()
+            .enumerate()
+            .map(|(i, &c)| (c, i as u8))
+            .collect();
+        let depth = match colors.len() {
+            0..=2 => 1,
+            3..=4 => 2,
+            5..=16 => 4,
+            _ => 8,
+        };
+        let translucent = colors.iter().take_while(|c| c[3] < 255).count();
+
+        Self {
+            width: image.width(),
+            height: image.height(),
+            color: png::ColorType::Indexed,
+            depth,
+            channels: 1,
+            samples: image.pixels().map(|p| index[&p.0]).collect(),
+            palette: colors.iter().flat_map(|c| [c[0], c[1], c[2]]).collect(),
+            trns: colors[..translucent].iter().map(|c| c[3]).collect(),
+        }
+    }
+
+    // Bytes per complete pixel, at least one, as filters use it
+    fn filter_bpp(&self) -> usize {
+        (self.channels * self.depth as usize).div_ceil(8)
+    }
+
+    // Packed row of the pixels at `x0, x0 + dx, ...` in row `y`
+    fn pack_row(&self, y: u32, x0: u32, dx: u32, out: &mut Vec<u8>) {
+        out.clear();
+        let row = &self.samples[y as usize * self.width as usize * self.channels..]
+            [..self.width as usize * self.channels];
+        let pixels = (x0..self.width).step_by(dx as usize);
+        if self.depth == 8 {
+            for x in pixels {
+                let start = x as usize * self.channels;
+                out.extend_from_slice(&row[start..start + self.channels]);
+            }
+            return;
+        }
+
+        let per_byte = 8 / self.depth as usize;
+        let mut byte = 0u8;
+        let mut filled = 0;
+        for x in pixels {
+            byte |= row[x as usize] << (8 - self.depth as usize * (filled + 1));
+            filled += 1;
+            if filled == per_byte {
+                out.push(byte);
+                byte = 0;
+                filled = 0;
+            }
+        }
+        if filled > 0 {
+            out.push(byte);
+        }
+    }
+
+    // Filtered scanlines of the whole image, or of every non-empty Adam7
+    // pass in order
+    fn filter(&self, interlaced: bool, filter: PngFilter) -> Vec<u8> {
+        let passes: &[(u32, u32, u32, u32)] = if interlaced {
+            &ADAM7_PASSES
+        } else {
+            &[(0, 0, 1, 1)]
+        };
+        let bpp = self.filter_bpp();
+        let mut out = Vec::new();
+        let (mut line, mut previous) = (Vec::new(), Vec::new());
+        let mut filtered = Vec::new();
+
+        for &(x0, y0, dx, dy) in passes {
+            if self.width <= x0 || self.height <= y0 {
+                continue;
+            }
+            previous.clear();
+            for y in (y0..self.height).step_by(dy as usize) {
+                self.pack_row(y, x0, dx, &mut line);
+                previous.resize(line.len(), 0);
+                filtered.resize(line.len() + 1, 0);
+                filter_row(filter, bpp, &previous, &line, &mut filtered);
+                out.extend_from_slice(&filtered);
+                std::mem::swap(&mut previous, &mut line);
+            }
+        }
+        out
+    }
+
+    // PNG file with a single IDAT chunk holding `compressed`
+    fn write(&self, interlaced: bool, compressed: &[u8]) -> Result<Vec<u8>> {
+        let mut info = png::Info::with_size(self.width, self.height);
+        info.color_type = self.color;
+        info.bit_depth = match self.depth {
+            1 => png::BitDepth::One,
+            2 => png::BitDepth::Two,
+            4 => png::BitDepth::Four,
+            _ => png::BitDepth::Eight,
+        };
+        info.interlaced = interlaced;
+        if !self.palette.is_empty() {
+            info.palette = Some(self.palette.as_slice().into());
+        }
+        if !self.trns.is_empty() {
+            info.trns = Some(self.trns.as_slice().into());
+        }
+
+        let mut buffer = Vec::new();
+        {
+            let encoder = png::Encoder::with_info(&mut buffer, info).map_err(png_error)?;
+            let mut writer = encoder.write_header().map_err(png_error)?;
+            writer
+                .write_chunk(png::chunk::IDAT, compressed)
+                .map_err(png_error)?;
+            writer.finish().map_err(png_error)?;
+        }
+        Ok(buffer)
+    }
+}
+
+fn png_error(e: png::EncodingError) -> PipelineError {
+    match e {
+        png::EncodingError::IoError(e) => PipelineError::IoError(e),
+        e => PipelineError::ImageError(ImageError::Encoding(EncodingError::new(
+            ImageFormatHint::Exact(ImageFormat::Png),
+            e,
+        ))),
+    }
+}
+
+// Write the filter type byte followed by the filtered scanline
+fn filter_row(filter: PngFilter, bpp: usize, previous: &[u8], line: &[u8], out: &mut [u8]) {
+    if filter == PngFilter::Adaptive {
+        // Minimum sum of absolute differences, as libpng does
+        let cost = |out: &[u8]| -> u64 {
+            out[1..]
+                .iter()
+                .map(|&b| (b as i8).unsigned_abs() as u64)
+                .sum()
+        };
+        let mut best = (u64::MAX, PngFilter::None);
+        for candidate in &ALL_FILTERS[..5] {
+            filter_row(*candidate, bpp, previous, line, out);
+            let candidate_cost = cost(out);
+            if candidate_cost < best.0 {
+                best = (candidate_cost, *candidate);
+            }
+        }
+        filter_row(best.1, bpp, previous, line, out);
+        return;
+    }
+
+    out[0] = match filter {
+        PngFilter::None => 0,
+        PngFilter::Sub => 1,
+        PngFilter::Up => 2,
+        PngFilter::Average => 3,
+        PngFilter::Paeth | PngFilter::Adaptive => 4,
+    };
+    for i in 0..line.len() {
+        let left = if i >= bpp { line[i - bpp] } else { 0 };
+        let up = previous[i];
+        let up_left = if i >= bpp { previous[i - bpp] } else { 0 };
+        let predicted = match filter {
+            PngFilter::None => 0,
+            PngFilter::Sub => left,
+            PngFilter::Up => up,
+            PngFilter::Average => ((left as u16 + up as u16) / 2) as u8,
+            PngFilter::Paeth | PngFilter::Adaptive => paeth(left, up, up_left),
+        };
+        out[i + 1] = line[i].wrapping_sub(predicted);
+    }
+}
+
+fn paeth(a: u8, b: u8, c: u8) -> u8 {
+    let p = a as i16 + b as i16 - c as i16;
+    let (pa, pb, pc) = (
+        (p - a as i16).abs(),
+        (p - b as i16).abs(),
+        (p - c as i16).abs(),
+    );
+    if pa <= pb && pa <= pc {
+        a
+    } else if pb <= pc {
+        b
+    } else {
+        c
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ImagePipeline, OutputFormat};
+    use image::Rgba;
+    use std::io::Cursor;
+
+    fn optimized(image: &RgbaImage, png_effort: PngEffort, interlaced: bool) -> Vec<u8> {
+        let options = EncodeOptions {
+            png_effort,
+            interlaced,
+            ..EncodeOptions::default()
+        };
+        ImagePipeline::encode_with_options(image, OutputFormat::Png, &options).unwrap()
+    }
+
+    fn color_type(bytes: &[u8]) -> (png::ColorType, png::BitDepth) {
+        let reader = png::Decoder::new(Cursor::new(bytes)).read_info().unwrap();
+        (reader.info().color_type, reader.info().bit_depth)
+    }
+
+    #[test]
+    fn test_reduction_is_lossless() {
+        let images = [
+            // Opaque photo-like RGB
+            RgbaImage::from_fn(33, 17, |x, y| {
+                Rgba([(x * 7) as u8, (y * 13) as u8, (x * y) as u8, 255])
+            }),
+            // Gray with a gradient alpha
+            RgbaImage::from_fn(33, 17, |x, y| {
+                let v = (x * 5 + y) as u8;
+                Rgba([v, v, v, (x * 7) as u8])
+            }),
+            // Three colors, one translucent
+            RgbaImage::from_fn(33, 17, |x, y| match (x + y) % 3 {
+                0 => Rgba([255, 0, 0, 255]),
+                1 => Rgba([0, 0, 255, 128]),
+                _ => Rgba([0, 0, 0, 0]),
+            }),
+            // 200 colors, 8-bit palette
+            RgbaImage::from_fn(40, 40, |x, y| Rgba([((x * 40 + y) % 200) as u8, 9, 0, 255])),
+            RgbaImage::new(1, 1),
+        ];
+        for image in &images {
+            for effort in [PngEffort::Fast, PngEffort::Balanced, PngEffort::Max] {
+                for interlaced in [false, true] {
+                    let bytes = optimized(image, effort, interlaced);
+                    let decoded = ImagePipeline::load_from_bytes(&bytes).unwrap();
+                    assert_eq!(&decoded, image, "{:?} {:?}", effort, color_type(&bytes));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_chooses_smallest_color_type() {
+        let gray = RgbaImage::from_fn(64, 64, |x, y| {
+            let v = (x * 3 + y * 5) as u8;
+            Rgba([v, v, v, 255])
+        });
+        assert_eq!(
+            color_type(&optimized(&gray, PngEffort::Fast, false)),
+            (png::ColorType::Grayscale, png::BitDepth::Eight)
+        );
+        let two_colors = RgbaImage::from_fn(64, 64, |x, _| {
+            Rgba(if x % 2 == 0 {
+                [0, 0, 0, 255]
+            } else {
+                [255, 200, 0, 255]
+            })
+        });
+        assert_eq!(
+            color_type(&optimized(&two_colors, PngEffort::Fast, false)),
+            (png::ColorType::Indexed, png::BitDepth::One)
+        );
+        let rgb = RgbaImage::from_fn(64, 64, |x, y| Rgba([x as u8 * 4, y as u8 * 4, 7, 255]));
+        assert_eq!(
+            color_type(&optimized(&rgb, PngEffort::Fast, false)).0,
+            png::ColorType::Rgb
+        );
+    }
+
+    #[test]
+    fn test_effort_shrinks_output() {
+        let image = RgbaImage::from_fn(96, 96, |x, y| {
+            Rgba([(x * 2) as u8, (y * 2) as u8, ((x + y) / 2) as u8, 255])
+        });
+        let plain = ImagePipeline::encode_to_png(&image).unwrap();
+        let fast = optimized(&image, PngEffort::Fast, false);
+        let balanced = optimized(&image, PngEffort::Balanced, false);
+        assert!(
+            fast.len() < plain.len(),
+            "{} vs {}",
+            fast.len(),
+            plain.len()
+        );
+        assert!(balanced.len() <= fast.len());
+        assert!(optimized(&image, PngEffort::Max, false).len() <= balanced.len());
+    }
+}
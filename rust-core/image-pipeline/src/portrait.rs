@@ -0,0 +1,168 @@
+//! Portrait mode: blur the background, keep the subject sharp
+//!
+//! The subject comes from a mask (a segmentation, a chroma-key matte, or a
+//! rectangle) or, without one, from a depth-from-defocus guess: photos with a
+//! shallow depth of field already have a sharp subject in front of a softer
+//! background, so regions with fine detail are taken to be in front. The guess
+//! is weighted towards the center, where portraits usually place the subject.
+
+use crate::filters::blur_cancellable;
+use crate::{CancellationToken, PipelineError, Region, Result};
+use image::imageops::{self, FilterType};
+use image::{GrayImage, Luma, RgbaImage};
+use std::sync::Arc;
+
+/// Longest side of the downscaled copy used to estimate the subject
+const ANALYSIS_SIZE: u32 = 256;
+
+/// Blur everything outside the subject with `sigma`
+/// mask: subject weights (255 = keep sharp), or `None` to estimate them
+/// feather: sigma in pixels by which the mask edge is softened (0 = hard edge)
+pub fn portrait_blur(
+    image: &RgbaImage,
+    mask: Option<&Region>,
+    sigma: f32,
+    feather: f32,
+) -> Result<RgbaImage> {
+    portrait_blur_cancellable(image, mask, sigma, feather, &CancellationToken::new())
+}
+
+/// `portrait_blur` that stops early with `PipelineError::Cancelled` once `token` is cancelled
+pub fn portrait_blur_cancellable(
+    image: &RgbaImage,
+    mask: Option<&Region>,
+    sigma: f32,
+    feather: f32,
+    token: &CancellationToken,
+) -> Result<RgbaImage> {
+    let mut subject = match mask {
+        Some(region) => subject_from_region(region, image.width(), image.height())?,
+        None => estimate_subject(image),
+    };
+    if feather > 0.0 {
+        subject = imageops::blur(&subject, feather);
+    }
+    let background = blur_cancellable(image, sigma, token)?;
+    Region::Mask(Arc::new(subject)).blend(&background, image)
+}
+
+/// Guess which parts of `image` are the in-focus subject (255) and which the background (0)
+pub fn estimate_subject(image: &RgbaImage) -> GrayImage {
+    let (width, height) = image.dimensions();
+    if width == 0 || height == 0 {
+        return GrayImage::new(width, height);
+    }
+    let scale = (ANALYSIS_SIZE as f64 / width.max(height) as f64).min(1.0);
+    let w = ((width as f64 * scale).round() as u32).max(1);
+    let h = ((height as f64 * scale).round() as u32).max(1);
+    let luma = imageops::grayscale(&imageops::resize(image, w, h, FilterType::Triangle));
+
+    // Laplacian magnitude: defocus removes exactly this fine detail
+    let at = |x: i64, y: i64| {
+        luma[(
+            x.clamp(0, w as i64 - 1) as u32,
+            y.clamp(0, h as i64 - 1) as u32,
+        )][0] as i32
+    };
+    let detail = GrayImage::from_fn(w, h, |x, y| {
+        let (x, y) = (x as i64, y as i64);
+        let laplacian = 4 * at(x, y) - at(x - 1, y) - at(x + 1, y) - at(x, y - 1) - at(x, y + 1);
+        Luma([laplacian.unsigned_abs().min(255) as u8])
+    });
+
+    // Spread detail over whole regions so flat parts of the subject count too
+    let spread = imageops::blur(&detail, w.max(h) as f32 / 20.0);
+    let (cx, cy) = (w as f32 / 2.0, h as f32 / 2.0);
+    let radius = cx.hypot(cy);
+    let weighted: Vec<f32> = spread
+        .enumerate_pixels()
+        .map(|(x, y, p)| {
+            let r = (x as f32 + 0.5 - cx).hypot(y as f32 + 0.5 - cy) / radius;
+            p[0] as f32 * (1.0 - 0.5 * r * r)
+        })
+        .collect();
+    let mean = (weighted.iter().sum::<f32>() / weighted.len() as f32).max(f32::EPSILON);
+
+    // Clearly above average is subject, clearly below is background
+    let small = GrayImage::from_fn(w, h, |x, y| {
+        let v = weighted[(y * w + x) as usize] / mean;
+        Luma([((v - 0.75) * 2.0).clamp(0.0, 1.0).mul_add(255.0, 0.5) as u8])
+    });
+    imageops::resize(&small, width, height, FilterType::Triangle)
+}
+
+// Subject weights covering a `width` x `height` image
+fn subject_from_region(region: &Region, width: u32, height: u32) -> Result<GrayImage> {
+    match region {
+        Region::Rect {
+            x,
+            y,
+            width: w,
+            height: h,
+        } => Ok(GrayImage::from_fn(width, height, |px, py| {
+            let inside = px >= *x && px - x < *w && py >= *y && py - y < *h;
+            Luma([if inside { 255 } else { 0 }])
+        })),
+        Region::Mask(mask) => {
+            if mask.dimensions() != (width, height) {
+                return Err(PipelineError::InvalidParameter(format!(
+                    "mask is {}x{} but the image is {}x{}",
+                    mask.width(),
+                    mask.height(),
+                    width,
+                    height
+                )));
+            }
+            Ok(mask.as_ref().clone())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgba;
+
+    // Fine checkerboard subject in the middle of a smooth gradient
+    fn create_portrait() -> RgbaImage {
+        RgbaImage::from_fn(96, 64, |x, y| {
+            if (32..64).contains(&x) && (16..48).contains(&y) {
+                let v = if (x + y) % 2 == 0 { 40 } else { 220 };
+                Rgba([v, v, v, 255])
+            } else {
+                Rgba([(x * 2) as u8, 120, (y * 3) as u8, 255])
+            }
+        })
+    }
+
+    #[test]
+    fn test_mask_keeps_subject_sharp() {
+        let image = create_portrait();
+        let subject = Region::Rect {
+            x: 32,
+            y: 16,
+            width: 32,
+            height: 32,
+        };
+        let result = portrait_blur(&image, Some(&subject), 4.0, 0.0).unwrap();
+        assert_eq!(result[(40, 30)], image[(40, 30)]);
+        assert_ne!(result[(31, 30)], image[(31, 30)]);
+
+        // Feathering lets the blur reach a little into the subject
+        let feathered = portrait_blur(&image, Some(&subject), 4.0, 3.0).unwrap();
+        assert_ne!(feathered[(32, 30)], image[(32, 30)]);
+        assert_eq!(feathered[(48, 32)], image[(48, 32)]);
+
+        let wrong_size = Region::mask_from_raw(2, 2, &[255; 4]).unwrap();
+        assert!(portrait_blur(&image, Some(&wrong_size), 4.0, 0.0).is_err());
+    }
+
+    #[test]
+    fn test_estimate_finds_detailed_center() {
+        let subject = estimate_subject(&create_portrait());
+        assert_eq!(subject.dimensions(), (96, 64));
+        assert!(subject[(48, 32)][0] > 200);
+        assert!(subject[(4, 4)][0] < 50);
+        assert!(subject[(92, 60)][0] < 50);
+    }
+}
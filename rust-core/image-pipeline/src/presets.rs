@@ -0,0 +1,171 @@
+//! Named, versioned pipelines that can be saved and shared
+//!
+//! A preset is a spec document with a name:
+//!
+//! ```json
+//! {"name": "Vintage", "version": 1, "operations": [{"type": "sepia"}]}
+//! ```
+
+use crate::spec::{GrayscaleSpec, OperationSpec, PipelineSpec, SPEC_VERSION};
+use crate::{FilterOperation, PipelineError, Result, SHARPEN_AMOUNT, SHARPEN_RADIUS};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// A named list of operations
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Preset {
+    pub name: String,
+    /// Spec format version the operations are written in
+    pub version: u32,
+    #[serde(rename = "operations")]
+    pub ops: Vec<OperationSpec>,
+}
+
+impl Preset {
+    /// Save `operations` under `name`
+    pub fn new(name: impl Into<String>, operations: &[FilterOperation]) -> Self {
+        let spec = PipelineSpec::from_operations(operations);
+        Self {
+            name: name.into(),
+            version: spec.version,
+            ops: spec.operations,
+        }
+    }
+
+    /// Parse a preset, checking its version and operations like a spec
+    pub fn from_json(json: &str) -> Result<Self> {
+        let mut value: Value = serde_json::from_str(json)
+            .map_err(|e| PipelineError::InvalidSpec(format!("malformed preset: {}", e)))?;
+        let name = match value.as_object_mut().and_then(|obj| obj.remove("name")) {
+            Some(Value::String(name)) if !name.trim().is_empty() => name,
+            _ => {
+                return Err(PipelineError::InvalidSpec(
+                    "preset needs a non-empty \"name\"".into(),
+                ))
+            }
+        };
+        let spec = PipelineSpec::from_value(value)?;
+        Ok(Self {
+            name,
+            version: spec.version,
+            ops: spec.operations,
+        })
+    }
+
+    /// Serialize to JSON
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).expect("preset serialization cannot fail")
+    }
+
+    /// Validate all parameters and produce the executable operation list
+    pub fn compile(&self) -> Result<Vec<FilterOperation>> {
+        PipelineSpec {
+            version: self.version,
+            operations: self.ops.clone(),
+        }
+        .compile()
+    }
+}
+
+/// Presets that ship with the library
+pub fn builtin() -> Vec<Preset> {
+    let preset = |name: &str, ops: Vec<OperationSpec>| Preset {
+        name: name.to_string(),
+        version: SPEC_VERSION,
+        ops,
+    };
+    vec![
+        preset(
+            "Vintage",
+            vec![
+                OperationSpec::Sepia,
+                OperationSpec::Contrast { value: 0.85 },
+                OperationSpec::Brightness { value: 0.04 },
+                OperationSpec::Noise {
+                    amount: 0.06,
+                    monochrome: true,
+                    seed: 1970,
+                },
+            ],
+        ),
+        preset(
+            "B&W Punchy",
+            vec![
+                OperationSpec::Grayscale {
+                    mode: GrayscaleSpec::Bt709,
+                },
+                OperationSpec::Contrast { value: 1.45 },
+                OperationSpec::Gamma { value: 0.9 },
+            ],
+        ),
+        preset(
+            "Faded",
+            vec![
+                OperationSpec::Contrast { value: 0.75 },
+                OperationSpec::Brightness { value: 0.08 },
+                OperationSpec::Gamma { value: 1.1 },
+            ],
+        ),
+        preset(
+            "Crisp",
+            vec![
+                OperationSpec::Sharpen {
+                    amount: SHARPEN_AMOUNT,
+                    radius: SHARPEN_RADIUS,
+                    threshold: 0,
+                },
+                OperationSpec::Contrast { value: 1.15 },
+            ],
+        ),
+    ]
+}
+
+/// Find a built-in preset by name, ignoring case
+pub fn find_builtin(name: &str) -> Option<Preset> {
+    builtin()
+        .into_iter()
+        .find(|preset| preset.name.eq_ignore_ascii_case(name.trim()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let preset = Preset::new(
+            "Warm",
+            &[FilterOperation::Brightness(0.1), FilterOperation::Blur(1.5)],
+        );
+        let parsed = Preset::from_json(&preset.to_json()).unwrap();
+        assert_eq!(parsed, preset);
+        assert_eq!(parsed.compile().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn test_rejects_invalid_presets() {
+        for json in [
+            r#"{"operations": []}"#,
+            r#"{"name": " ", "operations": []}"#,
+            r#"[{"type": "grayscale"}]"#,
+            r#"{"name": "Future", "version": 99, "operations": []}"#,
+            r#"{"name": "Bad", "operations": [{"type": "blur"}]}"#,
+        ] {
+            assert!(Preset::from_json(json).is_err(), "{}", json);
+        }
+        let out_of_range = Preset::from_json(
+            r#"{"name": "Too bright", "operations": [{"type": "brightness", "value": 3}]}"#,
+        )
+        .unwrap();
+        assert!(out_of_range.compile().is_err());
+    }
+
+    #[test]
+    fn test_builtin_presets_compile() {
+        for preset in builtin() {
+            assert!(preset.compile().is_ok(), "{}", preset.name);
+        }
+        assert_eq!(find_builtin("b&w punchy").unwrap().name, "B&W Punchy");
+        assert!(find_builtin("missing").is_none());
+    }
+}
@@ -0,0 +1,322 @@
+//! Decoding camera RAW files into linear float images
+//!
+//! Sensor data is read with rawloader, which knows the formats and per-camera
+//! calibration of most DSLRs and mirrorless bodies (CR2, NEF, ARW, ORF, RAF,
+//! DNG and others). `load_raw` then develops it minimally: black and white
+//! level scaling, the white balance recorded by the camera, bilinear
+//! demosaicing and the camera-to-sRGB color matrix. The result is linear like
+//! `tonemap::load_hdr`, so it can be exposed and tone mapped with
+//! `tonemap::tonemap` before the 8-bit operations.
+
+use crate::{rgba_len, PipelineError, Result};
+use image::error::{DecodingError, ImageFormatHint};
+use image::metadata::Orientation;
+use image::{DynamicImage, ImageError, Rgba32FImage};
+use rawloader::{RawImage, RawImageData};
+use rayon::prelude::*;
+use std::panic::{self, AssertUnwindSafe};
+
+/// Linear sRGB (D65) from CIE XYZ
+const XYZ_TO_SRGB: [[f32; 3]; 3] = [
+    [3.240_454_2, -1.537_138_5, -0.498_531_4],
+    [-0.969_266, 1.876_010_8, 0.041_556],
+    [0.055_643_4, -0.204_025_9, 1.057_225_2],
+];
+
+/// Decode a camera RAW file into linear sRGB, with 1.0 at sensor saturation
+///
+/// The camera's orientation is applied. Malformed files, including ones that
+/// make the decoder panic, are reported as decoding errors.
+pub fn load_raw(bytes: &[u8]) -> Result<Rgba32FImage> {
+    let decoded = panic::catch_unwind(AssertUnwindSafe(|| {
+        rawloader::decode(&mut &bytes[..]).map_err(|e| decoding_error(e.to_string()))
+    }))
+    .unwrap_or_else(|_| Err(decoding_error("malformed RAW data".to_string())))?;
+    develop(&decoded)
+}
+
+fn develop(raw: &RawImage) -> Result<Rgba32FImage> {
+    let [top, right, bottom, left] = raw.crops;
+    let width = raw.width.saturating_sub(left + right);
+    let height = raw.height.saturating_sub(top + bottom);
+    if width == 0 || height == 0 || !matches!(raw.cpp, 1 | 3) {
+        return Err(decoding_error(format!(
+            "unsupported RAW layout: {}x{} with {} samples per pixel",
+            raw.width, raw.height, raw.cpp
+        )));
+    }
+    let expected = raw.width * raw.height * raw.cpp;
+    let samples = match &raw.data {
+        RawImageData::Integer(data) => data.len(),
+        RawImageData::Float(data) => data.len(),
+    };
+    if samples < expected {
+        return Err(decoding_error("truncated RAW data".to_string()));
+    }
+    let (width, height) = (width as u32, height as u32);
+    rgba_len(width, height)?;
+
+    let develop = Develop::new(raw);
+    let mut result = Rgba32FImage::new(width, height);
+    let row_len = width as usize * 4;
+    let pixels: &mut [f32] = &mut result;
+    pixels
+        .par_chunks_mut(row_len)
+        .enumerate()
+        .for_each(|(y, out)| {
+            for (x, pixel) in out.chunks_exact_mut(4).enumerate() {
+                let camera = develop.camera_rgb(raw, top + y, left + x);
+                let rgb = develop.to_srgb(camera);
+                pixel[..3].copy_from_slice(&rgb);
+                pixel[3] = 1.0;
+            }
+        });
+
+    let orientation =
+        Orientation::from_exif(raw.orientation.to_u16() as u8).unwrap_or(Orientation::NoTransforms);
+    if orientation == Orientation::NoTransforms {
+        return Ok(result);
+    }
+    let mut oriented = DynamicImage::ImageRgba32F(result);
+    oriented.apply_orientation(orientation);
+    Ok(oriented.into_rgba32f())
+}
+
+/// Per-file constants for turning sensor samples into linear sRGB
+struct Develop {
+    /// Subtracted from every sample, per CFA color (RGBE order)
+    black: [f32; 4],
+    /// Reciprocal of the range between black and saturation
+    scale: [f32; 4],
+    /// White balance multipliers relative to green
+    white_balance: [f32; 4],
+    /// Camera RGB to linear sRGB; rows sum to 1 so white stays neutral
+    matrix: [[f32; 3]; 3],
+    monochrome: bool,
+}
+
+impl Develop {
+    fn new(raw: &RawImage) -> Self {
+        let black = raw.blacklevels.map(f32::from);
+        let mut scale = [1.0; 4];
+        for c in 0..4 {
+            let range = raw.whitelevels[c] as f32 - black[c];
+            if range > 0.0 {
+                scale[c] = 1.0 / range;
+            }
+        }
+
+        // Some cameras record no white balance; fall back to daylight
+        let recorded = raw.wb_coeffs;
+        let coeffs = if recorded[..3].iter().all(|c| c.is_finite() && *c > 0.0) {
+            recorded
+        } else {
+            raw.neutralwb()
+        };
+        let green = if coeffs[1] > 0.0 { coeffs[1] } else { 1.0 };
+        let white_balance = coeffs.map(|c| {
+            if c.is_finite() && c > 0.0 {
+                c / green
+            } else {
+                1.0
+            }
+        });
+
+        Self {
+            black,
+            scale,
+            white_balance,
+            matrix: camera_to_srgb(raw),
+            monochrome: raw.is_monochrome(),
+        }
+    }
+
+    // White-balanced camera RGB at a sensor position, interpolating the
+    // colors the CFA did not sample there from the 3x3 neighbourhood
+    fn camera_rgb(&self, raw: &RawImage, row: usize, col: usize) -> [f32; 3] {
+        if raw.cpp == 3 {
+            return [0, 1, 2].map(|c| self.sample(raw, (row * raw.width + col) * 3 + c, c));
+        }
+        let index = row * raw.width + col;
+        if self.monochrome {
+            return [self.sample(raw, index, 0); 3];
+        }
+
+        let mut sum = [0.0f32; 3];
+        let mut count = [0u32; 3];
+        let own = cfa_channel(raw.cfa.color_at(row, col));
+        for dy in -1isize..=1 {
+            for dx in -1isize..=1 {
+                let (Some(y), Some(x)) = (
+                    row.checked_add_signed(dy).filter(|&y| y < raw.height),
+                    col.checked_add_signed(dx).filter(|&x| x < raw.width),
+                ) else {
+                    continue;
+                };
+                let color = raw.cfa.color_at(y, x);
+                let channel = cfa_channel(color);
+                // The sampled color is taken as is, not averaged with neighbours
+                if channel == own && (dx, dy) != (0, 0) {
+                    continue;
+                }
+                sum[channel] += self.sample(raw, y * raw.width + x, color);
+                count[channel] += 1;
+            }
+        }
+        [0, 1, 2].map(|c| {
+            if count[c] > 0 {
+                sum[c] / count[c] as f32
+            } else {
+                0.0
+            }
+        })
+    }
+
+    // Normalized, white-balanced sample; clipped where green saturates so
+    // blown highlights stay white instead of turning pink
+    fn sample(&self, raw: &RawImage, index: usize, color: usize) -> f32 {
+        let normalized = match &raw.data {
+            RawImageData::Integer(data) => {
+                ((data[index] as f32 - self.black[color]) * self.scale[color]).clamp(0.0, 1.0)
+            }
+            RawImageData::Float(data) => data[index].clamp(0.0, 1.0),
+        };
+        (normalized * self.white_balance[color]).min(1.0)
+    }
+
+    fn to_srgb(&self, camera: [f32; 3]) -> [f32; 3] {
+        if self.monochrome {
+            return camera;
+        }
+        self.matrix
+            .map(|row| (row[0] * camera[0] + row[1] * camera[1] + row[2] * camera[2]).max(0.0))
+    }
+}
+
+// rawloader numbers CFA colors R, G, B, E (emerald, a second green)
+fn cfa_channel(color: usize) -> usize {
+    match color {
+        0 => 0,
+        2 => 2,
+        _ => 1,
+    }
+}
+
+// XYZ_TO_SRGB * cam_to_xyz, with each row scaled to sum to 1; cameras
+// without a calibration matrix get the identity
+fn camera_to_srgb(raw: &RawImage) -> [[f32; 3]; 3] {
+    let identity = [[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]];
+    if raw.xyz_to_cam[..3].iter().flatten().all(|&v| v == 0.0) {
+        return identity;
+    }
+    let cam_to_xyz = raw.cam_to_xyz();
+    let mut matrix = [[0.0f32; 3]; 3];
+    for (i, row) in matrix.iter_mut().enumerate() {
+        for (j, value) in row.iter_mut().enumerate() {
+            *value = (0..3).map(|k| XYZ_TO_SRGB[i][k] * cam_to_xyz[k][j]).sum();
+        }
+        let sum: f32 = row.iter().sum();
+        if !sum.is_finite() || sum.abs() < 1e-6 {
+            return identity;
+        }
+        row.iter_mut().for_each(|v| *v /= sum);
+    }
+    matrix
+}
+
+fn decoding_error(message: String) -> PipelineError {
+    ImageError::Decoding(DecodingError::new(
+        ImageFormatHint::Name("RAW".into()),
+        message,
+    ))
+    .into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rawloader::{Orientation as RawOrientation, CFA};
+
+    // RGGB sensor with 12-bit samples over a black level of 256, showing a
+    // flat gray card lit so that it reads `levels` per color
+    fn bayer(width: usize, height: usize, levels: [u16; 3]) -> RawImage {
+        let cfa = CFA::new("RGGB");
+        let data = (0..width * height)
+            .map(|i| 256 + levels[cfa_channel(cfa.color_at(i / width, i % width))])
+            .collect();
+        RawImage {
+            make: "Test".into(),
+            model: "Sensor".into(),
+            clean_make: "Test".into(),
+            clean_model: "Sensor".into(),
+            width,
+            height,
+            cpp: 1,
+            wb_coeffs: [2.0, 1.0, 1.5, f32::NAN],
+            whitelevels: [4095; 4],
+            blacklevels: [256; 4],
+            xyz_to_cam: [[0.0; 3]; 4],
+            cfa,
+            crops: [0; 4],
+            blackareas: Vec::new(),
+            orientation: RawOrientation::Normal,
+            data: RawImageData::Integer(data),
+        }
+    }
+
+    #[test]
+    fn test_white_balance_and_demosaic() {
+        // The camera's white balance makes the card neutral
+        let image = develop(&bayer(8, 6, [600, 1200, 800])).unwrap();
+        assert_eq!(image.dimensions(), (8, 6));
+        let expected = 1200.0 / 3839.0;
+        for pixel in image.pixels() {
+            for &channel in &pixel.0[..3] {
+                assert!((channel - expected).abs() < 1e-3, "{:?}", pixel);
+            }
+            assert_eq!(pixel[3], 1.0);
+        }
+    }
+
+    #[test]
+    fn test_highlights_clip_to_white() {
+        let image = develop(&bayer(4, 4, [3839, 3839, 3839])).unwrap();
+        assert!(image
+            .pixels()
+            .all(|p| p.0[..3].iter().all(|&v| (v - 1.0).abs() < 1e-5)));
+    }
+
+    #[test]
+    fn test_crop_and_orientation() {
+        let mut raw = bayer(10, 8, [100, 100, 100]);
+        raw.crops = [1, 2, 1, 0];
+        raw.orientation = RawOrientation::Rotate90;
+        assert_eq!(develop(&raw).unwrap().dimensions(), (6, 8));
+    }
+
+    #[test]
+    fn test_color_matrix_keeps_white_neutral() {
+        let mut raw = bayer(4, 4, [1000, 1000, 1000]);
+        raw.wb_coeffs = [1.0, 1.0, 1.0, f32::NAN];
+        // A typical camera matrix (Canon EOS 5D Mark II, scaled by 1/10000)
+        raw.xyz_to_cam = [
+            [0.4716, 0.0603, -0.0830],
+            [-0.7798, 1.5474, 0.2480],
+            [-0.1496, 0.1937, 0.6651],
+            [0.0, 0.0, 0.0],
+        ];
+        let pixel = *develop(&raw).unwrap().get_pixel(1, 1);
+        assert!((pixel[0] - pixel[1]).abs() < 1e-3 && (pixel[1] - pixel[2]).abs() < 1e-3);
+    }
+
+    #[test]
+    fn test_invalid_input() {
+        assert!(matches!(
+            load_raw(b"not a raw file"),
+            Err(PipelineError::ImageError(_))
+        ));
+        let mut raw = bayer(4, 4, [0; 3]);
+        raw.data = RawImageData::Integer(vec![0; 4]);
+        assert!(develop(&raw).is_err());
+    }
+}
@@ -0,0 +1,307 @@
+//! Introspection of the available operations and their parameters
+//!
+//! Front-ends build controls from this instead of hard-coding slider ranges;
+//! the ranges mirror what `FilterOperation::validate` accepts. Each entry is
+//! the schema of a built-in filter in `ops`. Names and types follow the JSON
+//! spec format (see `spec`).
+
+use crate::ops::{
+    AffineFilter, BlurFilter, BrightnessFilter, ChromaKeyFilter, ChromaticAberrationFilter,
+    ColorMatrixFilter, ContrastFilter, CustomFilter, DeskewFilter, DropShadowFilter,
+    EdgeDetectFilter, GammaFilter, GradientMapFilter, GrayscaleFilter, InvertFilter,
+    LensCorrectFilter, Lut3DFilter, MaskedFilter, ModelFilter, NoiseFilter, PadFilter,
+    PixelateFilter, PortraitBlurFilter, ResizeFilter, RotateFilter, SeamCarveFilter,
+    SelectiveColorFilter, SepiaFilter, ShadowsHighlightsFilter, SharpenFilter, SmartCropFilter,
+    ThumbnailFilter, UpscaleFilter, WatermarkFilter,
+};
+use serde::Serialize;
+use serde_json::Value;
+
+/// Kind of value a parameter takes in a spec document
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ParamType {
+    Number,
+    Integer,
+    Boolean,
+    String,
+    /// One of `options`
+    Enum,
+    /// `[r, g, b, a]`, each 0-255
+    Color,
+    /// Array of numbers
+    Matrix,
+    /// A rectangle or mask path, see `RegionSpec`
+    Region,
+    /// A nested operation
+    Operation,
+    /// Arbitrary JSON
+    Object,
+}
+
+/// Description of one parameter
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ParamInfo {
+    pub name: &'static str,
+    #[serde(rename = "type")]
+    pub kind: ParamType,
+    pub description: &'static str,
+    /// Smallest accepted value
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub min: Option<f64>,
+    /// Largest accepted value
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max: Option<f64>,
+    /// Whether `min` itself is rejected (values must be strictly greater)
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub exclusive_min: bool,
+    /// Value used when the parameter is omitted; `None` means it is required
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub default: Option<Value>,
+    /// Accepted names for `Enum` parameters
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub options: Vec<&'static str>,
+}
+
+/// Description of one operation
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct OperationInfo {
+    /// Value of the `type` field in a spec
+    #[serde(rename = "type")]
+    pub name: &'static str,
+    pub description: &'static str,
+    /// Whether the output size can differ from the input
+    pub changes_size: bool,
+    pub params: Vec<ParamInfo>,
+}
+
+impl ParamInfo {
+    pub(crate) fn new(name: &'static str, kind: ParamType, description: &'static str) -> Self {
+        Self {
+            name,
+            kind,
+            description,
+            min: None,
+            max: None,
+            exclusive_min: false,
+            default: None,
+            options: Vec::new(),
+        }
+    }
+
+    pub(crate) fn range(mut self, min: f64, max: f64) -> Self {
+        self.min = Some(min);
+        self.max = Some(max);
+        self
+    }
+
+    pub(crate) fn max(mut self, max: f64) -> Self {
+        self.max = Some(max);
+        self
+    }
+
+    pub(crate) fn min(mut self, min: f64) -> Self {
+        self.min = Some(min);
+        self
+    }
+
+    pub(crate) fn above(mut self, min: f64) -> Self {
+        self.min = Some(min);
+        self.exclusive_min = true;
+        self
+    }
+
+    pub(crate) fn default(mut self, value: Value) -> Self {
+        self.default = Some(value);
+        self
+    }
+
+    pub(crate) fn options(mut self, options: &[&'static str]) -> Self {
+        self.options = options.to_vec();
+        self
+    }
+}
+
+pub(crate) fn op(
+    name: &'static str,
+    description: &'static str,
+    params: Vec<ParamInfo>,
+) -> OperationInfo {
+    OperationInfo {
+        name,
+        description,
+        changes_size: false,
+        params,
+    }
+}
+
+pub(crate) fn resizing(mut info: OperationInfo) -> OperationInfo {
+    info.changes_size = true;
+    info
+}
+
+pub(crate) fn size_params() -> Vec<ParamInfo> {
+    vec![
+        ParamInfo::new("width", ParamType::Integer, "Target width in pixels").min(1.0),
+        ParamInfo::new("height", ParamType::Integer, "Target height in pixels").min(1.0),
+    ]
+}
+
+pub(crate) fn number(name: &'static str, description: &'static str) -> ParamInfo {
+    ParamInfo::new(name, ParamType::Number, description)
+}
+
+/// Every operation a spec can contain, in spec order
+pub fn registry() -> Vec<OperationInfo> {
+    vec![
+        GrayscaleFilter::info(),
+        BrightnessFilter::info(),
+        ContrastFilter::info(),
+        BlurFilter::info(),
+        SharpenFilter::info(),
+        EdgeDetectFilter::info(),
+        ResizeFilter::info(),
+        ThumbnailFilter::info(),
+        SmartCropFilter::info(),
+        SeamCarveFilter::info(),
+        PadFilter::info(),
+        InvertFilter::info(),
+        SepiaFilter::info(),
+        ColorMatrixFilter::info(),
+        GammaFilter::info(),
+        Lut3DFilter::info(),
+        NoiseFilter::info(),
+        PixelateFilter::info(),
+        DeskewFilter::info(),
+        RotateFilter::info(),
+        AffineFilter::info(),
+        LensCorrectFilter::info(),
+        ChromaticAberrationFilter::info(),
+        ChromaKeyFilter::info(),
+        GradientMapFilter::info(),
+        SelectiveColorFilter::info(),
+        ShadowsHighlightsFilter::info(),
+        PortraitBlurFilter::info(),
+        ModelFilter::info(),
+        UpscaleFilter::info(),
+        WatermarkFilter::info(),
+        DropShadowFilter::info(),
+        MaskedFilter::info(),
+        CustomFilter::info(),
+    ]
+}
+
+pub fn registry_json() -> String {
+    serde_json::to_string(&registry()).expect("registry is serializable")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{OperationSpec, IDENTITY_COLOR_MATRIX, MAX_BLUR_SIGMA};
+    use serde_json::json;
+
+    // A mid-range value for `param` of `info`, or its default
+    fn sample_value(info: &OperationInfo, param: &ParamInfo) -> Value {
+        if let Some(default) = &param.default {
+            return default.clone();
+        }
+        match param.kind {
+            ParamType::Number | ParamType::Integer => {
+                let value = match (param.min, param.max) {
+                    (Some(min), Some(max)) => (min + max) / 2.0,
+                    (Some(min), None) => min + 1.0,
+                    _ => 0.5,
+                };
+                numeric(param, value)
+            }
+            ParamType::Matrix if info.name == "color_matrix" => json!(IDENTITY_COLOR_MATRIX),
+            ParamType::Matrix => json!([1.0, 0.0, 0.0, 0.0, 1.0, 0.0]),
+            ParamType::Object if info.name == "gradient_map" => json!([
+                {"position": 0.0, "color": [0, 0, 0, 255]},
+                {"position": 1.0, "color": [255, 255, 255, 255]}
+            ]),
+            _ => unreachable!("{} has no sample", param.name),
+        }
+    }
+
+    fn numeric(param: &ParamInfo, value: f64) -> Value {
+        if param.kind == ParamType::Integer {
+            json!(value.round() as i64)
+        } else {
+            json!(value)
+        }
+    }
+
+    fn build(info: &OperationInfo, overrides: &[(&str, Value)]) -> Value {
+        let mut object = serde_json::Map::new();
+        object.insert("type".to_string(), json!(info.name));
+        for param in &info.params {
+            object.insert(param.name.to_string(), sample_value(info, param));
+        }
+        for (name, value) in overrides {
+            object.insert(name.to_string(), value.clone());
+        }
+        Value::Object(object)
+    }
+
+    fn validate(value: Value) -> bool {
+        serde_json::from_value::<OperationSpec>(value)
+            .ok()
+            .and_then(|spec| spec.to_operation().ok())
+            .is_some_and(|op| op.validate().is_ok())
+    }
+
+    #[test]
+    fn test_ranges_match_validation() {
+        for info in registry() {
+            // Need external files or nested operations
+            if matches!(
+                info.name,
+                "lut3d" | "model" | "watermark" | "masked" | "custom"
+            ) {
+                continue;
+            }
+            assert!(validate(build(&info, &[])), "{}", info.name);
+
+            for param in &info.params {
+                if let Some(max) = param.max {
+                    let over = build(&info, &[(param.name, numeric(param, max + 1.0))]);
+                    assert!(!validate(over), "{}.{} above max", info.name, param.name);
+                }
+                if let Some(min) = param.min {
+                    let at = build(&info, &[(param.name, numeric(param, min))]);
+                    assert_eq!(
+                        validate(at),
+                        !param.exclusive_min,
+                        "{}.{}",
+                        info.name,
+                        param.name
+                    );
+                    if param.kind == ParamType::Number {
+                        let under = build(&info, &[(param.name, numeric(param, min - 1.0))]);
+                        assert!(!validate(under), "{}.{} below min", info.name, param.name);
+                    }
+                }
+                for option in &param.options {
+                    assert!(validate(build(&info, &[(param.name, json!(option))])));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_registry_json() {
+        let parsed: Value = serde_json::from_str(&registry_json()).unwrap();
+        let blur = parsed
+            .as_array()
+            .unwrap()
+            .iter()
+            .find(|op| op["type"] == "blur")
+            .unwrap();
+        assert_eq!(blur["params"][0]["name"], "sigma");
+        assert_eq!(blur["params"][0]["max"], MAX_BLUR_SIGMA as f64);
+        assert_eq!(blur["params"][0]["exclusive_min"], true);
+        assert!(blur["params"][0].get("default").is_none());
+    }
+}
@@ -0,0 +1,272 @@
+//! Loading images from HTTP responses (`remote` feature)
+//!
+//! Services that fetch originals from a bucket or CDN hand the response
+//! headers and body stream to `ImagePipeline::load_from_response`, which
+//! enforces the size limit while reading and refuses content types that are
+//! not images before anything is decoded. It works with any HTTP client.
+//!
+//! Services without a client of their own use the async
+//! `ImagePipeline::load_from_url`, which fetches with reqwest under the same
+//! checks.
+
+use crate::{ImagePipeline, PipelineError, Result};
+use image::RgbaImage;
+use std::io::Read;
+
+/// Default cap on a fetched body (25 MiB)
+pub const DEFAULT_MAX_BYTES: u64 = 25 * 1024 * 1024;
+
+/// What a remote response must satisfy to be decoded
+#[derive(Debug, Clone)]
+pub struct RemoteLimits {
+    /// Largest accepted body in bytes
+    pub max_bytes: u64,
+    /// Accepted media types, without parameters; empty accepts any
+    pub allowed_types: Vec<String>,
+}
+
+impl Default for RemoteLimits {
+    fn default() -> Self {
+        Self {
+            max_bytes: DEFAULT_MAX_BYTES,
+            allowed_types: [
+                "image/jpeg",
+                "image/png",
+                "image/gif",
+                "image/webp",
+                "image/bmp",
+                "image/tiff",
+                #[cfg(feature = "avif-decode")]
+                "image/avif",
+            ]
+            .iter()
+            .map(|t| t.to_string())
+            .collect(),
+        }
+    }
+}
+
+impl RemoteLimits {
+    /// Check a `Content-Type` header; a missing header is rejected unless any type is allowed
+    pub fn check_content_type(&self, content_type: Option<&str>) -> Result<()> {
+        if self.allowed_types.is_empty() {
+            return Ok(());
+        }
+        let media_type = content_type
+            .and_then(|value| value.split(';').next())
+            .map(|value| value.trim().to_ascii_lowercase())
+            .unwrap_or_default();
+        if self
+            .allowed_types
+            .iter()
+            .any(|allowed| allowed.eq_ignore_ascii_case(&media_type))
+        {
+            Ok(())
+        } else if media_type.is_empty() {
+            Err(PipelineError::InvalidParameter(
+                "response has no content type".to_string(),
+            ))
+        } else {
+            Err(PipelineError::InvalidParameter(format!(
+                "unsupported content type {}",
+                media_type
+            )))
+        }
+    }
+
+    /// Read `body` to the end, failing as soon as it exceeds `max_bytes`
+    ///
+    /// `content_length` from the headers, when present, rejects oversized
+    /// responses without reading them.
+    pub fn read_body(&self, content_length: Option<u64>, body: impl Read) -> Result<Vec<u8>> {
+        if let Some(length) = content_length {
+            self.check_size(length)?;
+        }
+        let mut bytes =
+            Vec::with_capacity(content_length.unwrap_or(0).min(self.max_bytes) as usize);
+        // One byte past the limit tells an oversized body from one that fits exactly
+        body.take(self.max_bytes.saturating_add(1))
+            .read_to_end(&mut bytes)?;
+        self.check_size(bytes.len() as u64)?;
+        Ok(bytes)
+    }
+
+    fn check_size(&self, size: u64) -> Result<()> {
+        if size > self.max_bytes {
+            return Err(PipelineError::InvalidParameter(format!(
+                "response larger than {} bytes",
+                self.max_bytes
+            )));
+        }
+        Ok(())
+    }
+}
+
+impl ImagePipeline {
+    /// Decode an image from an HTTP response after checking it against `limits`
+    pub fn load_from_response(
+        content_type: Option<&str>,
+        content_length: Option<u64>,
+        body: impl Read,
+        limits: &RemoteLimits,
+    ) -> Result<RgbaImage> {
+        limits.check_content_type(content_type)?;
+        let bytes = limits.read_body(content_length, body)?;
+        Self::load_from_bytes(&bytes)
+    }
+
+    /// Fetch and decode an image with the default `RemoteLimits`
+    ///
+    /// Builds a new client per call; services fetching many images should
+    /// share one with `load_from_url_with`.
+    pub async fn load_from_url(url: &str) -> Result<RgbaImage> {
+        Self::load_from_url_with(&reqwest::Client::new(), url, &RemoteLimits::default()).await
+    }
+
+    /// Fetch `url` with `client` and decode it after checking it against `limits`
+    ///
+    /// Fails on non-success statuses and stops downloading as soon as the body
+    /// exceeds `max_bytes`. Decoding runs on the calling task.
+    pub async fn load_from_url_with(
+        client: &reqwest::Client,
+        url: &str,
+        limits: &RemoteLimits,
+    ) -> Result<RgbaImage> {
+        let mut response = client.get(url).send().await.map_err(fetch_error)?;
+        let status = response.status();
+        if !status.is_success() {
+            return Err(PipelineError::IoError(std::io::Error::other(format!(
+                "{} responded with {}",
+                url, status
+            ))));
+        }
+        let content_type = response
+            .headers()
+            .get(reqwest::header::CONTENT_TYPE)
+            .and_then(|value| value.to_str().ok());
+        limits.check_content_type(content_type)?;
+
+        let content_length = response.content_length();
+        if let Some(length) = content_length {
+            limits.check_size(length)?;
+        }
+        let mut bytes =
+            Vec::with_capacity(content_length.unwrap_or(0).min(limits.max_bytes) as usize);
+        while let Some(chunk) = response.chunk().await.map_err(fetch_error)? {
+            limits.check_size((bytes.len() + chunk.len()) as u64)?;
+            bytes.extend_from_slice(&chunk);
+        }
+        Self::load_from_bytes(&bytes)
+    }
+}
+
+fn fetch_error(err: reqwest::Error) -> PipelineError {
+    PipelineError::IoError(std::io::Error::other(err))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgba;
+
+    fn png() -> Vec<u8> {
+        let image = RgbaImage::from_pixel(3, 2, Rgba([1, 2, 3, 255]));
+        ImagePipeline::encode_to_png(&image).unwrap()
+    }
+
+    #[test]
+    fn test_load_from_response() {
+        let body = png();
+        let limits = RemoteLimits::default();
+        let image = ImagePipeline::load_from_response(
+            Some("Image/PNG; charset=binary"),
+            Some(body.len() as u64),
+            body.as_slice(),
+            &limits,
+        )
+        .unwrap();
+        assert_eq!(image.dimensions(), (3, 2));
+
+        for content_type in [None, Some("text/html"), Some("image/svg+xml")] {
+            assert!(ImagePipeline::load_from_response(
+                content_type,
+                None,
+                body.as_slice(),
+                &limits
+            )
+            .is_err());
+        }
+        let any = RemoteLimits {
+            allowed_types: Vec::new(),
+            ..limits
+        };
+        assert!(ImagePipeline::load_from_response(None, None, body.as_slice(), &any).is_ok());
+    }
+
+    // Serve one canned response on a local port and return its URL
+    fn serve(head: &'static str, body: Vec<u8>) -> String {
+        use std::io::Write;
+        use std::net::TcpListener;
+
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/image", listener.local_addr().unwrap());
+        std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = [0u8; 1024];
+            let _ = stream.read(&mut request);
+            let _ = write!(stream, "{}\r\nConnection: close\r\n\r\n", head);
+            let _ = stream.write_all(&body);
+        });
+        url
+    }
+
+    #[tokio::test]
+    async fn test_load_from_url() {
+        let body = png();
+        let head = "HTTP/1.1 200 OK\r\nContent-Type: image/png";
+        let image = ImagePipeline::load_from_url(&serve(head, body.clone()))
+            .await
+            .unwrap();
+        assert_eq!(image.dimensions(), (3, 2));
+
+        let html = "HTTP/1.1 200 OK\r\nContent-Type: text/html";
+        assert!(ImagePipeline::load_from_url(&serve(html, body.clone()))
+            .await
+            .is_err());
+        let missing = "HTTP/1.1 404 Not Found\r\nContent-Type: image/png";
+        assert!(ImagePipeline::load_from_url(&serve(missing, body.clone()))
+            .await
+            .is_err());
+
+        // No length header, so the limit has to stop the download
+        let small = RemoteLimits {
+            max_bytes: body.len() as u64 - 1,
+            ..RemoteLimits::default()
+        };
+        let err =
+            ImagePipeline::load_from_url_with(&reqwest::Client::new(), &serve(head, body), &small)
+                .await
+                .unwrap_err();
+        assert!(err.to_string().contains("larger than"), "{}", err);
+    }
+
+    #[test]
+    fn test_size_limit() {
+        let body = png();
+        let exact = RemoteLimits {
+            max_bytes: body.len() as u64,
+            ..RemoteLimits::default()
+        };
+        assert_eq!(exact.read_body(None, body.as_slice()).unwrap(), body);
+
+        let small = RemoteLimits {
+            max_bytes: body.len() as u64 - 1,
+            ..RemoteLimits::default()
+        };
+        // Without a length header, and with one that understates the body
+        assert!(small.read_body(None, body.as_slice()).is_err());
+        assert!(small.read_body(Some(10), body.as_slice()).is_err());
+        // A declared length over the limit is refused before reading
+        assert!(small.read_body(Some(u64::MAX), std::io::empty()).is_err());
+    }
+}
@@ -0,0 +1,327 @@
+//! Metadata scrubbing for privacy-sensitive uploads
+//!
+//! `ImagePipeline::sanitize` decodes an image and encodes the pixels into a new
+//! file, so nothing from the original container survives: no EXIF (GPS
+//! position, camera serial numbers), XMP, ICC profiles, comments, text chunks
+//! or data appended after the image. The container is walked first only to
+//! report what was there.
+//!
+//! Re-encoding alone keeps anything hidden in the pixels themselves. Lossy
+//! output (JPEG) destroys least-significant-bit payloads anyway; for lossless
+//! output the lowest bit of every color channel is replaced with noise, a
+//! change of at most one level that no viewer can see.
+
+use crate::filters::SplitMix64;
+use crate::hash::sha256;
+use crate::{ImagePipeline, LoadOptions, OutputFormat, Result};
+use image::{DynamicImage, ImageDecoder, ImageFormat, ImageReader};
+use std::io::Cursor;
+
+/// JPEG quality of sanitized JPEGs
+pub const SANITIZE_JPEG_QUALITY: u8 = 90;
+
+/// What `ImagePipeline::sanitize` removed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SanitizeReport {
+    /// Format of the sanitized file: the input's, or PNG for GIF input
+    pub format: OutputFormat,
+    /// Metadata blocks found in the input, in file order, e.g. "EXIF", "XMP",
+    /// "ICC profile", "comment" or a PNG chunk type such as "tEXt"
+    pub removed: Vec<String>,
+    /// Bytes after the end of the image data, where archives or payloads hide
+    pub trailing_bytes: u64,
+    /// Whether the EXIF orientation was applied to the pixels before being dropped
+    pub orientation_applied: bool,
+    /// Frames after the first that were dropped (animated GIF input)
+    pub dropped_frames: u32,
+}
+
+impl ImagePipeline {
+    /// Decode and re-encode `bytes`, dropping all metadata, ancillary chunks
+    /// and trailing data; returns the clean file and what was removed
+    ///
+    /// Input is checked against `LoadOptions::untrusted`. The EXIF orientation
+    /// is applied first so the image still displays upright without it.
+    pub fn sanitize(bytes: &[u8]) -> Result<(Vec<u8>, SanitizeReport)> {
+        let image = Self::load_from_bytes_with_options(bytes, &LoadOptions::untrusted())?;
+        let reader = ImageReader::new(Cursor::new(bytes)).with_guessed_format()?;
+        let input_format = reader.format();
+        let orientation = reader.into_decoder()?.orientation()?;
+
+        let mut report = SanitizeReport {
+            format: match input_format {
+                Some(ImageFormat::Jpeg) => OutputFormat::Jpeg,
+                Some(ImageFormat::WebP) => OutputFormat::WebP,
+                _ => OutputFormat::Png,
+            },
+            removed: Vec::new(),
+            trailing_bytes: 0,
+            orientation_applied: orientation != image::metadata::Orientation::NoTransforms,
+            dropped_frames: 0,
+        };
+        match input_format {
+            Some(ImageFormat::Png) => scan_png(bytes, &mut report),
+            Some(ImageFormat::Jpeg) => scan_jpeg(bytes, &mut report),
+            Some(ImageFormat::WebP) => scan_webp(bytes, &mut report),
+            Some(ImageFormat::Gif) => scan_gif(bytes, &mut report),
+            _ => {}
+        }
+
+        let mut oriented = DynamicImage::ImageRgba8(image);
+        oriented.apply_orientation(orientation);
+        let mut image = oriented.into_rgba8();
+        if report.format != OutputFormat::Jpeg {
+            // Seeded from the content so the same upload always sanitizes identically
+            let seed = u64::from_le_bytes(sha256(image.as_raw())[..8].try_into().unwrap());
+            let mut rng = SplitMix64::new(seed);
+            for pixel in image.pixels_mut() {
+                let noise = rng.next_u64();
+                for c in 0..3 {
+                    pixel[c] = (pixel[c] & !1) | (noise >> c) as u8 & 1;
+                }
+            }
+        }
+        let encoded = Self::encode(&image, report.format, SANITIZE_JPEG_QUALITY)?;
+        Ok((encoded, report))
+    }
+}
+
+// PNG: every chunk other than the critical ones and transparency is metadata
+fn scan_png(bytes: &[u8], report: &mut SanitizeReport) {
+    let mut pos = 8;
+    while let Some(header) = bytes.get(pos..pos + 8) {
+        let length = u32::from_be_bytes(header[..4].try_into().unwrap()) as usize;
+        let kind = String::from_utf8_lossy(&header[4..8]).into_owned();
+        pos = pos.saturating_add(12).saturating_add(length);
+        match kind.as_str() {
+            "IEND" => {
+                report.trailing_bytes = bytes.len().saturating_sub(pos) as u64;
+                return;
+            }
+            "IHDR" | "PLTE" | "IDAT" | "tRNS" => {}
+            // Animation control; only the default image is kept
+            "acTL" | "fcTL" | "fdAT" => {}
+            "eXIf" => report.removed.push("EXIF".to_string()),
+            "iCCP" => report.removed.push("ICC profile".to_string()),
+            "iTXt" if header_keyword(bytes, pos - length - 4) == b"XML:com.adobe.xmp" => {
+                report.removed.push("XMP".to_string())
+            }
+            _ => report.removed.push(kind),
+        }
+    }
+}
+
+// Keyword of a PNG text chunk whose data starts at `start`
+fn header_keyword(bytes: &[u8], start: usize) -> &[u8] {
+    let data = bytes.get(start..).unwrap_or_default();
+    &data[..data.iter().position(|&b| b == 0).unwrap_or(0)]
+}
+
+// JPEG: application segments and comments before the first scan
+fn scan_jpeg(bytes: &[u8], report: &mut SanitizeReport) {
+    let mut pos = 2;
+    while pos + 4 <= bytes.len() && bytes[pos] == 0xFF {
+        let marker = bytes[pos + 1];
+        let length = u16::from_be_bytes([bytes[pos + 2], bytes[pos + 3]]) as usize;
+        let data = bytes.get(pos + 4..pos + 2 + length).unwrap_or_default();
+        match marker {
+            0xE1 if data.starts_with(b"Exif\0") => report.removed.push("EXIF".to_string()),
+            0xE1 if data.starts_with(b"http://ns.adobe.com/") => {
+                report.removed.push("XMP".to_string())
+            }
+            0xE2 if data.starts_with(b"ICC_PROFILE\0") => {
+                report.removed.push("ICC profile".to_string())
+            }
+            // JFIF is structural, not metadata
+            0xE0 if data.starts_with(b"JFIF\0") => {}
+            0xE0..=0xEF => report.removed.push(format!("APP{}", marker - 0xE0)),
+            0xFE => report.removed.push("comment".to_string()),
+            0xDA => break,
+            _ => {}
+        }
+        pos += 2 + length;
+    }
+
+    // Entropy-coded data escapes 0xFF, so the first unescaped end marker ends the image
+    let end = (pos..bytes.len().saturating_sub(1))
+        .find(|&i| bytes[i] == 0xFF && bytes[i + 1] == 0xD9)
+        .map_or(bytes.len(), |i| i + 2);
+    report.trailing_bytes = (bytes.len() - end) as u64;
+}
+
+// WebP: RIFF chunks other than the image data
+fn scan_webp(bytes: &[u8], report: &mut SanitizeReport) {
+    let Some(size) = bytes.get(4..8) else {
+        return;
+    };
+    let end = (u32::from_le_bytes(size.try_into().unwrap()) as usize).saturating_add(8);
+    report.trailing_bytes = bytes.len().saturating_sub(end) as u64;
+    let mut pos = 12;
+    while let Some(header) = bytes.get(pos..pos + 8).filter(|_| pos < end) {
+        let length = u32::from_le_bytes(header[4..].try_into().unwrap()) as usize;
+        match &header[..4] {
+            b"EXIF" => report.removed.push("EXIF".to_string()),
+            b"XMP " => report.removed.push("XMP".to_string()),
+            b"ICCP" => report.removed.push("ICC profile".to_string()),
+            b"VP8 " | b"VP8L" | b"VP8X" | b"ALPH" | b"ANIM" | b"ANMF" => {}
+            other => report
+                .removed
+                .push(String::from_utf8_lossy(other).into_owned()),
+        }
+        // Chunks are padded to an even length
+        pos = pos.saturating_add(8).saturating_add(length + (length & 1));
+    }
+}
+
+// GIF: comment, application and plain-text extensions, and extra frames
+fn scan_gif(bytes: &[u8], report: &mut SanitizeReport) {
+    // Skip a color table of the size encoded in `flags`, if present
+    let table = |flags: u8| {
+        if flags & 0x80 != 0 {
+            3 << ((flags & 7) + 1)
+        } else {
+            0
+        }
+    };
+    // Skip data sub-blocks starting at `pos`
+    let sub_blocks = |mut pos: usize| {
+        while let Some(&len) = bytes.get(pos) {
+            pos += 1 + len as usize;
+            if len == 0 {
+                break;
+            }
+        }
+        pos
+    };
+
+    let Some(&flags) = bytes.get(10) else {
+        return;
+    };
+    let mut pos = 13 + table(flags);
+    let mut frames = 0u32;
+    while let Some(&introducer) = bytes.get(pos) {
+        match introducer {
+            0x21 => {
+                let label = bytes.get(pos + 1).copied().unwrap_or_default();
+                match label {
+                    0xFE => report.removed.push("comment".to_string()),
+                    0x01 => report.removed.push("plain text".to_string()),
+                    0xFF => {
+                        let id = bytes.get(pos + 3..pos + 14).unwrap_or_default();
+                        report.removed.push(format!(
+                            "application extension {}",
+                            String::from_utf8_lossy(id)
+                        ));
+                    }
+                    _ => {}
+                }
+                pos = sub_blocks(pos + 2);
+            }
+            0x2C => {
+                frames += 1;
+                let flags = bytes.get(pos + 9).copied().unwrap_or_default();
+                // Descriptor, local color table, LZW code size, then the data
+                pos = sub_blocks(pos + 10 + table(flags) + 1);
+            }
+            0x3B => {
+                pos += 1;
+                break;
+            }
+            _ => break,
+        }
+    }
+    report.dropped_frames = frames.saturating_sub(1);
+    report.trailing_bytes = bytes.len().saturating_sub(pos) as u64;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{Rgba, RgbaImage};
+
+    fn sample() -> RgbaImage {
+        RgbaImage::from_fn(16, 8, |x, y| {
+            Rgba([(x * 16) as u8, (y * 32) as u8, 100, 255])
+        })
+    }
+
+    // PNG chunk with its CRC
+    fn chunk(kind: &[u8; 4], data: &[u8]) -> Vec<u8> {
+        let mut chunk = (data.len() as u32).to_be_bytes().to_vec();
+        chunk.extend_from_slice(kind);
+        chunk.extend_from_slice(data);
+        chunk.extend_from_slice(&crc32(&chunk[4..]).to_be_bytes());
+        chunk
+    }
+
+    fn crc32(data: &[u8]) -> u32 {
+        !data.iter().fold(!0u32, |mut crc, &byte| {
+            crc ^= byte as u32;
+            for _ in 0..8 {
+                crc = if crc & 1 != 0 {
+                    (crc >> 1) ^ 0xEDB8_8320
+                } else {
+                    crc >> 1
+                };
+            }
+            crc
+        })
+    }
+
+    #[test]
+    fn test_png_chunks_and_trailer_removed() {
+        let png = ImagePipeline::encode_to_png(&sample()).unwrap();
+        // Insert text and XMP after IHDR (8 byte signature + 25 byte chunk), then append a zip
+        let mut dirty = png[..33].to_vec();
+        dirty.extend(chunk(b"tEXt", b"Author\0Jane"));
+        dirty.extend(chunk(b"iTXt", b"XML:com.adobe.xmp\0\0\0\0\0<x:xmpmeta/>"));
+        dirty.extend_from_slice(&png[33..]);
+        dirty.extend_from_slice(b"PK\x03\x04secret");
+
+        let (clean, report) = ImagePipeline::sanitize(&dirty).unwrap();
+        assert_eq!(report.format, OutputFormat::Png);
+        assert_eq!(report.removed, ["tEXt", "XMP"]);
+        assert_eq!(report.trailing_bytes, 10);
+        assert!(!report.orientation_applied);
+
+        let mut again = report.clone();
+        again.removed.clear();
+        scan_png(&clean, &mut again);
+        assert!(again.removed.is_empty() && again.trailing_bytes == 0);
+
+        // Pixels change by at most the scrubbed low bit
+        let decoded = image::load_from_memory(&clean).unwrap().to_rgba8();
+        let max_change = decoded
+            .as_raw()
+            .iter()
+            .zip(sample().as_raw())
+            .map(|(a, b)| a.abs_diff(*b))
+            .max();
+        assert_eq!(max_change, Some(1));
+    }
+
+    #[test]
+    fn test_jpeg_segments_and_orientation() {
+        let jpeg = ImagePipeline::encode(&sample(), OutputFormat::Jpeg, 90).unwrap();
+        // Little-endian EXIF with one entry: orientation 6 (rotate 90 clockwise)
+        let mut exif =
+            b"Exif\0\0II*\0\x08\0\0\0\x01\0\x12\x01\x03\0\x01\0\0\0\x06\0\0\0\0\0\0\0".to_vec();
+        let mut app1 = vec![0xFF, 0xE1];
+        app1.extend_from_slice(&((exif.len() + 2) as u16).to_be_bytes());
+        app1.append(&mut exif);
+        let comment = [0xFF, 0xFE, 0x00, 0x07, b'h', b'e', b'l', b'l', b'o'];
+        let mut dirty = jpeg[..2].to_vec();
+        dirty.extend_from_slice(&app1);
+        dirty.extend_from_slice(&comment);
+        dirty.extend_from_slice(&jpeg[2..]);
+        dirty.extend_from_slice(b"trailing");
+
+        let (clean, report) = ImagePipeline::sanitize(&dirty).unwrap();
+        assert_eq!(report.format, OutputFormat::Jpeg);
+        assert_eq!(report.removed, ["EXIF", "comment"]);
+        assert_eq!(report.trailing_bytes, 8);
+        assert!(report.orientation_applied);
+        let decoded = image::load_from_memory(&clean).unwrap();
+        assert_eq!((decoded.width(), decoded.height()), (8, 16));
+    }
+}
@@ -1,14 +1,13 @@
+use crate::GrayscaleMode;
 use rayon::prelude::*;
 
+/// In-place grayscale, identical to `filters::grayscale_with`
 #[inline]
-pub fn grayscale_fast(pixels: &mut [u8]) {
+pub fn grayscale_fast(pixels: &mut [u8], mode: GrayscaleMode) {
     pixels.par_chunks_mut(16).for_each(|chunk| {
         for i in (0..chunk.len()).step_by(4) {
             if i + 3 < chunk.len() {
-                let r = chunk[i] as u16;
-                let g = chunk[i + 1] as u16;
-                let b = chunk[i + 2] as u16;
-                let gray = ((r + g + g + b) >> 2) as u8;
+                let gray = mode.gray(chunk[i], chunk[i + 1], chunk[i + 2]);
                 chunk[i] = gray;
                 chunk[i + 1] = gray;
                 chunk[i + 2] = gray;
@@ -104,13 +103,31 @@ mod tests {
     #[test]
     fn test_grayscale_fast() {
         let mut pixels = vec![255u8, 0, 0, 255, 0, 255, 0, 255, 0, 0, 255, 255];
-        grayscale_fast(&mut pixels);
+        grayscale_fast(&mut pixels, GrayscaleMode::Bt709);
 
         // Red pixel should become gray
         assert_eq!(pixels[0], pixels[1]);
         assert_eq!(pixels[1], pixels[2]);
         // Alpha should be unchanged
         assert_eq!(pixels[3], 255);
+
+        // Same result as the filter in every mode
+        let image = image::RgbaImage::from_fn(37, 11, |x, y| {
+            image::Rgba([(x * 7) as u8, (y * 23) as u8, (x * y) as u8, 200])
+        });
+        for mode in [
+            GrayscaleMode::Bt601,
+            GrayscaleMode::Bt709,
+            GrayscaleMode::Average,
+            GrayscaleMode::Blue,
+        ] {
+            let mut pixels = image.as_raw().clone();
+            grayscale_fast(&mut pixels, mode);
+            assert_eq!(
+                pixels,
+                *crate::filters::grayscale_with(&image, mode).as_raw()
+            );
+        }
     }
 
     #[test]
@@ -0,0 +1,1815 @@
+//! Declarative pipeline descriptions
+//!
+//! A spec is either a bare array of operations or an object with a version:
+//!
+//! ```json
+//! [{"type": "grayscale"}, {"type": "brightness", "value": 0.2}]
+//! {"version": 1, "operations": [{"type": "blur", "sigma": 2.0}]}
+//! ```
+
+use crate::{
+    Anchor, EdgeBorder, EdgeOptions, EdgeOutput, FilterOperation, FitMode, GrayscaleMode,
+    ImagePipeline, Interpolation, Lut3D, Mark, Model, PadFill, PipelineError, Postprocess,
+    Preprocess, Region, ResampleFilter, Result, TensorLayout, UpscaleMethod, SHARPEN_AMOUNT,
+    SHARPEN_RADIUS,
+};
+use image::Rgba;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::sync::Arc;
+
+/// Current spec format version
+pub const SPEC_VERSION: u32 = 1;
+
+/// A serializable description of a pipeline
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PipelineSpec {
+    #[serde(default = "default_version")]
+    pub version: u32,
+    pub operations: Vec<OperationSpec>,
+}
+
+/// A single operation as it appears in a spec document
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum OperationSpec {
+    Grayscale {
+        #[serde(default, skip_serializing_if = "is_default")]
+        mode: GrayscaleSpec,
+    },
+    Brightness {
+        value: f32,
+    },
+    Contrast {
+        value: f32,
+    },
+    Blur {
+        #[serde(alias = "value")]
+        sigma: f32,
+        /// Use the box-blur approximation
+        #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+        approximate: bool,
+    },
+    Sharpen {
+        #[serde(
+            default = "default_sharpen_amount",
+            skip_serializing_if = "is_default_sharpen_amount"
+        )]
+        amount: f32,
+        #[serde(
+            default = "default_sharpen_radius",
+            skip_serializing_if = "is_default_sharpen_radius"
+        )]
+        radius: f32,
+        #[serde(default, skip_serializing_if = "is_default")]
+        threshold: u8,
+    },
+    EdgeDetect {
+        #[serde(default, skip_serializing_if = "is_default")]
+        border: EdgeBorderSpec,
+        #[serde(default, skip_serializing_if = "is_default")]
+        output: EdgeOutputSpec,
+        /// Keep only gradients of at least this magnitude
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        threshold: Option<u8>,
+    },
+    Resize {
+        width: u32,
+        height: u32,
+        #[serde(default)]
+        filter: ResampleSpec,
+    },
+    Thumbnail {
+        width: u32,
+        height: u32,
+        #[serde(default)]
+        fit: FitSpec,
+        /// RGBA fill for `pad`, transparent when omitted
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        background: Option<[u8; 4]>,
+    },
+    SmartCrop {
+        width: u32,
+        height: u32,
+    },
+    SeamCarve {
+        width: u32,
+        height: u32,
+    },
+    Pad {
+        #[serde(default)]
+        top: u32,
+        #[serde(default)]
+        right: u32,
+        #[serde(default)]
+        bottom: u32,
+        #[serde(default)]
+        left: u32,
+        #[serde(default)]
+        fill: PadFillSpec,
+        /// RGBA fill for `color`, transparent when omitted
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        color: Option<[u8; 4]>,
+    },
+    Invert,
+    Sepia,
+    /// Rows of a 4x5 RGBA color matrix, offsets in the last column (0.0-1.0)
+    ColorMatrix {
+        matrix: [[f32; 5]; 4],
+    },
+    Gamma {
+        value: f32,
+    },
+    /// `.cube` file, resolved relative to the working directory
+    Lut3d {
+        path: String,
+    },
+    Noise {
+        amount: f32,
+        #[serde(default)]
+        monochrome: bool,
+        #[serde(default)]
+        seed: u64,
+    },
+    Pixelate {
+        block_size: u32,
+    },
+    Deskew,
+    Rotate {
+        degrees: f32,
+        /// RGBA fill for uncovered corners, transparent when omitted
+        #[serde(default)]
+        background: [u8; 4],
+        #[serde(default)]
+        expand: bool,
+    },
+    /// Row-major 2x3 matrix `[a, b, c, d, e, f]`
+    Affine {
+        matrix: [f32; 6],
+        #[serde(default)]
+        interpolation: InterpolationSpec,
+        /// RGBA fill for uncovered areas, transparent when omitted
+        #[serde(default)]
+        background: [u8; 4],
+    },
+    LensCorrect {
+        k1: f32,
+        #[serde(default)]
+        k2: f32,
+        #[serde(default)]
+        k3: f32,
+    },
+    ChromaticAberration {
+        #[serde(default)]
+        red_shift: f32,
+        #[serde(default)]
+        blue_shift: f32,
+    },
+    ChromaKey {
+        #[serde(default = "default_key_color")]
+        key_color: [u8; 4],
+        tolerance: f32,
+        #[serde(default)]
+        softness: f32,
+    },
+    /// Two stops at 0 and 1 give a duotone
+    GradientMap {
+        stops: Vec<GradientStopSpec>,
+    },
+    /// `hue_min` to `hue_max` in degrees, wrapping at 360
+    SelectiveColor {
+        hue_min: f32,
+        hue_max: f32,
+        #[serde(default)]
+        hue_shift: f32,
+        #[serde(default = "default_scale", skip_serializing_if = "is_default_scale")]
+        saturation: f32,
+        #[serde(default)]
+        lightness: f32,
+    },
+    ShadowsHighlights {
+        #[serde(default)]
+        shadows: f32,
+        #[serde(default)]
+        highlights: f32,
+        radius: f32,
+    },
+    /// Without a mask the subject is estimated from image detail
+    PortraitBlur {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        mask: Option<RegionSpec>,
+        sigma: f32,
+        #[serde(default)]
+        feather: f32,
+    },
+    /// ONNX model file, needs the `onnx` feature
+    Model {
+        path: String,
+        #[serde(default, skip_serializing_if = "is_default")]
+        pre: PreprocessSpec,
+        #[serde(default, skip_serializing_if = "is_default")]
+        post: ModelOutputSpec,
+    },
+    /// With `model`, a super-resolution ONNX model replaces `method`
+    Upscale {
+        factor: f32,
+        #[serde(default, skip_serializing_if = "is_default")]
+        method: UpscaleSpec,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        model: Option<String>,
+        #[serde(default, skip_serializing_if = "is_default")]
+        pre: PreprocessSpec,
+    },
+    /// Watermark image file, resolved relative to the working directory
+    Watermark {
+        path: String,
+        #[serde(default, skip_serializing_if = "is_default")]
+        anchor: AnchorSpec,
+        #[serde(default, skip_serializing_if = "is_default")]
+        margin: u32,
+        #[serde(
+            default = "default_opacity",
+            skip_serializing_if = "is_default_opacity"
+        )]
+        opacity: f32,
+        #[serde(default, skip_serializing_if = "is_default")]
+        tile: bool,
+        /// Mark width as a fraction of the image width; its own size when omitted
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        scale: Option<f32>,
+    },
+    DropShadow {
+        #[serde(default)]
+        offset_x: i32,
+        #[serde(default)]
+        offset_y: i32,
+        sigma: f32,
+        /// RGBA shadow color, opaque black when omitted
+        #[serde(default = "default_shadow_color")]
+        color: [u8; 4],
+        #[serde(
+            default = "default_opacity",
+            skip_serializing_if = "is_default_opacity"
+        )]
+        opacity: f32,
+    },
+    Masked {
+        region: RegionSpec,
+        op: Box<OperationSpec>,
+    },
+    /// A filter registered with `ImagePipeline::register_filter`
+    Custom {
+        name: String,
+        #[serde(default)]
+        params: Value,
+    },
+}
+
+/// Fit mode of a thumbnail operation
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FitSpec {
+    #[default]
+    Contain,
+    Cover,
+    Pad,
+}
+
+/// Fill of a pad operation
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum PadFillSpec {
+    Color,
+    #[default]
+    Transparent,
+    Replicate,
+    Mirror,
+}
+
+/// Weighting of a grayscale operation
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum GrayscaleSpec {
+    Bt601,
+    #[default]
+    Bt709,
+    Average,
+    Red,
+    Green,
+    Blue,
+}
+
+impl From<GrayscaleSpec> for GrayscaleMode {
+    fn from(spec: GrayscaleSpec) -> Self {
+        match spec {
+            GrayscaleSpec::Bt601 => GrayscaleMode::Bt601,
+            GrayscaleSpec::Bt709 => GrayscaleMode::Bt709,
+            GrayscaleSpec::Average => GrayscaleMode::Average,
+            GrayscaleSpec::Red => GrayscaleMode::Red,
+            GrayscaleSpec::Green => GrayscaleMode::Green,
+            GrayscaleSpec::Blue => GrayscaleMode::Blue,
+        }
+    }
+}
+
+impl From<GrayscaleMode> for GrayscaleSpec {
+    fn from(mode: GrayscaleMode) -> Self {
+        match mode {
+            GrayscaleMode::Bt601 => GrayscaleSpec::Bt601,
+            GrayscaleMode::Bt709 => GrayscaleSpec::Bt709,
+            GrayscaleMode::Average => GrayscaleSpec::Average,
+            GrayscaleMode::Red => GrayscaleSpec::Red,
+            GrayscaleMode::Green => GrayscaleSpec::Green,
+            GrayscaleMode::Blue => GrayscaleSpec::Blue,
+        }
+    }
+}
+
+/// Border handling of an edge detection operation
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EdgeBorderSpec {
+    #[default]
+    Zero,
+    Replicate,
+}
+
+/// Output of an edge detection operation
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum EdgeOutputSpec {
+    #[default]
+    Magnitude,
+    Direction,
+}
+
+/// Interpolation of a resize operation
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ResampleSpec {
+    Nearest,
+    Bilinear,
+    CatmullRom,
+    #[default]
+    Lanczos3,
+}
+
+impl From<ResampleSpec> for ResampleFilter {
+    fn from(spec: ResampleSpec) -> Self {
+        match spec {
+            ResampleSpec::Nearest => ResampleFilter::Nearest,
+            ResampleSpec::Bilinear => ResampleFilter::Bilinear,
+            ResampleSpec::CatmullRom => ResampleFilter::CatmullRom,
+            ResampleSpec::Lanczos3 => ResampleFilter::Lanczos3,
+        }
+    }
+}
+
+impl From<ResampleFilter> for ResampleSpec {
+    fn from(filter: ResampleFilter) -> Self {
+        match filter {
+            ResampleFilter::Nearest => ResampleSpec::Nearest,
+            ResampleFilter::Bilinear => ResampleSpec::Bilinear,
+            ResampleFilter::CatmullRom => ResampleSpec::CatmullRom,
+            ResampleFilter::Lanczos3 => ResampleSpec::Lanczos3,
+        }
+    }
+}
+
+/// Sampling of a geometric transform
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum InterpolationSpec {
+    Nearest,
+    #[default]
+    Bilinear,
+    Bicubic,
+}
+
+impl From<InterpolationSpec> for Interpolation {
+    fn from(spec: InterpolationSpec) -> Self {
+        match spec {
+            InterpolationSpec::Nearest => Interpolation::Nearest,
+            InterpolationSpec::Bilinear => Interpolation::Bilinear,
+            InterpolationSpec::Bicubic => Interpolation::Bicubic,
+        }
+    }
+}
+
+impl From<Interpolation> for InterpolationSpec {
+    fn from(interpolation: Interpolation) -> Self {
+        match interpolation {
+            Interpolation::Nearest => InterpolationSpec::Nearest,
+            Interpolation::Bilinear => InterpolationSpec::Bilinear,
+            Interpolation::Bicubic => InterpolationSpec::Bicubic,
+        }
+    }
+}
+
+/// Input tensor preparation of a model operation, see `Preprocess`
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PreprocessSpec {
+    /// `[width, height]` the image is resized to first
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub size: Option<[u32; 2]>,
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub layout: TensorLayoutSpec,
+    #[serde(default, skip_serializing_if = "is_default")]
+    pub mean: [f32; 3],
+    #[serde(default = "default_std", skip_serializing_if = "is_default_std")]
+    pub std: [f32; 3],
+}
+
+impl Default for PreprocessSpec {
+    fn default() -> Self {
+        Self::from(&Preprocess::default())
+    }
+}
+
+impl From<&PreprocessSpec> for Preprocess {
+    fn from(spec: &PreprocessSpec) -> Self {
+        Preprocess {
+            size: spec.size.map(|[width, height]| (width, height)),
+            layout: match spec.layout {
+                TensorLayoutSpec::Nchw => TensorLayout::Nchw,
+                TensorLayoutSpec::Nhwc => TensorLayout::Nhwc,
+            },
+            mean: spec.mean,
+            std: spec.std,
+        }
+    }
+}
+
+impl From<&Preprocess> for PreprocessSpec {
+    fn from(pre: &Preprocess) -> Self {
+        PreprocessSpec {
+            size: pre.size.map(|(width, height)| [width, height]),
+            layout: match pre.layout {
+                TensorLayout::Nchw => TensorLayoutSpec::Nchw,
+                TensorLayout::Nhwc => TensorLayoutSpec::Nhwc,
+            },
+            mean: pre.mean,
+            std: pre.std,
+        }
+    }
+}
+
+/// Classical method of an upscale operation
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum UpscaleSpec {
+    Lanczos,
+    #[default]
+    Detail,
+}
+
+/// Placement of a watermark
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum AnchorSpec {
+    TopLeft,
+    Top,
+    TopRight,
+    Left,
+    Center,
+    Right,
+    BottomLeft,
+    Bottom,
+    #[default]
+    BottomRight,
+}
+
+impl From<AnchorSpec> for Anchor {
+    fn from(spec: AnchorSpec) -> Self {
+        match spec {
+            AnchorSpec::TopLeft => Anchor::TopLeft,
+            AnchorSpec::Top => Anchor::Top,
+            AnchorSpec::TopRight => Anchor::TopRight,
+            AnchorSpec::Left => Anchor::Left,
+            AnchorSpec::Center => Anchor::Center,
+            AnchorSpec::Right => Anchor::Right,
+            AnchorSpec::BottomLeft => Anchor::BottomLeft,
+            AnchorSpec::Bottom => Anchor::Bottom,
+            AnchorSpec::BottomRight => Anchor::BottomRight,
+        }
+    }
+}
+
+impl From<Anchor> for AnchorSpec {
+    fn from(anchor: Anchor) -> Self {
+        match anchor {
+            Anchor::TopLeft => AnchorSpec::TopLeft,
+            Anchor::Top => AnchorSpec::Top,
+            Anchor::TopRight => AnchorSpec::TopRight,
+            Anchor::Left => AnchorSpec::Left,
+            Anchor::Center => AnchorSpec::Center,
+            Anchor::Right => AnchorSpec::Right,
+            Anchor::BottomLeft => AnchorSpec::BottomLeft,
+            Anchor::Bottom => AnchorSpec::Bottom,
+            Anchor::BottomRight => AnchorSpec::BottomRight,
+        }
+    }
+}
+
+/// Dimension order of a model's tensors
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TensorLayoutSpec {
+    #[default]
+    Nchw,
+    Nhwc,
+}
+
+/// What a model's output becomes
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ModelOutputSpec {
+    #[default]
+    Image,
+    Alpha,
+}
+
+impl From<ModelOutputSpec> for Postprocess {
+    fn from(spec: ModelOutputSpec) -> Self {
+        match spec {
+            ModelOutputSpec::Image => Postprocess::Image,
+            ModelOutputSpec::Alpha => Postprocess::Alpha,
+        }
+    }
+}
+
+impl From<Postprocess> for ModelOutputSpec {
+    fn from(post: Postprocess) -> Self {
+        match post {
+            Postprocess::Image => ModelOutputSpec::Image,
+            Postprocess::Alpha => ModelOutputSpec::Alpha,
+        }
+    }
+}
+
+/// One color of a gradient map ramp at `position` (0.0 to 1.0) of luminance
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct GradientStopSpec {
+    pub position: f32,
+    pub color: [u8; 4],
+}
+
+/// Region of a masked operation: a rectangle or a mask image path
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(untagged, deny_unknown_fields)]
+pub enum RegionSpec {
+    Rect {
+        x: u32,
+        y: u32,
+        width: u32,
+        height: u32,
+    },
+    Mask {
+        mask: String,
+    },
+}
+
+fn default_version() -> u32 {
+    SPEC_VERSION
+}
+
+fn is_default<T: Default + PartialEq>(value: &T) -> bool {
+    *value == T::default()
+}
+
+fn default_sharpen_amount() -> f32 {
+    SHARPEN_AMOUNT
+}
+
+fn is_default_sharpen_amount(amount: &f32) -> bool {
+    *amount == SHARPEN_AMOUNT
+}
+
+fn default_sharpen_radius() -> f32 {
+    SHARPEN_RADIUS
+}
+
+fn is_default_sharpen_radius(radius: &f32) -> bool {
+    *radius == SHARPEN_RADIUS
+}
+
+fn default_std() -> [f32; 3] {
+    [1.0; 3]
+}
+
+fn is_default_std(std: &[f32; 3]) -> bool {
+    *std == default_std()
+}
+
+fn default_key_color() -> [u8; 4] {
+    [0, 255, 0, 255]
+}
+
+fn default_shadow_color() -> [u8; 4] {
+    [0, 0, 0, 255]
+}
+
+fn default_opacity() -> f32 {
+    1.0
+}
+
+fn is_default_opacity(opacity: &f32) -> bool {
+    *opacity == default_opacity()
+}
+
+fn default_scale() -> f32 {
+    1.0
+}
+
+fn is_default_scale(scale: &f32) -> bool {
+    *scale == default_scale()
+}
+
+impl PipelineSpec {
+    /// Build a spec from an operation list
+    pub fn from_operations(operations: &[FilterOperation]) -> Self {
+        Self {
+            version: SPEC_VERSION,
+            operations: operations.iter().map(OperationSpec::from).collect(),
+        }
+    }
+
+    /// Parse a spec from JSON
+    pub fn from_json(json: &str) -> Result<Self> {
+        let value: Value = serde_json::from_str(json)
+            .map_err(|e| PipelineError::InvalidSpec(format!("malformed JSON: {}", e)))?;
+        Self::from_value(value)
+    }
+
+    /// Parse a spec from YAML
+    #[cfg(feature = "yaml")]
+    pub fn from_yaml(yaml: &str) -> Result<Self> {
+        let value: Value = serde_yaml::from_str(yaml)
+            .map_err(|e| PipelineError::InvalidSpec(format!("malformed YAML: {}", e)))?;
+        Self::from_value(value)
+    }
+
+    /// Serialize the spec to JSON
+    pub fn to_json(&self) -> String {
+        serde_json::to_string(self).expect("spec serialization cannot fail")
+    }
+
+    /// Validate all parameters and produce the executable operation list
+    pub fn compile(&self) -> Result<Vec<FilterOperation>> {
+        let operations = self
+            .operations
+            .iter()
+            .enumerate()
+            .map(|(index, op)| {
+                op.to_operation()
+                    .map_err(|e| PipelineError::InvalidSpec(format!("operation {}: {}", index, e)))
+            })
+            .collect::<Result<Vec<FilterOperation>>>()?;
+        ImagePipeline::validate(&operations)?;
+        Ok(operations)
+    }
+
+    // Operations are deserialized one at a time so errors can name the offending index
+    pub(crate) fn from_value(value: Value) -> Result<Self> {
+        let (version, operations) = match value {
+            Value::Array(items) => (SPEC_VERSION, items),
+            Value::Object(mut obj) => {
+                let version = match obj.remove("version") {
+                    None => SPEC_VERSION,
+                    Some(v) => v
+                        .as_u64()
+                        .and_then(|v| u32::try_from(v).ok())
+                        .ok_or_else(|| {
+                            PipelineError::InvalidSpec("\"version\" must be an integer".into())
+                        })?,
+                };
+                match obj.remove("operations") {
+                    Some(Value::Array(items)) => (version, items),
+                    Some(_) => {
+                        return Err(PipelineError::InvalidSpec(
+                            "\"operations\" must be an array".into(),
+                        ))
+                    }
+                    None => {
+                        return Err(PipelineError::InvalidSpec(
+                            "missing \"operations\" field".into(),
+                        ))
+                    }
+                }
+            }
+            _ => {
+                return Err(PipelineError::InvalidSpec(
+                    "expected an array of operations or an object with \"operations\"".into(),
+                ))
+            }
+        };
+
+        if version > SPEC_VERSION {
+            return Err(PipelineError::InvalidSpec(format!(
+                "unsupported spec version {} (latest is {})",
+                version, SPEC_VERSION
+            )));
+        }
+
+        let operations = operations
+            .into_iter()
+            .enumerate()
+            .map(|(index, item)| {
+                serde_json::from_value(item)
+                    .map_err(|e| PipelineError::InvalidSpec(format!("operation {}: {}", index, e)))
+            })
+            .collect::<Result<Vec<OperationSpec>>>()?;
+
+        Ok(Self {
+            version,
+            operations,
+        })
+    }
+}
+
+impl OperationSpec {
+    /// Whether compiling this operation reads a file (a LUT, model, mark or
+    /// mask image), which services running untrusted specs must refuse
+    ///
+    /// Every operation with a path field has to be listed here.
+    pub fn reads_files(&self) -> bool {
+        match self {
+            OperationSpec::Lut3d { .. }
+            | OperationSpec::Model { .. }
+            | OperationSpec::Watermark { .. } => true,
+            OperationSpec::PortraitBlur { mask, .. } => {
+                matches!(mask, Some(RegionSpec::Mask { .. }))
+            }
+            OperationSpec::Upscale { model, .. } => model.is_some(),
+            OperationSpec::Masked { region, op } => {
+                matches!(region, RegionSpec::Mask { .. }) || op.reads_files()
+            }
+            OperationSpec::Grayscale { .. }
+            | OperationSpec::Brightness { .. }
+            | OperationSpec::Contrast { .. }
+            | OperationSpec::Blur { .. }
+            | OperationSpec::Sharpen { .. }
+            | OperationSpec::EdgeDetect { .. }
+            | OperationSpec::Resize { .. }
+            | OperationSpec::Thumbnail { .. }
+            | OperationSpec::SmartCrop { .. }
+            | OperationSpec::SeamCarve { .. }
+            | OperationSpec::Pad { .. }
+            | OperationSpec::Invert
+            | OperationSpec::Sepia
+            | OperationSpec::ColorMatrix { .. }
+            | OperationSpec::Gamma { .. }
+            | OperationSpec::Noise { .. }
+            | OperationSpec::Pixelate { .. }
+            | OperationSpec::Deskew
+            | OperationSpec::Rotate { .. }
+            | OperationSpec::Affine { .. }
+            | OperationSpec::LensCorrect { .. }
+            | OperationSpec::ChromaticAberration { .. }
+            | OperationSpec::ChromaKey { .. }
+            | OperationSpec::GradientMap { .. }
+            | OperationSpec::SelectiveColor { .. }
+            | OperationSpec::ShadowsHighlights { .. }
+            | OperationSpec::DropShadow { .. }
+            | OperationSpec::Custom { .. } => false,
+        }
+    }
+
+    /// Convert to an executable operation without validating parameters
+    ///
+    /// Fails only when an operation refers to a resource that cannot be loaded.
+    pub fn to_operation(&self) -> Result<FilterOperation> {
+        Ok(match *self {
+            OperationSpec::Grayscale { mode } => FilterOperation::Grayscale(mode.into()),
+            OperationSpec::Brightness { value } => FilterOperation::Brightness(value),
+            OperationSpec::Contrast { value } => FilterOperation::Contrast(value),
+            OperationSpec::Blur { sigma, approximate } => {
+                if approximate {
+                    FilterOperation::ApproximateBlur(sigma)
+                } else {
+                    FilterOperation::Blur(sigma)
+                }
+            }
+            OperationSpec::Sharpen {
+                amount,
+                radius,
+                threshold,
+            } => FilterOperation::Sharpen {
+                amount,
+                radius,
+                threshold,
+            },
+            OperationSpec::EdgeDetect {
+                border,
+                output,
+                threshold,
+            } => FilterOperation::EdgeDetect(EdgeOptions {
+                border: match border {
+                    EdgeBorderSpec::Zero => EdgeBorder::Zero,
+                    EdgeBorderSpec::Replicate => EdgeBorder::Replicate,
+                },
+                output: match output {
+                    EdgeOutputSpec::Magnitude => EdgeOutput::Magnitude,
+                    EdgeOutputSpec::Direction => EdgeOutput::Direction,
+                },
+                threshold,
+            }),
+            OperationSpec::Resize {
+                width,
+                height,
+                filter,
+            } => FilterOperation::Resize {
+                width,
+                height,
+                filter: filter.into(),
+            },
+            OperationSpec::SmartCrop { width, height } => {
+                FilterOperation::SmartCrop { width, height }
+            }
+            OperationSpec::SeamCarve { width, height } => {
+                FilterOperation::SeamCarve { width, height }
+            }
+            OperationSpec::Pad {
+                top,
+                right,
+                bottom,
+                left,
+                fill,
+                color,
+            } => FilterOperation::Pad {
+                top,
+                right,
+                bottom,
+                left,
+                fill: match fill {
+                    PadFillSpec::Color => PadFill::Color(Rgba(color.unwrap_or([0; 4]))),
+                    PadFillSpec::Transparent => PadFill::Transparent,
+                    PadFillSpec::Replicate => PadFill::Replicate,
+                    PadFillSpec::Mirror => PadFill::Mirror,
+                },
+            },
+            OperationSpec::Thumbnail {
+                width,
+                height,
+                fit,
+                background,
+            } => FilterOperation::Thumbnail {
+                width,
+                height,
+                fit: match fit {
+                    FitSpec::Contain => FitMode::Contain,
+                    FitSpec::Cover => FitMode::Cover,
+                    FitSpec::Pad => FitMode::Pad(Rgba(background.unwrap_or([0; 4]))),
+                },
+            },
+            OperationSpec::Invert => FilterOperation::Invert,
+            OperationSpec::Sepia => FilterOperation::Sepia,
+            OperationSpec::ColorMatrix { matrix } => FilterOperation::ColorMatrix(matrix),
+            OperationSpec::Gamma { value } => FilterOperation::Gamma(value),
+            OperationSpec::Noise {
+                amount,
+                monochrome,
+                seed,
+            } => FilterOperation::Noise {
+                amount,
+                monochrome,
+                seed,
+            },
+            OperationSpec::Pixelate { block_size } => FilterOperation::Pixelate(block_size),
+            OperationSpec::Deskew => FilterOperation::Deskew,
+            OperationSpec::Rotate {
+                degrees,
+                background,
+                expand,
+            } => FilterOperation::Rotate {
+                degrees,
+                background: Rgba(background),
+                expand,
+            },
+            OperationSpec::Affine {
+                matrix,
+                interpolation,
+                background,
+            } => FilterOperation::Affine {
+                matrix,
+                interpolation: interpolation.into(),
+                background: Rgba(background),
+            },
+            OperationSpec::LensCorrect { k1, k2, k3 } => {
+                FilterOperation::LensCorrect { k1, k2, k3 }
+            }
+            OperationSpec::ChromaticAberration {
+                red_shift,
+                blue_shift,
+            } => FilterOperation::ChromaticAberration {
+                red_shift,
+                blue_shift,
+            },
+            OperationSpec::ChromaKey {
+                key_color,
+                tolerance,
+                softness,
+            } => FilterOperation::ChromaKey {
+                key_color: Rgba(key_color),
+                tolerance,
+                softness,
+            },
+            OperationSpec::GradientMap { ref stops } => FilterOperation::GradientMap(
+                stops
+                    .iter()
+                    .map(|stop| (stop.position, Rgba(stop.color)))
+                    .collect(),
+            ),
+            OperationSpec::SelectiveColor {
+                hue_min,
+                hue_max,
+                hue_shift,
+                saturation,
+                lightness,
+            } => FilterOperation::SelectiveColor {
+                hue_range: (hue_min, hue_max),
+                hue_shift,
+                saturation,
+                lightness,
+            },
+            OperationSpec::ShadowsHighlights {
+                shadows,
+                highlights,
+                radius,
+            } => FilterOperation::ShadowsHighlights {
+                shadows,
+                highlights,
+                radius,
+            },
+            OperationSpec::DropShadow {
+                offset_x,
+                offset_y,
+                sigma,
+                color,
+                opacity,
+            } => FilterOperation::DropShadow {
+                offset: (offset_x, offset_y),
+                sigma,
+                color: Rgba(color),
+                opacity,
+            },
+            OperationSpec::Model {
+                ref path,
+                ref pre,
+                post,
+            } => FilterOperation::Model {
+                model: Arc::new(Model::load(path)?),
+                pre: Preprocess::from(pre),
+                post: post.into(),
+            },
+            OperationSpec::Upscale {
+                factor,
+                method,
+                ref model,
+                ref pre,
+            } => FilterOperation::Upscale {
+                factor,
+                method: match (model, method) {
+                    (Some(path), _) => UpscaleMethod::Model {
+                        model: Arc::new(Model::load(path)?),
+                        pre: Preprocess::from(pre),
+                    },
+                    (None, UpscaleSpec::Lanczos) => UpscaleMethod::Lanczos,
+                    (None, UpscaleSpec::Detail) => UpscaleMethod::Detail,
+                },
+            },
+            OperationSpec::Watermark {
+                ref path,
+                anchor,
+                margin,
+                opacity,
+                tile,
+                scale,
+            } => FilterOperation::Watermark {
+                mark: Arc::new(Mark::load(path)?),
+                anchor: anchor.into(),
+                margin,
+                opacity,
+                tile,
+                scale,
+            },
+            OperationSpec::PortraitBlur {
+                ref mask,
+                sigma,
+                feather,
+            } => FilterOperation::PortraitBlur {
+                mask: mask.as_ref().map(RegionSpec::to_region).transpose()?,
+                sigma,
+                feather,
+            },
+            OperationSpec::Masked { ref region, ref op } => FilterOperation::Masked {
+                region: region.to_region()?,
+                op: Box::new(op.to_operation()?),
+            },
+            OperationSpec::Lut3d { ref path } => {
+                FilterOperation::Lut3D(Arc::new(Lut3D::load_cube(path)?))
+            }
+            OperationSpec::Custom {
+                ref name,
+                ref params,
+            } => FilterOperation::Custom {
+                name: name.clone(),
+                params: params.clone(),
+            },
+        })
+    }
+}
+
+impl RegionSpec {
+    /// Convert to a region, loading the mask image if one is referenced
+    pub fn to_region(&self) -> Result<Region> {
+        match *self {
+            RegionSpec::Rect {
+                x,
+                y,
+                width,
+                height,
+            } => Ok(Region::Rect {
+                x,
+                y,
+                width,
+                height,
+            }),
+            RegionSpec::Mask { ref mask } => Region::load_mask(mask),
+        }
+    }
+}
+
+impl From<&Region> for RegionSpec {
+    fn from(region: &Region) -> Self {
+        match *region {
+            Region::Rect {
+                x,
+                y,
+                width,
+                height,
+            } => RegionSpec::Rect {
+                x,
+                y,
+                width,
+                height,
+            },
+            // In-memory masks have no path and cannot be reloaded from a spec
+            Region::Mask(_) => RegionSpec::Mask {
+                mask: String::new(),
+            },
+        }
+    }
+}
+
+impl From<&FilterOperation> for OperationSpec {
+    fn from(op: &FilterOperation) -> Self {
+        match *op {
+            FilterOperation::Grayscale(mode) => OperationSpec::Grayscale { mode: mode.into() },
+            FilterOperation::Brightness(value) => OperationSpec::Brightness { value },
+            FilterOperation::Contrast(value) => OperationSpec::Contrast { value },
+            FilterOperation::Blur(sigma) => OperationSpec::Blur {
+                sigma,
+                approximate: false,
+            },
+            FilterOperation::ApproximateBlur(sigma) => OperationSpec::Blur {
+                sigma,
+                approximate: true,
+            },
+            FilterOperation::Sharpen {
+                amount,
+                radius,
+                threshold,
+            } => OperationSpec::Sharpen {
+                amount,
+                radius,
+                threshold,
+            },
+            FilterOperation::EdgeDetect(options) => OperationSpec::EdgeDetect {
+                border: match options.border {
+                    EdgeBorder::Zero => EdgeBorderSpec::Zero,
+                    EdgeBorder::Replicate => EdgeBorderSpec::Replicate,
+                },
+                output: match options.output {
+                    EdgeOutput::Magnitude => EdgeOutputSpec::Magnitude,
+                    EdgeOutput::Direction => EdgeOutputSpec::Direction,
+                },
+                threshold: options.threshold,
+            },
+            FilterOperation::Resize {
+                width,
+                height,
+                filter,
+            } => OperationSpec::Resize {
+                width,
+                height,
+                filter: filter.into(),
+            },
+            FilterOperation::SmartCrop { width, height } => {
+                OperationSpec::SmartCrop { width, height }
+            }
+            FilterOperation::SeamCarve { width, height } => {
+                OperationSpec::SeamCarve { width, height }
+            }
+            FilterOperation::Pad {
+                top,
+                right,
+                bottom,
+                left,
+                fill,
+            } => {
+                let (fill, color) = match fill {
+                    PadFill::Color(color) => (PadFillSpec::Color, Some(color.0)),
+                    PadFill::Transparent => (PadFillSpec::Transparent, None),
+                    PadFill::Replicate => (PadFillSpec::Replicate, None),
+                    PadFill::Mirror => (PadFillSpec::Mirror, None),
+                };
+                OperationSpec::Pad {
+                    top,
+                    right,
+                    bottom,
+                    left,
+                    fill,
+                    color,
+                }
+            }
+            FilterOperation::Thumbnail { width, height, fit } => {
+                let (fit, background) = match fit {
+                    FitMode::Contain => (FitSpec::Contain, None),
+                    FitMode::Cover => (FitSpec::Cover, None),
+                    FitMode::Pad(color) => (FitSpec::Pad, Some(color.0)),
+                };
+                OperationSpec::Thumbnail {
+                    width,
+                    height,
+                    fit,
+                    background,
+                }
+            }
+            FilterOperation::Invert => OperationSpec::Invert,
+            FilterOperation::Sepia => OperationSpec::Sepia,
+            FilterOperation::ColorMatrix(matrix) => OperationSpec::ColorMatrix { matrix },
+            FilterOperation::Gamma(value) => OperationSpec::Gamma { value },
+            FilterOperation::Noise {
+                amount,
+                monochrome,
+                seed,
+            } => OperationSpec::Noise {
+                amount,
+                monochrome,
+                seed,
+            },
+            FilterOperation::Pixelate(block_size) => OperationSpec::Pixelate { block_size },
+            FilterOperation::Deskew => OperationSpec::Deskew,
+            FilterOperation::Rotate {
+                degrees,
+                background,
+                expand,
+            } => OperationSpec::Rotate {
+                degrees,
+                background: background.0,
+                expand,
+            },
+            FilterOperation::Affine {
+                matrix,
+                interpolation,
+                background,
+            } => OperationSpec::Affine {
+                matrix,
+                interpolation: interpolation.into(),
+                background: background.0,
+            },
+            FilterOperation::LensCorrect { k1, k2, k3 } => {
+                OperationSpec::LensCorrect { k1, k2, k3 }
+            }
+            FilterOperation::ChromaticAberration {
+                red_shift,
+                blue_shift,
+            } => OperationSpec::ChromaticAberration {
+                red_shift,
+                blue_shift,
+            },
+            FilterOperation::ChromaKey {
+                key_color,
+                tolerance,
+                softness,
+            } => OperationSpec::ChromaKey {
+                key_color: key_color.0,
+                tolerance,
+                softness,
+            },
+            FilterOperation::GradientMap(ref stops) => OperationSpec::GradientMap {
+                stops: stops
+                    .iter()
+                    .map(|&(position, color)| GradientStopSpec {
+                        position,
+                        color: color.0,
+                    })
+                    .collect(),
+            },
+            FilterOperation::SelectiveColor {
+                hue_range: (hue_min, hue_max),
+                hue_shift,
+                saturation,
+                lightness,
+            } => OperationSpec::SelectiveColor {
+                hue_min,
+                hue_max,
+                hue_shift,
+                saturation,
+                lightness,
+            },
+            FilterOperation::ShadowsHighlights {
+                shadows,
+                highlights,
+                radius,
+            } => OperationSpec::ShadowsHighlights {
+                shadows,
+                highlights,
+                radius,
+            },
+            FilterOperation::DropShadow {
+                offset,
+                sigma,
+                color,
+                opacity,
+            } => OperationSpec::DropShadow {
+                offset_x: offset.0,
+                offset_y: offset.1,
+                sigma,
+                color: color.0,
+                opacity,
+            },
+            // Models parsed from memory have no path and cannot be reloaded from a spec
+            FilterOperation::Model {
+                ref model,
+                ref pre,
+                post,
+            } => OperationSpec::Model {
+                path: model.source().unwrap_or_default().to_string(),
+                pre: PreprocessSpec::from(pre),
+                post: post.into(),
+            },
+            FilterOperation::Upscale { factor, ref method } => {
+                let (method, model, pre) = match method {
+                    UpscaleMethod::Lanczos => {
+                        (UpscaleSpec::Lanczos, None, PreprocessSpec::default())
+                    }
+                    UpscaleMethod::Detail => (UpscaleSpec::Detail, None, PreprocessSpec::default()),
+                    UpscaleMethod::Model { model, pre } => (
+                        UpscaleSpec::default(),
+                        Some(model.source().unwrap_or_default().to_string()),
+                        PreprocessSpec::from(pre),
+                    ),
+                };
+                OperationSpec::Upscale {
+                    factor,
+                    method,
+                    model,
+                    pre,
+                }
+            }
+            // Marks created in memory have no path and cannot be reloaded from a spec
+            FilterOperation::Watermark {
+                ref mark,
+                anchor,
+                margin,
+                opacity,
+                tile,
+                scale,
+            } => OperationSpec::Watermark {
+                path: mark.source().unwrap_or_default().to_string(),
+                anchor: anchor.into(),
+                margin,
+                opacity,
+                tile,
+                scale,
+            },
+            FilterOperation::PortraitBlur {
+                ref mask,
+                sigma,
+                feather,
+            } => OperationSpec::PortraitBlur {
+                mask: mask.as_ref().map(RegionSpec::from),
+                sigma,
+                feather,
+            },
+            FilterOperation::Masked { ref region, ref op } => OperationSpec::Masked {
+                region: RegionSpec::from(region),
+                op: Box::new(OperationSpec::from(op.as_ref())),
+            },
+            // LUTs parsed from memory have no path and cannot be reloaded from a spec
+            FilterOperation::Lut3D(ref lut) => OperationSpec::Lut3d {
+                path: lut.source().unwrap_or_default().to_string(),
+            },
+            FilterOperation::Custom {
+                ref name,
+                ref params,
+            } => OperationSpec::Custom {
+                name: name.clone(),
+                params: params.clone(),
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_bare_array() {
+        let spec = PipelineSpec::from_json(
+            r#"[{"type": "grayscale"}, {"type": "blur", "sigma": 2.0},
+                {"type": "resize", "width": 10, "height": 20}]"#,
+        )
+        .unwrap();
+        let ops = spec.compile().unwrap();
+
+        assert_eq!(ops.len(), 3);
+        assert!(matches!(ops[1], FilterOperation::Blur(s) if s == 2.0));
+        assert!(matches!(
+            ops[2],
+            FilterOperation::Resize {
+                width: 10,
+                height: 20,
+                filter: ResampleFilter::Lanczos3,
+            }
+        ));
+    }
+
+    #[test]
+    fn test_resize_filter() {
+        let spec = PipelineSpec::from_json(
+            r#"[{"type": "resize", "width": 8, "height": 8, "filter": "nearest"}]"#,
+        )
+        .unwrap();
+        assert!(matches!(
+            spec.compile().unwrap()[0],
+            FilterOperation::Resize {
+                filter: ResampleFilter::Nearest,
+                ..
+            }
+        ));
+        assert!(PipelineSpec::from_json(
+            r#"[{"type": "resize", "width": 8, "height": 8, "filter": "box"}]"#
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_parse_versioned_object() {
+        let spec = PipelineSpec::from_json(
+            r#"{"version": 1, "operations": [{"type": "blur", "value": 1.5}]}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            spec.operations,
+            vec![OperationSpec::Blur {
+                sigma: 1.5,
+                approximate: false
+            }]
+        );
+    }
+
+    #[test]
+    fn test_parse_approximate_blur() {
+        let json = r#"[{"type":"blur","sigma":12.0,"approximate":true}]"#;
+        let spec = PipelineSpec::from_json(json).unwrap();
+        let ops = spec.compile().unwrap();
+        assert!(matches!(ops[0], FilterOperation::ApproximateBlur(s) if s == 12.0));
+        assert_eq!(PipelineSpec::from_operations(&ops), spec);
+        // The flag is left out when false, so existing specs serialize as before
+        let exact = PipelineSpec::from_operations(&[FilterOperation::Blur(1.0)]);
+        assert!(!exact.to_json().contains("approximate"));
+    }
+
+    #[test]
+    fn test_parse_color_matrix() {
+        let json = r#"[{"type":"color_matrix","matrix":[[0,0,1,0,0],[0,1,0,0,0],[1,0,0,0,0],[0,0,0,1,0]]}]"#;
+        let spec = PipelineSpec::from_json(json).unwrap();
+        let ops = spec.compile().unwrap();
+        assert!(matches!(ops[0], FilterOperation::ColorMatrix(m) if m[0][2] == 1.0));
+        assert_eq!(PipelineSpec::from_operations(&ops), spec);
+        // Rows must have five entries
+        assert!(PipelineSpec::from_json(
+            r#"[{"type":"color_matrix","matrix":[[1,0,0,0],[0,1,0,0],[0,0,1,0],[0,0,0,1]]}]"#
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_parse_grayscale_mode() {
+        let spec = PipelineSpec::from_json(
+            r#"[{"type": "grayscale"}, {"type": "grayscale", "mode": "red"}]"#,
+        )
+        .unwrap();
+        let ops = spec.compile().unwrap();
+        assert!(matches!(
+            ops[0],
+            FilterOperation::Grayscale(GrayscaleMode::Bt709)
+        ));
+        assert!(matches!(
+            ops[1],
+            FilterOperation::Grayscale(GrayscaleMode::Red)
+        ));
+        assert_eq!(PipelineSpec::from_operations(&ops), spec);
+        assert!(PipelineSpec::from_json(r#"[{"type": "grayscale", "mode": "luma"}]"#).is_err());
+    }
+
+    #[test]
+    fn test_parse_sharpen_defaults() {
+        let spec = PipelineSpec::from_json(
+            r#"[{"type": "sharpen"}, {"type": "sharpen", "amount": 0.8, "radius": 2.0, "threshold": 4}]"#,
+        )
+        .unwrap();
+        let ops = spec.compile().unwrap();
+        assert!(matches!(
+            ops[0],
+            FilterOperation::Sharpen { amount, radius, threshold: 0 }
+                if amount == SHARPEN_AMOUNT && radius == SHARPEN_RADIUS
+        ));
+        assert!(matches!(
+            ops[1],
+            FilterOperation::Sharpen { amount, radius, threshold: 4 } if amount == 0.8 && radius == 2.0
+        ));
+        assert_eq!(PipelineSpec::from_operations(&ops), spec);
+        assert!(PipelineSpec::from_operations(&ops[..1])
+            .to_json()
+            .contains(r#"{"type":"sharpen"}"#));
+    }
+
+    #[test]
+    fn test_parse_edge_detect_options() {
+        let spec = PipelineSpec::from_json(
+            r#"[{"type": "edge_detect"},
+                {"type": "edge_detect", "border": "replicate", "output": "direction", "threshold": 30}]"#,
+        )
+        .unwrap();
+        let ops = spec.compile().unwrap();
+        assert!(matches!(ops[0], FilterOperation::EdgeDetect(o) if o == EdgeOptions::default()));
+        assert!(matches!(
+            ops[1],
+            FilterOperation::EdgeDetect(EdgeOptions {
+                border: EdgeBorder::Replicate,
+                output: EdgeOutput::Direction,
+                threshold: Some(30),
+            })
+        ));
+        assert_eq!(PipelineSpec::from_operations(&ops), spec);
+        assert!(PipelineSpec::from_operations(&ops[..1])
+            .to_json()
+            .contains(r#"{"type":"edge_detect"}"#));
+    }
+
+    #[test]
+    fn test_parse_errors_name_operation() {
+        let err =
+            PipelineSpec::from_json(r#"[{"type": "invert"}, {"type": "brightness"}]"#).unwrap_err();
+        assert!(err.to_string().contains("operation 1"));
+
+        let err = PipelineSpec::from_json(r#"[{"type": "blurr", "sigma": 1.0}]"#).unwrap_err();
+        assert!(err.to_string().contains("blurr"));
+    }
+
+    #[test]
+    fn test_compile_validates_ranges() {
+        let spec = PipelineSpec::from_json(r#"[{"type": "sepia"}, {"type": "blur", "sigma": -3}]"#)
+            .unwrap();
+        let err = spec.compile().unwrap_err();
+        assert!(matches!(err, PipelineError::InvalidParameter(_)));
+        assert!(err.to_string().contains("operation 1 (blur)"));
+    }
+
+    #[test]
+    fn test_json_roundtrip() {
+        let ops = vec![
+            FilterOperation::Contrast(1.2),
+            FilterOperation::Resize {
+                width: 64,
+                height: 32,
+                filter: ResampleFilter::Nearest,
+            },
+        ];
+        let spec = PipelineSpec::from_operations(&ops);
+        let parsed = PipelineSpec::from_json(&spec.to_json()).unwrap();
+        assert_eq!(parsed, spec);
+    }
+
+    #[test]
+    fn test_parse_thumbnail() {
+        let spec = PipelineSpec::from_json(
+            r#"[{"type": "thumbnail", "width": 64, "height": 64},
+                {"type": "thumbnail", "width": 32, "height": 16, "fit": "pad", "background": [255, 255, 255, 255]}]"#,
+        )
+        .unwrap();
+        let ops = spec.compile().unwrap();
+
+        assert!(matches!(
+            ops[0],
+            FilterOperation::Thumbnail {
+                fit: FitMode::Contain,
+                ..
+            }
+        ));
+        assert!(matches!(
+            ops[1],
+            FilterOperation::Thumbnail {
+                fit: FitMode::Pad(Rgba([255, 255, 255, 255])),
+                ..
+            }
+        ));
+        assert_eq!(PipelineSpec::from_operations(&ops), spec);
+    }
+
+    #[test]
+    fn test_parse_pad() {
+        let spec = PipelineSpec::from_json(
+            r#"[{"type": "pad", "top": 10, "bottom": 10},
+                {"type": "pad", "left": 4, "right": 4, "fill": "color", "color": [0, 0, 0, 255]},
+                {"type": "pad", "top": 8, "fill": "mirror"}]"#,
+        )
+        .unwrap();
+        let ops = spec.compile().unwrap();
+
+        assert!(matches!(
+            ops[0],
+            FilterOperation::Pad {
+                top: 10,
+                right: 0,
+                bottom: 10,
+                left: 0,
+                fill: PadFill::Transparent,
+            }
+        ));
+        assert!(matches!(
+            ops[1],
+            FilterOperation::Pad {
+                left: 4,
+                fill: PadFill::Color(Rgba([0, 0, 0, 255])),
+                ..
+            }
+        ));
+        assert!(matches!(
+            ops[2],
+            FilterOperation::Pad {
+                fill: PadFill::Mirror,
+                ..
+            }
+        ));
+        assert_eq!(PipelineSpec::from_operations(&ops), spec);
+    }
+
+    #[test]
+    fn test_parse_drop_shadow() {
+        let spec = PipelineSpec::from_json(
+            r#"[{"type": "drop_shadow", "offset_x": 4, "offset_y": 6, "sigma": 5.0, "opacity": 0.4}]"#,
+        )
+        .unwrap();
+        let ops = spec.compile().unwrap();
+        assert!(matches!(
+            ops[0],
+            FilterOperation::DropShadow {
+                offset: (4, 6),
+                color: Rgba([0, 0, 0, 255]),
+                ..
+            }
+        ));
+        assert_eq!(PipelineSpec::from_operations(&ops), spec);
+        assert!(
+            PipelineSpec::from_json(r#"[{"type": "drop_shadow", "sigma": -1.0}]"#)
+                .unwrap()
+                .compile()
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_parse_gradient_map() {
+        let spec = PipelineSpec::from_json(
+            r#"[{"type": "gradient_map", "stops": [
+                    {"position": 0.0, "color": [20, 30, 90, 255]},
+                    {"position": 1.0, "color": [250, 120, 180, 255]}]}]"#,
+        )
+        .unwrap();
+        let ops = spec.compile().unwrap();
+        match &ops[0] {
+            FilterOperation::GradientMap(stops) => {
+                assert_eq!(stops.len(), 2);
+                assert_eq!(stops[1], (1.0, Rgba([250, 120, 180, 255])));
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+        assert_eq!(PipelineSpec::from_operations(&ops), spec);
+        assert!(
+            PipelineSpec::from_json(r#"[{"type": "gradient_map", "stops": []}]"#)
+                .unwrap()
+                .compile()
+                .is_err()
+        );
+    }
+
+    #[test]
+    fn test_parse_selective_color() {
+        let spec = PipelineSpec::from_json(
+            r#"[{"type": "selective_color", "hue_min": 190, "hue_max": 250, "saturation": 1.3}]"#,
+        )
+        .unwrap();
+        let ops = spec.compile().unwrap();
+        assert!(matches!(
+            ops[0],
+            FilterOperation::SelectiveColor {
+                hue_range: (190.0, 250.0),
+                hue_shift: 0.0,
+                lightness: 0.0,
+                ..
+            }
+        ));
+        assert_eq!(PipelineSpec::from_operations(&ops), spec);
+        assert!(PipelineSpec::from_json(
+            r#"[{"type": "selective_color", "hue_min": 0, "hue_max": 30, "lightness": 2.0}]"#
+        )
+        .unwrap()
+        .compile()
+        .is_err());
+    }
+
+    #[test]
+    fn test_parse_shadows_highlights() {
+        let spec = PipelineSpec::from_json(
+            r#"[{"type": "shadows_highlights", "shadows": 0.5, "radius": 30.0}]"#,
+        )
+        .unwrap();
+        let ops = spec.compile().unwrap();
+        assert!(matches!(
+            ops[0],
+            FilterOperation::ShadowsHighlights {
+                shadows: 0.5,
+                highlights: 0.0,
+                radius: 30.0,
+            }
+        ));
+        assert_eq!(PipelineSpec::from_operations(&ops), spec);
+        assert!(PipelineSpec::from_json(
+            r#"[{"type": "shadows_highlights", "highlights": 1.5, "radius": 30.0}]"#
+        )
+        .unwrap()
+        .compile()
+        .is_err());
+    }
+
+    #[test]
+    fn test_reads_files() {
+        let reads = |json: &str| PipelineSpec::from_json(json).unwrap().operations[0].reads_files();
+        assert!(reads(r#"[{"type": "lut3d", "path": "a.cube"}]"#));
+        assert!(reads(r#"[{"type": "model", "path": "a.onnx"}]"#));
+        assert!(reads(r#"[{"type": "watermark", "path": "logo.png"}]"#));
+        assert!(reads(
+            r#"[{"type": "upscale", "factor": 2.0, "model": "sr.onnx"}]"#
+        ));
+        assert!(reads(
+            r#"[{"type": "portrait_blur", "sigma": 4.0, "mask": {"mask": "m.png"}}]"#
+        ));
+        assert!(reads(
+            r#"[{"type": "masked", "region": {"x": 0, "y": 0, "width": 1, "height": 1},
+                 "op": {"type": "watermark", "path": "logo.png"}}]"#
+        ));
+        assert!(!reads(r#"[{"type": "upscale", "factor": 2.0}]"#));
+        assert!(!reads(r#"[{"type": "blur", "sigma": 2.0}]"#));
+    }
+
+    #[test]
+    fn test_parse_affine() {
+        let spec = PipelineSpec::from_json(
+            r#"[{"type": "affine", "matrix": [1, 0.2, 0, 0, 1, 0]},
+                {"type": "affine", "matrix": [0, 0, 1, 0, 0, 1]}]"#,
+        )
+        .unwrap();
+        let op = spec.operations[0].to_operation().unwrap();
+        assert!(matches!(
+            op,
+            FilterOperation::Affine {
+                interpolation: Interpolation::Bilinear,
+                background: Rgba([0, 0, 0, 0]),
+                ..
+            }
+        ));
+        // A singular matrix parses but fails validation
+        assert!(spec.compile().is_err());
+    }
+
+    #[test]
+    fn test_parse_masked_rect() {
+        let spec = PipelineSpec::from_json(
+            r#"[{"type": "masked", "region": {"x": 1, "y": 2, "width": 3, "height": 4},
+                 "op": {"type": "blur", "sigma": 2.0}}]"#,
+        )
+        .unwrap();
+        let ops = spec.compile().unwrap();
+        assert!(matches!(
+            &ops[0],
+            FilterOperation::Masked { region: Region::Rect { width: 3, .. }, op }
+                if matches!(**op, FilterOperation::Blur(_))
+        ));
+        assert_eq!(PipelineSpec::from_operations(&ops), spec);
+    }
+
+    #[test]
+    fn test_lut3d_loads_cube_file() {
+        let path = std::env::temp_dir().join("image_pipeline_spec_identity.cube");
+        std::fs::write(
+            &path,
+            "LUT_3D_SIZE 2\n0 0 0\n1 0 0\n0 1 0\n1 1 0\n0 0 1\n1 0 1\n0 1 1\n1 1 1\n",
+        )
+        .unwrap();
+
+        let json = format!(
+            r#"[{{"type": "lut3d", "path": {:?}}}]"#,
+            path.display().to_string()
+        );
+        let ops = PipelineSpec::from_json(&json).unwrap().compile().unwrap();
+        assert!(matches!(&ops[0], FilterOperation::Lut3D(lut) if lut.size() == 2));
+
+        let missing =
+            PipelineSpec::from_json(r#"[{"type": "lut3d", "path": "/nonexistent.cube"}]"#)
+                .unwrap()
+                .compile()
+                .unwrap_err();
+        assert!(missing.to_string().contains("operation 0"));
+        std::fs::remove_file(path).ok();
+    }
+
+    #[test]
+    fn test_watermark_spec_round_trip() {
+        let path = std::env::temp_dir().join("image_pipeline_spec_mark.png");
+        image::RgbaImage::from_pixel(4, 2, Rgba([255, 255, 255, 128]))
+            .save(&path)
+            .unwrap();
+
+        let json = format!(
+            r#"[{{"type": "watermark", "path": {:?}, "anchor": "top_right", "opacity": 0.5}}]"#,
+            path.display().to_string()
+        );
+        let spec = PipelineSpec::from_json(&json).unwrap();
+        let ops = spec.compile().unwrap();
+        let FilterOperation::Watermark {
+            ref mark,
+            anchor,
+            margin,
+            tile,
+            scale,
+            ..
+        } = ops[0]
+        else {
+            panic!("expected a watermark, got {:?}", ops[0]);
+        };
+        assert_eq!(mark.image().dimensions(), (4, 2));
+        assert_eq!(
+            (anchor, margin, tile, scale),
+            (Anchor::TopRight, 0, false, None)
+        );
+        assert_eq!(PipelineSpec::from_operations(&ops), spec);
+        std::fs::remove_file(path).ok();
+    }
+
+    #[test]
+    fn test_model_spec_parameters() {
+        let spec: OperationSpec = serde_json::from_value(serde_json::json!({
+            "type": "model",
+            "path": "/nonexistent.onnx",
+            "pre": {"size": [224, 224], "layout": "nhwc", "std": [0.5, 0.5, 0.5]},
+            "post": "alpha",
+        }))
+        .unwrap();
+        let OperationSpec::Model { ref pre, post, .. } = spec else {
+            panic!("not a model spec: {:?}", spec);
+        };
+        let pre = Preprocess::from(pre);
+        assert_eq!(pre.size, Some((224, 224)));
+        assert_eq!(pre.layout, TensorLayout::Nhwc);
+        assert_eq!(pre.mean, [0.0; 3]);
+        assert_eq!(Postprocess::from(post), Postprocess::Alpha);
+        assert!(spec.to_operation().is_err());
+
+        // Defaults are left out when serializing
+        let plain = OperationSpec::Model {
+            path: "a.onnx".to_string(),
+            pre: PreprocessSpec::default(),
+            post: ModelOutputSpec::Image,
+        };
+        assert_eq!(
+            serde_json::to_string(&plain).unwrap(),
+            r#"{"type":"model","path":"a.onnx"}"#
+        );
+    }
+
+    #[cfg(feature = "yaml")]
+    #[test]
+    fn test_parse_yaml() {
+        let yaml = "operations:\n  - type: grayscale\n  - type: contrast\n    value: 1.4\n";
+        let spec = PipelineSpec::from_yaml(yaml).unwrap();
+        assert_eq!(spec.operations.len(), 2);
+    }
+}
@@ -0,0 +1,168 @@
+//! Real-time processing of same-sized frames, such as a webcam or video stream
+//!
+//! `FrameProcessor` validates and compiles a pipeline once, then runs it on
+//! every frame in place. Runs of point operations are fused into a single
+//! lookup pass (even a lone brightness or gamma step) and 3D LUTs are applied
+//! directly to a buffer allocated up front, so a pipeline made only of point
+//! operations and LUTs does no per-frame allocation.
+
+use crate::optimize::{self, FusedPointOps};
+use crate::{CancellationToken, FilterOperation, ImagePipeline, Lut3D, PipelineError, Result};
+use image::RgbaImage;
+use std::sync::Arc;
+
+/// One precompiled step of a frame pipeline
+#[derive(Debug, Clone)]
+enum FrameStage {
+    /// A run of point operations folded into one per-pixel program
+    Point(FusedPointOps),
+    /// A 3D color lookup table
+    Lut3D(Arc<Lut3D>),
+    /// Any other operation, run through the regular pipeline
+    Operation(FilterOperation),
+}
+
+/// Pipeline compiled for repeated use on frames of one fixed size
+#[derive(Debug, Clone)]
+pub struct FrameProcessor {
+    stages: Vec<FrameStage>,
+    buffer: RgbaImage,
+}
+
+impl FrameProcessor {
+    /// Compile `operations` for frames of `width` x `height`
+    ///
+    /// Fails if any operation is invalid or can change the frame size, since
+    /// frames are processed in place.
+    pub fn new(width: u32, height: u32, operations: &[FilterOperation]) -> Result<Self> {
+        if width == 0 || height == 0 {
+            return Err(PipelineError::InvalidParameter(
+                "frame size must be at least 1x1".to_string(),
+            ));
+        }
+        ImagePipeline::validate(operations)?;
+        if let Some((index, op)) = operations
+            .iter()
+            .enumerate()
+            .find(|(_, op)| op.changes_size())
+        {
+            return Err(PipelineError::InvalidParameter(format!(
+                "operation {} ({}) changes the frame size and cannot run in place",
+                index,
+                op.name()
+            )));
+        }
+
+        let mut stages = Vec::new();
+        let mut index = 0;
+        while index < operations.len() {
+            let run_end = operations[index..]
+                .iter()
+                .position(|op| !optimize::is_point_operation(op))
+                .map_or(operations.len(), |offset| index + offset);
+
+            if run_end > index {
+                let fused = FusedPointOps::from_operations(&operations[index..run_end])
+                    .expect("run contains only point operations");
+                stages.push(FrameStage::Point(fused));
+                index = run_end;
+            } else {
+                stages.push(match &operations[index] {
+                    FilterOperation::Lut3D(lut) => FrameStage::Lut3D(Arc::clone(lut)),
+                    op => FrameStage::Operation(op.clone()),
+                });
+                index += 1;
+            }
+        }
+
+        Ok(Self {
+            stages,
+            buffer: RgbaImage::new(width, height),
+        })
+    }
+
+    /// Frame size this processor was compiled for
+    pub fn dimensions(&self) -> (u32, u32) {
+        self.buffer.dimensions()
+    }
+
+    /// Run the pipeline on a raw RGBA frame, overwriting it with the result
+    ///
+    /// `frame` must hold exactly `width * height * 4` bytes.
+    pub fn process_frame_in_place(&mut self, frame: &mut [u8]) -> Result<()> {
+        if frame.len() != self.buffer.len() {
+            let (width, height) = self.dimensions();
+            return Err(PipelineError::InvalidParameter(format!(
+                "expected a {}x{} RGBA frame ({} bytes), got {} bytes",
+                width,
+                height,
+                self.buffer.len(),
+                frame.len()
+            )));
+        }
+        if self.stages.is_empty() {
+            return Ok(());
+        }
+
+        self.buffer.copy_from_slice(frame);
+        let token = CancellationToken::new();
+        for stage in &self.stages {
+            match stage {
+                FrameStage::Point(fused) => fused.apply_in_place(&mut self.buffer),
+                FrameStage::Lut3D(lut) => lut.apply_in_place(&mut self.buffer),
+                FrameStage::Operation(op) => {
+                    self.buffer = ImagePipeline::apply_operation(&self.buffer, op, &token)?;
+                }
+            }
+        }
+        frame.copy_from_slice(&self.buffer);
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::Rgba;
+
+    fn frame() -> RgbaImage {
+        RgbaImage::from_fn(32, 24, |x, y| {
+            Rgba([(x * 8) as u8, (y * 10) as u8, ((x + y) * 4) as u8, 255])
+        })
+    }
+
+    #[test]
+    fn test_matches_pipeline() {
+        let operations = [
+            FilterOperation::Brightness(0.1),
+            FilterOperation::Blur(1.0),
+            FilterOperation::Gamma(1.4),
+            FilterOperation::Lut3D(Arc::new(Lut3D::identity(17))),
+            FilterOperation::Sepia,
+            FilterOperation::Contrast(1.2),
+        ];
+        let image = frame();
+        let expected = ImagePipeline::new().process(&image, &operations).unwrap();
+
+        let mut processor = FrameProcessor::new(32, 24, &operations).unwrap();
+        for _ in 0..2 {
+            let mut pixels = image.as_raw().clone();
+            processor.process_frame_in_place(&mut pixels).unwrap();
+            assert_eq!(pixels, *expected.as_raw());
+        }
+    }
+
+    #[test]
+    fn test_rejects_size_changes_and_wrong_frames() {
+        let resize = FilterOperation::Resize {
+            width: 16,
+            height: 12,
+            filter: crate::ResampleFilter::Bilinear,
+        };
+        assert!(FrameProcessor::new(32, 24, &[resize]).is_err());
+        assert!(FrameProcessor::new(0, 24, &[]).is_err());
+
+        let mut processor = FrameProcessor::new(32, 24, &[FilterOperation::Invert]).unwrap();
+        assert!(processor.process_frame_in_place(&mut [0; 16]).is_err());
+    }
+}
@@ -0,0 +1,452 @@
+//! Test support: proptest strategies and golden-image comparisons
+//!
+//! The strategies generate images and regions for the `proptest!` properties
+//! below; failing cases shrink towards small images and simple values, and
+//! proptest records their seeds under `proptest-regressions` so they are
+//! replayed first on the next run. Golden references live in
+//! `testdata/golden`; set `IMAGE_PIPELINE_UPDATE_GOLDEN=1` to (re)write them
+//! after an intentional change to filter output.
+
+use crate::Region;
+use image::{Rgba, RgbaImage};
+use proptest::collection::vec;
+use proptest::prelude::*;
+use std::path::PathBuf;
+
+/// Number of generated cases per property
+pub const CASES: u32 = 64;
+
+pub fn color() -> impl Strategy<Value = Rgba<u8>> {
+    any::<[u8; 4]>().prop_map(Rgba)
+}
+
+/// Image of 1x1 to `max_side`x`max_side` pixels, mixing noise, flat
+/// areas and gradients so both smooth and high-frequency content occur
+pub fn image(max_side: u32) -> impl Strategy<Value = RgbaImage> {
+    (1..=max_side, 1..=max_side).prop_flat_map(|(width, height)| {
+        let pixels = (width * height) as usize;
+        prop_oneof![
+            vec(any::<[u8; 4]>(), pixels).prop_map(move |noise| {
+                RgbaImage::from_raw(width, height, noise.concat()).unwrap()
+            }),
+            color().prop_map(move |fill| RgbaImage::from_pixel(width, height, fill)),
+            (color(), color(), 0.0f32..=360.0).prop_map(move |(from, to, angle)| {
+                crate::generate::linear_gradient(width, height, from, to, angle)
+            }),
+        ]
+    })
+}
+
+/// Rectangle that may extend past a `width`x`height` image
+pub fn rect(width: u32, height: u32) -> impl Strategy<Value = Region> {
+    (0..=width, 0..=height, 1..=width + 1, 1..=height + 1).prop_map(|(x, y, width, height)| {
+        Region::Rect {
+            x,
+            y,
+            width,
+            height,
+        }
+    })
+}
+
+/// How far an output may drift from its reference
+#[derive(Debug, Clone, Copy)]
+pub struct Tolerance {
+    /// Largest per-channel difference that still counts as equal
+    pub per_channel: u8,
+    /// Fraction of pixels allowed to exceed `per_channel`
+    pub outlier_fraction: f64,
+    /// Largest mean luma difference, which catches small global shifts
+    pub mean_luma: f64,
+}
+
+impl Default for Tolerance {
+    // Absorbs rounding differences between platforms and float paths, but
+    // not visible changes such as a shifted curve or a one-pixel offset
+    fn default() -> Self {
+        Self {
+            per_channel: 2,
+            outlier_fraction: 0.001,
+            mean_luma: 0.5,
+        }
+    }
+}
+
+/// Compare two images, describing the first way they exceed `tolerance`
+pub fn compare(
+    actual: &RgbaImage,
+    expected: &RgbaImage,
+    tolerance: Tolerance,
+) -> Result<(), String> {
+    if actual.dimensions() != expected.dimensions() {
+        return Err(format!(
+            "size {:?} differs from expected {:?}",
+            actual.dimensions(),
+            expected.dimensions()
+        ));
+    }
+
+    let luma = |p: &Rgba<u8>| 0.2126 * p[0] as f64 + 0.7152 * p[1] as f64 + 0.0722 * p[2] as f64;
+    let mut outliers = 0usize;
+    let mut luma_diff = 0.0;
+    let mut worst = (0u8, 0, 0);
+    for ((x, y, a), b) in actual.enumerate_pixels().zip(expected.pixels()) {
+        let diff = (0..4).map(|c| a[c].abs_diff(b[c])).max().unwrap_or(0);
+        if diff > tolerance.per_channel {
+            outliers += 1;
+        }
+        if diff > worst.0 {
+            worst = (diff, x, y);
+        }
+        luma_diff += luma(a) - luma(b);
+    }
+
+    let pixels = (actual.width() as usize * actual.height() as usize).max(1);
+    let outlier_fraction = outliers as f64 / pixels as f64;
+    if outlier_fraction > tolerance.outlier_fraction {
+        return Err(format!(
+            "{} pixels ({:.2}%) differ by more than {}; worst is {} at ({}, {})",
+            outliers,
+            outlier_fraction * 100.0,
+            tolerance.per_channel,
+            worst.0,
+            worst.1,
+            worst.2
+        ));
+    }
+    let mean_luma = (luma_diff / pixels as f64).abs();
+    if mean_luma > tolerance.mean_luma {
+        return Err(format!("mean luma shifted by {:.3}", mean_luma));
+    }
+    Ok(())
+}
+
+/// Compare `actual` against the golden image `name`, or write it when updating
+pub fn assert_golden(name: &str, actual: &RgbaImage) {
+    let path = golden_dir().join(format!("{name}.png"));
+    if std::env::var_os("IMAGE_PIPELINE_UPDATE_GOLDEN").is_some() {
+        std::fs::create_dir_all(golden_dir()).unwrap();
+        actual.save(&path).unwrap();
+        return;
+    }
+
+    let expected = image::open(&path)
+        .unwrap_or_else(|e| {
+            panic!(
+                "missing golden image {} ({e}); run with IMAGE_PIPELINE_UPDATE_GOLDEN=1",
+                path.display()
+            )
+        })
+        .to_rgba8();
+    if let Err(reason) = compare(actual, &expected, Tolerance::default()) {
+        panic!("golden image {name} mismatch: {reason}");
+    }
+}
+
+fn golden_dir() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("testdata/golden")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        generate, optimize, Anchor, EdgeBorder, EdgeOptions, EdgeOutput, FilterOperation, FitMode,
+        GrayscaleMode, ImagePipeline, Interpolation, Lut3D, Mark, PadFill, ResampleFilter,
+        UpscaleMethod,
+    };
+    use std::sync::Arc;
+
+    const SWAP_RED_BLUE: &str =
+        "LUT_3D_SIZE 2\n0 0 0\n0 0 1\n0 1 0\n0 1 1\n1 0 0\n1 0 1\n1 1 0\n1 1 1\n";
+
+    // One representative configuration of every operation
+    fn golden_operations() -> Vec<(String, FilterOperation)> {
+        let mut ops = vec![
+            FilterOperation::Grayscale(GrayscaleMode::Bt709),
+            FilterOperation::Brightness(0.2),
+            FilterOperation::Contrast(1.4),
+            FilterOperation::Blur(1.5),
+            FilterOperation::ApproximateBlur(4.0),
+            FilterOperation::sharpen(),
+            FilterOperation::EdgeDetect(EdgeOptions::default()),
+            FilterOperation::Thumbnail {
+                width: 40,
+                height: 40,
+                fit: FitMode::Pad(Rgba([0, 0, 255, 255])),
+            },
+            FilterOperation::SmartCrop {
+                width: 32,
+                height: 32,
+            },
+            FilterOperation::SeamCarve {
+                width: 56,
+                height: 48,
+            },
+            FilterOperation::Pad {
+                top: 6,
+                right: 10,
+                bottom: 6,
+                left: 4,
+                fill: PadFill::Mirror,
+            },
+            FilterOperation::Invert,
+            FilterOperation::Sepia,
+            FilterOperation::ColorMatrix([
+                [0.9, 0.1, 0.0, 0.0, 0.05],
+                [0.0, 0.8, 0.2, 0.0, 0.0],
+                [0.1, 0.0, 0.7, 0.0, 0.1],
+                [0.0, 0.0, 0.0, 1.0, 0.0],
+            ]),
+            FilterOperation::Gamma(2.2),
+            FilterOperation::Lut3D(Arc::new(Lut3D::parse_cube(SWAP_RED_BLUE).unwrap())),
+            FilterOperation::Noise {
+                amount: 0.1,
+                monochrome: false,
+                seed: 42,
+            },
+            FilterOperation::Pixelate(8),
+            FilterOperation::Deskew,
+            FilterOperation::Rotate {
+                degrees: 30.0,
+                background: Rgba([255, 255, 255, 255]),
+                expand: true,
+            },
+            FilterOperation::Affine {
+                matrix: [0.9, 0.3, -4.0, -0.2, 1.1, 6.0],
+                interpolation: Interpolation::Bicubic,
+                background: Rgba([0, 0, 0, 255]),
+            },
+            FilterOperation::LensCorrect {
+                k1: -0.25,
+                k2: 0.05,
+                k3: 0.0,
+            },
+            FilterOperation::ChromaticAberration {
+                red_shift: 3.0,
+                blue_shift: -2.0,
+            },
+            FilterOperation::ChromaKey {
+                key_color: Rgba([0, 255, 0, 255]),
+                tolerance: 0.3,
+                softness: 0.1,
+            },
+            FilterOperation::GradientMap(vec![
+                (0.0, Rgba([20, 30, 90, 255])),
+                (1.0, Rgba([250, 120, 180, 255])),
+            ]),
+            FilterOperation::SelectiveColor {
+                hue_range: (180.0, 270.0),
+                hue_shift: -20.0,
+                saturation: 1.4,
+                lightness: 0.05,
+            },
+            FilterOperation::ShadowsHighlights {
+                shadows: 0.6,
+                highlights: 0.4,
+                radius: 6.0,
+            },
+            FilterOperation::PortraitBlur {
+                mask: None,
+                sigma: 3.0,
+                feather: 2.0,
+            },
+            FilterOperation::Upscale {
+                factor: 2.0,
+                method: UpscaleMethod::Detail,
+            },
+            FilterOperation::Watermark {
+                mark: Arc::new(Mark::from_image(generate::checkerboard(
+                    12,
+                    8,
+                    2,
+                    Rgba([255, 255, 255, 200]),
+                    Rgba([0, 0, 0, 0]),
+                ))),
+                anchor: Anchor::BottomRight,
+                margin: 4,
+                opacity: 0.6,
+                tile: true,
+                scale: None,
+            },
+            FilterOperation::DropShadow {
+                offset: (3, 4),
+                sigma: 2.5,
+                color: Rgba([20, 0, 40, 255]),
+                opacity: 0.6,
+            },
+            FilterOperation::Masked {
+                region: Region::Rect {
+                    x: 16,
+                    y: 8,
+                    width: 24,
+                    height: 24,
+                },
+                op: Box::new(FilterOperation::Invert),
+            },
+        ]
+        .into_iter()
+        .map(|op| (op.name().to_string(), op))
+        .collect::<Vec<_>>();
+
+        ops.push((
+            "grayscale_bt601".to_string(),
+            FilterOperation::Grayscale(GrayscaleMode::Bt601),
+        ));
+        ops.push((
+            "edge_detect_direction".to_string(),
+            FilterOperation::EdgeDetect(EdgeOptions {
+                border: EdgeBorder::Replicate,
+                output: EdgeOutput::Direction,
+                threshold: Some(40),
+            }),
+        ));
+
+        for filter in [
+            ResampleFilter::Nearest,
+            ResampleFilter::Bilinear,
+            ResampleFilter::CatmullRom,
+            ResampleFilter::Lanczos3,
+        ] {
+            ops.push((
+                format!("resize_{}", filter.name()),
+                FilterOperation::Resize {
+                    width: 40,
+                    height: 30,
+                    filter,
+                },
+            ));
+        }
+        ops
+    }
+
+    #[test]
+    fn test_golden_outputs() {
+        let input = generate::test_pattern(64, 48);
+        let pipeline = ImagePipeline::new();
+        for (name, op) in golden_operations() {
+            let output = pipeline.process(&input, &[op]).unwrap();
+            assert_golden(&name, &output);
+        }
+    }
+
+    #[test]
+    fn test_compare_tolerance() {
+        let a = generate::solid(10, 10, Rgba([100, 100, 100, 255]));
+        let mut b = a.clone();
+        b.put_pixel(3, 3, Rgba([102, 100, 100, 255]));
+        assert!(compare(&a, &b, Tolerance::default()).is_ok());
+
+        b.put_pixel(4, 4, Rgba([140, 100, 100, 255]));
+        assert!(compare(&a, &b, Tolerance::default()).is_err());
+        let shifted = generate::solid(10, 10, Rgba([101, 101, 101, 255]));
+        assert!(compare(&a, &shifted, Tolerance::default()).is_err());
+    }
+
+    fn point_filter() -> impl Strategy<Value = FilterOperation> {
+        prop_oneof![
+            Just(FilterOperation::Grayscale(GrayscaleMode::Bt709)),
+            (-1.0f32..=1.0).prop_map(FilterOperation::Brightness),
+            (0.0f32..=3.0).prop_map(FilterOperation::Contrast),
+            (0.1f32..=4.0).prop_map(FilterOperation::Gamma),
+            Just(FilterOperation::Sepia),
+            (0.1f32..=4.0).prop_map(FilterOperation::Blur),
+            (1u32..=9).prop_map(FilterOperation::Pixelate),
+        ]
+    }
+
+    fn fusable_op() -> impl Strategy<Value = FilterOperation> {
+        prop_oneof![
+            (-0.5f32..=0.5).prop_map(FilterOperation::Brightness),
+            (0.5f32..=2.0).prop_map(FilterOperation::Contrast),
+            (0.5f32..=2.5).prop_map(FilterOperation::Gamma),
+            Just(FilterOperation::Invert),
+        ]
+    }
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(CASES))]
+
+        #[test]
+        fn prop_point_filters_keep_size_and_alpha(image in image(24), op in point_filter()) {
+            let output = ImagePipeline::new()
+                .process(&image, std::slice::from_ref(&op))
+                .unwrap();
+            prop_assert_eq!(output.dimensions(), image.dimensions(), "{:?} changed the size", op);
+            if !matches!(op, FilterOperation::Blur(_) | FilterOperation::Pixelate(_)) {
+                prop_assert!(
+                    output.pixels().zip(image.pixels()).all(|(a, b)| a[3] == b[3]),
+                    "{:?} changed alpha",
+                    op
+                );
+            }
+        }
+
+        #[test]
+        fn prop_invert_is_involution(image in image(32)) {
+            let twice = ImagePipeline::new()
+                .process(&image, &[FilterOperation::Invert, FilterOperation::Invert])
+                .unwrap();
+            prop_assert!(twice == image, "invert twice differs from input");
+        }
+
+        #[test]
+        fn prop_fused_matches_sequential(image in image(16), ops in vec(fusable_op(), 1..=5)) {
+            let fused = optimize::FusedPointOps::from_operations(&ops).unwrap();
+            let mut sequential = ImagePipeline::new();
+            sequential.fuse_operations = false;
+            compare(
+                &fused.apply(&image),
+                &sequential.process(&image, &ops).unwrap(),
+                Tolerance {
+                    per_channel: 0,
+                    ..Tolerance::default()
+                },
+            )
+            .map_err(TestCaseError::fail)?;
+        }
+
+        #[test]
+        fn prop_resize_hits_target_size(
+            image in image(32),
+            width in 1u32..=48,
+            height in 1u32..=48,
+            filter in prop_oneof![Just(ResampleFilter::Nearest), Just(ResampleFilter::Bilinear)],
+        ) {
+            let output = crate::filters::resize(&image, width, height, filter);
+            prop_assert_eq!(output.dimensions(), (width, height));
+        }
+
+        #[test]
+        fn prop_masked_leaves_outside_untouched(
+            (image, region) in image(24).prop_flat_map(|image| {
+                let area = rect(image.width(), image.height());
+                (Just(image), area)
+            }),
+        ) {
+            let Region::Rect {
+                x,
+                y,
+                width,
+                height,
+            } = region
+            else {
+                unreachable!()
+            };
+            let op = FilterOperation::Masked {
+                region,
+                op: Box::new(FilterOperation::Invert),
+            };
+            let output = ImagePipeline::new().process(&image, &[op]).unwrap();
+            for (px, py, pixel) in output.enumerate_pixels() {
+                let inside = px >= x && py >= y && px - x < width && py - y < height;
+                prop_assert!(
+                    inside || pixel == image.get_pixel(px, py),
+                    "pixel ({}, {}) outside the rect changed",
+                    px,
+                    py
+                );
+            }
+        }
+    }
+}
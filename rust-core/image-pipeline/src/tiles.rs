@@ -0,0 +1,375 @@
+//! Tile pyramids for deep-zoom viewers
+//!
+//! `generate_tiles` cuts a large image into fixed-size tiles at every zoom
+//! level, halving the resolution from one level to the next, and writes them
+//! with a descriptor for the viewer:
+//!
+//! - Deep Zoom (`.dzi`, OpenSeadragon's native format): tiles at
+//!   `{name}_files/{level}/{column}_{row}.{ext}`, level 0 being 1x1 pixel.
+//! - IIIF Image API 3.0, level 0: static tiles at
+//!   `{name}/{x},{y},{w},{h}/{width},{height}/0/default.{ext}` and an
+//!   `{name}/info.json` listing the tile size and scale factors, servable
+//!   from any static file host.
+//!
+//! Output goes through a `TileSink`, so tiles can be written to a directory,
+//! kept in memory or uploaded to object storage.
+
+use crate::filters::{resize_straight, ResampleFilter};
+use crate::{EncodeOptions, ImagePipeline, OutputFormat, PipelineError, Result};
+use image::RgbaImage;
+use rayon::prelude::*;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+
+/// Destination for the files of a tile pyramid
+pub trait TileSink {
+    /// Store `bytes` at `path`, a relative path with `/` separators
+    fn write(&mut self, path: &str, bytes: &[u8]) -> Result<()>;
+}
+
+/// Keeps every file in memory, keyed by path
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MemorySink {
+    pub files: BTreeMap<String, Vec<u8>>,
+}
+
+impl TileSink for MemorySink {
+    fn write(&mut self, path: &str, bytes: &[u8]) -> Result<()> {
+        self.files.insert(path.to_string(), bytes.to_vec());
+        Ok(())
+    }
+}
+
+/// Writes files below a root directory, creating subdirectories as needed
+#[derive(Debug, Clone)]
+pub struct DirectorySink {
+    root: PathBuf,
+}
+
+impl DirectorySink {
+    pub fn new(root: impl Into<PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+}
+
+impl TileSink for DirectorySink {
+    fn write(&mut self, path: &str, bytes: &[u8]) -> Result<()> {
+        let target = self.root.join(path);
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        fs::write(target, bytes)?;
+        Ok(())
+    }
+}
+
+/// Tile naming and descriptor format
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum TileLayout {
+    /// Deep Zoom Image (`.dzi` descriptor)
+    #[default]
+    DeepZoom,
+    /// IIIF Image API 3.0 level 0; `base_url` is where the `{name}` directory
+    /// will be served from and becomes the `id` in `info.json`
+    Iiif { base_url: String },
+}
+
+/// How `generate_tiles` cuts and encodes tiles
+#[derive(Debug, Clone, PartialEq)]
+pub struct TileOptions {
+    /// Side of a tile in pixels, without overlap
+    pub tile_size: u32,
+    /// Pixels repeated from neighbouring tiles on each inner edge, which
+    /// hides seams when the viewer scales tiles; Deep Zoom only
+    pub overlap: u32,
+    pub format: OutputFormat,
+    pub encode: EncodeOptions,
+    pub layout: TileLayout,
+}
+
+impl Default for TileOptions {
+    fn default() -> Self {
+        Self {
+            tile_size: 254,
+            overlap: 1,
+            format: OutputFormat::Jpeg,
+            encode: EncodeOptions::default(),
+            layout: TileLayout::DeepZoom,
+        }
+    }
+}
+
+/// Write the tile pyramid of `image` and its descriptor to `sink`
+///
+/// `name` prefixes every path. Returns the number of tiles written.
+pub fn generate_tiles(
+    image: &RgbaImage,
+    name: &str,
+    options: &TileOptions,
+    sink: &mut dyn TileSink,
+) -> Result<usize> {
+    let (width, height) = image.dimensions();
+    if width == 0 || height == 0 {
+        return Err(PipelineError::InvalidParameter(
+            "cannot tile an empty image".to_string(),
+        ));
+    }
+    if options.tile_size == 0 {
+        return Err(PipelineError::InvalidParameter(
+            "tile size must be at least 1".to_string(),
+        ));
+    }
+    if matches!(options.layout, TileLayout::Iiif { .. }) && options.overlap > 0 {
+        return Err(PipelineError::InvalidParameter(
+            "IIIF tiles cannot overlap; set overlap to 0".to_string(),
+        ));
+    }
+    if name.is_empty() || name.contains(['/', '\\']) || name == "." || name == ".." {
+        return Err(PipelineError::InvalidParameter(format!(
+            "invalid pyramid name: {:?}",
+            name
+        )));
+    }
+    options.encode.validate(options.format)?;
+
+    // Deep Zoom goes down to 1x1; IIIF stops once the image fits in one tile
+    let max_level = 32 - (width.max(height) - 1).leading_zeros();
+    let levels = match options.layout {
+        TileLayout::DeepZoom => max_level + 1,
+        TileLayout::Iiif { .. } => {
+            let mut levels = 1;
+            while width.max(height).div_ceil(1 << (levels - 1)) > options.tile_size {
+                levels += 1;
+            }
+            levels
+        }
+    };
+
+    let ext = options.format.extension();
+    let mut count = 0;
+    let mut current = image.clone();
+    for step in 0..levels {
+        if step > 0 {
+            let (w, h) = current.dimensions();
+            current = resize_straight(
+                &current,
+                w.div_ceil(2),
+                h.div_ceil(2),
+                ResampleFilter::Lanczos3,
+            );
+        }
+        let scale = 1u64 << step;
+        let tiles = tile_rects(current.dimensions(), options);
+        let encoded = tiles
+            .par_iter()
+            .map(|&(column, row, x, y, w, h)| {
+                let tile = image::imageops::crop_imm(&current, x, y, w, h).to_image();
+                let bytes =
+                    ImagePipeline::encode_with_options(&tile, options.format, &options.encode)?;
+                let path = match &options.layout {
+                    TileLayout::DeepZoom => format!(
+                        "{}_files/{}/{}_{}.{}",
+                        name,
+                        max_level - step,
+                        column,
+                        row,
+                        ext
+                    ),
+                    TileLayout::Iiif { .. } => {
+                        // Region in full-resolution pixels, clamped to the image
+                        let rx = x as u64 * scale;
+                        let ry = y as u64 * scale;
+                        let rw = (w as u64 * scale).min(width as u64 - rx);
+                        let rh = (h as u64 * scale).min(height as u64 - ry);
+                        format!(
+                            "{}/{},{},{},{}/{},{}/0/default.{}",
+                            name, rx, ry, rw, rh, w, h, ext
+                        )
+                    }
+                };
+                Ok((path, bytes))
+            })
+            .collect::<Result<Vec<_>>>()?;
+        for (path, bytes) in encoded {
+            sink.write(&path, &bytes)?;
+            count += 1;
+        }
+    }
+
+    match &options.layout {
+        TileLayout::DeepZoom => {
+            let descriptor = format!(
+                concat!(
+                    "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n",
+                    "<Image xmlns=\"http://schemas.microsoft.com/deepzoom/2008\" ",
+                    "Format=\"{}\" Overlap=\"{}\" TileSize=\"{}\">",
+                    "<Size Width=\"{}\" Height=\"{}\"/></Image>\n"
+                ),
+                ext, options.overlap, options.tile_size, width, height
+            );
+            sink.write(&format!("{}.dzi", name), descriptor.as_bytes())?;
+        }
+        TileLayout::Iiif { base_url } => {
+            let id = format!("{}/{}", base_url.trim_end_matches('/'), name);
+            let info = serde_json::json!({
+                "@context": "http://iiif.io/api/image/3/context.json",
+                "id": id,
+                "type": "ImageService3",
+                "protocol": "http://iiif.io/api/image",
+                "profile": "level0",
+                "width": width,
+                "height": height,
+                "tiles": [{
+                    "width": options.tile_size,
+                    "height": options.tile_size,
+                    "scaleFactors": (0..levels).map(|step| 1u64 << step).collect::<Vec<_>>(),
+                }],
+                "preferredFormats": [ext],
+            });
+            let bytes = serde_json::to_vec_pretty(&info)
+                .map_err(|e| PipelineError::ProcessingError(e.to_string()))?;
+            sink.write(&format!("{}/info.json", name), &bytes)?;
+        }
+    }
+    Ok(count)
+}
+
+// (column, row, x, y, width, height) of every tile of a level, row by row;
+// Deep Zoom tiles extend by the overlap into their neighbours
+fn tile_rects(
+    (width, height): (u32, u32),
+    options: &TileOptions,
+) -> Vec<(u32, u32, u32, u32, u32, u32)> {
+    let size = options.tile_size;
+    let overlap = options.overlap;
+    let span = |index: u32, total: u32| {
+        let start = (index * size).saturating_sub(overlap);
+        let end = ((index + 1) * size).saturating_add(overlap).min(total);
+        (start, end - start)
+    };
+    let mut rects = Vec::new();
+    for row in 0..height.div_ceil(size) {
+        let (y, h) = span(row, height);
+        for column in 0..width.div_ceil(size) {
+            let (x, w) = span(column, width);
+            rects.push((column, row, x, y, w, h));
+        }
+    }
+    rects
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::generate;
+
+    fn decode(sink: &MemorySink, path: &str) -> RgbaImage {
+        let bytes = sink
+            .files
+            .get(path)
+            .unwrap_or_else(|| panic!("missing {path}"));
+        ImagePipeline::load_from_bytes(bytes).unwrap()
+    }
+
+    #[test]
+    fn test_deep_zoom() {
+        let image = generate::test_pattern(600, 300);
+        let options = TileOptions {
+            tile_size: 256,
+            format: OutputFormat::Png,
+            ..TileOptions::default()
+        };
+        let mut sink = MemorySink::default();
+        let count = generate_tiles(&image, "scan", &options, &mut sink).unwrap();
+
+        // Levels 0-10; level 10 is full size with 3x2 tiles, level 9 (300x150) has 2x1
+        assert_eq!(sink.files.len(), count + 1);
+        assert_eq!(decode(&sink, "scan_files/0/0_0.png").dimensions(), (1, 1));
+        assert_eq!(
+            decode(&sink, "scan_files/9/1_0.png").dimensions(),
+            (45, 150)
+        );
+        assert!(!sink.files.contains_key("scan_files/9/0_1.png"));
+        // Inner tiles overlap their neighbours by one pixel on each side
+        assert_eq!(
+            decode(&sink, "scan_files/10/0_0.png").dimensions(),
+            (257, 257)
+        );
+        assert_eq!(
+            decode(&sink, "scan_files/10/1_1.png").dimensions(),
+            (258, 45)
+        );
+        let last = decode(&sink, "scan_files/10/2_1.png");
+        assert_eq!(last.dimensions(), (89, 45));
+        assert_eq!(last[(0, 0)], image[(511, 255)]);
+
+        let dzi = String::from_utf8(sink.files["scan.dzi"].clone()).unwrap();
+        assert!(dzi.contains("Format=\"png\" Overlap=\"1\" TileSize=\"256\""));
+        assert!(dzi.contains("<Size Width=\"600\" Height=\"300\"/>"));
+    }
+
+    #[test]
+    fn test_iiif() {
+        let image = generate::test_pattern(500, 200);
+        let options = TileOptions {
+            tile_size: 128,
+            overlap: 0,
+            layout: TileLayout::Iiif {
+                base_url: "https://example.org/iiif/".to_string(),
+            },
+            ..TileOptions::default()
+        };
+        let mut sink = MemorySink::default();
+        let count = generate_tiles(&image, "map", &options, &mut sink).unwrap();
+        // Scale factors 1, 2, 4: 4x2 + 2x1 + 1x1 tiles
+        assert_eq!(count, 11);
+        assert_eq!(
+            decode(&sink, "map/384,128,116,72/116,72/0/default.jpg").dimensions(),
+            (116, 72)
+        );
+        assert_eq!(
+            decode(&sink, "map/256,0,244,200/122,100/0/default.jpg").dimensions(),
+            (122, 100)
+        );
+        assert!(sink
+            .files
+            .contains_key("map/0,0,500,200/125,50/0/default.jpg"));
+
+        let info: serde_json::Value = serde_json::from_slice(&sink.files["map/info.json"]).unwrap();
+        assert_eq!(info["id"], "https://example.org/iiif/map");
+        assert_eq!(info["width"], 500);
+        assert_eq!(
+            info["tiles"][0]["scaleFactors"],
+            serde_json::json!([1, 2, 4])
+        );
+
+        // IIIF has no overlap
+        let overlapping = TileOptions {
+            overlap: 1,
+            ..options
+        };
+        assert!(generate_tiles(&image, "map", &overlapping, &mut sink).is_err());
+    }
+
+    #[test]
+    fn test_directory_sink_and_invalid_input() {
+        let dir = std::env::temp_dir().join(format!("tiles-test-{}", std::process::id()));
+        let mut sink = DirectorySink::new(&dir);
+        let image = generate::solid(10, 10, image::Rgba([1, 2, 3, 255]));
+        generate_tiles(&image, "small", &TileOptions::default(), &mut sink).unwrap();
+        assert!(dir.join("small.dzi").is_file());
+        assert!(dir.join("small_files/4/0_0.jpg").is_file());
+        fs::remove_dir_all(&dir).unwrap();
+
+        let mut memory = MemorySink::default();
+        let zero = TileOptions {
+            tile_size: 0,
+            ..TileOptions::default()
+        };
+        assert!(generate_tiles(&image, "small", &zero, &mut memory).is_err());
+        let default = TileOptions::default();
+        assert!(generate_tiles(&image, "../up", &default, &mut memory).is_err());
+        assert!(generate_tiles(&RgbaImage::new(0, 5), "empty", &default, &mut memory).is_err());
+    }
+}
@@ -0,0 +1,233 @@
+//! HDR tone mapping: linear float images to displayable 8-bit sRGB
+//!
+//! Float images come from `load_hdr` (Radiance `.hdr`, OpenEXR, or any format the
+//! `image` crate decodes) or from merging exposure brackets.
+
+use crate::{load, LoadOptions, Result};
+use image::{Rgba32FImage, RgbaImage};
+use rayon::prelude::*;
+
+/// Operator compressing scene luminance into the displayable range
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ToneMapOperator {
+    /// Extended Reinhard on luminance; the brightest pixel maps to white
+    Reinhard,
+    /// ACES filmic curve (Narkowicz fit) per channel; punchy contrast, soft highlights
+    AcesFilmic,
+    /// Drago adaptive logarithmic mapping on luminance
+    /// bias: 0.5 to 1.0, lower values brighten the shadows (0.85 is typical)
+    Drago { bias: f32 },
+}
+
+impl ToneMapOperator {
+    /// Parse an operator name ("reinhard", "aces", "drago"); Drago uses bias 0.85
+    pub fn from_name(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "reinhard" => Some(ToneMapOperator::Reinhard),
+            "aces" | "aces_filmic" | "filmic" => Some(ToneMapOperator::AcesFilmic),
+            "drago" => Some(ToneMapOperator::Drago { bias: 0.85 }),
+            _ => None,
+        }
+    }
+
+    /// Canonical name of the operator
+    pub fn name(&self) -> &'static str {
+        match self {
+            ToneMapOperator::Reinhard => "reinhard",
+            ToneMapOperator::AcesFilmic => "aces",
+            ToneMapOperator::Drago { .. } => "drago",
+        }
+    }
+}
+
+/// Decode an image into linear float RGBA, keeping values above 1.0
+///
+/// 8-bit formats are assumed to be sRGB-encoded and are linearized. The
+/// format and size are checked against `options` before decoding, as in
+/// `ImagePipeline::load_from_bytes_with_options`, counting 16 bytes per pixel
+/// of float output against `max_alloc`.
+pub fn load_hdr(bytes: &[u8], options: &LoadOptions) -> Result<Rgba32FImage> {
+    let image = load::decode(bytes, options, 16)?;
+    let is_float = matches!(
+        image.color(),
+        image::ColorType::Rgb32F | image::ColorType::Rgba32F
+    );
+    let mut result = image.to_rgba32f();
+    if !is_float {
+        for pixel in result.pixels_mut() {
+            for channel in pixel.0.iter_mut().take(3) {
+                *channel = srgb_to_linear(*channel);
+            }
+        }
+    }
+    Ok(result)
+}
+
+/// Tone map a linear HDR image to 8-bit sRGB
+/// exposure: adjustment in stops applied before mapping (0.0 = none)
+pub fn tonemap(image: &Rgba32FImage, operator: ToneMapOperator, exposure: f32) -> RgbaImage {
+    let gain = exposure.exp2();
+    let (width, height) = image.dimensions();
+    let luminances: Vec<f32> = image
+        .pixels()
+        .map(|p| luminance(&[p[0], p[1], p[2]]) * gain)
+        .collect();
+    let max_luminance = luminances.iter().copied().fold(0.0f32, f32::max);
+
+    // Drago maps relative to the log-average (world adaptation) luminance
+    let log_average = if luminances.is_empty() {
+        1.0
+    } else {
+        let sum: f64 = luminances.iter().map(|&l| (1e-4 + l as f64).ln()).sum();
+        (sum / luminances.len() as f64).exp() as f32
+    };
+
+    let mut result = RgbaImage::new(width, height);
+    let pixels: &mut [u8] = &mut result;
+    pixels
+        .par_chunks_mut(4)
+        .zip(image.as_raw().par_chunks(4))
+        .zip(luminances.par_iter())
+        .for_each(|((out, pixel), &l)| {
+            let rgb = [pixel[0], pixel[1], pixel[2]].map(|v| v.max(0.0) * gain);
+            let mapped = match operator {
+                ToneMapOperator::Reinhard => {
+                    let white = max_luminance.max(1.0);
+                    scale_luminance(rgb, l, l * (1.0 + l / (white * white)) / (1.0 + l))
+                }
+                ToneMapOperator::AcesFilmic => rgb.map(aces_filmic),
+                ToneMapOperator::Drago { bias } => {
+                    let adapted_max = max_luminance / log_average;
+                    let target = drago(l / log_average, adapted_max, bias);
+                    scale_luminance(rgb, l, target)
+                }
+            };
+            for (channel, value) in out.iter_mut().zip(mapped) {
+                *channel = (linear_to_srgb(value.clamp(0.0, 1.0)) * 255.0).round() as u8;
+            }
+            out[3] = (pixel[3].clamp(0.0, 1.0) * 255.0).round() as u8;
+        });
+
+    result
+}
+
+// Rec. 709 relative luminance of linear RGB
+fn luminance(rgb: &[f32; 3]) -> f32 {
+    0.2126 * rgb[0] + 0.7152 * rgb[1] + 0.0722 * rgb[2]
+}
+
+// Scale RGB so its luminance becomes `target`, preserving the hue
+fn scale_luminance(rgb: [f32; 3], luminance: f32, target: f32) -> [f32; 3] {
+    if luminance <= 0.0 {
+        return [0.0; 3];
+    }
+    rgb.map(|v| v * target / luminance)
+}
+
+fn aces_filmic(x: f32) -> f32 {
+    (x * (2.51 * x + 0.03)) / (x * (2.43 * x + 0.59) + 0.14)
+}
+
+// Drago et al. 2003 with a display maximum of 100 cd/m2 normalized to 1.0
+fn drago(l: f32, max: f32, bias: f32) -> f32 {
+    if max <= 0.0 {
+        return 0.0;
+    }
+    let exponent = bias.clamp(0.5, 1.0).ln() / 0.5f32.ln();
+    let denominator = (2.0 + 8.0 * (l / max).powf(exponent)).ln();
+    (1.0 + l).ln() / (denominator * (1.0 + max).log10())
+}
+
+fn srgb_to_linear(v: f32) -> f32 {
+    if v <= 0.04045 {
+        v / 12.92
+    } else {
+        ((v + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+fn linear_to_srgb(v: f32) -> f32 {
+    if v <= 0.0031308 {
+        v * 12.92
+    } else {
+        1.055 * v.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::PipelineError;
+    use image::Rgba;
+
+    // Horizontal ramp spanning ten stops, 1/32 to 32
+    fn hdr_ramp() -> Rgba32FImage {
+        Rgba32FImage::from_fn(101, 4, |x, _| {
+            let v = ((x as f32 / 10.0) - 5.0).exp2();
+            Rgba([v, v * 0.8, v * 0.6, 1.0])
+        })
+    }
+
+    #[test]
+    fn test_operators_are_monotonic_and_in_range() {
+        let image = hdr_ramp();
+        for operator in [
+            ToneMapOperator::Reinhard,
+            ToneMapOperator::AcesFilmic,
+            ToneMapOperator::Drago { bias: 0.85 },
+        ] {
+            let result = tonemap(&image, operator, 0.0);
+            let row: Vec<u8> = (0..101).map(|x| result.get_pixel(x, 0)[0]).collect();
+            assert!(row.windows(2).all(|w| w[0] <= w[1]), "{}", operator.name());
+            assert!(
+                row[0] < 100 && row[100] > 200,
+                "{}: {:?}",
+                operator.name(),
+                row
+            );
+            assert_eq!(result.get_pixel(50, 2)[3], 255);
+        }
+    }
+
+    #[test]
+    fn test_exposure_and_black() {
+        let black = Rgba32FImage::from_pixel(4, 4, Rgba([0.0, 0.0, 0.0, 1.0]));
+        let result = tonemap(&black, ToneMapOperator::Reinhard, 0.0);
+        assert_eq!(*result.get_pixel(0, 0), Rgba([0, 0, 0, 255]));
+
+        let image = hdr_ramp();
+        let dark = tonemap(&image, ToneMapOperator::AcesFilmic, -2.0);
+        let bright = tonemap(&image, ToneMapOperator::AcesFilmic, 2.0);
+        assert!(dark.get_pixel(50, 0)[1] < bright.get_pixel(50, 0)[1]);
+    }
+
+    #[test]
+    fn test_load_hdr_linearizes_8bit() {
+        let image = RgbaImage::from_pixel(2, 2, Rgba([188, 255, 0, 255]));
+        let mut bytes = Vec::new();
+        image
+            .write_to(
+                &mut std::io::Cursor::new(&mut bytes),
+                image::ImageFormat::Png,
+            )
+            .unwrap();
+        let hdr = load_hdr(&bytes, &LoadOptions::default()).unwrap();
+        let pixel = hdr.get_pixel(0, 0);
+        assert!((pixel[0] - 0.5).abs() < 0.01);
+        assert_eq!(pixel[1], 1.0);
+
+        // Tone mapping a value inside the display range round-trips closely
+        let back = tonemap(&hdr, ToneMapOperator::AcesFilmic, 0.0);
+        assert!(back.get_pixel(0, 0)[0] > 150);
+
+        // Limits apply before decoding, counting 16 bytes per float pixel
+        let small = LoadOptions {
+            max_alloc: Some(32),
+            ..LoadOptions::default()
+        };
+        assert!(matches!(
+            load_hdr(&bytes, &small),
+            Err(PipelineError::ResourceLimit(_))
+        ));
+    }
+}
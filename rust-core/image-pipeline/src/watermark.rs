@@ -0,0 +1,309 @@
+//! Invisible watermarks for provenance marking
+//!
+//! The payload is hidden in the luma of 8x8 blocks, the same grid JPEG
+//! compresses on. Every block carries one bit in two low-to-mid frequency DCT
+//! coefficients, quantized to one of two interleaved lattices (quantization index
+//! modulation). JPEG rounds those coefficients by less than half the lattice
+//! step, so the bits survive re-encoding at ordinary qualities. Bits are
+//! repeated over as many blocks as the image allows, scattered by a fixed
+//! permutation and recovered by majority vote, then checked against a checksum.
+//!
+//! The mark survives JPEG re-encoding and mild color adjustments. It does not
+//! survive cropping, resizing or rotation, which move the block grid.
+
+use crate::filters::SplitMix64;
+use crate::hash::sha256;
+use crate::{PipelineError, Result};
+use image::RgbaImage;
+use std::f32::consts::{PI, TAU};
+
+/// Side of the DCT blocks
+const BLOCK: usize = 8;
+/// Coefficients carrying each block's bit, as (horizontal, vertical) frequency
+const COEFFICIENTS: [(usize, usize); 2] = [(1, 2), (2, 1)];
+/// Lattice step at strength 1.0, comfortably above JPEG's rounding at quality 75
+const BASE_STEP: f32 = 24.0;
+/// Fewest copies of each bit that are still reliable
+const MIN_REPEATS: usize = 3;
+/// Share of the blocks (1 in N) that hold the payload length
+const HEADER_SHARE: usize = 8;
+/// Payload length field, in bits
+const LENGTH_BITS: usize = 16;
+/// Checksum bytes appended to the payload
+const CHECKSUM_LEN: usize = 4;
+/// Strengths are multiples of 1 / this, so extraction can try each of them
+const STRENGTH_LEVELS: f32 = 4.0;
+/// Fixed seed of the block permutation; embedding and extraction must agree
+const SEED: u64 = 0x5741_5445_524D_4B31;
+
+/// Hide `payload` in `image`
+///
+/// strength: 0.25-4.0 in steps of 0.25 (others are rounded); higher survives
+/// harsher compression but starts to show as faint texture in flat areas (1.0
+/// is invisible in photos)
+pub fn embed_invisible(image: &RgbaImage, payload: &[u8], strength: f32) -> Result<RgbaImage> {
+    if !(0.25..=4.0).contains(&strength) {
+        return Err(PipelineError::InvalidParameter(format!(
+            "strength must be between 0.25 and 4.0, got {}",
+            strength
+        )));
+    }
+    if payload.len() >= 1 << LENGTH_BITS {
+        return Err(PipelineError::InvalidParameter(format!(
+            "payload is {} bytes, at most {} fit",
+            payload.len(),
+            (1 << LENGTH_BITS) - 1
+        )));
+    }
+    let layout = Layout::new(image);
+    let body = body_bits(payload);
+    if layout.header_blocks < LENGTH_BITS * MIN_REPEATS
+        || layout.body_blocks < body.len() * MIN_REPEATS
+    {
+        return Err(PipelineError::InvalidParameter(format!(
+            "{} byte payload does not fit in a {}x{} image",
+            payload.len(),
+            image.width(),
+            image.height()
+        )));
+    }
+
+    let length: Vec<bool> = (0..LENGTH_BITS)
+        .map(|i| (payload.len() >> (LENGTH_BITS - 1 - i)) & 1 == 1)
+        .collect();
+    let step = BASE_STEP * (strength * STRENGTH_LEVELS).round() / STRENGTH_LEVELS;
+    let basis = Basis::new();
+    let mut result = image.clone();
+    for (slot, &block) in layout.order.iter().enumerate() {
+        let bit = if slot < layout.header_blocks {
+            length[slot % LENGTH_BITS]
+        } else {
+            body[(slot - layout.header_blocks) % body.len()]
+        };
+        layout.embed_bit(&mut result, block, bit, step, &basis);
+    }
+    Ok(result)
+}
+
+/// Recover a payload hidden by `embed_invisible`, or `None` if the image
+/// carries no intact mark
+///
+/// The strength used for embedding need not be known; each one is tried.
+pub fn extract_invisible(image: &RgbaImage) -> Option<Vec<u8>> {
+    let layout = Layout::new(image);
+    if layout.header_blocks < LENGTH_BITS * MIN_REPEATS {
+        return None;
+    }
+    let basis = Basis::new();
+    let coefficients: Vec<[f32; 2]> = layout
+        .order
+        .iter()
+        .map(|&block| layout.coefficients(image, block, &basis))
+        .collect();
+
+    // Try every strength a mark can have been embedded with; a wrong step reads
+    // noise, which the checksum rejects
+    (1..=4 * STRENGTH_LEVELS as u32)
+        .map(|level| BASE_STEP * level as f32 / STRENGTH_LEVELS)
+        .find_map(|step| decode(&layout, &coefficients, step))
+}
+
+// Payload bits for `step`, if the length and checksum are consistent
+fn decode(layout: &Layout, coefficients: &[[f32; 2]], step: f32) -> Option<Vec<u8>> {
+    let vote = |slots: &mut dyn Iterator<Item = usize>, bits: usize| {
+        let mut scores = vec![0f32; bits];
+        for (i, slot) in slots.enumerate() {
+            scores[i % bits] += coefficients[slot]
+                .iter()
+                .map(|&c| (TAU * c.rem_euclid(step) / step).cos())
+                .sum::<f32>();
+        }
+        // Near the even lattice scores +1, near the odd one -1
+        scores.into_iter().map(|s| s < 0.0).collect::<Vec<bool>>()
+    };
+
+    let length = vote(&mut (0..layout.header_blocks), LENGTH_BITS)
+        .iter()
+        .fold(0usize, |acc, &bit| acc << 1 | bit as usize);
+    let bits = (length + CHECKSUM_LEN) * 8;
+    if layout.body_blocks < bits * MIN_REPEATS {
+        return None;
+    }
+    let body = vote(
+        &mut (layout.header_blocks..layout.header_blocks + layout.body_blocks),
+        bits,
+    );
+    let bytes: Vec<u8> = body
+        .chunks(8)
+        .map(|byte| byte.iter().fold(0u8, |acc, &bit| acc << 1 | bit as u8))
+        .collect();
+    let (payload, checksum) = bytes.split_at(length);
+    (sha256(payload)[..CHECKSUM_LEN] == *checksum).then(|| payload.to_vec())
+}
+
+// Payload followed by its checksum, most significant bit first
+fn body_bits(payload: &[u8]) -> Vec<bool> {
+    payload
+        .iter()
+        .chain(&sha256(payload)[..CHECKSUM_LEN])
+        .flat_map(|byte| (0..8).rev().map(move |i| (byte >> i) & 1 == 1))
+        .collect()
+}
+
+// Orthonormal 2D DCT basis functions of the coefficients in use
+struct Basis([[f32; BLOCK * BLOCK]; 2]);
+
+impl Basis {
+    fn new() -> Self {
+        let scale = |k: usize| {
+            if k == 0 {
+                (1.0 / BLOCK as f32).sqrt()
+            } else {
+                (2.0 / BLOCK as f32).sqrt()
+            }
+        };
+        let cos =
+            |k: usize, n: usize| (PI * (2 * n + 1) as f32 * k as f32 / (2 * BLOCK) as f32).cos();
+        Basis(COEFFICIENTS.map(|(u, v)| {
+            std::array::from_fn(|i| {
+                let (x, y) = (i % BLOCK, i / BLOCK);
+                scale(u) * scale(v) * cos(u, x) * cos(v, y)
+            })
+        }))
+    }
+}
+
+// Which blocks hold which bits
+struct Layout {
+    /// Blocks per row
+    columns: usize,
+    /// Block indices in embedding order: the header first, then the body
+    order: Vec<usize>,
+    header_blocks: usize,
+    body_blocks: usize,
+}
+
+impl Layout {
+    fn new(image: &RgbaImage) -> Self {
+        let columns = image.width() as usize / BLOCK;
+        let count = columns * (image.height() as usize / BLOCK);
+        let mut order: Vec<usize> = (0..count).collect();
+        let mut rng = SplitMix64::new(SEED);
+        for i in (1..count).rev() {
+            order.swap(i, (rng.next_u64() % (i as u64 + 1)) as usize);
+        }
+        let header_blocks = count / HEADER_SHARE;
+        Layout {
+            columns,
+            order,
+            header_blocks,
+            body_blocks: count - header_blocks,
+        }
+    }
+
+    // Top-left pixel of `block`
+    fn origin(&self, block: usize) -> (u32, u32) {
+        (
+            (block % self.columns * BLOCK) as u32,
+            (block / self.columns * BLOCK) as u32,
+        )
+    }
+
+    fn coefficients(&self, image: &RgbaImage, block: usize, basis: &Basis) -> [f32; 2] {
+        let (x0, y0) = self.origin(block);
+        let mut sums = [0f32; 2];
+        for i in 0..BLOCK * BLOCK {
+            let p = image.get_pixel(x0 + (i % BLOCK) as u32, y0 + (i / BLOCK) as u32);
+            let luma = 0.299 * p[0] as f32 + 0.587 * p[1] as f32 + 0.114 * p[2] as f32;
+            for (sum, function) in sums.iter_mut().zip(&basis.0) {
+                *sum += luma * function[i];
+            }
+        }
+        sums
+    }
+
+    // Move both coefficients of `block` onto the lattice of `bit`
+    fn embed_bit(&self, image: &mut RgbaImage, block: usize, bit: bool, step: f32, basis: &Basis) {
+        let offset = if bit { step / 2.0 } else { 0.0 };
+        let deltas = self
+            .coefficients(image, block, basis)
+            .map(|c| ((c - offset) / step).round() * step + offset - c);
+        let (x0, y0) = self.origin(block);
+        for i in 0..BLOCK * BLOCK {
+            // Equal changes to R, G and B change luma by the same amount
+            let change: f32 = deltas.iter().zip(&basis.0).map(|(d, f)| d * f[i]).sum();
+            let p = image.get_pixel_mut(x0 + (i % BLOCK) as u32, y0 + (i / BLOCK) as u32);
+            for c in 0..3 {
+                p[c] = (p[c] as f32 + change).round().clamp(0.0, 255.0) as u8;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ImagePipeline, OutputFormat};
+    use image::Rgba;
+
+    // Smooth gradients with some texture, away from pure black and white
+    fn photo() -> RgbaImage {
+        RgbaImage::from_fn(256, 192, |x, y| {
+            let texture = ((x * 7 + y * 13) % 17) as u8;
+            Rgba([
+                40 + (x / 2) as u8,
+                60 + (y / 2) as u8 + texture,
+                120 + texture * 2,
+                255,
+            ])
+        })
+    }
+
+    #[test]
+    fn test_round_trip_is_invisible() {
+        let image = photo();
+        let marked = embed_invisible(&image, b"gen:model-x/42", 1.0).unwrap();
+        assert_eq!(
+            extract_invisible(&marked).as_deref(),
+            Some(&b"gen:model-x/42"[..])
+        );
+
+        let max_change = image
+            .as_raw()
+            .iter()
+            .zip(marked.as_raw())
+            .map(|(a, b)| a.abs_diff(*b))
+            .max()
+            .unwrap();
+        assert!(max_change <= 12, "{max_change}");
+
+        assert_eq!(extract_invisible(&image), None);
+        assert_eq!(
+            extract_invisible(&embed_invisible(&image, b"", 1.0).unwrap()),
+            Some(vec![])
+        );
+    }
+
+    #[test]
+    fn test_survives_jpeg() {
+        let marked = embed_invisible(&photo(), b"provenance", 1.0).unwrap();
+        for quality in [90, 75] {
+            let jpeg = ImagePipeline::encode(&marked, OutputFormat::Jpeg, quality).unwrap();
+            let decoded = image::load_from_memory(&jpeg).unwrap().to_rgba8();
+            assert_eq!(
+                extract_invisible(&decoded).as_deref(),
+                Some(&b"provenance"[..]),
+                "quality {quality}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_rejects_bad_input() {
+        let image = photo();
+        assert!(embed_invisible(&image, b"x", 0.0).is_err());
+        assert!(embed_invisible(&image, &[0; 200], 1.0).is_err());
+        assert!(embed_invisible(&RgbaImage::new(8, 8), b"x", 1.0).is_err());
+        assert_eq!(extract_invisible(&RgbaImage::new(4, 4)), None);
+    }
+}
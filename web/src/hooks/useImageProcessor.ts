@@ -21,7 +21,7 @@ interface WasmProcessor {
     blur(sigma: number): void
     sharpen(): void
     edge_detect(): void
-    resize(width: number, height: number): void
+    resize(width: number, height: number, filter?: string): void
     invert(): void
     sepia(): void
     reset(data: Uint8Array, width: number, height: number): void